@@ -0,0 +1,71 @@
+use std::io::Cursor;
+
+use binrw::BinReaderExt;
+use criterion::{black_box, criterion_group, criterion_main, BenchmarkId, Criterion};
+use moc3_bench::SyntheticModel;
+use moc3_rs::{
+    data::Moc3Data,
+    puppet::{framedata_for_puppet, puppet_from_moc3},
+};
+
+const MODELS: [(&str, SyntheticModel); 3] = [
+    ("small", SyntheticModel::SMALL),
+    ("medium", SyntheticModel::MEDIUM),
+    ("large", SyntheticModel::LARGE),
+];
+
+fn read_moc3(bytes: &[u8]) -> Moc3Data {
+    Cursor::new(bytes).read_le().unwrap()
+}
+
+fn parse(c: &mut Criterion) {
+    let mut group = c.benchmark_group("parse");
+    for (name, model) in MODELS {
+        let bytes = model.to_moc3();
+        group.bench_with_input(BenchmarkId::from_parameter(name), &bytes, |b, bytes| {
+            b.iter(|| read_moc3(black_box(bytes)))
+        });
+    }
+    group.finish();
+}
+
+fn build(c: &mut Criterion) {
+    let mut group = c.benchmark_group("build");
+    for (name, model) in MODELS {
+        let read = read_moc3(&model.to_moc3());
+        group.bench_with_input(BenchmarkId::from_parameter(name), &read, |b, read| {
+            b.iter(|| puppet_from_moc3(black_box(read)).unwrap())
+        });
+    }
+    group.finish();
+}
+
+fn update(c: &mut Criterion) {
+    let mut group = c.benchmark_group("update");
+    for (name, model) in MODELS {
+        let puppet = puppet_from_moc3(&read_moc3(&model.to_moc3())).unwrap();
+        let mut frame_data = framedata_for_puppet(&puppet);
+        let param_data = puppet.param_data();
+        let part_opacities = vec![1.0; puppet.part_count as usize];
+
+        // Sweep the parameters around so every frame actually interpolates something.
+        let mut frame = 0;
+        let mut params = param_data.defaults.clone();
+
+        group.bench_function(BenchmarkId::from_parameter(name), |b| {
+            b.iter(|| {
+                frame += 1;
+                for (i, param) in params.iter_mut().enumerate() {
+                    let t = (frame + i * 7) as f32 * 0.05;
+                    *param = t.sin() * param_data.maxes[i];
+                }
+
+                puppet.update(black_box(&params), &part_opacities, &mut frame_data);
+            })
+        });
+    }
+    group.finish();
+}
+
+criterion_group!(benches, parse, build, update);
+criterion_main!(benches);
@@ -0,0 +1,736 @@
+//! Programmatically generated moc3 models, so benchmarks and tests don't need any
+//! (usually licensed) real models, along with a CPU rasterizer for checking how they look.
+
+mod raster;
+mod writer;
+
+use glam::{vec2, Vec2};
+use moc3_rs::puppet::{framedata_for_puppet, PuppetFrameData, PuppetRef};
+
+use writer::TableWriter;
+
+pub use raster::rasterize;
+
+// The newest version without any of the optional sections.
+const VERSION_3_03: u8 = 2;
+// The first version with blend shapes, along with keyform colors.
+const VERSION_4_02: u8 = 4;
+
+// Every parameter goes from -RANGE to RANGE, with a key at each end and in the middle.
+const RANGE: f32 = 30.0;
+const KEYS: [f32; 3] = [-RANGE, 0.0, RANGE];
+
+/// The shape of a generated model.
+///
+/// Models are made out of limbs, each being a rotation deformer with a warp deformer inside
+/// of it holding a stack of grid-shaped art meshes. Every deformer and art mesh is bound to
+/// parameters, so updating a generated model does all the same work a real one would.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct SyntheticModel {
+    pub limbs: usize,
+    pub meshes_per_limb: usize,
+    /// How many vertexes art meshes have along each side.
+    pub mesh_resolution: usize,
+    /// How many cells warp deformers have along each side.
+    pub warp_resolution: usize,
+    /// This must be at least 2, since art meshes are bound to two parameters.
+    pub parameters: usize,
+    /// Adds a blend shape parameter that widens every art mesh, which makes the model a
+    /// v4.02 one. It's held back by the last regular parameter, fully applying from 0 up and
+    /// fading out towards the bottom of its range.
+    pub blend_shapes: bool,
+    /// Gives every limb a draw order group of its own under its part, so art meshes are
+    /// only sorted against the others in their limb. The root group is written last, to
+    /// make sure groups don't have to come in any particular order.
+    pub draw_order_groups: bool,
+    /// Puts another rotation deformer inside every limb's warp deformer, holding the limb's
+    /// last art mesh, so rotation deformers get turned by warp deformers too.
+    pub nested_rotations: bool,
+    /// Glues the first art mesh of every limb to the first one of the next limb, pulling a
+    /// corner of each towards the other.
+    pub glues: bool,
+}
+
+impl SyntheticModel {
+    /// Just big enough to have more than one of everything, for tests that need to stay
+    /// quick (or run under miri).
+    pub const TINY: Self = Self {
+        limbs: 2,
+        meshes_per_limb: 2,
+        mesh_resolution: 3,
+        warp_resolution: 2,
+        parameters: 3,
+        blend_shapes: false,
+        draw_order_groups: false,
+        nested_rotations: false,
+        glues: false,
+    };
+
+    pub const SMALL: Self = Self {
+        limbs: 4,
+        meshes_per_limb: 4,
+        mesh_resolution: 8,
+        warp_resolution: 3,
+        parameters: 8,
+        blend_shapes: false,
+        draw_order_groups: false,
+        nested_rotations: false,
+        glues: false,
+    };
+
+    pub const MEDIUM: Self = Self {
+        limbs: 16,
+        meshes_per_limb: 8,
+        mesh_resolution: 16,
+        warp_resolution: 5,
+        parameters: 32,
+        blend_shapes: false,
+        draw_order_groups: false,
+        nested_rotations: false,
+        glues: false,
+    };
+
+    pub const LARGE: Self = Self {
+        limbs: 48,
+        meshes_per_limb: 16,
+        mesh_resolution: 24,
+        warp_resolution: 8,
+        parameters: 96,
+        blend_shapes: false,
+        draw_order_groups: false,
+        nested_rotations: false,
+        glues: false,
+    };
+
+    pub fn art_mesh_count(&self) -> usize {
+        self.limbs * self.meshes_per_limb
+    }
+
+    /// Writes the model out as a moc3 file.
+    pub fn to_moc3(&self) -> Vec<u8> {
+        assert!(
+            self.parameters >= 2,
+            "synthetic models need at least 2 parameters"
+        );
+        assert!(
+            self.mesh_resolution >= 2 && self.mesh_resolution.pow(2) <= u16::MAX as usize,
+            "art mesh vertexes must be indexable with u16"
+        );
+        assert!(self.warp_resolution >= 1);
+
+        Builder::new(*self).build()
+    }
+}
+
+/// Updates a puppet with every part fully opaque and every parameter set by `params`,
+/// which gets each parameter's index, minimum and maximum.
+pub fn pose_puppet(puppet: &PuppetRef, params: impl Fn(usize, f32, f32) -> f32) -> PuppetFrameData {
+    let param_data = puppet.param_data();
+    let params: Vec<f32> = (0..param_data.count as usize)
+        .map(|i| params(i, param_data.mins[i], param_data.maxes[i]))
+        .collect();
+
+    let mut frame_data = framedata_for_puppet(puppet);
+    puppet.update(
+        &params,
+        &vec![1.0; puppet.part_count as usize],
+        &mut frame_data,
+    );
+    frame_data
+}
+
+// Where a limb sits horizontally, spreading them evenly over the canvas.
+fn limb_x(limb: usize, limbs: usize) -> f32 {
+    if limbs == 1 {
+        0.0
+    } else {
+        -0.5 + limb as f32 / (limbs - 1) as f32
+    }
+}
+
+struct Builder {
+    model: SyntheticModel,
+
+    keyform_binding_starts: Vec<u32>,
+    keyform_binding_counts: Vec<u32>,
+    parameter_binding_indices: Vec<u32>,
+
+    positions: Vec<Vec2>,
+    uvs: Vec<Vec2>,
+    vertex_indices: Vec<u16>,
+}
+
+impl Builder {
+    fn new(model: SyntheticModel) -> Self {
+        Self {
+            model,
+            keyform_binding_starts: Vec::new(),
+            keyform_binding_counts: Vec::new(),
+            parameter_binding_indices: Vec::new(),
+            positions: Vec::new(),
+            uvs: Vec::new(),
+            vertex_indices: Vec::new(),
+        }
+    }
+
+    // Every parameter has a single parameter binding with the same index, so keyform
+    // bindings can refer to parameters directly.
+    fn bind(&mut self, parameters: &[usize]) -> u32 {
+        let index = self.keyform_binding_starts.len() as u32;
+        self.keyform_binding_starts
+            .push(self.parameter_binding_indices.len() as u32);
+        self.keyform_binding_counts.push(parameters.len() as u32);
+        self.parameter_binding_indices
+            .extend(parameters.iter().map(|x| *x as u32));
+        index
+    }
+
+    // Returns where the positions start, counted in floats like the format does.
+    fn push_positions(&mut self, positions: impl IntoIterator<Item = Vec2>) -> u32 {
+        let start = self.positions.len() as u32 * 2;
+        self.positions.extend(positions);
+        start
+    }
+
+    fn build(mut self) -> Vec<u8> {
+        let model = self.model;
+        let limbs = model.limbs;
+        let meshes = model.art_mesh_count();
+        let params = model.parameters;
+
+        // Parts only have a single keyform, so they don't need any parameters.
+        let unbound = self.bind(&[]);
+
+        let part_count = limbs + 1;
+        let mut part_ids = vec!["PartRoot".to_string()];
+        part_ids.extend((0..limbs).map(|l| format!("PartLimb{l}")));
+        let mut part_parents = vec![-1i32];
+        part_parents.extend((0..limbs).map(|_| 0));
+
+        // Deformers go rotation, warp and then the nested rotation for each limb, so parents
+        // always come first.
+        let per_limb = 2 + model.nested_rotations as usize;
+        let rotation_count = limbs * (per_limb - 1);
+        let deformer_count = limbs * per_limb;
+        let mut deformer_ids = Vec::with_capacity(deformer_count);
+        let mut deformer_bindings = Vec::with_capacity(deformer_count);
+        let mut deformer_parent_parts = Vec::with_capacity(deformer_count);
+        let mut deformer_parents = Vec::with_capacity(deformer_count);
+        let mut deformer_types = Vec::with_capacity(deformer_count);
+        let mut deformer_specifics = Vec::with_capacity(deformer_count);
+
+        let mut rotation_bindings = Vec::with_capacity(rotation_count);
+        // Typed out, since float literals would otherwise make these f64 and write them out
+        // as twice as many floats as the format expects.
+        let mut rotation_angles: Vec<f32> = Vec::with_capacity(rotation_count * 3);
+        let mut rotation_origins = Vec::with_capacity(rotation_count * 3);
+        let mut rotation_scales: Vec<f32> = Vec::with_capacity(rotation_count * 3);
+
+        let grid = model.warp_resolution;
+        let grid_points = (grid + 1) * (grid + 1);
+        let mut warp_bindings = Vec::with_capacity(limbs);
+        let mut warp_position_starts = Vec::with_capacity(limbs * 3);
+
+        for limb in 0..limbs {
+            let rotation_binding = self.bind(&[limb % params]);
+            deformer_ids.push(format!("Rotation{limb}"));
+            deformer_bindings.push(rotation_binding);
+            deformer_parent_parts.push(limb as i32 + 1);
+            deformer_parents.push(-1i32);
+            deformer_types.push(1u32);
+            deformer_specifics.push(rotation_bindings.len() as u32);
+
+            rotation_bindings.push(rotation_binding);
+            for angle in [-10.0, 0.0, 10.0] {
+                rotation_angles.push(angle);
+                rotation_origins.push(vec2(limb_x(limb, limbs), 0.0));
+                rotation_scales.push(1.0);
+            }
+
+            let warp_binding = self.bind(&[(limb + 1) % params]);
+            warp_bindings.push(warp_binding);
+            for sway in [-0.05, 0.0, 0.05] {
+                let start = self.push_positions((0..grid_points).map(|i| {
+                    let u = (i % (grid + 1)) as f32 / grid as f32;
+                    let v = (i / (grid + 1)) as f32 / grid as f32;
+                    vec2((u - 0.5) * 0.2 + sway * v, (v - 0.5) * 0.8)
+                }));
+                warp_position_starts.push(start);
+            }
+
+            deformer_ids.push(format!("Warp{limb}"));
+            deformer_bindings.push(warp_binding);
+            deformer_parent_parts.push(limb as i32 + 1);
+            deformer_parents.push((limb * per_limb) as i32);
+            deformer_types.push(0u32);
+            deformer_specifics.push(limb as u32);
+
+            if model.nested_rotations {
+                // Near the bottom of the warp deformer, and drifting sideways as it turns so
+                // its origin gets blended too.
+                let nested_binding = self.bind(&[(limb + 2) % params]);
+                deformer_ids.push(format!("Nested{limb}"));
+                deformer_bindings.push(nested_binding);
+                deformer_parent_parts.push(limb as i32 + 1);
+                deformer_parents.push((limb * per_limb + 1) as i32);
+                deformer_types.push(1u32);
+                deformer_specifics.push(rotation_bindings.len() as u32);
+
+                rotation_bindings.push(nested_binding);
+                for (angle, x) in [(-20.0, 0.45), (0.0, 0.5), (20.0, 0.55)] {
+                    rotation_angles.push(angle);
+                    rotation_origins.push(vec2(x, 0.9));
+                    rotation_scales.push(0.2);
+                }
+            }
+        }
+
+        let side = model.mesh_resolution;
+        let vertexes = side * side;
+        let band = 0.9 / model.meshes_per_limb as f32;
+
+        let mut mesh_ids = Vec::with_capacity(meshes);
+        let mut mesh_bindings = Vec::with_capacity(meshes);
+        let mut mesh_parent_parts = Vec::with_capacity(meshes);
+        let mut mesh_parents = Vec::with_capacity(meshes);
+        let mut mesh_uv_starts = Vec::with_capacity(meshes);
+        let mut mesh_index_starts = Vec::with_capacity(meshes);
+        let mut mesh_index_counts = Vec::with_capacity(meshes);
+        let mut mesh_position_starts = Vec::with_capacity(meshes * 9);
+        let mut mesh_draw_orders = Vec::with_capacity(meshes * 9);
+
+        for limb in 0..limbs {
+            for m in 0..model.meshes_per_limb {
+                let mesh = limb * model.meshes_per_limb + m;
+                // Consecutive parameters, which are always different as there are at least 2.
+                let a = mesh % params;
+                let b = (mesh + 1) % params;
+
+                mesh_ids.push(format!("ArtMesh{mesh}"));
+                mesh_bindings.push(self.bind(&[a, b]));
+                mesh_parent_parts.push(limb as i32 + 1);
+                // The nested rotation deformer holds the last art mesh.
+                let nested = model.nested_rotations && m + 1 == model.meshes_per_limb;
+                let parent = limb * per_limb + 1 + nested as usize;
+                mesh_parents.push(parent as i32);
+
+                let grid_uv = (0..vertexes).map(|i| {
+                    vec2(
+                        (i % side) as f32 / (side - 1) as f32,
+                        (i / side) as f32 / (side - 1) as f32,
+                    )
+                });
+
+                mesh_uv_starts.push(self.uvs.len() as u32 * 2);
+                self.uvs.extend(grid_uv.clone());
+
+                mesh_index_starts.push(self.vertex_indices.len() as u32);
+                for y in 0..side - 1 {
+                    for x in 0..side - 1 {
+                        let i = (x + y * side) as u16;
+                        let below = i + side as u16;
+                        self.vertex_indices
+                            .extend([i, i + 1, below, i + 1, below + 1, below]);
+                    }
+                }
+                mesh_index_counts.push(((side - 1) * (side - 1) * 6) as u32);
+
+                // The first mesh of every limb sticks out of the sides of its warp
+                // deformer, so extrapolation gets exercised as well.
+                let (left, width) = if m == 0 { (-0.1, 1.2) } else { (0.1, 0.8) };
+
+                // The first parameter varies fastest between keyforms.
+                for kb in 0..3 {
+                    for ka in 0..3 {
+                        let bend = (ka as f32 - 1.0) * 0.03;
+                        let stretch = (kb as f32 - 1.0) * 0.01;
+                        let top = 0.05 + m as f32 * band;
+                        let start = self.push_positions(grid_uv.clone().map(|uv| {
+                            let position = vec2(
+                                left + uv.x * width + bend * uv.y,
+                                top + uv.y * band * 0.9 + stretch * uv.x,
+                            );
+                            // Rotation deformers hold their art meshes around their origin.
+                            if nested {
+                                position - vec2(0.5, 0.9)
+                            } else {
+                                position
+                            }
+                        }));
+                        mesh_position_starts.push(start);
+                        mesh_draw_orders.push(500.0 + m as f32);
+                    }
+                }
+            }
+        }
+
+        // Blend shape keyforms come after all of the regular ones, and hold offsets rather
+        // than positions: nothing at the bottom key, and widening the mesh at the top one.
+        let mut blend_position_starts = Vec::new();
+        if model.blend_shapes {
+            for _ in 0..meshes {
+                let start = self.push_positions((0..vertexes).map(|_| Vec2::ZERO));
+                blend_position_starts.push(start);
+                let start = self.push_positions((0..vertexes).map(|i| {
+                    let u = (i % side) as f32 / (side - 1) as f32;
+                    vec2((u - 0.5) * 0.1, 0.0)
+                }));
+                blend_position_starts.push(start);
+            }
+        }
+        let mesh_keyforms = meshes * 9 + blend_position_starts.len();
+        mesh_position_starts.extend(&blend_position_starts);
+        mesh_draw_orders.resize(mesh_keyforms, 500.0);
+
+        // The blend shape parameter goes last, with its keys after everyone else's.
+        let all_params = params + model.blend_shapes as usize;
+        let mut keys = KEYS.repeat(params);
+        if model.blend_shapes {
+            keys.extend([0.0, 1.0]);
+        }
+
+        // Bottom right corners glued to top left ones, meeting halfway.
+        let glue_count = if model.glues {
+            limbs.saturating_sub(1)
+        } else {
+            0
+        };
+
+        let keyform_bindings = self.keyform_binding_starts.len();
+        let keyform_positions = self.positions.len() * 2;
+        let colors = (limbs + rotation_count) * 3 + meshes * 9;
+
+        // The root group, plus one per limb if they have their own.
+        let groups = 1 + limbs * model.draw_order_groups as usize;
+
+        let mut table = TableWriter::default();
+
+        // Counts
+        let mut counts = vec![
+            part_count,
+            deformer_count,
+            limbs,
+            rotation_count,
+            meshes,
+            all_params,
+            part_count,
+            limbs * 3,
+            rotation_count * 3,
+            mesh_keyforms,
+            keyform_positions,
+            self.parameter_binding_indices.len(),
+            keyform_bindings,
+            params,
+            keys.len(),
+            self.uvs.len() * 2,
+            self.vertex_indices.len(),
+            0,
+            groups,
+            meshes + groups - 1,
+            glue_count,
+            glue_count * 2,
+            glue_count,
+        ];
+        if model.blend_shapes {
+            counts.extend([colors, colors, 1, meshes, 0, meshes, 1, 1, 3]);
+        }
+        table.array(&counts.iter().map(|x| *x as u32).collect::<Vec<_>>());
+
+        // Canvas
+        let mut canvas: Vec<u8> =
+            bytemuck::cast_slice(&[1000.0f32, 500.0, 500.0, 1000.0, 1000.0]).to_vec();
+        canvas.push(0);
+        table.bytes(canvas);
+
+        // Parts
+        table.raw(0);
+        table.ids(&part_ids);
+        table.array(&vec![unbound; part_count]);
+        table.array(&(0..part_count as u32).collect::<Vec<_>>());
+        table.array(&vec![1u32; part_count]);
+        table.array(&vec![1u32; part_count]);
+        table.array(&vec![1u32; part_count]);
+        table.array(&part_parents);
+
+        // Deformers
+        table.raw(0);
+        table.ids(&deformer_ids);
+        table.array(&deformer_bindings);
+        table.array(&vec![1u32; deformer_count]);
+        table.array(&vec![1u32; deformer_count]);
+        table.array(&deformer_parent_parts);
+        table.array(&deformer_parents);
+        table.array(&deformer_types);
+        table.array(&deformer_specifics);
+
+        // Warp deformers
+        table.array(&warp_bindings);
+        table.array(&(0..limbs as u32).map(|x| x * 3).collect::<Vec<_>>());
+        table.array(&vec![3u32; limbs]);
+        table.array(&vec![grid_points as u32; limbs]);
+        table.array(&vec![grid as u32; limbs]);
+        table.array(&vec![grid as u32; limbs]);
+
+        // Rotation deformers
+        table.array(&rotation_bindings);
+        table.array(
+            &(0..rotation_count as u32)
+                .map(|x| x * 3)
+                .collect::<Vec<_>>(),
+        );
+        table.array(&vec![3u32; rotation_count]);
+        table.array(&vec![0.0f32; rotation_count]);
+
+        // Art meshes
+        for _ in 0..4 {
+            table.raw(0);
+        }
+        table.ids(&mesh_ids);
+        table.array(&mesh_bindings);
+        table.array(&(0..meshes as u32).map(|x| x * 9).collect::<Vec<_>>());
+        table.array(&vec![9u32; meshes]);
+        table.array(&vec![1u32; meshes]);
+        table.array(&vec![1u32; meshes]);
+        table.array(&mesh_parent_parts);
+        table.array(&mesh_parents);
+        table.array(&vec![0u32; meshes]);
+        // Normal blending, single sided, not inverted.
+        table.array(&vec![0u8; meshes]);
+        table.array(&vec![vertexes as u32; meshes]);
+        table.array(&mesh_uv_starts);
+        table.array(&mesh_index_starts);
+        table.array(&mesh_index_counts);
+        table.array(&vec![0u32; meshes]);
+        table.array(&vec![0u32; meshes]);
+
+        // Parameters, with the blend shape parameter not having any regular bindings.
+        let mut param_ids: Vec<String> = (0..params).map(|p| format!("Param{p}")).collect();
+        let mut maxes = vec![RANGE; params];
+        let mut mins = vec![-RANGE; params];
+        let mut binding_counts = vec![1u32; params];
+        if model.blend_shapes {
+            param_ids.push("ParamBlend".to_string());
+            maxes.push(1.0);
+            mins.push(0.0);
+            binding_counts.push(0);
+        }
+        table.raw(0);
+        table.ids(&param_ids);
+        table.array(&maxes);
+        table.array(&mins);
+        table.array(&vec![0.0f32; all_params]);
+        table.array(&vec![0u32; all_params]);
+        table.array(&vec![1u32; all_params]);
+        table.array(
+            &(0..all_params as u32)
+                .map(|x| x.min(params as u32))
+                .collect::<Vec<_>>(),
+        );
+        table.array(&binding_counts);
+
+        // Part keyforms
+        table.array(&vec![500.0f32; part_count]);
+
+        // Warp deformer keyforms
+        table.array(&vec![1.0f32; limbs * 3]);
+        table.array(&warp_position_starts);
+
+        // Rotation deformer keyforms
+        table.array(&vec![1.0f32; rotation_count * 3]);
+        table.array(&rotation_angles);
+        table.array(&rotation_origins.iter().map(|x| x.x).collect::<Vec<_>>());
+        table.array(&rotation_origins.iter().map(|x| x.y).collect::<Vec<_>>());
+        table.array(&rotation_scales);
+        table.array(&vec![0u32; rotation_count * 3]);
+        table.array(&vec![0u32; rotation_count * 3]);
+
+        // Art mesh keyforms
+        table.array(&vec![1.0f32; mesh_keyforms]);
+        table.array(&mesh_draw_orders);
+        table.array(&mesh_position_starts);
+
+        // Keyform positions
+        table.array(&self.positions);
+
+        // Parameter binding indices
+        table.array(&self.parameter_binding_indices);
+
+        // Keyform bindings
+        table.array(&self.keyform_binding_starts);
+        table.array(&self.keyform_binding_counts);
+
+        // Parameter bindings
+        table.array(&(0..params as u32).map(|x| x * 3).collect::<Vec<_>>());
+        table.array(&vec![3u32; params]);
+
+        // Keys
+        table.array(&keys);
+
+        // UVs
+        table.array(&self.uvs);
+
+        // Vertex indices
+        table.array(&self.vertex_indices);
+
+        // Art mesh masks
+        table.array::<u32>(&[]);
+
+        if model.draw_order_groups {
+            // One group per limb holding its meshes, then the root group holding the limbs'
+            // parts. The groups span exactly the draw orders in them.
+            let per_limb = model.meshes_per_limb as u32;
+            let mut starts: Vec<u32> = (0..limbs as u32).map(|x| x * per_limb).collect();
+            starts.push(meshes as u32);
+            let mut object_counts = vec![per_limb; limbs];
+            object_counts.push(limbs as u32);
+            let mut total_counts = vec![per_limb; limbs];
+            total_counts.push(meshes as u32 + limbs as u32);
+            let mut maximums = vec![500 + per_limb - 1; limbs];
+            maximums.push(500);
+
+            table.array(&starts);
+            table.array(&object_counts);
+            table.array(&total_counts);
+            table.array(&maximums);
+            table.array(&vec![500u32; limbs + 1]);
+
+            let mut types = vec![0u32; meshes];
+            types.extend(vec![1u32; limbs]);
+            let mut indices: Vec<u32> = (0..meshes as u32).collect();
+            indices.extend(1..=limbs as u32);
+            let mut self_indices = vec![-1i32; meshes];
+            self_indices.extend(0..limbs as i32);
+
+            table.array(&types);
+            table.array(&indices);
+            table.array(&self_indices);
+        } else {
+            // Every art mesh directly in the root group.
+            table.array(&[0u32]);
+            table.array(&[meshes as u32]);
+            table.array(&[meshes as u32]);
+            table.array(&[1000u32]);
+            table.array(&[0u32]);
+
+            table.array(&vec![0u32; meshes]);
+            table.array(&(0..meshes as u32).collect::<Vec<_>>());
+            table.array(&vec![-1i32; meshes]);
+        }
+
+        // Glues, which have a single keyform each, so they're always fully applied.
+        let glue_indices: Vec<u32> = (0..glue_count as u32).collect();
+        let first_meshes = |limb: u32| limb * model.meshes_per_limb as u32;
+        table.raw(0);
+        table.ids(
+            &(0..glue_count)
+                .map(|g| format!("Glue{g}"))
+                .collect::<Vec<_>>(),
+        );
+        table.array(&vec![unbound; glue_count]);
+        table.array(&glue_indices);
+        table.array(&vec![1u32; glue_count]);
+        table.array(
+            &glue_indices
+                .iter()
+                .map(|x| first_meshes(*x))
+                .collect::<Vec<_>>(),
+        );
+        table.array(
+            &glue_indices
+                .iter()
+                .map(|x| first_meshes(x + 1))
+                .collect::<Vec<_>>(),
+        );
+        table.array(&glue_indices.iter().map(|x| x * 2).collect::<Vec<_>>());
+        table.array(&vec![2u32; glue_count]);
+
+        // Glue infos
+        table.array(&vec![0.5f32; glue_count * 2]);
+        table.array(&[vertexes as u16 - 1, 0].repeat(glue_count));
+
+        // Glue keyforms
+        table.array(&vec![1.0f32; glue_count]);
+
+        // Warp deformer keyforms (3.03)
+        table.array(&vec![1u32; limbs]);
+
+        if !model.blend_shapes {
+            return table.finish(VERSION_3_03);
+        }
+
+        // Parameter extensions
+        table.raw(0);
+        let mut key_starts: Vec<u32> = (0..params as u32).map(|x| x * 3).collect();
+        key_starts.push(params as u32 * 3);
+        let mut key_counts = vec![3u32; params];
+        key_counts.push(2);
+        table.array(&key_starts);
+        table.array(&key_counts);
+
+        // Where the keyform colors of every warp deformer, rotation deformer and art mesh
+        // start, in that order. They're all left neutral.
+        table.array(&(0..limbs as u32).map(|x| x * 3).collect::<Vec<_>>());
+        table.array(
+            &(0..rotation_count as u32)
+                .map(|x| (limbs as u32 + x) * 3)
+                .collect::<Vec<_>>(),
+        );
+        table.array(
+            &(0..meshes as u32)
+                .map(|x| ((limbs + rotation_count) * 3) as u32 + x * 9)
+                .collect::<Vec<_>>(),
+        );
+
+        // Multiply colors, then screen colors, each as red, green and blue.
+        for value in [1.0f32, 0.0] {
+            for _ in 0..3 {
+                table.array(&vec![value; colors]);
+            }
+        }
+
+        // Parameters (4.02)
+        let mut types = vec![0u32; params];
+        types.push(1);
+        let mut blend_binding_counts = vec![0u32; params];
+        blend_binding_counts.push(1);
+        table.array(&types);
+        table.array(&vec![0u32; all_params]);
+        table.array(&blend_binding_counts);
+
+        // Blend shape parameter bindings, with the bottom key being where it does nothing.
+        table.array(&[params as u32 * 3]);
+        table.array(&[2u32]);
+        table.array(&[0u32]);
+
+        // Blend shape keyform bindings, one for every art mesh, all sharing one constraint.
+        table.array(&vec![0u32; meshes]);
+        table.array(
+            &(0..meshes as u32)
+                .map(|x| (meshes * 9) as u32 + x * 2)
+                .collect::<Vec<_>>(),
+        );
+        table.array(&vec![2u32; meshes]);
+        table.array(&vec![0u32; meshes]);
+        table.array(&vec![1u32; meshes]);
+
+        // Blend shape warp deformers
+        for _ in 0..3 {
+            table.array::<u32>(&[]);
+        }
+
+        // Blend shape art meshes
+        table.array(&(0..meshes as u32).collect::<Vec<_>>());
+        table.array(&(0..meshes as u32).collect::<Vec<_>>());
+        table.array(&vec![1u32; meshes]);
+
+        // Blend shape constraints
+        table.array(&[0u32]);
+        table.array(&[params as u32 - 1]);
+        table.array(&[0u32]);
+        table.array(&[3u32]);
+        table.array(&KEYS);
+        table.array(&[0.0f32, 1.0, 1.0]);
+
+        table.finish(VERSION_4_02)
+    }
+}
@@ -0,0 +1,115 @@
+use glam::{vec2, vec3, Vec2, Vec3};
+use image::{Rgba, RgbaImage};
+use moc3_rs::puppet::{PuppetFrameData, PuppetRef};
+
+// How many checker squares go across each art mesh's UVs, so deformation shows up inside
+// meshes too and not just along their edges.
+const CHECKERS: f32 = 8.0;
+
+const BACKGROUND: Vec3 = Vec3::ONE;
+
+// A distinct color for every art mesh, spreading hues out with the golden ratio.
+fn mesh_color(index: usize) -> Vec3 {
+    let hue = (index as f32 * 0.618_034).fract() * 6.0;
+    let x = 1.0 - (hue % 2.0 - 1.0).abs();
+    let color = match hue as u32 {
+        0 => vec3(1.0, x, 0.0),
+        1 => vec3(x, 1.0, 0.0),
+        2 => vec3(0.0, 1.0, x),
+        3 => vec3(0.0, x, 1.0),
+        4 => vec3(x, 0.0, 1.0),
+        _ => vec3(1.0, 0.0, x),
+    };
+
+    color * 0.7 + 0.15
+}
+
+fn edge(a: Vec2, b: Vec2, p: Vec2) -> f32 {
+    (b - a).perp_dot(p - a)
+}
+
+/// Draws a posed puppet without a GPU, giving the same result on every machine.
+///
+/// Textures aren't available here, so every art mesh gets a flat color with a checkerboard
+/// over its UVs instead. Meshes are drawn in render order with their opacities and blend
+/// colors, using the same camera as the wgpu renderer. Masks and blend modes are ignored.
+pub fn rasterize(
+    puppet: &PuppetRef,
+    frame_data: &PuppetFrameData,
+    width: u32,
+    height: u32,
+) -> RgbaImage {
+    let size = vec2(width as f32, height as f32);
+    let mut pixels = vec![BACKGROUND; (width * height) as usize];
+
+    // Matches the scale and flip done in the vertex shader.
+    let to_pixel = |x: Vec2| {
+        let clip = x * vec2(1.5, -1.5);
+        vec2(clip.x + 1.0, 1.0 - clip.y) * 0.5 * size
+    };
+
+    for mesh in frame_data.render_order().iter().copied() {
+        let mesh = mesh as usize;
+        let opacity = frame_data.art_mesh_opacities()[mesh].clamp(0.0, 1.0);
+        if opacity <= 0.0 {
+            continue;
+        }
+
+        let colors = frame_data.art_mesh_colors()[mesh];
+        let base = mesh_color(mesh);
+        let vertexes = &frame_data.art_mesh_positions()[mesh];
+        let uvs = &puppet.art_mesh_uvs[mesh];
+
+        for triangle in puppet.art_mesh_indices[mesh].chunks_exact(3) {
+            let [a, b, c] = [0, 1, 2].map(|i| triangle[i] as usize);
+            let [pa, pb, pc] = [a, b, c].map(|i| to_pixel(vertexes[i]));
+            if !(pa.is_finite() && pb.is_finite() && pc.is_finite()) {
+                continue;
+            }
+
+            let area = edge(pa, pb, pc);
+            if area == 0.0 {
+                continue;
+            }
+
+            let min = pa.min(pb).min(pc).max(Vec2::ZERO).floor();
+            let max = pa.max(pb).max(pc).min(size - 1.0).ceil();
+
+            for y in min.y as u32..=max.y as u32 {
+                for x in min.x as u32..=max.x as u32 {
+                    let p = vec2(x as f32 + 0.5, y as f32 + 0.5);
+                    let weights = vec3(edge(pb, pc, p), edge(pc, pa, p), edge(pa, pb, p)) / area;
+
+                    // Dividing by the area makes this work for both windings.
+                    if weights.min_element() < 0.0 {
+                        continue;
+                    }
+
+                    let uv = uvs[a] * weights.x + uvs[b] * weights.y + uvs[c] * weights.z;
+                    let checker = (uv * CHECKERS).floor();
+                    let shade = if (checker.x + checker.y) as i32 % 2 == 0 {
+                        1.0
+                    } else {
+                        0.75
+                    };
+
+                    let mut color = base * shade * colors.multiply_color;
+                    color = color + colors.screen_color - color * colors.screen_color;
+
+                    let pixel = &mut pixels[(x + y * width) as usize];
+                    *pixel = pixel.lerp(color.clamp(Vec3::ZERO, Vec3::ONE), opacity);
+                }
+            }
+        }
+    }
+
+    RgbaImage::from_fn(width, height, |x, y| {
+        let color = pixels[(x + y * width) as usize] * 255.0;
+        Rgba([
+            color.x.round() as u8,
+            color.y.round() as u8,
+            color.z.round() as u8,
+            255,
+        ])
+    })
+}
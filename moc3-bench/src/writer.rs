@@ -0,0 +1,73 @@
+use bytemuck::Pod;
+
+// Everything in a moc3 is aligned to this, which also keeps arrays castable in place.
+const ALIGNMENT: usize = 64;
+
+enum Slot {
+    Raw(u32),
+    Data(Vec<u8>),
+}
+
+/// Writes the section offset table of a moc3 file, one entry at a time in the order the
+/// parser reads them, with every array placed after the table.
+#[derive(Default)]
+pub struct TableWriter {
+    slots: Vec<Slot>,
+}
+
+impl TableWriter {
+    /// A plain value stored in the table itself.
+    pub fn raw(&mut self, value: u32) {
+        self.slots.push(Slot::Raw(value));
+    }
+
+    /// A pointer to the given bytes.
+    pub fn bytes(&mut self, data: Vec<u8>) {
+        self.slots.push(Slot::Data(data));
+    }
+
+    /// A pointer to the given array.
+    pub fn array<T: Pod>(&mut self, data: &[T]) {
+        self.bytes(bytemuck::cast_slice(data).to_vec());
+    }
+
+    /// A pointer to an array of IDs, each null-padded to 64 bytes.
+    pub fn ids(&mut self, ids: &[String]) {
+        let mut data = vec![0; ids.len() * 64];
+        for (chunk, id) in data.chunks_exact_mut(64).zip(ids) {
+            // Leave room for the null terminator.
+            let len = id.len().min(63);
+            chunk[..len].copy_from_slice(&id.as_bytes()[..len]);
+        }
+        self.bytes(data);
+    }
+
+    pub fn finish(self, version: u8) -> Vec<u8> {
+        let mut out = Vec::new();
+        out.extend_from_slice(b"MOC3");
+        out.push(version);
+        // Little endian.
+        out.push(0);
+        out.resize(ALIGNMENT, 0);
+
+        let table_start = out.len();
+        out.resize(table_start + self.slots.len() * 4, 0);
+
+        for (i, slot) in self.slots.into_iter().enumerate() {
+            let value = match slot {
+                Slot::Raw(value) => value,
+                Slot::Data(data) => {
+                    out.resize(out.len().next_multiple_of(ALIGNMENT), 0);
+                    let offset = out.len() as u32;
+                    out.extend_from_slice(&data);
+                    offset
+                }
+            };
+
+            let at = table_start + i * 4;
+            out[at..at + 4].copy_from_slice(&value.to_le_bytes());
+        }
+
+        out
+    }
+}
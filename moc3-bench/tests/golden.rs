@@ -0,0 +1,101 @@
+//! Renders generated models at fixed parameter values and compares them against the images
+//! in `tests/golden`. Run with `MOC3_BLESS=1` to write new images after an intended change.
+
+use std::path::PathBuf;
+
+use image::RgbaImage;
+use moc3_bench::{pose_puppet, rasterize, SyntheticModel};
+use moc3_rs::parse_puppet;
+
+const SIZE: u32 = 256;
+
+// Per channel, to allow for floating point differences between platforms.
+const CHANNEL_TOLERANCE: u8 = 8;
+// Triangle edges can land on either side of a pixel center, so allow a few pixels to differ.
+const MISMATCH_TOLERANCE: f32 = 0.005;
+
+fn render(model: SyntheticModel, params: impl Fn(usize, f32, f32) -> f32) -> RgbaImage {
+    let puppet = parse_puppet(&model.to_moc3()).unwrap();
+    let frame_data = pose_puppet(&puppet, params);
+    rasterize(&puppet, &frame_data, SIZE, SIZE)
+}
+
+fn check(name: &str, image: RgbaImage) {
+    let path = PathBuf::from(env!("CARGO_MANIFEST_DIR"))
+        .join("tests/golden")
+        .join(format!("{name}.png"));
+
+    if std::env::var_os("MOC3_BLESS").is_some() {
+        std::fs::create_dir_all(path.parent().unwrap()).unwrap();
+        image.save(&path).unwrap();
+        return;
+    }
+
+    let golden = match image::open(&path) {
+        Ok(golden) => golden.into_rgba8(),
+        Err(e) => panic!(
+            "couldn't open {}: {e} (run with MOC3_BLESS=1 to create it)",
+            path.display()
+        ),
+    };
+    assert_eq!(golden.dimensions(), image.dimensions());
+
+    let mismatched = golden
+        .pixels()
+        .zip(image.pixels())
+        .filter(|(a, b)| {
+            a.0.iter()
+                .zip(b.0)
+                .any(|(a, b)| a.abs_diff(b) > CHANNEL_TOLERANCE)
+        })
+        .count();
+
+    let allowed = ((SIZE * SIZE) as f32 * MISMATCH_TOLERANCE) as usize;
+    if mismatched > allowed {
+        let actual = path.with_extension("actual.png");
+        image.save(&actual).unwrap();
+        panic!(
+            "{name} differs from the golden image in {mismatched} pixels (at most {allowed} allowed), see {}",
+            actual.display()
+        );
+    }
+}
+
+#[test]
+fn defaults() {
+    check(
+        "small_defaults",
+        render(SyntheticModel::SMALL, |_, _, _| 0.0),
+    );
+}
+
+#[test]
+fn maximums() {
+    check(
+        "small_maximums",
+        render(SyntheticModel::SMALL, |_, _, max| max),
+    );
+}
+
+#[test]
+fn minimums() {
+    check(
+        "small_minimums",
+        render(SyntheticModel::SMALL, |_, min, _| min),
+    );
+}
+
+#[test]
+fn in_between() {
+    // Alternating parameters, stopping between keys so every kind of interpolation is used.
+    check(
+        "medium_in_between",
+        render(SyntheticModel::MEDIUM, |i, min, max| {
+            if i % 2 == 0 {
+                min * 0.4
+            } else {
+                max * 0.7
+            }
+        }),
+    );
+}
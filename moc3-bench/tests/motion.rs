@@ -0,0 +1,94 @@
+//! Plays motions through a queue on a generated model, checking how they fade and override
+//! each other.
+
+use glam::vec2;
+use moc3_bench::SyntheticModel;
+use moc3_impressionism::{
+    motion::{CurveTarget, MotionCurve},
+    Motion, MotionQueue, MotionSettings,
+};
+use moc3_rs::{parse_puppet, puppet::PuppetRef};
+
+// Holds a parameter at one value for two seconds, taking half a second to fade either way.
+fn hold(id: &str, value: f32) -> Motion {
+    Motion {
+        duration: 2.0,
+        fps: 30.0,
+        looping: false,
+        beziers_restricted: true,
+        fade_in: Some(0.5),
+        fade_out: Some(0.5),
+        curves: vec![MotionCurve::linear(
+            CurveTarget::Parameter,
+            id.to_owned(),
+            vec![vec2(0.0, value), vec2(2.0, value)],
+        )],
+        events: Vec::new(),
+    }
+}
+
+fn with_priority(priority: i32) -> MotionSettings {
+    MotionSettings {
+        priority,
+        ..Default::default()
+    }
+}
+
+fn apply(queue: &MotionQueue, puppet: &PuppetRef) -> Vec<f32> {
+    let param_data = puppet.param_data();
+    let mut params = vec![0.0; param_data.count as usize];
+    let mut opacities = vec![1.0; puppet.part_count as usize];
+    queue.apply(param_data, &mut params, &mut opacities);
+    params
+}
+
+#[test]
+fn motions_fade_in_and_out() {
+    let puppet = parse_puppet(&SyntheticModel::SMALL.to_moc3()).unwrap();
+    let mut queue = MotionQueue::new(1);
+    let id = queue
+        .play(&puppet, hold("Param0", 10.0), MotionSettings::default())
+        .unwrap();
+
+    // Fades ease in and out, so they're halfway through at half their time.
+    for (delta, expected) in [(0.25, 5.0), (0.75, 10.0), (0.75, 5.0)] {
+        queue.update(delta);
+        let value = apply(&queue, &puppet)[0];
+        assert!((value - expected).abs() < 1e-4, "{value} != {expected}");
+    }
+
+    queue.update(0.25);
+    assert!(!queue.is_playing(id));
+    assert_eq!(apply(&queue, &puppet)[0], 0.0);
+}
+
+#[test]
+fn higher_priorities_win() {
+    let puppet = parse_puppet(&SyntheticModel::SMALL.to_moc3()).unwrap();
+    let mut queue = MotionQueue::new(1);
+
+    let first = queue
+        .play(&puppet, hold("Param0", 10.0), with_priority(2))
+        .unwrap();
+    assert!(queue
+        .play(&puppet, hold("Param0", 20.0), with_priority(1))
+        .is_none());
+    // Anything animating something else is never in the way.
+    let elsewhere = queue
+        .play(&puppet, hold("Param1", 20.0), with_priority(1))
+        .unwrap();
+
+    // Overriding the first motion fades it out over its fade out time.
+    let second = queue
+        .play(&puppet, hold("Param0", 20.0), with_priority(3))
+        .unwrap();
+    queue.update(0.25);
+    assert!(queue.is_playing(first));
+    queue.update(0.5);
+    assert!(!queue.is_playing(first));
+    assert!(queue.is_playing(second) && queue.is_playing(elsewhere));
+
+    let params = apply(&queue, &puppet);
+    assert!((params[0] - 20.0).abs() < 1e-4);
+    assert!((params[1] - 20.0).abs() < 1e-4);
+}
@@ -0,0 +1,156 @@
+//! Runs pendulums through the traces in `tests/pendulum` and checks every point ends up
+//! where the trace says it should, to get the integrators matching what they're meant to
+//! reproduce.
+//!
+//! Each trace is a CSV file where the first field of every line says what it is:
+//!
+//! ```text
+//! integrator,cubism
+//! threshold,<movement threshold>
+//! vertex,<x>,<y>,<mobility>,<delay>,<acceleration>,<radius>
+//! step,<delta seconds>,<translation x>,<translation y>,<rotation in degrees>,<x>,<y>,...
+//! ```
+//!
+//! Steps list the position of every point after the update, root included. Lines starting
+//! with `#` are comments, which should say where the trace came from. Traces recorded from
+//! the official runtime can be dropped in as they are.
+
+use std::path::{Path, PathBuf};
+
+use glam::Vec2;
+use moc3_impressionism::{
+    data::PhysicsVertex,
+    pendulum::{Integrator, Pendulum, UpdateData},
+};
+
+// Traces are written with six decimals, and errors add up over a few hundred steps.
+const TOLERANCE: f32 = 1e-3;
+
+struct Step {
+    delta_seconds: f32,
+    translation: Vec2,
+    rotation: f32,
+    positions: Vec<Vec2>,
+}
+
+struct Trace {
+    integrator: Integrator,
+    threshold: f32,
+    vertexes: Vec<PhysicsVertex>,
+    steps: Vec<Step>,
+}
+
+fn parse(path: &Path) -> Trace {
+    let text = std::fs::read_to_string(path)
+        .unwrap_or_else(|e| panic!("couldn't read {}: {e}", path.display()));
+
+    let mut trace = Trace {
+        integrator: Integrator::default(),
+        threshold: 0.0,
+        vertexes: Vec::new(),
+        steps: Vec::new(),
+    };
+    for (number, line) in text.lines().enumerate() {
+        let line = line.trim();
+        if line.is_empty() || line.starts_with('#') {
+            continue;
+        }
+
+        let mut fields = line.split(',');
+        let kind = fields.next().unwrap();
+        let context = || format!("{}:{}", path.display(), number + 1);
+        if kind == "integrator" {
+            trace.integrator = match fields.next() {
+                Some("heuristic") => Integrator::Heuristic,
+                Some("verlet") => Integrator::Verlet,
+                Some("cubism") => Integrator::Cubism,
+                other => panic!("{}: unknown integrator {other:?}", context()),
+            };
+            continue;
+        }
+
+        let values: Vec<f32> = fields
+            .map(|x| {
+                x.trim()
+                    .parse()
+                    .unwrap_or_else(|e| panic!("{}: {x:?} isn't a number: {e}", context()))
+            })
+            .collect();
+        match (kind, values.as_slice()) {
+            ("threshold", &[threshold]) => trace.threshold = threshold,
+            ("vertex", &[x, y, mobility, delay, acceleration, radius]) => {
+                trace.vertexes.push(PhysicsVertex {
+                    position: Vec2::new(x, y),
+                    mobility,
+                    delay,
+                    acceleration,
+                    radius,
+                })
+            }
+            ("step", &[delta_seconds, x, y, rotation, ref positions @ ..])
+                if positions.len() == trace.vertexes.len() * 2 =>
+            {
+                trace.steps.push(Step {
+                    delta_seconds,
+                    translation: Vec2::new(x, y),
+                    rotation,
+                    positions: positions.chunks(2).map(|x| Vec2::new(x[0], x[1])).collect(),
+                })
+            }
+            _ => panic!("{}: malformed {kind} line", context()),
+        }
+    }
+
+    trace
+}
+
+// Returns where the trace was first diverged from, if it was.
+fn run(trace: &Trace) -> Option<String> {
+    let mut pendulum = Pendulum::new(trace.vertexes.iter().copied());
+    pendulum.integrator = trace.integrator;
+
+    for (i, step) in trace.steps.iter().enumerate() {
+        pendulum.update_points(
+            step.delta_seconds,
+            UpdateData {
+                translation: step.translation,
+                rotation: step.rotation.to_radians(),
+                movement_threshold: trace.threshold,
+                ..Default::default()
+            },
+        );
+
+        for (j, (point, expected)) in pendulum.points.iter().zip(&step.positions).enumerate() {
+            let actual = point.cur_position;
+            if actual.distance(*expected) > TOLERANCE {
+                return Some(format!(
+                    "step {i}, point {j}: expected {expected}, got {actual}"
+                ));
+            }
+        }
+    }
+
+    None
+}
+
+#[test]
+fn traces() {
+    let directory = PathBuf::from(env!("CARGO_MANIFEST_DIR")).join("tests/pendulum");
+    let mut paths: Vec<PathBuf> = std::fs::read_dir(&directory)
+        .unwrap()
+        .map(|x| x.unwrap().path())
+        .filter(|x| x.extension().is_some_and(|x| x == "csv"))
+        .collect();
+    paths.sort();
+    assert!(!paths.is_empty(), "no traces in {}", directory.display());
+
+    // Every trace gets run even after one fails, to see how far off things are overall.
+    let failures: Vec<String> = paths
+        .iter()
+        .filter_map(|path| {
+            let name = path.file_name().unwrap().to_string_lossy();
+            run(&parse(path)).map(|x| format!("{name} diverged at {x}"))
+        })
+        .collect();
+    assert!(failures.is_empty(), "{}", failures.join("\n"));
+}
@@ -0,0 +1,66 @@
+//! Loads physics3.json settings against a generated model's parameters.
+
+use moc3_bench::SyntheticModel;
+use moc3_impressionism::{
+    data::Physics3Data, PhysicsController, PhysicsTargetKind, PhysicsType, UnknownPhysicsTarget,
+};
+use moc3_rs::parse_puppet;
+
+fn physics(inputs: &str, outputs: &str) -> serde_json::Result<Physics3Data> {
+    serde_json::from_str(&format!(
+        r#"{{
+            "Version": 3,
+            "Meta": {{
+                "TotalInputCount": 2,
+                "TotalOutputCount": 1,
+                "VertexCount": 2,
+                "PhysicsSettingCount": 1,
+                "EffectiveForces": {{}},
+                "PhysicsDictionary": []
+            }},
+            "PhysicsSettings": [{{
+                "Id": "Hair",
+                "Input": [{inputs}],
+                "Output": [{outputs}]
+            }}]
+        }}"#
+    ))
+}
+
+#[test]
+fn unknown_targets_are_reported() {
+    let puppet = parse_puppet(&SyntheticModel::SMALL.to_moc3()).unwrap();
+    let param_data = puppet.param_data();
+
+    // Lowercase names from other tools load the same as Cubism's.
+    let data = physics(
+        r#"{ "Source": { "Target": "Parameter", "Id": "Param0" }, "Weight": 60, "Type": "X", "Reflect": false },
+           { "Source": { "Target": "parameter", "Id": "ParamMissing" }, "Weight": 40, "Type": "angle", "Reflect": false }"#,
+        r#"{ "Destination": { "Target": "Parameter", "Id": "Param1" }, "VertexIndex": 1, "Scale": 1, "Weight": 100, "Type": "Angle", "Reflect": false }"#,
+    )
+    .unwrap();
+    let inputs = &data.physics_settings[0].input;
+    assert_eq!(inputs[1].source.target, PhysicsTargetKind::Parameter);
+    assert_eq!(inputs[1].ty, PhysicsType::Angle);
+
+    assert_eq!(
+        data.validate(param_data),
+        [UnknownPhysicsTarget {
+            setting: "Hair",
+            id: "ParamMissing",
+            is_output: false,
+        }]
+    );
+    let controller = PhysicsController::new(&data, param_data);
+    let setting = &controller.settings()[0];
+    assert_eq!(setting.inputs().len(), 1);
+    assert_eq!(setting.inputs()[0].parameter_index, 0);
+    assert_eq!(setting.outputs()[0].parameter_index, 1);
+
+    // Physics can only drive parameters.
+    assert!(physics(
+        r#"{ "Source": { "Target": "PartOpacity", "Id": "Param0" }, "Weight": 100, "Type": "X", "Reflect": false }"#,
+        "",
+    )
+    .is_err());
+}
@@ -0,0 +1,33 @@
+//! Checks that puppets come back from serialization updating exactly like they went in.
+//! Only runs with the `serde` feature: `cargo test -p moc3-bench --features serde`.
+
+#![cfg(feature = "serde")]
+
+use moc3_bench::{pose_puppet, SyntheticModel};
+use moc3_rs::{parse_puppet, puppet::Puppet};
+
+#[test]
+fn puppets_round_trip() {
+    // Every optional feature, so each kind of node and applicator gets serialized.
+    let model = SyntheticModel {
+        blend_shapes: true,
+        draw_order_groups: true,
+        nested_rotations: true,
+        glues: true,
+        ..SyntheticModel::SMALL
+    };
+    let puppet = parse_puppet(&model.to_moc3()).unwrap();
+
+    let json = serde_json::to_string(&puppet).unwrap();
+    let loaded: Puppet = serde_json::from_str(&json).unwrap();
+    assert_eq!(loaded.art_mesh_count, puppet.art_mesh_count);
+    assert_eq!(loaded.param_data().ids, puppet.param_data().ids);
+
+    let pose = |_, min, max| min * 0.3 + max * 0.5;
+    let expected = pose_puppet(&puppet, pose);
+    let actual = pose_puppet(&loaded, pose);
+
+    assert_eq!(actual.art_mesh_positions(), expected.art_mesh_positions());
+    assert_eq!(actual.art_mesh_opacities(), expected.art_mesh_opacities());
+    assert_eq!(actual.render_order(), expected.render_order());
+}
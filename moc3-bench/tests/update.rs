@@ -0,0 +1,839 @@
+//! Runs whole updates on a tiny generated model, checking that borrowed and owned puppets agree.
+//! These are kept small enough to also run under miri:
+//! `cargo +nightly miri test -p moc3-bench --test update`.
+
+use std::io::Cursor;
+
+use binrw::BinReaderExt;
+use glam::Vec2;
+use moc3_bench::{pose_puppet, SyntheticModel};
+use moc3_rs::{
+    data::Moc3Data,
+    parse_puppet, parse_puppet_best_effort, parse_puppet_ref,
+    puppet::{
+        framedata_for_puppet, ApplicatorTarget, DeferredDeformError, DeformerKind,
+        InputSanitization, KeyformEditError, MeshParent, PuppetFrameData, TransformData,
+        UpdateStage, WarpExtrapolation,
+    },
+    ParseError,
+};
+
+// With this many parameters, the last regular one isn't bound to anything, so it only
+// changes how much of the blend shape gets applied.
+const BLENDED: SyntheticModel = SyntheticModel {
+    parameters: 6,
+    blend_shapes: true,
+    ..SyntheticModel::TINY
+};
+
+const NESTED: SyntheticModel = SyntheticModel {
+    nested_rotations: true,
+    ..SyntheticModel::TINY
+};
+
+const GLUED: SyntheticModel = SyntheticModel {
+    glues: true,
+    ..SyntheticModel::TINY
+};
+
+#[test]
+fn borrowed_matches_owned() {
+    let bytes = SyntheticModel::TINY.to_moc3();
+    let owned = parse_puppet(&bytes).unwrap();
+    let borrowed = parse_puppet_ref(&bytes).unwrap();
+
+    for pose in [
+        |_, _, _| 0.0,
+        |_, min, _| min,
+        |_, _, max| max,
+        |i, min, max| if i % 2 == 0 { min * 0.3 } else { max * 0.6 },
+    ] as [fn(usize, f32, f32) -> f32; 4]
+    {
+        let owned = pose_puppet(&owned, pose);
+        let borrowed = pose_puppet(&borrowed, pose);
+
+        // Miri adds noise to trigonometry, so the two can't be compared exactly.
+        for (a, b) in owned
+            .art_mesh_positions()
+            .iter()
+            .zip(borrowed.art_mesh_positions())
+        {
+            assert!(a.iter().zip(b).all(|(a, b)| a.abs_diff_eq(*b, 1e-4)));
+        }
+        assert_eq!(owned.render_order(), borrowed.render_order());
+        assert!(owned
+            .art_mesh_positions()
+            .iter()
+            .flatten()
+            .all(|x| x.is_finite()));
+    }
+}
+
+fn same_positions(a: &PuppetFrameData, b: &PuppetFrameData) -> bool {
+    same_positions_of(a.art_mesh_positions(), b.art_mesh_positions())
+}
+
+fn same_positions_of(a: &[Vec<Vec2>], b: &[Vec<Vec2>]) -> bool {
+    a.iter()
+        .zip(b)
+        .all(|(a, b)| a.iter().zip(b).all(|(a, b)| a.abs_diff_eq(*b, 1e-5)))
+}
+
+#[test]
+fn partial_matches_full() {
+    for model in [SyntheticModel::TINY, BLENDED, NESTED, GLUED] {
+        partial_matches_full_for(model);
+    }
+}
+
+fn partial_matches_full_for(model: SyntheticModel) {
+    let puppet = parse_puppet(&model.to_moc3()).unwrap();
+    let param_data = puppet.param_data();
+
+    for changed in 0..param_data.count as usize {
+        let before = |_, min: f32, _| min * 0.5;
+        let after = |i, min: f32, max: f32| if i == changed { max * 0.8 } else { min * 0.5 };
+
+        let mut partial = pose_puppet(&puppet, before);
+        let value = after(changed, param_data.mins[changed], param_data.maxes[changed]);
+        puppet.update_partial(&[(changed, value)], &mut partial);
+
+        let full = pose_puppet(&puppet, after);
+        assert!(same_positions(&partial, &full));
+        assert_eq!(partial.art_mesh_opacities(), full.art_mesh_opacities());
+        assert_eq!(partial.render_order(), full.render_order());
+
+        // Going over the same value again reuses what it can from the last partial update.
+        puppet.update_partial(&[(changed, value)], &mut partial);
+        assert!(same_positions(&partial, &full));
+        assert!(same_positions_of(
+            partial.warp_deformer_grids(),
+            full.warp_deformer_grids()
+        ));
+    }
+}
+
+#[test]
+fn subsets_draw_like_the_full_puppet() {
+    let puppet = parse_puppet(&GLUED.to_moc3()).unwrap();
+    // The first limb is glued to the second, which is turned by other parameters.
+    let subset = puppet.part_subset("PartLimb0").unwrap();
+    assert_eq!(subset.glues().len(), 1);
+
+    let pose = |i, min: f32, max: f32| if i % 2 == 0 { min * 0.7 } else { max * 0.4 };
+    let full = pose_puppet(&puppet, pose);
+    let part = pose_puppet(&subset, pose);
+    let drawn = ..GLUED.meshes_per_limb;
+    assert!(same_positions_of(
+        &part.art_mesh_positions()[drawn],
+        &full.art_mesh_positions()[drawn]
+    ));
+}
+
+#[test]
+fn deferred_matches_full() {
+    let puppet = parse_puppet(&SyntheticModel::TINY.to_moc3()).unwrap();
+    let pose = |i, min: f32, max: f32| if i % 2 == 0 { min * 0.4 } else { max * 0.7 };
+    let full = pose_puppet(&puppet, pose);
+
+    let mut deferred = framedata_for_puppet(&puppet);
+    deferred.set_deferred_deform(&puppet, true).unwrap();
+    let param_data = puppet.param_data();
+    let params: Vec<f32> = (0..param_data.count as usize)
+        .map(|i| pose(i, param_data.mins[i], param_data.maxes[i]))
+        .collect();
+    puppet.update(
+        &params,
+        &vec![1.0; puppet.part_count as usize],
+        &mut deferred,
+    );
+
+    assert_eq!(deferred.art_mesh_opacities(), full.art_mesh_opacities());
+    assert_eq!(deferred.render_order(), full.render_order());
+
+    let deform = deferred.deferred_deform().unwrap();
+    let positions = puppet.keyform_positions();
+    for (i, mesh) in full.art_mesh_positions().iter().enumerate() {
+        let keyforms = deform.keyforms(i);
+        let total: f32 = keyforms.iter().map(|k| k.weight).sum();
+        assert!((total - 1.0).abs() < 1e-5);
+        assert!(keyforms
+            .iter()
+            .all(|k| k.start as usize + mesh.len() <= positions.len()));
+        assert_ne!(deform.parents()[i].kind, MeshParent::NONE);
+    }
+}
+
+#[test]
+fn high_precision_matches_full() {
+    let puppet = parse_puppet(&SyntheticModel::TINY.to_moc3()).unwrap();
+    let param_data = puppet.param_data();
+    let pose = |i, min: f32, max: f32| if i % 2 == 0 { min * 0.4 } else { max * 0.7 };
+    let params: Vec<f32> = (0..param_data.count as usize)
+        .map(|i| pose(i, param_data.mins[i], param_data.maxes[i]))
+        .collect();
+    let opacities = vec![1.0; puppet.part_count as usize];
+
+    let mut precise = framedata_for_puppet(&puppet);
+    precise.set_high_precision(true);
+    puppet.update(&params, &opacities, &mut precise);
+    let full = pose_puppet(&puppet, pose);
+    assert!(same_positions(&precise, &full));
+    assert!(same_positions_of(
+        precise.warp_deformer_grids(),
+        full.warp_deformer_grids()
+    ));
+
+    // Partial updates start from the parents kept from the last one.
+    puppet.update_partial(&[(0, param_data.maxes[0] * 0.8)], &mut precise);
+    let after = |i, min: f32, max: f32| if i == 0 { max * 0.8 } else { pose(i, min, max) };
+    assert!(same_positions(&precise, &pose_puppet(&puppet, after)));
+}
+
+#[test]
+fn extrapolation_only_moves_outside_points() {
+    let puppet = parse_puppet(&SyntheticModel::TINY.to_moc3()).unwrap();
+    let param_data = puppet.param_data();
+    let params: Vec<f32> = param_data.maxes.iter().map(|max| max * 0.9).collect();
+    let opacities = vec![1.0; puppet.part_count as usize];
+    let update_with = |extrapolation| {
+        let mut frame_data = framedata_for_puppet(&puppet);
+        frame_data.set_warp_extrapolation(extrapolation);
+        puppet.update(&params, &opacities, &mut frame_data);
+        frame_data
+    };
+
+    let cubism = update_with(WarpExtrapolation::CubismApprox);
+    let clamped = update_with(WarpExtrapolation::ClampToEdge);
+    let linear = update_with(WarpExtrapolation::LinearExtrapolate);
+    for (i, positions) in cubism.art_mesh_positions().iter().enumerate() {
+        let (clamped, linear) = (
+            &clamped.art_mesh_positions()[i],
+            &linear.art_mesh_positions()[i],
+        );
+
+        // Only the first mesh of every limb sticks out of its warp deformer.
+        if i % SyntheticModel::TINY.meshes_per_limb != 0 {
+            assert_eq!(positions, clamped);
+            assert_eq!(positions, linear);
+            continue;
+        }
+        assert_ne!(positions, clamped);
+        assert_ne!(positions, linear);
+        assert_ne!(clamped, linear);
+
+        // Interpolating within cells never leaves the grid's bounds.
+        let grid = &cubism.warp_deformer_grids()[i / SyntheticModel::TINY.meshes_per_limb];
+        let min = grid.iter().copied().reduce(Vec2::min).unwrap();
+        let max = grid.iter().copied().reduce(Vec2::max).unwrap();
+        for vertex in clamped {
+            assert!(vertex.cmpge(min - 1e-5).all() && vertex.cmple(max + 1e-5).all());
+        }
+    }
+}
+
+#[test]
+fn partial_updates_follow_extrapolation_changes() {
+    let bytes = NESTED.to_moc3();
+    let mut puppet = parse_puppet_ref(&bytes).unwrap();
+    // The first limb's nested rotation deformer, moved below its warp deformer's grid so
+    // extrapolating decides where it ends up.
+    let nested = puppet.object_keyforms(ApplicatorTarget::RotationDeformer, 1)[0];
+    let (applicator, keyforms) = (nested.applicator_index(), nested.keyform_count());
+    let mut edit = puppet.edit_keyforms(applicator).unwrap();
+    for keyform in 0..keyforms {
+        let transform = TransformData {
+            origin: Vec2::new(0.5, 1.5),
+            scale: 0.2,
+            angle: 0.0,
+        };
+        edit.set_transform(keyform, transform).unwrap();
+    }
+
+    let mut params: Vec<f32> = puppet.param_data().maxes.iter().map(|x| x * 0.5).collect();
+    let opacities = vec![1.0; puppet.part_count as usize];
+    let mut frame_data = framedata_for_puppet(&puppet);
+    puppet.update(&params, &opacities, &mut frame_data);
+
+    // Only the nested rotation deformer's parameter changes, so its warp deformer is clean.
+    frame_data.set_warp_extrapolation(WarpExtrapolation::ClampToEdge);
+    params[2] = 10.0;
+    puppet.update_partial(&[(2, params[2])], &mut frame_data);
+
+    let mut expected = framedata_for_puppet(&puppet);
+    expected.set_warp_extrapolation(WarpExtrapolation::ClampToEdge);
+    puppet.update(&params, &opacities, &mut expected);
+    let (partial, full) = (
+        frame_data.rotation_deformers()[1],
+        expected.rotation_deformers()[1],
+    );
+    assert_eq!((partial.origin, partial.angle), (full.origin, full.angle));
+}
+
+#[test]
+fn blend_shapes_are_weighted() {
+    let puppet = parse_puppet(&BLENDED.to_moc3()).unwrap();
+    let (constraint, blend) = (BLENDED.parameters - 1, BLENDED.parameters);
+    let params = |amount: f32, limit: f32| {
+        move |i, _, _| match i {
+            _ if i == blend => amount,
+            _ if i == constraint => limit,
+            _ => 0.0,
+        }
+    };
+    let pose = |amount, limit| pose_puppet(&puppet, params(amount, limit));
+
+    let base = pose(0.0, 0.0);
+    assert!(!same_positions(&pose(1.0, 0.0), &base));
+    // Halfway down the constraint lets half of the blend shape through, the same as only
+    // going halfway up the blend shape parameter.
+    assert!(same_positions(&pose(1.0, -15.0), &pose(0.5, 0.0)));
+    assert!(same_positions(&pose(1.0, -30.0), &base));
+
+    let mut deferred = framedata_for_puppet(&puppet);
+    deferred.set_deferred_deform(&puppet, true).unwrap();
+    let param_data = puppet.param_data();
+    let values: Vec<f32> = (0..param_data.count as usize)
+        .map(|i| params(1.0, -15.0)(i, 0.0, 0.0))
+        .collect();
+    puppet.update(
+        &values,
+        &vec![1.0; puppet.part_count as usize],
+        &mut deferred,
+    );
+
+    // The base keyforms add up to 1, with the blend shape's on top.
+    let deform = deferred.deferred_deform().unwrap();
+    for i in 0..puppet.art_mesh_count as usize {
+        let total: f32 = deform.keyforms(i).iter().map(|k| k.weight).sum();
+        assert!((total - 1.5).abs() < 1e-5);
+    }
+}
+
+#[test]
+fn draw_order_groups_sort_within_limbs() {
+    let limb_major: Vec<u32> = (0..SyntheticModel::TINY.art_mesh_count() as u32).collect();
+    let pose = |_, _, _| 0.0;
+
+    // Every limb's first mesh comes before any of their second ones when they're all in
+    // the same group, but not once each limb gets its own.
+    let flat = pose_puppet(
+        &parse_puppet(&SyntheticModel::TINY.to_moc3()).unwrap(),
+        pose,
+    );
+    assert_ne!(flat.render_order(), limb_major);
+
+    let grouped = SyntheticModel {
+        draw_order_groups: true,
+        ..SyntheticModel::TINY
+    };
+    let puppet = parse_puppet(&grouped.to_moc3()).unwrap();
+    assert_eq!(pose_puppet(&puppet, pose).render_order(), limb_major);
+}
+
+#[test]
+fn optimized_meshes_draw_the_same_triangles() {
+    let model = SyntheticModel {
+        mesh_resolution: 8,
+        ..SyntheticModel::TINY
+    };
+    let original = parse_puppet(&model.to_moc3()).unwrap();
+    let mut optimized = original.clone();
+    let report = optimized.optimize_meshes();
+    assert!(report.acmr_after <= report.acmr_before);
+
+    // Nothing gets merged in the generated meshes, so at most the order changes.
+    assert_eq!(report.merged_vertexes, 0);
+    for (a, b) in original
+        .art_mesh_indices
+        .iter()
+        .zip(&optimized.art_mesh_indices)
+    {
+        let sorted = |x: &[u16]| {
+            let mut triangles: Vec<_> = x.chunks(3).map(|x| x.to_vec()).collect();
+            triangles.sort();
+            triangles
+        };
+        assert_eq!(sorted(a), sorted(b));
+    }
+}
+
+#[test]
+fn compacted_keyforms_match_dense() {
+    for model in [SyntheticModel::TINY, BLENDED] {
+        let dense = parse_puppet(&model.to_moc3()).unwrap();
+        let mut compacted = dense.clone();
+        let report = compacted.compact_keyforms(0.0);
+        assert!(report.compacted_applicators > 0);
+        assert!(report.bytes_after < report.bytes_before);
+        assert_eq!(
+            compacted.memory_report().total_bytes(),
+            dense.memory_report().total_bytes() - (report.bytes_before - report.bytes_after)
+        );
+
+        let pose = |i, min: f32, max: f32| if i % 2 == 0 { min * 0.3 } else { max * 0.8 };
+        assert!(same_positions(
+            &pose_puppet(&dense, pose),
+            &pose_puppet(&compacted, pose)
+        ));
+    }
+}
+
+#[test]
+fn compacted_keyforms_are_never_deferred() {
+    let dense = parse_puppet(&SyntheticModel::TINY.to_moc3()).unwrap();
+    let mut compacted = dense.clone();
+    // Nothing moves by this much, so every art mesh gets compacted down to its first keyform.
+    compacted.compact_keyforms(1.0);
+
+    let mut frame_data = framedata_for_puppet(&compacted);
+    assert_eq!(
+        frame_data.set_deferred_deform(&compacted, true),
+        Err(DeferredDeformError::CompactedKeyforms)
+    );
+    assert!(frame_data.deferred_deform().is_none());
+    assert_eq!(frame_data.set_deferred_deform(&compacted, false), Ok(()));
+
+    // Frame data deferring from before the puppet was compacted deforms everything again,
+    // whether the next update is a full one or a partial one.
+    let pose = |i, min: f32, max: f32| if i % 2 == 0 { min * 0.3 } else { max * 0.8 };
+    let expected = pose_puppet(&compacted, pose);
+    let param_data = dense.param_data();
+    let params: Vec<f32> = (0..param_data.count as usize)
+        .map(|i| pose(i, param_data.mins[i], param_data.maxes[i]))
+        .collect();
+    let opacities = vec![1.0; dense.part_count as usize];
+
+    let mut full = framedata_for_puppet(&dense);
+    full.set_deferred_deform(&dense, true).unwrap();
+    dense.update(&params, &opacities, &mut full);
+    compacted.update(&params, &opacities, &mut full);
+    assert!(full.deferred_deform().is_none());
+    assert!(same_positions(&full, &expected));
+
+    let mut partial = framedata_for_puppet(&dense);
+    partial.set_deferred_deform(&dense, true).unwrap();
+    dense.update(&params, &opacities, &mut partial);
+    compacted.update_partial(&[(0, params[0])], &mut partial);
+    assert!(partial.deferred_deform().is_none());
+    assert!(same_positions(&partial, &expected));
+}
+
+#[test]
+fn keyforms_can_be_inspected() {
+    let puppet = parse_puppet(&BLENDED.to_moc3()).unwrap();
+    let mut compacted = puppet.clone();
+    compacted.compact_keyforms(0.0);
+
+    // Art meshes are laid out over two parameters, with the blend shape after them.
+    let keyforms = puppet.object_keyforms(ApplicatorTarget::ArtMesh, 0);
+    let [base, blend] = keyforms.as_slice() else {
+        panic!("expected a base and a blend shape");
+    };
+    let axes: Vec<_> = base.axes().map(|x| (x.parameter_id, x.keys)).collect();
+    assert_eq!(
+        axes,
+        [
+            ("Param0", &[-30.0, 0.0, 30.0][..]),
+            ("Param1", &[-30.0, 0.0, 30.0][..])
+        ]
+    );
+    assert_eq!(base.keyform_count(), 9);
+    assert_eq!(base.keyform_index(&[2, 1]), Some(5));
+    assert_eq!(base.keyform_index(&[3, 0]), None);
+    assert_eq!(base.opacity(4), Some(1.0));
+    assert!(base.transform(0).is_none());
+
+    assert_eq!(blend.axes().next().unwrap().parameter_id, "ParamBlend");
+    assert!(blend.blend_constraints().is_some());
+    assert!(blend.opacity(0).is_none());
+
+    // Compacted keyforms read back the same as the shared table, give or take rounding.
+    for (dense, compacted) in puppet.keyforms().zip(compacted.keyforms()) {
+        for keyform in 0..dense.keyform_count() {
+            let (Some(a), Some(b)) = (dense.positions(keyform), compacted.positions(keyform))
+            else {
+                continue;
+            };
+            assert!(same_positions_of(&[a.into_owned()], &[b.into_owned()]));
+        }
+    }
+
+    let rotation = &puppet.object_keyforms(ApplicatorTarget::RotationDeformer, 0)[0];
+    assert_eq!(rotation.keyform_count(), 3);
+    assert!(rotation.positions(0).is_none());
+    assert!(rotation.transform(2).is_some());
+}
+
+#[test]
+fn edited_keyforms_are_redone_by_partial_updates() {
+    let bytes = SyntheticModel::TINY.to_moc3();
+    let mut puppet = parse_puppet_ref(&bytes).unwrap();
+    let mut frame_data = pose_puppet(&puppet, |_, _, _| 0.0);
+    let before = frame_data.clone();
+
+    // Every parameter at 0 lands right on the middle keyform.
+    let base = puppet.object_keyforms(ApplicatorTarget::ArtMesh, 0)[0];
+    let (applicator, middle) = (
+        base.applicator_index(),
+        base.keyform_index(&[1, 1]).unwrap(),
+    );
+    let moved: Vec<Vec2> = base
+        .positions(middle)
+        .unwrap()
+        .iter()
+        .map(|x| *x + Vec2::new(0.1, 0.0))
+        .collect();
+
+    let mut edit = puppet.edit_keyforms(applicator).unwrap();
+    assert_eq!(
+        edit.set_positions(middle, &moved[1..]),
+        Err(KeyformEditError::PositionCount {
+            expected: moved.len(),
+            actual: moved.len() - 1
+        })
+    );
+    assert_eq!(
+        edit.set_transform(middle, TransformData::ZERO),
+        Err(KeyformEditError::NoSuchValue)
+    );
+    edit.set_positions(middle, &moved).unwrap();
+    edit.set_opacity(middle, 0.5).unwrap();
+
+    // The last parameter doesn't drive the first art mesh, which is redone anyway.
+    puppet.update_partial(
+        &[(SyntheticModel::TINY.parameters - 1, 0.0)],
+        &mut frame_data,
+    );
+    assert!(!same_positions(&frame_data, &before));
+    assert!(same_positions(
+        &frame_data,
+        &pose_puppet(&puppet, |_, _, _| 0.0)
+    ));
+    assert_eq!(frame_data.art_mesh_opacities()[0], 0.5);
+
+    // The moc3 the puppet was borrowing from is left alone.
+    let original = parse_puppet(&bytes).unwrap();
+    assert!(same_positions(
+        &pose_puppet(&original, |_, _, _| 0.0),
+        &before
+    ));
+}
+
+#[test]
+fn hooks_run_between_stages() {
+    let puppet = parse_puppet(&SyntheticModel::TINY.to_moc3()).unwrap();
+    let params = vec![0.0; puppet.param_data().count as usize];
+    let parts = vec![1.0; puppet.part_count as usize];
+    let plain = pose_puppet(&puppet, |_, _, _| 0.0);
+
+    let mut stages = Vec::new();
+    let mut frame_data = framedata_for_puppet(&puppet);
+    puppet.update_with_hook(&params, &parts, &mut frame_data, |stage, frame_data| {
+        stages.push(stage);
+        if stage == UpdateStage::Applicators {
+            frame_data.rotation_deformers_mut()[0].angle += 30.0;
+        }
+    });
+
+    assert_eq!(
+        stages,
+        [
+            UpdateStage::Parameters,
+            UpdateStage::Applicators,
+            UpdateStage::Deformers,
+            UpdateStage::Glue,
+            UpdateStage::RenderOrder,
+        ]
+    );
+    // Only the first limb is in the rotated deformer.
+    let meshes = SyntheticModel::TINY.meshes_per_limb;
+    let positions = frame_data.art_mesh_positions();
+    assert!(!same_positions_of(
+        &positions[..meshes],
+        &plain.art_mesh_positions()[..meshes]
+    ));
+    assert!(same_positions_of(
+        &positions[meshes..],
+        &plain.art_mesh_positions()[meshes..]
+    ));
+}
+
+#[test]
+fn diff_finds_added_objects() {
+    let puppet = parse_puppet(&SyntheticModel::TINY.to_moc3()).unwrap();
+    assert!(puppet.diff(&puppet).is_empty());
+
+    let bigger = SyntheticModel {
+        limbs: 3,
+        parameters: 4,
+        ..SyntheticModel::TINY
+    };
+    let diff = puppet.diff(&parse_puppet(&bigger.to_moc3()).unwrap());
+    assert_eq!(diff.parameters.added, ["Param3"]);
+    assert_eq!(diff.parts.added, ["PartLimb2"]);
+    assert_eq!(
+        diff.art_meshes.added.len(),
+        SyntheticModel::TINY.meshes_per_limb
+    );
+    assert!(diff.art_meshes.removed.is_empty());
+    assert!(diff.deformers.added.contains(&"Warp2".to_string()));
+}
+
+#[test]
+fn sections_stay_apart() {
+    let bytes = BLENDED.to_moc3();
+    let read: Moc3Data = Cursor::new(&bytes).read_le().unwrap();
+
+    let mut sections = read.sections();
+    sections.retain(|x| x.len > 0);
+    sections.sort_by_key(|x| x.offset);
+    assert!(sections.last().unwrap().range().end <= bytes.len());
+    assert!(sections.windows(2).all(|x| x[0].range().end <= x[1].offset));
+
+    let positions = sections
+        .iter()
+        .find(|x| x.name == "keyform_positions.coords")
+        .unwrap();
+    assert_eq!(positions.offset, read.positions_offset());
+}
+
+#[test]
+fn newer_versions_load_best_effort() {
+    let mut bytes = BLENDED.to_moc3();
+    let (_, skipped) = parse_puppet_best_effort(&bytes).unwrap();
+    assert!(skipped.is_empty());
+
+    // Right after the magic.
+    bytes[4] = 9;
+    assert!(parse_puppet(&bytes).is_err());
+    let (puppet, skipped) = parse_puppet_best_effort(&bytes).unwrap();
+    assert_eq!(skipped.unknown_version, Some(9));
+    assert_eq!(puppet.art_mesh_count as usize, BLENDED.art_mesh_count());
+}
+
+#[test]
+fn corrupted_offsets_are_errors() {
+    let bytes = BLENDED.to_moc3();
+    let read: Moc3Data = Cursor::new(&bytes).read_le().unwrap();
+
+    for name in [
+        "art_meshes.vertex_counts",
+        "art_meshes.keyform_sources_starts",
+        "art_meshes.parent_deformer_indices",
+        "deformers.specific_sources_indices",
+        "parameters.parameter_binding_sources_counts",
+        "warp_deformer_keyforms.keyform_position_sources_starts",
+        // The first two triangle corners, pointing past the mesh's vertexes.
+        "vertex_indices.indices",
+    ] {
+        let section = read
+            .sections()
+            .into_iter()
+            .find(|x| x.name == name)
+            .unwrap();
+        let mut corrupted = bytes.clone();
+        corrupted[section.offset..section.offset + 4].copy_from_slice(&i32::MAX.to_le_bytes());
+        assert!(
+            matches!(parse_puppet(&corrupted), Err(ParseError::Layout(_))),
+            "{name}"
+        );
+        assert!(parse_puppet_ref(&corrupted).is_err(), "{name}");
+    }
+
+    // Warp deformers without any cells can't deform anything.
+    for name in ["warp_deformers.rows", "warp_deformers.columns"] {
+        let section = read
+            .sections()
+            .into_iter()
+            .find(|x| x.name == name)
+            .unwrap();
+        let mut corrupted = bytes.clone();
+        corrupted[section.offset..section.offset + 4].copy_from_slice(&0u32.to_le_bytes());
+        assert!(
+            matches!(parse_puppet(&corrupted), Err(ParseError::Layout(_))),
+            "{name}"
+        );
+    }
+
+    // Meshes are drawn as whole triangles.
+    let section = read
+        .sections()
+        .into_iter()
+        .find(|x| x.name == "art_meshes.vertex_index_sources_counts")
+        .unwrap();
+    let mut corrupted = bytes.clone();
+    corrupted[section.offset..section.offset + 4].copy_from_slice(&5u32.to_le_bytes());
+    match parse_puppet(&corrupted) {
+        Err(ParseError::TriangleCount(e)) => assert_eq!((e.index, e.index_count), (0, 5)),
+        other => panic!("expected a triangle count error, got {other:?}"),
+    }
+    assert!(parse_puppet_ref(&corrupted).is_err());
+}
+
+#[test]
+fn warp_grids_must_fit_their_points() {
+    let bytes = SyntheticModel::TINY.to_moc3();
+    let read: Moc3Data = Cursor::new(&bytes).read_le().unwrap();
+    let section = |name| {
+        read.sections()
+            .into_iter()
+            .find(|x| x.name == name)
+            .unwrap()
+            .offset
+    };
+    let write = |bytes: &mut Vec<u8>, name, value: u32| {
+        let offset = section(name);
+        bytes[offset..offset + 4].copy_from_slice(&value.to_le_bytes());
+    };
+
+    let mut corrupted = bytes.clone();
+    write(&mut corrupted, "warp_deformers.vertex_counts", 5);
+    match parse_puppet(&corrupted) {
+        Err(ParseError::WarpGrid(e)) => assert_eq!((e.index, e.vertex_count), (0, 5)),
+        other => panic!("expected a warp grid error, got {other:?}"),
+    }
+
+    // Rows and columns counting points rather than cells still add up to the same grid.
+    let grid = SyntheticModel::TINY.warp_resolution as u32 + 1;
+    let mut by_points = bytes.clone();
+    write(&mut by_points, "warp_deformers.rows", grid);
+    write(&mut by_points, "warp_deformers.columns", grid);
+    let expected = pose_puppet(&parse_puppet(&bytes).unwrap(), |_, _, max| max * 0.6);
+    let reconciled = pose_puppet(&parse_puppet(&by_points).unwrap(), |_, _, max| max * 0.6);
+    assert!(same_positions(&expected, &reconciled));
+}
+
+#[test]
+fn malformed_values_are_errors() {
+    for (model, name, element, value) in [
+        // Glued to itself.
+        (GLUED, "glues.art_mesh_indices_b", 0, 0),
+        // Keys out of order, then ones not covering the whole parameter.
+        (SyntheticModel::TINY, "keys.values", 0, 10.0f32.to_bits()),
+        (SyntheticModel::TINY, "keys.values", 0, (-20.0f32).to_bits()),
+        // Fewer keyforms than the keys of its bindings add up to.
+        (
+            SyntheticModel::TINY,
+            "art_meshes.keyform_sources_counts",
+            0,
+            2,
+        ),
+        (
+            BLENDED,
+            "blend_shape_keyform_bindings.keyform_sources_blend_shape_counts",
+            0,
+            1,
+        ),
+        // The second warp deformer using the first one's data.
+        (
+            SyntheticModel::TINY,
+            "deformers.specific_sources_indices",
+            3,
+            0,
+        ),
+    ] {
+        let bytes = model.to_moc3();
+        let read: Moc3Data = Cursor::new(&bytes).read_le().unwrap();
+        let offset = read
+            .sections()
+            .into_iter()
+            .find(|x| x.name == name)
+            .unwrap()
+            .offset
+            + element * 4;
+        let mut corrupted = bytes.clone();
+        corrupted[offset..offset + 4].copy_from_slice(&value.to_le_bytes());
+        match parse_puppet(&corrupted) {
+            Err(ParseError::Layout(e)) => {
+                assert_eq!(e.what, name);
+                assert!(e.to_string().ends_with("is malformed"), "{e}");
+            }
+            other => panic!("expected {name} to be malformed, got {other:?}"),
+        }
+        assert!(parse_puppet_ref(&corrupted).is_err(), "{name}");
+    }
+}
+
+#[test]
+fn parameter_keys_are_read() {
+    let old = parse_puppet(&SyntheticModel::TINY.to_moc3()).unwrap();
+    assert!(old.param_data().keys.iter().all(|x| x.is_empty()));
+    assert_eq!(old.param_data().snap_to_key(0, 29.96), 29.96);
+
+    let puppet = parse_puppet(&BLENDED.to_moc3()).unwrap();
+    let param_data = puppet.param_data();
+    assert_eq!(param_data.keys[0], [-30.0, 0.0, 30.0]);
+    assert_eq!(param_data.keys[BLENDED.parameters], [0.0, 1.0]);
+    // Shown with one decimal, so anything within 0.05 of a key looks like it.
+    assert_eq!(param_data.snap_to_key(0, 29.96), 30.0);
+    assert_eq!(param_data.snap_to_key(0, 29.9), 29.9);
+}
+
+#[test]
+fn bad_inputs_are_sanitized() {
+    let puppet = parse_puppet(&SyntheticModel::TINY.to_moc3()).unwrap();
+    let parts = vec![1.0; puppet.part_count as usize];
+    let mut frame_data = framedata_for_puppet(&puppet);
+    // Infinities have always been clamped, so that's what happens unless asked otherwise.
+    assert_eq!(frame_data.input_sanitization(), InputSanitization::Disabled);
+
+    for (sanitization, expected) in [
+        (InputSanitization::Disabled, [f32::NAN, 30.0, -30.0]),
+        (InputSanitization::UseDefault, [0.0, 0.0, 0.0]),
+        (InputSanitization::UsePrevious, [5.0, 6.0, 7.0]),
+    ] {
+        frame_data.set_input_sanitization(sanitization);
+        puppet.update(&[5.0, 6.0, 7.0], &parts, &mut frame_data);
+        assert!(frame_data.sanitized_params().is_empty());
+
+        puppet.update(
+            &[f32::NAN, f32::INFINITY, f32::NEG_INFINITY],
+            &parts,
+            &mut frame_data,
+        );
+        assert_eq!(frame_data.sanitized_params(), [0, 1, 2]);
+        let params = frame_data.params();
+        assert!(
+            params
+                .iter()
+                .zip(expected)
+                .all(|(a, b)| a == &b || (a.is_nan() && b.is_nan())),
+            "{sanitization:?}: {params:?}"
+        );
+    }
+}
+
+#[test]
+fn deformers_are_indexed_by_kind() {
+    let puppet = parse_puppet(&NESTED.to_moc3()).unwrap();
+    let frame_data = pose_puppet(&puppet, |_, _, max| max * 0.5);
+
+    // Each limb stores its rotation deformer, then its warp deformer, then the nested one.
+    for (id, kind, index) in [
+        ("Rotation0", DeformerKind::Rotation, 0),
+        ("Warp0", DeformerKind::Warp, 0),
+        ("Nested0", DeformerKind::Rotation, 1),
+        ("Rotation1", DeformerKind::Rotation, 2),
+        ("Warp1", DeformerKind::Warp, 1),
+        ("Nested1", DeformerKind::Rotation, 3),
+    ] {
+        assert_eq!(puppet.deformer_index(id), Some((kind, index)));
+        assert_eq!(puppet.deformer_id(kind, index), Some(id));
+    }
+    assert_eq!(puppet.deformer_index("Missing"), None);
+    assert_eq!(puppet.deformer_id(DeformerKind::Warp, 2), None);
+
+    // The index is the one frame data is laid out by.
+    let (_, warp) = puppet.deformer_index("Warp1").unwrap();
+    let (_, rotation) = puppet.deformer_index("Nested1").unwrap();
+    assert_eq!(frame_data.warp_deformer_grids().len(), 2);
+    assert_eq!(frame_data.rotation_deformers().len(), 4);
+    assert_eq!(
+        puppet.warp_deformer_grid_sizes()[warp],
+        [NESTED.warp_resolution as u32; 2]
+    );
+    // Only the nested rotation deformers get scaled down by a warp deformer above them.
+    assert_eq!(frame_data.rotation_deformers()[rotation - 1].scale, 1.0);
+    assert!(frame_data.rotation_deformers()[rotation].scale < 1.0);
+}
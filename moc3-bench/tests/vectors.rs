@@ -0,0 +1,102 @@
+//! Poses generated models and compares the results against the snapshots in
+//! `tests/vectors`, catching changes to how models deform even where they're too small to
+//! show up in the golden images. Run with `MOC3_BLESS=1` to write new snapshots after an
+//! intended change.
+
+use std::path::PathBuf;
+
+use moc3_bench::{pose_puppet, SyntheticModel};
+use moc3_rs::{
+    parse_puppet,
+    puppet::{FrameSnapshot, SnapshotTolerance},
+};
+
+fn snapshot(model: SyntheticModel, params: impl Fn(usize, f32, f32) -> f32) -> FrameSnapshot {
+    let puppet = parse_puppet(&model.to_moc3()).unwrap();
+    pose_puppet(&puppet, params).snapshot()
+}
+
+fn check(name: &str, snapshot: FrameSnapshot) {
+    let path = PathBuf::from(env!("CARGO_MANIFEST_DIR"))
+        .join("tests/vectors")
+        .join(format!("{name}.txt"));
+
+    if std::env::var_os("MOC3_BLESS").is_some() {
+        std::fs::create_dir_all(path.parent().unwrap()).unwrap();
+        let text = format!(
+            "# {name}, written by tests/vectors.rs\n{}",
+            snapshot.to_text()
+        );
+        std::fs::write(&path, text).unwrap();
+        return;
+    }
+
+    let text = match std::fs::read_to_string(&path) {
+        Ok(text) => text,
+        Err(e) => panic!(
+            "couldn't read {}: {e} (run with MOC3_BLESS=1 to create it)",
+            path.display()
+        ),
+    };
+    let expected = FrameSnapshot::from_text(&text).unwrap();
+    if let Err(e) = expected.compare(&snapshot, SnapshotTolerance::default()) {
+        panic!("{name} differs from {}: {e}", path.display());
+    }
+}
+
+#[test]
+fn text_round_trips() {
+    let snapshot = snapshot(SyntheticModel::TINY, |i, min, max| {
+        if i % 2 == 0 {
+            min * 0.4
+        } else {
+            max
+        }
+    });
+    let read = FrameSnapshot::from_text(&snapshot.to_text()).unwrap();
+    assert_eq!(read, snapshot);
+    assert_eq!(read.digest(1e-4), snapshot.digest(1e-4));
+}
+
+#[test]
+fn different_poses_mismatch() {
+    let rest = snapshot(SyntheticModel::TINY, |_, _, _| 0.0);
+    let moved = snapshot(SyntheticModel::TINY, |_, _, max| max);
+    assert!(rest.compare(&moved, SnapshotTolerance::default()).is_err());
+    assert_ne!(rest.digest(1e-4), moved.digest(1e-4));
+}
+
+#[test]
+fn defaults() {
+    check(
+        "tiny_defaults",
+        snapshot(SyntheticModel::TINY, |_, _, _| 0.0),
+    );
+}
+
+#[test]
+fn in_between() {
+    check(
+        "tiny_in_between",
+        snapshot(SyntheticModel::TINY, |i, min, max| {
+            if i % 2 == 0 {
+                min * 0.4
+            } else {
+                max * 0.7
+            }
+        }),
+    );
+}
+
+#[test]
+fn blend_shapes() {
+    let model = SyntheticModel {
+        parameters: 6,
+        blend_shapes: true,
+        ..SyntheticModel::TINY
+    };
+    check(
+        "tiny_blend_shapes",
+        snapshot(model, |i, _, max| if i == 5 { 0.6 } else { max * 0.5 }),
+    );
+}
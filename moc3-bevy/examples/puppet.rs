@@ -0,0 +1,57 @@
+//! Shows a model with its first parameter swaying back and forth.
+//!
+//! `cargo run -p moc3-bevy --example puppet -- model/model.model3.json`, with the path
+//! relative to the `assets` directory.
+
+use bevy::prelude::*;
+use moc3_bevy::{Moc3Model, Moc3Plugin, Moc3Puppet};
+
+#[derive(Resource)]
+struct ModelPath(String);
+
+fn main() {
+    let path = std::env::args()
+        .nth(1)
+        .unwrap_or("model/model.model3.json".to_string());
+
+    App::new()
+        .add_plugins((DefaultPlugins, Moc3Plugin))
+        .insert_resource(ModelPath(path))
+        .add_systems(Startup, setup)
+        .add_systems(Update, sway)
+        .run();
+}
+
+fn setup(
+    mut commands: Commands,
+    asset_server: Res<AssetServer>,
+    mut images: ResMut<Assets<Image>>,
+    path: Res<ModelPath>,
+) {
+    let target = images.add(Moc3Puppet::target_image(1000, 1000));
+
+    commands.spawn(Camera2dBundle::default());
+    commands.spawn((
+        Moc3Puppet::new(asset_server.load(path.0.clone()), target.clone()),
+        SpriteBundle {
+            texture: target,
+            ..default()
+        },
+    ));
+}
+
+fn sway(time: Res<Time>, models: Res<Assets<Moc3Model>>, mut puppets: Query<&mut Moc3Puppet>) {
+    for mut puppet in &mut puppets {
+        let Some(model) = models.get(&puppet.model) else {
+            continue;
+        };
+        // The parameters are only filled in after the model's first update.
+        if puppet.params.is_empty() {
+            continue;
+        }
+
+        let param_data = model.puppet.param_data();
+        let t = time.elapsed_seconds().sin() * 0.5 + 0.5;
+        puppet.params[0] = param_data.mins[0] + (param_data.maxes[0] - param_data.mins[0]) * t;
+    }
+}
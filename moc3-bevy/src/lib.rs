@@ -0,0 +1,336 @@
+//! Draws puppets inside Bevy, through the wgpu renderer.
+//!
+//! Add [Moc3Plugin], load a `.model3.json` (or a bare `.moc3`, drawn with placeholder
+//! colors) as a [Moc3Model], and spawn a [Moc3Puppet] with an image from
+//! [Moc3Puppet::target_image] to draw into. The image can then be shown like any other,
+//! for example with a sprite.
+//!
+//! Puppets are drawn with premultiplied alpha, which Bevy's sprites don't expect, so
+//! partially transparent edges come out slightly darker than they should.
+
+use std::{path::Path, sync::Arc};
+
+use bevy::{
+    asset::{io::Reader, AssetLoader, AsyncReadExt, LoadContext, ReadAssetBytesError},
+    prelude::*,
+    render::{
+        render_asset::RenderAssets,
+        render_graph::{Node, NodeRunError, RenderGraph, RenderGraphContext},
+        render_resource::{Texture, TextureView},
+        renderer::{RenderContext, RenderDevice, RenderQueue},
+        Extract, RenderApp,
+    },
+    utils::{BoxedFuture, HashMap},
+};
+use image::RgbaImage;
+use moc3_rs::{
+    parse_puppet,
+    puppet::{framedata_for_puppet, Puppet, PuppetFrameData},
+    ParseError,
+};
+use moc3_wgpu::renderer::{new_renderer, Renderer};
+use serde::Deserialize;
+use thiserror::Error;
+use wgpu::{
+    Extent3d, TextureDescriptor, TextureDimension, TextureFormat, TextureUsages,
+    TextureViewDescriptor,
+};
+
+/// The render graph node drawing every puppet into its image, before any cameras run.
+pub const PUPPET_NODE: &str = "moc3_puppets";
+
+pub struct Moc3Plugin;
+
+impl Plugin for Moc3Plugin {
+    fn build(&self, app: &mut App) {
+        app.init_asset::<Moc3Model>()
+            .init_asset_loader::<Moc3Loader>()
+            .add_systems(PostUpdate, update_puppets);
+
+        let Ok(render_app) = app.get_sub_app_mut(RenderApp) else {
+            return;
+        };
+
+        render_app
+            .init_resource::<PuppetRenderers>()
+            .add_systems(ExtractSchedule, prepare_puppets);
+
+        let mut graph = render_app.world.resource_mut::<RenderGraph>();
+        graph.add_node(PUPPET_NODE, PuppetNode);
+        graph.add_node_edge(PUPPET_NODE, bevy::render::main_graph::node::CAMERA_DRIVER);
+    }
+}
+
+/// A model and its textures, loaded from a `.model3.json` or a bare `.moc3`.
+#[derive(Asset, TypePath, Debug, Clone)]
+pub struct Moc3Model {
+    pub puppet: Arc<Puppet>,
+    pub textures: Arc<[RgbaImage]>,
+}
+
+/// A posed instance of a [Moc3Model], drawn into [Moc3Puppet::target] every frame.
+#[derive(Component)]
+pub struct Moc3Puppet {
+    pub model: Handle<Moc3Model>,
+    pub target: Handle<Image>,
+    /// Filled in with the model's defaults once it's loaded.
+    pub params: Vec<f32>,
+    /// Filled in with full opacity once the model is loaded.
+    pub part_opacities: Vec<f32>,
+
+    // The model the frame data was made for, which changes if the handle is swapped out.
+    loaded: Option<AssetId<Moc3Model>>,
+    frame_data: Option<PuppetFrameData>,
+}
+
+impl Moc3Puppet {
+    pub fn new(model: Handle<Moc3Model>, target: Handle<Image>) -> Self {
+        Self {
+            model,
+            target,
+            params: Vec::new(),
+            part_opacities: Vec::new(),
+            loaded: None,
+            frame_data: None,
+        }
+    }
+
+    /// The results of the last update, once the model has loaded.
+    pub fn frame_data(&self) -> Option<&PuppetFrameData> {
+        self.frame_data.as_ref()
+    }
+
+    /// Makes an image a puppet can be drawn into.
+    pub fn target_image(width: u32, height: u32) -> Image {
+        let mut image = Image::new_fill(
+            Extent3d {
+                width,
+                height,
+                depth_or_array_layers: 1,
+            },
+            TextureDimension::D2,
+            &[0; 4],
+            TextureFormat::Rgba8UnormSrgb,
+        );
+
+        // Puppets are drawn somewhere else and copied in, see [PuppetRenderer].
+        image.texture_descriptor.usage |= TextureUsages::COPY_DST;
+        image
+    }
+}
+
+fn update_puppets(models: Res<Assets<Moc3Model>>, mut puppets: Query<&mut Moc3Puppet>) {
+    for mut puppet in &mut puppets {
+        let puppet = &mut *puppet;
+        let Some(model) = models.get(&puppet.model) else {
+            continue;
+        };
+
+        if puppet.loaded != Some(puppet.model.id()) {
+            puppet.params = model.puppet.param_data().defaults.clone();
+            puppet.part_opacities = vec![1.0; model.puppet.part_count as usize];
+            puppet.frame_data = Some(framedata_for_puppet(&model.puppet));
+            puppet.loaded = Some(puppet.model.id());
+        }
+
+        let frame_data = puppet.frame_data.as_mut().unwrap();
+        model
+            .puppet
+            .update(&puppet.params, &puppet.part_opacities, frame_data);
+    }
+}
+
+// The renderer blends in sRGB like Cubism does, so it draws into a plain texture that's
+// then copied into the sRGB image, which everything sampling it reads as linear colors.
+// Drawing through a plain view of the image instead isn't supported by WebGL or OpenGL.
+struct PuppetRenderer {
+    model: AssetId<Moc3Model>,
+    image: Texture,
+    size: Extent3d,
+    target: Texture,
+    view: TextureView,
+    renderer: Renderer,
+}
+
+#[derive(Resource, Default)]
+struct PuppetRenderers(HashMap<Entity, PuppetRenderer>);
+
+// Preparing only writes the frame data to buffers, so it's done straight from the main
+// world instead of copying all of the frame data over first.
+fn prepare_puppets(
+    puppets: Extract<Query<(Entity, &Moc3Puppet)>>,
+    models: Extract<Res<Assets<Moc3Model>>>,
+    images: Res<RenderAssets<Image>>,
+    device: Res<RenderDevice>,
+    queue: Res<RenderQueue>,
+    mut renderers: ResMut<PuppetRenderers>,
+) {
+    renderers.0.retain(|entity, _| puppets.contains(*entity));
+
+    for (entity, puppet) in &puppets {
+        let (Some(model), Some(frame_data), Some(image)) = (
+            models.get(&puppet.model),
+            puppet.frame_data.as_ref(),
+            images.get(&puppet.target),
+        ) else {
+            renderers.0.remove(&entity);
+            continue;
+        };
+
+        let size = Extent3d {
+            width: image.size.x as u32,
+            height: image.size.y as u32,
+            depth_or_array_layers: 1,
+        };
+
+        let is_stale = !renderers
+            .0
+            .get(&entity)
+            .is_some_and(|x| x.model == puppet.model.id() && x.image.id() == image.texture.id());
+        if is_stale {
+            let renderer = new_renderer(
+                &model.puppet,
+                device.wgpu_device(),
+                &queue.0,
+                TextureFormat::Rgba8Unorm,
+                &model.textures,
+            );
+            let target = device.create_texture(&TextureDescriptor {
+                size,
+                mip_level_count: 1,
+                sample_count: 1,
+                dimension: TextureDimension::D2,
+                format: TextureFormat::Rgba8Unorm,
+                usage: TextureUsages::RENDER_ATTACHMENT | TextureUsages::COPY_SRC,
+                view_formats: &[],
+                label: Some("moc3 puppet"),
+            });
+            let view = target.create_view(&TextureViewDescriptor::default());
+
+            renderers.0.insert(
+                entity,
+                PuppetRenderer {
+                    model: puppet.model.id(),
+                    image: image.texture.clone(),
+                    size,
+                    target,
+                    view,
+                    renderer,
+                },
+            );
+        }
+
+        renderers.0.get_mut(&entity).unwrap().renderer.prepare(
+            device.wgpu_device(),
+            &queue.0,
+            size,
+            frame_data,
+        );
+    }
+}
+
+struct PuppetNode;
+
+impl Node for PuppetNode {
+    fn run(
+        &self,
+        _graph: &mut RenderGraphContext,
+        render_context: &mut RenderContext,
+        world: &World,
+    ) -> Result<(), NodeRunError> {
+        let encoder = render_context.command_encoder();
+        for puppet in world.resource::<PuppetRenderers>().0.values() {
+            puppet.renderer.render(&puppet.view, encoder);
+            encoder.copy_texture_to_texture(
+                puppet.target.as_image_copy(),
+                puppet.image.as_image_copy(),
+                puppet.size,
+            );
+        }
+        Ok(())
+    }
+}
+
+#[derive(Deserialize)]
+#[serde(rename_all = "PascalCase")]
+struct Model3 {
+    file_references: FileReferences,
+}
+
+#[derive(Deserialize)]
+#[serde(rename_all = "PascalCase")]
+struct FileReferences {
+    moc: String,
+    #[serde(default)]
+    textures: Vec<String>,
+}
+
+#[derive(Error, Debug)]
+pub enum Moc3LoadError {
+    #[error("could not read asset")]
+    Io(#[from] std::io::Error),
+    #[error("could not read a file the model3.json refers to")]
+    ReadBytes(#[from] ReadAssetBytesError),
+    #[error("could not parse model3.json")]
+    Json(#[from] serde_json::Error),
+    #[error(transparent)]
+    Parse(#[from] ParseError),
+    #[error("could not decode texture")]
+    Texture(#[from] image::ImageError),
+}
+
+#[derive(Default)]
+pub struct Moc3Loader;
+
+impl AssetLoader for Moc3Loader {
+    type Asset = Moc3Model;
+    type Settings = ();
+    type Error = Moc3LoadError;
+
+    fn load<'a>(
+        &'a self,
+        reader: &'a mut Reader,
+        _settings: &'a (),
+        load_context: &'a mut LoadContext,
+    ) -> BoxedFuture<'a, Result<Moc3Model, Moc3LoadError>> {
+        Box::pin(async move {
+            let mut bytes = Vec::new();
+            reader.read_to_end(&mut bytes).await?;
+
+            let is_moc3 = load_context.path().extension().is_some_and(|x| x == "moc3");
+            if is_moc3 {
+                return Ok(Moc3Model {
+                    puppet: Arc::new(parse_puppet(&bytes)?),
+                    textures: Arc::new([]),
+                });
+            }
+
+            let model3: Model3 = serde_json::from_slice(&bytes)?;
+            let directory = load_context.path().parent().unwrap_or(Path::new(""));
+            let references = model3.file_references;
+
+            let moc3_path = directory.join(&references.moc);
+            let texture_paths: Vec<_> = references
+                .textures
+                .iter()
+                .map(|x| directory.join(x))
+                .collect();
+
+            let puppet = parse_puppet(&load_context.read_asset_bytes(moc3_path).await?)?;
+            let mut textures = Vec::new();
+            for path in texture_paths {
+                let bytes = load_context.read_asset_bytes(path).await?;
+                textures.push(image::load_from_memory(&bytes)?.into_rgba8());
+            }
+
+            Ok(Moc3Model {
+                puppet: Arc::new(puppet),
+                textures: textures.into(),
+            })
+        })
+    }
+
+    fn extensions(&self) -> &[&str] {
+        &["model3.json", "moc3"]
+    }
+}
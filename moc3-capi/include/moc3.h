@@ -0,0 +1,75 @@
+/*
+ * C API for moc3-rs. See moc3-capi/src/lib.rs for the details of every function.
+ *
+ * Pointers returned from a model stay valid until it's next updated or freed. Panics are
+ * caught, making functions return what they would for bad arguments, and updates false.
+ */
+
+#ifndef MOC3_H
+#define MOC3_H
+
+#include <stdbool.h>
+#include <stddef.h>
+#include <stdint.h>
+
+#ifdef __cplusplus
+extern "C" {
+#endif
+
+typedef struct Moc3Model Moc3Model;
+
+/* The bits of moc3_art_mesh_flags. */
+#define MOC3_BLEND_MODE_MASK 0x3
+#define MOC3_BLEND_NORMAL 0x0
+#define MOC3_BLEND_ADDITIVE 0x1
+#define MOC3_BLEND_MULTIPLICATIVE 0x2
+#define MOC3_FLAG_DOUBLE_SIDED 0x4
+#define MOC3_FLAG_INVERTED 0x8
+
+/* Returns NULL if the moc3 couldn't be parsed. The bytes are copied. */
+Moc3Model *moc3_model_load(const uint8_t *bytes, size_t len);
+void moc3_model_free(Moc3Model *model);
+/* Returns false if the update failed. */
+bool moc3_model_update(Moc3Model *model);
+/* A column major 3x3 matrix applied after mapping the canvas to -1..1, y up, or NULL to
+ * stop working out NDC positions. Positions are overwritten in place, so pointers from
+ * moc3_art_mesh_ndc_positions stay valid. */
+bool moc3_model_set_view(Moc3Model *model, const float *matrix);
+
+uint32_t moc3_parameter_count(const Moc3Model *model);
+/* Returns -1 if there's no parameter with this ID. */
+int32_t moc3_parameter_index(const Moc3Model *model, const char *id);
+const char *moc3_parameter_id(const Moc3Model *model, uint32_t index);
+bool moc3_parameter_range(const Moc3Model *model, uint32_t index, float *min, float *max,
+                          float *default_value);
+float moc3_parameter_get(const Moc3Model *model, uint32_t index);
+bool moc3_parameter_set(Moc3Model *model, uint32_t index, float value);
+bool moc3_parameter_set_by_id(Moc3Model *model, const char *id, float value);
+
+uint32_t moc3_part_count(const Moc3Model *model);
+bool moc3_part_set_opacity(Moc3Model *model, uint32_t index, float opacity);
+
+uint32_t moc3_art_mesh_count(const Moc3Model *model);
+const char *moc3_art_mesh_id(const Moc3Model *model, uint32_t index);
+int32_t moc3_art_mesh_texture(const Moc3Model *model, uint32_t index);
+uint32_t moc3_art_mesh_vertex_count(const Moc3Model *model, uint32_t index);
+/* Interleaved x, y pairs, moc3_art_mesh_vertex_count of them. */
+const float *moc3_art_mesh_positions(const Moc3Model *model, uint32_t index);
+/* The same in normalized device coordinates, or NULL without moc3_model_set_view. */
+const float *moc3_art_mesh_ndc_positions(const Moc3Model *model, uint32_t index);
+/* Interleaved u, v pairs, moc3_art_mesh_vertex_count of them. */
+const float *moc3_art_mesh_uvs(const Moc3Model *model, uint32_t index);
+const uint16_t *moc3_art_mesh_indices(const Moc3Model *model, uint32_t index, uint32_t *count);
+uint8_t moc3_art_mesh_flags(const Moc3Model *model, uint32_t index);
+const uint32_t *moc3_art_mesh_masks(const Moc3Model *model, uint32_t index, uint32_t *count);
+
+/* Art mesh indices, back to front. */
+const uint32_t *moc3_render_orders(const Moc3Model *model, uint32_t *count);
+/* One per art mesh. */
+const float *moc3_art_mesh_opacities(const Moc3Model *model);
+
+#ifdef __cplusplus
+}
+#endif
+
+#endif
@@ -0,0 +1,548 @@
+//! A C API for loading and posing models, so engines written in other languages can use
+//! moc3-rs in place of the Cubism core. The matching header is `include/moc3.h`.
+//!
+//! A model is loaded from the bytes of a moc3, has its parameters set, and is updated,
+//! after which its vertex positions, render orders and opacities can be read back.
+//! Pointers handed out stay valid until the next update, or until the model is freed.
+//!
+//! Panics never unwind into the caller. Functions that panic return the same value they
+//! give for bad arguments instead (null, false, -1, 0 or NaN), and updates return false.
+
+use std::{
+    ffi::{c_char, CStr, CString},
+    panic::{self, AssertUnwindSafe},
+};
+
+use glam::{Mat3, Vec2};
+use moc3_rs::{
+    parse_puppet,
+    puppet::{framedata_for_puppet, Puppet, PuppetFrameData},
+};
+
+/// A loaded model along with everything it needs to be updated.
+pub struct Moc3Model {
+    puppet: Puppet,
+    frame_data: PuppetFrameData,
+    params: Vec<f32>,
+    part_opacities: Vec<f32>,
+    // Set by moc3_model_set_view, along with the positions it gives.
+    view: Option<Mat3>,
+    ndc_positions: Vec<Vec<Vec2>>,
+
+    // Kept around so IDs can be handed out as C strings.
+    parameter_ids: Vec<CString>,
+    art_mesh_ids: Vec<CString>,
+}
+
+// Every function requires a live model, so these only save on repeating the dereference.
+unsafe fn model_ref<'a>(model: *const Moc3Model) -> &'a Moc3Model {
+    &*model
+}
+
+unsafe fn model_mut<'a>(model: *mut Moc3Model) -> &'a mut Moc3Model {
+    &mut *model
+}
+
+// Unwinding into C is undefined behavior, so every entry point runs inside this. A model
+// that panicked halfway through an update may hold a mix of old and new outputs, but it's
+// still safe to read from and free.
+fn catch<T>(failed: T, f: impl FnOnce() -> T) -> T {
+    panic::catch_unwind(AssertUnwindSafe(f)).unwrap_or(failed)
+}
+
+fn c_strings<'a>(ids: impl Iterator<Item = &'a str>) -> Vec<CString> {
+    // IDs come from fixed size, null padded fields, so they can't contain nulls.
+    ids.map(|x| CString::new(x).unwrap_or_default()).collect()
+}
+
+/// Loads a model from the `len` bytes of a moc3 at `bytes`, which are copied and can be
+/// freed afterwards. The model starts out at its default pose, already updated.
+///
+/// Returns null if the moc3 couldn't be parsed.
+///
+/// # Safety
+///
+/// `bytes` must point to at least `len` readable bytes.
+#[no_mangle]
+pub unsafe extern "C" fn moc3_model_load(bytes: *const u8, len: usize) -> *mut Moc3Model {
+    catch(std::ptr::null_mut(), || {
+        if bytes.is_null() {
+            return std::ptr::null_mut();
+        }
+
+        let bytes = std::slice::from_raw_parts(bytes, len);
+        let Ok(puppet) = parse_puppet(bytes) else {
+            return std::ptr::null_mut();
+        };
+
+        let params = puppet.param_data().defaults.clone();
+        let part_opacities = vec![1.0; puppet.part_count as usize];
+        let mut frame_data = framedata_for_puppet(&puppet);
+        puppet.update(&params, &part_opacities, &mut frame_data);
+
+        let parameter_ids = c_strings(puppet.param_data().ids.iter().map(String::as_str));
+        let art_mesh_ids = c_strings(
+            (0..puppet.art_mesh_count as usize).map(|i| puppet.art_mesh_id(i).unwrap_or_default()),
+        );
+
+        Box::into_raw(Box::new(Moc3Model {
+            puppet,
+            frame_data,
+            params,
+            part_opacities,
+            view: None,
+            ndc_positions: Vec::new(),
+            parameter_ids,
+            art_mesh_ids,
+        }))
+    })
+}
+
+/// Frees a model from [moc3_model_load]. Null is ignored.
+///
+/// # Safety
+///
+/// `model` must be null or a model from [moc3_model_load] that hasn't been freed yet.
+#[no_mangle]
+pub unsafe extern "C" fn moc3_model_free(model: *mut Moc3Model) {
+    catch((), || {
+        if !model.is_null() {
+            drop(Box::from_raw(model));
+        }
+    })
+}
+
+/// Poses the model with its current parameters and part opacities. Returns false if the
+/// update failed, which can only happen with a model that's broken in some unexpected way.
+///
+/// # Safety
+///
+/// `model` must be a live model from [moc3_model_load].
+#[no_mangle]
+pub unsafe extern "C" fn moc3_model_update(model: *mut Moc3Model) -> bool {
+    catch(false, || {
+        let model = model_mut(model);
+        model
+            .puppet
+            .update(&model.params, &model.part_opacities, &mut model.frame_data);
+        update_ndc_positions(model);
+        true
+    })
+}
+
+fn update_ndc_positions(model: &mut Moc3Model) {
+    if let Some(view) = model.view {
+        model
+            .puppet
+            .ndc_positions(&model.frame_data, view, &mut model.ndc_positions);
+    }
+}
+
+/// Has every update also work out vertex positions in normalized device coordinates,
+/// for [moc3_art_mesh_ndc_positions]. The canvas fills -1 to 1 with +y pointing up, and
+/// is then moved by `matrix`, nine floats making up a 3x3 matrix in column major order.
+/// Passing null stops this again.
+///
+/// The positions are worked out straight away, so there's no need to update first. They're
+/// overwritten in place, so pointers from [moc3_art_mesh_ndc_positions] stay valid across
+/// view changes just like they do across updates. Returns false under the same conditions
+/// as [moc3_model_update].
+///
+/// # Safety
+///
+/// `model` must be a live model from [moc3_model_load], and `matrix` must be null or point
+/// to nine readable floats.
+#[no_mangle]
+pub unsafe extern "C" fn moc3_model_set_view(model: *mut Moc3Model, matrix: *const f32) -> bool {
+    catch(false, || {
+        let model = model_mut(model);
+        model.view = (!matrix.is_null())
+            .then(|| Mat3::from_cols_slice(std::slice::from_raw_parts(matrix, 9)));
+        update_ndc_positions(model);
+        true
+    })
+}
+
+/// # Safety
+///
+/// `model` must be a live model from [moc3_model_load].
+#[no_mangle]
+pub unsafe extern "C" fn moc3_parameter_count(model: *const Moc3Model) -> u32 {
+    catch(0, || model_ref(model).params.len() as u32)
+}
+
+/// Finds the index of the parameter with the given ID, or -1 if there isn't one.
+///
+/// # Safety
+///
+/// `model` must be a live model from [moc3_model_load], and `id` a null terminated string.
+#[no_mangle]
+pub unsafe extern "C" fn moc3_parameter_index(model: *const Moc3Model, id: *const c_char) -> i32 {
+    catch(-1, || {
+        let id = CStr::from_ptr(id);
+        model_ref(model)
+            .parameter_ids
+            .iter()
+            .position(|x| x.as_c_str() == id)
+            .map_or(-1, |x| x as i32)
+    })
+}
+
+/// The ID of a parameter, or null if the index is out of range.
+///
+/// # Safety
+///
+/// `model` must be a live model from [moc3_model_load].
+#[no_mangle]
+pub unsafe extern "C" fn moc3_parameter_id(model: *const Moc3Model, index: u32) -> *const c_char {
+    catch(std::ptr::null(), || {
+        model_ref(model)
+            .parameter_ids
+            .get(index as usize)
+            .map_or(std::ptr::null(), |x| x.as_ptr())
+    })
+}
+
+/// Writes a parameter's range and default value to whichever of the outputs aren't null.
+/// Returns false if the index is out of range.
+///
+/// # Safety
+///
+/// `model` must be a live model from [moc3_model_load], and the outputs null or writable.
+#[no_mangle]
+pub unsafe extern "C" fn moc3_parameter_range(
+    model: *const Moc3Model,
+    index: u32,
+    min: *mut f32,
+    max: *mut f32,
+    default: *mut f32,
+) -> bool {
+    catch(false, || {
+        let param_data = model_ref(model).puppet.param_data();
+        let index = index as usize;
+        if index >= param_data.count as usize {
+            return false;
+        }
+
+        for (out, value) in [
+            (min, param_data.mins[index]),
+            (max, param_data.maxes[index]),
+            (default, param_data.defaults[index]),
+        ] {
+            if !out.is_null() {
+                *out = value;
+            }
+        }
+        true
+    })
+}
+
+/// The value a parameter will have in the next update, or NaN if the index is out of range.
+///
+/// # Safety
+///
+/// `model` must be a live model from [moc3_model_load].
+#[no_mangle]
+pub unsafe extern "C" fn moc3_parameter_get(model: *const Moc3Model, index: u32) -> f32 {
+    catch(f32::NAN, || {
+        model_ref(model)
+            .params
+            .get(index as usize)
+            .copied()
+            .unwrap_or(f32::NAN)
+    })
+}
+
+/// Sets a parameter for the next update. Returns false if the index is out of range.
+///
+/// # Safety
+///
+/// `model` must be a live model from [moc3_model_load].
+#[no_mangle]
+pub unsafe extern "C" fn moc3_parameter_set(model: *mut Moc3Model, index: u32, value: f32) -> bool {
+    catch(false, || {
+        match model_mut(model).params.get_mut(index as usize) {
+            Some(param) => {
+                *param = value;
+                true
+            }
+            None => false,
+        }
+    })
+}
+
+/// Like [moc3_parameter_set], but by ID. Returns false if there's no such parameter.
+///
+/// # Safety
+///
+/// `model` must be a live model from [moc3_model_load], and `id` a null terminated string.
+#[no_mangle]
+pub unsafe extern "C" fn moc3_parameter_set_by_id(
+    model: *mut Moc3Model,
+    id: *const c_char,
+    value: f32,
+) -> bool {
+    catch(false, || match moc3_parameter_index(model, id) {
+        -1 => false,
+        index => moc3_parameter_set(model, index as u32, value),
+    })
+}
+
+/// # Safety
+///
+/// `model` must be a live model from [moc3_model_load].
+#[no_mangle]
+pub unsafe extern "C" fn moc3_part_count(model: *const Moc3Model) -> u32 {
+    catch(0, || model_ref(model).part_opacities.len() as u32)
+}
+
+/// Sets a part's opacity for the next update. Returns false if the index is out of range.
+///
+/// # Safety
+///
+/// `model` must be a live model from [moc3_model_load].
+#[no_mangle]
+pub unsafe extern "C" fn moc3_part_set_opacity(
+    model: *mut Moc3Model,
+    index: u32,
+    opacity: f32,
+) -> bool {
+    catch(false, || {
+        match model_mut(model).part_opacities.get_mut(index as usize) {
+            Some(part) => {
+                *part = opacity;
+                true
+            }
+            None => false,
+        }
+    })
+}
+
+/// # Safety
+///
+/// `model` must be a live model from [moc3_model_load].
+#[no_mangle]
+pub unsafe extern "C" fn moc3_art_mesh_count(model: *const Moc3Model) -> u32 {
+    catch(0, || model_ref(model).puppet.art_mesh_count)
+}
+
+/// The ID of an art mesh, or null if the index is out of range.
+///
+/// # Safety
+///
+/// `model` must be a live model from [moc3_model_load].
+#[no_mangle]
+pub unsafe extern "C" fn moc3_art_mesh_id(model: *const Moc3Model, index: u32) -> *const c_char {
+    catch(std::ptr::null(), || {
+        model_ref(model)
+            .art_mesh_ids
+            .get(index as usize)
+            .map_or(std::ptr::null(), |x| x.as_ptr())
+    })
+}
+
+/// The index of the texture an art mesh is drawn with, or -1 if the index is out of range.
+///
+/// # Safety
+///
+/// `model` must be a live model from [moc3_model_load].
+#[no_mangle]
+pub unsafe extern "C" fn moc3_art_mesh_texture(model: *const Moc3Model, index: u32) -> i32 {
+    catch(-1, || {
+        model_ref(model)
+            .puppet
+            .art_mesh_textures
+            .get(index as usize)
+            .map_or(-1, |x| *x as i32)
+    })
+}
+
+/// The vertex count of an art mesh, which its positions and UVs have two floats each for.
+/// Out of range indices have no vertexes.
+///
+/// # Safety
+///
+/// `model` must be a live model from [moc3_model_load].
+#[no_mangle]
+pub unsafe extern "C" fn moc3_art_mesh_vertex_count(model: *const Moc3Model, index: u32) -> u32 {
+    catch(0, || {
+        model_ref(model)
+            .puppet
+            .art_mesh_uvs
+            .get(index as usize)
+            .map_or(0, |x| x.len() as u32)
+    })
+}
+
+/// The posed vertex positions of an art mesh as interleaved x and y floats, or null if the
+/// index is out of range.
+///
+/// # Safety
+///
+/// `model` must be a live model from [moc3_model_load].
+#[no_mangle]
+pub unsafe extern "C" fn moc3_art_mesh_positions(
+    model: *const Moc3Model,
+    index: u32,
+) -> *const f32 {
+    catch(std::ptr::null(), || {
+        model_ref(model)
+            .frame_data
+            .art_mesh_positions()
+            .get(index as usize)
+            .map_or(std::ptr::null(), |x| {
+                bytemuck::cast_slice::<_, f32>(x).as_ptr()
+            })
+    })
+}
+
+/// Like [moc3_art_mesh_positions], but in normalized device coordinates as set up by
+/// [moc3_model_set_view]. Null if that hasn't been called, or if the index is out of range.
+///
+/// # Safety
+///
+/// `model` must be a live model from [moc3_model_load].
+#[no_mangle]
+pub unsafe extern "C" fn moc3_art_mesh_ndc_positions(
+    model: *const Moc3Model,
+    index: u32,
+) -> *const f32 {
+    catch(std::ptr::null(), || {
+        let model = model_ref(model);
+        // The positions are kept around without a view, so their pointers stay stable.
+        if model.view.is_none() {
+            return std::ptr::null();
+        }
+
+        model
+            .ndc_positions
+            .get(index as usize)
+            .map_or(std::ptr::null(), |x| {
+                bytemuck::cast_slice::<_, f32>(x).as_ptr()
+            })
+    })
+}
+
+/// The texture coordinates of an art mesh as interleaved u and v floats, or null if the
+/// index is out of range. These never change.
+///
+/// # Safety
+///
+/// `model` must be a live model from [moc3_model_load].
+#[no_mangle]
+pub unsafe extern "C" fn moc3_art_mesh_uvs(model: *const Moc3Model, index: u32) -> *const f32 {
+    catch(std::ptr::null(), || {
+        model_ref(model)
+            .puppet
+            .art_mesh_uvs
+            .get(index as usize)
+            .map_or(std::ptr::null(), |x| {
+                bytemuck::cast_slice::<_, f32>(x).as_ptr()
+            })
+    })
+}
+
+/// The triangle indices of an art mesh, with their count written to `count`, or null if
+/// the index is out of range. These never change.
+///
+/// # Safety
+///
+/// `model` must be a live model from [moc3_model_load], and `count` writable.
+#[no_mangle]
+pub unsafe extern "C" fn moc3_art_mesh_indices(
+    model: *const Moc3Model,
+    index: u32,
+    count: *mut u32,
+) -> *const u16 {
+    catch(std::ptr::null(), || {
+        match model_ref(model).puppet.art_mesh_indices.get(index as usize) {
+            Some(indices) => {
+                *count = indices.len() as u32;
+                indices.as_ptr()
+            }
+            None => {
+                *count = 0;
+                std::ptr::null()
+            }
+        }
+    })
+}
+
+/// The flags of an art mesh packed into a byte, as the moc3 stores them: the blend mode in
+/// the low two bits (0 for normal, 1 for additive, 2 for multiplicative), then whether
+/// it's double sided, then whether its masks are inverted. Out of range indices have no
+/// flags set.
+///
+/// # Safety
+///
+/// `model` must be a live model from [moc3_model_load].
+#[no_mangle]
+pub unsafe extern "C" fn moc3_art_mesh_flags(model: *const Moc3Model, index: u32) -> u8 {
+    catch(0, || {
+        model_ref(model)
+            .puppet
+            .art_mesh_flags
+            .get(index as usize)
+            .map_or(0, |x| x.into_bytes()[0])
+    })
+}
+
+/// The indices of the art meshes masking an art mesh, with their count written to
+/// `count`, or null if the index is out of range.
+///
+/// # Safety
+///
+/// `model` must be a live model from [moc3_model_load], and `count` writable.
+#[no_mangle]
+pub unsafe extern "C" fn moc3_art_mesh_masks(
+    model: *const Moc3Model,
+    index: u32,
+    count: *mut u32,
+) -> *const u32 {
+    catch(std::ptr::null(), || {
+        match model_ref(model)
+            .puppet
+            .art_mesh_mask_indices
+            .get(index as usize)
+        {
+            Some(masks) => {
+                *count = masks.len() as u32;
+                masks.as_ptr()
+            }
+            None => {
+                *count = 0;
+                std::ptr::null()
+            }
+        }
+    })
+}
+
+/// The indices of the art meshes in the order they should be drawn, back to front, with
+/// their count written to `count`.
+///
+/// # Safety
+///
+/// `model` must be a live model from [moc3_model_load], and `count` writable.
+#[no_mangle]
+pub unsafe extern "C" fn moc3_render_orders(
+    model: *const Moc3Model,
+    count: *mut u32,
+) -> *const u32 {
+    catch(std::ptr::null(), || {
+        let orders = &model_ref(model).frame_data.render_order();
+        *count = orders.len() as u32;
+        orders.as_ptr()
+    })
+}
+
+/// The opacity of every art mesh, one per art mesh.
+///
+/// # Safety
+///
+/// `model` must be a live model from [moc3_model_load].
+#[no_mangle]
+pub unsafe extern "C" fn moc3_art_mesh_opacities(model: *const Moc3Model) -> *const f32 {
+    catch(std::ptr::null(), || {
+        model_ref(model).frame_data.art_mesh_opacities().as_ptr()
+    })
+}
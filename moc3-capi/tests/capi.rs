@@ -0,0 +1,71 @@
+use moc3_bench::SyntheticModel;
+use moc3_capi::*;
+
+#[test]
+fn load_pose_and_read_back() {
+    let bytes = SyntheticModel::SMALL.to_moc3();
+
+    unsafe {
+        assert!(moc3_model_load(bytes.as_ptr(), 16).is_null());
+
+        let model = moc3_model_load(bytes.as_ptr(), bytes.len());
+        assert!(!model.is_null());
+
+        let id = moc3_parameter_id(model, 1);
+        assert_eq!(moc3_parameter_index(model, id), 1);
+        assert_eq!(moc3_parameter_index(model, c"missing".as_ptr()), -1);
+        assert!(moc3_parameter_id(model, moc3_parameter_count(model)).is_null());
+
+        let (mut min, mut max) = (0.0, 0.0);
+        assert!(moc3_parameter_range(
+            model,
+            1,
+            &mut min,
+            &mut max,
+            std::ptr::null_mut()
+        ));
+
+        let count = moc3_art_mesh_vertex_count(model, 0) as usize;
+        let rest =
+            std::slice::from_raw_parts(moc3_art_mesh_positions(model, 0), count * 2).to_vec();
+
+        assert!(moc3_parameter_set_by_id(model, id, max));
+        assert_eq!(moc3_parameter_get(model, 1), max);
+        assert!(moc3_model_update(model));
+
+        let posed = std::slice::from_raw_parts(moc3_art_mesh_positions(model, 0), count * 2);
+        assert_ne!(rest, posed);
+        assert!(posed.iter().all(|x| x.is_finite()));
+
+        let mut order_count = 0;
+        let orders = moc3_render_orders(model, &mut order_count);
+        assert_eq!(order_count, moc3_art_mesh_count(model));
+        assert!(std::slice::from_raw_parts(orders, order_count as usize)
+            .iter()
+            .all(|x| *x < order_count));
+
+        assert!(moc3_art_mesh_ndc_positions(model, 0).is_null());
+        let identity = [1.0, 0.0, 0.0, 0.0, 1.0, 0.0, 0.0, 0.0, 1.0];
+        assert!(moc3_model_set_view(model, identity.as_ptr()));
+        let ndc_pointer = moc3_art_mesh_ndc_positions(model, 0);
+        let ndc = std::slice::from_raw_parts(ndc_pointer, count * 2).to_vec();
+        assert!(ndc.iter().all(|x| x.is_finite()));
+
+        // Zooming in on the canvas moves everything away from the middle of the screen.
+        let zoomed = [2.0, 0.0, 0.0, 0.0, 2.0, 0.0, 0.0, 0.0, 1.0];
+        assert!(moc3_model_set_view(model, zoomed.as_ptr()));
+        assert!(moc3_model_update(model));
+        let zoomed = std::slice::from_raw_parts(ndc_pointer, count * 2);
+        assert!(ndc
+            .iter()
+            .zip(zoomed)
+            .all(|(a, b)| (a * 2.0 - b).abs() < 1e-5));
+
+        assert!(moc3_model_set_view(model, std::ptr::null()));
+        assert!(moc3_art_mesh_ndc_positions(model, 0).is_null());
+        assert!(moc3_model_set_view(model, identity.as_ptr()));
+        assert_eq!(moc3_art_mesh_ndc_positions(model, 0), ndc_pointer);
+
+        moc3_model_free(model);
+    }
+}
@@ -0,0 +1,68 @@
+//! A bare bones parameter editor: sliders for every parameter next to a preview.
+//!
+//! `cargo run -p moc3-egui --example preview -- model.moc3 [texture.png ...]`
+
+use eframe::egui;
+use moc3_egui::{register_puppet, PuppetView};
+use moc3_rs::{
+    parse_puppet,
+    puppet::{framedata_for_puppet, Puppet, PuppetFrameData},
+};
+
+struct Preview {
+    puppet: Puppet,
+    frame_data: PuppetFrameData,
+    params: Vec<f32>,
+}
+
+impl eframe::App for Preview {
+    fn update(&mut self, ctx: &egui::Context, _frame: &mut eframe::Frame) {
+        let param_data = self.puppet.param_data();
+
+        egui::SidePanel::right("parameters").show(ctx, |ui| {
+            egui::ScrollArea::vertical().show(ui, |ui| {
+                for (i, param) in self.params.iter_mut().enumerate() {
+                    let range = param_data.mins[i]..=param_data.maxes[i];
+                    ui.add(egui::Slider::new(param, range).text(&param_data.ids[i]));
+                }
+            });
+        });
+
+        self.puppet.update(
+            &self.params,
+            &vec![1.0; self.puppet.part_count as usize],
+            &mut self.frame_data,
+        );
+
+        egui::CentralPanel::default().show(ctx, |ui| {
+            ui.add(PuppetView::new(egui::Id::new("puppet"), &self.frame_data));
+        });
+    }
+}
+
+fn main() -> eframe::Result<()> {
+    let mut args = std::env::args().skip(1);
+    let bytes = std::fs::read(args.next().expect("no model given")).unwrap();
+    let puppet = parse_puppet(&bytes).unwrap();
+    let textures: Vec<_> = args.map(|x| image::open(x).unwrap().into_rgba8()).collect();
+
+    let options = eframe::NativeOptions {
+        renderer: eframe::Renderer::Wgpu,
+        ..Default::default()
+    };
+
+    eframe::run_native(
+        "moc3 preview",
+        options,
+        Box::new(move |cc| {
+            let render_state = cc.wgpu_render_state.as_ref().unwrap();
+            register_puppet(render_state, egui::Id::new("puppet"), &puppet, &textures);
+
+            Box::new(Preview {
+                frame_data: framedata_for_puppet(&puppet),
+                params: puppet.param_data().defaults.clone(),
+                puppet,
+            })
+        }),
+    )
+}
@@ -0,0 +1,44 @@
+// Copies the rendered puppet into egui's render pass, covering the callback's viewport.
+
+@group(0) @binding(0)
+var t_puppet: texture_2d<f32>;
+@group(0) @binding(1)
+var s_puppet: sampler;
+
+struct VertexOutput {
+    @builtin(position) position: vec4f,
+    @location(0) uv: vec2f,
+}
+
+@vertex
+fn vs_main(@builtin(vertex_index) index: u32) -> VertexOutput {
+    // A single triangle covering the whole viewport.
+    let uv = vec2f(f32((index << 1u) & 2u), f32(index & 2u));
+
+    var out: VertexOutput;
+    out.position = vec4f(uv.x * 2.0 - 1.0, 1.0 - uv.y * 2.0, 0.0, 1.0);
+    out.uv = uv;
+    return out;
+}
+
+@fragment
+fn fs_main(in: VertexOutput) -> @location(0) vec4f {
+    return textureSample(t_puppet, s_puppet, in.uv);
+}
+
+fn linear_from_gamma(gamma: vec3f) -> vec3f {
+    let cutoff = gamma < vec3f(0.04045);
+    let lower = gamma / vec3f(12.92);
+    let higher = pow((gamma + vec3f(0.055)) / vec3f(1.055), vec3f(2.4));
+    return select(higher, lower, cutoff);
+}
+
+// The puppet is drawn in gamma space, which an sRGB target would encode a second time.
+@fragment
+fn fs_main_srgb(in: VertexOutput) -> @location(0) vec4f {
+    let color = textureSample(t_puppet, s_puppet, in.uv);
+    if color.a <= 0.0 {
+        return vec4f(0.0);
+    }
+    return vec4f(linear_from_gamma(color.rgb / color.a) * color.a, color.a);
+}
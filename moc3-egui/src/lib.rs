@@ -0,0 +1,365 @@
+//! Shows puppets inside egui, through the wgpu renderer.
+//!
+//! Register a puppet once with [register_puppet], then add a [PuppetView] with the same ID
+//! every frame. Views can be panned by dragging and zoomed with the scroll wheel, and a
+//! double click resets them.
+//!
+//! This needs egui to be running on wgpu (`eframe`'s `wgpu` feature), without
+//! multisampling or a depth buffer, which are both off by default.
+
+use std::{borrow::Cow, collections::HashMap};
+
+use egui::{Id, Pos2, Response, Sense, Ui, Vec2, Widget};
+use egui_wgpu::{CallbackResources, CallbackTrait, RenderState};
+use glam::{vec3, Mat4};
+use image::RgbaImage;
+use moc3_rs::puppet::{PuppetFrameData, PuppetRef};
+use moc3_wgpu::renderer::{new_renderer, Renderer};
+use wgpu::*;
+
+/// Sets up drawing `puppet` in the [PuppetView]s with the given ID, replacing whatever
+/// was registered under it before.
+pub fn register_puppet(
+    render_state: &RenderState,
+    id: Id,
+    puppet: &PuppetRef,
+    textures: &[RgbaImage],
+) {
+    let device = &render_state.device;
+    let renderer = new_renderer(
+        puppet,
+        device,
+        &render_state.queue,
+        TextureFormat::Rgba8Unorm,
+        textures,
+    );
+
+    let resources = &mut render_state.renderer.write().callback_resources;
+    if resources.get::<PuppetViews>().is_none() {
+        resources.insert(PuppetViews::new(device, render_state.target_format));
+    }
+
+    resources.get_mut::<PuppetViews>().unwrap().puppets.insert(
+        id,
+        PuppetTarget {
+            renderer,
+            size: [0, 0],
+            target: None,
+        },
+    );
+}
+
+/// Forgets a puppet from [register_puppet], freeing everything it used on the GPU.
+pub fn unregister_puppet(render_state: &RenderState, id: Id) {
+    let resources = &mut render_state.renderer.write().callback_resources;
+    if let Some(views) = resources.get_mut::<PuppetViews>() {
+        views.puppets.remove(&id);
+    }
+}
+
+/// Where a [PuppetView] is looking, kept in egui's memory between frames.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct PuppetCamera {
+    /// How far the puppet is moved, in clip space.
+    pub offset: glam::Vec2,
+    pub zoom: f32,
+}
+
+impl Default for PuppetCamera {
+    fn default() -> Self {
+        Self {
+            offset: glam::Vec2::ZERO,
+            zoom: 1.0,
+        }
+    }
+}
+
+impl PuppetCamera {
+    pub fn load(ui: &Ui, id: Id) -> Self {
+        ui.data(|x| x.get_temp(id)).unwrap_or_default()
+    }
+
+    pub fn store(self, ui: &Ui, id: Id) {
+        ui.data_mut(|x| x.insert_temp(id, self));
+    }
+
+    fn matrix(&self) -> Mat4 {
+        Mat4::from_translation(self.offset.extend(0.0))
+            * Mat4::from_scale(vec3(self.zoom, self.zoom, 1.0))
+    }
+}
+
+/// Draws the puppet registered under an ID, posed with the given frame data.
+///
+/// The frame data is copied for the paint callback, so a view costs about as much as the
+/// puppet's vertexes every frame. Every view of a puppet must use its own ID.
+pub struct PuppetView<'a> {
+    id: Id,
+    frame_data: &'a PuppetFrameData,
+    size: Option<Vec2>,
+}
+
+impl<'a> PuppetView<'a> {
+    pub fn new(id: Id, frame_data: &'a PuppetFrameData) -> Self {
+        Self {
+            id,
+            frame_data,
+            size: None,
+        }
+    }
+
+    /// The size of the view, all of the available space by default.
+    pub fn size(mut self, size: Vec2) -> Self {
+        self.size = Some(size);
+        self
+    }
+}
+
+impl Widget for PuppetView<'_> {
+    fn ui(self, ui: &mut Ui) -> Response {
+        let size = self.size.unwrap_or_else(|| ui.available_size());
+        let (rect, response) = ui.allocate_exact_size(size, Sense::click_and_drag());
+
+        let mut camera = PuppetCamera::load(ui, self.id);
+        let to_clip = |pos: Pos2| {
+            let pos = (pos - rect.center()) / rect.size() * 2.0;
+            glam::vec2(pos.x, -pos.y)
+        };
+
+        if response.dragged() {
+            let delta = response.drag_delta() / rect.size() * 2.0;
+            camera.offset += glam::vec2(delta.x, -delta.y);
+        }
+
+        if let Some(hover) = response.hover_pos() {
+            let scroll = ui.input(|x| x.scroll_delta.y);
+            if scroll != 0.0 {
+                // Zoom around the cursor, so whatever's under it stays there.
+                let factor = (scroll * 0.002).exp();
+                let cursor = to_clip(hover);
+                camera.offset = cursor - (cursor - camera.offset) * factor;
+                camera.zoom *= factor;
+            }
+        }
+
+        if response.double_clicked() {
+            camera = PuppetCamera::default();
+        }
+        camera.store(ui, self.id);
+
+        if ui.is_rect_visible(rect) {
+            let pixels = rect.size() * ui.ctx().pixels_per_point();
+            ui.painter().add(egui_wgpu::Callback::new_paint_callback(
+                rect,
+                PuppetCallback {
+                    id: self.id,
+                    size: [pixels.x.round() as u32, pixels.y.round() as u32],
+                    camera: camera.matrix(),
+                    frame_data: self.frame_data.clone(),
+                },
+            ));
+        }
+
+        response
+    }
+}
+
+struct PuppetCallback {
+    id: Id,
+    size: [u32; 2],
+    camera: Mat4,
+    frame_data: PuppetFrameData,
+}
+
+impl CallbackTrait for PuppetCallback {
+    fn prepare(
+        &self,
+        device: &Device,
+        queue: &Queue,
+        egui_encoder: &mut CommandEncoder,
+        callback_resources: &mut CallbackResources,
+    ) -> Vec<CommandBuffer> {
+        if self.size.contains(&0) {
+            return Vec::new();
+        }
+
+        let Some(views) = callback_resources.get_mut::<PuppetViews>() else {
+            return Vec::new();
+        };
+        let Some(puppet) = views.puppets.get_mut(&self.id) else {
+            return Vec::new();
+        };
+
+        let size = Extent3d {
+            width: self.size[0],
+            height: self.size[1],
+            depth_or_array_layers: 1,
+        };
+        if puppet.target.is_none() || puppet.size != self.size {
+            puppet.target = Some(views.blit.target(device, size));
+            puppet.size = self.size;
+        }
+
+        let (view, _) = puppet.target.as_ref().unwrap();
+        puppet.renderer.set_camera(self.camera);
+        puppet
+            .renderer
+            .prepare(device, queue, size, &self.frame_data);
+        puppet.renderer.render(view, egui_encoder);
+
+        Vec::new()
+    }
+
+    fn paint<'a>(
+        &'a self,
+        _info: egui::PaintCallbackInfo,
+        render_pass: &mut RenderPass<'a>,
+        callback_resources: &'a CallbackResources,
+    ) {
+        let Some(views) = callback_resources.get::<PuppetViews>() else {
+            return;
+        };
+        let Some((_, bind_group)) = views.puppets.get(&self.id).and_then(|x| x.target.as_ref())
+        else {
+            return;
+        };
+
+        render_pass.set_pipeline(&views.blit.pipeline);
+        render_pass.set_bind_group(0, bind_group, &[]);
+        render_pass.draw(0..3, 0..1);
+    }
+}
+
+struct PuppetTarget {
+    renderer: Renderer,
+    size: [u32; 2],
+    // Where the puppet is drawn, and the bind group for copying it into egui.
+    target: Option<(TextureView, BindGroup)>,
+}
+
+// Everything for drawing puppets, kept in egui-wgpu's callback resources.
+struct PuppetViews {
+    blit: Blit,
+    puppets: HashMap<Id, PuppetTarget>,
+}
+
+impl PuppetViews {
+    fn new(device: &Device, format: TextureFormat) -> Self {
+        Self {
+            blit: Blit::new(device, format),
+            puppets: HashMap::new(),
+        }
+    }
+}
+
+struct Blit {
+    pipeline: RenderPipeline,
+    layout: BindGroupLayout,
+    sampler: Sampler,
+}
+
+impl Blit {
+    fn new(device: &Device, format: TextureFormat) -> Self {
+        let shader = device.create_shader_module(ShaderModuleDescriptor {
+            label: None,
+            source: ShaderSource::Wgsl(Cow::Borrowed(include_str!("blit.wgsl"))),
+        });
+
+        let layout = device.create_bind_group_layout(&BindGroupLayoutDescriptor {
+            entries: &[
+                BindGroupLayoutEntry {
+                    binding: 0,
+                    visibility: ShaderStages::FRAGMENT,
+                    ty: BindingType::Texture {
+                        multisampled: false,
+                        sample_type: TextureSampleType::Float { filterable: true },
+                        view_dimension: TextureViewDimension::D2,
+                    },
+                    count: None,
+                },
+                BindGroupLayoutEntry {
+                    binding: 1,
+                    visibility: ShaderStages::FRAGMENT,
+                    ty: BindingType::Sampler(SamplerBindingType::Filtering),
+                    count: None,
+                },
+            ],
+            label: None,
+        });
+
+        let pipeline_layout = device.create_pipeline_layout(&PipelineLayoutDescriptor {
+            bind_group_layouts: &[&layout],
+            ..PipelineLayoutDescriptor::default()
+        });
+
+        let pipeline = device.create_render_pipeline(&RenderPipelineDescriptor {
+            label: None,
+            layout: Some(&pipeline_layout),
+            vertex: VertexState {
+                module: &shader,
+                entry_point: "vs_main",
+                buffers: &[],
+            },
+            fragment: Some(FragmentState {
+                module: &shader,
+                entry_point: if format.is_srgb() {
+                    "fs_main_srgb"
+                } else {
+                    "fs_main"
+                },
+                targets: &[Some(ColorTargetState {
+                    format,
+                    blend: Some(BlendState::PREMULTIPLIED_ALPHA_BLENDING),
+                    write_mask: ColorWrites::ALL,
+                })],
+            }),
+            primitive: PrimitiveState::default(),
+            depth_stencil: None,
+            multisample: MultisampleState::default(),
+            multiview: None,
+        });
+
+        let sampler = device.create_sampler(&SamplerDescriptor {
+            min_filter: FilterMode::Linear,
+            mag_filter: FilterMode::Linear,
+            ..SamplerDescriptor::default()
+        });
+
+        Self {
+            pipeline,
+            layout,
+            sampler,
+        }
+    }
+
+    fn target(&self, device: &Device, size: Extent3d) -> (TextureView, BindGroup) {
+        let texture = device.create_texture(&TextureDescriptor {
+            size,
+            mip_level_count: 1,
+            sample_count: 1,
+            dimension: TextureDimension::D2,
+            format: TextureFormat::Rgba8Unorm,
+            usage: TextureUsages::RENDER_ATTACHMENT | TextureUsages::TEXTURE_BINDING,
+            view_formats: &[],
+            label: Some("moc3 puppet view"),
+        });
+        let view = texture.create_view(&TextureViewDescriptor::default());
+
+        let bind_group = device.create_bind_group(&BindGroupDescriptor {
+            layout: &self.layout,
+            entries: &[
+                BindGroupEntry {
+                    binding: 0,
+                    resource: BindingResource::TextureView(&view),
+                },
+                BindGroupEntry {
+                    binding: 1,
+                    resource: BindingResource::Sampler(&self.sampler),
+                },
+            ],
+            label: None,
+        });
+
+        (view, bind_group)
+    }
+}
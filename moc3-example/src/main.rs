@@ -1,32 +1,279 @@
-use binrw::BinReaderExt;
-use moc3_rs::{
-    data::Moc3Data,
-    puppet::{framedata_for_puppet, puppet_from_moc3, Puppet, PuppetFrameData},
+//! A small model viewer: sliders for every parameter, idle motions and physics, drawn with
+//! the wgpu renderer under an egui panel.
+//!
+//! ```text
+//! moc3-example [model.model3.json | model.moc3 [texture.png ...]]
+//! ```
+//!
+//! Models can also be dropped onto the window. Textures and physics for a bare .moc3 are
+//! found next to it if they're laid out like Cubism exports them, or can be dropped or
+//! given along with it. Whatever's being viewed is reloaded whenever its files change, so
+//! it can be left open while editing and exporting a model.
+
+use std::{error::Error, path::PathBuf, time::Instant};
+
+use moc3_impressionism::{
+    data::Physics3Data, BreathController, EyeBlinkController, EyeBlinkSettings, PhysicsController,
+};
+use moc3_rs::puppet::{framedata_for_puppet, ParamData, Puppet, PuppetFrameData};
+use moc3_wgpu::{
+    debug::DebugOverlay,
+    load::{LoadedModel, ModelFiles},
+    renderer::{new_renderer, request_device, FitMode, Renderer},
+    watch::ModelWatcher,
+};
+use wgpu::{CompositeAlphaMode, Device, Queue, TextureFormat};
+use winit::{
+    event::{Event, WindowEvent},
+    event_loop::EventLoop,
+    window::WindowBuilder,
 };
-use moc3_wgpu::renderer::new_renderer;
-use std::fs::File;
-use std::io::BufReader;
-use wgpu::{CompositeAlphaMode, TextureFormat};
-use winit::{event::Event, event_loop::EventLoop, window::WindowBuilder};
 
-fn main() {
-    let f = File::open("test.moc3").unwrap();
-    let mut reader = BufReader::new(f);
-    let read: Moc3Data = reader.read_le().unwrap();
+const FORMAT: TextureFormat = TextureFormat::Bgra8Unorm;
+
+// Everything about the model being viewed, replaced whenever another one is loaded.
+struct Model {
+    name: String,
+    // What the model was loaded from, to load it again from when it changes.
+    paths: Vec<PathBuf>,
+    files: ModelFiles,
+    watcher: Option<ModelWatcher>,
+
+    puppet: Puppet,
+    frame_data: PuppetFrameData,
+    renderer: Renderer,
+    // What the sliders are set to, before idle motions and physics are added on top.
+    params: Vec<f32>,
+    posed: Vec<f32>,
+    opacities: Vec<f32>,
+
+    physics: Option<PhysicsController>,
+    breath: BreathController,
+    blink: EyeBlinkController,
+}
+
+impl Model {
+    fn load(device: &Device, queue: &Queue, paths: &[PathBuf]) -> Result<Self, String> {
+        let LoadedModel {
+            puppet,
+            textures,
+            files,
+        } = read_model(paths)?;
+        let param_data = puppet.param_data();
+        let physics = read_physics(&files, param_data)?;
+        let (breath, blink) = idle_motions(param_data);
+
+        Ok(Self {
+            name: paths[0]
+                .file_name()
+                .map(|x| x.to_string_lossy().into_owned())
+                .unwrap_or_default(),
+            paths: paths.to_vec(),
+            // Not being able to watch just means no reloading.
+            watcher: ModelWatcher::new(&files).ok(),
+            files,
+
+            frame_data: framedata_for_puppet(&puppet),
+            renderer: new_renderer(&puppet, device, queue, FORMAT, &textures),
+            params: param_data.defaults.clone(),
+            posed: param_data.defaults.clone(),
+            opacities: vec![1.0; puppet.part_count as usize],
+
+            physics,
+            breath,
+            blink,
+            puppet,
+        })
+    }
+
+    // Picks up any changes to the model's files, keeping the sliders where they were.
+    fn reload_changes(&mut self, device: &Device, queue: &Queue) -> Result<(), String> {
+        let Some(changes) = self.watcher.as_mut().and_then(ModelWatcher::poll) else {
+            return Ok(());
+        };
+
+        if changes.model {
+            let LoadedModel {
+                puppet,
+                textures,
+                files,
+            } = read_model(&self.paths)?;
+            let param_data = puppet.param_data();
+            self.physics = read_physics(&files, param_data)?;
+            (self.breath, self.blink) = idle_motions(param_data);
+
+            self.params = param_data.carry_over(self.puppet.param_data(), &self.params);
+            self.posed = self.params.clone();
+            self.opacities = vec![1.0; puppet.part_count as usize];
 
-    let puppet = puppet_from_moc3(&read);
-    drop(read);
+            self.renderer.reload(device, queue, &puppet, &textures);
+            self.frame_data = framedata_for_puppet(&puppet);
+            self.puppet = puppet;
 
-    let frame_data = framedata_for_puppet(&puppet);
+            // Textures or physics might have come or gone along with it.
+            self.watcher = ModelWatcher::new(&files).ok();
+            self.files = files;
+            return Ok(());
+        }
+
+        for index in changes.textures {
+            let texture = self
+                .files
+                .load_texture(index)
+                .map_err(|e| error_chain(&e))?;
+            self.renderer.set_texture(device, queue, index, &texture);
+        }
+        if changes.physics {
+            self.physics = read_physics(&self.files, self.puppet.param_data())?;
+        }
+        Ok(())
+    }
+
+    fn update(&mut self, delta_seconds: f32, settings: &Settings) {
+        let param_data = self.puppet.param_data();
+        self.posed.copy_from_slice(&self.params);
+
+        if settings.breathing {
+            self.breath.update(delta_seconds);
+            self.breath.apply(param_data, &mut self.posed);
+        }
+        if settings.blinking {
+            self.blink.update(delta_seconds);
+            self.blink.apply(param_data, &mut self.posed);
+        }
+        if let (true, Some(physics)) = (settings.physics, &mut self.physics) {
+            physics.update(delta_seconds, param_data, &mut self.posed);
+        }
+
+        self.puppet
+            .update(&self.posed, &self.opacities, &mut self.frame_data);
+    }
+}
+
+// Textures given along with a model (only ever needed for a bare .moc3) override any found
+// next to it.
+fn read_model(paths: &[PathBuf]) -> Result<LoadedModel, String> {
+    let (model_path, textures) = paths.split_first().ok_or("no model given")?;
+    let mut files = ModelFiles::find(model_path).map_err(|e| error_chain(&e))?;
+    if !textures.is_empty() {
+        files.textures = textures.to_vec();
+    }
+    files.load().map_err(|e| error_chain(&e))
+}
+
+fn read_physics(
+    files: &ModelFiles,
+    param_data: &ParamData,
+) -> Result<Option<PhysicsController>, String> {
+    let Some(path) = &files.physics else {
+        return Ok(None);
+    };
+
+    let json = std::fs::read(path).map_err(|e| format!("couldn't read {}: {e}", path.display()))?;
+    let data: Physics3Data = serde_json::from_slice(&json)
+        .map_err(|e| format!("couldn't parse {}: {e}", path.display()))?;
+    for unknown in data.validate(param_data) {
+        eprintln!(
+            "{}: physics setting {} refers to unknown parameter {}",
+            path.display(),
+            unknown.setting,
+            unknown.id
+        );
+    }
+    Ok(Some(PhysicsController::new(&data, param_data)))
+}
 
-    pollster::block_on(run(puppet, frame_data));
+fn idle_motions(param_data: &ParamData) -> (BreathController, EyeBlinkController) {
+    let mut blink = EyeBlinkController::new(EyeBlinkSettings::default(), 1);
+    blink.bind(param_data, ["ParamEyeLOpen", "ParamEyeROpen"]);
+    (BreathController::with_default_parameters(param_data), blink)
 }
 
-pub async fn run(puppet: Puppet, mut frame_data: PuppetFrameData) {
+// Errors from loading say which file went wrong, with why underneath.
+fn error_chain(error: &dyn Error) -> String {
+    let mut message = error.to_string();
+    let mut source = error.source();
+    while let Some(error) = source {
+        message += &format!(": {error}");
+        source = error.source();
+    }
+    message
+}
+
+struct Settings {
+    breathing: bool,
+    blinking: bool,
+    physics: bool,
+    placeholder_mode: bool,
+    fit_mode: FitMode,
+    debug_overlay: DebugOverlay,
+}
+
+impl Default for Settings {
+    fn default() -> Self {
+        Self {
+            breathing: true,
+            blinking: true,
+            physics: true,
+            placeholder_mode: false,
+            fit_mode: FitMode::Contain,
+            debug_overlay: DebugOverlay::default(),
+        }
+    }
+}
+
+fn settings_ui(ui: &mut egui::Ui, settings: &mut Settings, model: &mut Model) {
+    ui.heading(&model.name);
+
+    ui.checkbox(&mut settings.breathing, "Breathing");
+    ui.checkbox(&mut settings.blinking, "Blinking");
+    ui.add_enabled(
+        model.physics.is_some(),
+        egui::Checkbox::new(&mut settings.physics, "Physics"),
+    );
+    ui.checkbox(&mut settings.placeholder_mode, "Placeholder colors");
+
+    ui.horizontal(|ui| {
+        for (fit_mode, name) in [
+            (FitMode::Contain, "Contain"),
+            (FitMode::Cover, "Cover"),
+            (FitMode::Fill, "Fill"),
+        ] {
+            ui.selectable_value(&mut settings.fit_mode, fit_mode, name);
+        }
+    });
+
+    ui.collapsing("Debug overlay", |ui| {
+        let overlay = &mut settings.debug_overlay;
+        ui.checkbox(&mut overlay.wireframes, "Wireframes");
+        ui.checkbox(&mut overlay.masks, "Masks");
+        ui.checkbox(&mut overlay.warp_grids, "Warp deformers");
+        ui.checkbox(&mut overlay.rotation_origins, "Rotation deformers");
+    });
+
+    ui.separator();
+    let param_data = model.puppet.param_data();
+    if ui.button("Reset parameters").clicked() {
+        model.params.copy_from_slice(&param_data.defaults);
+    }
+    egui::ScrollArea::vertical().show(ui, |ui| {
+        for (i, param) in model.params.iter_mut().enumerate() {
+            let range = param_data.mins[i]..=param_data.maxes[i];
+            ui.add(egui::Slider::new(param, range).text(&param_data.ids[i]));
+        }
+    });
+}
+
+fn main() {
+    let paths: Vec<PathBuf> = std::env::args_os().skip(1).map(PathBuf::from).collect();
+    pollster::block_on(run(paths));
+}
+
+pub async fn run(paths: Vec<PathBuf>) {
     let event_loop = EventLoop::new();
     let window = WindowBuilder::new()
-        .with_inner_size(winit::dpi::PhysicalSize::new(1000, 1000))
-        .with_resizable(false)
+        .with_title("moc3 viewer")
+        .with_inner_size(winit::dpi::LogicalSize::new(1000, 1000))
         .with_transparent(true)
         .build(&event_loop)
         .unwrap();
@@ -41,22 +288,11 @@ pub async fn run(puppet: Puppet, mut frame_data: PuppetFrameData) {
         })
         .await
         .unwrap();
+    let (device, queue) = request_device(&adapter).await.unwrap();
 
-    let (device, queue) = adapter
-        .request_device(
-            &wgpu::DeviceDescriptor {
-                features: wgpu::Features::empty(),
-                limits: wgpu::Limits::default(),
-                label: None,
-            },
-            None,
-        )
-        .await
-        .unwrap();
-
-    let config = wgpu::SurfaceConfiguration {
+    let mut config = wgpu::SurfaceConfiguration {
         usage: wgpu::TextureUsages::RENDER_ATTACHMENT,
-        format: wgpu::TextureFormat::Bgra8Unorm,
+        format: FORMAT,
         width: window.inner_size().width,
         height: window.inner_size().height,
         present_mode: wgpu::PresentMode::AutoVsync,
@@ -65,30 +301,155 @@ pub async fn run(puppet: Puppet, mut frame_data: PuppetFrameData) {
     };
     surface.configure(&device, &config);
 
-    let img = image::io::Reader::open("texture.png")
-        .unwrap()
-        .decode()
-        .unwrap()
-        .into_rgba8();
-
-    let mut renderer = new_renderer(&puppet, &device, &queue, TextureFormat::Bgra8Unorm, &[img]);
-    let params = puppet.param_data().defaults.clone();
-    let opacities = vec![1.0; puppet.part_count as usize];
-    // Somehow the Close button doesn't work... Figure that out
-    event_loop.run(move |event, _, _| match event {
+    let egui_ctx = egui::Context::default();
+    let mut egui_state = egui_winit::State::new(&event_loop);
+    let mut egui_renderer = egui_wgpu::Renderer::new(&device, FORMAT, None, 1);
+
+    let mut settings = Settings::default();
+    let mut error = None;
+    let mut model = if paths.is_empty() {
+        None
+    } else {
+        Model::load(&device, &queue, &paths)
+            .map_err(|e| error = Some(e))
+            .ok()
+    };
+    // Files dropped together arrive one event at a time, so they're gathered up until the
+    // next frame.
+    let mut dropped = Vec::new();
+    let mut last_frame = Instant::now();
+
+    event_loop.run(move |event, _, control_flow| match event {
+        Event::WindowEvent { event, .. } => {
+            if egui_state.on_event(&egui_ctx, &event).consumed {
+                return;
+            }
+
+            match event {
+                WindowEvent::CloseRequested => control_flow.set_exit(),
+                // The surface is always as big as the window in physical pixels, and the
+                // renderer fits the puppet into whatever shape that is.
+                WindowEvent::Resized(size)
+                | WindowEvent::ScaleFactorChanged {
+                    new_inner_size: &mut size,
+                    ..
+                } if size.width > 0 && size.height > 0 => {
+                    config.width = size.width;
+                    config.height = size.height;
+                    surface.configure(&device, &config);
+                }
+                WindowEvent::DroppedFile(path) => dropped.push(path),
+                _ => {}
+            }
+        }
         Event::RedrawRequested(_) => {
-            let output = surface.get_current_texture().unwrap();
-            let view = (output.texture).create_view(&wgpu::TextureViewDescriptor::default());
+            if !dropped.is_empty() {
+                // Textures for a bare .moc3 follow it, in whatever order they were dropped.
+                dropped.sort_by_key(|x: &PathBuf| x.extension().is_some_and(|x| x == "png"));
+                match Model::load(&device, &queue, &dropped) {
+                    Ok(loaded) => {
+                        model = Some(loaded);
+                        error = None;
+                    }
+                    Err(e) => error = Some(e),
+                }
+                dropped.clear();
+            }
+            if let Some(model) = &mut model {
+                if let Err(e) = model.reload_changes(&device, &queue) {
+                    error = Some(e);
+                }
+            }
 
-            puppet.update(&params, &opacities, &mut frame_data);
+            let now = Instant::now();
+            let delta_seconds = (now - last_frame).as_secs_f32();
+            last_frame = now;
 
-            renderer.prepare(&device, &queue, output.texture.size(), &frame_data);
+            let input = egui_state.take_egui_input(&window);
+            let output = egui_ctx.run(input, |ctx| {
+                egui::SidePanel::right("settings").show(ctx, |ui| {
+                    if let Some(error) = &error {
+                        ui.colored_label(egui::Color32::RED, error);
+                    }
+                    match &mut model {
+                        Some(model) => settings_ui(ui, &mut settings, model),
+                        None => {
+                            ui.label("Drop a .model3.json or .moc3 to view it.");
+                        }
+                    }
+                });
+            });
+            egui_state.handle_platform_output(&window, &egui_ctx, output.platform_output);
+
+            let frame = surface.get_current_texture().unwrap();
+            let view = frame
+                .texture
+                .create_view(&wgpu::TextureViewDescriptor::default());
             let mut encoder =
                 device.create_command_encoder(&wgpu::CommandEncoderDescriptor { label: None });
-            renderer.render(&view, &mut encoder);
-            queue.submit(std::iter::once(encoder.finish()));
 
-            output.present();
+            match &mut model {
+                Some(model) => {
+                    model.update(delta_seconds, &settings);
+
+                    let renderer = &mut model.renderer;
+                    renderer.set_placeholder_mode(settings.placeholder_mode);
+                    renderer.set_fit_mode(settings.fit_mode);
+                    if renderer.debug_overlay() != settings.debug_overlay {
+                        renderer.set_debug_overlay(&device, &model.puppet, settings.debug_overlay);
+                    }
+                    renderer.prepare(&device, &queue, frame.texture.size(), &model.frame_data);
+                    renderer.render(&view, &mut encoder);
+                }
+                None => {
+                    encoder.begin_render_pass(&wgpu::RenderPassDescriptor {
+                        color_attachments: &[Some(wgpu::RenderPassColorAttachment {
+                            view: &view,
+                            resolve_target: None,
+                            ops: wgpu::Operations {
+                                load: wgpu::LoadOp::Clear(wgpu::Color::TRANSPARENT),
+                                store: true,
+                            },
+                        })],
+                        depth_stencil_attachment: None,
+                        label: None,
+                    });
+                }
+            }
+
+            // The panel goes over the puppet in a pass of its own.
+            let screen = egui_wgpu::renderer::ScreenDescriptor {
+                size_in_pixels: [config.width, config.height],
+                pixels_per_point: egui_ctx.pixels_per_point(),
+            };
+            let primitives = egui_ctx.tessellate(output.shapes);
+            for (id, delta) in &output.textures_delta.set {
+                egui_renderer.update_texture(&device, &queue, *id, delta);
+            }
+            let mut commands =
+                egui_renderer.update_buffers(&device, &queue, &mut encoder, &primitives, &screen);
+            {
+                let mut rpass = encoder.begin_render_pass(&wgpu::RenderPassDescriptor {
+                    color_attachments: &[Some(wgpu::RenderPassColorAttachment {
+                        view: &view,
+                        resolve_target: None,
+                        ops: wgpu::Operations {
+                            load: wgpu::LoadOp::Load,
+                            store: true,
+                        },
+                    })],
+                    depth_stencil_attachment: None,
+                    label: None,
+                });
+                egui_renderer.render(&mut rpass, &primitives, &screen);
+            }
+            for id in &output.textures_delta.free {
+                egui_renderer.free_texture(id);
+            }
+
+            commands.push(encoder.finish());
+            queue.submit(commands);
+            frame.present();
         }
         Event::MainEventsCleared => {
             window.request_redraw();
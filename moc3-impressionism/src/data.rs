@@ -1,6 +1,9 @@
 use glam::Vec2;
+use moc3_rs::puppet::{ParamData, PuppetRef};
 use serde::{Deserialize, Deserializer, Serialize};
 
+use crate::params::parameter_index;
+
 #[derive(Clone, Debug, Deserialize, Serialize)]
 #[serde(rename_all = "PascalCase")]
 pub struct Physics3Data {
@@ -28,7 +31,7 @@ pub struct PhysicsInput {
     pub source: PhysicsTarget,
     pub weight: f32,
     #[serde(rename = "Type")]
-    pub ty: String,
+    pub ty: PhysicsType,
     pub reflect: bool,
 }
 
@@ -40,10 +43,26 @@ pub struct PhysicsOutput {
     pub scale: f32,
     pub weight: f32,
     #[serde(rename = "Type")]
-    pub ty: String,
+    pub ty: PhysicsType,
     pub reflect: bool,
 }
 
+/// What an input reads from a parameter, or what an output writes to one. Lowercase names
+/// are accepted too, as some tools write those.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Deserialize, Serialize)]
+pub enum PhysicsType {
+    /// Horizontal translation.
+    #[serde(alias = "x")]
+    X,
+    /// Vertical translation.
+    #[serde(alias = "y")]
+    Y,
+    /// Rotation - for inputs this tilts the whole pendulum, for outputs it's the angle
+    /// of a vertex relative to the one before it.
+    #[serde(alias = "angle")]
+    Angle,
+}
+
 #[derive(Clone, Copy, Debug, Deserialize, Serialize)]
 #[serde(rename_all = "PascalCase")]
 pub struct PhysicsVertex {
@@ -70,13 +89,30 @@ pub struct ParamterData {
     pub default: f32,
 }
 
+/// What kind of object a [PhysicsTarget] refers to. Physics only ever reads and writes
+/// parameters.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Deserialize, Serialize)]
+pub enum PhysicsTargetKind {
+    #[serde(alias = "parameter")]
+    Parameter,
+}
+
 #[derive(Clone, Debug, Deserialize, Serialize)]
 #[serde(rename_all = "PascalCase")]
 pub struct PhysicsTarget {
-    pub target: String,
+    pub target: PhysicsTargetKind,
     pub id: String,
 }
 
+impl PhysicsTarget {
+    /// The index of the parameter this refers to, if the puppet has it.
+    pub fn parameter_index(&self, param_data: &ParamData) -> Option<usize> {
+        match self.target {
+            PhysicsTargetKind::Parameter => parameter_index(param_data, &self.id),
+        }
+    }
+}
+
 #[derive(Clone, Debug, Deserialize, Serialize)]
 #[serde(rename_all = "PascalCase")]
 pub struct Physics3Meta {
@@ -84,6 +120,9 @@ pub struct Physics3Meta {
     pub total_output_count: usize,
     pub vertex_count: usize,
     pub physics_setting_count: usize,
+    /// The rate physics is meant to be simulated at, only present in newer files.
+    #[serde(default)]
+    pub fps: Option<f32>,
     pub effective_forces: ForceData,
     pub physics_dictionary: Vec<PhysicsIdData>,
 }
@@ -98,12 +137,114 @@ pub struct PhysicsIdData {
 #[derive(Clone, Copy, Debug, Deserialize, Serialize)]
 #[serde(rename_all = "PascalCase")]
 pub struct ForceData {
-    #[serde(default, deserialize_with = "deserialize_vec2")]
+    #[serde(default = "default_gravity", deserialize_with = "deserialize_vec2")]
     pub gravity: Vec2,
     #[serde(default, deserialize_with = "deserialize_vec2")]
     pub wind: Vec2,
 }
 
+/// The contents of a userdata3.json, which tags drawables with arbitrary strings that
+/// applications use for things like hit areas.
+#[derive(Clone, Debug, Deserialize, Serialize)]
+#[serde(rename_all = "PascalCase")]
+pub struct UserData3 {
+    pub version: usize,
+    pub meta: UserData3Meta,
+    #[serde(default)]
+    pub user_data: Vec<UserDataEntry>,
+}
+
+#[derive(Clone, Debug, Deserialize, Serialize)]
+#[serde(rename_all = "PascalCase")]
+pub struct UserData3Meta {
+    pub user_data_count: usize,
+    pub total_user_data_size: usize,
+}
+
+#[derive(Clone, Debug, Deserialize, Serialize)]
+#[serde(rename_all = "PascalCase")]
+pub struct UserDataEntry {
+    /// What kind of object [UserDataEntry::id] refers to, which is always `ArtMesh` so far.
+    pub target: String,
+    pub id: String,
+    pub value: String,
+}
+
+/// The contents of a motion3.json, which animates parameters and part opacities over
+/// time. See [Motion](crate::motion::Motion) for playing one.
+#[derive(Clone, Debug, Deserialize, Serialize)]
+#[serde(rename_all = "PascalCase")]
+pub struct Motion3Data {
+    pub version: usize,
+    pub meta: Motion3Meta,
+    pub curves: Vec<Motion3Curve>,
+    #[serde(default)]
+    pub user_data: Vec<Motion3UserData>,
+}
+
+#[derive(Clone, Debug, Deserialize, Serialize)]
+#[serde(rename_all = "PascalCase")]
+pub struct Motion3Meta {
+    pub duration: f32,
+    pub fps: f32,
+    #[serde(default, rename = "Loop")]
+    pub looping: bool,
+    /// Whether every bezier's control points sit within its segment in time, which lets
+    /// them be evaluated without solving for time first.
+    #[serde(default)]
+    pub are_beziers_restricted: bool,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub fade_in_time: Option<f32>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub fade_out_time: Option<f32>,
+    pub curve_count: usize,
+    pub total_segment_count: usize,
+    pub total_point_count: usize,
+    #[serde(default)]
+    pub user_data_count: usize,
+    #[serde(default)]
+    pub total_user_data_size: usize,
+}
+
+#[derive(Clone, Debug, Deserialize, Serialize)]
+#[serde(rename_all = "PascalCase")]
+pub struct Motion3Curve {
+    /// What [Motion3Curve::id] refers to: `Parameter`, `PartOpacity` or `Model`.
+    pub target: String,
+    pub id: String,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub fade_in_time: Option<f32>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub fade_out_time: Option<f32>,
+    /// The time and value of the first point, then the type of each segment followed by
+    /// the times and values of its points, all flattened together.
+    pub segments: Vec<f32>,
+}
+
+/// An event fired when a motion reaches the given time.
+#[derive(Clone, Debug, Deserialize, Serialize)]
+#[serde(rename_all = "PascalCase")]
+pub struct Motion3UserData {
+    pub time: f32,
+    pub value: String,
+}
+
+impl UserData3 {
+    /// Attaches every art mesh entry to the puppet, returning how many matched an art mesh.
+    pub fn attach(&self, puppet: &mut PuppetRef) -> usize {
+        self.user_data
+            .iter()
+            .filter(|x| x.target == "ArtMesh")
+            .filter(|x| puppet.set_art_mesh_user_data(&x.id, x.value.clone()))
+            .count()
+    }
+}
+
+// Physics files without forces get the usual downwards pull (in Cubism's +y up space).
+fn default_gravity() -> Vec2 {
+    Vec2::NEG_Y
+}
+
 fn deserialize_vec2<'de, D>(deserializer: D) -> Result<Vec2, D::Error>
 where
     D: Deserializer<'de>,
@@ -118,3 +259,50 @@ where
 
     Ok(Vec2::new(res.x, res.y))
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::params::test_puppet;
+
+    const USER_DATA: &str = r#"{
+        "Version": 3,
+        "Meta": { "UserDataCount": 3, "TotalUserDataSize": 17 },
+        "UserData": [
+            { "Target": "ArtMesh", "Id": "ArtMesh1", "Value": "HitHead" },
+            { "Target": "ArtMesh", "Id": "Missing", "Value": "Lost" },
+            { "Target": "Part", "Id": "PartRoot", "Value": "Ignored" }
+        ]
+    }"#;
+
+    #[test]
+    fn parses_and_attaches_user_data() {
+        let user_data: UserData3 = serde_json::from_str(USER_DATA).unwrap();
+        assert_eq!(user_data.version, 3);
+        assert_eq!(user_data.meta.user_data_count, 3);
+        assert_eq!(user_data.meta.total_user_data_size, 17);
+        assert_eq!(user_data.user_data.len(), 3);
+        assert_eq!(user_data.user_data[0].target, "ArtMesh");
+        assert_eq!(user_data.user_data[0].id, "ArtMesh1");
+        assert_eq!(user_data.user_data[0].value, "HitHead");
+
+        // Only art mesh entries that name a real art mesh get attached.
+        let mut puppet = test_puppet();
+        assert_eq!(user_data.attach(&mut puppet), 1);
+        assert_eq!(puppet.art_mesh_user_data(1), Some("HitHead"));
+        assert_eq!(puppet.art_mesh_user_data(0), None);
+        assert_eq!(
+            puppet.art_meshes_with_user_data().collect::<Vec<_>>(),
+            [(1, "HitHead")]
+        );
+    }
+
+    #[test]
+    fn user_data_is_optional() {
+        let user_data: UserData3 = serde_json::from_str(
+            r#"{ "Version": 3, "Meta": { "UserDataCount": 0, "TotalUserDataSize": 0 } }"#,
+        )
+        .unwrap();
+        assert!(user_data.user_data.is_empty());
+    }
+}
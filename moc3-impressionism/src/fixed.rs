@@ -0,0 +1,125 @@
+/// Turns variable frame deltas into a whole number of fixed-size simulation steps.
+///
+/// Physics (and anything else stepped through it) then sees exactly the same sequence of
+/// deltas no matter how fast the machine renders, so headless renders and tests come out
+/// identical everywhere. Leftover time is carried over to the next frame, and
+/// [FixedTimestep::alpha] says how far between the last two steps the frame falls, for
+/// interpolating outputs so motion stays smooth at any frame rate.
+#[derive(Clone, Debug)]
+pub struct FixedTimestep {
+    step: f32,
+    /// The most steps a single frame may run. After a long stall (a breakpoint, or the
+    /// window being minimized) the remaining backlog is dropped instead of making the
+    /// next frame take even longer.
+    pub max_steps: u32,
+    // Kept in f64 so long sessions don't slowly drift.
+    accumulator: f64,
+}
+
+impl FixedTimestep {
+    pub fn new(step: f32) -> Self {
+        assert!(step > 0.0, "the fixed timestep must be positive");
+
+        Self {
+            step,
+            max_steps: 8,
+            accumulator: 0.0,
+        }
+    }
+
+    /// A step of `1 / rate` seconds.
+    pub fn with_rate(rate: f32) -> Self {
+        Self::new(1.0 / rate)
+    }
+
+    pub fn step(&self) -> f32 {
+        self.step
+    }
+
+    pub fn reset(&mut self) {
+        self.accumulator = 0.0;
+    }
+
+    /// Adds `delta_seconds` of frame time, returning how many steps should be run.
+    /// Negative and non-finite deltas count as no time at all.
+    pub fn advance(&mut self, delta_seconds: f32) -> u32 {
+        // An infinite delta would leave the accumulator NaN for good.
+        if delta_seconds.is_finite() && delta_seconds > 0.0 {
+            self.accumulator += delta_seconds as f64;
+        }
+
+        let step = self.step as f64;
+        let steps = (self.accumulator / step).floor();
+        if steps > self.max_steps as f64 {
+            self.accumulator %= step;
+            self.max_steps
+        } else {
+            self.accumulator -= steps * step;
+            steps as u32
+        }
+    }
+
+    /// Adds `delta_seconds` of frame time and calls `update` once per step with the
+    /// fixed delta, returning the number of steps run.
+    pub fn run(&mut self, delta_seconds: f32, mut update: impl FnMut(f32)) -> u32 {
+        let steps = self.advance(delta_seconds);
+        for _ in 0..steps {
+            update(self.step);
+        }
+
+        steps
+    }
+
+    /// How far the current frame is between the previous step and the latest one, in `[0, 1)`.
+    pub fn alpha(&self) -> f32 {
+        (self.accumulator / self.step as f64) as f32
+    }
+}
+
+/// Blends the outputs of the previous and latest steps by [FixedTimestep::alpha].
+pub fn interpolate_outputs(previous: &[f32], current: &[f32], alpha: f32, out: &mut [f32]) {
+    for ((o, a), b) in out.iter_mut().zip(previous).zip(current) {
+        *o = a + (b - a) * alpha;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn carries_leftover_time() {
+        let mut timestep = FixedTimestep::new(0.25);
+        assert_eq!(timestep.advance(0.6), 2);
+        assert!((timestep.alpha() - 0.4).abs() < 1e-4);
+        assert_eq!(timestep.advance(0.15), 1);
+        assert!(timestep.alpha().abs() < 1e-4);
+    }
+
+    #[test]
+    fn drops_backlog_past_max_steps() {
+        let mut timestep = FixedTimestep::new(0.25);
+        assert_eq!(timestep.advance(10.1), timestep.max_steps);
+        assert!((timestep.alpha() - 0.4).abs() < 1e-3);
+    }
+
+    #[test]
+    fn ignores_bad_deltas() {
+        let mut timestep = FixedTimestep::new(0.25);
+        timestep.advance(0.1);
+        for delta in [-1.0, f32::NAN, f32::INFINITY, f32::NEG_INFINITY] {
+            assert_eq!(timestep.advance(delta), 0);
+            assert!((timestep.alpha() - 0.4).abs() < 1e-4);
+        }
+
+        // Good deltas afterwards still step as usual.
+        assert_eq!(timestep.run(0.4, |delta| assert_eq!(delta, 0.25)), 2);
+    }
+
+    #[test]
+    fn interpolates_between_steps() {
+        let mut out = [0.0; 2];
+        interpolate_outputs(&[0.0, 10.0], &[1.0, 20.0], 0.25, &mut out);
+        assert_eq!(out, [0.25, 12.5]);
+    }
+}
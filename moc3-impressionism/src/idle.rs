@@ -0,0 +1,344 @@
+use std::f32::consts::TAU;
+
+use moc3_rs::puppet::ParamData;
+
+use crate::params::{clamp_parameter, parameter_index, parameter_indices, XorShift};
+
+/// Timing for [EyeBlinkController], all in seconds.
+#[derive(Clone, Copy, Debug)]
+pub struct EyeBlinkSettings {
+    /// Average time the eyes stay open between blinks.
+    pub interval: f32,
+    /// How much the interval may randomly vary, as a fraction of `interval`.
+    pub interval_randomness: f32,
+    pub closing: f32,
+    pub closed: f32,
+    pub opening: f32,
+}
+
+impl Default for EyeBlinkSettings {
+    fn default() -> Self {
+        // The official framework blinks every 4 seconds or so, with the blink itself
+        // taking around a fifth of a second.
+        Self {
+            interval: 4.0,
+            interval_randomness: 0.5,
+            closing: 0.1,
+            closed: 0.05,
+            opening: 0.15,
+        }
+    }
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+enum BlinkState {
+    Interval,
+    Closing,
+    Closed,
+    Opening,
+}
+
+/// Procedurally blinks the eyes at random intervals.
+///
+/// The blink value (1 for open, 0 for closed) multiplies whatever is already in the
+/// bound parameters, so blinking composes with tracked or animated eye openness.
+#[derive(Clone, Debug)]
+pub struct EyeBlinkController {
+    pub settings: EyeBlinkSettings,
+    parameters: Vec<usize>,
+    rng: XorShift,
+    state: BlinkState,
+    state_time: f32,
+    next_blink: f32,
+    value: f32,
+}
+
+impl EyeBlinkController {
+    pub fn new(settings: EyeBlinkSettings, seed: u32) -> Self {
+        let mut ret = Self {
+            settings,
+            parameters: Vec::new(),
+            rng: XorShift::new(seed),
+            state: BlinkState::Interval,
+            state_time: 0.0,
+            next_blink: 0.0,
+            value: 1.0,
+        };
+        ret.next_blink = ret.random_interval();
+
+        ret
+    }
+
+    /// Binds the controller to the eye parameters (usually `ParamEyeLOpen` and
+    /// `ParamEyeROpen`), returning the IDs that could not be found.
+    pub fn bind<'a>(
+        &mut self,
+        params: &ParamData,
+        ids: impl IntoIterator<Item = &'a str>,
+    ) -> Vec<&'a str> {
+        let (found, missing) = parameter_indices(params, ids);
+        self.parameters = found;
+        missing
+    }
+
+    pub fn parameters(&self) -> &[usize] {
+        &self.parameters
+    }
+
+    /// The current eye openness, 1 being fully open.
+    pub fn value(&self) -> f32 {
+        self.value
+    }
+
+    fn random_interval(&mut self) -> f32 {
+        let spread = self.settings.interval * self.settings.interval_randomness;
+        let offset = (self.rng.next_f32() * 2.0 - 1.0) * spread;
+
+        (self.settings.interval + offset).max(0.0)
+    }
+
+    pub fn update(&mut self, delta_seconds: f32) -> f32 {
+        let settings = &self.settings;
+        if settings.interval <= 0.0 && settings.closing + settings.closed + settings.opening <= 0.0
+        {
+            // There's no time to spend in any state, so we'd spin forever below.
+            self.value = 1.0;
+            return self.value;
+        }
+
+        self.state_time += delta_seconds.max(0.0);
+
+        // A long enough delta can skip past several states at once, so loop until we
+        // land in the state that the accumulated time belongs to.
+        loop {
+            let duration = match self.state {
+                BlinkState::Interval => self.next_blink,
+                BlinkState::Closing => self.settings.closing,
+                BlinkState::Closed => self.settings.closed,
+                BlinkState::Opening => self.settings.opening,
+            };
+            if self.state_time < duration {
+                break;
+            }
+
+            self.state_time -= duration;
+            self.state = match self.state {
+                BlinkState::Interval => BlinkState::Closing,
+                BlinkState::Closing => BlinkState::Closed,
+                BlinkState::Closed => BlinkState::Opening,
+                BlinkState::Opening => {
+                    self.next_blink = self.random_interval();
+                    BlinkState::Interval
+                }
+            };
+        }
+
+        let progress = |duration: f32| {
+            if duration > 0.0 {
+                (self.state_time / duration).clamp(0.0, 1.0)
+            } else {
+                1.0
+            }
+        };
+
+        self.value = match self.state {
+            BlinkState::Interval => 1.0,
+            BlinkState::Closing => 1.0 - progress(self.settings.closing),
+            BlinkState::Closed => 0.0,
+            BlinkState::Opening => progress(self.settings.opening),
+        };
+        self.value
+    }
+
+    pub fn apply(&self, param_data: &ParamData, params: &mut [f32]) {
+        for index in self.parameters.iter().copied() {
+            params[index] = clamp_parameter(param_data, index, params[index] * self.value);
+        }
+    }
+}
+
+/// A single parameter driven by [BreathController].
+#[derive(Clone, Copy, Debug)]
+pub struct BreathParameter {
+    pub parameter_index: usize,
+    /// The value the sine wave oscillates around.
+    pub offset: f32,
+    /// The amplitude of the sine wave.
+    pub peak: f32,
+    /// The length of a single breath, in seconds.
+    pub cycle: f32,
+    /// How strongly the breath is added on top of the existing value.
+    pub weight: f32,
+}
+
+/// Makes the puppet gently breathe by adding sine waves onto parameters.
+#[derive(Clone, Debug, Default)]
+pub struct BreathController {
+    pub parameters: Vec<BreathParameter>,
+    time: f32,
+}
+
+impl BreathController {
+    pub fn new(parameters: Vec<BreathParameter>) -> Self {
+        Self {
+            parameters,
+            time: 0.0,
+        }
+    }
+
+    /// The setup used by the official samples, skipping any parameters the puppet lacks.
+    pub fn with_default_parameters(param_data: &ParamData) -> Self {
+        let defaults = [
+            ("ParamAngleX", 0.0, 15.0, 6.5345, 0.5),
+            ("ParamAngleY", 0.0, 8.0, 3.5345, 0.5),
+            ("ParamAngleZ", 0.0, 10.0, 5.5345, 0.5),
+            ("ParamBodyAngleX", 0.0, 4.0, 15.5345, 0.5),
+            ("ParamBreath", 0.5, 0.5, 3.2345, 1.0),
+        ];
+
+        let parameters = defaults
+            .into_iter()
+            .filter_map(|(id, offset, peak, cycle, weight)| {
+                Some(BreathParameter {
+                    parameter_index: parameter_index(param_data, id)?,
+                    offset,
+                    peak,
+                    cycle,
+                    weight,
+                })
+            })
+            .collect();
+
+        Self::new(parameters)
+    }
+
+    pub fn update(&mut self, delta_seconds: f32) {
+        self.time += delta_seconds.max(0.0);
+    }
+
+    pub fn apply(&self, param_data: &ParamData, params: &mut [f32]) {
+        for breath in &self.parameters {
+            let index = breath.parameter_index;
+            let phase = if breath.cycle > 0.0 {
+                self.time * TAU / breath.cycle
+            } else {
+                0.0
+            };
+
+            let value = breath.offset + breath.peak * phase.sin();
+            params[index] =
+                clamp_parameter(param_data, index, params[index] + value * breath.weight);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::params::test_puppet;
+
+    fn close(a: f32, b: f32) -> bool {
+        (a - b).abs() < 1e-4
+    }
+
+    // Blinks once a second, without any randomness.
+    fn steady() -> EyeBlinkController {
+        EyeBlinkController::new(
+            EyeBlinkSettings {
+                interval: 1.0,
+                interval_randomness: 0.0,
+                ..Default::default()
+            },
+            1,
+        )
+    }
+
+    #[test]
+    fn blinks_close_then_open() {
+        let mut blink = steady();
+        assert_eq!(blink.update(0.5), 1.0);
+        // Halfway through closing, fully closed, then halfway through opening.
+        assert!(close(blink.update(0.55), 0.5));
+        assert_eq!(blink.update(0.075), 0.0);
+        assert!(close(blink.update(0.1), 0.5));
+        assert_eq!(blink.update(0.1), 1.0);
+    }
+
+    #[test]
+    fn long_updates_skip_whole_blinks() {
+        let mut blink = steady();
+        // Two full blinks of 1.3 seconds, then halfway into closing again.
+        assert!(close(blink.update(3.65), 0.5));
+
+        let mut instant = EyeBlinkController::new(
+            EyeBlinkSettings {
+                interval: 0.0,
+                closing: 0.0,
+                closed: 0.0,
+                opening: 0.0,
+                ..Default::default()
+            },
+            1,
+        );
+        assert_eq!(instant.update(1.0), 1.0);
+    }
+
+    #[test]
+    fn random_intervals_stay_in_range() {
+        let settings = EyeBlinkSettings::default();
+        for seed in 1..20 {
+            let mut blink = EyeBlinkController::new(settings, seed);
+            let mut time = 0.0;
+            while blink.update(0.01) == 1.0 {
+                time += 0.01;
+            }
+            assert!((2.0..=6.01).contains(&time), "{seed}: {time}");
+        }
+    }
+
+    #[test]
+    fn blinking_scales_eye_parameters() {
+        let puppet = test_puppet();
+        let param_data = puppet.param_data();
+        let mut blink = steady();
+        assert_eq!(
+            blink.bind(param_data, ["Param2", "ParamEyeROpen"]),
+            ["ParamEyeROpen"]
+        );
+
+        blink.update(1.05);
+        let mut params = vec![10.0; param_data.count as usize];
+        blink.apply(param_data, &mut params);
+        assert!(close(params[2], 5.0));
+        assert_eq!(params[1], 10.0);
+    }
+
+    #[test]
+    fn breathing_follows_a_sine_wave() {
+        let puppet = test_puppet();
+        let param_data = puppet.param_data();
+        assert!(BreathController::with_default_parameters(param_data)
+            .parameters
+            .is_empty());
+
+        let mut breath = BreathController::new(vec![BreathParameter {
+            parameter_index: 0,
+            offset: 1.0,
+            peak: 4.0,
+            cycle: 4.0,
+            weight: 0.5,
+        }]);
+        let mut breathe = |delta| {
+            breath.update(delta);
+            let mut params = vec![2.0; param_data.count as usize];
+            breath.apply(param_data, &mut params);
+            params[0]
+        };
+
+        // The top of the breath a quarter of the way in, and the bottom three quarters in.
+        assert!(close(breathe(1.0), 2.0 + (1.0 + 4.0) * 0.5));
+        assert!(close(breathe(2.0), 2.0 + (1.0 - 4.0) * 0.5));
+        // Time doesn't run backwards.
+        assert!(close(breathe(-1.0), 2.0 + (1.0 - 4.0) * 0.5));
+    }
+}
@@ -0,0 +1,177 @@
+use std::collections::VecDeque;
+
+/// Resamples parameter input arriving at a low or irregular rate (such as face tracking
+/// at 15-30 Hz) into smooth values at render rate.
+///
+/// Samples are tagged with the time they were captured, and [InputInterpolator::sample]
+/// linearly interpolates between the two samples around `time - delay`. Rendering slightly
+/// in the past means there's almost always a newer sample to interpolate towards, trading
+/// a bit of latency for smoothness. Timestamps are plain seconds from whatever clock the
+/// caller likes, as long as it is monotonic.
+#[derive(Clone, Debug)]
+pub struct InputInterpolator {
+    /// How far behind the requested time to sample, in seconds. Around one input
+    /// interval is usually a good choice.
+    pub delay: f64,
+    /// How far past the newest sample values may be extrapolated, in seconds. Zero holds
+    /// the newest sample instead.
+    pub max_extrapolation: f64,
+
+    parameter_count: usize,
+    capacity: usize,
+    samples: VecDeque<(f64, Vec<f32>)>,
+}
+
+impl InputInterpolator {
+    /// Keeps the newest `capacity` samples, which has to cover `delay` worth of input and
+    /// then some. Sampling further back than the oldest kept sample holds it instead, so
+    /// a delay of three input intervals needs a capacity of at least five.
+    pub fn new(parameter_count: usize, delay: f64, capacity: usize) -> Self {
+        assert!(capacity >= 2, "interpolating needs at least two samples");
+        Self {
+            delay,
+            max_extrapolation: 0.0,
+            parameter_count,
+            capacity,
+            samples: VecDeque::with_capacity(capacity),
+        }
+    }
+
+    pub fn capacity(&self) -> usize {
+        self.capacity
+    }
+
+    pub fn parameter_count(&self) -> usize {
+        self.parameter_count
+    }
+
+    pub fn clear(&mut self) {
+        self.samples.clear();
+    }
+
+    /// The timestamp of the newest sample, if any.
+    pub fn latest_timestamp(&self) -> Option<f64> {
+        self.samples.back().map(|x| x.0)
+    }
+
+    /// Records a new sample. Samples older than the newest one are dropped, as they'd
+    /// make the timeline go backwards.
+    pub fn push(&mut self, timestamp: f64, values: &[f32]) {
+        debug_assert_eq!(values.len(), self.parameter_count);
+
+        if let Some(latest) = self.latest_timestamp() {
+            if timestamp < latest {
+                return;
+            }
+        }
+
+        // Reuse the oldest buffer instead of allocating a fresh one for every sample.
+        let mut buffer = if self.samples.len() >= self.capacity {
+            self.samples.pop_front().unwrap().1
+        } else {
+            Vec::with_capacity(self.parameter_count)
+        };
+        buffer.clear();
+        buffer.extend_from_slice(values);
+
+        self.samples.push_back((timestamp, buffer));
+    }
+
+    /// Writes the interpolated values for `time` into `out`, returning false (and leaving
+    /// `out` untouched) if no samples have been pushed yet.
+    pub fn sample(&self, time: f64, out: &mut [f32]) -> bool {
+        debug_assert_eq!(out.len(), self.parameter_count);
+
+        let target = time - self.delay;
+
+        let (first_time, first) = match self.samples.front() {
+            Some(x) => x,
+            None => return false,
+        };
+        if self.samples.len() == 1 || target <= *first_time {
+            out.copy_from_slice(first);
+            return true;
+        }
+
+        // Find the first sample after the target, the one before it being our lower bound.
+        // Running off the end means we need to extrapolate from the last two samples.
+        let upper = self
+            .samples
+            .iter()
+            .position(|(timestamp, _)| *timestamp > target)
+            .unwrap_or(self.samples.len() - 1);
+        let (lower_time, lower) = &self.samples[upper - 1];
+        let (upper_time, upper) = &self.samples[upper];
+
+        let span = upper_time - lower_time;
+        if span <= 0.0 {
+            out.copy_from_slice(upper);
+            return true;
+        }
+
+        let target = target.min(upper_time + self.max_extrapolation);
+        let t = ((target - lower_time) / span).max(0.0) as f32;
+        for ((o, a), b) in out.iter_mut().zip(lower).zip(upper) {
+            *o = a + (b - a) * t;
+        }
+
+        true
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn sample(interpolator: &InputInterpolator, time: f64) -> f32 {
+        let mut out = [0.0];
+        assert!(interpolator.sample(time, &mut out));
+        out[0]
+    }
+
+    // One sample every tenth of a second, each a tenth higher than the last.
+    fn ramp(delay: f64, capacity: usize, count: usize) -> InputInterpolator {
+        let mut interpolator = InputInterpolator::new(1, delay, capacity);
+        for i in 0..count {
+            interpolator.push(i as f64 * 0.1, &[i as f32 * 0.1]);
+        }
+        interpolator
+    }
+
+    #[test]
+    fn samples_behind_by_the_delay() {
+        let interpolator = ramp(0.1, 8, 5);
+        assert!((sample(&interpolator, 0.35) - 0.25).abs() < 1e-5);
+        // Before the first sample, and past the newest one with no extrapolation.
+        assert_eq!(sample(&interpolator, 0.0), 0.0);
+        assert_eq!(sample(&interpolator, 9.0), 0.4);
+
+        assert!(!InputInterpolator::new(1, 0.1, 8).sample(1.0, &mut [0.0]));
+    }
+
+    #[test]
+    fn extrapolates_only_so_far() {
+        let mut interpolator = ramp(0.0, 8, 5);
+        interpolator.max_extrapolation = 0.05;
+        assert!((sample(&interpolator, 0.45) - 0.45).abs() < 1e-5);
+        assert!((sample(&interpolator, 2.0) - 0.45).abs() < 1e-5);
+    }
+
+    #[test]
+    fn older_samples_are_ignored() {
+        let mut interpolator = ramp(0.0, 8, 3);
+        interpolator.push(0.05, &[5.0]);
+        assert_eq!(interpolator.latest_timestamp(), Some(0.2));
+        assert!((sample(&interpolator, 0.15) - 0.15).abs() < 1e-5);
+    }
+
+    #[test]
+    fn overflowing_drops_the_oldest_samples() {
+        // A delay of 0.4 seconds, which 3 samples don't reach back far enough for.
+        let short = ramp(0.4, 3, 10);
+        let long = ramp(0.4, 6, 10);
+        assert_eq!(short.samples.len(), 3);
+        assert_eq!(sample(&short, 0.95), 0.7);
+        assert!((sample(&long, 0.95) - 0.55).abs() < 1e-5);
+    }
+}
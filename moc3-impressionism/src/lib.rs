@@ -1,5 +1,32 @@
 pub mod data;
+pub mod fixed;
+pub mod idle;
+pub mod interpolate;
+pub mod lipsync;
+pub mod look;
+pub mod motion;
+mod params;
 pub mod pendulum;
+pub mod physics;
+pub mod queue;
+pub mod smooth;
+pub mod tracking;
+#[cfg(feature = "vmc")]
+pub mod vmc;
 
-pub use data::PhysicsVertex;
+pub use data::{PhysicsTargetKind, PhysicsType, PhysicsVertex, UserData3};
+pub use fixed::FixedTimestep;
+pub use idle::{BreathController, BreathParameter, EyeBlinkController, EyeBlinkSettings};
+pub use interpolate::InputInterpolator;
+pub use lipsync::{LipSync, LipSyncSettings};
+pub use look::{LookAtController, LookAtParameter};
+pub use motion::{Motion, MotionPlayer, MotionRecorder};
 pub use pendulum::*;
+pub use physics::{
+    ControlledInput, ControlledOutput, PhysicsController, PhysicsSettingState, UnknownPhysicsTarget,
+};
+pub use queue::{MotionId, MotionQueue, MotionSettings};
+pub use smooth::{ParamSmoother, SmoothingKind};
+pub use tracking::{
+    FaceTrackingMapper, InvalidTrackingParameter, TrackingFrame, TrackingMapping, TrackingSource,
+};
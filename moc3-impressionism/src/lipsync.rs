@@ -0,0 +1,196 @@
+use moc3_rs::puppet::ParamData;
+
+use crate::params::{clamp_parameter, parameter_indices};
+
+/// Settings controlling how an audio envelope is turned into mouth movement.
+#[derive(Clone, Copy, Debug)]
+pub struct LipSyncSettings {
+    /// Multiplier applied to the envelope before clamping to `[0, 1]`.
+    pub gain: f32,
+    /// Envelope levels below this are treated as silence.
+    pub noise_floor: f32,
+    /// Time constant (in seconds) used while the mouth is opening.
+    pub attack: f32,
+    /// Time constant (in seconds) used while the mouth is closing.
+    pub release: f32,
+    /// How strongly the lip sync value is added on top of existing parameter values.
+    pub weight: f32,
+}
+
+impl Default for LipSyncSettings {
+    fn default() -> Self {
+        // These roughly match what the official samples use - a quick attack, a slightly
+        // slower release, and a weight of 0.8 when adding onto the mouth parameter.
+        Self {
+            gain: 4.0,
+            noise_floor: 0.01,
+            attack: 0.03,
+            release: 0.08,
+            weight: 0.8,
+        }
+    }
+}
+
+/// Drives `ParamMouthOpenY`-style parameters from audio volume.
+///
+/// Feed it either raw samples with [LipSync::push_samples] or an envelope value you
+/// computed yourself with [LipSync::push_envelope], then call [LipSync::apply] on the
+/// parameter slice right before it is handed to [moc3_rs::puppet::Puppet::update].
+#[derive(Clone, Debug)]
+pub struct LipSync {
+    pub settings: LipSyncSettings,
+    parameters: Vec<usize>,
+    value: f32,
+}
+
+impl LipSync {
+    pub fn new(settings: LipSyncSettings) -> Self {
+        Self {
+            settings,
+            parameters: Vec::new(),
+            value: 0.0,
+        }
+    }
+
+    /// Binds the lip sync output to the parameters with the given IDs, returning
+    /// the IDs that could not be found on the puppet.
+    pub fn bind<'a>(
+        &mut self,
+        params: &ParamData,
+        ids: impl IntoIterator<Item = &'a str>,
+    ) -> Vec<&'a str> {
+        let (found, missing) = parameter_indices(params, ids);
+        self.parameters = found;
+        missing
+    }
+
+    pub fn parameters(&self) -> &[usize] {
+        &self.parameters
+    }
+
+    /// The current smoothed mouth opening, in `[0, 1]`.
+    pub fn value(&self) -> f32 {
+        self.value
+    }
+
+    pub fn reset(&mut self) {
+        self.value = 0.0;
+    }
+
+    /// Updates the lip sync state with a block of mono samples in `[-1, 1]` that took
+    /// `delta_seconds` to play back.
+    pub fn push_samples(&mut self, samples: &[f32], delta_seconds: f32) -> f32 {
+        if samples.is_empty() {
+            return self.push_envelope(0.0, delta_seconds);
+        }
+
+        let sum: f32 = samples.iter().map(|x| x * x).sum();
+        let rms = (sum / samples.len() as f32).sqrt();
+        self.push_envelope(rms, delta_seconds)
+    }
+
+    /// Updates the lip sync state with an externally computed envelope (such as RMS or peak).
+    pub fn push_envelope(&mut self, envelope: f32, delta_seconds: f32) -> f32 {
+        let envelope = if envelope.is_finite() { envelope } else { 0.0 };
+        let target = if envelope < self.settings.noise_floor {
+            0.0
+        } else {
+            (envelope * self.settings.gain).clamp(0.0, 1.0)
+        };
+
+        let time_constant = if target > self.value {
+            self.settings.attack
+        } else {
+            self.settings.release
+        };
+
+        // Exponential smoothing, written in terms of a time constant so the
+        // result doesn't depend on how often we get called.
+        if time_constant <= 0.0 {
+            self.value = target;
+        } else if delta_seconds > 0.0 {
+            let alpha = 1.0 - (-delta_seconds / time_constant).exp();
+            self.value += (target - self.value) * alpha;
+        }
+
+        self.value
+    }
+
+    /// Adds the current value onto the bound parameters, clamping to the parameter ranges.
+    pub fn apply(&self, param_data: &ParamData, params: &mut [f32]) {
+        for index in self.parameters.iter().copied() {
+            let res = params[index] + self.value * self.settings.weight;
+            params[index] = clamp_parameter(param_data, index, res);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::params::test_puppet;
+
+    fn close(a: f32, b: f32) -> bool {
+        (a - b).abs() < 1e-5
+    }
+
+    #[test]
+    fn opens_quickly_and_closes_slowly() {
+        let settings = LipSyncSettings::default();
+        let mut lip_sync = LipSync::new(settings);
+
+        // Loud enough to open the mouth fully, for one attack time constant.
+        let opened = lip_sync.push_envelope(1.0, settings.attack);
+        assert!(close(opened, 1.0 - (-1.0f32).exp()));
+
+        let closed = lip_sync.push_envelope(0.0, settings.attack);
+        let release = (-settings.attack / settings.release).exp();
+        assert!(close(closed, opened * release));
+    }
+
+    #[test]
+    fn smoothing_ignores_call_rate() {
+        let mut once = LipSync::new(LipSyncSettings::default());
+        let mut twice = once.clone();
+        once.push_envelope(0.2, 0.04);
+        twice.push_envelope(0.2, 0.02);
+        twice.push_envelope(0.2, 0.02);
+        assert!(close(once.value(), twice.value()));
+    }
+
+    #[test]
+    fn quiet_and_broken_input_is_silence() {
+        let mut lip_sync = LipSync::new(LipSyncSettings {
+            attack: 0.0,
+            release: 0.0,
+            ..Default::default()
+        });
+        assert_eq!(lip_sync.push_envelope(0.005, 0.1), 0.0);
+        assert_eq!(lip_sync.push_samples(&[0.5, -0.5], 0.1), 1.0);
+        assert_eq!(lip_sync.push_envelope(f32::NAN, 0.1), 0.0);
+        assert_eq!(lip_sync.push_samples(&[], 0.1), 0.0);
+    }
+
+    #[test]
+    fn applies_on_top_of_parameters() {
+        let puppet = test_puppet();
+        let param_data = puppet.param_data();
+        let mut lip_sync = LipSync::new(LipSyncSettings {
+            attack: 0.0,
+            ..Default::default()
+        });
+        assert_eq!(
+            lip_sync.bind(param_data, ["Param1", "ParamMissing"]),
+            ["ParamMissing"]
+        );
+        assert_eq!(lip_sync.parameters(), [1]);
+
+        lip_sync.push_envelope(1.0, 0.1);
+        let mut params = vec![0.0; param_data.count as usize];
+        params[1] = 29.5;
+        lip_sync.apply(param_data, &mut params);
+        // Added with the default weight of 0.8, and clamped to the top of the range.
+        assert_eq!(params[1], 30.0);
+        assert_eq!(params[0], 0.0);
+    }
+}
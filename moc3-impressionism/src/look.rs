@@ -0,0 +1,226 @@
+use glam::{vec2, Vec2};
+use moc3_rs::puppet::{Canvas, ParamData};
+
+use crate::params::{clamp_parameter, parameter_index};
+
+/// A single parameter driven by [LookAtController].
+///
+/// The gaze adds `x * gaze.x + y * gaze.y + xy * gaze.x * gaze.y` to the parameter, where
+/// the gaze goes from -1 to 1 on both axes.
+#[derive(Clone, Copy, Debug)]
+pub struct LookAtParameter {
+    pub parameter_index: usize,
+    pub x: f32,
+    pub y: f32,
+    /// Mostly for tilting the head while looking into a corner.
+    pub xy: f32,
+}
+
+/// Makes the puppet look towards a point, like dragging in the official samples.
+///
+/// The gaze follows the target with limited speed and acceleration, so it eases in and
+/// out of moves instead of snapping to wherever the pointer is. Targets go from -1 to 1 on
+/// both axes, with +y pointing up, see [LookAtController::set_target_in_canvas] for going
+/// from model coordinates.
+#[derive(Clone, Debug)]
+pub struct LookAtController {
+    pub parameters: Vec<LookAtParameter>,
+    /// How fast the gaze can move at most, in target units per second.
+    pub max_speed: f32,
+    /// Seconds to get up to full speed from a standstill, and to stop again.
+    pub acceleration_time: f32,
+    target: Vec2,
+    gaze: Vec2,
+    velocity: Vec2,
+}
+
+impl LookAtController {
+    pub fn new(parameters: Vec<LookAtParameter>) -> Self {
+        // The same speeds as the official framework.
+        Self {
+            parameters,
+            max_speed: 4.0,
+            acceleration_time: 0.15,
+            target: Vec2::ZERO,
+            gaze: Vec2::ZERO,
+            velocity: Vec2::ZERO,
+        }
+    }
+
+    /// The setup used by the official samples, skipping any parameters the puppet lacks.
+    pub fn with_default_parameters(param_data: &ParamData) -> Self {
+        let defaults = [
+            ("ParamAngleX", 30.0, 0.0, 0.0),
+            ("ParamAngleY", 0.0, 30.0, 0.0),
+            ("ParamAngleZ", 0.0, 0.0, -30.0),
+            ("ParamBodyAngleX", 10.0, 0.0, 0.0),
+            ("ParamEyeBallX", 1.0, 0.0, 0.0),
+            ("ParamEyeBallY", 0.0, 1.0, 0.0),
+        ];
+
+        let parameters = defaults
+            .into_iter()
+            .filter_map(|(id, x, y, xy)| {
+                Some(LookAtParameter {
+                    parameter_index: parameter_index(param_data, id)?,
+                    x,
+                    y,
+                    xy,
+                })
+            })
+            .collect();
+
+        Self::new(parameters)
+    }
+
+    pub fn target(&self) -> Vec2 {
+        self.target
+    }
+
+    /// Where to look, clamped to -1 to 1 on both axes. Zero looks straight ahead.
+    pub fn set_target(&mut self, target: Vec2) {
+        self.target = target.clamp(Vec2::NEG_ONE, Vec2::ONE);
+    }
+
+    /// Looks towards a point in model coordinates, where the edges of the canvas are as far
+    /// as the gaze goes.
+    pub fn set_target_in_canvas(&mut self, canvas: &Canvas, point: Vec2) {
+        let (min, max) = canvas.bounds();
+        let target = (point - (min + max) / 2.0) / ((max - min) / 2.0);
+        // Model coordinates point down.
+        self.set_target(vec2(target.x, -target.y));
+    }
+
+    /// Where the puppet is looking right now.
+    pub fn gaze(&self) -> Vec2 {
+        self.gaze
+    }
+
+    /// Looks at the target straight away, without easing.
+    pub fn snap(&mut self) {
+        self.gaze = self.target;
+        self.velocity = Vec2::ZERO;
+    }
+
+    pub fn update(&mut self, delta_seconds: f32) -> Vec2 {
+        let delta_seconds = delta_seconds.max(0.0);
+        let to_target = self.target - self.gaze;
+        let distance = to_target.length();
+        if distance < 1e-4 && self.velocity.length() < 1e-3 {
+            self.snap();
+            return self.gaze;
+        }
+
+        let acceleration = if self.acceleration_time > 0.0 {
+            self.max_speed / self.acceleration_time
+        } else {
+            f32::INFINITY
+        };
+        // Slow down early enough to stop right on the target.
+        let speed = self.max_speed.min((2.0 * acceleration * distance).sqrt());
+        let wanted = to_target.normalize_or_zero() * speed;
+
+        let change = wanted - self.velocity;
+        let max_change = acceleration * delta_seconds;
+        self.velocity += if change.length() > max_change {
+            change.normalize_or_zero() * max_change
+        } else {
+            change
+        };
+
+        let step = self.velocity * delta_seconds;
+        if step.length() >= distance && step.dot(to_target) > 0.0 {
+            self.snap();
+        } else {
+            self.gaze += step;
+        }
+        self.gaze
+    }
+
+    /// Adds the gaze onto the parameters, on top of whatever is there already.
+    pub fn apply(&self, param_data: &ParamData, params: &mut [f32]) {
+        let Vec2 { x, y } = self.gaze;
+        for parameter in &self.parameters {
+            let index = parameter.parameter_index;
+            let value = parameter.x * x + parameter.y * y + parameter.xy * x * y;
+            params[index] = clamp_parameter(param_data, index, params[index] + value);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::params::test_puppet;
+
+    #[test]
+    fn eases_in_and_stops_on_the_target() {
+        let mut look = LookAtController::new(Vec::new());
+        look.set_target(vec2(1.0, 0.0));
+
+        // Still speeding up, so well short of what full speed would cover.
+        let first = look.update(0.05).x;
+        assert!(
+            first > 0.0 && first < look.max_speed * 0.05 / 2.0,
+            "{first}"
+        );
+
+        let mut last = first;
+        for _ in 0..100 {
+            let gaze = look.update(0.05);
+            assert!(gaze.x >= last && gaze.x <= 1.0, "{gaze}");
+            assert_eq!(gaze.y, 0.0);
+            last = gaze.x;
+        }
+        assert_eq!(look.gaze(), vec2(1.0, 0.0));
+    }
+
+    #[test]
+    fn canvas_targets_point_up_and_clamp() {
+        let canvas = Canvas {
+            size: vec2(200.0, 100.0),
+            origin: vec2(100.0, 50.0),
+            pixels_per_unit: 100.0,
+        };
+        let mut look = LookAtController::new(Vec::new());
+        look.set_target_in_canvas(&canvas, vec2(0.5, -0.25));
+        assert_eq!(look.target(), vec2(0.5, 0.5));
+        look.set_target_in_canvas(&canvas, vec2(-3.0, 3.0));
+        assert_eq!(look.target(), vec2(-1.0, -1.0));
+    }
+
+    #[test]
+    fn apply_adds_onto_parameters_and_clamps() {
+        let puppet = test_puppet();
+        let param_data = puppet.param_data();
+        let mut look = LookAtController::new(vec![
+            LookAtParameter {
+                parameter_index: 0,
+                x: 20.0,
+                y: 0.0,
+                xy: 0.0,
+            },
+            LookAtParameter {
+                parameter_index: 1,
+                x: 0.0,
+                y: 5.0,
+                xy: 10.0,
+            },
+        ]);
+        look.set_target(vec2(0.5, -1.0));
+        look.snap();
+
+        let mut params = [25.0, 1.0, 0.0, 0.0, 0.0, 0.0, 0.0, 0.0];
+        look.apply(param_data, &mut params);
+        assert_eq!(params[0], 30.0);
+        assert_eq!(params[1], 1.0 - 5.0 - 5.0);
+        assert_eq!(params[2], 0.0);
+    }
+
+    #[test]
+    fn default_parameters_skip_missing_ones() {
+        let puppet = test_puppet();
+        let look = LookAtController::with_default_parameters(puppet.param_data());
+        assert!(look.parameters.is_empty());
+    }
+}
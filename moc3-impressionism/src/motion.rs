@@ -0,0 +1,726 @@
+use glam::{vec2, Vec2};
+use moc3_rs::puppet::{ParamData, PuppetRef};
+use thiserror::Error;
+
+use crate::data::{Motion3Curve, Motion3Data, Motion3Meta, Motion3UserData};
+
+#[derive(Error, Debug, Clone, PartialEq)]
+pub enum MotionError {
+    #[error("curve {0} has no points")]
+    Empty(String),
+    #[error("curve {0} has an unknown segment type {1}")]
+    UnknownSegment(String, f32),
+    #[error("curve {0} ends partway through a segment")]
+    Truncated(String),
+}
+
+/// How a segment of a [MotionCurve] gets from one point to the next.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum SegmentKind {
+    Linear,
+    /// A cubic bezier, with two control points between the ends.
+    Bezier,
+    /// Holds the starting value until the segment ends.
+    Stepped,
+    /// Jumps straight to the ending value.
+    InverseStepped,
+}
+
+impl SegmentKind {
+    fn from_code(code: f32) -> Option<Self> {
+        match code as i32 {
+            0 => Some(Self::Linear),
+            1 => Some(Self::Bezier),
+            2 => Some(Self::Stepped),
+            3 => Some(Self::InverseStepped),
+            _ => None,
+        }
+    }
+
+    fn code(self) -> f32 {
+        match self {
+            Self::Linear => 0.0,
+            Self::Bezier => 1.0,
+            Self::Stepped => 2.0,
+            Self::InverseStepped => 3.0,
+        }
+    }
+
+    // Not counting the point it starts from, which is the end of the segment before it.
+    fn point_count(self) -> usize {
+        match self {
+            Self::Bezier => 3,
+            _ => 1,
+        }
+    }
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum CurveTarget {
+    Parameter,
+    PartOpacity,
+    /// Model-wide values, like how much eye blinking and lip sync apply. These aren't
+    /// played by [MotionPlayer].
+    Model,
+}
+
+impl CurveTarget {
+    fn name(self) -> &'static str {
+        match self {
+            Self::Parameter => "Parameter",
+            Self::PartOpacity => "PartOpacity",
+            Self::Model => "Model",
+        }
+    }
+}
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+struct Segment {
+    kind: SegmentKind,
+    // The index of the point it starts from.
+    start: usize,
+}
+
+impl Segment {
+    fn end(&self) -> usize {
+        self.start + self.kind.point_count()
+    }
+}
+
+/// A single animated value: points of (time, value) joined up by segments.
+#[derive(Clone, Debug)]
+pub struct MotionCurve {
+    pub target: CurveTarget,
+    pub id: String,
+    /// Fade times for just this curve, overriding the motion's own.
+    pub fade_in: Option<f32>,
+    pub fade_out: Option<f32>,
+    points: Vec<Vec2>,
+    segments: Vec<Segment>,
+}
+
+impl MotionCurve {
+    /// A curve joining every point with straight lines. There must be at least one point,
+    /// and they must be in order of time.
+    pub fn linear(target: CurveTarget, id: String, points: Vec<Vec2>) -> Self {
+        assert!(!points.is_empty(), "curves need at least one point");
+        let segments = (0..points.len().saturating_sub(1))
+            .map(|start| Segment {
+                kind: SegmentKind::Linear,
+                start,
+            })
+            .collect();
+
+        Self {
+            target,
+            id,
+            fade_in: None,
+            fade_out: None,
+            points,
+            segments,
+        }
+    }
+
+    fn from_data(curve: &Motion3Curve) -> Result<Self, MotionError> {
+        let target = match curve.target.as_str() {
+            "Parameter" => CurveTarget::Parameter,
+            "PartOpacity" => CurveTarget::PartOpacity,
+            _ => CurveTarget::Model,
+        };
+
+        let data = &curve.segments;
+        if data.len() < 2 {
+            return Err(MotionError::Empty(curve.id.clone()));
+        }
+
+        let mut points = vec![vec2(data[0], data[1])];
+        let mut segments = Vec::new();
+        let mut rest = &data[2..];
+        while let Some((&code, after)) = rest.split_first() {
+            let kind = SegmentKind::from_code(code)
+                .ok_or_else(|| MotionError::UnknownSegment(curve.id.clone(), code))?;
+            let len = kind.point_count() * 2;
+            if after.len() < len {
+                return Err(MotionError::Truncated(curve.id.clone()));
+            }
+
+            segments.push(Segment {
+                kind,
+                start: points.len() - 1,
+            });
+            points.extend(after[..len].chunks_exact(2).map(|x| vec2(x[0], x[1])));
+            rest = &after[len..];
+        }
+
+        Ok(Self {
+            target,
+            id: curve.id.clone(),
+            fade_in: curve.fade_in_time,
+            fade_out: curve.fade_out_time,
+            points,
+            segments,
+        })
+    }
+
+    fn to_data(&self) -> Motion3Curve {
+        let mut data = vec![self.points[0].x, self.points[0].y];
+        for segment in &self.segments {
+            data.push(segment.kind.code());
+            for point in &self.points[segment.start + 1..=segment.end()] {
+                data.extend([point.x, point.y]);
+            }
+        }
+
+        Motion3Curve {
+            target: self.target.name().to_owned(),
+            id: self.id.clone(),
+            fade_in_time: self.fade_in,
+            fade_out_time: self.fade_out,
+            segments: data,
+        }
+    }
+
+    /// The curve's value at the given time, holding its first and last values outside of
+    /// the time it covers. Restricted beziers are evaluated the cheaper way, see
+    /// [Motion3Meta::are_beziers_restricted].
+    pub fn value_at(&self, time: f32, beziers_restricted: bool) -> f32 {
+        let first = self.points[0];
+        if time <= first.x || self.segments.is_empty() {
+            return first.y;
+        }
+
+        // Right on a point, the segment starting there wins like in the official framework,
+        // so stepped segments have already stepped by the time they end.
+        let index = self
+            .segments
+            .partition_point(|x| self.points[x.end()].x <= time);
+        let Some(segment) = self.segments.get(index) else {
+            return self.points[self.points.len() - 1].y;
+        };
+
+        let points = &self.points[segment.start..=segment.end()];
+        let (start, end) = (points[0], points[points.len() - 1]);
+        let progress = if end.x > start.x {
+            (time - start.x) / (end.x - start.x)
+        } else {
+            1.0
+        };
+
+        match segment.kind {
+            SegmentKind::Linear => start.y + (end.y - start.y) * progress,
+            SegmentKind::Stepped => start.y,
+            SegmentKind::InverseStepped => end.y,
+            SegmentKind::Bezier => {
+                let t = if beziers_restricted {
+                    progress
+                } else {
+                    // Time along the curve only rises (or the file is broken anyway), so
+                    // bisecting finds where it passes the time we want.
+                    let (mut low, mut high) = (0.0, 1.0);
+                    for _ in 0..24 {
+                        let middle = (low + high) * 0.5;
+                        if bezier(points, middle).x < time {
+                            low = middle;
+                        } else {
+                            high = middle;
+                        }
+                    }
+                    (low + high) * 0.5
+                };
+                bezier(points, t).y
+            }
+        }
+    }
+}
+
+fn bezier(points: &[Vec2], t: f32) -> Vec2 {
+    let u = 1.0 - t;
+    points[0] * (u * u * u)
+        + points[1] * (3.0 * u * u * t)
+        + points[2] * (3.0 * u * t * t)
+        + points[3] * (t * t * t)
+}
+
+/// An animation of a puppet's parameters and part opacities, usually from a motion3.json.
+#[derive(Clone, Debug)]
+pub struct Motion {
+    pub duration: f32,
+    /// The frame rate the motion was made at, which doesn't affect how it plays.
+    pub fps: f32,
+    pub looping: bool,
+    pub beziers_restricted: bool,
+    /// How long the motion takes to fade in and out when played in a
+    /// [MotionQueue](crate::queue::MotionQueue), if the file says.
+    pub fade_in: Option<f32>,
+    pub fade_out: Option<f32>,
+    pub curves: Vec<MotionCurve>,
+    /// Events along the motion, as their times and values. Playing a motion reports each
+    /// one as it's passed, see [MotionPlayer::events].
+    pub events: Vec<(f32, String)>,
+}
+
+impl Motion {
+    pub fn from_data(data: &Motion3Data) -> Result<Self, MotionError> {
+        Ok(Self {
+            duration: data.meta.duration,
+            fps: data.meta.fps,
+            looping: data.meta.looping,
+            beziers_restricted: data.meta.are_beziers_restricted,
+            fade_in: data.meta.fade_in_time,
+            fade_out: data.meta.fade_out_time,
+            curves: data
+                .curves
+                .iter()
+                .map(MotionCurve::from_data)
+                .collect::<Result<_, _>>()?,
+            events: data
+                .user_data
+                .iter()
+                .map(|x| (x.time, x.value.clone()))
+                .collect(),
+        })
+    }
+
+    /// The motion in the shape of a motion3.json, ready to be serialized.
+    pub fn to_data(&self) -> Motion3Data {
+        Motion3Data {
+            version: 3,
+            meta: Motion3Meta {
+                duration: self.duration,
+                fps: self.fps,
+                looping: self.looping,
+                are_beziers_restricted: self.beziers_restricted,
+                fade_in_time: self.fade_in,
+                fade_out_time: self.fade_out,
+                curve_count: self.curves.len(),
+                total_segment_count: self.curves.iter().map(|x| x.segments.len()).sum(),
+                total_point_count: self.curves.iter().map(|x| x.points.len()).sum(),
+                user_data_count: self.events.len(),
+                total_user_data_size: self.events.iter().map(|(_, x)| x.len()).sum(),
+            },
+            curves: self.curves.iter().map(MotionCurve::to_data).collect(),
+            user_data: self
+                .events
+                .iter()
+                .map(|(time, value)| Motion3UserData {
+                    time: *time,
+                    value: value.clone(),
+                })
+                .collect(),
+        }
+    }
+}
+
+/// Plays a [Motion] on a puppet, overriding the parameters and part opacities it animates.
+#[derive(Clone, Debug)]
+pub struct MotionPlayer {
+    motion: Motion,
+    /// How much the motion overrides what's already there, from 0 to 1.
+    pub weight: f32,
+    time: f32,
+    // Pairs of curve indexes and the parameter or part they animate.
+    parameters: Vec<(usize, usize)>,
+    parts: Vec<(usize, usize)>,
+    // The events passed by the last update, by index.
+    fired: Vec<usize>,
+}
+
+impl MotionPlayer {
+    pub fn new(motion: Motion) -> Self {
+        Self {
+            motion,
+            weight: 1.0,
+            time: 0.0,
+            parameters: Vec::new(),
+            parts: Vec::new(),
+            fired: Vec::new(),
+        }
+    }
+
+    pub fn motion(&self) -> &Motion {
+        &self.motion
+    }
+
+    /// Binds every curve to the puppet's parameters and parts, returning the IDs of the
+    /// curves that could not be found.
+    pub fn bind(&mut self, puppet: &PuppetRef) -> Vec<&str> {
+        let param_data = puppet.param_data();
+        self.parameters.clear();
+        self.parts.clear();
+
+        let mut missing = Vec::new();
+        for (i, curve) in self.motion.curves.iter().enumerate() {
+            let found = match curve.target {
+                CurveTarget::Parameter => param_data
+                    .ids
+                    .iter()
+                    .position(|x| *x == curve.id)
+                    .map(|index| self.parameters.push((i, index))),
+                CurveTarget::PartOpacity => puppet
+                    .part_index(&curve.id)
+                    .map(|index| self.parts.push((i, index))),
+                CurveTarget::Model => Some(()),
+            };
+            if found.is_none() {
+                missing.push(curve.id.as_str());
+            }
+        }
+
+        missing
+    }
+
+    /// Stops animating any bound curves the closure returns false for, such as to leave
+    /// some parameters to something else. This lasts until the player is bound again.
+    pub fn retain_curves(&mut self, mut keep: impl FnMut(&MotionCurve) -> bool) {
+        let curves = &self.motion.curves;
+        self.parameters.retain(|(curve, _)| keep(&curves[*curve]));
+        self.parts.retain(|(curve, _)| keep(&curves[*curve]));
+    }
+
+    /// Every parameter the motion animates, by index.
+    pub fn parameters(&self) -> impl Iterator<Item = usize> + '_ {
+        self.parameters.iter().map(|(_, index)| *index)
+    }
+
+    /// Every part whose opacity the motion animates, by index.
+    pub fn parts(&self) -> impl Iterator<Item = usize> + '_ {
+        self.parts.iter().map(|(_, index)| *index)
+    }
+
+    /// How far into the motion playback is, in seconds.
+    pub fn time(&self) -> f32 {
+        self.time
+    }
+
+    pub fn set_time(&mut self, time: f32) {
+        self.time = time.clamp(0.0, self.motion.duration);
+    }
+
+    /// Whether a motion that doesn't loop has played all the way through.
+    pub fn is_finished(&self) -> bool {
+        !self.motion.looping && self.time >= self.motion.duration
+    }
+
+    pub fn update(&mut self, delta_seconds: f32) {
+        let duration = self.motion.duration;
+        let before = self.time;
+        self.time += delta_seconds.max(0.0);
+
+        let wrapped = self.motion.looping && duration > 0.0 && self.time >= duration;
+        if self.motion.looping && duration > 0.0 {
+            self.time %= duration;
+        } else {
+            self.time = self.time.min(duration);
+        }
+
+        self.fired.clear();
+        if delta_seconds <= 0.0 {
+            return;
+        }
+        // Events right at the start fire as soon as playback leaves it.
+        let passed = |start: f32, end: f32, time: f32| {
+            (time > start || (start == 0.0 && time == 0.0)) && time <= end
+        };
+        let now = self.time;
+        if wrapped {
+            self.fire(|time| passed(before, duration, time));
+            self.fire(|time| passed(0.0, now, time));
+        } else {
+            self.fire(|time| passed(before, now, time));
+        }
+    }
+
+    fn fire(&mut self, passed: impl Fn(f32) -> bool) {
+        let events = &self.motion.events;
+        let start = self.fired.len();
+        self.fired
+            .extend((0..events.len()).filter(|i| passed(events[*i].0)));
+        self.fired[start..].sort_by(|a, b| events[*a].0.total_cmp(&events[*b].0));
+    }
+
+    /// The values of the events the last [MotionPlayer::update] passed, in the order they
+    /// were passed. Seeking with [MotionPlayer::set_time] skips any events in between.
+    pub fn events(&self) -> impl Iterator<Item = &str> {
+        self.fired.iter().map(|i| self.motion.events[*i].1.as_str())
+    }
+
+    pub fn apply(&self, param_data: &ParamData, params: &mut [f32], opacities: &mut [f32]) {
+        self.apply_weighted(param_data, params, opacities, |_| self.weight);
+    }
+
+    // Like apply, but each curve can be weighted differently for fading.
+    pub(crate) fn apply_weighted(
+        &self,
+        param_data: &ParamData,
+        params: &mut [f32],
+        opacities: &mut [f32],
+        weight: impl Fn(&MotionCurve) -> f32,
+    ) {
+        let restricted = self.motion.beziers_restricted;
+        for &(curve, index) in &self.parameters {
+            let curve = &self.motion.curves[curve];
+            let value = curve.value_at(self.time, restricted);
+            let value = value.clamp(param_data.mins[index], param_data.maxes[index]);
+            params[index] += (value - params[index]) * weight(curve);
+        }
+        for &(curve, index) in &self.parts {
+            let curve = &self.motion.curves[curve];
+            let value = curve.value_at(self.time, restricted);
+            opacities[index] += (value - opacities[index]) * weight(curve);
+        }
+    }
+}
+
+/// Records every parameter of a puppet over time, such as during a face tracking session,
+/// to be saved as a motion3.json.
+#[derive(Clone, Debug)]
+pub struct MotionRecorder {
+    ids: Vec<String>,
+    times: Vec<f32>,
+    // Every recorded frame's values, one frame after another.
+    values: Vec<f32>,
+}
+
+impl MotionRecorder {
+    pub fn new(param_data: &ParamData) -> Self {
+        Self {
+            ids: param_data.ids.clone(),
+            times: Vec::new(),
+            values: Vec::new(),
+        }
+    }
+
+    /// Records the parameters as they are at the given time, in seconds from whenever's
+    /// convenient. Frames that aren't later than the last one are ignored.
+    pub fn record(&mut self, time: f32, params: &[f32]) {
+        assert_eq!(params.len(), self.ids.len(), "wrong number of parameters");
+        if self.times.last().is_some_and(|x| time <= *x) {
+            return;
+        }
+
+        self.times.push(time);
+        self.values.extend_from_slice(params);
+    }
+
+    /// How many frames have been recorded.
+    pub fn len(&self) -> usize {
+        self.times.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.times.is_empty()
+    }
+
+    pub fn clear(&mut self) {
+        self.times.clear();
+        self.values.clear();
+    }
+
+    /// Turns everything recorded so far into a motion starting from the first frame, with
+    /// every frame joined up by straight lines.
+    ///
+    /// With a tolerance above zero, frames are dropped wherever the lines can skip them
+    /// without any value being off by more than the tolerance. Recordings are usually
+    /// mostly noise-free stretches, so even a small tolerance shrinks them a lot.
+    pub fn finish(&self, tolerance: f32) -> Motion {
+        let start = self.times.first().copied().unwrap_or_default();
+        let times: Vec<f32> = self.times.iter().map(|x| x - start).collect();
+        let duration = times.last().copied().unwrap_or_default();
+
+        let count = self.ids.len();
+        let curves = if self.is_empty() {
+            Vec::new()
+        } else {
+            self.ids
+                .iter()
+                .enumerate()
+                .map(|(i, id)| {
+                    let points: Vec<Vec2> = times
+                        .iter()
+                        .zip(self.values[i..].iter().step_by(count))
+                        .map(|(time, value)| vec2(*time, *value))
+                        .collect();
+                    let points = if tolerance > 0.0 {
+                        simplify(&points, tolerance)
+                    } else {
+                        points
+                    };
+                    MotionCurve::linear(CurveTarget::Parameter, id.clone(), points)
+                })
+                .collect()
+        };
+
+        Motion {
+            duration,
+            // The rate frames came in at on average, for lack of anything better.
+            fps: if duration > 0.0 {
+                (times.len() - 1) as f32 / duration
+            } else {
+                30.0
+            },
+            looping: false,
+            beziers_restricted: true,
+            fade_in: None,
+            fade_out: None,
+            curves,
+            events: Vec::new(),
+        }
+    }
+}
+
+// Ramer-Douglas-Peucker, measuring how far off each point is in value rather than distance
+// since time and values aren't in the same units.
+fn simplify(points: &[Vec2], tolerance: f32) -> Vec<Vec2> {
+    if points.len() <= 2 {
+        return points.to_vec();
+    }
+
+    let mut keep = vec![false; points.len()];
+    keep[0] = true;
+    keep[points.len() - 1] = true;
+
+    // Long recordings could recurse deeply, so spans still to check go on a stack.
+    let mut spans = vec![(0, points.len() - 1)];
+    while let Some((first, last)) = spans.pop() {
+        let (a, b) = (points[first], points[last]);
+        let furthest = (first + 1..last)
+            .map(|i| {
+                let t = (points[i].x - a.x) / (b.x - a.x);
+                (i, (a.y + (b.y - a.y) * t - points[i].y).abs())
+            })
+            .max_by(|x, y| x.1.total_cmp(&y.1));
+
+        if let Some((i, error)) = furthest {
+            if error > tolerance {
+                keep[i] = true;
+                spans.push((first, i));
+                spans.push((i, last));
+            }
+        }
+    }
+
+    points
+        .iter()
+        .zip(keep)
+        .filter(|(_, keep)| *keep)
+        .map(|(point, _)| *point)
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn curve_data(segments: Vec<f32>) -> Motion3Curve {
+        Motion3Curve {
+            target: "Parameter".to_owned(),
+            id: "ParamAngleX".to_owned(),
+            fade_in_time: Some(0.5),
+            fade_out_time: None,
+            segments,
+        }
+    }
+
+    // A linear, stepped and then inverse stepped segment, a second long each, starting
+    // one second in.
+    fn every_straight_kind() -> MotionCurve {
+        MotionCurve::from_data(&curve_data(vec![
+            1.0, 0.0, //
+            0.0, 2.0, 10.0, //
+            2.0, 3.0, 20.0, //
+            3.0, 4.0, 30.0,
+        ]))
+        .unwrap()
+    }
+
+    #[test]
+    fn segment_kinds() {
+        let curve = every_straight_kind();
+        assert_eq!(curve.value_at(1.5, true), 5.0);
+        assert_eq!(curve.value_at(2.5, true), 10.0);
+        assert_eq!(curve.value_at(3.5, true), 30.0);
+    }
+
+    #[test]
+    fn times_outside_and_between_segments() {
+        let curve = every_straight_kind();
+        assert_eq!(curve.value_at(-5.0, true), 0.0);
+        assert_eq!(curve.value_at(1.0, true), 0.0);
+        assert_eq!(curve.value_at(4.0, true), 30.0);
+        assert_eq!(curve.value_at(60.0, true), 30.0);
+
+        // Each point belongs to the segment starting there, so the stepped segment doesn't
+        // hold its value through its end, and the inverse stepped one jumps right away.
+        assert_eq!(curve.value_at(2.0, true), 10.0);
+        assert_eq!(curve.value_at(3.0, true), 30.0);
+
+        let single = MotionCurve::from_data(&curve_data(vec![0.0, 7.0])).unwrap();
+        assert_eq!(single.value_at(3.0, false), 7.0);
+    }
+
+    #[test]
+    fn restricted_and_bisected_beziers() {
+        // Control points bunched up towards the end in time, so the two ways of
+        // evaluating disagree. Values along it go as the cube of the bezier's t.
+        let curve = MotionCurve::from_data(&curve_data(vec![
+            0.0, 0.0, //
+            1.0, 0.9, 0.0, 1.0, 0.0, 1.0, 1.0,
+        ]))
+        .unwrap();
+
+        // Restricted beziers take progress through the segment as t.
+        assert!((curve.value_at(0.5, true) - 0.125).abs() < 1e-6);
+
+        // Bisecting finds the t where the curve really is halfway through in time.
+        let bisected = curve.value_at(0.5, false);
+        let t = bisected.cbrt();
+        let points = [
+            vec2(0.0, 0.0),
+            vec2(0.9, 0.0),
+            vec2(1.0, 0.0),
+            vec2(1.0, 1.0),
+        ];
+        assert!((bezier(&points, t).x - 0.5).abs() < 1e-4, "{bisected}");
+
+        for restricted in [true, false] {
+            assert_eq!(curve.value_at(0.0, restricted), 0.0);
+            assert_eq!(curve.value_at(1.0, restricted), 1.0);
+        }
+    }
+
+    #[test]
+    fn curves_round_trip() {
+        let data = curve_data(vec![
+            0.0, 1.0, //
+            0.0, 0.5, 2.0, //
+            1.0, 0.6, 2.5, 0.9, 3.0, 1.0, 3.0, //
+            2.0, 1.5, 0.0, //
+            3.0, 2.0, 1.0,
+        ]);
+        let curve = MotionCurve::from_data(&data).unwrap();
+        assert_eq!(curve.segments.len(), 4);
+        assert_eq!(curve.points.len(), 7);
+
+        let back = curve.to_data();
+        assert_eq!(back.target, data.target);
+        assert_eq!(back.id, data.id);
+        assert_eq!(back.fade_in_time, data.fade_in_time);
+        assert_eq!(back.fade_out_time, data.fade_out_time);
+        assert_eq!(back.segments, data.segments);
+    }
+
+    #[test]
+    fn broken_curves_are_errors() {
+        let id = || "ParamAngleX".to_owned();
+        let error = |segments| MotionCurve::from_data(&curve_data(segments)).unwrap_err();
+        assert_eq!(error(vec![0.0]), MotionError::Empty(id()));
+        assert_eq!(
+            error(vec![0.0, 0.0, 7.0, 1.0, 1.0]),
+            MotionError::UnknownSegment(id(), 7.0)
+        );
+        assert_eq!(
+            error(vec![0.0, 0.0, 1.0, 0.3, 0.0, 0.6]),
+            MotionError::Truncated(id())
+        );
+    }
+}
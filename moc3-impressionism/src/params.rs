@@ -0,0 +1,59 @@
+use moc3_rs::puppet::ParamData;
+
+// Small helpers shared by the procedural controllers, all of which want to look
+// parameters up by ID once and then poke at the raw parameter slice every frame.
+
+pub(crate) fn parameter_index(param_data: &ParamData, id: &str) -> Option<usize> {
+    param_data.ids.iter().position(|x| x == id)
+}
+
+// Resolves every ID, returning the indexes that were found and the IDs that weren't.
+pub(crate) fn parameter_indices<'a>(
+    param_data: &ParamData,
+    ids: impl IntoIterator<Item = &'a str>,
+) -> (Vec<usize>, Vec<&'a str>) {
+    let mut found = Vec::new();
+    let mut missing = Vec::new();
+    for id in ids {
+        match parameter_index(param_data, id) {
+            Some(index) => found.push(index),
+            None => missing.push(id),
+        }
+    }
+
+    (found, missing)
+}
+
+pub(crate) fn clamp_parameter(param_data: &ParamData, index: usize, value: f32) -> f32 {
+    value.clamp(param_data.mins[index], param_data.maxes[index])
+}
+
+// A tiny xorshift generator, so the controllers can be randomized without
+// pulling in a dependency (and so results are reproducible given a seed).
+#[derive(Clone, Debug)]
+pub(crate) struct XorShift(u32);
+
+impl XorShift {
+    pub fn new(seed: u32) -> Self {
+        // Zero is the one state xorshift can never leave.
+        Self(if seed == 0 { 0x9E37_79B9 } else { seed })
+    }
+
+    // Returns a value in [0, 1).
+    pub fn next_f32(&mut self) -> f32 {
+        let mut x = self.0;
+        x ^= x << 13;
+        x ^= x >> 17;
+        x ^= x << 5;
+        self.0 = x;
+
+        (x >> 8) as f32 / (1u32 << 24) as f32
+    }
+}
+
+// A generated puppet for the controllers' tests, with parameters `Param0` to `Param7`
+// ranging from -30 to 30 and resting at 0.
+#[cfg(test)]
+pub(crate) fn test_puppet() -> moc3_rs::puppet::Puppet {
+    moc3_rs::parse_puppet(&moc3_bench::SyntheticModel::SMALL.to_moc3()).unwrap()
+}
@@ -1,3 +1,5 @@
+use std::f32::consts::{PI, TAU};
+
 use glam::Vec2;
 
 use crate::data::PhysicsVertex;
@@ -9,13 +11,57 @@ pub struct PendulumPoint {
     pub cur_velocity: Vec2,
 }
 
+impl PendulumPoint {
+    /// The position between the last two updates, for use with
+    /// [crate::fixed::FixedTimestep::alpha].
+    pub fn interpolated_position(&self, alpha: f32) -> Vec2 {
+        self.last_position.lerp(self.cur_position, alpha)
+    }
+}
+
+#[derive(Clone, Copy, Debug)]
 pub struct UpdateData {
     pub translation: Vec2,
     pub rotation: f32, // radians
+    /// Which way (and how strongly) gravity pulls before rotation, with +y pointing down.
+    pub gravity: Vec2,
+    /// A constant force applied on top of gravity, unaffected by rotation.
+    pub wind: Vec2,
+    /// Horizontal positions closer to zero than this snap to it, which Cubism does to
+    /// keep pendulums from jittering at rest. Only [Integrator::Cubism] uses this.
+    pub movement_threshold: f32,
+}
+
+impl Default for UpdateData {
+    fn default() -> Self {
+        Self {
+            translation: Vec2::ZERO,
+            rotation: 0.0,
+            gravity: Vec2::Y,
+            wind: Vec2::ZERO,
+            movement_threshold: 0.0,
+        }
+    }
+}
+
+/// How a [Pendulum] is stepped forward in time.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum Integrator {
+    /// Worked out by watching models move, see [Pendulum::update_points].
+    #[default]
+    Heuristic,
+    /// Plain Verlet integration, with each point's speed carried over from how far it
+    /// moved last step (scaled down by its mobility) rather than kept separately.
+    Verlet,
+    /// A reimplementation of what the official Cubism framework does, quirks included.
+    Cubism,
 }
 
 pub struct Pendulum {
+    pub integrator: Integrator,
     last_global_rotation: f32,
+    // Which way gravity pulled last update, for the Cubism integrator.
+    last_gravity: Vec2,
     pub points: Vec<PendulumPoint>,
     vertexes: Vec<PhysicsVertex>,
 }
@@ -25,7 +71,9 @@ impl Pendulum {
         let vertexes = vertexes.into_iter();
 
         let mut ret = Pendulum {
+            integrator: Integrator::default(),
             last_global_rotation: 0.0,
+            last_gravity: Vec2::Y,
             points: Vec::with_capacity(vertexes.size_hint().0),
             vertexes: Vec::with_capacity(vertexes.size_hint().0),
         };
@@ -75,7 +123,29 @@ impl Pendulum {
     // and the user applying energy via parameters. The settings for each bob (vertex) were determined
     // experimentally. Acceleration and radius seem pretty obvious, delay seems to have a time-slowing effect
     // and mobility is just some fudge factor applied to the velocity (maybe?, could also be accel).
+    pub fn vertexes(&self) -> &[PhysicsVertex] {
+        &self.vertexes
+    }
+
+    /// The vertex settings can be changed at any time, taking effect on the next update.
+    /// Positions only decide where the points start out, so changing them does nothing.
+    pub fn vertexes_mut(&mut self) -> &mut [PhysicsVertex] {
+        &mut self.vertexes
+    }
+
     pub fn update_points(&mut self, delta_seconds: f32, update_data: UpdateData) {
+        if self.points.is_empty() {
+            return;
+        }
+
+        match self.integrator {
+            Integrator::Heuristic => self.update_heuristic(delta_seconds, update_data),
+            Integrator::Verlet => self.update_verlet(delta_seconds, update_data),
+            Integrator::Cubism => self.update_cubism(delta_seconds, update_data),
+        }
+    }
+
+    fn update_heuristic(&mut self, delta_seconds: f32, update_data: UpdateData) {
         let delta_seconds = delta_seconds * 20.0;
         if delta_seconds == 0.0 {
             return;
@@ -84,8 +154,9 @@ impl Pendulum {
         // Rotating the entire world gives the pendulum an angle change of factor of 0.2, weird.
         let effective_rotation_change = (self.last_global_rotation - update_data.rotation) / 5.0;
 
-        // Calculate which way gravity points, remember +y is down.
-        let gravity_vector = Vec2::from(update_data.rotation.sin_cos());
+        // Calculate which way gravity points, remember +y is down. With the default
+        // gravity this works out to (sin, cos) of the rotation.
+        let gravity_vector = Vec2::from_angle(-update_data.rotation).rotate(update_data.gravity);
 
         // This is technically unused, but it's kept updated for debugging reasons.
         self.points[0].last_position = self.points[0].cur_position;
@@ -99,7 +170,7 @@ impl Pendulum {
 
             // The force applied to the pendulum due to gravity
             // (we assume mass is 1 for simplicity).
-            let force = gravity_vector * vertex.acceleration;
+            let force = gravity_vector * vertex.acceleration + update_data.wind;
             // Delay scales the passage of time - fancy time dilation!
             let effective_time = delta_seconds * vertex.delay;
 
@@ -134,4 +205,89 @@ impl Pendulum {
 
         self.last_global_rotation = update_data.rotation;
     }
+
+    fn update_verlet(&mut self, delta_seconds: f32, update_data: UpdateData) {
+        // The same time scale as Cubism, so vertex settings mean roughly the same thing.
+        let delta_seconds = delta_seconds * 30.0;
+        let gravity = Vec2::from_angle(-update_data.rotation).rotate(update_data.gravity);
+
+        self.points[0].last_position = self.points[0].cur_position;
+        self.points[0].cur_position = update_data.translation;
+
+        for i in 1..self.points.len() {
+            let vertex = self.vertexes[i];
+            let parent = self.points[i - 1].cur_position;
+            let point = &mut self.points[i];
+
+            let effective_time = delta_seconds * vertex.delay;
+            let force = gravity * vertex.acceleration + update_data.wind;
+            let carried = (point.cur_position - point.last_position) * vertex.mobility;
+            let moved = point.cur_position + carried + force * effective_time * effective_time;
+
+            point.last_position = point.cur_position;
+            point.cur_position = parent + (moved - parent).normalize_or_zero() * vertex.radius;
+            point.cur_velocity = if effective_time == 0.0 {
+                Vec2::ZERO
+            } else {
+                (point.cur_position - point.last_position) / effective_time
+            };
+        }
+    }
+
+    // Follows CubismPhysics.cpp's UpdateParticles (and the bit of Evaluate before it)
+    // line for line, so it can be compared against traces from the official framework.
+    fn update_cubism(&mut self, delta_seconds: f32, update_data: UpdateData) {
+        const AIR_RESISTANCE: f32 = 5.0;
+
+        // The translation is turned against the rotation first. Cubism reuses the new x
+        // when working out y, and so does this to match.
+        let (sin, cos) = (-update_data.rotation).sin_cos();
+        let mut translation = update_data.translation;
+        translation.x = translation.x * cos - translation.y * sin;
+        translation.y = translation.x * sin + translation.y * cos;
+
+        // Gravity gets turned the other way from the translation, again as Cubism does.
+        let gravity = Vec2::from_angle(update_data.rotation)
+            .rotate(update_data.gravity)
+            .normalize_or_zero();
+        let radian = direction_to_radians(self.last_gravity, gravity) / AIR_RESISTANCE;
+        let (sin, cos) = radian.sin_cos();
+
+        self.points[0].last_position = self.points[0].cur_position;
+        self.points[0].cur_position = translation;
+
+        for i in 1..self.points.len() {
+            let vertex = self.vertexes[i];
+            let parent = self.points[i - 1].cur_position;
+            let point = &mut self.points[i];
+
+            let force = gravity * vertex.acceleration + update_data.wind;
+            point.last_position = point.cur_position;
+            let delay = vertex.delay * delta_seconds * 30.0;
+
+            // The same reuse of the new x as above.
+            let mut direction = point.cur_position - parent;
+            direction.x = cos * direction.x - direction.y * sin;
+            direction.y = sin * direction.x + direction.y * cos;
+
+            let moved = parent + direction + point.cur_velocity * delay + force * delay * delay;
+            point.cur_position = parent + (moved - parent).normalize_or_zero() * vertex.radius;
+
+            if point.cur_position.x.abs() < update_data.movement_threshold {
+                point.cur_position.x = 0.0;
+            }
+            if delay != 0.0 {
+                point.cur_velocity =
+                    (point.cur_position - point.last_position) / delay * vertex.mobility;
+            }
+        }
+
+        self.last_gravity = gravity;
+    }
+}
+
+// The signed angle needed to rotate `from` onto `to`, in [-pi, pi].
+pub(crate) fn direction_to_radians(from: Vec2, to: Vec2) -> f32 {
+    let angle = to.y.atan2(to.x) - from.y.atan2(from.x);
+    (angle + PI).rem_euclid(TAU) - PI
 }
@@ -0,0 +1,398 @@
+use glam::Vec2;
+use moc3_rs::puppet::ParamData;
+
+use crate::{
+    data::{ParamterData, Physics3Data, PhysicsNormalization, PhysicsSetting, PhysicsType},
+    fixed::FixedTimestep,
+    params::clamp_parameter,
+    pendulum::{direction_to_radians, Integrator, Pendulum, PendulumPoint, UpdateData},
+};
+
+// Physics3 weights are given as percentages.
+const MAXIMUM_WEIGHT: f32 = 100.0;
+// How close to still Cubism lets pendulums get before snapping them there, relative to
+// the position normalization.
+const MOVEMENT_THRESHOLD: f32 = 0.001;
+
+/// A parameter feeding into a physics setting.
+#[derive(Clone, Debug)]
+pub struct ControlledInput {
+    pub parameter_index: usize,
+    /// How much this input contributes, in `[0, 1]` (physics3.json uses percentages).
+    pub weight: f32,
+    pub ty: PhysicsType,
+    pub reflect: bool,
+}
+
+/// A parameter driven by a physics setting.
+#[derive(Clone, Debug)]
+pub struct ControlledOutput {
+    pub parameter_index: usize,
+    /// The pendulum vertex this output follows. Vertex 0 is the root, which never moves
+    /// on its own, so it is ignored.
+    pub vertex_index: usize,
+    pub scale: f32,
+    /// How much this output overrides the existing value, in `[0, 1]`.
+    pub weight: f32,
+    pub ty: PhysicsType,
+    pub reflect: bool,
+    // The values from the last two steps, for interpolating between them.
+    values: Option<[f32; 2]>,
+}
+
+/// A single physics setting (usually one strand of hair or piece of clothing) being simulated.
+pub struct PhysicsSettingState {
+    pub id: String,
+    pub pendulum: Pendulum,
+    /// Replaces [PhysicsController::gravity] for just this setting.
+    pub gravity_override: Option<Vec2>,
+    /// Replaces [PhysicsController::wind] for just this setting.
+    pub wind_override: Option<Vec2>,
+
+    pub normalization: PhysicsNormalization,
+    inputs: Vec<ControlledInput>,
+    outputs: Vec<ControlledOutput>,
+}
+
+impl PhysicsSettingState {
+    fn new(setting: &PhysicsSetting, param_data: &ParamData) -> Self {
+        let default_normalization = PhysicsNormalization {
+            position: ParamterData {
+                minimum: -10.0,
+                maximum: 10.0,
+                default: 0.0,
+            },
+            angle: ParamterData {
+                minimum: -10.0,
+                maximum: 10.0,
+                default: 0.0,
+            },
+        };
+
+        Self {
+            id: setting.id.clone(),
+            pendulum: Pendulum::new(setting.vertices.iter().copied()),
+            gravity_override: None,
+            wind_override: None,
+            normalization: setting.normalization.unwrap_or(default_normalization),
+            inputs: setting
+                .input
+                .iter()
+                .filter_map(|input| {
+                    Some(ControlledInput {
+                        parameter_index: input.source.parameter_index(param_data)?,
+                        weight: input.weight / MAXIMUM_WEIGHT,
+                        ty: input.ty,
+                        reflect: input.reflect,
+                    })
+                })
+                .collect(),
+            outputs: setting
+                .output
+                .iter()
+                .filter_map(|output| {
+                    Some(ControlledOutput {
+                        parameter_index: output.destination.parameter_index(param_data)?,
+                        vertex_index: output.vertex_index,
+                        scale: output.scale,
+                        weight: output.weight / MAXIMUM_WEIGHT,
+                        ty: output.ty,
+                        reflect: output.reflect,
+                        values: None,
+                    })
+                })
+                .collect(),
+        }
+    }
+
+    pub fn inputs(&self) -> &[ControlledInput] {
+        &self.inputs
+    }
+
+    /// Inputs can be tweaked freely while physics is running.
+    pub fn inputs_mut(&mut self) -> &mut [ControlledInput] {
+        &mut self.inputs
+    }
+
+    pub fn outputs(&self) -> &[ControlledOutput] {
+        &self.outputs
+    }
+
+    /// Outputs can be tweaked freely while physics is running.
+    pub fn outputs_mut(&mut self) -> &mut [ControlledOutput] {
+        &mut self.outputs
+    }
+}
+
+/// An input or output referring to a parameter the puppet doesn't have, from
+/// [Physics3Data::validate].
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct UnknownPhysicsTarget<'a> {
+    /// The ID of the physics setting it's in.
+    pub setting: &'a str,
+    pub id: &'a str,
+    pub is_output: bool,
+}
+
+impl Physics3Data {
+    /// Resolves every input and output against the puppet's parameters, returning the
+    /// ones that couldn't be found. [PhysicsController] skips these, which is usually a
+    /// sign the physics was made for a different version of the model.
+    pub fn validate(&self, param_data: &ParamData) -> Vec<UnknownPhysicsTarget<'_>> {
+        let mut unknown = Vec::new();
+        for setting in &self.physics_settings {
+            let inputs = setting.input.iter().map(|x| (&x.source, false));
+            let outputs = setting.output.iter().map(|x| (&x.destination, true));
+            for (target, is_output) in inputs.chain(outputs) {
+                if target.parameter_index(param_data).is_none() {
+                    unknown.push(UnknownPhysicsTarget {
+                        setting: &setting.id,
+                        id: &target.id,
+                        is_output,
+                    });
+                }
+            }
+        }
+
+        unknown
+    }
+}
+
+/// Runs every physics setting from a physics3.json against a puppet's parameters.
+pub struct PhysicsController {
+    /// The direction gravity pulls in, with +y pointing up like physics3.json's
+    /// `EffectiveForces`. The length scales how strong it is.
+    pub gravity: Vec2,
+    /// A constant force on every pendulum, in the same space as `gravity`.
+    pub wind: Vec2,
+    settings: Vec<PhysicsSettingState>,
+    timestep: Option<FixedTimestep>,
+}
+
+// Maps a parameter value onto the normalization range, with the parameter's midpoint
+// landing on the normalization default. This mirrors what the official runtime does,
+// including the sign flip for non-reflected inputs.
+fn normalize_parameter(
+    param_data: &ParamData,
+    index: usize,
+    value: f32,
+    normalization: ParamterData,
+    reflect: bool,
+) -> f32 {
+    let max = param_data.maxes[index].max(param_data.mins[index]);
+    let min = param_data.maxes[index].min(param_data.mins[index]);
+    let value = value.clamp(min, max);
+
+    let norm_max = normalization.maximum.max(normalization.minimum);
+    let norm_min = normalization.maximum.min(normalization.minimum);
+    let norm_default = normalization.default;
+
+    let middle = min + (max - min) / 2.0;
+    let offset = value - middle;
+
+    let result = if offset > 0.0 && max != middle {
+        offset * ((norm_max - norm_default) / (max - middle)) + norm_default
+    } else if offset < 0.0 && min != middle {
+        offset * ((norm_min - norm_default) / (min - middle)) + norm_default
+    } else {
+        norm_default
+    };
+
+    if reflect {
+        result
+    } else {
+        -result
+    }
+}
+
+// Cubism's physics space has +y pointing up, but our pendulums have +y pointing down.
+fn flip_y(x: Vec2) -> Vec2 {
+    Vec2::new(x.x, -x.y)
+}
+
+impl PhysicsController {
+    /// Sets up the physics for a puppet. Inputs and outputs referring to parameters the
+    /// puppet doesn't have are skipped, see [Physics3Data::validate].
+    pub fn new(data: &Physics3Data, param_data: &ParamData) -> Self {
+        let settings = data
+            .physics_settings
+            .iter()
+            .map(|setting| PhysicsSettingState::new(setting, param_data))
+            .collect();
+
+        Self {
+            gravity: data.meta.effective_forces.gravity,
+            wind: data.meta.effective_forces.wind,
+            settings,
+            timestep: Some(FixedTimestep::with_rate(data.meta.fps.unwrap_or(60.0))),
+        }
+    }
+
+    /// Swaps in new physics settings (such as after the file was edited), keeping the
+    /// motion of settings that still have the same ID and number of vertices so the
+    /// puppet doesn't visibly snap. Per-setting overrides are kept as well.
+    pub fn reload(&mut self, data: &Physics3Data, param_data: &ParamData) {
+        let mut old_settings = std::mem::take(&mut self.settings);
+
+        for setting in &data.physics_settings {
+            let mut state = PhysicsSettingState::new(setting, param_data);
+
+            if let Some(old) = old_settings.iter_mut().find(|x| x.id == state.id) {
+                state.gravity_override = old.gravity_override;
+                state.wind_override = old.wind_override;
+                if old.pendulum.points.len() == state.pendulum.points.len() {
+                    state.pendulum.points = std::mem::take(&mut old.pendulum.points);
+                }
+            }
+
+            self.settings.push(state);
+        }
+
+        self.gravity = data.meta.effective_forces.gravity;
+        self.wind = data.meta.effective_forces.wind;
+        if let Some(fps) = data.meta.fps {
+            self.timestep = Some(FixedTimestep::with_rate(fps));
+        }
+    }
+
+    /// Steps every setting's pendulum with the given integrator from now on, see
+    /// [Integrator]. Settings can also be changed one at a time through
+    /// [Pendulum::integrator].
+    pub fn set_integrator(&mut self, integrator: Integrator) {
+        for setting in &mut self.settings {
+            setting.pendulum.integrator = integrator;
+        }
+    }
+
+    pub fn settings(&self) -> &[PhysicsSettingState] {
+        &self.settings
+    }
+
+    pub fn settings_mut(&mut self) -> &mut [PhysicsSettingState] {
+        &mut self.settings
+    }
+
+    pub fn setting_mut(&mut self, id: &str) -> Option<&mut PhysicsSettingState> {
+        self.settings.iter_mut().find(|x| x.id == id)
+    }
+
+    /// How physics is stepped, or [None] to step once per update with whatever delta
+    /// is given. Fixed steps keep pendulums stable at low frame rates, with outputs
+    /// interpolated between steps so they stay smooth at high ones.
+    pub fn timestep(&self) -> Option<&FixedTimestep> {
+        self.timestep.as_ref()
+    }
+
+    pub fn set_timestep(&mut self, timestep: Option<FixedTimestep>) {
+        self.timestep = timestep;
+    }
+
+    /// Advances physics by `delta_seconds`, reading inputs from and writing outputs
+    /// to `params`.
+    pub fn update(&mut self, delta_seconds: f32, param_data: &ParamData, params: &mut [f32]) {
+        let (steps, step, alpha) = match &mut self.timestep {
+            Some(timestep) => {
+                let steps = timestep.advance(delta_seconds);
+                (steps, timestep.step(), timestep.alpha())
+            }
+            None => (1, delta_seconds, 1.0),
+        };
+
+        for setting in &mut self.settings {
+            if setting.pendulum.points.is_empty() {
+                continue;
+            }
+
+            let gravity = flip_y(setting.gravity_override.unwrap_or(self.gravity));
+            let wind = flip_y(setting.wind_override.unwrap_or(self.wind));
+
+            // Inputs don't change between substeps, so only gather them once.
+            let mut translation = Vec2::ZERO;
+            let mut angle = 0.0;
+            for input in &setting.inputs {
+                let index = input.parameter_index;
+                let normalization = match input.ty {
+                    PhysicsType::X | PhysicsType::Y => setting.normalization.position,
+                    PhysicsType::Angle => setting.normalization.angle,
+                };
+                let value = normalize_parameter(
+                    param_data,
+                    index,
+                    params[index],
+                    normalization,
+                    input.reflect,
+                ) * input.weight;
+
+                match input.ty {
+                    PhysicsType::X => translation.x += value,
+                    PhysicsType::Y => translation.y += value,
+                    PhysicsType::Angle => angle += value,
+                }
+            }
+
+            for _ in 0..steps {
+                setting.pendulum.update_points(
+                    step,
+                    UpdateData {
+                        translation,
+                        rotation: angle.to_radians(),
+                        gravity,
+                        wind,
+                        movement_threshold: MOVEMENT_THRESHOLD
+                            * setting.normalization.position.maximum,
+                    },
+                );
+
+                let points = &setting.pendulum.points;
+                for output in &mut setting.outputs {
+                    if let Some(value) = output_value(output, points, gravity) {
+                        output.values = Some(match output.values {
+                            Some([_, current]) => [current, value],
+                            None => [value, value],
+                        });
+                    }
+                }
+            }
+
+            for output in &setting.outputs {
+                let Some([previous, current]) = output.values else {
+                    continue;
+                };
+                let value = previous + (current - previous) * alpha;
+
+                let index = output.parameter_index;
+                let blended = if output.weight >= 1.0 {
+                    value
+                } else {
+                    params[index] * (1.0 - output.weight) + value * output.weight
+                };
+                params[index] = clamp_parameter(param_data, index, blended);
+            }
+        }
+    }
+}
+
+fn output_value(output: &ControlledOutput, points: &[PendulumPoint], gravity: Vec2) -> Option<f32> {
+    let vertex = output.vertex_index;
+    if vertex == 0 || vertex >= points.len() {
+        return None;
+    }
+
+    let translation = points[vertex].cur_position - points[vertex - 1].cur_position;
+    let value = match output.ty {
+        PhysicsType::X => translation.x,
+        PhysicsType::Y => translation.y,
+        PhysicsType::Angle => {
+            // Angles are measured against the segment before this one, or against
+            // gravity for the first segment hanging off the root.
+            let parent = if vertex >= 2 {
+                points[vertex - 1].cur_position - points[vertex - 2].cur_position
+            } else {
+                gravity
+            };
+            direction_to_radians(parent, translation)
+        }
+    };
+
+    Some(if output.reflect { -value } else { value } * output.scale)
+}
@@ -0,0 +1,301 @@
+use std::f32::consts::PI;
+
+use moc3_rs::puppet::{ParamData, PuppetRef};
+
+use crate::{
+    motion::{Motion, MotionCurve, MotionPlayer},
+    params::XorShift,
+};
+
+// What the official framework fades motions in and out over when their files don't say.
+const DEFAULT_FADE: f32 = 1.0;
+
+// Idle motions give way to anything else that's played.
+const IDLE_PRIORITY: i32 = i32::MIN;
+
+/// How a motion is played by a [MotionQueue].
+#[derive(Clone, Debug)]
+pub struct MotionSettings {
+    /// Motions can't start over playing motions with a higher priority that animate any of
+    /// the same parameters or parts, and fade out those with the same or a lower one.
+    pub priority: i32,
+    /// Overrides the motion's own fade times, in seconds. Curves with fade times of their
+    /// own keep them.
+    pub fade_in: Option<f32>,
+    pub fade_out: Option<f32>,
+    /// Overrides whether the motion loops.
+    pub looping: Option<bool>,
+    /// Limits the motion to only the parameters and parts with these IDs, so it can be
+    /// layered with others that animate the rest.
+    pub mask: Option<Vec<String>>,
+    /// How much the motion overrides what's under it, from 0 to 1.
+    pub weight: f32,
+}
+
+impl Default for MotionSettings {
+    fn default() -> Self {
+        Self {
+            priority: 0,
+            fade_in: None,
+            fade_out: None,
+            looping: None,
+            mask: None,
+            weight: 1.0,
+        }
+    }
+}
+
+/// Identifies a motion played by a [MotionQueue].
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+pub struct MotionId(u64);
+
+#[derive(Clone, Debug)]
+struct Playing {
+    id: MotionId,
+    player: MotionPlayer,
+    priority: i32,
+    fade_in: f32,
+    fade_out: f32,
+    // Seconds since it started, which keeps counting through loops.
+    elapsed: f32,
+    // When it's done fading out after being stopped, in elapsed time.
+    stopped_by: Option<f32>,
+}
+
+impl Playing {
+    fn is_fading_out(&self) -> bool {
+        let motion = self.player.motion();
+        self.stopped_by.is_some()
+            || (!motion.looping && motion.duration - self.player.time() <= self.fade_out)
+    }
+
+    fn is_done(&self) -> bool {
+        match self.stopped_by {
+            Some(end) => self.elapsed >= end,
+            None => self.player.is_finished(),
+        }
+    }
+
+    fn overlaps(&self, other: &MotionPlayer) -> bool {
+        self.player
+            .parameters()
+            .any(|x| other.parameters().any(|y| x == y))
+            || self.player.parts().any(|x| other.parts().any(|y| x == y))
+    }
+
+    fn weight(&self, curve: &MotionCurve) -> f32 {
+        let fade_in = curve.fade_in.unwrap_or(self.fade_in);
+        let fade_out = curve.fade_out.unwrap_or(self.fade_out);
+
+        let motion = self.player.motion();
+        let remaining = match self.stopped_by {
+            Some(end) => end - self.elapsed,
+            None if !motion.looping => motion.duration - self.player.time(),
+            None => f32::INFINITY,
+        };
+
+        self.player.weight * fade(self.elapsed, fade_in) * fade(remaining, fade_out)
+    }
+}
+
+// Eases from 0 to 1 over the fade time, the same way the official framework does.
+fn fade(time: f32, fade_time: f32) -> f32 {
+    if fade_time <= 0.0 {
+        return if time > 0.0 { 1.0 } else { 0.0 };
+    }
+
+    let t = (time / fade_time).clamp(0.0, 1.0);
+    0.5 - 0.5 * (t * PI).cos()
+}
+
+/// Plays any number of motions at once, blending them into a single set of parameters.
+///
+/// Motions are applied from the lowest priority to the highest, so higher priority motions
+/// override lower ones wherever they animate the same things and leave everything else
+/// alone. They fade in as they start, and fade out as they end, get stopped, or get
+/// replaced by another motion. Whenever nothing else is in the way, one of the idle
+/// motions is picked at random and played.
+#[derive(Clone, Debug)]
+pub struct MotionQueue {
+    // Kept sorted by priority, then by when they started.
+    playing: Vec<Playing>,
+    next_id: u64,
+
+    idle_motions: Vec<(MotionPlayer, MotionSettings)>,
+    rng: XorShift,
+
+    // Events passed by the last update, kept here since motions can finish during it.
+    fired: Vec<(MotionId, String)>,
+}
+
+impl MotionQueue {
+    pub fn new(seed: u32) -> Self {
+        Self {
+            playing: Vec::new(),
+            next_id: 0,
+
+            idle_motions: Vec::new(),
+            rng: XorShift::new(seed),
+
+            fired: Vec::new(),
+        }
+    }
+
+    /// Sets the motions to fall back to whenever nothing else is playing. These always
+    /// have the lowest priority, and masking them keeps them playing under motions that
+    /// animate everything else rather than waiting for those to finish.
+    pub fn set_idle_motions(
+        &mut self,
+        puppet: &PuppetRef,
+        motions: impl IntoIterator<Item = Motion>,
+        settings: MotionSettings,
+    ) {
+        self.idle_motions = motions
+            .into_iter()
+            .map(|motion| {
+                let player = bound_player(puppet, motion, &settings);
+                (player, settings.clone())
+            })
+            .collect();
+    }
+
+    /// Starts playing a motion, returning [None] if a motion with a higher priority that
+    /// animates any of the same things is playing.
+    pub fn play(
+        &mut self,
+        puppet: &PuppetRef,
+        motion: Motion,
+        settings: MotionSettings,
+    ) -> Option<MotionId> {
+        let player = bound_player(puppet, motion, &settings);
+        self.start(player, &settings)
+    }
+
+    // Motions on their way out are never in the way, nor need replacing.
+    fn overlapping<'a>(&'a self, player: &'a MotionPlayer) -> impl Iterator<Item = &'a Playing> {
+        self.playing
+            .iter()
+            .filter(|x| !x.is_fading_out() && x.overlaps(player))
+    }
+
+    fn start(&mut self, player: MotionPlayer, settings: &MotionSettings) -> Option<MotionId> {
+        let priority = settings.priority;
+        if self.overlapping(&player).any(|x| x.priority > priority) {
+            return None;
+        }
+        let replaced: Vec<MotionId> = self.overlapping(&player).map(|x| x.id).collect();
+        for id in replaced {
+            self.stop(id);
+        }
+
+        let id = MotionId(self.next_id);
+        self.next_id += 1;
+
+        let motion = player.motion();
+        let playing = Playing {
+            id,
+            priority,
+            fade_in: settings.fade_in.or(motion.fade_in).unwrap_or(DEFAULT_FADE),
+            fade_out: settings
+                .fade_out
+                .or(motion.fade_out)
+                .unwrap_or(DEFAULT_FADE),
+            elapsed: 0.0,
+            stopped_by: None,
+            player,
+        };
+        let index = self.playing.partition_point(|x| x.priority <= priority);
+        self.playing.insert(index, playing);
+
+        Some(id)
+    }
+
+    /// Fades a motion out, if it's still playing.
+    pub fn stop(&mut self, id: MotionId) {
+        if let Some(playing) = self.playing.iter_mut().find(|x| x.id == id) {
+            playing
+                .stopped_by
+                .get_or_insert(playing.elapsed + playing.fade_out);
+        }
+    }
+
+    /// Fades every motion out, idle ones included.
+    pub fn stop_all(&mut self) {
+        for playing in &mut self.playing {
+            playing
+                .stopped_by
+                .get_or_insert(playing.elapsed + playing.fade_out);
+        }
+    }
+
+    /// Whether the motion is still playing, even if it's fading out.
+    pub fn is_playing(&self, id: MotionId) -> bool {
+        self.playing.iter().any(|x| x.id == id)
+    }
+
+    /// Whether only idle motions (or nothing at all) are playing, not counting motions
+    /// that are fading out.
+    pub fn is_idle(&self) -> bool {
+        self.playing
+            .iter()
+            .all(|x| x.priority == IDLE_PRIORITY || x.is_fading_out())
+    }
+
+    pub fn update(&mut self, delta_seconds: f32) {
+        let delta_seconds = delta_seconds.max(0.0);
+        self.fired.clear();
+        for playing in &mut self.playing {
+            playing.elapsed += delta_seconds;
+            playing.player.update(delta_seconds);
+            self.fired
+                .extend(playing.player.events().map(|x| (playing.id, x.to_owned())));
+        }
+        self.playing.retain(|x| !x.is_done());
+
+        // The next idle motion fades in as the last one fades out, as soon as nothing's in
+        // its way.
+        let idling = self
+            .playing
+            .iter()
+            .any(|x| !x.is_fading_out() && x.priority == IDLE_PRIORITY);
+        if idling || self.idle_motions.is_empty() {
+            return;
+        }
+        let index = (self.rng.next_f32() * self.idle_motions.len() as f32) as usize;
+        let (player, settings) = &self.idle_motions[index.min(self.idle_motions.len() - 1)];
+        if self.overlapping(player).next().is_none() {
+            let settings = MotionSettings {
+                priority: IDLE_PRIORITY,
+                ..settings.clone()
+            };
+            self.start(player.clone(), &settings);
+        }
+    }
+
+    /// The events every motion passed during the last [MotionQueue::update], along with
+    /// which motion they're from, like [MotionPlayer::events].
+    pub fn events(&self) -> impl Iterator<Item = (MotionId, &str)> {
+        self.fired.iter().map(|(id, value)| (*id, value.as_str()))
+    }
+
+    pub fn apply(&self, param_data: &ParamData, params: &mut [f32], opacities: &mut [f32]) {
+        for playing in &self.playing {
+            playing
+                .player
+                .apply_weighted(param_data, params, opacities, |curve| playing.weight(curve));
+        }
+    }
+}
+
+fn bound_player(puppet: &PuppetRef, mut motion: Motion, settings: &MotionSettings) -> MotionPlayer {
+    if let Some(looping) = settings.looping {
+        motion.looping = looping;
+    }
+    let mut player = MotionPlayer::new(motion);
+    player.weight = settings.weight;
+    player.bind(puppet);
+    if let Some(mask) = &settings.mask {
+        player.retain_curves(|curve| mask.contains(&curve.id));
+    }
+    player
+}
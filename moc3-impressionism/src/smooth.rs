@@ -0,0 +1,191 @@
+use moc3_rs::puppet::ParamData;
+
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum SmoothingKind {
+    /// Values jump straight to the target.
+    None,
+    /// Values approach the target exponentially, moving a fixed fraction of the
+    /// remaining distance per time constant.
+    #[default]
+    Exponential,
+    /// A critically damped spring. Compared to exponential smoothing this eases in as
+    /// well as out, at the cost of lagging slightly more behind fast moving targets.
+    CriticallyDamped,
+}
+
+/// Smooths noisy parameter input (such as face tracking) before it reaches
+/// [moc3_rs::puppet::Puppet::update].
+///
+/// The smoother holds one value per puppet parameter, laid out just like [ParamData],
+/// so its output can be passed to the puppet as-is.
+#[derive(Clone, Debug)]
+pub struct ParamSmoother {
+    kinds: Vec<SmoothingKind>,
+    time_constants: Vec<f32>,
+
+    // Needed to take the short way around for repeating parameters.
+    ranges: Vec<Option<(f32, f32)>>,
+
+    current: Vec<f32>,
+    velocities: Vec<f32>,
+}
+
+impl ParamSmoother {
+    pub fn new(param_data: &ParamData, kind: SmoothingKind, time_constant: f32) -> Self {
+        let count = param_data.count as usize;
+
+        let mut ranges = Vec::with_capacity(count);
+        for i in 0..count {
+            ranges.push(param_data.repeats[i].then_some((param_data.mins[i], param_data.maxes[i])));
+        }
+
+        Self {
+            kinds: vec![kind; count],
+            time_constants: vec![time_constant; count],
+            ranges,
+            current: param_data.defaults.clone(),
+            velocities: vec![0.0; count],
+        }
+    }
+
+    pub fn set_kind(&mut self, index: usize, kind: SmoothingKind) {
+        self.kinds[index] = kind;
+    }
+
+    /// Sets the time constant (in seconds) for a parameter. Larger is smoother but laggier.
+    pub fn set_time_constant(&mut self, index: usize, time_constant: f32) {
+        self.time_constants[index] = time_constant;
+    }
+
+    pub fn values(&self) -> &[f32] {
+        &self.current
+    }
+
+    /// Snaps the smoother to the given values, discarding any momentum.
+    pub fn reset(&mut self, values: &[f32]) {
+        self.current.copy_from_slice(values);
+        self.velocities.fill(0.0);
+    }
+
+    /// Moves every parameter towards `targets` by `delta_seconds`, returning the smoothed values.
+    pub fn update(&mut self, targets: &[f32], delta_seconds: f32) -> &[f32] {
+        debug_assert_eq!(targets.len(), self.current.len());
+
+        let dt = delta_seconds.max(0.0);
+        for (i, mut target) in targets.iter().copied().enumerate() {
+            let current = self.current[i];
+
+            // For repeating parameters (like a full rotation), approach the target via
+            // whichever direction is shorter, and wrap the result back into range.
+            if let Some((min, max)) = self.ranges[i] {
+                let range = max - min;
+                if range > 0.0 {
+                    let diff = (target - current).rem_euclid(range);
+                    target = if diff > range / 2.0 {
+                        current + diff - range
+                    } else {
+                        current + diff
+                    };
+                }
+            }
+
+            let time_constant = self.time_constants[i];
+            let next = if time_constant <= 0.0 || !current.is_finite() {
+                self.velocities[i] = 0.0;
+                target
+            } else {
+                match self.kinds[i] {
+                    SmoothingKind::None => target,
+                    SmoothingKind::Exponential => {
+                        let alpha = 1.0 - (-dt / time_constant).exp();
+                        current + (target - current) * alpha
+                    }
+                    SmoothingKind::CriticallyDamped => {
+                        // The classic closed-form critically damped spring, with the
+                        // exponential approximated by a polynomial.
+                        // (Game Programming Gems 4, chapter 1.10)
+                        let omega = 2.0 / time_constant;
+                        let x = omega * dt;
+                        let exp = 1.0 / (1.0 + x + 0.48 * x * x + 0.235 * x * x * x);
+                        let change = current - target;
+                        let temp = (self.velocities[i] + omega * change) * dt;
+                        self.velocities[i] = (self.velocities[i] - omega * temp) * exp;
+                        target + (change + temp) * exp
+                    }
+                }
+            };
+
+            self.current[i] = match self.ranges[i] {
+                Some((min, max)) if max > min => min + (next - min).rem_euclid(max - min),
+                _ => next,
+            };
+        }
+
+        &self.current
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::params::test_puppet;
+
+    fn smoother(kind: SmoothingKind) -> ParamSmoother {
+        ParamSmoother::new(test_puppet().param_data(), kind, 0.1)
+    }
+
+    #[test]
+    fn exponential_covers_most_of_the_way_per_time_constant() {
+        let mut smoother = smoother(SmoothingKind::Exponential);
+        let targets = [10.0; 8];
+        let value = smoother.update(&targets, 0.1)[0];
+        assert!((value - 10.0 * (1.0 - (-1.0f32).exp())).abs() < 1e-4);
+
+        smoother.set_kind(1, SmoothingKind::None);
+        smoother.set_time_constant(2, 0.0);
+        let values = smoother.update(&targets, 0.0);
+        assert_eq!(values[1], 10.0);
+        assert_eq!(values[2], 10.0);
+        assert!(values[0] < 10.0);
+    }
+
+    #[test]
+    fn springs_ease_in_without_overshooting() {
+        let mut spring = smoother(SmoothingKind::CriticallyDamped);
+        let mut exponential = smoother(SmoothingKind::Exponential);
+        let targets = [10.0; 8];
+        assert!(spring.update(&targets, 0.01)[0] < exponential.update(&targets, 0.01)[0]);
+
+        let mut last = 0.0;
+        for _ in 0..200 {
+            let value = spring.update(&targets, 0.01)[0];
+            assert!((last..=10.0).contains(&value), "{value}");
+            last = value;
+        }
+        assert!((last - 10.0).abs() < 1e-2);
+    }
+
+    #[test]
+    fn repeating_parameters_go_the_short_way() {
+        let mut param_data = test_puppet().param_data().clone();
+        param_data.repeats[0] = true;
+        let mut smoother = ParamSmoother::new(&param_data, SmoothingKind::Exponential, 0.1);
+
+        // From near the top of the range to near the bottom goes up and wraps around.
+        let mut values = [0.0; 8];
+        values[0] = 25.0;
+        smoother.reset(&values);
+        values[0] = -25.0;
+        let value = smoother.update(&values, 0.05)[0];
+        assert!(!(-25.0..=25.0).contains(&value), "{value}");
+        let value = smoother.update(&values, 10.0)[0];
+        assert!((value + 25.0).abs() < 1e-3, "{value}");
+    }
+
+    #[test]
+    fn broken_values_are_replaced() {
+        let mut smoother = smoother(SmoothingKind::CriticallyDamped);
+        smoother.reset(&[f32::NAN; 8]);
+        assert_eq!(smoother.update(&[3.0; 8], 0.01), [3.0; 8]);
+    }
+}
@@ -0,0 +1,428 @@
+use std::collections::HashMap;
+
+use glam::Vec3;
+use moc3_rs::puppet::ParamData;
+use thiserror::Error;
+
+use crate::params::{clamp_parameter, parameter_index};
+
+/// A single frame of face tracking data, in the shape ARKit and VMC senders provide it.
+#[derive(Clone, Debug, Default)]
+pub struct TrackingFrame {
+    /// Blendshape weights keyed by name (such as `jawOpen` or `eyeBlinkLeft`), usually in `[0, 1]`.
+    pub blendshapes: HashMap<String, f32>,
+    /// Head rotation in degrees, as (pitch, yaw, roll).
+    pub head_rotation: Vec3,
+    /// Head position, in whatever units the tracker uses.
+    pub head_position: Vec3,
+}
+
+#[derive(Clone, Debug, PartialEq, Eq, Hash)]
+pub enum TrackingSource {
+    Blendshape(String),
+    HeadPitch,
+    HeadYaw,
+    HeadRoll,
+    HeadX,
+    HeadY,
+    HeadZ,
+}
+
+impl TrackingSource {
+    fn read(&self, frame: &TrackingFrame) -> Option<f32> {
+        match self {
+            TrackingSource::Blendshape(name) => frame.blendshapes.get(name).copied(),
+            TrackingSource::HeadPitch => Some(frame.head_rotation.x),
+            TrackingSource::HeadYaw => Some(frame.head_rotation.y),
+            TrackingSource::HeadRoll => Some(frame.head_rotation.z),
+            TrackingSource::HeadX => Some(frame.head_position.x),
+            TrackingSource::HeadY => Some(frame.head_position.y),
+            TrackingSource::HeadZ => Some(frame.head_position.z),
+        }
+    }
+}
+
+/// The response curve applied to the normalized input, which is in `[0, 1]`.
+#[derive(Clone, Copy, Debug, Default, PartialEq)]
+pub enum TrackingCurve {
+    #[default]
+    Linear,
+    /// Raises the input to the given power - above 1 makes small movements less
+    /// sensitive, below 1 makes them more sensitive.
+    Power(f32),
+    /// Eases in and out of the range ends.
+    Smoothstep,
+}
+
+impl TrackingCurve {
+    fn apply(self, t: f32) -> f32 {
+        match self {
+            TrackingCurve::Linear => t,
+            TrackingCurve::Power(exponent) => t.powf(exponent),
+            TrackingCurve::Smoothstep => t * t * (3.0 - 2.0 * t),
+        }
+    }
+}
+
+/// Maps one tracking value onto one puppet parameter.
+///
+/// The (calibrated) input is normalized from `input_range` into `[0, 1]`, passed through
+/// the curve, and then scaled into `output_range`. Ranges may be reversed to invert the
+/// mapping, such as turning `eyeBlinkLeft` into `ParamEyeLOpen`. Several mappings may
+/// target the same parameter, in which case their weighted results are summed.
+#[derive(Clone, Debug)]
+pub struct TrackingMapping {
+    pub source: TrackingSource,
+    pub parameter_index: usize,
+    pub input_range: (f32, f32),
+    pub output_range: (f32, f32),
+    pub curve: TrackingCurve,
+    pub weight: f32,
+}
+
+impl TrackingMapping {
+    pub fn new(source: TrackingSource, parameter_index: usize) -> Self {
+        Self {
+            source,
+            parameter_index,
+            input_range: (0.0, 1.0),
+            output_range: (0.0, 1.0),
+            curve: TrackingCurve::Linear,
+            weight: 1.0,
+        }
+    }
+
+    pub fn with_input_range(self, min: f32, max: f32) -> Self {
+        Self {
+            input_range: (min, max),
+            ..self
+        }
+    }
+
+    pub fn with_output_range(self, min: f32, max: f32) -> Self {
+        Self {
+            output_range: (min, max),
+            ..self
+        }
+    }
+
+    pub fn with_curve(self, curve: TrackingCurve) -> Self {
+        Self { curve, ..self }
+    }
+
+    pub fn with_weight(self, weight: f32) -> Self {
+        Self { weight, ..self }
+    }
+
+    fn map(&self, value: f32) -> f32 {
+        let (in_min, in_max) = self.input_range;
+        let t = if in_max != in_min {
+            ((value - in_min) / (in_max - in_min)).clamp(0.0, 1.0)
+        } else {
+            0.0
+        };
+
+        let (out_min, out_max) = self.output_range;
+        out_min + (out_max - out_min) * self.curve.apply(t)
+    }
+}
+
+/// A [TrackingMapping] targeting a parameter the puppet doesn't have.
+#[derive(Error, Debug, Clone, Copy, PartialEq, Eq)]
+#[error("mapping targets parameter {parameter_index}, but there are only {count}")]
+pub struct InvalidTrackingParameter {
+    pub parameter_index: usize,
+    pub count: usize,
+}
+
+/// Converts face tracking frames into puppet parameters.
+#[derive(Clone, Debug, Default)]
+pub struct FaceTrackingMapper {
+    // Sorted by parameter, so every parameter's mappings are next to each other.
+    mappings: Vec<TrackingMapping>,
+    neutral: HashMap<TrackingSource, f32>,
+}
+
+impl FaceTrackingMapper {
+    pub fn new(
+        mappings: Vec<TrackingMapping>,
+        param_data: &ParamData,
+    ) -> Result<Self, InvalidTrackingParameter> {
+        let mut mapper = Self::default();
+        for mapping in mappings {
+            mapper.add_mapping(mapping, param_data)?;
+        }
+        Ok(mapper)
+    }
+
+    pub fn mappings(&self) -> &[TrackingMapping] {
+        &self.mappings
+    }
+
+    /// Adds a mapping after any others for the same parameter, as long as the puppet has
+    /// that parameter.
+    pub fn add_mapping(
+        &mut self,
+        mapping: TrackingMapping,
+        param_data: &ParamData,
+    ) -> Result<(), InvalidTrackingParameter> {
+        let count = param_data.count as usize;
+        if mapping.parameter_index >= count {
+            return Err(InvalidTrackingParameter {
+                parameter_index: mapping.parameter_index,
+                count,
+            });
+        }
+
+        let at = self
+            .mappings
+            .partition_point(|x| x.parameter_index <= mapping.parameter_index);
+        self.mappings.insert(at, mapping);
+        Ok(())
+    }
+
+    /// Removes every mapping the closure returns false for.
+    pub fn retain_mappings(&mut self, keep: impl FnMut(&TrackingMapping) -> bool) {
+        self.mappings.retain(keep);
+    }
+
+    /// Maps ARKit's blendshapes and head pose onto the standard Cubism parameter IDs,
+    /// skipping any parameters the puppet doesn't have.
+    pub fn with_default_mappings(param_data: &ParamData) -> Self {
+        // Head rotation is in degrees on both ends: (source, parameter, output extent).
+        let head = [
+            (TrackingSource::HeadYaw, "ParamAngleX", 30.0),
+            (TrackingSource::HeadPitch, "ParamAngleY", 30.0),
+            (TrackingSource::HeadRoll, "ParamAngleZ", 30.0),
+            (TrackingSource::HeadYaw, "ParamBodyAngleX", 10.0),
+        ];
+
+        // Blendshapes are in [0, 1]: (blendshape, parameter, output range, weight).
+        let blendshapes = [
+            ("eyeBlinkLeft", "ParamEyeLOpen", 1.0, 0.0, 1.0),
+            ("eyeBlinkRight", "ParamEyeROpen", 1.0, 0.0, 1.0),
+            ("eyeLookOutLeft", "ParamEyeBallX", 0.0, -1.0, 0.5),
+            ("eyeLookInLeft", "ParamEyeBallX", 0.0, 1.0, 0.5),
+            ("eyeLookOutRight", "ParamEyeBallX", 0.0, 1.0, 0.5),
+            ("eyeLookInRight", "ParamEyeBallX", 0.0, -1.0, 0.5),
+            ("eyeLookUpLeft", "ParamEyeBallY", 0.0, 1.0, 0.5),
+            ("eyeLookUpRight", "ParamEyeBallY", 0.0, 1.0, 0.5),
+            ("eyeLookDownLeft", "ParamEyeBallY", 0.0, -1.0, 0.5),
+            ("eyeLookDownRight", "ParamEyeBallY", 0.0, -1.0, 0.5),
+            ("browInnerUp", "ParamBrowLY", 0.0, 1.0, 1.0),
+            ("browInnerUp", "ParamBrowRY", 0.0, 1.0, 1.0),
+            ("jawOpen", "ParamMouthOpenY", 0.0, 1.0, 1.0),
+            ("mouthSmileLeft", "ParamMouthForm", 0.0, 1.0, 0.5),
+            ("mouthSmileRight", "ParamMouthForm", 0.0, 1.0, 0.5),
+            ("mouthFrownLeft", "ParamMouthForm", 0.0, -1.0, 0.5),
+            ("mouthFrownRight", "ParamMouthForm", 0.0, -1.0, 0.5),
+            ("cheekPuff", "ParamCheek", 0.0, 1.0, 1.0),
+        ];
+
+        let mut mappings = Vec::new();
+        for (source, id, extent) in head {
+            if let Some(index) = parameter_index(param_data, id) {
+                mappings.push(
+                    TrackingMapping::new(source, index)
+                        .with_input_range(-30.0, 30.0)
+                        .with_output_range(-extent, extent),
+                );
+            }
+        }
+        for (name, id, out_min, out_max, weight) in blendshapes {
+            if let Some(index) = parameter_index(param_data, id) {
+                mappings.push(
+                    TrackingMapping::new(TrackingSource::Blendshape(name.to_string()), index)
+                        .with_output_range(out_min, out_max)
+                        .with_weight(weight),
+                );
+            }
+        }
+
+        Self::new(mappings, param_data).expect("parameters were looked up on the puppet")
+    }
+
+    /// Records the given frame as the user's resting pose. Afterwards, every input is
+    /// measured relative to it.
+    pub fn calibrate(&mut self, frame: &TrackingFrame) {
+        self.neutral.clear();
+        for mapping in &self.mappings {
+            if let Some(value) = mapping.source.read(frame) {
+                self.neutral.insert(mapping.source.clone(), value);
+            }
+        }
+    }
+
+    pub fn clear_calibration(&mut self) {
+        self.neutral.clear();
+    }
+
+    /// Writes the mapped values into `params`. Parameters with no mapping (or whose
+    /// sources are missing from the frame) are left untouched.
+    pub fn apply(&self, frame: &TrackingFrame, param_data: &ParamData, params: &mut [f32]) {
+        for group in self
+            .mappings
+            .chunk_by(|a, b| a.parameter_index == b.parameter_index)
+        {
+            let mut total = None;
+            for mapping in group {
+                let Some(raw) = mapping.source.read(frame) else {
+                    continue;
+                };
+                if !raw.is_finite() {
+                    continue;
+                }
+
+                let value = raw - self.neutral.get(&mapping.source).copied().unwrap_or(0.0);
+                *total.get_or_insert(0.0) += mapping.map(value) * mapping.weight;
+            }
+
+            if let Some(total) = total {
+                let index = group[0].parameter_index;
+                params[index] = clamp_parameter(param_data, index, total);
+            }
+        }
+    }
+
+    /// Produces a full parameter slice for [moc3_rs::puppet::Puppet::update], starting
+    /// from the parameter defaults.
+    pub fn map(&self, frame: &TrackingFrame, param_data: &ParamData) -> Vec<f32> {
+        let mut params = param_data.defaults.clone();
+        self.apply(frame, param_data, &mut params);
+        params
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::params::test_puppet;
+
+    fn frame(blendshapes: &[(&str, f32)], head_rotation: Vec3) -> TrackingFrame {
+        TrackingFrame {
+            blendshapes: blendshapes
+                .iter()
+                .map(|(name, value)| (name.to_string(), *value))
+                .collect(),
+            head_rotation,
+            head_position: Vec3::ZERO,
+        }
+    }
+
+    fn blendshape(name: &str) -> TrackingSource {
+        TrackingSource::Blendshape(name.to_owned())
+    }
+
+    #[test]
+    fn mappings_normalize_curve_and_scale() {
+        let mapping = TrackingMapping::new(TrackingSource::HeadYaw, 0)
+            .with_input_range(-10.0, 10.0)
+            .with_output_range(20.0, -20.0);
+        assert_eq!(mapping.map(5.0), -10.0);
+        assert_eq!(mapping.map(50.0), -20.0);
+
+        let eased = mapping.clone().with_curve(TrackingCurve::Smoothstep);
+        assert_eq!(eased.map(0.0), 0.0);
+        assert!(eased.map(-5.0) > mapping.map(-5.0));
+        let power = mapping.with_curve(TrackingCurve::Power(2.0));
+        assert_eq!(power.map(0.0), 10.0);
+    }
+
+    #[test]
+    fn mappings_on_one_parameter_add_up() {
+        let puppet = test_puppet();
+        let param_data = puppet.param_data();
+        let mapper = FaceTrackingMapper::new(
+            vec![
+                TrackingMapping::new(blendshape("mouthSmileLeft"), 1).with_weight(0.5),
+                TrackingMapping::new(TrackingSource::HeadRoll, 0).with_output_range(0.0, 40.0),
+                TrackingMapping::new(blendshape("mouthSmileRight"), 1).with_weight(0.5),
+                TrackingMapping::new(blendshape("jawOpen"), 2),
+            ],
+            param_data,
+        )
+        .unwrap();
+        let indices: Vec<_> = mapper
+            .mappings()
+            .iter()
+            .map(|x| x.parameter_index)
+            .collect();
+        assert_eq!(indices, [0, 1, 1, 2]);
+
+        let mut params = vec![7.0; param_data.count as usize];
+        let frame = frame(
+            &[("mouthSmileLeft", 1.0), ("mouthSmileRight", 0.5)],
+            Vec3::new(0.0, 0.0, 1.0),
+        );
+        mapper.apply(&frame, param_data, &mut params);
+        // Clamped to the top of the range, summed, and left alone for lack of a source.
+        assert_eq!(params[..3], [30.0, 0.75, 7.0]);
+    }
+
+    #[test]
+    fn broken_values_are_skipped() {
+        let puppet = test_puppet();
+        let param_data = puppet.param_data();
+        let mapper = FaceTrackingMapper::new(
+            vec![TrackingMapping::new(blendshape("jawOpen"), 0)],
+            param_data,
+        )
+        .unwrap();
+        let params = mapper.map(&frame(&[("jawOpen", f32::NAN)], Vec3::ZERO), param_data);
+        assert_eq!(params, param_data.defaults);
+    }
+
+    #[test]
+    fn calibration_measures_from_the_resting_pose() {
+        let puppet = test_puppet();
+        let param_data = puppet.param_data();
+        let mut mapper = FaceTrackingMapper::new(
+            vec![TrackingMapping::new(TrackingSource::HeadPitch, 0)
+                .with_input_range(-30.0, 30.0)
+                .with_output_range(-30.0, 30.0)],
+            param_data,
+        )
+        .unwrap();
+
+        let resting = frame(&[], Vec3::new(5.0, 0.0, 0.0));
+        mapper.calibrate(&resting);
+        assert_eq!(mapper.map(&resting, param_data)[0], 0.0);
+        let looking_up = frame(&[], Vec3::new(15.0, 0.0, 0.0));
+        assert_eq!(mapper.map(&looking_up, param_data)[0], 10.0);
+
+        mapper.clear_calibration();
+        assert_eq!(mapper.map(&looking_up, param_data)[0], 15.0);
+    }
+
+    #[test]
+    fn unknown_parameters_are_rejected() {
+        let puppet = test_puppet();
+        let param_data = puppet.param_data();
+        let mut mapper = FaceTrackingMapper::default();
+        assert_eq!(
+            mapper.add_mapping(TrackingMapping::new(TrackingSource::HeadX, 8), param_data),
+            Err(InvalidTrackingParameter {
+                parameter_index: 8,
+                count: 8,
+            })
+        );
+        assert!(mapper.mappings().is_empty());
+    }
+
+    #[test]
+    fn default_mappings_skip_missing_parameters() {
+        let mut param_data = test_puppet().param_data().clone();
+        assert!(FaceTrackingMapper::with_default_mappings(&param_data)
+            .mappings()
+            .is_empty());
+
+        param_data.ids[3] = "ParamEyeLOpen".to_owned();
+        let mapper = FaceTrackingMapper::with_default_mappings(&param_data);
+        assert_eq!(mapper.mappings().len(), 1);
+        // Blinking closes the eye.
+        let params = mapper.map(&frame(&[("eyeBlinkLeft", 1.0)], Vec3::ZERO), &param_data);
+        assert_eq!(params[3], 0.0);
+        let params = mapper.map(&frame(&[("eyeBlinkLeft", 0.25)], Vec3::ZERO), &param_data);
+        assert_eq!(params[3], 0.75);
+    }
+}
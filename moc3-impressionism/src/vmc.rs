@@ -0,0 +1,337 @@
+use std::{
+    collections::HashMap,
+    io,
+    net::{ToSocketAddrs, UdpSocket},
+};
+
+use glam::{EulerRot, Quat, Vec3};
+
+use crate::tracking::TrackingFrame;
+
+// VMC is a thin layer over OSC, and only uses a tiny bit of it, so rather than pulling
+// in a full OSC implementation we decode just what's needed here.
+//
+// https://protocol.vmc.info/specification
+
+#[derive(Clone, Debug, PartialEq)]
+pub enum OscArg {
+    Int(i32),
+    Float(f32),
+    String(String),
+    Bool(bool),
+}
+
+impl OscArg {
+    fn as_f32(&self) -> Option<f32> {
+        match self {
+            OscArg::Float(x) => Some(*x),
+            OscArg::Int(x) => Some(*x as f32),
+            _ => None,
+        }
+    }
+
+    fn as_str(&self) -> Option<&str> {
+        match self {
+            OscArg::String(x) => Some(x),
+            _ => None,
+        }
+    }
+}
+
+// Reads a null-terminated string padded out to a multiple of four bytes.
+fn read_osc_string(data: &[u8], pos: &mut usize) -> Option<String> {
+    let rest = data.get(*pos..)?;
+    let len = rest.iter().position(|x| *x == 0)?;
+    let ret = std::str::from_utf8(&rest[..len]).ok()?.to_string();
+    *pos += (len + 4) & !3;
+
+    Some(ret)
+}
+
+fn read_u32(data: &[u8], pos: &mut usize) -> Option<u32> {
+    let bytes = data.get(*pos..*pos + 4)?;
+    *pos += 4;
+
+    Some(u32::from_be_bytes(bytes.try_into().unwrap()))
+}
+
+// Nobody nests bundles more than a level or two deep, so anything past this is junk or
+// someone trying to blow the stack.
+const MAX_BUNDLE_DEPTH: usize = 8;
+
+/// Decodes an OSC packet (a message or a bundle), calling `handler` for every message.
+/// Returns false if the packet was malformed, though messages before the problem will
+/// still have been handled.
+pub fn decode_osc_packet(data: &[u8], handler: &mut impl FnMut(&str, &[OscArg])) -> bool {
+    decode_osc_packet_at(data, handler, 0)
+}
+
+fn decode_osc_packet_at(
+    data: &[u8],
+    handler: &mut impl FnMut(&str, &[OscArg]),
+    depth: usize,
+) -> bool {
+    if data.starts_with(b"#bundle\0") {
+        if depth >= MAX_BUNDLE_DEPTH {
+            return false;
+        }
+
+        // Skip the tag and the time tag, we always apply messages immediately.
+        let mut pos = 16;
+        while pos < data.len() {
+            let Some(size) = read_u32(data, &mut pos) else {
+                return false;
+            };
+            let Some(end) = pos.checked_add(size as usize) else {
+                return false;
+            };
+            let Some(element) = data.get(pos..end) else {
+                return false;
+            };
+            if !decode_osc_packet_at(element, handler, depth + 1) {
+                return false;
+            }
+            pos = end;
+        }
+
+        return true;
+    }
+
+    let mut pos = 0;
+    let Some(address) = read_osc_string(data, &mut pos) else {
+        return false;
+    };
+    let Some(tags) = read_osc_string(data, &mut pos) else {
+        return false;
+    };
+
+    let mut args = Vec::new();
+    for tag in tags.chars().skip_while(|x| *x == ',') {
+        let arg = match tag {
+            'i' => read_u32(data, &mut pos).map(|x| OscArg::Int(x as i32)),
+            'f' => read_u32(data, &mut pos).map(|x| OscArg::Float(f32::from_bits(x))),
+            's' => read_osc_string(data, &mut pos).map(OscArg::String),
+            'T' => Some(OscArg::Bool(true)),
+            'F' => Some(OscArg::Bool(false)),
+            _ => None,
+        };
+
+        match arg {
+            Some(arg) => args.push(arg),
+            None => return false,
+        }
+    }
+
+    handler(&address, &args);
+    true
+}
+
+/// A bone transform as sent by the performer, in Unity's coordinate space.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct VmcBone {
+    pub position: Vec3,
+    pub rotation: Quat,
+}
+
+/// Listens for VMC protocol messages over UDP and assembles them into [TrackingFrame]s
+/// for [crate::tracking::FaceTrackingMapper].
+///
+/// Blendshape values keep their VMC names, which are VRM preset names (`Blink_L`, `A`...)
+/// or ARKit names when the sender supports perfect sync. The head bone's rotation is
+/// converted to degrees and used as the frame's head rotation.
+pub struct VmcReceiver {
+    socket: UdpSocket,
+    buffer: Vec<u8>,
+
+    pending_blendshapes: HashMap<String, f32>,
+    bones: HashMap<String, VmcBone>,
+    frame: TrackingFrame,
+}
+
+impl VmcReceiver {
+    /// Binds to the given address (VMC performers conventionally send to port 39539).
+    pub fn bind(address: impl ToSocketAddrs) -> io::Result<Self> {
+        let socket = UdpSocket::bind(address)?;
+        socket.set_nonblocking(true)?;
+
+        Ok(Self {
+            socket,
+            buffer: vec![0; 65536],
+            pending_blendshapes: HashMap::new(),
+            bones: HashMap::new(),
+            frame: TrackingFrame::default(),
+        })
+    }
+
+    /// The most recently completed frame.
+    pub fn frame(&self) -> &TrackingFrame {
+        &self.frame
+    }
+
+    pub fn bones(&self) -> &HashMap<String, VmcBone> {
+        &self.bones
+    }
+
+    /// Processes every packet waiting on the socket without blocking, returning whether
+    /// a new frame was completed.
+    pub fn poll(&mut self) -> io::Result<bool> {
+        let mut updated = false;
+
+        loop {
+            let len = match self.socket.recv(&mut self.buffer) {
+                Ok(len) => len,
+                Err(e) if e.kind() == io::ErrorKind::WouldBlock => break,
+                Err(e) => return Err(e),
+            };
+
+            let pending = &mut self.pending_blendshapes;
+            let bones = &mut self.bones;
+            let frame = &mut self.frame;
+
+            // Malformed packets are simply dropped, there's nothing useful to do with them.
+            decode_osc_packet(&self.buffer[..len], &mut |address, args| {
+                updated |= handle_message(address, args, pending, bones, frame);
+            });
+        }
+
+        Ok(updated)
+    }
+}
+
+fn handle_message(
+    address: &str,
+    args: &[OscArg],
+    pending_blendshapes: &mut HashMap<String, f32>,
+    bones: &mut HashMap<String, VmcBone>,
+    frame: &mut TrackingFrame,
+) -> bool {
+    match address {
+        "/VMC/Ext/Blend/Val" => {
+            if let (Some(name), Some(value)) = (
+                args.first().and_then(OscArg::as_str),
+                args.get(1).and_then(OscArg::as_f32),
+            ) {
+                pending_blendshapes.insert(name.to_string(), value);
+            }
+            false
+        }
+        // Blendshapes arrive one by one, and are only meant to take effect together.
+        "/VMC/Ext/Blend/Apply" => {
+            frame.blendshapes.extend(pending_blendshapes.drain());
+            true
+        }
+        "/VMC/Ext/Bone/Pos" => {
+            let name = args.first().and_then(OscArg::as_str);
+            let values: Option<Vec<f32>> = args
+                .get(1..8)
+                .and_then(|x| x.iter().map(OscArg::as_f32).collect());
+
+            if let (Some(name), Some(values)) = (name, values) {
+                let bone = VmcBone {
+                    position: Vec3::new(values[0], values[1], values[2]),
+                    rotation: Quat::from_xyzw(values[3], values[4], values[5], values[6]),
+                };
+                if name == "Head" {
+                    let (yaw, pitch, roll) = bone.rotation.to_euler(EulerRot::YXZ);
+                    frame.head_rotation =
+                        Vec3::new(pitch.to_degrees(), yaw.to_degrees(), roll.to_degrees());
+                    frame.head_position = bone.position;
+                }
+                bones.insert(name.to_string(), bone);
+            }
+            false
+        }
+        _ => false,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn osc_string(out: &mut Vec<u8>, value: &str) {
+        out.extend_from_slice(value.as_bytes());
+        out.resize((out.len() + 4) & !3, 0);
+    }
+
+    fn message(address: &str, value: f32) -> Vec<u8> {
+        let mut out = Vec::new();
+        osc_string(&mut out, address);
+        osc_string(&mut out, ",sf");
+        osc_string(&mut out, "Blink_L");
+        out.extend_from_slice(&value.to_bits().to_be_bytes());
+        out
+    }
+
+    fn bundle(elements: &[Vec<u8>]) -> Vec<u8> {
+        let mut out = b"#bundle\0".to_vec();
+        out.extend_from_slice(&[0, 0, 0, 0, 0, 0, 0, 1]);
+        for element in elements {
+            out.extend_from_slice(&(element.len() as u32).to_be_bytes());
+            out.extend_from_slice(element);
+        }
+        out
+    }
+
+    fn decode(data: &[u8]) -> (bool, Vec<(String, Vec<OscArg>)>) {
+        let mut messages = Vec::new();
+        let ok = decode_osc_packet(data, &mut |address, args| {
+            messages.push((address.to_string(), args.to_vec()))
+        });
+        (ok, messages)
+    }
+
+    #[test]
+    fn plain_message() {
+        let (ok, messages) = decode(&message("/VMC/Ext/Blend/Val", 0.5));
+        assert!(ok);
+        assert_eq!(
+            messages,
+            [(
+                "/VMC/Ext/Blend/Val".to_string(),
+                vec![OscArg::String("Blink_L".to_string()), OscArg::Float(0.5)]
+            )]
+        );
+    }
+
+    #[test]
+    fn nested_bundles() {
+        let inner = bundle(&[message("/b", 2.0), message("/c", 3.0)]);
+        let (ok, messages) = decode(&bundle(&[message("/a", 1.0), inner]));
+        assert!(ok);
+        let addresses: Vec<_> = messages.iter().map(|x| x.0.as_str()).collect();
+        assert_eq!(addresses, ["/a", "/b", "/c"]);
+
+        // Too deep to be anything real.
+        let mut deep = message("/deep", 1.0);
+        for _ in 0..=MAX_BUNDLE_DEPTH {
+            deep = bundle(&[deep]);
+        }
+        let (ok, messages) = decode(&deep);
+        assert!(!ok);
+        assert!(messages.is_empty());
+    }
+
+    #[test]
+    fn truncated_message() {
+        let full = message("/a", 1.0);
+        // Cut into the float, and then into the string argument.
+        for len in [full.len() - 2, 14] {
+            let (ok, messages) = decode(&full[..len]);
+            assert!(!ok, "{len}");
+            assert!(messages.is_empty());
+        }
+    }
+
+    #[test]
+    fn oversized_bundle_element() {
+        let mut data = bundle(&[message("/a", 1.0)]);
+        data.extend_from_slice(&u32::MAX.to_be_bytes());
+        data.extend_from_slice(&message("/b", 2.0));
+
+        // The first message still gets through before the bad size is found.
+        let (ok, messages) = decode(&data);
+        assert!(!ok);
+        assert_eq!(messages.len(), 1);
+    }
+}
@@ -0,0 +1,257 @@
+//! Prints statistics about a .moc3 file without opening a window, or what changed between
+//! two versions of one.
+//!
+//! Usage: `moc3-inspect <model.moc3>` or `moc3-inspect --diff <old.moc3> <new.moc3>`
+
+use std::{collections::BTreeMap, io::Cursor, process::ExitCode};
+
+use binrw::BinReaderExt;
+use moc3_rs::{
+    data::{Moc3Data, ParameterType, Version},
+    puppet::{puppet_from_moc3, ApplicatorTarget, IdChanges, Puppet},
+};
+
+fn kib(bytes: usize) -> String {
+    format!("{:.1} KiB", bytes as f64 / 1024.0)
+}
+
+fn read_moc3(path: &str) -> Option<Moc3Data> {
+    let bytes = match std::fs::read(path) {
+        Ok(bytes) => bytes,
+        Err(e) => {
+            eprintln!("couldn't read {path}: {e}");
+            return None;
+        }
+    };
+
+    match Cursor::new(&bytes).read_le() {
+        Ok(read) => Some(read),
+        Err(e) => {
+            eprintln!("couldn't parse {path}: {e}");
+            None
+        }
+    }
+}
+
+fn build_puppet(path: &str, read: &Moc3Data) -> Option<Puppet> {
+    match puppet_from_moc3(read) {
+        Ok(puppet) => Some(puppet),
+        Err(e) => {
+            eprintln!("couldn't build {path}: {e}");
+            None
+        }
+    }
+}
+
+fn print_ids(name: &str, changes: &IdChanges) {
+    for id in &changes.added {
+        println!("  + {name} {id}");
+    }
+    for id in &changes.removed {
+        println!("  - {name} {id}");
+    }
+}
+
+fn diff(old_path: &str, new_path: &str) -> ExitCode {
+    let (Some(old), Some(new)) = (read_moc3(old_path), read_moc3(new_path)) else {
+        return ExitCode::FAILURE;
+    };
+    let (Some(old), Some(new)) = (build_puppet(old_path, &old), build_puppet(new_path, &new))
+    else {
+        return ExitCode::FAILURE;
+    };
+    let diff = old.diff(&new);
+
+    println!("{old_path} -> {new_path}");
+    if diff.is_empty() {
+        println!("  no differences");
+        return ExitCode::SUCCESS;
+    }
+
+    print_ids("parameter", &diff.parameters);
+    print_ids("art mesh", &diff.art_meshes);
+    print_ids("deformer", &diff.deformers);
+    print_ids("part", &diff.parts);
+    print_ids("glue", &diff.glues);
+    for change in &diff.parameter_changes {
+        let [min, default, max] = change.before;
+        let [new_min, new_default, new_max] = change.after;
+        println!(
+            "  ~ parameter {}: {min} .. {default} .. {max} -> {new_min} .. {new_default} .. {new_max}",
+            change.id
+        );
+    }
+    for change in &diff.keyform_counts {
+        println!(
+            "  ~ {:?} {}: {} -> {} keyforms",
+            change.target, change.id, change.before, change.after
+        );
+    }
+    for change in &diff.textures {
+        println!(
+            "  ~ art mesh {}: texture {} -> {}",
+            change.art_mesh, change.before, change.after
+        );
+    }
+
+    ExitCode::SUCCESS
+}
+
+fn main() -> ExitCode {
+    let args: Vec<String> = std::env::args().skip(1).collect();
+    let path = match &args[..] {
+        [flag, old, new] if flag == "--diff" => return diff(old, new),
+        [path] => path,
+        _ => {
+            eprintln!("usage: moc3-inspect <model.moc3>");
+            eprintln!("       moc3-inspect --diff <old.moc3> <new.moc3>");
+            return ExitCode::FAILURE;
+        }
+    };
+
+    let Some(read) = read_moc3(path) else {
+        return ExitCode::FAILURE;
+    };
+    let Some(puppet) = build_puppet(path, &read) else {
+        return ExitCode::FAILURE;
+    };
+
+    let counts = &read.table.count_info;
+    let canvas = &*read.table.canvas_info;
+    println!("{path}");
+    println!("  version: {:?}", read.header.version);
+    if !read.header.version.is_known() {
+        println!(
+            "  (newer than {:?}, so only what that version has was read)",
+            Version::LATEST
+        );
+    }
+    println!(
+        "  canvas: {} x {} ({} pixels per unit)",
+        canvas.canvas_width, canvas.canvas_height, canvas.pixels_per_unit
+    );
+
+    println!();
+    println!("counts");
+    for (name, count) in [
+        ("parts", counts.parts),
+        ("art meshes", counts.art_meshes),
+        ("warp deformers", counts.warp_deformers),
+        ("rotation deformers", counts.rotation_deformers),
+        ("glues", counts.glues),
+        ("parameters", counts.parameters),
+        ("draw order groups", counts.draw_order_groups),
+        ("keyform positions", counts.keyform_positions),
+    ] {
+        println!("  {name:<20} {count}");
+    }
+
+    let params = puppet.param_data();
+    println!();
+    println!("parameters");
+    for i in 0..params.count as usize {
+        let kind = match params.types[i] {
+            ParameterType::BlendShape => " (blend shape)",
+            _ => "",
+        };
+        let repeats = if params.repeats[i] { " (repeats)" } else { "" };
+        println!(
+            "  {:<32} {} .. {} .. {}{kind}{repeats}",
+            params.ids[i], params.mins[i], params.defaults[i], params.maxes[i]
+        );
+        if !params.keys[i].is_empty() {
+            println!("  {:<32} keys {:?}", "", params.keys[i]);
+        }
+    }
+
+    let mut textures = BTreeMap::new();
+    for texture in &puppet.art_mesh_textures {
+        *textures.entry(*texture).or_insert(0) += 1;
+    }
+    println!();
+    println!("textures");
+    for (texture, meshes) in textures {
+        println!("  texture {texture}: {meshes} art meshes");
+    }
+
+    let graph = puppet.dependency_graph();
+    let blend_shapes: Vec<_> = graph
+        .applicators
+        .iter()
+        .filter(|x| x.is_blend_shape)
+        .collect();
+    println!();
+    println!("blend shapes: {}", blend_shapes.len());
+    for applicator in blend_shapes {
+        let params: Vec<_> = applicator
+            .parameters
+            .iter()
+            .map(|x| graph.parameters[*x].as_str())
+            .collect();
+        println!(
+            "  {:?} {} <- {}",
+            applicator.target,
+            applicator.target_id,
+            params.join(", ")
+        );
+    }
+
+    let stats = puppet.stats();
+    println!();
+    println!("complexity");
+    for (name, value) in [
+        ("vertexes", stats.vertexes),
+        ("triangles", stats.triangles),
+        ("masked art meshes", stats.masked_art_meshes),
+        ("keyforms", stats.keyforms),
+        ("applicators", stats.applicators),
+        ("binding dimensions", stats.max_binding_dimensions),
+        ("estimated cost", stats.estimated_cost()),
+    ] {
+        println!("  {name:<20} {value}");
+    }
+
+    let report = puppet.clone().optimize_meshes();
+    println!();
+    println!("mesh optimization");
+    println!("  merged vertexes      {}", report.merged_vertexes);
+    println!("  removed triangles    {}", report.removed_triangles);
+    println!(
+        "  cache misses/tri     {:.3} -> {:.3}",
+        report.acmr_before, report.acmr_after
+    );
+
+    let memory = puppet.memory_report();
+    println!();
+    println!("memory (keyforms)");
+    println!(
+        "  shared positions     {}",
+        kib(memory.keyform_position_bytes)
+    );
+    println!("  shared keys          {}", kib(memory.binding_bytes));
+    for (name, target) in [
+        ("art meshes", ApplicatorTarget::ArtMesh),
+        ("warp deformers", ApplicatorTarget::WarpDeformer),
+        ("rotation deformers", ApplicatorTarget::RotationDeformer),
+        ("glues", ApplicatorTarget::Glue),
+        ("parts", ApplicatorTarget::Part),
+    ] {
+        let bytes: usize = memory
+            .applicators
+            .iter()
+            .filter(|x| x.target == target)
+            .map(|x| x.other_bytes)
+            .sum();
+        println!("  {name:<20} {}", kib(bytes));
+    }
+    println!("  total                {}", kib(memory.total_bytes()));
+
+    let compaction = puppet.clone().compact_keyforms(0.0);
+    println!(
+        "  compacted            {} ({} applicators)",
+        kib(compaction.bytes_after),
+        compaction.compacted_applicators
+    );
+
+    ExitCode::SUCCESS
+}
@@ -1,122 +1,279 @@
-use std::time::Instant;
+//! Shows a model's physics running against its own parameters, one setting at a time, for
+//! checking it against what Cubism does.
+//!
+//! ```text
+//! moc3-physicsview model.moc3 [model.physics3.json]
+//! ```
+//!
+//! The physics3.json defaults to the one named after the moc3, like Cubism exports them.
+
+use std::{
+    f32::consts::TAU,
+    path::{Path, PathBuf},
+    time::Instant,
+};
 
 use eframe::{
-    egui::{self, Sense},
+    egui::{self, Align2, FontId, Sense},
     epaint::{vec2, Color32, Pos2, Stroke, Vec2},
 };
-use moc3_impressionism::{Pendulum, PhysicsVertex, UpdateData};
+use moc3_impressionism::{data::Physics3Data, pendulum::Integrator, PhysicsController};
+use moc3_rs::{parse_puppet, puppet::ParamData};
 
-fn main() -> Result<(), eframe::Error> {
-    let options = eframe::NativeOptions {
-        viewport: egui::ViewportBuilder::default()
-            .with_inner_size([800.0, 800.0])
-            .with_resizable(false),
-        ..Default::default()
-    };
+// How often swaying inputs go back and forth, in seconds.
+const SWAY_PERIOD: f32 = 2.0;
 
-    let mut physics = Pendulum::new([
-        PhysicsVertex {
-            position: glam::Vec2::new(0.0, 0.0),
-            mobility: 1.0,
-            delay: 1.0,
-            acceleration: 1.0,
-            radius: 0.0,
-        },
-        PhysicsVertex {
-            position: glam::Vec2::new(0.0, 3.0),
-            mobility: 0.95,
-            delay: 0.8,
-            acceleration: 1.5,
-            radius: 3.0,
-        },
-        PhysicsVertex {
-            position: glam::Vec2::new(0.0, 6.0),
-            mobility: 0.95,
-            delay: 0.8,
-            acceleration: 1.5,
-            radius: 3.0,
-        },
-        PhysicsVertex {
-            position: glam::Vec2::new(0.0, 9.0),
-            mobility: 0.95,
-            delay: 0.8,
-            acceleration: 1.5,
-            radius: 3.0,
-        },
-    ]);
-
-    let mut last = None;
-    let mut translation = glam::Vec2::ZERO;
-    let mut rotation = 0.0;
-    eframe::run_simple_native("My egui App", options, move |ctx, _frame| {
-        egui::CentralPanel::default().show(ctx, |ui| {
-            if ui.input(|i| i.key_pressed(egui::Key::A)) {
-                translation.x += 2.0;
-            }
-            if ui.input(|i| i.key_pressed(egui::Key::D)) {
-                translation.x -= 2.0;
+struct View {
+    param_data: ParamData,
+    physics: PhysicsController,
+    // The name of every setting, from the physics dictionary if it has one.
+    names: Vec<String>,
+    // Every parameter any setting reads from, which get sliders.
+    inputs: Vec<usize>,
+
+    params: Vec<f32>,
+    posed: Vec<f32>,
+    selected: usize,
+    integrator: Integrator,
+    sway: bool,
+    time: f32,
+    last: Option<Instant>,
+}
+
+impl View {
+    fn load(moc3_path: &Path, physics_path: &Path) -> Result<Self, String> {
+        let bytes = std::fs::read(moc3_path)
+            .map_err(|e| format!("couldn't read {}: {e}", moc3_path.display()))?;
+        let puppet = parse_puppet(&bytes)
+            .map_err(|e| format!("couldn't parse {}: {e}", moc3_path.display()))?;
+        let json = std::fs::read(physics_path)
+            .map_err(|e| format!("couldn't read {}: {e}", physics_path.display()))?;
+        let data: Physics3Data = serde_json::from_slice(&json)
+            .map_err(|e| format!("couldn't parse {}: {e}", physics_path.display()))?;
+
+        let param_data = puppet.param_data().clone();
+        for unknown in data.validate(&param_data) {
+            eprintln!(
+                "physics setting {} refers to unknown parameter {}",
+                unknown.setting, unknown.id
+            );
+        }
+        let physics = PhysicsController::new(&data, &param_data);
+
+        let names = physics
+            .settings()
+            .iter()
+            .map(|setting| {
+                data.meta
+                    .physics_dictionary
+                    .iter()
+                    .find(|x| x.id == setting.id)
+                    .map_or_else(|| setting.id.clone(), |x| x.name.clone())
+            })
+            .collect();
+        let mut inputs: Vec<usize> = physics
+            .settings()
+            .iter()
+            .flat_map(|x| x.inputs().iter().map(|x| x.parameter_index))
+            .collect();
+        inputs.sort_unstable();
+        inputs.dedup();
+
+        Ok(Self {
+            params: param_data.defaults.clone(),
+            posed: param_data.defaults.clone(),
+            param_data,
+            physics,
+            names,
+            inputs,
+
+            selected: 0,
+            integrator: Integrator::default(),
+            sway: false,
+            time: 0.0,
+            last: None,
+        })
+    }
+
+    fn update(&mut self) {
+        let now = Instant::now();
+        let delta_seconds = self.last.map_or(0.0, |x| (now - x).as_secs_f32());
+        self.last = Some(now);
+        self.time += delta_seconds;
+
+        // Swaying moves the selected setting's inputs from one end of their range to the
+        // other, which is usually the quickest way to see a setting misbehave.
+        if self.sway {
+            let sway = (self.time / SWAY_PERIOD * TAU).sin();
+            for input in self.physics.settings()[self.selected].inputs() {
+                let index = input.parameter_index;
+                let (min, max) = (self.param_data.mins[index], self.param_data.maxes[index]);
+                self.params[index] = (min + max) / 2.0 + (max - min) / 2.0 * sway;
             }
+        }
+
+        self.posed.copy_from_slice(&self.params);
+        self.physics
+            .update(delta_seconds, &self.param_data, &mut self.posed);
+    }
 
-            match ui.input(|i| (i.key_pressed(egui::Key::Q), i.modifiers.shift)) {
-                (true, true) => rotation += std::f32::consts::FRAC_PI_4,
-                (true, false) => rotation += std::f32::consts::FRAC_PI_8,
-                _ => {}
+    fn side_panel(&mut self, ui: &mut egui::Ui) {
+        egui::ComboBox::from_label("Setting")
+            .selected_text(&self.names[self.selected])
+            .show_ui(ui, |ui| {
+                for (i, name) in self.names.iter().enumerate() {
+                    ui.selectable_value(&mut self.selected, i, name);
+                }
+            });
+        let integrator = self.integrator;
+        egui::ComboBox::from_label("Integrator")
+            .selected_text(format!("{:?}", self.integrator))
+            .show_ui(ui, |ui| {
+                for x in [
+                    Integrator::Heuristic,
+                    Integrator::Verlet,
+                    Integrator::Cubism,
+                ] {
+                    ui.selectable_value(&mut self.integrator, x, format!("{x:?}"));
+                }
+            });
+        if self.integrator != integrator {
+            self.physics.set_integrator(self.integrator);
+        }
+        ui.checkbox(&mut self.sway, "Sway inputs");
+
+        egui::ScrollArea::vertical().show(ui, |ui| {
+            ui.heading("Inputs");
+            let setting = &self.physics.settings()[self.selected];
+            for &index in &self.inputs {
+                let range = self.param_data.mins[index]..=self.param_data.maxes[index];
+                let used = setting.inputs().iter().find(|x| x.parameter_index == index);
+                let label = match used {
+                    Some(input) => format!(
+                        "{} ({:?}, {:.0}%{})",
+                        self.param_data.ids[index],
+                        input.ty,
+                        input.weight * 100.0,
+                        if input.reflect { ", reflected" } else { "" }
+                    ),
+                    None => self.param_data.ids[index].clone(),
+                };
+                ui.add(egui::Slider::new(&mut self.params[index], range).text(label));
             }
 
-            match ui.input(|i| (i.key_pressed(egui::Key::E), i.modifiers.shift)) {
-                (true, true) => rotation -= std::f32::consts::FRAC_PI_4,
-                (true, false) => rotation -= std::f32::consts::FRAC_PI_8,
-                _ => {}
+            ui.heading("Outputs");
+            for output in setting.outputs() {
+                let index = output.parameter_index;
+                let (min, max) = (self.param_data.mins[index], self.param_data.maxes[index]);
+                let value = self.posed[index];
+                ui.add(
+                    egui::ProgressBar::new((value - min) / (max - min))
+                        .text(format!("{} = {value:.2}", self.param_data.ids[index])),
+                );
             }
 
-            if last.is_none() {
-                last = Some(Instant::now());
+            // Tweaking the vertexes takes effect immediately, no restart needed.
+            ui.heading("Vertexes");
+            let setting = &mut self.physics.settings_mut()[self.selected];
+            for (i, vertex) in setting
+                .pendulum
+                .vertexes_mut()
+                .iter_mut()
+                .enumerate()
+                .skip(1)
+            {
+                ui.label(format!("Vertex {i}"));
+                ui.add(egui::Slider::new(&mut vertex.mobility, 0.0..=1.0).text("Mobility"));
+                ui.add(egui::Slider::new(&mut vertex.delay, 0.0..=2.0).text("Delay"));
+                ui.add(egui::Slider::new(&mut vertex.acceleration, 0.0..=5.0).text("Acceleration"));
+                ui.add(egui::Slider::new(&mut vertex.radius, 0.0..=20.0).text("Radius"));
+                ui.separator();
             }
-            let now = Instant::now();
-            physics.update_points(
-                (now - last.unwrap()).as_secs_f32(),
-                UpdateData {
-                    translation,
-                    rotation,
-                },
-            );
-            last = Some(now);
+        });
+    }
 
-            let origin = Pos2::new(400.0, 400.0);
-            let (_response, painter) = ui.allocate_painter(Vec2::splat(800.0), Sense::hover());
+    fn draw_pendulum(&self, ui: &mut egui::Ui) {
+        let (response, painter) = ui.allocate_painter(ui.available_size(), Sense::hover());
+        let rect = response.rect;
+        let setting = &self.physics.settings()[self.selected];
+        let alpha = self.physics.timestep().map_or(1.0, |x| x.alpha());
+        let points: Vec<Vec2> = setting
+            .pendulum
+            .points
+            .iter()
+            .map(|x| {
+                let position = x.interpolated_position(alpha);
+                vec2(position.x, position.y)
+            })
+            .collect();
 
-            let mut last_point = vec2(
-                physics.points[0].cur_position.x,
-                physics.points[0].cur_position.y,
-            );
-            let scale_factor = 20.0;
-            painter.circle(
-                origin + last_point * scale_factor,
-                20.0,
-                Color32::TRANSPARENT,
-                Stroke::new(2.0, Color32::RED),
+        // The chain fits in the view however it swings, with its root near the top.
+        let length: f32 = setting.pendulum.vertexes().iter().map(|x| x.radius).sum();
+        let scale = rect.height() * 0.7 / length.max(1.0);
+        let origin = Pos2::new(rect.center().x, rect.top() + rect.height() * 0.15);
+        let to_screen = |x: Vec2| origin + (x - points[0]) * scale;
+
+        let stroke = Stroke::new(2.0, Color32::RED);
+        for pair in points.windows(2) {
+            painter.line_segment([to_screen(pair[0]), to_screen(pair[1])], stroke);
+        }
+        for point in &points {
+            painter.circle_stroke(to_screen(*point), 8.0, stroke);
+        }
+
+        // Each output's value goes next to the vertex it follows.
+        for output in setting.outputs() {
+            let Some(point) = points.get(output.vertex_index) else {
+                continue;
+            };
+            painter.text(
+                to_screen(*point) + vec2(14.0, 0.0),
+                Align2::LEFT_CENTER,
+                format!(
+                    "{} = {:.2}",
+                    self.param_data.ids[output.parameter_index], self.posed[output.parameter_index]
+                ),
+                FontId::proportional(14.0),
+                ui.visuals().text_color(),
             );
-            for point in physics.points.iter_mut().skip(1) {
-                let next = vec2(point.cur_position.x, point.cur_position.y);
-
-                painter.line_segment(
-                    [
-                        origin + last_point * scale_factor,
-                        origin + next * scale_factor,
-                    ],
-                    Stroke::new(2.0, Color32::RED),
-                );
-                painter.circle(
-                    origin + next * scale_factor,
-                    20.0,
-                    Color32::TRANSPARENT,
-                    Stroke::new(2.0, Color32::RED),
-                );
+        }
+    }
+}
 
-                last_point = next;
-            }
+fn main() -> Result<(), eframe::Error> {
+    let mut args = std::env::args_os().skip(1).map(PathBuf::from);
+    let Some(moc3_path) = args.next() else {
+        eprintln!("usage: moc3-physicsview model.moc3 [model.physics3.json]");
+        std::process::exit(2);
+    };
+    let physics_path = args
+        .next()
+        .unwrap_or_else(|| moc3_path.with_extension("physics3.json"));
 
-            ctx.request_repaint();
-        });
+    let mut view = match View::load(&moc3_path, &physics_path) {
+        Ok(view) => view,
+        Err(e) => {
+            eprintln!("{e}");
+            std::process::exit(1);
+        }
+    };
+    if view.names.is_empty() {
+        eprintln!("{} has no physics settings", physics_path.display());
+        std::process::exit(1);
+    }
+
+    let options = eframe::NativeOptions {
+        viewport: egui::ViewportBuilder::default().with_inner_size([1200.0, 800.0]),
+        ..Default::default()
+    };
+    eframe::run_simple_native("moc3 physics", options, move |ctx, _frame| {
+        view.update();
+
+        egui::SidePanel::right("settings")
+            .min_width(360.0)
+            .show(ctx, |ui| view.side_panel(ui));
+        egui::CentralPanel::default().show(ctx, |ui| view.draw_pendulum(ui));
+
+        ctx.request_repaint();
     })
 }
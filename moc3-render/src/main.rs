@@ -0,0 +1,292 @@
+//! Renders a model to a PNG without opening a window, for previews and documentation.
+//!
+//! ```text
+//! moc3-render <model.model3.json | model.moc3> -o <out.png> [options]
+//!
+//!   --texture <png>        a texture for a bare .moc3, in order (repeatable)
+//!   --size <W>x<H>         the size of each frame, 1000x1000 by default
+//!   --param <ID>=<VALUE>   overrides a parameter's default (repeatable)
+//!   --sweep <ID>           renders a grid stepping this parameter from its minimum to maximum
+//!   --steps <N>            how many frames the sweep has, 5 by default
+//!   --columns <N>          how many frames go in each row of the grid, all of them by default
+//!   --placeholder          draws flat colors instead of textures
+//!   --soft-masks           draws masks with soft edges, like the editor
+//!   --sort-triangles       draws each mesh's triangles top to bottom, for self-overlapping
+//!                          translucent meshes
+//!   --background <BG>      transparent (the default), checkerboard, or a color like ff8800
+//! ```
+
+use std::{
+    path::{Path, PathBuf},
+    process::ExitCode,
+};
+
+use glam::Vec2;
+use image::{imageops, RgbaImage};
+use moc3_rs::{parse_puppet, puppet::framedata_for_puppet};
+use moc3_wgpu::{
+    background::Background,
+    capture::FrameCapture,
+    renderer::{new_renderer, TriangleSort},
+    soft_mask::SoftMask,
+};
+use serde::Deserialize;
+
+#[derive(Deserialize)]
+#[serde(rename_all = "PascalCase")]
+struct Model3 {
+    file_references: FileReferences,
+}
+
+#[derive(Deserialize)]
+#[serde(rename_all = "PascalCase")]
+struct FileReferences {
+    moc: String,
+    #[serde(default)]
+    textures: Vec<String>,
+}
+
+struct Options {
+    model: PathBuf,
+    output: PathBuf,
+    textures: Vec<PathBuf>,
+    width: u32,
+    height: u32,
+    params: Vec<(String, f32)>,
+    sweep: Option<String>,
+    steps: u32,
+    columns: Option<u32>,
+    placeholder: bool,
+    soft_masks: bool,
+    sort_triangles: bool,
+    background: Background,
+}
+
+fn parse_args() -> Result<Options, String> {
+    let mut args = std::env::args().skip(1);
+    let mut model = None;
+    let mut output = None;
+    let mut options = Options {
+        model: PathBuf::new(),
+        output: PathBuf::new(),
+        textures: Vec::new(),
+        width: 1000,
+        height: 1000,
+        params: Vec::new(),
+        sweep: None,
+        steps: 5,
+        columns: None,
+        placeholder: false,
+        soft_masks: false,
+        sort_triangles: false,
+        background: Background::None,
+    };
+
+    while let Some(arg) = args.next() {
+        let mut value = || args.next().ok_or(format!("{arg} needs a value"));
+        match arg.as_str() {
+            "-o" | "--output" => output = Some(PathBuf::from(value()?)),
+            "--texture" => options.textures.push(PathBuf::from(value()?)),
+            "--size" => {
+                let size = value()?;
+                let (width, height) = size
+                    .split_once('x')
+                    .and_then(|(w, h)| Some((w.parse().ok()?, h.parse().ok()?)))
+                    .ok_or(format!("bad size {size}, expected something like 512x512"))?;
+                options.width = width;
+                options.height = height;
+            }
+            "--param" => {
+                let param = value()?;
+                let (id, value) = param
+                    .split_once('=')
+                    .and_then(|(id, v)| Some((id.to_string(), v.parse().ok()?)))
+                    .ok_or(format!("bad parameter {param}, expected ID=VALUE"))?;
+                options.params.push((id, value));
+            }
+            "--sweep" => options.sweep = Some(value()?),
+            "--steps" => {
+                options.steps = value()?.parse().map_err(|_| "bad step count")?;
+            }
+            "--columns" => {
+                options.columns = Some(value()?.parse().map_err(|_| "bad column count")?);
+            }
+            "--placeholder" => options.placeholder = true,
+            "--soft-masks" => options.soft_masks = true,
+            "--sort-triangles" => options.sort_triangles = true,
+            "--background" => options.background = parse_background(&value()?)?,
+            _ if model.is_none() && !arg.starts_with('-') => model = Some(PathBuf::from(arg)),
+            _ => return Err(format!("unexpected argument {arg}")),
+        }
+    }
+
+    options.model = model.ok_or("no model given")?;
+    options.output = output.ok_or("no output given, use -o <out.png>")?;
+    if options.steps == 0 || options.columns == Some(0) {
+        return Err("steps and columns must be at least 1".to_string());
+    }
+
+    Ok(options)
+}
+
+fn parse_background(background: &str) -> Result<Background, String> {
+    match background {
+        "transparent" => return Ok(Background::None),
+        "checkerboard" => return Ok(Background::CHECKERBOARD),
+        _ => {}
+    }
+
+    let hex = background.trim_start_matches('#');
+    let color = u32::from_str_radix(hex, 16)
+        .ok()
+        .filter(|_| hex.len() == 6)
+        .ok_or(format!(
+            "bad background {background}, expected transparent, checkerboard or RRGGBB"
+        ))?;
+    let channel = |shift: u32| ((color >> shift) & 0xff) as f64 / 255.0;
+
+    Ok(Background::Solid(wgpu::Color {
+        r: channel(16),
+        g: channel(8),
+        b: channel(0),
+        a: 1.0,
+    }))
+}
+
+// Finds the moc3 and textures, either from a model3.json or as given on the command line.
+fn model_files(options: &Options) -> Result<(PathBuf, Vec<PathBuf>), String> {
+    let is_moc3 = options.model.extension().is_some_and(|x| x == "moc3");
+    if is_moc3 {
+        return Ok((options.model.clone(), options.textures.clone()));
+    }
+
+    let json = std::fs::read_to_string(&options.model)
+        .map_err(|e| format!("couldn't read {}: {e}", options.model.display()))?;
+    let model3: Model3 = serde_json::from_str(&json)
+        .map_err(|e| format!("couldn't parse {}: {e}", options.model.display()))?;
+
+    let directory = options.model.parent().unwrap_or(Path::new(""));
+    let references = model3.file_references;
+    Ok((
+        directory.join(references.moc),
+        references
+            .textures
+            .iter()
+            .map(|x| directory.join(x))
+            .collect(),
+    ))
+}
+
+fn run(options: Options) -> Result<(), String> {
+    let (moc3_path, texture_paths) = model_files(&options)?;
+
+    let bytes = std::fs::read(&moc3_path)
+        .map_err(|e| format!("couldn't read {}: {e}", moc3_path.display()))?;
+    let puppet =
+        parse_puppet(&bytes).map_err(|e| format!("couldn't parse {}: {e}", moc3_path.display()))?;
+
+    let mut textures = Vec::new();
+    if !options.placeholder {
+        for path in &texture_paths {
+            let texture =
+                image::open(path).map_err(|e| format!("couldn't open {}: {e}", path.display()))?;
+            textures.push(texture.into_rgba8());
+        }
+    }
+
+    let param_data = puppet.param_data();
+    let param_index = |id: &str| {
+        param_data
+            .ids
+            .iter()
+            .position(|x| x == id)
+            .ok_or(format!("the model has no parameter {id}"))
+    };
+
+    let mut params = param_data.defaults.clone();
+    for (id, value) in &options.params {
+        params[param_index(id)?] = *value;
+    }
+
+    // Every frame of the sweep, or just the one if there's no sweep.
+    let mut frames = Vec::new();
+    match &options.sweep {
+        Some(id) => {
+            let index = param_index(id)?;
+            let (min, max) = (param_data.mins[index], param_data.maxes[index]);
+            for step in 0..options.steps {
+                let t = step as f32 / (options.steps - 1).max(1) as f32;
+                let mut params = params.clone();
+                params[index] = min + (max - min) * t;
+                frames.push(params);
+            }
+        }
+        None => frames.push(params),
+    }
+
+    let instance = wgpu::Instance::new(wgpu::InstanceDescriptor::default());
+    let adapter = pollster::block_on(instance.request_adapter(&wgpu::RequestAdapterOptions {
+        power_preference: wgpu::PowerPreference::default(),
+        compatible_surface: None,
+        force_fallback_adapter: false,
+    }))
+    .ok_or("no graphics adapter available")?;
+    let (device, queue) = pollster::block_on(adapter.request_device(
+        &wgpu::DeviceDescriptor {
+            features: wgpu::Features::empty(),
+            limits: wgpu::Limits::default(),
+            label: None,
+        },
+        None,
+    ))
+    .map_err(|e| format!("couldn't open the graphics device: {e}"))?;
+
+    let mut renderer = new_renderer(&puppet, &device, &queue, FrameCapture::FORMAT, &textures);
+    renderer.set_placeholder_mode(options.placeholder);
+    renderer.set_background(&device, &queue, options.background);
+    if options.soft_masks {
+        renderer.set_soft_masks(&device, Some(SoftMask::EDITOR));
+    }
+    if options.sort_triangles {
+        // Model coordinates point down, so this draws lower triangles over higher ones.
+        for i in 0..puppet.art_mesh_count as usize {
+            renderer.set_triangle_sort(i, Some(TriangleSort::along(Vec2::Y)));
+        }
+    }
+    let mut capture = FrameCapture::new(&device, options.width, options.height);
+    let mut frame_data = framedata_for_puppet(&puppet);
+    let part_opacities = vec![1.0; puppet.part_count as usize];
+
+    let columns = options.columns.unwrap_or(frames.len() as u32);
+    let rows = (frames.len() as u32).div_ceil(columns);
+    let mut grid = RgbaImage::new(options.width * columns, options.height * rows);
+
+    for (i, params) in frames.iter().enumerate() {
+        puppet.update(params, &part_opacities, &mut frame_data);
+        let frame = capture
+            .capture(&device, &queue, &mut renderer, &frame_data)
+            .map_err(|e| format!("couldn't read back frame {i}: {e}"))?;
+
+        let (column, row) = (i as u32 % columns, i as u32 / columns);
+        imageops::replace(
+            &mut grid,
+            &frame,
+            (column * options.width) as i64,
+            (row * options.height) as i64,
+        );
+    }
+
+    grid.save(&options.output)
+        .map_err(|e| format!("couldn't write {}: {e}", options.output.display()))
+}
+
+fn main() -> ExitCode {
+    let result = parse_args().and_then(run);
+    match result {
+        Ok(()) => ExitCode::SUCCESS,
+        Err(e) => {
+            eprintln!("moc3-render: {e}");
+            ExitCode::FAILURE
+        }
+    }
+}
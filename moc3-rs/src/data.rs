@@ -1,6 +1,119 @@
+use std::{fmt, ops::Range};
+
 use binrw::{args, helpers::count_with, BinRead, FilePtr32, NullString};
 use glam::Vec2;
 use modular_bitfield::{bitfield, BitfieldSpecifier};
+use thiserror::Error;
+
+/// A moc3 whose sections point outside of each other, such as a mesh's vertexes running
+/// past the end of the UV table. Ranges are kept as u64s so ones that would overflow still
+/// come out as they were in the file.
+///
+/// Ranges that are in bounds but hold something that can't be used, like a glue between an
+/// art mesh and itself or parameter keys out of order, are reported the same way.
+#[derive(Error, Debug, Clone, PartialEq, Eq)]
+pub struct LayoutError {
+    /// The fields being read, named like [Section::name].
+    pub what: &'static str,
+    pub start: u64,
+    pub end: u64,
+    pub len: u64,
+}
+
+impl LayoutError {
+    // For indices into objects rather than arrays, like which art mesh a glue is for.
+    pub(crate) fn check_index(what: &'static str, index: u64, len: usize) -> Result<(), Self> {
+        if index >= len as u64 {
+            return Err(Self {
+                what,
+                start: index,
+                end: index + 1,
+                len: len as u64,
+            });
+        }
+        Ok(())
+    }
+
+    // For values that are in bounds but malformed, like unsorted keys.
+    pub(crate) fn malformed(what: &'static str, range: Range<usize>, len: usize) -> Self {
+        Self {
+            what,
+            start: range.start as u64,
+            end: range.end as u64,
+            len: len as u64,
+        }
+    }
+}
+
+impl fmt::Display for LayoutError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let Self {
+            what,
+            start,
+            end,
+            len,
+        } = self;
+        if end > len {
+            write!(
+                f,
+                "could not read {what}: {start}..{end} is out of bounds of {len}"
+            )
+        } else {
+            write!(f, "could not read {what}: {start}..{end} is malformed")
+        }
+    }
+}
+
+/// A warp deformer whose point count doesn't add up to a grid of its rows and columns, which
+/// would otherwise leave it reading its grid out of the wrong points.
+#[derive(Error, Debug, Clone, PartialEq, Eq)]
+#[error("warp deformer {index} has {vertex_count} points, which doesn't fit {rows} rows and {columns} columns of cells")]
+pub struct WarpGridError {
+    /// The warp deformer's index among the warp deformers.
+    pub index: usize,
+    pub rows: u32,
+    pub columns: u32,
+    pub vertex_count: u32,
+}
+
+/// An art mesh whose vertex indices don't split up into whole triangles.
+#[derive(Error, Debug, Clone, PartialEq, Eq)]
+#[error(
+    "art mesh {index} has {index_count} vertex indices, which isn't a whole number of triangles"
+)]
+pub struct TriangleCountError {
+    /// The art mesh's index among the art meshes.
+    pub index: usize,
+    pub index_count: u32,
+}
+
+// Bounds checked reads of the arrays in a moc3, for anything indexed by values from the file
+// itself. Arrays read with one of the counts are always exactly that long, so those can be
+// indexed by their own objects directly.
+pub(crate) trait Checked<T> {
+    fn at(&self, what: &'static str, index: usize) -> Result<&T, LayoutError>;
+    fn range(&self, what: &'static str, start: usize, count: usize) -> Result<&[T], LayoutError>;
+}
+
+impl<T> Checked<T> for [T] {
+    fn at(&self, what: &'static str, index: usize) -> Result<&T, LayoutError> {
+        self.range(what, index, 1).map(|x| &x[0])
+    }
+
+    fn range(&self, what: &'static str, start: usize, count: usize) -> Result<&[T], LayoutError> {
+        // Done in u64s, as a start and count from the file can add up past a 32 bit usize.
+        let (start, end) = (start as u64, start as u64 + count as u64);
+        if end > self.len() as u64 {
+            return Err(LayoutError {
+                what,
+                start,
+                end,
+                len: self.len() as u64,
+            });
+        }
+        Ok(&self[start as usize..end as usize])
+    }
+}
 
 #[binrw::parser(reader, endian)]
 fn vec2_parser() -> binrw::BinResult<Vec2> {
@@ -14,13 +127,38 @@ pub struct Header {
     pub big_endian: u8,
 }
 
+/// Newer versions sort after older ones, with [Version::Unknown] after all of them.
 #[derive(BinRead, Debug, Copy, Clone, PartialOrd, Ord, PartialEq, Eq)]
-#[br(repr = u8)]
+#[br(try_map = Self::from_byte)]
 pub enum Version {
-    V3_00 = 1,
-    V3_03 = 2,
-    V4_00 = 3,
-    V4_02 = 4,
+    V3_00,
+    V3_03,
+    V4_00,
+    V4_02,
+    /// A version newer than any this crate knows. Everything up to [Version::LATEST] is
+    /// still read as if it were that version, which is usually enough, see
+    /// [crate::parse_puppet_best_effort].
+    Unknown(u8),
+}
+
+impl Version {
+    /// The newest version this crate fully supports.
+    pub const LATEST: Self = Self::V4_02;
+
+    fn from_byte(byte: u8) -> Result<Self, &'static str> {
+        match byte {
+            0 => Err("invalid moc3 version"),
+            1 => Ok(Self::V3_00),
+            2 => Ok(Self::V3_03),
+            3 => Ok(Self::V4_00),
+            4 => Ok(Self::V4_02),
+            x => Ok(Self::Unknown(x)),
+        }
+    }
+
+    pub fn is_known(&self) -> bool {
+        !matches!(self, Self::Unknown(_))
+    }
 }
 
 #[derive(BinRead, Debug)]
@@ -170,7 +308,7 @@ pub struct RotationDeformerOffsets {
     pub base_angles: FilePtr32<Vec<f32>>,
 }
 
-#[derive(BitfieldSpecifier, Debug, Clone, Copy, PartialEq, Eq)]
+#[derive(BitfieldSpecifier, Debug, Clone, Copy, PartialEq, Eq, Hash)]
 #[bits = 2]
 pub enum BlendMode {
     Normal = 0,
@@ -187,6 +325,22 @@ pub struct ArtMeshFlags {
     pub inverted: bool,
 }
 
+// The bitfield macro hides the fields, so go through the packed byte instead.
+#[cfg(feature = "serde")]
+impl serde::Serialize for ArtMeshFlags {
+    fn serialize<S: serde::Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        serializer.serialize_u8(self.into_bytes()[0])
+    }
+}
+
+#[cfg(feature = "serde")]
+impl<'de> serde::Deserialize<'de> for ArtMeshFlags {
+    fn deserialize<D: serde::Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+        let byte = <u8 as serde::Deserialize>::deserialize(deserializer)?;
+        Self::from_bytes([byte]).map_err(|_| serde::de::Error::custom("invalid art mesh flags"))
+    }
+}
+
 #[derive(BinRead, Debug)]
 #[br(import {
     count: usize
@@ -252,6 +406,7 @@ pub struct ParameterOffsets {
 }
 
 #[derive(BinRead, Debug, Copy, Clone, PartialOrd, Ord, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
 #[br(repr = u32)]
 #[non_exhaustive]
 pub enum ParameterType {
@@ -713,6 +868,65 @@ pub struct ParameterExtensionsOffsets {
     pub keys_sources_counts: FilePtr32<Vec<u32>>,
 }
 
+/// Where one array of a moc3 is in the file, from [Moc3Data::sections].
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Section {
+    /// The fields of [SectionOffsetTable] pointing at it, like `art_meshes.vertex_counts`.
+    pub name: &'static str,
+    pub offset: usize,
+    /// In bytes.
+    pub len: usize,
+}
+
+impl Section {
+    fn of<T: FileSize>(name: &'static str, ptr: &FilePtr32<Vec<T>>) -> Self {
+        Self {
+            name,
+            offset: ptr.ptr as usize,
+            len: ptr.len() * T::SIZE,
+        }
+    }
+
+    pub fn range(&self) -> Range<usize> {
+        self.offset..self.offset + self.len
+    }
+}
+
+// How many bytes each element of an array takes up in the file, which isn't always how big
+// it is once parsed.
+trait FileSize {
+    const SIZE: usize;
+}
+
+macro_rules! file_size {
+    ($($ty:ty = $size:literal),* $(,)?) => {
+        $(impl FileSize for $ty {
+            const SIZE: usize = $size;
+        })*
+    };
+}
+
+file_size!(
+    u16 = 2,
+    u32 = 4,
+    i32 = 4,
+    f32 = 4,
+    Vec2 = 8,
+    Id = 64,
+    ArtMeshFlags = 1,
+    ParameterType = 4,
+    DrawOrderGroupObjectType = 4,
+);
+
+macro_rules! push_sections {
+    ($sections:ident, $group:ident: $offsets:expr, [$($field:ident),* $(,)?]) => {
+        $($sections.push(Section::of(
+            concat!(stringify!($group), ".", stringify!($field)),
+            &$offsets.$field,
+        ));)*
+    };
+}
+
 #[derive(BinRead, Debug)]
 pub struct Moc3Data {
     #[br(pad_size_to = 64)]
@@ -738,8 +952,177 @@ impl Moc3Data {
         self.table.keyform_positions.coords.value.as_ref().unwrap()
     }
 
+    /// Where the keyform positions start in the file, in bytes.
+    pub fn positions_offset(&self) -> usize {
+        self.table.keyform_positions.coords.ptr as usize
+    }
+
     pub fn uvs(&self) -> &[Vec2] {
         // TODO: nya want deref
         self.table.uvs.uvs.value.as_ref().unwrap()
     }
+
+    /// Where every section of the file is, for tools that need to find (or patch) data in
+    /// the raw bytes. Sections are listed in the order the table points at them, and the
+    /// table itself starts right after the 64 byte header.
+    pub fn sections(&self) -> Vec<Section> {
+        let table = &self.table;
+        // The counts that were read, as newer versions have more of them.
+        let counts = if self.header.version >= Version::V4_02 {
+            32
+        } else {
+            23
+        };
+        let mut sections = vec![
+            Section {
+                name: "count_info",
+                offset: table.count_info.ptr as usize,
+                len: counts * 4,
+            },
+            Section {
+                name: "canvas_info",
+                offset: table.canvas_info.ptr as usize,
+                len: 21,
+            },
+        ];
+
+        push_sections!(sections, parts: table.parts, [
+            ids, keyform_binding_sources_indices, keyform_sources_starts, keyform_sources_counts,
+            is_visible, is_enabled, parent_part_indices,
+        ]);
+        push_sections!(sections, deformers: table.deformers, [
+            ids, keyform_binding_sources_indices, is_visible, is_enabled, parent_part_indices,
+            parent_deformer_indices, types, specific_sources_indices,
+        ]);
+        push_sections!(sections, warp_deformers: table.warp_deformers, [
+            keyform_binding_sources_indices, keyform_sources_starts, keyform_sources_counts,
+            vertex_counts, rows, columns,
+        ]);
+        push_sections!(sections, rotation_deformers: table.rotation_deformers, [
+            keyform_binding_sources_indices, keyform_sources_starts, keyform_sources_counts,
+            base_angles,
+        ]);
+        push_sections!(sections, art_meshes: table.art_meshes, [
+            ids, keyform_binding_sources_indices, keyform_sources_starts, keyform_sources_counts,
+            is_visible, is_enabled, parent_part_indices, parent_deformer_indices, texture_nums,
+            art_mesh_flags, vertex_counts, uv_sources_starts, vertex_index_sources_starts,
+            vertex_index_sources_counts, art_mesh_mask_sources_starts, art_mesh_mask_sources_counts,
+        ]);
+        push_sections!(sections, parameters: table.parameters, [
+            ids, max_values, min_values, default_values, is_repeat, decimal_places,
+            parameter_binding_sources_starts, parameter_binding_sources_counts,
+        ]);
+        push_sections!(sections, part_keyforms: table.part_keyforms, [draw_orders]);
+        push_sections!(sections, warp_deformer_keyforms: table.warp_deformer_keyforms, [
+            opacities, keyform_position_sources_starts,
+        ]);
+        push_sections!(sections, rotation_deformer_keyforms: table.rotation_deformer_keyforms, [
+            opacities, angles, x_origin, y_origin, scales, is_reflect_x, is_reflect_y,
+        ]);
+        push_sections!(sections, art_mesh_keyforms: table.art_mesh_keyforms, [
+            opacities, draw_orders, keyform_position_sources_starts,
+        ]);
+        push_sections!(sections, keyform_positions: table.keyform_positions, [coords]);
+        push_sections!(sections, parameter_binding_indices: table.parameter_binding_indices, [
+            binding_sources_indices,
+        ]);
+        push_sections!(sections, keyform_bindings: table.keyform_bindings, [
+            parameter_binding_index_sources_starts, parameter_binding_index_sources_counts,
+        ]);
+        push_sections!(sections, parameter_bindings: table.parameter_bindings, [
+            keys_sources_starts, keys_sources_counts,
+        ]);
+        push_sections!(sections, keys: table.keys, [values]);
+        push_sections!(sections, uvs: table.uvs, [uvs]);
+        push_sections!(sections, vertex_indices: table.vertex_indices, [indices]);
+        push_sections!(sections, art_mesh_masks: table.art_mesh_masks, [art_mesh_source_indices]);
+        push_sections!(sections, draw_order_groups: table.draw_order_groups, [
+            object_sources_starts, object_sources_counts, object_sources_total_counts,
+            maximum_draw_orders, minimum_draw_orders,
+        ]);
+        push_sections!(sections, draw_order_group_objects: table.draw_order_group_objects, [
+            types, indices, self_indices,
+        ]);
+        push_sections!(sections, glues: table.glues, [
+            ids, keyform_binding_sources_indices, keyform_sources_starts, keyform_sources_counts,
+            art_mesh_indices_a, art_mesh_indices_b, glue_info_sources_starts,
+            glue_info_sources_counts,
+        ]);
+        push_sections!(sections, glue_infos: table.glue_infos, [weights, vertex_indices]);
+        push_sections!(sections, glue_keyforms: table.glue_keyforms, [intensities]);
+        if let Some(offsets) = &table.warp_deformer_keyforms_v303 {
+            push_sections!(sections, warp_deformer_keyforms_v303: offsets, [is_new_deformerrs]);
+        }
+        if let Some(offsets) = &table.parameter_extensions {
+            push_sections!(sections, parameter_extensions: offsets, [
+                keys_sources_starts, keys_sources_counts,
+            ]);
+        }
+        if let Some(offsets) = &table.warp_deformer_keyforms_v402 {
+            push_sections!(sections, warp_deformer_keyforms_v402: offsets, [
+                keyform_color_sources_start,
+            ]);
+        }
+        if let Some(offsets) = &table.rotation_deformer_keyforms_v402 {
+            push_sections!(sections, rotation_deformer_keyforms_v402: offsets, [
+                keyform_color_sources_start,
+            ]);
+        }
+        if let Some(offsets) = &table.art_mesh_deformer_keyforms_v402 {
+            push_sections!(sections, art_mesh_deformer_keyforms_v402: offsets, [
+                keyform_color_sources_start,
+            ]);
+        }
+        if let Some(offsets) = &table.keyform_multiply_colors {
+            push_sections!(sections, keyform_multiply_colors: offsets, [red, green, blue]);
+        }
+        if let Some(offsets) = &table.keyform_screen_colors {
+            push_sections!(sections, keyform_screen_colors: offsets, [red, green, blue]);
+        }
+        if let Some(offsets) = &table.parameters_v402 {
+            push_sections!(sections, parameters_v402: offsets, [
+                parameter_types, blend_shape_parameter_binding_sources_starts,
+                blend_shape_parameter_binding_sources_counts,
+            ]);
+        }
+        if let Some(offsets) = &table.blend_shape_parameter_bindings {
+            push_sections!(sections, blend_shape_parameter_bindings: offsets, [
+                keys_sources_starts, keys_sources_counts, base_key_indices,
+            ]);
+        }
+        if let Some(offsets) = &table.blend_shape_keyform_bindings {
+            push_sections!(sections, blend_shape_keyform_bindings: offsets, [
+                blend_shape_parameter_binding_sources_indices, keyform_sources_blend_shape_starts,
+                keyform_sources_blend_shape_counts, blend_shape_constraint_index_sources_starts,
+                blend_shape_constraint_index_sources_counts,
+            ]);
+        }
+        if let Some(offsets) = &table.blend_shape_warp_deformers {
+            push_sections!(sections, blend_shape_warp_deformers: offsets, [
+                target_indices, blend_shape_keyform_binding_sources_starts,
+                blend_shape_keyform_binding_sources_counts,
+            ]);
+        }
+        if let Some(offsets) = &table.blend_shape_art_meshes {
+            push_sections!(sections, blend_shape_art_meshes: offsets, [
+                target_indices, blend_shape_keyform_binding_sources_starts,
+                blend_shape_keyform_binding_sources_counts,
+            ]);
+        }
+        if let Some(offsets) = &table.blend_shape_constraint_indices {
+            push_sections!(sections, blend_shape_constraint_indices: offsets, [
+                blend_shape_constraint_sources_indices,
+            ]);
+        }
+        if let Some(offsets) = &table.blend_shape_constraints {
+            push_sections!(sections, blend_shape_constraints: offsets, [
+                parameter_indices, blend_shape_constraint_value_sources_starts,
+                blend_shape_constraint_value_sources_counts,
+            ]);
+        }
+        if let Some(offsets) = &table.blend_shape_constraint_values {
+            push_sections!(sections, blend_shape_constraint_values: offsets, [keys, weights]);
+        }
+        sections
+    }
 }
@@ -1,3 +1,5 @@
+use std::collections::HashMap;
+
 use glam::Vec2;
 
 // Glues are thankfully rather simple, they "glue" two
@@ -5,6 +7,13 @@ use glam::Vec2;
 // intensity value for how strong the glue is, and also
 // weights for figuring out which side has a stronger
 // pull.
+//
+// Vertexes come in pairs, one from each art mesh, with a weight
+// for each side. At full intensity a vertex moves its weight's
+// worth of the way towards the other one, so a pair only fully
+// meets if its two weights add up to 1. The editor keeps them
+// that way, but nothing in the file format requires it, and a
+// vertex can also be part of more than one pair.
 
 pub fn apply_glue(
     intensity: f32,
@@ -23,3 +32,128 @@ pub fn apply_glue(
         art_mesh_two[index[1] as usize] += (a - b) * weight[1] * intensity;
     }
 }
+
+// Rescales every pair's weights to add up to 1, then splits each vertex's
+// weight between all of the pairs it's in, so a vertex glued to several
+// others is pulled towards their average instead of past all of them.
+pub fn normalize_glue_weights(positions: &[u16], weights: &[f32]) -> Vec<f32> {
+    debug_assert_eq!(positions.len(), weights.len());
+
+    let mut pair_counts = [HashMap::new(), HashMap::new()];
+    for index in positions.chunks_exact(2) {
+        for (counts, vertex) in pair_counts.iter_mut().zip(index) {
+            *counts.entry(*vertex).or_insert(0) += 1;
+        }
+    }
+
+    let mut normalized = Vec::with_capacity(weights.len());
+    for (index, weight) in positions.chunks_exact(2).zip(weights.chunks_exact(2)) {
+        let sum = weight[0] + weight[1];
+        for side in 0..2 {
+            if sum > 0.0 {
+                normalized.push(weight[side] / sum / pair_counts[side][&index[side]] as f32);
+            } else {
+                normalized.push(0.0);
+            }
+        }
+    }
+
+    normalized
+}
+
+// Like [apply_glue], but every pull is worked out from the positions before
+// any of them are applied, so the result doesn't depend on the order of the
+// pairs. Meant for weights from [normalize_glue_weights].
+pub fn apply_glue_normalized(
+    intensity: f32,
+    positions: &[u16],
+    weights: &[f32],
+    deltas: &mut Vec<Vec2>,
+    art_mesh_one: &mut [Vec2],
+    art_mesh_two: &mut [Vec2],
+) {
+    debug_assert_eq!(positions.len(), weights.len());
+
+    // Intensities are interpolated between keyforms, so don't let that overshoot.
+    let intensity = intensity.clamp(0.0, 1.0);
+
+    deltas.clear();
+    for (index, weight) in positions.chunks_exact(2).zip(weights.chunks_exact(2)) {
+        let a = art_mesh_one[index[0] as usize];
+        let b = art_mesh_two[index[1] as usize];
+
+        deltas.push((b - a) * weight[0] * intensity);
+        deltas.push((a - b) * weight[1] * intensity);
+    }
+
+    for (index, delta) in positions.chunks_exact(2).zip(deltas.chunks_exact(2)) {
+        art_mesh_one[index[0] as usize] += delta[0];
+        art_mesh_two[index[1] as usize] += delta[1];
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use glam::vec2;
+
+    use super::*;
+
+    #[test]
+    fn normalized_pair_meets() {
+        let positions = [0, 0];
+        let weights = normalize_glue_weights(&positions, &[0.3, 0.1]);
+        assert_eq!(weights, [0.75, 0.25]);
+
+        let mut one = [vec2(0.0, 0.0)];
+        let mut two = [vec2(4.0, 0.0)];
+        apply_glue_normalized(
+            1.0,
+            &positions,
+            &weights,
+            &mut Vec::new(),
+            &mut one,
+            &mut two,
+        );
+
+        assert_eq!(one, [vec2(3.0, 0.0)]);
+        assert_eq!(two, one);
+    }
+
+    #[test]
+    fn zero_weights_stay_put() {
+        let weights = normalize_glue_weights(&[0, 0], &[0.0, 0.0]);
+        assert_eq!(weights, [0.0, 0.0]);
+    }
+
+    #[test]
+    fn shared_vertex_is_averaged() {
+        // The first mesh's only vertex is glued to both of the second mesh's vertexes.
+        let positions = [0, 0, 0, 1];
+        let weights = normalize_glue_weights(&positions, &[1.0, 0.0, 1.0, 0.0]);
+        assert_eq!(weights, [0.5, 0.0, 0.5, 0.0]);
+
+        let mut one = [vec2(0.0, 0.0)];
+        let mut two = [vec2(2.0, 0.0), vec2(0.0, 2.0)];
+        apply_glue_normalized(
+            1.0,
+            &positions,
+            &weights,
+            &mut Vec::new(),
+            &mut one,
+            &mut two,
+        );
+
+        assert_eq!(one, [vec2(1.0, 1.0)]);
+        assert_eq!(two, [vec2(2.0, 0.0), vec2(0.0, 2.0)]);
+    }
+
+    #[test]
+    fn raw_matches_weights() {
+        let mut one = [vec2(0.0, 0.0)];
+        let mut two = [vec2(4.0, 0.0)];
+        apply_glue(0.5, &[0, 0], &[0.5, 0.5], &mut one, &mut two);
+
+        assert_eq!(one, [vec2(1.0, 0.0)]);
+        assert_eq!(two, [vec2(3.0, 0.0)]);
+    }
+}
@@ -1,7 +1,8 @@
 use bytemuck::{Pod, Zeroable};
-use glam::{Mat3, Vec2};
+use glam::{DMat3, DVec2, Mat3, Vec2};
 
 #[derive(Pod, Zeroable, Debug, Default, Clone, Copy)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
 #[repr(C)]
 pub struct TransformData {
     pub origin: Vec2,
@@ -36,22 +37,58 @@ impl TransformData {
 // translation, scale, and reflection. We can just offload
 // all the hard work to glam.
 
+pub fn rotation_deformer_matrix(data: &TransformData, base_angle: f32) -> Mat3 {
+    Mat3::from_scale_angle_translation(
+        Vec2::splat(data.scale),
+        (base_angle + data.angle).to_radians(),
+        data.origin,
+    )
+}
+
+// Like [rotation_deformer_matrix], for deforming in double precision.
+pub fn rotation_deformer_matrix_f64(
+    origin: DVec2,
+    scale: f64,
+    angle: f64,
+    base_angle: f32,
+) -> DMat3 {
+    DMat3::from_scale_angle_translation(
+        DVec2::splat(scale),
+        (base_angle as f64 + angle).to_radians(),
+        origin,
+    )
+}
+
 pub fn apply_rotation_deformer(
     data: &TransformData,
     base_angle: f32,
     points_to_transform: &mut [Vec2],
 ) {
-    let transform_matrix = Mat3::from_scale_angle_translation(
-        Vec2::splat(data.scale),
-        (base_angle + data.angle).to_radians(),
-        data.origin,
-    );
+    let transform_matrix = rotation_deformer_matrix(data, base_angle);
 
     for i in points_to_transform {
         *i = transform_matrix.transform_point2(*i);
     }
 }
 
+// How much a rotation deformer turns everything in it, in degrees from -180 to 180. This is
+// what [calculate_rotation_deformer_angle] measures for a rotation deformer parent, without
+// transforming anything, and exactly, where measuring loses small angles to rounding.
+pub fn rotation_deformer_turn(data: &TransformData, base_angle: f32) -> f32 {
+    rotation_deformer_turn_f64(data.scale as f64, data.angle as f64, base_angle) as f32
+}
+
+// Like [rotation_deformer_turn], for deforming in double precision.
+pub fn rotation_deformer_turn_f64(scale: f64, angle: f64, base_angle: f32) -> f64 {
+    if scale == 0.0 || !scale.is_finite() || !angle.is_finite() {
+        return 0.0;
+    }
+
+    // A negative scale flips everything through the origin, which is another half turn.
+    let flip = if scale < 0.0 { 180.0 } else { 0.0 };
+    (base_angle as f64 + angle + flip + 180.0).rem_euclid(360.0) - 180.0
+}
+
 // Figures out how movement of a parent deformer changes the angle of a child deformer.
 pub fn calculate_rotation_deformer_angle<F>(origin: Vec2, fudge_factor: f32, transform: F) -> f32
 where
@@ -68,3 +105,69 @@ where
         0.0
     }
 }
+
+// Like [calculate_rotation_deformer_angle], for deforming in double precision.
+pub fn calculate_rotation_deformer_angle_f64<F>(
+    origin: DVec2,
+    fudge_factor: f64,
+    transform: F,
+) -> f64
+where
+    F: Fn(DVec2) -> DVec2,
+{
+    let direction = DVec2::NEG_Y * fudge_factor;
+    let transformed_origin = transform(origin);
+    let transformed_direction = transform(origin + direction);
+    let ret = transformed_direction - transformed_origin;
+
+    if ret.is_finite() && ret != DVec2::ZERO {
+        direction.angle_between(ret).to_degrees()
+    } else {
+        0.0
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use glam::vec2;
+
+    use super::*;
+
+    #[test]
+    fn turns_match_measured_angles() {
+        for (scale, angle, base_angle) in [
+            (1.0, 30.0, 0.0),
+            (2.5, -100.0, 45.0),
+            (-1.0, 30.0, 0.0),
+            (0.5, 170.0, 20.0),
+            (1.0, 0.0, -725.0),
+        ] {
+            let data = TransformData {
+                origin: vec2(0.3, -0.2),
+                scale,
+                angle,
+            };
+            let matrix = rotation_deformer_matrix(&data, base_angle);
+            let measured = calculate_rotation_deformer_angle(vec2(1.0, 2.0), 10.0, |p| {
+                matrix.transform_point2(p)
+            });
+            let turn = rotation_deformer_turn(&data, base_angle);
+
+            // Half turns either way are the same.
+            let difference = (turn - measured).rem_euclid(360.0);
+            assert!(
+                difference.min(360.0 - difference) < 1e-2,
+                "{turn} vs {measured}"
+            );
+        }
+
+        // Measuring rounds tiny turns away entirely.
+        let data = TransformData {
+            origin: Vec2::ZERO,
+            scale: 1.0,
+            angle: 0.01,
+        };
+        assert!((rotation_deformer_turn(&data, 0.0) - 0.01).abs() < 1e-6);
+        assert_eq!(rotation_deformer_turn(&data.with_scale(0.0), 0.0), 0.0);
+    }
+}
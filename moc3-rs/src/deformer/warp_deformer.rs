@@ -1,7 +1,7 @@
-use glam::{vec2, Vec2};
+use glam::{DVec2, Vec2};
 
 use crate::math::{
-    lerp::{bilinear_interp, triangular_interp},
+    lerp::{bilinear_interp, bilinear_interp_f64, triangular_interp, triangular_interp_f64},
     rescale,
 };
 
@@ -34,11 +34,106 @@ use crate::math::{
 // this time between the grid points on the edge or corner, and the points
 // that make a rectangle laying on the outer edge of the C area.
 
+/// What happens to points outside of a warp deformer's grid, see
+/// [PuppetFrameData::set_warp_extrapolation](crate::puppet::PuppetFrameData::set_warp_extrapolation).
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum WarpExtrapolation {
+    /// Follows the grid's edges out towards a parallelogram fitted to its corners, which is
+    /// our best guess at what Cubism does. Grids that are far from a parallelogram can fling
+    /// points a long way out with this.
+    #[default]
+    CubismApprox,
+    /// Points are moved to the closest spot on the grid's edge, so nothing ever ends up
+    /// outside of the grid.
+    ClampToEdge,
+    /// The grid carries on past its edge with the slope it has there, so points move as far
+    /// out as they were to begin with.
+    LinearExtrapolate,
+}
+
+/// A warp deformer's grid of points, stored row by row with one more point per row and
+/// column than the grid has cells.
+#[derive(Debug, Clone, Copy)]
+pub struct Grid2D<'a, T> {
+    points: &'a [T],
+    rows: usize,
+    columns: usize,
+}
+
+impl<'a, T: Copy> Grid2D<'a, T> {
+    /// Returns `None` unless there's at least one cell, and exactly as many points as the
+    /// cells need.
+    pub fn new(points: &'a [T], rows: usize, columns: usize) -> Option<Self> {
+        let valid =
+            rows > 0 && columns > 0 && (rows + 1).checked_mul(columns + 1) == Some(points.len());
+        valid.then_some(Self {
+            points,
+            rows,
+            columns,
+        })
+    }
+
+    pub fn rows(&self) -> usize {
+        self.rows
+    }
+
+    pub fn columns(&self) -> usize {
+        self.columns
+    }
+
+    /// The point in the given column and row, if the grid goes that far.
+    pub fn get(&self, x: usize, y: usize) -> Option<T> {
+        (x <= self.columns && y <= self.rows).then(|| self.points[x + y * (self.columns + 1)])
+    }
+
+    /// Like [Grid2D::get], for points known to be in the grid.
+    ///
+    /// # Panics
+    ///
+    /// If the grid doesn't go as far as `x` or `y`.
+    pub fn at(&self, x: usize, y: usize) -> T {
+        match self.get(x, y) {
+            Some(point) => point,
+            None => panic!(
+                "point ({x}, {y}) is outside of a {}x{} grid",
+                self.columns, self.rows
+            ),
+        }
+    }
+
+    /// The bottom-left, bottom-right, top-left and top-right points of the cell in the
+    /// given column and row.
+    pub fn cell(&self, x: usize, y: usize) -> [T; 4] {
+        assert!(
+            x < self.columns && y < self.rows,
+            "cell ({x}, {y}) is outside of a {}x{} grid",
+            self.columns,
+            self.rows
+        );
+        // Bounds are checked once for each row of the cell, rather than for every point.
+        let bottom = x + y * (self.columns + 1);
+        let top = bottom + self.columns + 1;
+        let (bottom, top) = (&self.points[bottom..bottom + 2], &self.points[top..top + 2]);
+        [bottom[0], bottom[1], top[0], top[1]]
+    }
+
+    /// The corners of the whole grid, in the same order as [Grid2D::cell].
+    pub fn corners(&self) -> [T; 4] {
+        [
+            self.at(0, 0),
+            self.at(self.columns, 0),
+            self.at(0, self.rows),
+            self.at(self.columns, self.rows),
+        ]
+    }
+}
+
 // the cases are as follows
 // | 6 | 7 | 8 |
 // | 3 | 4 | 5 |
 // | 0 | 1 | 2 |
-fn calc_case_index(point: Vec2) -> u32 {
+fn calc_case_index(point: impl Into<DVec2>) -> u32 {
+    let point = point.into();
     let x_ind = if point.x >= 1.0 {
         2
     } else if point.x >= 0.0 {
@@ -58,202 +153,425 @@ fn calc_case_index(point: Vec2) -> u32 {
     x_ind + y_ind * 3
 }
 
-// TODO: grid should be something with 2D indexing
-pub fn apply_warp_deformer(
-    grid: &[Vec2],
-    is_new_deformer: bool,
-    rows: usize,
-    columns: usize,
-    points_to_transform: &mut [Vec2],
-) {
-    // `columns` here is the number of columns in the deformer, which is defined
-    // by `columns + 1` points
-    //
-    // | 1 | 2 | ... | columns - 1 | columns |
-    let column_points = columns + 1;
-
-    for point_ref in points_to_transform.iter_mut() {
-        // rescales the point to be within ([0, columns], [0, rows]) for future indexing work.
-        let point = *point_ref;
-        let point_grid = point * vec2(columns as f32, rows as f32);
-        let grid_x = point_grid.x as usize;
-        let grid_y = point_grid.y as usize;
-
-        // Whether the point is directly inside the deformer - the simple case.
-        let is_normal = point.x >= 0.0 && point.x < 1.0 && point.y >= 0.0 && point.y < 1.0;
-        if is_normal {
-            // Trunced down, so this is the bottom-left corner of the grid.
-            let grid_index = grid_x + grid_y * column_points;
-
-            // It looks like the format started out with the barycenter interpolation,
-            // and then later switched to regular bilinear.
-            let res = if is_new_deformer {
-                bilinear_interp(
-                    point_grid.fract(),
-                    grid[grid_index],
-                    grid[grid_index + 1],
-                    grid[grid_index + column_points],
-                    grid[grid_index + column_points + 1],
-                )
-            } else {
-                triangular_interp(
-                    point_grid.fract(),
-                    grid[grid_index],
-                    grid[grid_index + 1],
-                    grid[grid_index + column_points],
-                    grid[grid_index + column_points + 1],
-                )
-            };
-
-            *point_ref = res;
-        } else {
-            // Oh boy. This is fun. Basically the mesh turns into parallelograms at the exteremes,
-            // and in the transition zone it gets interpolated between the original shape and the
-            // extreme parallelogram.
-            let centroid = (grid[0]
-                + grid[columns]
-                + grid[rows * column_points]
-                + grid[columns + rows * column_points])
-                / 4.0;
-
-            // The following code approximates a parallelogram from an arbitrary quadrilateral.
+// Deformers are applied in single precision normally, and double precision for
+// [PuppetFrameData::set_high_precision](crate::puppet::PuppetFrameData::set_high_precision).
+macro_rules! warp_deformer {
+    ($name:ident, $vec:ident, $float:ty, $bilinear:ident, $triangular:ident) => {
+        pub fn $name(
+            grid: Grid2D<$vec>,
+            is_new_deformer: bool,
+            extrapolation: WarpExtrapolation,
+            points_to_transform: &mut [$vec],
+        ) {
+            // `columns` here is the number of columns in the deformer, which is defined
+            // by `columns + 1` points
             //
-            // This was determined via educated guess, so I'm unsure if this is correct.
-            // Research online states that only the 4 corners of the deformer affect this,
-            // in particular, this appears to match Live2D behavior for when the top left
-            // and top right corners are inverted.
+            // | 1 | 2 | ... | columns - 1 | columns |
             //
-            // Calculate the diagonals of the quadrilateral
-            let diagonal_one = grid[columns + rows * column_points] - grid[0];
-            let diagonal_two = grid[columns] - grid[rows * column_points];
-
-            // Calculate the approximate parallelogram (vectors) of the quadrilateral.
-            let v_x: Vec2 = (diagonal_one + diagonal_two) / 2.0;
-            let v_y = (diagonal_one - diagonal_two) / 2.0;
-
-            // Move from the centroid to the new origin of the paralleogram
-            let origin = centroid - diagonal_one * 0.5;
-
-            let is_transition =
-                point.x >= -2.0 && point.x <= 3.0 && point.y >= -2.0 && point.y <= 3.0;
-            if is_transition {
-                // These don't appear to change interpolation mode between old and new,
-                // so I'm guessing that they remain the older barycentric interpolation.
-                // Not sure why, but I guess this is a rarer case anyways.
-                let res = match calc_case_index(point) {
-                    // Let's handle the side cases first
-                    7 => {
-                        let adjusted_grid_x = grid_x.min(columns - 1);
-                        let first_f = adjusted_grid_x as f32 / columns as f32;
-                        let second_f = (adjusted_grid_x + 1) as f32 / columns as f32;
-
-                        triangular_interp(
-                            vec2(
-                                point_grid.x - adjusted_grid_x as f32,
-                                rescale(point.y, 1.0, 3.0),
-                            ),
-                            grid[adjusted_grid_x + rows * column_points],
-                            grid[adjusted_grid_x + 1 + rows * column_points],
-                            origin + (v_x * first_f) + (v_y * 3.0),
-                            origin + (v_x * second_f) + (v_y * 3.0),
-                        )
-                    }
-                    1 => {
-                        let adjusted_grid_x = grid_x.min(columns - 1);
-                        let first_f = adjusted_grid_x as f32 / columns as f32;
-                        let second_f = (adjusted_grid_x + 1) as f32 / columns as f32;
-
-                        triangular_interp(
-                            vec2(
-                                point_grid.x - adjusted_grid_x as f32,
-                                rescale(point.y, -2.0, 0.0),
-                            ),
-                            origin + (v_x * first_f) + (v_y * -2.0),
-                            origin + (v_x * second_f) + (v_y * -2.0),
-                            grid[adjusted_grid_x],
-                            grid[adjusted_grid_x + 1],
-                        )
+            // Points are rescaled by this to be within ([0, columns], [0, rows]) for
+            // future indexing work.
+            let scale = $vec::new(grid.columns() as $float, grid.rows() as $float);
+            // Only worked out once there's a point outside of the deformer.
+            let mut parallelogram = None;
+
+            // Points are sorted into the ones directly inside the deformer and the rest a
+            // chunk at a time, so each kind gets a tight loop of its own. Art meshes usually
+            // sit entirely inside their deformer, which leaves nothing to branch on at all.
+            for chunk in points_to_transform.chunks_mut(u64::BITS as usize) {
+                let mut inside = 0u64;
+                for (i, point) in chunk.iter().enumerate() {
+                    let is_normal =
+                        point.x >= 0.0 && point.x < 1.0 && point.y >= 0.0 && point.y < 1.0;
+                    inside |= (is_normal as u64) << i;
+                }
+
+                // It looks like the format started out with the barycenter interpolation,
+                // and then later switched to regular bilinear.
+                let everything = u64::MAX >> (u64::BITS as usize - chunk.len());
+                if inside == everything {
+                    if is_new_deformer {
+                        interior(&grid, scale, chunk.iter_mut(), $bilinear);
+                    } else {
+                        interior(&grid, scale, chunk.iter_mut(), $triangular);
                     }
-                    3 => {
-                        let adjusted_grid_y = grid_y.min(rows - 1);
-                        let first_f = adjusted_grid_y as f32 / rows as f32;
-                        let second_f = (adjusted_grid_y + 1) as f32 / rows as f32;
-
-                        triangular_interp(
-                            vec2(
-                                rescale(point.x, -2.0, 0.0),
-                                point_grid.y - adjusted_grid_y as f32,
-                            ),
-                            origin + (v_x * -2.0) + (v_y * first_f),
-                            grid[adjusted_grid_y * column_points],
-                            origin + (v_x * -2.0) + (v_y * second_f),
-                            grid[(adjusted_grid_y + 1) * column_points],
-                        )
+                    continue;
+                }
+
+                let picked = |bits: u64| move |(i, _): &(usize, &mut $vec)| bits & (1 << i) != 0;
+                let points = chunk.iter_mut().enumerate().filter(picked(inside));
+                let points = points.map(|(_, point)| point);
+                if is_new_deformer {
+                    interior(&grid, scale, points, $bilinear);
+                } else {
+                    interior(&grid, scale, points, $triangular);
+                }
+
+                let outside = chunk.iter_mut().enumerate().filter(picked(!inside));
+                let outside = outside.map(|(_, point)| point);
+                let linear = extrapolation == WarpExtrapolation::LinearExtrapolate;
+                match extrapolation {
+                    WarpExtrapolation::CubismApprox => {
+                        let parallelogram =
+                            *parallelogram.get_or_insert_with(|| parallelogram_of(&grid));
+                        for point in outside {
+                            *point = exterior(&grid, scale, parallelogram, *point);
+                        }
                     }
-                    5 => {
-                        let adjusted_grid_y = grid_y.min(rows - 1);
-                        let first_f = adjusted_grid_y as f32 / rows as f32;
-                        let second_f = (adjusted_grid_y + 1) as f32 / rows as f32;
-
-                        triangular_interp(
-                            vec2(
-                                rescale(point.x, 1.0, 3.0),
-                                point_grid.y - adjusted_grid_y as f32,
-                            ),
-                            grid[columns + adjusted_grid_y * column_points],
-                            origin + (v_x * 3.0) + (v_y * first_f),
-                            grid[columns + (adjusted_grid_y + 1) * column_points],
-                            origin + (v_x * 3.0) + (v_y * second_f),
-                        )
+                    _ if is_new_deformer => beyond_edge(&grid, scale, outside, linear, $bilinear),
+                    _ => beyond_edge(&grid, scale, outside, linear, $triangular),
+                }
+            }
+
+            // The simple case, interpolating within the cell each point is in.
+            fn interior<'a>(
+                grid: &Grid2D<$vec>,
+                scale: $vec,
+                points: impl Iterator<Item = &'a mut $vec>,
+                interpolate: impl Fn($vec, $vec, $vec, $vec, $vec) -> $vec,
+            ) {
+                for point in points {
+                    let point_grid = *point * scale;
+                    // Trunced down, so this is the bottom-left corner of the cell. Points
+                    // inside are never negative, so what's left over is the same as
+                    // `fract`, without its call to floor.
+                    let (x, y) = (point_grid.x as usize, point_grid.y as usize);
+                    let [bottom_left, bottom_right, top_left, top_right] = grid.cell(x, y);
+                    *point = interpolate(
+                        point_grid - $vec::new(x as $float, y as $float),
+                        bottom_left,
+                        bottom_right,
+                        top_left,
+                        top_right,
+                    );
+                }
+            }
+
+            // Interpolates at the closest spot on the edge of the grid, and for linear
+            // extrapolation carries on from there with the slope the edge cell has. The cell's
+            // own interpolation is used either way, so points meet up with the inside smoothly.
+            fn beyond_edge<'a>(
+                grid: &Grid2D<$vec>,
+                scale: $vec,
+                points: impl Iterator<Item = &'a mut $vec>,
+                linear: bool,
+                interpolate: impl Fn($vec, $vec, $vec, $vec, $vec) -> $vec,
+            ) {
+                for point in points {
+                    let edge = point.clamp($vec::ZERO, $vec::ONE) * scale;
+                    let x = (edge.x as usize).min(grid.columns() - 1);
+                    let y = (edge.y as usize).min(grid.rows() - 1);
+                    let [bottom_left, bottom_right, top_left, top_right] = grid.cell(x, y);
+                    let at = |t| interpolate(t, bottom_left, bottom_right, top_left, top_right);
+
+                    let t = edge - $vec::new(x as $float, y as $float);
+                    let on_edge = at(t);
+                    if !linear {
+                        *point = on_edge;
+                        continue;
                     }
 
-                    // Now let's do the corner cases
-                    6 => triangular_interp(
-                        vec2(rescale(point.x, -2.0, 0.0), rescale(point.y, 1.0, 3.0)),
-                        origin + (v_x * -2.0) + (v_y * 1.0),
-                        grid[rows * column_points],
-                        origin + (v_x * -2.0) + (v_y * 3.0),
-                        origin + (v_x * 0.0) + (v_y * 3.0),
-                    ),
-                    8 => triangular_interp(
-                        vec2(rescale(point.x, 1.0, 3.0), rescale(point.y, 1.0, 3.0)),
-                        grid[columns + rows * column_points],
-                        origin + (v_x * 3.0) + (v_y * 1.0),
-                        origin + (v_x * 1.0) + (v_y * 3.0),
-                        origin + (v_x * 3.0) + (v_y * 3.0),
-                    ),
-                    0 => triangular_interp(
-                        vec2(rescale(point.x, -2.0, 0.0), rescale(point.y, -2.0, 0.0)),
-                        origin + (v_x * -2.0) + (v_y * -2.0),
-                        origin + (v_x * 0.0) + (v_y * -2.0),
-                        origin + (v_x * -2.0) + (v_y * 0.0),
-                        grid[0],
-                    ),
-                    2 => triangular_interp(
-                        vec2(rescale(point.x, 1.0, 3.0), rescale(point.y, -2.0, 0.0)),
-                        origin + (v_x * 1.0) + (v_y * -2.0),
-                        origin + (v_x * 3.0) + (v_y * -2.0),
-                        grid[columns],
-                        origin + (v_x * 3.0) + (v_y * 0.0),
-                    ),
-
-                    // 4 (and everything else) is unreachable
-                    _ => unreachable!(),
-                };
-
-                *point_ref = res;
-            } else {
-                // Simple extrapolation case
-                *point_ref = origin + Vec2::splat(point.x) * v_x + Vec2::splat(point.y) * v_y;
+                    // Each axis is carried on by itself, or bilinear cells would pick up a
+                    // cross term past the corners and grow quadratically there.
+                    let past = *point * scale - edge;
+                    *point = on_edge
+                        + (at(t + $vec::new(past.x, 0.0)) - on_edge)
+                        + (at(t + $vec::new(0.0, past.y)) - on_edge);
+                }
+            }
+
+            // Oh boy. This is fun. Basically the mesh turns into parallelograms at the exteremes,
+            // and in the transition zone it gets interpolated between the original shape and the
+            // extreme parallelogram.
+            //
+            // Returns the origin of the parallelogram, and the vectors along its sides.
+            fn parallelogram_of(grid: &Grid2D<$vec>) -> ($vec, $vec, $vec) {
+                let [bottom_left, bottom_right, top_left, top_right] = grid.corners();
+                let centroid = (bottom_left + bottom_right + top_left + top_right) / 4.0;
+
+                // The following code approximates a parallelogram from an arbitrary quadrilateral.
+                //
+                // This was determined via educated guess, so I'm unsure if this is correct.
+                // Research online states that only the 4 corners of the deformer affect this,
+                // in particular, this appears to match Live2D behavior for when the top left
+                // and top right corners are inverted.
+                //
+                // Calculate the diagonals of the quadrilateral
+                let diagonal_one = top_right - bottom_left;
+                let diagonal_two = bottom_right - top_left;
+
+                // Calculate the approximate parallelogram (vectors) of the quadrilateral.
+                let v_x: $vec = (diagonal_one + diagonal_two) / 2.0;
+                let v_y = (diagonal_one - diagonal_two) / 2.0;
+
+                // Move from the centroid to the new origin of the paralleogram
+                let origin = centroid - diagonal_one * 0.5;
+                (origin, v_x, v_y)
+            }
+
+            fn exterior(
+                grid: &Grid2D<$vec>,
+                scale: $vec,
+                (origin, v_x, v_y): ($vec, $vec, $vec),
+                point: $vec,
+            ) -> $vec {
+                let (rows, columns) = (grid.rows(), grid.columns());
+                let point_grid = point * scale;
+                let grid_x = point_grid.x as usize;
+                let grid_y = point_grid.y as usize;
+                let [bottom_left, bottom_right, top_left, top_right] = grid.corners();
+
+                let is_transition =
+                    point.x >= -2.0 && point.x <= 3.0 && point.y >= -2.0 && point.y <= 3.0;
+                if is_transition {
+                    // These don't appear to change interpolation mode between old and new,
+                    // so I'm guessing that they remain the older barycentric interpolation.
+                    // Not sure why, but I guess this is a rarer case anyways.
+                    match calc_case_index(point) {
+                        // Let's handle the side cases first
+                        7 => {
+                            let adjusted_grid_x = grid_x.min(columns - 1);
+                            let first_f = adjusted_grid_x as $float / columns as $float;
+                            let second_f = (adjusted_grid_x + 1) as $float / columns as $float;
+
+                            $triangular(
+                                $vec::new(
+                                    point_grid.x - adjusted_grid_x as $float,
+                                    rescale(point.y, 1.0, 3.0),
+                                ),
+                                grid.at(adjusted_grid_x, rows),
+                                grid.at(adjusted_grid_x + 1, rows),
+                                origin + (v_x * first_f) + (v_y * 3.0),
+                                origin + (v_x * second_f) + (v_y * 3.0),
+                            )
+                        }
+                        1 => {
+                            let adjusted_grid_x = grid_x.min(columns - 1);
+                            let first_f = adjusted_grid_x as $float / columns as $float;
+                            let second_f = (adjusted_grid_x + 1) as $float / columns as $float;
+
+                            $triangular(
+                                $vec::new(
+                                    point_grid.x - adjusted_grid_x as $float,
+                                    rescale(point.y, -2.0, 0.0),
+                                ),
+                                origin + (v_x * first_f) + (v_y * -2.0),
+                                origin + (v_x * second_f) + (v_y * -2.0),
+                                grid.at(adjusted_grid_x, 0),
+                                grid.at(adjusted_grid_x + 1, 0),
+                            )
+                        }
+                        3 => {
+                            let adjusted_grid_y = grid_y.min(rows - 1);
+                            let first_f = adjusted_grid_y as $float / rows as $float;
+                            let second_f = (adjusted_grid_y + 1) as $float / rows as $float;
+
+                            $triangular(
+                                $vec::new(
+                                    rescale(point.x, -2.0, 0.0),
+                                    point_grid.y - adjusted_grid_y as $float,
+                                ),
+                                origin + (v_x * -2.0) + (v_y * first_f),
+                                grid.at(0, adjusted_grid_y),
+                                origin + (v_x * -2.0) + (v_y * second_f),
+                                grid.at(0, adjusted_grid_y + 1),
+                            )
+                        }
+                        5 => {
+                            let adjusted_grid_y = grid_y.min(rows - 1);
+                            let first_f = adjusted_grid_y as $float / rows as $float;
+                            let second_f = (adjusted_grid_y + 1) as $float / rows as $float;
+
+                            $triangular(
+                                $vec::new(
+                                    rescale(point.x, 1.0, 3.0),
+                                    point_grid.y - adjusted_grid_y as $float,
+                                ),
+                                grid.at(columns, adjusted_grid_y),
+                                origin + (v_x * 3.0) + (v_y * first_f),
+                                grid.at(columns, adjusted_grid_y + 1),
+                                origin + (v_x * 3.0) + (v_y * second_f),
+                            )
+                        }
+
+                        // Now let's do the corner cases
+                        6 => $triangular(
+                            $vec::new(rescale(point.x, -2.0, 0.0), rescale(point.y, 1.0, 3.0)),
+                            origin + (v_x * -2.0) + (v_y * 1.0),
+                            top_left,
+                            origin + (v_x * -2.0) + (v_y * 3.0),
+                            origin + (v_x * 0.0) + (v_y * 3.0),
+                        ),
+                        8 => $triangular(
+                            $vec::new(rescale(point.x, 1.0, 3.0), rescale(point.y, 1.0, 3.0)),
+                            top_right,
+                            origin + (v_x * 3.0) + (v_y * 1.0),
+                            origin + (v_x * 1.0) + (v_y * 3.0),
+                            origin + (v_x * 3.0) + (v_y * 3.0),
+                        ),
+                        0 => $triangular(
+                            $vec::new(rescale(point.x, -2.0, 0.0), rescale(point.y, -2.0, 0.0)),
+                            origin + (v_x * -2.0) + (v_y * -2.0),
+                            origin + (v_x * 0.0) + (v_y * -2.0),
+                            origin + (v_x * -2.0) + (v_y * 0.0),
+                            bottom_left,
+                        ),
+                        2 => $triangular(
+                            $vec::new(rescale(point.x, 1.0, 3.0), rescale(point.y, -2.0, 0.0)),
+                            origin + (v_x * 1.0) + (v_y * -2.0),
+                            origin + (v_x * 3.0) + (v_y * -2.0),
+                            bottom_right,
+                            origin + (v_x * 3.0) + (v_y * 0.0),
+                        ),
+
+                        // 4 (and everything else) is unreachable
+                        _ => unreachable!(),
+                    }
+                } else {
+                    // Simple extrapolation case
+                    origin + $vec::splat(point.x) * v_x + $vec::splat(point.y) * v_y
+                }
             }
         }
-    }
+    };
 }
 
+warp_deformer!(
+    apply_warp_deformer,
+    Vec2,
+    f32,
+    bilinear_interp,
+    triangular_interp
+);
+warp_deformer!(
+    apply_warp_deformer_f64,
+    DVec2,
+    f64,
+    bilinear_interp_f64,
+    triangular_interp_f64
+);
+
 #[cfg(test)]
 mod tests {
+    use glam::vec2;
+
     use super::*;
 
+    #[test]
+    fn grids_need_every_point() {
+        let points = [Vec2::ZERO; 6];
+        assert!(Grid2D::new(&points, 1, 2).is_some());
+        assert!(Grid2D::new(&points, 2, 2).is_none());
+        assert!(Grid2D::new(&points[..0], 0, 0).is_none());
+
+        let points: Vec<u32> = (0..6).collect();
+        let grid = Grid2D::new(&points, 1, 2).unwrap();
+        assert_eq!(grid.get(2, 1), Some(5));
+        assert_eq!(grid.get(3, 0), None);
+        assert_eq!(grid.cell(1, 0), [1, 2, 4, 5]);
+        assert_eq!(grid.corners(), [0, 2, 3, 5]);
+    }
+
+    #[test]
+    fn batches_match_single_points() {
+        // A slightly skewed 3x2 grid, so every case comes out differently.
+        let points: Vec<Vec2> = (0..12)
+            .map(|i| vec2((i % 4) as f32 + (i / 4) as f32 * 0.3, (i / 4) as f32))
+            .collect();
+        let grid = Grid2D::new(&points, 2, 3).unwrap();
+
+        // More than a chunk's worth, from well outside the grid to well inside it.
+        let batch: Vec<Vec2> = (0..150)
+            .map(|i| vec2((i % 15) as f32 * 0.5 - 3.0, (i / 15) as f32 * 0.7 - 2.5))
+            .collect();
+        let policies = [
+            WarpExtrapolation::CubismApprox,
+            WarpExtrapolation::ClampToEdge,
+            WarpExtrapolation::LinearExtrapolate,
+        ];
+        for (is_new_deformer, extrapolation) in [false, true]
+            .into_iter()
+            .flat_map(|is_new_deformer| policies.map(|policy| (is_new_deformer, policy)))
+        {
+            let mut together = batch.clone();
+            apply_warp_deformer(grid, is_new_deformer, extrapolation, &mut together);
+            for (point, expected) in batch.iter().zip(together) {
+                let mut single = [*point];
+                apply_warp_deformer(grid, is_new_deformer, extrapolation, &mut single);
+                assert_eq!(single[0], expected, "{point}");
+            }
+        }
+    }
+
+    #[test]
+    fn extrapolation_policies() {
+        // A plain grid stretched to twice the size, which linear extrapolation carries on
+        // forever, and which clamping stops at.
+        let points: Vec<Vec2> = (0..12)
+            .map(|i| vec2((i % 4) as f32 / 3.0, (i / 4) as f32 / 2.0) * 2.0)
+            .collect();
+        let grid = Grid2D::new(&points, 2, 3).unwrap();
+        let outside = [
+            vec2(-4.0, 0.5),
+            vec2(1.5, -0.25),
+            vec2(7.0, 9.0),
+            vec2(-1.0, 2.0),
+        ];
+
+        for is_new_deformer in [false, true] {
+            let mut linear = outside;
+            apply_warp_deformer(
+                grid,
+                is_new_deformer,
+                WarpExtrapolation::LinearExtrapolate,
+                &mut linear,
+            );
+            let mut clamped = outside;
+            apply_warp_deformer(
+                grid,
+                is_new_deformer,
+                WarpExtrapolation::ClampToEdge,
+                &mut clamped,
+            );
+
+            for ((point, linear), clamped) in outside.iter().zip(linear).zip(clamped) {
+                assert!(
+                    linear.abs_diff_eq(*point * 2.0, 1e-5),
+                    "{point} -> {linear}"
+                );
+                let edge = point.clamp(Vec2::ZERO, Vec2::ONE) * 2.0;
+                assert!(clamped.abs_diff_eq(edge, 1e-5), "{point} -> {clamped}");
+            }
+        }
+
+        // On a skewed grid, points just outside the edge land right next to the ones just
+        // inside it, unlike with Cubism's parallelogram.
+        let points: Vec<Vec2> = (0..12)
+            .map(|i| {
+                vec2(
+                    (i % 4) as f32 + (i / 4) as f32 * 0.3,
+                    ((i / 4) * (i % 4)) as f32,
+                )
+            })
+            .collect();
+        let grid = Grid2D::new(&points, 2, 3).unwrap();
+        for policy in [
+            WarpExtrapolation::ClampToEdge,
+            WarpExtrapolation::LinearExtrapolate,
+        ] {
+            for edge in [
+                vec2(0.0, 0.3),
+                vec2(0.999, 0.7),
+                vec2(0.4, 0.0),
+                vec2(0.6, 0.999),
+            ] {
+                let nudge = (edge - Vec2::splat(0.5)).signum() * 1e-3;
+                let mut points = [edge, edge + nudge];
+                apply_warp_deformer(grid, true, policy, &mut points);
+                assert!(points[0].distance(points[1]) < 0.05, "{policy:?} at {edge}");
+            }
+        }
+    }
+
     #[test]
     fn test_case_index() {
         assert_eq!(calc_case_index(vec2(-2.0, 3.0)), 6);
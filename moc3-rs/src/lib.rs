@@ -1,8 +1,10 @@
+#![forbid(unsafe_code)]
+
 use std::io::Cursor;
 
 use binrw::BinReaderExt;
-use data::Moc3Data;
-use puppet::{puppet_from_moc3, Puppet};
+use data::{LayoutError, Moc3Data, TriangleCountError, Version, WarpGridError};
+use puppet::{puppet_from_moc3, puppet_ref_from_bytes, Puppet, PuppetRef};
 use thiserror::Error;
 
 pub mod data;
@@ -11,11 +13,94 @@ mod math;
 pub mod puppet;
 
 #[derive(Error, Debug)]
-#[error("could not parse moc3")]
-pub struct ParseError;
+pub enum ParseError {
+    #[error("could not parse moc3")]
+    Format,
+    /// The file reads fine, but its sections point outside of each other.
+    #[error("could not parse moc3: {0}")]
+    Layout(#[from] LayoutError),
+    #[error("could not parse moc3: {0}")]
+    WarpGrid(#[from] WarpGridError),
+    #[error("could not parse moc3: {0}")]
+    TriangleCount(#[from] TriangleCountError),
+}
 
-pub fn parse_puppet(bytes: &[u8]) -> Result<Puppet, ParseError> {
+/// What [parse_puppet_best_effort] had to leave out of a moc3 newer than this crate
+/// supports.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct SkippedFeatures {
+    /// The file's version byte, if it isn't a version this crate knows.
+    pub unknown_version: Option<u8>,
+    /// Where every section the file's table points at past the ones this crate reads
+    /// starts, which is usually whatever newer features the model uses.
+    pub unknown_sections: Vec<usize>,
+}
+
+impl SkippedFeatures {
+    pub fn is_empty(&self) -> bool {
+        self.unknown_version.is_none() && self.unknown_sections.is_empty()
+    }
+}
+
+// Gives where the section offset table stops being read, too.
+fn read_moc3(bytes: &[u8]) -> Result<(Moc3Data, usize), ParseError> {
     let mut cursor = Cursor::new(bytes);
-    let read: Moc3Data = cursor.read_le().map_err(|_| ParseError)?;
-    Ok(puppet_from_moc3(&read))
+    let read: Moc3Data = cursor.read_le().map_err(|_| ParseError::Format)?;
+    Ok((read, cursor.position() as usize))
+}
+
+fn read_known_moc3(bytes: &[u8]) -> Result<Moc3Data, ParseError> {
+    let (read, _) = read_moc3(bytes)?;
+    if !read.header.version.is_known() {
+        return Err(ParseError::Format);
+    }
+    Ok(read)
+}
+
+/// Parses a moc3 into a puppet. Files newer than [Version::LATEST] are rejected, see
+/// [parse_puppet_best_effort] for loading them anyway.
+pub fn parse_puppet(bytes: &[u8]) -> Result<Puppet, ParseError> {
+    let read = read_known_moc3(bytes)?;
+    puppet_from_moc3(&read)
+}
+
+/// Like [parse_puppet], but the puppet borrows its keyform positions (usually most of the
+/// model) straight from `bytes` instead of copying them. Passing a memory-mapped moc3 keeps
+/// those positions paged in from disk as needed.
+pub fn parse_puppet_ref(bytes: &[u8]) -> Result<PuppetRef<'_>, ParseError> {
+    let read = read_known_moc3(bytes)?;
+    puppet_ref_from_bytes(&read, bytes)
+}
+
+/// Like [parse_puppet], but models from newer versions of Cubism are read as if they were
+/// [Version::LATEST], leaving out anything added since. Those models usually still load and
+/// move, just without whatever newer features they use, which are listed in what's
+/// returned alongside the puppet.
+pub fn parse_puppet_best_effort(bytes: &[u8]) -> Result<(Puppet, SkippedFeatures), ParseError> {
+    let (read, table_end) = read_moc3(bytes)?;
+    let mut skipped = SkippedFeatures::default();
+    if let Version::Unknown(version) = read.header.version {
+        skipped.unknown_version = Some(version);
+    }
+
+    // Newer versions add their sections to the end of the table, and the table is followed
+    // by padding up until the first section, so anything set in between points at
+    // something this crate doesn't read.
+    let first_section = read
+        .sections()
+        .iter()
+        .map(|x| x.offset)
+        .filter(|x| *x >= table_end)
+        .min()
+        .unwrap_or(bytes.len())
+        .min(bytes.len());
+    if let Some(rest) = bytes.get(table_end..first_section) {
+        skipped.unknown_sections = rest
+            .chunks_exact(4)
+            .map(|x| u32::from_le_bytes([x[0], x[1], x[2], x[3]]) as usize)
+            .filter(|x| (table_end..bytes.len()).contains(x))
+            .collect();
+    }
+
+    Ok((puppet_from_moc3(&read)?, skipped))
 }
@@ -1,34 +1,43 @@
-use glam::Vec2;
+use glam::{DVec2, Vec2};
 
-/// Traditional bilinear interpolation
-pub fn bilinear_interp(
-    t: Vec2,
-    bottom_left: Vec2,
-    bottom_right: Vec2,
-    top_left: Vec2,
-    top_right: Vec2,
-) -> Vec2 {
-    let neg = Vec2::ONE - t;
+// The same interpolations are needed in single and double precision, see
+// [PuppetFrameData::set_high_precision](crate::puppet::PuppetFrameData::set_high_precision).
+macro_rules! interps {
+    ($vec:ident, $bilinear:ident, $triangular:ident) => {
+        /// Traditional bilinear interpolation
+        pub fn $bilinear(
+            t: $vec,
+            bottom_left: $vec,
+            bottom_right: $vec,
+            top_left: $vec,
+            top_right: $vec,
+        ) -> $vec {
+            let neg = $vec::ONE - t;
 
-    bottom_left * neg.x * neg.y
-        + bottom_right * t.x * neg.y
-        + top_left * neg.x * t.y
-        + top_right * t.x * t.y
-}
+            bottom_left * neg.x * neg.y
+                + bottom_right * t.x * neg.y
+                + top_left * neg.x * t.y
+                + top_right * t.x * t.y
+        }
 
-/// Barycentric triangular interpolation
-pub fn triangular_interp(
-    t: Vec2,
-    bottom_left: Vec2,
-    bottom_right: Vec2,
-    top_left: Vec2,
-    top_right: Vec2,
-) -> Vec2 {
-    let neg = Vec2::ONE - t;
+        /// Barycentric triangular interpolation
+        pub fn $triangular(
+            t: $vec,
+            bottom_left: $vec,
+            bottom_right: $vec,
+            top_left: $vec,
+            top_right: $vec,
+        ) -> $vec {
+            let neg = $vec::ONE - t;
 
-    if t.x + t.y > 1.0 {
-        top_right + (top_left - top_right) * neg.x + (bottom_right - top_right) * neg.y
-    } else {
-        bottom_left + (bottom_right - bottom_left) * t.x + (top_left - bottom_left) * t.y
-    }
+            if t.x + t.y > 1.0 {
+                top_right + (top_left - top_right) * neg.x + (bottom_right - top_right) * neg.y
+            } else {
+                bottom_left + (bottom_right - bottom_left) * t.x + (top_left - bottom_left) * t.y
+            }
+        }
+    };
 }
+
+interps!(Vec2, bilinear_interp, triangular_interp);
+interps!(DVec2, bilinear_interp_f64, triangular_interp_f64);
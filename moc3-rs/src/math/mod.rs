@@ -1,6 +1,8 @@
+use std::ops::{Div, Sub};
+
 pub mod lerp;
 
 /// Rescales `t` from `[lower, upper]` to `[0, 1]`
-pub fn rescale(t: f32, lower: f32, upper: f32) -> f32 {
+pub fn rescale<T: Copy + Sub<Output = T> + Div<Output = T>>(t: T, lower: T, upper: T) -> T {
     (t - lower) / (upper - lower)
 }
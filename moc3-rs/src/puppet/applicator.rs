@@ -5,7 +5,7 @@ use glam::Vec2;
 
 use crate::{deformer::rotation_deformer::TransformData, math::rescale};
 
-use super::{BlendColor, PuppetFrameData};
+use super::{sparse::SparseKeyforms, BlendColor, KeyformWeight, PuppetFrameData};
 
 // Returns the index of the element directly less than and the index of the element directly
 // greater than the given element.
@@ -33,14 +33,21 @@ fn lower_upper_indices(slice: &[f32], elem: &f32) -> (usize, usize) {
             }
         }
         Err(index) => {
-            // We assume that an invalid value is in between the first and last element, so
-            // this subtraction will work fine.
+            // Keys are checked to cover their whole parameter when the puppet is built, and
+            // values are clamped to the parameter before getting here, except for NaN, which
+            // sorts after all of them. That lands it in the last cell rather than past it.
+            let index = index.min(slice.len() - 1);
             (index - 1, index)
         }
     }
 }
 
+// The most bindings a single keyform lookup will blend between, since each one doubles the
+// keyforms that get read.
+const MAX_BLENDED_BINDINGS: usize = 16;
+
 #[derive(Debug, Clone)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
 pub struct BlendShapeConstraints {
     pub parameter_index: usize,
     pub keys: Vec<f32>,
@@ -48,33 +55,98 @@ pub struct BlendShapeConstraints {
 }
 
 impl BlendShapeConstraints {
+    /// How much of the blend shape this lets through for the given parameters. Outside of
+    /// the constraint's keys, the weight of the closest one is used.
     pub fn process(&self, parameters: &[f32]) -> f32 {
-        let param = parameters[self.parameter_index];
+        match self.keys.len() {
+            0 => return 1.0,
+            1 => return self.weights[0],
+            _ => {}
+        }
+
+        let param =
+            parameters[self.parameter_index].clamp(self.keys[0], self.keys[self.keys.len() - 1]);
         let (lower, upper) = lower_upper_indices(&self.keys, &param);
         let scaled = rescale(param, self.keys[lower], self.keys[upper]);
 
-        let res = ((1.0 - scaled) * self.weights[lower]) + (scaled * self.weights[upper]);
-        res
+        ((1.0 - scaled) * self.weights[lower]) + (scaled * self.weights[upper])
     }
 }
 
+// Blend shapes are held back by the most restrictive of their constraints.
+fn blend_weight(constraints: &[BlendShapeConstraints], parameters: &[f32]) -> f32 {
+    constraints
+        .iter()
+        .map(|x| x.process(parameters))
+        .fold(1.0, f32::min)
+}
+
+/// A parameter and the keys it is interpolated between. Lots of applicators share the same
+/// ones, so they're only stored once on the puppet and looked up once per update.
+#[derive(Debug, Clone)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct ParamBinding {
+    pub parameter_index: usize,
+    pub keys: Vec<f32>,
+}
+
+impl ParamBinding {
+    /// Finds the keys surrounding this binding's parameter.
+    pub fn cell(&self, parameters: &[f32]) -> BindingCell {
+        // A single key has nothing to interpolate between.
+        if self.keys.len() < 2 {
+            return BindingCell {
+                lower: 0,
+                t: 0.0,
+                key_count: self.keys.len(),
+            };
+        }
+
+        let param = parameters[self.parameter_index];
+        let (lower, upper) = lower_upper_indices(&self.keys, &param);
+
+        BindingCell {
+            lower,
+            t: rescale(param, self.keys[lower], self.keys[upper]),
+            key_count: self.keys.len(),
+        }
+    }
+}
+
+/// Where a parameter falls between the keys of a [ParamBinding].
+#[derive(Debug, Clone, Copy, Default)]
+pub struct BindingCell {
+    lower: usize,
+    // How far between the lower and upper keys the parameter is.
+    t: f32,
+    key_count: usize,
+}
+
 /// A [ParamApplicator] is a type that can handle the work required
 /// to transform the puppet data given the input parameters.
 #[derive(Debug, Clone)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
 pub struct ParamApplicator {
-    pub data: Vec<(Vec<f32>, usize)>,
+    /// Indices into the puppet's [ParamBinding]s.
+    pub data: Vec<usize>,
 
     pub kind_index: u32,
     pub values: ApplicatorKind,
+    /// The keyform positions, when they've been moved out of the shared table by
+    /// [PuppetRef::compact_keyforms](super::PuppetRef::compact_keyforms).
+    pub sparse: Option<SparseKeyforms>,
     pub blend: Option<Vec<BlendShapeConstraints>>,
 }
 
 #[derive(Debug, Clone)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
 pub enum ApplicatorKind {
-    // vertexes, opacities, draw orders, (multiply, screen)
-    ArtMesh(Vec<Vec<Vec2>>, Vec<f32>, Vec<f32>, Vec<BlendColor>),
-    // vertexes, opacities, (multiply, screen)
-    WarpDeformer(Vec<Vec<Vec2>>, Vec<f32>, Vec<BlendColor>),
+    // Vertexes are stored as offsets into the puppet's shared keyform position table,
+    // instead of every keyform owning a copy.
+    // vertex starts, opacities, draw orders, (multiply, screen)
+    ArtMesh(Vec<u32>, Vec<f32>, Vec<f32>, Vec<BlendColor>),
+    // vertex starts, opacities, (multiply, screen)
+    WarpDeformer(Vec<u32>, Vec<f32>, Vec<BlendColor>),
     // (origin, scale, angle), opacities, (multiply, screen)
     RotationDeformer(Vec<TransformData>, Vec<f32>, Vec<BlendColor>),
     // intensities
@@ -83,86 +155,253 @@ pub enum ApplicatorKind {
     Part(Vec<f32>),
 }
 
+impl ApplicatorKind {
+    /// How many keyforms there are, which is the product of the key counts of every binding.
+    pub fn keyform_count(&self) -> usize {
+        match self {
+            ApplicatorKind::ArtMesh(starts, ..) | ApplicatorKind::WarpDeformer(starts, ..) => {
+                starts.len()
+            }
+            ApplicatorKind::RotationDeformer(transforms, ..) => transforms.len(),
+            ApplicatorKind::Glue(intensities) => intensities.len(),
+            ApplicatorKind::Part(draw_orders) => draw_orders.len(),
+        }
+    }
+}
+
 impl ParamApplicator {
-    // This entire thing needs to be shredded and rewritten.
-    fn do_interpolate<'a, F>(&'a self, parameters: &[f32], out: &mut [f32], get_choices: F)
+    // Multilinear interpolation between the keyforms surrounding the current parameters.
+    fn do_interpolate<'a, F>(&'a self, cells: &[BindingCell], out: &mut [f32], get_choices: F)
     where
         F: Fn(usize) -> &'a [f32],
     {
-        let data = &self.data;
-        let mut rescaled_params = [f32::NAN; 31];
-        assert!(data.len() <= 31);
+        self.add_weighted(cells, 1.0, out, get_choices);
+    }
+
+    // The same as [do_interpolate], with everything added scaled by `weight`.
+    fn add_weighted<'a, F>(
+        &'a self,
+        cells: &[BindingCell],
+        weight: f32,
+        out: &mut [f32],
+        get_choices: F,
+    ) where
+        F: Fn(usize) -> &'a [f32],
+    {
+        self.for_each_corner(cells, |index, mult| {
+            let data = get_choices(index);
+            debug_assert_eq!(data.len(), out.len());
+            let mult = mult * weight;
+            for (o, d) in out.iter_mut().zip(data) {
+                *o += d * mult;
+            }
+        });
+    }
+
+    // Calls `f` with the index and weight of every keyform blended by [do_interpolate].
+    //
+    // Only bindings whose parameter sits strictly between two keys need both of them, so the
+    // rest are folded into the base keyform and the corners are only walked for what's left.
+    // If an unusual model still has more than [MAX_BLENDED_BINDINGS] of those, the ones
+    // closest to a key get snapped to it instead of blowing up the number of corners.
+    fn for_each_corner<F>(&self, cells: &[BindingCell], mut f: F)
+    where
+        F: FnMut(usize, f32),
+    {
+        // The stride and weight of every binding that needs blending.
+        let mut blended = [(0, 0.0); MAX_BLENDED_BINDINGS];
+        let mut blended_count = 0;
 
         let mut base_index = 0;
-        {
-            let mut last_size = 1;
-            for (i, (keys, index)) in data.iter().enumerate() {
-                let (lower, upper) = lower_upper_indices(keys, &parameters[*index]);
-                rescaled_params[i] = rescale(parameters[*index], keys[lower], keys[upper]);
-
-                base_index += lower * last_size;
-                last_size *= keys.len();
+        let mut stride = 1;
+        for binding in &self.data {
+            let cell = cells[*binding];
+            base_index += cell.lower * stride;
+
+            if cell.t >= 1.0 {
+                base_index += stride;
+            } else if cell.t > 0.0 {
+                let mut entry = (stride, cell.t);
+                if blended_count < MAX_BLENDED_BINDINGS {
+                    blended[blended_count] = entry;
+                    blended_count += 1;
+                } else {
+                    // Keep whichever bindings are furthest from their keys.
+                    let distance = |t: f32| (t - 0.5).abs();
+                    let closest = blended
+                        .iter_mut()
+                        .max_by(|a, b| distance(a.1).total_cmp(&distance(b.1)))
+                        .unwrap();
+                    if distance(closest.1) > distance(entry.1) {
+                        std::mem::swap(closest, &mut entry);
+                    }
+                    if entry.1 >= 0.5 {
+                        base_index += entry.0;
+                    }
+                }
             }
+
+            stride *= cell.key_count;
         }
 
-        for num in 0..(1 << data.len()) {
+        let blended = &blended[..blended_count];
+        for corner in 0..(1usize << blended.len()) {
             let mut mult = 1.0;
             let mut index = base_index;
 
-            let mut last_size = 1;
-            for (i, (keys, _)) in data.iter().enumerate() {
-                if num & (1 << i) != 0 {
-                    index += last_size;
-                    mult *= rescaled_params[i];
+            for (i, (stride, t)) in blended.iter().enumerate() {
+                if corner & (1 << i) != 0 {
+                    index += stride;
+                    mult *= t;
                 } else {
-                    mult *= 1.0 - rescaled_params[i];
+                    mult *= 1.0 - t;
                 }
-                last_size *= keys.len();
             }
 
-            let data = get_choices(index);
-            debug_assert_eq!(data.len(), out.len());
-            for (o, d) in out.iter_mut().zip(data) {
-                *o += d * mult;
+            f(index, mult);
+        }
+    }
+
+    // Grabs one keyform's vertexes out of the shared position table.
+    fn keyform_positions<'a>(
+        positions: &'a [Vec2],
+        starts: &[u32],
+        index: usize,
+        len: usize,
+    ) -> &'a [f32] {
+        let start = starts[index] as usize;
+        cast_slice(&positions[start..start + len])
+    }
+
+    // Adds the blended keyform positions to `out`, scaled by `weight`, reading them from
+    // wherever they're stored.
+    fn add_keyforms(
+        &self,
+        positions: &[Vec2],
+        starts: &[u32],
+        cells: &[BindingCell],
+        weight: f32,
+        out: &mut [Vec2],
+    ) {
+        let Some(sparse) = &self.sparse else {
+            let len = out.len();
+            self.add_weighted(cells, weight, cast_slice_mut(out), |a| {
+                Self::keyform_positions(positions, starts, a, len)
+            });
+            return;
+        };
+
+        // Every keyform shares the base, so it only needs adding once.
+        let mut total = 0.0;
+        self.for_each_corner(cells, |index, mult| {
+            let mult = mult * weight;
+            total += mult;
+            for (point, delta) in sparse.changes(index) {
+                out[point] += delta * mult;
+            }
+        });
+        for (o, base) in out.iter_mut().zip(&sparse.base) {
+            *o += *base * total;
+        }
+    }
+
+    // One point of one of an art mesh's or warp deformer's keyforms, wherever it's stored.
+    pub(super) fn keyform_point(&self, positions: &[Vec2], keyform: usize, point: usize) -> Vec2 {
+        if let Some(sparse) = &self.sparse {
+            return sparse.position(keyform, point);
+        }
+        match &self.values {
+            ApplicatorKind::ArtMesh(starts, ..) | ApplicatorKind::WarpDeformer(starts, ..) => {
+                positions[starts[keyform] as usize + point]
+            }
+            _ => Vec2::ZERO,
+        }
+    }
+
+    // Blends keyforms into an art mesh's vertexes. Blend shapes are added on top of what's
+    // there, scaled by `weight`, while anything else replaces it. When deforming is
+    // deferred, this only notes down which keyforms to blend instead.
+    fn apply_art_mesh_keyforms(
+        &self,
+        positions: &[Vec2],
+        starts: &[u32],
+        weight: f32,
+        frame_data: &mut PuppetFrameData,
+    ) {
+        let cells = &frame_data.binding_cells;
+        let ind = self.kind_index as usize;
+        let is_blend = self.blend.is_some();
+
+        if let Some(deferred) = &mut frame_data.deferred {
+            let keyforms = deferred.keyforms_mut(ind, is_blend);
+            if weight != 0.0 {
+                self.for_each_corner(cells, |index, mult| {
+                    keyforms.push(KeyformWeight {
+                        start: starts[index],
+                        weight: mult * weight,
+                    })
+                });
+            }
+            return;
+        }
+
+        let vertexes = &mut frame_data.art_mesh_data[ind];
+        if !is_blend {
+            vertexes.fill(Vec2::ZERO);
+        } else if weight == 0.0 {
+            return;
+        }
+        self.add_keyforms(positions, starts, cells, weight, vertexes);
+    }
+
+    // Only does the part of [ParamApplicator::apply] that affects draw orders.
+    pub fn apply_draw_order(&self, frame_data: &mut PuppetFrameData) {
+        let cells = &frame_data.binding_cells;
+        let ind = self.kind_index as usize;
+        let (out, draw_orders) = match &self.values {
+            // Blend shapes don't change draw orders.
+            ApplicatorKind::ArtMesh(_, _, draw_orders, _) if self.blend.is_none() => {
+                (&mut frame_data.art_mesh_draw_orders[ind], draw_orders)
             }
+            ApplicatorKind::Part(draw_orders) => {
+                (&mut frame_data.part_draw_orders[ind], draw_orders)
+            }
+            _ => return,
+        };
+
+        let mut order = 0.0;
+        self.do_interpolate(cells, slice::from_mut(&mut order), |a| {
+            slice::from_ref(&draw_orders[a])
+        });
+
+        // Only whole draw orders matter for sorting, so smaller changes leave the render
+        // order as it was.
+        if order.round() != out.round() {
+            frame_data.render_order_stale = true;
         }
+        *out = order;
     }
 
-    pub fn apply(&self, frame_data: &mut PuppetFrameData) {
+    pub fn apply(&self, positions: &[Vec2], frame_data: &mut PuppetFrameData) {
         let parameters = &frame_data.corrected_params;
+        let cells = &frame_data.binding_cells;
         let ind = self.kind_index as usize;
         match &self.values {
-            ApplicatorKind::ArtMesh(choices, opacities, draw_orders, colors) => {
+            // Blend shapes only ever add to the vertexes of what they're on, on top of the
+            // base keyforms which always come first. Their opacities, draw orders and colors
+            // are left alone, as the editor doesn't let them change those.
+            ApplicatorKind::ArtMesh(choices, opacities, _, colors) => {
                 if let Some(constraints) = &self.blend {
-                    let mut lowest_weight: f32 = 1.0;
-
-                    for constraint in constraints {
-                        lowest_weight = lowest_weight.min(constraint.process(parameters));
-                    }
-
-                    self.do_interpolate(
-                        parameters,
-                        bytemuck::cast_slice_mut(&mut frame_data.art_mesh_data[ind]),
-                        |a| bytemuck::cast_slice(choices[a].as_slice()),
-                    );
+                    let weight = blend_weight(constraints, parameters);
+                    self.apply_art_mesh_keyforms(positions, choices, weight, frame_data);
                 } else {
-                    frame_data.art_mesh_data[ind].fill(Vec2::ZERO);
-                    self.do_interpolate(
-                        parameters,
-                        bytemuck::cast_slice_mut(&mut frame_data.art_mesh_data[ind]),
-                        |a| bytemuck::cast_slice(choices[a].as_slice()),
-                    );
-
-                    frame_data.art_mesh_draw_orders[ind] = 0.0;
-                    self.do_interpolate(
-                        parameters,
-                        slice::from_mut(&mut frame_data.art_mesh_draw_orders[ind]),
-                        |a| slice::from_ref(&draw_orders[a]),
-                    );
+                    self.apply_art_mesh_keyforms(positions, choices, 1.0, frame_data);
+                    self.apply_draw_order(frame_data);
 
+                    let cells = &frame_data.binding_cells;
                     frame_data.art_mesh_opacities[ind] = 0.0;
                     self.do_interpolate(
-                        parameters,
+                        cells,
                         slice::from_mut(&mut frame_data.art_mesh_opacities[ind]),
                         |a| slice::from_ref(&opacities[a]),
                     );
@@ -170,7 +409,7 @@ impl ParamApplicator {
                     if !colors.is_empty() {
                         frame_data.art_mesh_colors[ind] = BlendColor::ZERO;
                         self.do_interpolate(
-                            parameters,
+                            cells,
                             cast_slice_mut(slice::from_mut(&mut frame_data.art_mesh_colors[ind])),
                             |a| cast_slice(slice::from_ref(&colors[a])),
                         );
@@ -180,29 +419,17 @@ impl ParamApplicator {
                 }
             }
             ApplicatorKind::WarpDeformer(choices, opacities, colors) => {
+                let grid = &mut frame_data.warp_deformer_data[ind];
                 if let Some(constraints) = &self.blend {
-                    let mut lowest_weight: f32 = 1.0;
-
-                    for constraint in constraints {
-                        lowest_weight = lowest_weight.min(constraint.process(parameters));
-                    }
-
-                    self.do_interpolate(
-                        parameters,
-                        bytemuck::cast_slice_mut(&mut frame_data.warp_deformer_data[ind]),
-                        |a| bytemuck::cast_slice(choices[a].as_slice()),
-                    );
+                    let weight = blend_weight(constraints, parameters);
+                    self.add_keyforms(positions, choices, cells, weight, grid);
                 } else {
-                    frame_data.warp_deformer_data[ind].fill(Vec2::ZERO);
-                    self.do_interpolate(
-                        parameters,
-                        bytemuck::cast_slice_mut(&mut frame_data.warp_deformer_data[ind]),
-                        |a| bytemuck::cast_slice(choices[a].as_slice()),
-                    );
+                    grid.fill(Vec2::ZERO);
+                    self.add_keyforms(positions, choices, cells, 1.0, grid);
 
                     frame_data.warp_deformer_opacities[ind] = 0.0;
                     self.do_interpolate(
-                        parameters,
+                        cells,
                         slice::from_mut(&mut frame_data.warp_deformer_opacities[ind]),
                         |a| slice::from_ref(&opacities[a]),
                     );
@@ -210,7 +437,7 @@ impl ParamApplicator {
                     if !colors.is_empty() {
                         frame_data.warp_deformer_colors[ind] = BlendColor::ZERO;
                         self.do_interpolate(
-                            parameters,
+                            cells,
                             cast_slice_mut(slice::from_mut(
                                 &mut frame_data.warp_deformer_colors[ind],
                             )),
@@ -224,14 +451,14 @@ impl ParamApplicator {
             ApplicatorKind::RotationDeformer(choices, opacities, colors) => {
                 frame_data.rotation_deformer_data[ind] = TransformData::ZERO;
                 self.do_interpolate(
-                    parameters,
+                    cells,
                     cast_slice_mut(slice::from_mut(&mut frame_data.rotation_deformer_data[ind])),
                     |a| cast_slice(slice::from_ref(&choices[a])),
                 );
 
                 frame_data.rotation_deformer_opacities[ind] = 0.0;
                 self.do_interpolate(
-                    parameters,
+                    cells,
                     slice::from_mut(&mut frame_data.rotation_deformer_opacities[ind]),
                     |a| slice::from_ref(&opacities[a]),
                 );
@@ -239,7 +466,7 @@ impl ParamApplicator {
                 if !colors.is_empty() {
                     frame_data.rotation_deformer_colors[ind] = BlendColor::ZERO;
                     self.do_interpolate(
-                        parameters,
+                        cells,
                         cast_slice_mut(slice::from_mut(
                             &mut frame_data.rotation_deformer_colors[ind],
                         )),
@@ -252,19 +479,12 @@ impl ParamApplicator {
             ApplicatorKind::Glue(intensities) => {
                 frame_data.glue_data[ind] = 0.0;
                 self.do_interpolate(
-                    parameters,
+                    cells,
                     slice::from_mut(&mut frame_data.glue_data[ind]),
                     |a| slice::from_ref(&intensities[a]),
                 );
             }
-            ApplicatorKind::Part(draw_orders) => {
-                frame_data.part_draw_orders[ind] = 0.0;
-                self.do_interpolate(
-                    parameters,
-                    slice::from_mut(&mut frame_data.part_draw_orders[ind]),
-                    |a| slice::from_ref(&draw_orders[a]),
-                );
-            }
+            ApplicatorKind::Part(_) => self.apply_draw_order(frame_data),
         }
     }
 }
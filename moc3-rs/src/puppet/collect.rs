@@ -1,9 +1,14 @@
+use std::collections::HashMap;
+
 use glam::vec3;
 
-use super::{applicator::BlendShapeConstraints, BlendColor, ParamData};
+use super::{
+    applicator::{BlendShapeConstraints, ParamBinding},
+    BlendColor, ParamData,
+};
 
 use crate::{
-    data::{Moc3Data, ParameterType, Version},
+    data::{BlendShapeOffsets, Checked, LayoutError, Moc3Data, ParameterType, Version},
     puppet::applicator::{ApplicatorKind, ParamApplicator},
 };
 
@@ -11,260 +16,379 @@ pub fn collect_blend_shape_constraints(
     read: &Moc3Data,
     constraint_index_start: usize,
     constraint_index_count: usize,
-) -> Vec<BlendShapeConstraints> {
+) -> Result<Vec<BlendShapeConstraints>, LayoutError> {
     let blend_shape_constraints = read.table.blend_shape_constraints.as_ref().unwrap();
     let blend_shape_constraint_indices =
         read.table.blend_shape_constraint_indices.as_ref().unwrap();
     let blend_shape_constraint_values = read.table.blend_shape_constraint_values.as_ref().unwrap();
+    let param_count = read.table.count_info.parameters as usize;
 
     let mut ret = Vec::new();
 
-    for i in constraint_index_start..constraint_index_start + constraint_index_count {
-        let index =
-            blend_shape_constraint_indices.blend_shape_constraint_sources_indices[i] as usize;
-
-        let parameter_index = blend_shape_constraints.parameter_indices[index] as usize;
-        let value_start =
-            blend_shape_constraints.blend_shape_constraint_value_sources_starts[index] as usize;
-        let value_count =
-            blend_shape_constraints.blend_shape_constraint_value_sources_counts[index] as usize;
+    let indices = blend_shape_constraint_indices
+        .blend_shape_constraint_sources_indices
+        .range(
+            "blend_shape_constraint_indices.blend_shape_constraint_sources_indices",
+            constraint_index_start,
+            constraint_index_count,
+        )?;
+    for index in indices {
+        let index = *index as usize;
+
+        let parameter_index = *blend_shape_constraints
+            .parameter_indices
+            .at("blend_shape_constraints.parameter_indices", index)?
+            as usize;
+        LayoutError::check_index(
+            "blend_shape_constraints.parameter_indices",
+            parameter_index as u64,
+            param_count,
+        )?;
+        let value_start = *blend_shape_constraints
+            .blend_shape_constraint_value_sources_starts
+            .at(
+                "blend_shape_constraints.blend_shape_constraint_value_sources_starts",
+                index,
+            )? as usize;
+        let value_count = *blend_shape_constraints
+            .blend_shape_constraint_value_sources_counts
+            .at(
+                "blend_shape_constraints.blend_shape_constraint_value_sources_counts",
+                index,
+            )? as usize;
+
+        // Parameters are clamped between the first and last key, which have to be in order.
+        let keys = blend_shape_constraint_values.keys.range(
+            "blend_shape_constraint_values.keys",
+            value_start,
+            value_count,
+        )?;
+        if !keys.windows(2).all(|x| x[0] < x[1]) {
+            return Err(LayoutError::malformed(
+                "blend_shape_constraint_values.keys",
+                value_start..value_start + value_count,
+                blend_shape_constraint_values.keys.len(),
+            ));
+        }
 
         ret.push(BlendShapeConstraints {
             parameter_index,
-            keys: blend_shape_constraint_values.keys[value_start..value_start + value_count]
-                .to_owned(),
-            weights: blend_shape_constraint_values.weights[value_start..value_start + value_count]
+            keys: keys.to_owned(),
+            weights: blend_shape_constraint_values
+                .weights
+                .range(
+                    "blend_shape_constraint_values.weights",
+                    value_start,
+                    value_count,
+                )?
                 .to_owned(),
         })
     }
 
-    ret
+    Ok(ret)
 }
 
-pub fn collect_blend_shapes(
+// The keyforms of a blend shape, which come out of the regular keyform tables of whatever
+// it's for, along with what those tables are called in errors.
+struct BlendShapeKeyforms<'a> {
+    /// How many of whatever the blend shapes are for there are.
+    targets: (&'static str, usize),
+    position_starts: (&'static str, &'a [u32]),
+    opacities: (&'static str, &'a [f32]),
+    draw_orders: Option<(&'static str, &'a [f32])>,
+}
+
+// Every blend shape applicator of one kind of object.
+fn collect_blend_shapes_of(
     read: &Moc3Data,
+    bindings: &mut BindingInterner,
     blend_shape_parameter_bindings_to_parameter: &[usize],
+    blend_shapes: &BlendShapeOffsets,
+    blend_shape_count: usize,
+    keyforms: BlendShapeKeyforms,
     applicators: &mut Vec<ParamApplicator>,
-) {
-    if read.header.version < Version::V4_02 {
-        return;
-    }
-
-    let positions = read.positions();
-    let keys = read.keys();
-
+) -> Result<(), LayoutError> {
     let blend_shape_keyform_bindings = read.table.blend_shape_keyform_bindings.as_ref().unwrap();
     let blend_shape_parameter_bindings =
         read.table.blend_shape_parameter_bindings.as_ref().unwrap();
 
-    {
-        let blend_shape_art_meshes = read.table.blend_shape_art_meshes.as_ref().unwrap();
-
-        let art_meshes = &read.table.art_meshes;
-        let art_mesh_keyforms = &read.table.art_mesh_keyforms;
-
-        for i in 0..read.table.count_info.blend_shape_art_meshes {
-            let i = i as usize;
-
-            let target_index = blend_shape_art_meshes.target_indices[i] as usize;
-            let vertexes = art_meshes.vertex_counts[target_index] as usize;
-            let start =
-                blend_shape_art_meshes.blend_shape_keyform_binding_sources_starts[i] as usize;
-            let count: usize =
-                blend_shape_art_meshes.blend_shape_keyform_binding_sources_counts[i] as usize;
-
-            for a in start..start + count {
-                let param_binding_index = blend_shape_keyform_bindings
-                    .blend_shape_parameter_binding_sources_indices[a]
-                    as usize;
-                let keyform_start =
-                    blend_shape_keyform_bindings.keyform_sources_blend_shape_starts[a] as usize;
-                let keyform_count =
-                    blend_shape_keyform_bindings.keyform_sources_blend_shape_counts[a] as usize;
-
-                let mut positions_to_bind = Vec::new();
-                for keyform in keyform_start..keyform_start + keyform_count {
-                    let position_start =
-                        art_mesh_keyforms.keyform_position_sources_starts[keyform] as usize / 2;
-                    positions_to_bind
-                        .push(positions[position_start..position_start + vertexes].to_owned());
+    for i in 0..blend_shape_count {
+        let target_index = blend_shapes.target_indices[i] as usize;
+        let (what, target_count) = keyforms.targets;
+        LayoutError::check_index(what, target_index as u64, target_count)?;
+        let start = blend_shapes.blend_shape_keyform_binding_sources_starts[i] as usize;
+        let count = blend_shapes.blend_shape_keyform_binding_sources_counts[i] as usize;
+        // Checked up front, so the rest of these can be indexed with `a`.
+        blend_shape_keyform_bindings
+            .blend_shape_constraint_index_sources_counts
+            .range(
+                "blend_shape_keyform_bindings.blend_shape_constraint_index_sources_counts",
+                start,
+                count,
+            )?;
+
+        for a in start..start + count {
+            let param_binding_index = blend_shape_keyform_bindings
+                .blend_shape_parameter_binding_sources_indices[a]
+                as usize;
+            let keyform_start =
+                blend_shape_keyform_bindings.keyform_sources_blend_shape_starts[a] as usize;
+            let keyform_count =
+                blend_shape_keyform_bindings.keyform_sources_blend_shape_counts[a] as usize;
+
+            let (what, position_starts) = keyforms.position_starts;
+            let positions_to_bind = position_starts
+                .range(what, keyform_start, keyform_count)?
+                .iter()
+                .map(|x| x / 2)
+                .collect();
+            let (what, opacities) = keyforms.opacities;
+            let opacities_to_bind = opacities
+                .range(what, keyform_start, keyform_count)?
+                .to_vec();
+
+            let x = {
+                let key_starts = *blend_shape_parameter_bindings.keys_sources_starts.at(
+                    "blend_shape_parameter_bindings.keys_sources_starts",
+                    param_binding_index,
+                )? as usize;
+                let key_counts = blend_shape_parameter_bindings.keys_sources_counts
+                    [param_binding_index] as usize;
+
+                let parameter_index =
+                    blend_shape_parameter_bindings_to_parameter[param_binding_index];
+                let keys = binding_keys(read, parameter_index, key_starts, key_counts)?;
+                // A blend shape only has the one binding to lay its keyforms out over.
+                if keys.len() != keyform_count {
+                    return Err(LayoutError::malformed(
+                        "blend_shape_keyform_bindings.keyform_sources_blend_shape_counts",
+                        a..a + 1,
+                        blend_shape_keyform_bindings
+                            .keyform_sources_blend_shape_counts
+                            .len(),
+                    ));
                 }
-
-                let opacities_to_bind = art_mesh_keyforms.opacities
-                    [keyform_start..keyform_start + keyform_count]
-                    .to_vec();
-                let draw_orders_to_bind = art_mesh_keyforms.draw_orders
-                    [keyform_start..keyform_start + keyform_count]
-                    .to_vec();
-
-                let x = {
-                    let key_starts = blend_shape_parameter_bindings.keys_sources_starts
-                        [param_binding_index] as usize;
-                    let key_counts = blend_shape_parameter_bindings.keys_sources_counts
-                        [param_binding_index] as usize;
-
-                    (
-                        keys[key_starts..key_starts + key_counts].to_owned(),
-                        blend_shape_parameter_bindings_to_parameter[param_binding_index],
-                    )
-                };
-
-                let constraint_index_start = blend_shape_keyform_bindings
-                    .blend_shape_constraint_index_sources_starts[a]
-                    as usize;
-                let constraint_index_count = blend_shape_keyform_bindings
-                    .blend_shape_constraint_index_sources_counts[a]
-                    as usize;
-
-                applicators.push(ParamApplicator {
-                    kind_index: target_index as u32,
-                    values: ApplicatorKind::ArtMesh(
-                        positions_to_bind,
-                        opacities_to_bind,
-                        draw_orders_to_bind,
-                        Vec::new(),
-                    ),
-                    data: vec![x],
-                    blend: Some(collect_blend_shape_constraints(
-                        read,
-                        constraint_index_start,
-                        constraint_index_count,
-                    )),
-                });
-            }
+                bindings.intern(parameter_index, keys)
+            };
+
+            let constraint_index_start = blend_shape_keyform_bindings
+                .blend_shape_constraint_index_sources_starts[a]
+                as usize;
+            let constraint_index_count = blend_shape_keyform_bindings
+                .blend_shape_constraint_index_sources_counts[a]
+                as usize;
+
+            let values = match keyforms.draw_orders {
+                Some((what, draw_orders)) => ApplicatorKind::ArtMesh(
+                    positions_to_bind,
+                    opacities_to_bind,
+                    draw_orders
+                        .range(what, keyform_start, keyform_count)?
+                        .to_vec(),
+                    Vec::new(),
+                ),
+                None => {
+                    ApplicatorKind::WarpDeformer(positions_to_bind, opacities_to_bind, Vec::new())
+                }
+            };
+            applicators.push(ParamApplicator {
+                kind_index: target_index as u32,
+                values,
+                data: vec![x],
+                sparse: None,
+                blend: Some(collect_blend_shape_constraints(
+                    read,
+                    constraint_index_start,
+                    constraint_index_count,
+                )?),
+            });
         }
     }
 
-    {
-        let blend_shape_warp_deformers = read.table.blend_shape_warp_deformers.as_ref().unwrap();
-
-        let warp_deformers = &read.table.warp_deformers;
-        let warp_deformer_keyforms = &read.table.warp_deformer_keyforms;
-
-        for i in 0..read.table.count_info.blend_shape_warp_deformers {
-            let i = i as usize;
-
-            let target_index = blend_shape_warp_deformers.target_indices[i] as usize;
-            let vertexes = warp_deformers.vertex_counts[target_index] as usize;
-            let start =
-                blend_shape_warp_deformers.blend_shape_keyform_binding_sources_starts[i] as usize;
-            let count =
-                blend_shape_warp_deformers.blend_shape_keyform_binding_sources_counts[i] as usize;
-
-            for a in start..start + count {
-                let param_binding_index = blend_shape_keyform_bindings
-                    .blend_shape_parameter_binding_sources_indices[a]
-                    as usize;
-                let keyform_start =
-                    blend_shape_keyform_bindings.keyform_sources_blend_shape_starts[a] as usize;
-                let keyform_count =
-                    blend_shape_keyform_bindings.keyform_sources_blend_shape_counts[a] as usize;
-
-                let mut positions_to_bind = Vec::new();
-                for keyform in keyform_start..keyform_start + keyform_count {
-                    let position_start = warp_deformer_keyforms.keyform_position_sources_starts
-                        [keyform] as usize
-                        / 2;
-                    positions_to_bind
-                        .push(positions[position_start..position_start + vertexes].to_owned());
-                }
+    Ok(())
+}
 
-                let opacities_to_bind = warp_deformer_keyforms.opacities
-                    [keyform_start..keyform_start + keyform_count]
-                    .to_vec();
-
-                let x = {
-                    let key_starts = blend_shape_parameter_bindings.keys_sources_starts
-                        [param_binding_index] as usize;
-                    let key_counts = blend_shape_parameter_bindings.keys_sources_counts
-                        [param_binding_index] as usize;
-
-                    (
-                        keys[key_starts..key_starts + key_counts].to_owned(),
-                        blend_shape_parameter_bindings_to_parameter[param_binding_index],
-                    )
-                };
-
-                let constraint_index_start = blend_shape_keyform_bindings
-                    .blend_shape_constraint_index_sources_starts[a]
-                    as usize;
-                let constraint_index_count = blend_shape_keyform_bindings
-                    .blend_shape_constraint_index_sources_counts[a]
-                    as usize;
-
-                applicators.push(ParamApplicator {
-                    kind_index: target_index as u32,
-                    values: ApplicatorKind::WarpDeformer(
-                        positions_to_bind,
-                        opacities_to_bind,
-                        Vec::new(),
-                    ),
-                    data: vec![x],
-                    blend: Some(collect_blend_shape_constraints(
-                        read,
-                        constraint_index_start,
-                        constraint_index_count,
-                    )),
-                });
-            }
-        }
+pub fn collect_blend_shapes(
+    read: &Moc3Data,
+    bindings: &mut BindingInterner,
+    blend_shape_parameter_bindings_to_parameter: &[usize],
+    applicators: &mut Vec<ParamApplicator>,
+) -> Result<(), LayoutError> {
+    if read.header.version < Version::V4_02 {
+        return Ok(());
     }
+    let counts = &read.table.count_info;
+
+    let art_mesh_keyforms = &read.table.art_mesh_keyforms;
+    collect_blend_shapes_of(
+        read,
+        bindings,
+        blend_shape_parameter_bindings_to_parameter,
+        read.table.blend_shape_art_meshes.as_ref().unwrap(),
+        counts.blend_shape_art_meshes as usize,
+        BlendShapeKeyforms {
+            targets: (
+                "blend_shape_art_meshes.target_indices",
+                counts.art_meshes as usize,
+            ),
+            position_starts: (
+                "art_mesh_keyforms.keyform_position_sources_starts",
+                &art_mesh_keyforms.keyform_position_sources_starts,
+            ),
+            opacities: ("art_mesh_keyforms.opacities", &art_mesh_keyforms.opacities),
+            draw_orders: Some((
+                "art_mesh_keyforms.draw_orders",
+                &art_mesh_keyforms.draw_orders,
+            )),
+        },
+        applicators,
+    )?;
+
+    let warp_deformer_keyforms = &read.table.warp_deformer_keyforms;
+    collect_blend_shapes_of(
+        read,
+        bindings,
+        blend_shape_parameter_bindings_to_parameter,
+        read.table.blend_shape_warp_deformers.as_ref().unwrap(),
+        counts.blend_shape_warp_deformers as usize,
+        BlendShapeKeyforms {
+            targets: (
+                "blend_shape_warp_deformers.target_indices",
+                counts.warp_deformers as usize,
+            ),
+            position_starts: (
+                "warp_deformer_keyforms.keyform_position_sources_starts",
+                &warp_deformer_keyforms.keyform_position_sources_starts,
+            ),
+            opacities: (
+                "warp_deformer_keyforms.opacities",
+                &warp_deformer_keyforms.opacities,
+            ),
+            draw_orders: None,
+        },
+        applicators,
+    )
 }
 
 pub fn collect_colors_to_bind(
     read: &Moc3Data,
     colors_start: usize,
     count: usize,
-) -> Vec<BlendColor> {
-    let keyform_multiply_colors = read.table.keyform_multiply_colors.as_ref().unwrap();
-    let keyform_screen_colors = read.table.keyform_screen_colors.as_ref().unwrap();
-
-    let mut ret = Vec::with_capacity(count);
-
-    for i in colors_start..colors_start + count {
-        let multiply_color = vec3(
-            keyform_multiply_colors.red[i],
-            keyform_multiply_colors.green[i],
-            keyform_multiply_colors.blue[i],
-        );
-
-        let screen_color = vec3(
-            keyform_screen_colors.red[i],
-            keyform_screen_colors.green[i],
-            keyform_screen_colors.blue[i],
-        );
-        ret.push(BlendColor {
-            multiply_color,
-            screen_color,
-        });
+) -> Result<Vec<BlendColor>, LayoutError> {
+    let multiply = read.table.keyform_multiply_colors.as_ref().unwrap();
+    let screen = read.table.keyform_screen_colors.as_ref().unwrap();
+    let channel =
+        |what, channel: &[f32]| channel.range(what, colors_start, count).map(|x| x.to_vec());
+
+    let multiply = [
+        channel("keyform_multiply_colors.red", &multiply.red)?,
+        channel("keyform_multiply_colors.green", &multiply.green)?,
+        channel("keyform_multiply_colors.blue", &multiply.blue)?,
+    ];
+    let screen = [
+        channel("keyform_screen_colors.red", &screen.red)?,
+        channel("keyform_screen_colors.green", &screen.green)?,
+        channel("keyform_screen_colors.blue", &screen.blue)?,
+    ];
+
+    Ok((0..count)
+        .map(|i| BlendColor {
+            multiply_color: vec3(multiply[0][i], multiply[1][i], multiply[2][i]),
+            screen_color: vec3(screen[0][i], screen[1][i], screen[2][i]),
+        })
+        .collect())
+}
+
+// The keys of a parameter binding, which have to go up strictly for the keys surrounding a
+// parameter to be binary searched, and cover the whole parameter so it always falls between
+// two of them. A single key has nothing to interpolate between, so anything goes with it.
+fn binding_keys(
+    read: &Moc3Data,
+    parameter_index: usize,
+    start: usize,
+    count: usize,
+) -> Result<&[f32], LayoutError> {
+    let all_keys = read.keys();
+    let keys = all_keys.range("keys.values", start, count)?;
+    let parameters = &read.table.parameters;
+    let min = *parameters
+        .min_values
+        .at("parameters.min_values", parameter_index)?;
+    let max = parameters.max_values[parameter_index];
+
+    let sorted = keys.windows(2).all(|x| x[0] < x[1]);
+    let covered = match keys {
+        [] => false,
+        [_] => true,
+        [first, .., last] => *first <= min && *last >= max,
+    };
+    if !(sorted && covered) {
+        return Err(LayoutError::malformed(
+            "keys.values",
+            start..start + count,
+            all_keys.len(),
+        ));
+    }
+    Ok(keys)
+}
+
+// Hands out indices for parameter bindings, reusing the same one for every applicator
+// interpolating between the same keys of the same parameter.
+#[derive(Default)]
+pub struct BindingInterner {
+    pub bindings: Vec<ParamBinding>,
+    // Floats can't be hashed, so keys are compared by their bits.
+    lookup: HashMap<(usize, Vec<u32>), usize>,
+}
+
+impl BindingInterner {
+    pub fn intern(&mut self, parameter_index: usize, keys: &[f32]) -> usize {
+        let bits = keys.iter().map(|x| x.to_bits()).collect();
+        *self
+            .lookup
+            .entry((parameter_index, bits))
+            .or_insert_with(|| {
+                self.bindings.push(ParamBinding {
+                    parameter_index,
+                    keys: keys.to_vec(),
+                });
+                self.bindings.len() - 1
+            })
     }
-    ret
 }
 
 pub fn collect_parameter_bindings(
     read: &Moc3Data,
+    bindings: &mut BindingInterner,
     parameter_bindings_to_parameter: &[usize],
     parameter_bindings_start: usize,
     parameter_bindings_count: usize,
-) -> Vec<(Vec<f32>, usize)> {
+) -> Result<Vec<usize>, LayoutError> {
     let parameter_bindings = &read.table.parameter_bindings;
     let parameter_binding_indices = &read.table.parameter_binding_indices;
-    let keys = read.keys();
 
     let mut ret = Vec::new();
 
-    for i in parameter_bindings_start..parameter_bindings_start + parameter_bindings_count {
-        let ind = parameter_binding_indices.binding_sources_indices[i] as usize;
-        let key_starts = parameter_bindings.keys_sources_starts[ind] as usize;
+    let indices = parameter_binding_indices.binding_sources_indices.range(
+        "parameter_binding_indices.binding_sources_indices",
+        parameter_bindings_start,
+        parameter_bindings_count,
+    )?;
+    for ind in indices {
+        let ind = *ind as usize;
+        let key_starts = *parameter_bindings
+            .keys_sources_starts
+            .at("parameter_bindings.keys_sources_starts", ind)? as usize;
         let key_counts = parameter_bindings.keys_sources_counts[ind] as usize;
 
-        ret.push((
-            keys[key_starts..key_starts + key_counts].to_owned(),
-            parameter_bindings_to_parameter[ind],
+        let parameter_index = parameter_bindings_to_parameter[ind];
+        ret.push(bindings.intern(
+            parameter_index,
+            binding_keys(read, parameter_index, key_starts, key_counts)?,
         ))
     }
 
-    ret
+    Ok(ret)
 }
 
 pub fn collect_param_data(read: &Moc3Data) -> ParamData {
@@ -292,6 +416,22 @@ pub fn collect_param_data(read: &Moc3Data) -> ParamData {
         }
     }
 
+    // Keys out of range of the table are left out rather than guessed at.
+    let mut param_keys = vec![Vec::new(); param_count as usize];
+    if let Some(extensions) = &read.table.parameter_extensions {
+        let keys = read.keys();
+        let ranges = extensions
+            .keys_sources_starts
+            .iter()
+            .zip(extensions.keys_sources_counts.iter());
+        for (param_keys, (start, count)) in param_keys.iter_mut().zip(ranges) {
+            let range = *start as usize..*start as usize + *count as usize;
+            if let Some(keys) = keys.get(range) {
+                *param_keys = keys.to_vec();
+            }
+        }
+    }
+
     ParamData {
         count: param_count,
         ids: param_ids,
@@ -301,5 +441,6 @@ pub fn collect_param_data(read: &Moc3Data) -> ParamData {
         repeats: param_repeats,
         decimals: parameters.decimal_places.clone(),
         types: param_types,
+        keys: param_keys,
     }
 }
@@ -0,0 +1,248 @@
+use bytemuck::{Pod, Zeroable};
+use glam::Vec2;
+use thiserror::Error;
+
+use crate::deformer::rotation_deformer::{rotation_deformer_matrix, TransformData};
+
+use super::{applicator::ApplicatorKind, node::WarpDeformerData, PuppetFrameData, PuppetRef};
+
+/// Why [PuppetFrameData::set_deferred_deform] couldn't defer deforming.
+#[derive(Error, Debug, Clone, Copy, PartialEq, Eq)]
+pub enum DeferredDeformError {
+    /// The puppet's art mesh keyforms were compacted by [PuppetRef::compact_keyforms], so
+    /// there's nothing in [PuppetRef::keyform_positions] for [KeyformWeight]s to point at.
+    #[error("art meshes with compacted keyforms can't have their deforming deferred")]
+    CompactedKeyforms,
+}
+
+/// One keyform blended into an art mesh: its vertexes start at `start` in
+/// [PuppetRef::keyform_positions], and are scaled by `weight` before being added up.
+#[derive(Pod, Zeroable, Debug, Clone, Copy, PartialEq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+#[repr(C)]
+pub struct KeyformWeight {
+    pub start: u32,
+    pub weight: f32,
+}
+
+/// How an art mesh's blended vertexes are moved by the deformer it sits under.
+///
+/// Laid out so it can be copied straight into a GPU storage buffer.
+#[derive(Pod, Zeroable, Debug, Clone, Copy, PartialEq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+#[repr(C)]
+pub struct MeshParent {
+    /// One of [MeshParent::NONE], [MeshParent::ROTATION], [MeshParent::WARP] or
+    /// [MeshParent::WARP_BILINEAR].
+    pub kind: u32,
+    /// Where a warp deformer's grid starts, counting every grid in
+    /// [DeferredDeform::warp_grids] one after another.
+    pub grid_start: u32,
+    pub rows: u32,
+    pub columns: u32,
+    /// A rotation deformer's transform, as the columns of a 2D affine matrix.
+    pub transform: [Vec2; 3],
+}
+
+impl MeshParent {
+    /// The art mesh isn't under any deformer, and is used as blended.
+    pub const NONE: u32 = 0;
+    pub const ROTATION: u32 = 1;
+    /// A warp deformer interpolating within each cell of its grid by triangles, like older
+    /// models do.
+    pub const WARP: u32 = 2;
+    /// A warp deformer interpolating within each cell of its grid bilinearly.
+    pub const WARP_BILINEAR: u32 = 3;
+
+    const ROOT: Self = Self {
+        kind: Self::NONE,
+        grid_start: 0,
+        rows: 0,
+        columns: 0,
+        transform: [Vec2::X, Vec2::Y, Vec2::ZERO],
+    };
+
+    fn rotation(data: &TransformData, base_angle: f32) -> Self {
+        let matrix = rotation_deformer_matrix(data, base_angle);
+        Self {
+            kind: Self::ROTATION,
+            transform: [
+                matrix.x_axis.truncate(),
+                matrix.y_axis.truncate(),
+                matrix.z_axis.truncate(),
+            ],
+            ..Self::ROOT
+        }
+    }
+
+    fn warp(grid_start: u32, data: &WarpDeformerData) -> Self {
+        Self {
+            kind: if data.is_new_deformerr {
+                Self::WARP_BILINEAR
+            } else {
+                Self::WARP
+            },
+            grid_start,
+            rows: data.rows,
+            columns: data.columns,
+            ..Self::ROOT
+        }
+    }
+}
+
+// What the frame data keeps track of instead of art mesh vertexes, while deforming is
+// deferred.
+#[derive(Debug, Clone)]
+pub(super) struct DeferredState {
+    keyforms: Vec<Vec<KeyformWeight>>,
+    parents: Vec<MeshParent>,
+    warp_grid_starts: Vec<u32>,
+}
+
+impl DeferredState {
+    fn new(frame_data: &PuppetFrameData) -> Self {
+        let mut warp_grid_starts = Vec::with_capacity(frame_data.warp_deformer_data.len());
+        let mut start = 0;
+        for grid in &frame_data.warp_deformer_data {
+            warp_grid_starts.push(start);
+            start += grid.len() as u32;
+        }
+
+        Self {
+            keyforms: vec![Vec::new(); frame_data.art_mesh_data.len()],
+            parents: vec![MeshParent::ROOT; frame_data.art_mesh_data.len()],
+            warp_grid_starts,
+        }
+    }
+
+    // Blend shapes are added on top of whatever the base keyforms were.
+    pub(super) fn keyforms_mut(
+        &mut self,
+        art_mesh: usize,
+        is_blend: bool,
+    ) -> &mut Vec<KeyformWeight> {
+        let keyforms = &mut self.keyforms[art_mesh];
+        if !is_blend {
+            keyforms.clear();
+        }
+        keyforms
+    }
+
+    pub(super) fn set_warp_parent(
+        &mut self,
+        art_mesh: usize,
+        warp_index: usize,
+        data: &WarpDeformerData,
+    ) {
+        self.parents[art_mesh] = MeshParent::warp(self.warp_grid_starts[warp_index], data);
+    }
+
+    pub(super) fn set_rotation_parent(
+        &mut self,
+        art_mesh: usize,
+        data: &TransformData,
+        base_angle: f32,
+    ) {
+        self.parents[art_mesh] = MeshParent::rotation(data, base_angle);
+    }
+}
+
+/// The art mesh deforming left undone by [PuppetRef::update] when
+/// [PuppetFrameData::set_deferred_deform] is on, for doing on the GPU instead.
+///
+/// Each vertex ends up as the sum of its mesh's keyforms, each read from
+/// [PuppetRef::keyform_positions] at the keyform's start plus the vertex's index and scaled by
+/// its weight. That's then moved by the mesh's [MeshParent], and finally the puppet's glues
+/// are applied one after another, like [PuppetRef::glues] describes.
+#[derive(Debug, Clone, Copy)]
+pub struct DeferredDeform<'a> {
+    frame_data: &'a PuppetFrameData,
+    state: &'a DeferredState,
+}
+
+impl<'a> DeferredDeform<'a> {
+    /// The keyforms blended into an art mesh, in the order they're added up.
+    pub fn keyforms(&self, art_mesh_index: usize) -> &'a [KeyformWeight] {
+        &self.state.keyforms[art_mesh_index]
+    }
+
+    pub fn parents(&self) -> &'a [MeshParent] {
+        &self.state.parents
+    }
+
+    /// The fully deformed grid of every warp deformer.
+    pub fn warp_grids(&self) -> &'a [Vec<Vec2>] {
+        &self.frame_data.warp_deformer_data
+    }
+
+    /// The intensity of every glue, indexed by [GlueNode::kind_index](super::GlueNode).
+    pub fn glue_intensities(&self) -> &'a [f32] {
+        &self.frame_data.glue_data
+    }
+}
+
+impl PuppetFrameData {
+    /// Whether art mesh deforming is left for someone else, see
+    /// [PuppetFrameData::set_deferred_deform].
+    pub fn deferred_deform(&self) -> Option<DeferredDeform<'_>> {
+        self.deferred.as_ref().map(|state| DeferredDeform {
+            frame_data: self,
+            state,
+        })
+    }
+
+    /// Skips blending, deforming and gluing art mesh vertexes in every update, which is
+    /// usually most of the work for big models. Only which keyforms to blend and how each
+    /// mesh's parent deformer moves it are worked out, see [DeferredDeform].
+    ///
+    /// Everything else, including deformers, opacities and draw orders, is still updated
+    /// as usual. [PuppetFrameData::art_mesh_positions] is left untouched, so anything reading it
+    /// (like [PuppetRef::measure_velocities]) sees stale vertexes. A full update is needed
+    /// after turning this on or off.
+    ///
+    /// Puppets whose art mesh keyforms were compacted by [PuppetRef::compact_keyforms] can't
+    /// be deferred. If the puppet gets compacted afterwards, the next update turns deferring
+    /// back off and deforms everything itself.
+    pub fn set_deferred_deform(
+        &mut self,
+        puppet: &PuppetRef,
+        deferred: bool,
+    ) -> Result<(), DeferredDeformError> {
+        if deferred && puppet.has_compacted_art_meshes() {
+            return Err(DeferredDeformError::CompactedKeyforms);
+        }
+
+        if deferred != self.deferred.is_some() {
+            self.deferred = deferred.then(|| DeferredState::new(self));
+        }
+        Ok(())
+    }
+}
+
+impl PuppetRef<'_> {
+    fn has_compacted_art_meshes(&self) -> bool {
+        self.applicators
+            .iter()
+            .any(|x| x.sparse.is_some() && matches!(x.values, ApplicatorKind::ArtMesh(..)))
+    }
+
+    // Turns deferring off for frame data that started before the puppet was compacted,
+    // returning whether it did, in which case every art mesh needs deforming again.
+    pub(super) fn stop_deferring_if_compacted(&self, frame_data: &mut PuppetFrameData) -> bool {
+        let stop = frame_data.deferred.is_some() && self.has_compacted_art_meshes();
+        if stop {
+            frame_data.deferred = None;
+        }
+        stop
+    }
+
+    /// Every keyform's vertexes, which [KeyformWeight::start] indexes into.
+    ///
+    /// These are the raw positions from the moc3, before any deformer has moved them, so
+    /// they're relative to whatever deformer each keyform's object sits under: from 0 to 1
+    /// across a warp deformer's grid, around a rotation deformer's origin, or in model
+    /// coordinates for objects that aren't under any deformer.
+    pub fn keyform_positions(&self) -> &[Vec2] {
+        &self.keyform_positions
+    }
+}
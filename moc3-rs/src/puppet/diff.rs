@@ -0,0 +1,185 @@
+use std::collections::{HashMap, HashSet};
+
+use super::{applicator::ApplicatorKind, memory::ApplicatorTarget, PuppetRef};
+
+/// The IDs only one of two puppets has, in the order that puppet stores them.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct IdChanges {
+    pub added: Vec<String>,
+    pub removed: Vec<String>,
+}
+
+impl IdChanges {
+    fn new<'a>(before: &'a [String], after: &'a [String]) -> Self {
+        let only_in = |a: &'a [String], b: &'a [String]| {
+            let b: HashSet<&str> = b.iter().map(|x| x.as_str()).collect();
+            a.iter()
+                .filter(|x| !b.contains(x.as_str()))
+                .cloned()
+                .collect()
+        };
+        Self {
+            added: only_in(after, before),
+            removed: only_in(before, after),
+        }
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.added.is_empty() && self.removed.is_empty()
+    }
+}
+
+/// A parameter both puppets have, whose range or default moved. Values are
+/// `[min, default, max]`.
+#[derive(Debug, Clone, PartialEq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct ParameterChange {
+    pub id: String,
+    pub before: [f32; 3],
+    pub after: [f32; 3],
+}
+
+/// An object both puppets have, with a different number of keyforms. Blend shapes count
+/// towards the object they add onto.
+#[derive(Debug, Clone, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct KeyformCountChange {
+    pub target: ApplicatorTarget,
+    pub id: String,
+    pub before: usize,
+    pub after: usize,
+}
+
+/// An art mesh both puppets have, drawn from a different texture.
+#[derive(Debug, Clone, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct TextureChange {
+    pub art_mesh: String,
+    pub before: u32,
+    pub after: u32,
+}
+
+/// How one version of a model differs from another, from [PuppetRef::diff]. Objects are
+/// matched up by ID, so renaming one shows up as removing it and adding another.
+#[derive(Debug, Clone, Default, PartialEq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct PuppetDiff {
+    pub parameters: IdChanges,
+    pub art_meshes: IdChanges,
+    /// Warp and rotation deformers together.
+    pub deformers: IdChanges,
+    pub parts: IdChanges,
+    pub glues: IdChanges,
+    pub parameter_changes: Vec<ParameterChange>,
+    pub keyform_counts: Vec<KeyformCountChange>,
+    pub textures: Vec<TextureChange>,
+}
+
+impl PuppetDiff {
+    /// Whether the two puppets have the same objects, with the same parameter ranges,
+    /// keyform counts and textures. Keyforms can still have moved.
+    pub fn is_empty(&self) -> bool {
+        self.parameters.is_empty()
+            && self.art_meshes.is_empty()
+            && self.deformers.is_empty()
+            && self.parts.is_empty()
+            && self.glues.is_empty()
+            && self.parameter_changes.is_empty()
+            && self.keyform_counts.is_empty()
+            && self.textures.is_empty()
+    }
+}
+
+impl PuppetRef<'_> {
+    /// Compares this puppet against a newer version of it, for reviewing model updates or
+    /// tracking down differences between exports.
+    pub fn diff(&self, newer: &PuppetRef) -> PuppetDiff {
+        let mut diff = PuppetDiff {
+            parameters: IdChanges::new(&self.params.ids, &newer.params.ids),
+            art_meshes: IdChanges::new(self.art_mesh_ids.ids(), newer.art_mesh_ids.ids()),
+            deformers: IdChanges::new(self.deformer_ids.ids(), newer.deformer_ids.ids()),
+            parts: IdChanges::new(self.part_ids.ids(), newer.part_ids.ids()),
+            glues: IdChanges::new(self.glue_ids.ids(), newer.glue_ids.ids()),
+            ..Default::default()
+        };
+
+        let ranges = |puppet: &PuppetRef, i: usize| {
+            let params = &puppet.params;
+            [params.mins[i], params.defaults[i], params.maxes[i]]
+        };
+        for (i, id) in self.params.ids.iter().enumerate() {
+            let Some(j) = newer.params.ids.iter().position(|x| x == id) else {
+                continue;
+            };
+            let (before, after) = (ranges(self, i), ranges(newer, j));
+            if before != after {
+                diff.parameter_changes.push(ParameterChange {
+                    id: id.clone(),
+                    before,
+                    after,
+                });
+            }
+        }
+
+        let after: HashMap<_, _> = newer.keyform_counts().into_iter().collect();
+        for (key, before) in self.keyform_counts() {
+            match after.get(&key) {
+                Some(&after) if after != before => {
+                    let (target, id) = key;
+                    diff.keyform_counts.push(KeyformCountChange {
+                        target,
+                        id,
+                        before,
+                        after,
+                    });
+                }
+                _ => {}
+            }
+        }
+
+        for (i, id) in self.art_mesh_ids.ids().iter().enumerate() {
+            let before = self.art_mesh_textures.get(i);
+            let after = newer
+                .art_mesh_index(id)
+                .and_then(|x| newer.art_mesh_textures.get(x));
+            if let (Some(&before), Some(&after)) = (before, after) {
+                if before != after {
+                    diff.textures.push(TextureChange {
+                        art_mesh: id.clone(),
+                        before,
+                        after,
+                    });
+                }
+            }
+        }
+
+        diff
+    }
+
+    // Every object with keyforms and how many it has, in the order they're first applied.
+    fn keyform_counts(&self) -> Vec<((ApplicatorTarget, String), usize)> {
+        let graph = self.dependency_graph();
+        let mut counts: Vec<((ApplicatorTarget, String), usize)> = Vec::new();
+        let mut seen: HashMap<(ApplicatorTarget, String), usize> = HashMap::new();
+
+        for (applicator, dependency) in self.applicators.iter().zip(graph.applicators) {
+            let keyforms = match &applicator.values {
+                ApplicatorKind::ArtMesh(starts, ..) | ApplicatorKind::WarpDeformer(starts, ..) => {
+                    starts.len()
+                }
+                ApplicatorKind::RotationDeformer(transforms, ..) => transforms.len(),
+                ApplicatorKind::Glue(intensities) => intensities.len(),
+                ApplicatorKind::Part(draw_orders) => draw_orders.len(),
+            };
+
+            let key = (dependency.target, dependency.target_id);
+            let index = *seen.entry(key.clone()).or_insert_with(|| {
+                counts.push((key, 0));
+                counts.len() - 1
+            });
+            counts[index].1 += keyforms;
+        }
+        counts
+    }
+}
@@ -1,69 +1,106 @@
-use std::cmp::Ordering;
-
 use indextree::{Arena, NodeId};
 
-use super::PuppetFrameData;
+use super::{PuppetFrameData, PuppetRef};
 
 #[derive(Debug, Clone)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
 pub enum DrawOrderNode {
-    ArtMesh { index: u32 },
-    Part { index: u32 },
+    ArtMesh {
+        index: u32,
+    },
+    /// A part owning a draw order group, or `u32::MAX` for groups that don't belong to a
+    /// part. Its children's draw orders are kept within the group's range, when it has
+    /// one.
+    Part {
+        index: u32,
+        range: Option<[f32; 2]>,
+    },
+}
+
+// A draw order group being worked through, as a range of its sorted children in the
+// scratch space.
+#[derive(Debug, Clone, Copy)]
+pub(super) struct DrawOrderGroupCursor {
+    start: usize,
+    next: usize,
+    end: usize,
 }
 
-// This is such a hack. Processing this draw order tree requires so much allocating and pointer indirection and just mucking
-// around with data. Sigh. I think I just bite the bullet and use recursion here or something, I'm really not sure
-// how this is supposed to be done otherwise.
-//
-// I feel like I'm missing something simple but it doesn't appear like I am alas. I want to boil this down into a simple array
-// I can sort, but I feel like that loses precision somehow - a f32 only has 32 bits of precision, and each draw order group contains
-// 8 bits (0-1000), so I can nest at most 4 in a row with that implmentation.
-fn draw_order_tree_rec(
+// Adds the children of a group to the end of the scratch space, sorted by draw order.
+fn push_group(
     draw_order_nodes: &Arena<DrawOrderNode>,
-    draw_order_root: NodeId,
-    cur_index: &mut usize,
+    group: NodeId,
     frame_data: &mut PuppetFrameData,
 ) {
-    let mut orders: Vec<(f32, NodeId)> = Vec::new();
-    for i in draw_order_root.children(&draw_order_nodes) {
-        let data = draw_order_nodes[i].get();
+    let range = match draw_order_nodes[group].get() {
+        DrawOrderNode::Part { range, .. } => *range,
+        DrawOrderNode::ArtMesh { .. } => None,
+    };
 
-        match data {
-            DrawOrderNode::ArtMesh { index } => {
-                orders.push((frame_data.art_mesh_draw_orders[*index as usize].round(), i));
-            }
-            DrawOrderNode::Part { index } => {
-                orders.push((frame_data.part_draw_orders[*index as usize], i));
-            }
-        }
+    let start = frame_data.draw_order_scratch.len();
+    for i in group.children(draw_order_nodes) {
+        let order = match draw_order_nodes[i].get() {
+            DrawOrderNode::ArtMesh { index } => frame_data.art_mesh_draw_orders[*index as usize],
+            DrawOrderNode::Part { index, .. } => frame_data.part_draw_orders[*index as usize],
+        };
+        let order = match range {
+            Some([min, max]) => order.round().clamp(min, max),
+            None => order.round(),
+        };
+        frame_data.draw_order_scratch.push((order, i));
     }
-    orders.sort_unstable_by(|a, b| {
-        let first = a.0.total_cmp(&b.0);
-        if first == Ordering::Equal {
-            a.1.cmp(&b.1)
-        } else {
-            first
-        }
+
+    // Children are visited in the order they appear in their group, and the sort is stable,
+    // so ties keep that order.
+    let end = frame_data.draw_order_scratch.len();
+    frame_data.draw_order_scratch[start..end].sort_by(|a, b| a.0.total_cmp(&b.0));
+
+    frame_data.draw_order_stack.push(DrawOrderGroupCursor {
+        start,
+        next: start,
+        end,
     });
+}
 
-    for (_, id) in orders {
-        let child = draw_order_nodes[id].get();
+/// Fills in [PuppetFrameData::render_order] from the draw orders of the art meshes
+/// and parts, which [PuppetRef::update] already does as its last step when any of them
+/// changed. Use [PuppetRef::update_draw_orders] to only recalculate draw orders from new
+/// parameters.
+///
+/// This matches the official runtime: draw orders are rounded to whole numbers (as in the
+/// editor) and kept within the minimum and maximum their draw order group has in the moc3,
+/// art meshes and parts are sorted within their group with parts recursing into their own
+/// children, and ties keep the order objects are listed in the group. Models with more than
+/// one root group draw them one after another, in the order they're listed.
+pub fn compute_render_order(puppet: &PuppetRef, frame_data: &mut PuppetFrameData) {
+    let nodes = &puppet.draw_order_nodes;
+    frame_data.draw_order_scratch.clear();
+    frame_data.draw_order_stack.clear();
+    frame_data.render_order_stale = false;
 
-        match child {
-            DrawOrderNode::ArtMesh { index: part_index } => {
-                frame_data.art_mesh_render_orders[*cur_index] = *part_index;
-                *cur_index += 1;
+    // Walks the tree depth first, with the stack holding every group that's still being
+    // gone through. Nested groups are sorted into the scratch space after their parent's
+    // children, so finishing a group only needs to drop the end of it.
+    let mut cur_index = 0;
+    for root in puppet.draw_order_roots.iter().copied() {
+        push_group(nodes, root, frame_data);
+        while let Some(cursor) = frame_data.draw_order_stack.last_mut() {
+            if cursor.next == cursor.end {
+                frame_data.draw_order_scratch.truncate(cursor.start);
+                frame_data.draw_order_stack.pop();
+                continue;
             }
-            DrawOrderNode::Part { .. } => {
-                draw_order_tree_rec(draw_order_nodes, id, cur_index, frame_data);
+
+            let id = frame_data.draw_order_scratch[cursor.next].1;
+            cursor.next += 1;
+
+            match nodes[id].get() {
+                DrawOrderNode::ArtMesh { index } => {
+                    frame_data.art_mesh_render_orders[cur_index] = *index;
+                    cur_index += 1;
+                }
+                DrawOrderNode::Part { .. } => push_group(nodes, id, frame_data),
             }
         }
     }
 }
-
-pub fn draw_order_tree(
-    draw_order_nodes: &Arena<DrawOrderNode>,
-    draw_order_root: NodeId,
-    frame_data: &mut PuppetFrameData,
-) {
-    draw_order_tree_rec(draw_order_nodes, draw_order_root, &mut 0, frame_data);
-}
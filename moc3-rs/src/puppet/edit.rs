@@ -0,0 +1,232 @@
+use glam::Vec2;
+use thiserror::Error;
+
+use super::{
+    applicator::ApplicatorKind, sparse::SparseKeyforms, BlendColor, PuppetRef, TransformData,
+};
+
+/// Why a keyform couldn't be changed by [KeyformsMut].
+#[derive(Error, Debug, Clone, Copy, PartialEq, Eq)]
+pub enum KeyformEditError {
+    #[error("keyform {keyform} is out of range of {count} keyforms")]
+    KeyformOutOfRange { keyform: usize, count: usize },
+    #[error("expected {expected} positions, got {actual}")]
+    PositionCount { expected: usize, actual: usize },
+    /// The keyforms don't have the value being changed, like the opacity of a glue or of a
+    /// blend shape.
+    #[error("keyforms of this applicator don't have that value")]
+    NoSuchValue,
+}
+
+/// Changes the keyforms of a single applicator, from [PuppetRef::edit_keyforms].
+///
+/// Edits apply from the next update. Partial updates redo edited applicators along with
+/// whatever their parameters change, so a tool can keep tweaking keyforms while scrubbing
+/// sliders. Renderers that uploaded [PuppetRef::keyform_positions] for deferred deforming
+/// need to upload it again after positions change.
+#[derive(Debug)]
+pub struct KeyformsMut<'p, 'a> {
+    puppet: &'p mut PuppetRef<'a>,
+    applicator: usize,
+}
+
+impl KeyformsMut<'_, '_> {
+    fn keyform_count(&self) -> usize {
+        self.puppet.applicators[self.applicator]
+            .values
+            .keyform_count()
+    }
+
+    fn check_keyform(&self, keyform: usize) -> Result<(), KeyformEditError> {
+        let count = self.keyform_count();
+        if keyform >= count {
+            return Err(KeyformEditError::KeyformOutOfRange { keyform, count });
+        }
+        Ok(())
+    }
+
+    // Notes down that the applicator changed, for partial updates to pick up.
+    fn touch(&mut self) {
+        self.puppet.keyform_edits += 1;
+        self.puppet.applicator_edits[self.applicator] = self.puppet.keyform_edits;
+    }
+
+    /// Replaces the vertexes of an art mesh keyform or the points of a warp deformer
+    /// keyform. Blend shapes take the offsets to add instead.
+    ///
+    /// Keyforms shared with other applicators get their own copy, and borrowed puppets
+    /// copy their keyform positions the first time this is called.
+    pub fn set_positions(
+        &mut self,
+        keyform: usize,
+        positions: &[Vec2],
+    ) -> Result<(), KeyformEditError> {
+        self.check_keyform(keyform)?;
+        let edited = self.applicator;
+        let puppet = &mut *self.puppet;
+        let applicator = &mut puppet.applicators[edited];
+        let index = applicator.kind_index as usize;
+        let (starts, expected) = match &mut applicator.values {
+            ApplicatorKind::ArtMesh(starts, ..) => {
+                (starts, puppet.art_mesh_vertexes[index] as usize)
+            }
+            ApplicatorKind::WarpDeformer(starts, ..) => {
+                (starts, puppet.warp_deformer_grid_count[index] as usize)
+            }
+            _ => return Err(KeyformEditError::NoSuchValue),
+        };
+        if positions.len() != expected {
+            return Err(KeyformEditError::PositionCount {
+                expected,
+                actual: positions.len(),
+            });
+        }
+
+        // Compacted keyforms are all stored against the first one, so they're simplest to
+        // put back together and compact again.
+        if let Some(sparse) = &mut applicator.sparse {
+            let mut keyforms: Vec<Vec<Vec2>> = (0..starts.len())
+                .map(|k| (0..expected).map(|x| sparse.position(k, x)).collect())
+                .collect();
+            keyforms[keyform].copy_from_slice(positions);
+            *sparse = SparseKeyforms::encode(keyforms.iter().map(|x| &x[..]), 0.0);
+            self.touch();
+            return Ok(());
+        }
+
+        let start = starts[keyform];
+        let shared = puppet.applicators.iter().enumerate().any(|(i, other)| {
+            let other_starts = match &other.values {
+                _ if other.sparse.is_some() => return false,
+                ApplicatorKind::ArtMesh(starts, ..) | ApplicatorKind::WarpDeformer(starts, ..) => {
+                    starts
+                }
+                _ => return false,
+            };
+            other_starts
+                .iter()
+                .enumerate()
+                .any(|(k, x)| *x == start && (i, k) != (edited, keyform))
+        });
+
+        let table = puppet.keyform_positions.to_mut();
+        let start = start as usize;
+        if shared || start + expected > table.len() {
+            let (ApplicatorKind::ArtMesh(starts, ..) | ApplicatorKind::WarpDeformer(starts, ..)) =
+                &mut puppet.applicators[edited].values
+            else {
+                unreachable!("applicator should have positions");
+            };
+            starts[keyform] = table.len() as u32;
+            table.extend_from_slice(positions);
+        } else {
+            table[start..start + expected].copy_from_slice(positions);
+        }
+        self.touch();
+        Ok(())
+    }
+
+    /// Changes the opacity of an art mesh or deformer keyform.
+    pub fn set_opacity(&mut self, keyform: usize, opacity: f32) -> Result<(), KeyformEditError> {
+        self.check_keyform(keyform)?;
+        let applicator = &mut self.puppet.applicators[self.applicator];
+        // Blend shapes only ever move positions.
+        if applicator.blend.is_some() {
+            return Err(KeyformEditError::NoSuchValue);
+        }
+        match &mut applicator.values {
+            ApplicatorKind::ArtMesh(_, opacities, ..)
+            | ApplicatorKind::WarpDeformer(_, opacities, _)
+            | ApplicatorKind::RotationDeformer(_, opacities, _) => opacities[keyform] = opacity,
+            _ => return Err(KeyformEditError::NoSuchValue),
+        }
+        self.touch();
+        Ok(())
+    }
+
+    /// Changes the multiply and screen colors of an art mesh or deformer keyform. Every
+    /// other keyform keeps the default colors if none were bound before.
+    pub fn set_color(&mut self, keyform: usize, color: BlendColor) -> Result<(), KeyformEditError> {
+        self.check_keyform(keyform)?;
+        let count = self.keyform_count();
+        let applicator = &mut self.puppet.applicators[self.applicator];
+        if applicator.blend.is_some() {
+            return Err(KeyformEditError::NoSuchValue);
+        }
+        match &mut applicator.values {
+            ApplicatorKind::ArtMesh(.., colors)
+            | ApplicatorKind::WarpDeformer(.., colors)
+            | ApplicatorKind::RotationDeformer(.., colors) => {
+                if colors.is_empty() {
+                    colors.resize(count, BlendColor::default());
+                }
+                colors[keyform] = color;
+            }
+            _ => return Err(KeyformEditError::NoSuchValue),
+        }
+        self.touch();
+        Ok(())
+    }
+
+    /// Changes the draw order of an art mesh or part keyform.
+    pub fn set_draw_order(
+        &mut self,
+        keyform: usize,
+        draw_order: f32,
+    ) -> Result<(), KeyformEditError> {
+        self.check_keyform(keyform)?;
+        let applicator = &mut self.puppet.applicators[self.applicator];
+        if applicator.blend.is_some() {
+            return Err(KeyformEditError::NoSuchValue);
+        }
+        match &mut applicator.values {
+            ApplicatorKind::ArtMesh(_, _, draw_orders, _) | ApplicatorKind::Part(draw_orders) => {
+                draw_orders[keyform] = draw_order
+            }
+            _ => return Err(KeyformEditError::NoSuchValue),
+        }
+        self.touch();
+        Ok(())
+    }
+
+    /// Changes the origin, scale and angle of a rotation deformer keyform.
+    pub fn set_transform(
+        &mut self,
+        keyform: usize,
+        transform: TransformData,
+    ) -> Result<(), KeyformEditError> {
+        self.check_keyform(keyform)?;
+        match &mut self.puppet.applicators[self.applicator].values {
+            ApplicatorKind::RotationDeformer(transforms, ..) => transforms[keyform] = transform,
+            _ => return Err(KeyformEditError::NoSuchValue),
+        }
+        self.touch();
+        Ok(())
+    }
+
+    /// Changes the intensity of a glue keyform.
+    pub fn set_glue_intensity(
+        &mut self,
+        keyform: usize,
+        intensity: f32,
+    ) -> Result<(), KeyformEditError> {
+        self.check_keyform(keyform)?;
+        match &mut self.puppet.applicators[self.applicator].values {
+            ApplicatorKind::Glue(intensities) => intensities[keyform] = intensity,
+            _ => return Err(KeyformEditError::NoSuchValue),
+        }
+        self.touch();
+        Ok(())
+    }
+}
+
+impl<'a> PuppetRef<'a> {
+    /// Changes the keyforms of the applicator at `applicator` in [PuppetRef::keyforms], for
+    /// tweaking a model in place without writing it back out to a moc3.
+    pub fn edit_keyforms(&mut self, applicator: usize) -> Option<KeyformsMut<'_, 'a>> {
+        (applicator < self.applicators.len()).then_some(KeyformsMut {
+            puppet: self,
+            applicator,
+        })
+    }
+}
@@ -0,0 +1,160 @@
+use std::{collections::HashMap, fmt::Write};
+
+use super::{memory::ApplicatorTarget, node::NodeKind, PuppetRef};
+
+/// One applicator, with the parameters it reads from and what it writes to.
+#[derive(Debug, Clone)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct ApplicatorDependency {
+    /// The indices of the parameters this applicator reads from.
+    pub parameters: Vec<usize>,
+    pub target: ApplicatorTarget,
+    pub target_id: String,
+    /// Blend shapes add on top of another applicator for the same target instead of
+    /// replacing what it wrote.
+    pub is_blend_shape: bool,
+}
+
+/// Which parameters end up affecting which objects, from [PuppetRef::dependency_graph].
+///
+/// With the `serde` feature this can be serialized (to JSON or anything else), and
+/// [DependencyGraph::to_dot] writes it out for GraphViz.
+#[derive(Debug, Clone, Default)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct DependencyGraph {
+    /// The ID of every parameter, which [ApplicatorDependency::parameters] index into.
+    pub parameters: Vec<String>,
+    /// Every applicator, in the order they're applied.
+    pub applicators: Vec<ApplicatorDependency>,
+    /// The deformer (or art mesh) hierarchy as `[parent, child]` ID pairs. Objects are
+    /// also moved by every parameter that moves one of their ancestors.
+    pub hierarchy: Vec<[String; 2]>,
+}
+
+impl DependencyGraph {
+    /// Writes the graph in GraphViz's DOT format, with parameters on the left and
+    /// everything they drive to the right. Blend shapes are dashed, and the deformer
+    /// hierarchy is dotted.
+    pub fn to_dot(&self) -> String {
+        let mut out = String::new();
+        out.push_str("digraph {\n    rankdir=LR;\n    node [shape=box];\n");
+
+        for id in &self.parameters {
+            writeln!(out, "    \"param/{}\" [shape=ellipse];", escape(id)).unwrap();
+        }
+
+        for applicator in &self.applicators {
+            let target = node_name(applicator.target, &applicator.target_id);
+            let style = if applicator.is_blend_shape {
+                " [style=dashed]"
+            } else {
+                ""
+            };
+
+            for param in &applicator.parameters {
+                writeln!(
+                    out,
+                    "    \"param/{}\" -> \"{target}\"{style};",
+                    escape(&self.parameters[*param])
+                )
+                .unwrap();
+            }
+        }
+
+        for [parent, child] in &self.hierarchy {
+            writeln!(
+                out,
+                "    \"{}\" -> \"{}\" [style=dotted];",
+                escape(parent),
+                escape(child)
+            )
+            .unwrap();
+        }
+
+        out.push_str("}\n");
+        out
+    }
+}
+
+fn escape(id: &str) -> String {
+    id.replace('\\', "\\\\").replace('"', "\\\"")
+}
+
+// Deformers and art meshes use their bare IDs so the hierarchy edges line up with them.
+fn node_name(target: ApplicatorTarget, id: &str) -> String {
+    match target {
+        ApplicatorTarget::Glue => format!("glue/{}", escape(id)),
+        ApplicatorTarget::Part => format!("part/{}", escape(id)),
+        _ => escape(id),
+    }
+}
+
+impl PuppetRef<'_> {
+    /// Works out which parameters drive which art meshes, deformers, glues and parts, for
+    /// debugging models or finding parameters that are expensive to change.
+    pub fn dependency_graph(&self) -> DependencyGraph {
+        let mut warp_deformer_ids = HashMap::new();
+        let mut rotation_deformer_ids = HashMap::new();
+        let mut hierarchy = Vec::new();
+
+        for root in self.node_roots.iter().copied() {
+            for id in root.descendants(&self.nodes) {
+                let node = self.nodes[id].get();
+                match node.data {
+                    NodeKind::WarpDeformer(_, index) => {
+                        warp_deformer_ids.insert(index, node.id.as_str());
+                    }
+                    NodeKind::RotationDeformer(_, index) => {
+                        rotation_deformer_ids.insert(index, node.id.as_str());
+                    }
+                    NodeKind::ArtMesh(_) => {}
+                }
+
+                if let Some(parent) = self.nodes[id].parent() {
+                    hierarchy.push([self.nodes[parent].get().id.clone(), node.id.clone()]);
+                }
+            }
+        }
+
+        let applicators = self
+            .applicators
+            .iter()
+            .map(|applicator| {
+                let target = ApplicatorTarget::of(&applicator.values);
+                let index = applicator.kind_index;
+                let target_id = match target {
+                    ApplicatorTarget::ArtMesh => self.art_mesh_id(index as usize),
+                    ApplicatorTarget::WarpDeformer => warp_deformer_ids.get(&index).copied(),
+                    ApplicatorTarget::RotationDeformer => {
+                        rotation_deformer_ids.get(&index).copied()
+                    }
+                    ApplicatorTarget::Glue => self.glue_id(index as usize),
+                    ApplicatorTarget::Part => self.part_id(index as usize),
+                };
+
+                // Blend shapes also read the parameters limiting how much they apply.
+                let mut parameters: Vec<usize> = applicator
+                    .data
+                    .iter()
+                    .map(|x| self.bindings[*x].parameter_index)
+                    .chain(applicator.blend.iter().flatten().map(|x| x.parameter_index))
+                    .collect();
+                parameters.sort_unstable();
+                parameters.dedup();
+
+                ApplicatorDependency {
+                    parameters,
+                    target,
+                    target_id: target_id.unwrap_or_default().to_string(),
+                    is_blend_shape: applicator.blend.is_some(),
+                }
+            })
+            .collect();
+
+        DependencyGraph {
+            parameters: self.params.ids.clone(),
+            applicators,
+            hierarchy,
+        }
+    }
+}
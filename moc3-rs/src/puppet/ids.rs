@@ -0,0 +1,107 @@
+use std::collections::HashMap;
+
+use crate::data::{DeformerOffsets, Id};
+
+use super::PuppetRef;
+
+// The IDs of one kind of object, in index order, with a map going back the other way.
+#[derive(Debug, Clone, Default)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub(super) struct IdTable {
+    ids: Vec<String>,
+    indices: HashMap<String, u32>,
+}
+
+impl IdTable {
+    pub(super) fn new(ids: &[Id]) -> Self {
+        let ids: Vec<String> = ids.iter().map(|x| x.name.to_string()).collect();
+
+        // IDs should be unique, but if they aren't, the first one wins.
+        let mut indices = HashMap::with_capacity(ids.len());
+        for (i, id) in ids.iter().enumerate() {
+            indices.entry(id.clone()).or_insert(i as u32);
+        }
+
+        Self { ids, indices }
+    }
+
+    fn index(&self, id: &str) -> Option<usize> {
+        self.indices.get(id).map(|x| *x as usize)
+    }
+
+    fn id(&self, index: usize) -> Option<&str> {
+        self.ids.get(index).map(|x| x.as_str())
+    }
+
+    pub(super) fn ids(&self) -> &[String] {
+        &self.ids
+    }
+}
+
+/// The two kinds of deformer, which are indexed separately in [super::PuppetFrameData].
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub enum DeformerKind {
+    Warp,
+    Rotation,
+}
+
+// The kind and index within that kind of every deformer, in the order the moc3 stores them.
+// Deformers of unknown types aren't built, so they don't get one.
+pub(super) fn deformer_kinds(deformers: &DeformerOffsets) -> Vec<Option<(DeformerKind, u32)>> {
+    deformers
+        .types
+        .iter()
+        .zip(deformers.specific_sources_indices.iter())
+        .map(|(kind, index)| match kind {
+            0 => Some((DeformerKind::Warp, *index)),
+            1 => Some((DeformerKind::Rotation, *index)),
+            _ => None,
+        })
+        .collect()
+}
+
+impl PuppetRef<'_> {
+    /// The index of the art mesh with the given ID, as used by [super::PuppetFrameData]
+    /// and the `art_mesh_*` fields.
+    pub fn art_mesh_index(&self, id: &str) -> Option<usize> {
+        self.art_mesh_ids.index(id)
+    }
+
+    pub fn art_mesh_id(&self, index: usize) -> Option<&str> {
+        self.art_mesh_ids.id(index)
+    }
+
+    /// The kind of the deformer with the given ID, and its index among deformers of that
+    /// kind, as used by [super::PuppetFrameData] and the `*_deformer_*` methods.
+    pub fn deformer_index(&self, id: &str) -> Option<(DeformerKind, usize)> {
+        let (kind, index) = self.deformer_kinds[self.deformer_ids.index(id)?]?;
+        Some((kind, index as usize))
+    }
+
+    pub fn deformer_id(&self, kind: DeformerKind, index: usize) -> Option<&str> {
+        let position = self
+            .deformer_kinds
+            .iter()
+            .position(|x| *x == Some((kind, index as u32)))?;
+        self.deformer_ids.id(position)
+    }
+
+    /// The index of the part with the given ID, as used for part opacities.
+    pub fn part_index(&self, id: &str) -> Option<usize> {
+        self.part_ids.index(id)
+    }
+
+    pub fn part_id(&self, index: usize) -> Option<&str> {
+        self.part_ids.id(index)
+    }
+
+    /// The index of the glue with the given ID, matching [super::GlueNode::kind_index].
+    pub fn glue_index(&self, id: &str) -> Option<usize> {
+        self.glue_ids.index(id)
+    }
+
+    pub fn glue_id(&self, index: usize) -> Option<&str> {
+        self.glue_ids.id(index)
+    }
+}
@@ -0,0 +1,209 @@
+use std::borrow::Cow;
+
+use glam::Vec2;
+
+use super::{
+    applicator::{ApplicatorKind, BlendShapeConstraints, ParamApplicator},
+    memory::ApplicatorTarget,
+    BlendColor, PuppetRef, TransformData,
+};
+
+/// One parameter an object's keyforms are laid out over.
+#[derive(Debug, Clone, Copy)]
+pub struct KeyformAxis<'a> {
+    pub parameter_index: usize,
+    pub parameter_id: &'a str,
+    /// The parameter values keyforms were made at, in increasing order.
+    pub keys: &'a [f32],
+}
+
+/// A read-only view of the keyforms of one applicator, from [PuppetRef::keyforms].
+///
+/// Keyforms form a grid over [ObjectKeyforms::axes], with the first axis changing fastest,
+/// so an art mesh keyed on 3 values of one parameter and 5 of another has 15 keyforms.
+#[derive(Debug, Clone, Copy)]
+pub struct ObjectKeyforms<'a> {
+    puppet: &'a PuppetRef<'a>,
+    applicator: &'a ParamApplicator,
+    index: usize,
+}
+
+impl<'a> ObjectKeyforms<'a> {
+    /// Where this applicator is in [PuppetRef::keyforms], for changing its keyforms with
+    /// [PuppetRef::edit_keyforms].
+    pub fn applicator_index(&self) -> usize {
+        self.index
+    }
+
+    pub fn target(&self) -> ApplicatorTarget {
+        ApplicatorTarget::of(&self.applicator.values)
+    }
+
+    /// The index of the art mesh, deformer, glue or part these keyforms are for.
+    pub fn target_index(&self) -> usize {
+        self.applicator.kind_index as usize
+    }
+
+    /// Blend shapes are added on top of the object's other keyforms instead of replacing
+    /// them, and only ever move positions.
+    pub fn is_blend_shape(&self) -> bool {
+        self.applicator.blend.is_some()
+    }
+
+    /// What limits how much of a blend shape applies, which is `None` for anything else.
+    pub fn blend_constraints(&self) -> Option<&'a [BlendShapeConstraints]> {
+        self.applicator.blend.as_deref()
+    }
+
+    pub fn axes(&self) -> impl ExactSizeIterator<Item = KeyformAxis<'a>> + '_ {
+        let puppet = self.puppet;
+        self.applicator.data.iter().map(move |x| {
+            let binding = &puppet.bindings[*x];
+            KeyformAxis {
+                parameter_index: binding.parameter_index,
+                parameter_id: &puppet.params.ids[binding.parameter_index],
+                keys: &binding.keys,
+            }
+        })
+    }
+
+    pub fn keyform_count(&self) -> usize {
+        self.applicator.values.keyform_count()
+    }
+
+    /// The keyform at the given key of every axis, in the same order as
+    /// [ObjectKeyforms::axes].
+    pub fn keyform_index(&self, keys: &[usize]) -> Option<usize> {
+        if keys.len() != self.applicator.data.len() {
+            return None;
+        }
+
+        let mut index = 0;
+        let mut stride = 1;
+        for (key, axis) in keys.iter().zip(self.axes()) {
+            if *key >= axis.keys.len().max(1) {
+                return None;
+            }
+            index += key * stride;
+            stride *= axis.keys.len();
+        }
+        Some(index)
+    }
+
+    /// The vertexes of an art mesh keyform or points of a warp deformer keyform, or `None`
+    /// for anything else. Blend shapes give offsets to add instead.
+    pub fn positions(&self, keyform: usize) -> Option<Cow<'a, [Vec2]>> {
+        let index = self.target_index();
+        let (starts, len) = match &self.applicator.values {
+            ApplicatorKind::ArtMesh(starts, ..) => {
+                (starts, self.puppet.art_mesh_vertexes[index] as usize)
+            }
+            ApplicatorKind::WarpDeformer(starts, ..) => {
+                (starts, self.puppet.warp_deformer_grid_count[index] as usize)
+            }
+            _ => return None,
+        };
+        if keyform >= starts.len() {
+            return None;
+        }
+
+        // Compacted keyforms have to be put back together.
+        if let Some(sparse) = &self.applicator.sparse {
+            return Some(Cow::Owned(
+                (0..len).map(|x| sparse.position(keyform, x)).collect(),
+            ));
+        }
+        let start = starts[keyform] as usize;
+        Some(Cow::Borrowed(
+            &self.puppet.keyform_positions[start..start + len],
+        ))
+    }
+
+    /// The opacity of an art mesh or deformer keyform.
+    pub fn opacity(&self, keyform: usize) -> Option<f32> {
+        if self.is_blend_shape() {
+            return None;
+        }
+        match &self.applicator.values {
+            ApplicatorKind::ArtMesh(_, opacities, ..)
+            | ApplicatorKind::WarpDeformer(_, opacities, _)
+            | ApplicatorKind::RotationDeformer(_, opacities, _) => opacities.get(keyform).copied(),
+            _ => None,
+        }
+    }
+
+    /// The multiply and screen colors of an art mesh or deformer keyform. Objects without
+    /// any bound colors give the default ones.
+    pub fn color(&self, keyform: usize) -> Option<BlendColor> {
+        if self.is_blend_shape() || keyform >= self.keyform_count() {
+            return None;
+        }
+        match &self.applicator.values {
+            ApplicatorKind::ArtMesh(.., colors)
+            | ApplicatorKind::WarpDeformer(.., colors)
+            | ApplicatorKind::RotationDeformer(.., colors) => {
+                Some(colors.get(keyform).copied().unwrap_or_default())
+            }
+            _ => None,
+        }
+    }
+
+    /// The draw order of an art mesh or part keyform.
+    pub fn draw_order(&self, keyform: usize) -> Option<f32> {
+        if self.is_blend_shape() {
+            return None;
+        }
+        match &self.applicator.values {
+            ApplicatorKind::ArtMesh(_, _, draw_orders, _) | ApplicatorKind::Part(draw_orders) => {
+                draw_orders.get(keyform).copied()
+            }
+            _ => None,
+        }
+    }
+
+    /// The origin, scale and angle of a rotation deformer keyform.
+    pub fn transform(&self, keyform: usize) -> Option<TransformData> {
+        match &self.applicator.values {
+            ApplicatorKind::RotationDeformer(transforms, ..) => transforms.get(keyform).copied(),
+            _ => None,
+        }
+    }
+
+    /// The intensity of a glue keyform.
+    pub fn glue_intensity(&self, keyform: usize) -> Option<f32> {
+        match &self.applicator.values {
+            ApplicatorKind::Glue(intensities) => intensities.get(keyform).copied(),
+            _ => None,
+        }
+    }
+}
+
+impl PuppetRef<'_> {
+    /// The keyforms of every applicator, in the order they are applied, for editors and
+    /// other tools showing how a model is put together.
+    pub fn keyforms(&self) -> impl ExactSizeIterator<Item = ObjectKeyforms<'_>> + '_ {
+        self.applicators
+            .iter()
+            .enumerate()
+            .map(|(index, applicator)| ObjectKeyforms {
+                puppet: self,
+                applicator,
+                index,
+            })
+    }
+
+    /// The keyforms of a single art mesh, deformer, glue or part. Its base keyforms come
+    /// first, followed by any blend shapes.
+    pub fn object_keyforms(
+        &self,
+        target: ApplicatorTarget,
+        index: usize,
+    ) -> Vec<ObjectKeyforms<'_>> {
+        let mut keyforms: Vec<_> = self
+            .keyforms()
+            .filter(|x| x.target() == target && x.target_index() == index)
+            .collect();
+        keyforms.sort_by_key(|x| x.is_blend_shape());
+        keyforms
+    }
+}
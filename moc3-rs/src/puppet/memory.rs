@@ -0,0 +1,154 @@
+use std::mem::{size_of, size_of_val};
+
+use glam::Vec2;
+
+use super::{applicator::ApplicatorKind, PuppetRef};
+
+/// What an applicator drives.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub enum ApplicatorTarget {
+    ArtMesh,
+    WarpDeformer,
+    RotationDeformer,
+    Glue,
+    Part,
+}
+
+impl ApplicatorTarget {
+    pub(super) fn of(kind: &ApplicatorKind) -> Self {
+        match kind {
+            ApplicatorKind::ArtMesh(..) => Self::ArtMesh,
+            ApplicatorKind::WarpDeformer(..) => Self::WarpDeformer,
+            ApplicatorKind::RotationDeformer(..) => Self::RotationDeformer,
+            ApplicatorKind::Glue(_) => Self::Glue,
+            ApplicatorKind::Part(_) => Self::Part,
+        }
+    }
+}
+
+/// The memory used by a single applicator's keyforms.
+#[derive(Debug, Clone, Copy)]
+pub struct ApplicatorMemory {
+    pub target: ApplicatorTarget,
+    /// The index of the art mesh, deformer, glue or part this applicator drives.
+    pub kind_index: u32,
+    pub keyforms: usize,
+    /// The bytes of keyform positions this applicator reads from the shared table, or
+    /// stores itself once compacted by [PuppetRef::compact_keyforms].
+    pub position_bytes: usize,
+    /// The bytes of everything else the applicator owns (opacities, colors, keys and so on).
+    pub other_bytes: usize,
+}
+
+/// A breakdown of how much memory a puppet's keyforms use, from [PuppetRef::memory_report].
+#[derive(Debug, Clone, Default)]
+pub struct MemoryReport {
+    /// The size of the keyform position table shared by every applicator.
+    pub keyform_position_bytes: usize,
+    /// The size of every applicator's own compacted keyform positions.
+    pub sparse_position_bytes: usize,
+    /// The size of the parameter keys shared by every applicator.
+    pub binding_bytes: usize,
+    /// Every applicator, in the order they are applied.
+    pub applicators: Vec<ApplicatorMemory>,
+}
+
+impl MemoryReport {
+    /// The total bytes used by keyforms, counting the shared position table once.
+    pub fn total_bytes(&self) -> usize {
+        self.keyform_position_bytes
+            + self.sparse_position_bytes
+            + self.binding_bytes
+            + self
+                .applicators
+                .iter()
+                .map(|x| x.other_bytes)
+                .sum::<usize>()
+    }
+}
+
+// Taking a slice makes sure this is the size of the contents, not of the Vec itself.
+fn bytes<T>(x: &[T]) -> usize {
+    size_of_val(x)
+}
+
+impl PuppetRef<'_> {
+    /// Reports how much memory the keyforms take up per applicator, which is most of a
+    /// puppet's size for larger models.
+    pub fn memory_report(&self) -> MemoryReport {
+        let applicators = self
+            .applicators
+            .iter()
+            .map(|applicator| {
+                let index = applicator.kind_index as usize;
+                let (target, keyforms, position_bytes, other_bytes) = match &applicator.values {
+                    ApplicatorKind::ArtMesh(starts, opacities, draw_orders, colors) => (
+                        ApplicatorTarget::ArtMesh,
+                        starts.len(),
+                        starts.len() * self.art_mesh_vertexes[index] as usize * size_of::<Vec2>(),
+                        bytes(starts) + bytes(opacities) + bytes(draw_orders) + bytes(colors),
+                    ),
+                    ApplicatorKind::WarpDeformer(starts, opacities, colors) => (
+                        ApplicatorTarget::WarpDeformer,
+                        starts.len(),
+                        starts.len()
+                            * self.warp_deformer_grid_count[index] as usize
+                            * size_of::<Vec2>(),
+                        bytes(starts) + bytes(opacities) + bytes(colors),
+                    ),
+                    ApplicatorKind::RotationDeformer(transforms, opacities, colors) => (
+                        ApplicatorTarget::RotationDeformer,
+                        transforms.len(),
+                        0,
+                        bytes(transforms) + bytes(opacities) + bytes(colors),
+                    ),
+                    ApplicatorKind::Glue(intensities) => (
+                        ApplicatorTarget::Glue,
+                        intensities.len(),
+                        0,
+                        bytes(intensities),
+                    ),
+                    ApplicatorKind::Part(draw_orders) => (
+                        ApplicatorTarget::Part,
+                        draw_orders.len(),
+                        0,
+                        bytes(draw_orders),
+                    ),
+                };
+
+                let position_bytes = applicator
+                    .sparse
+                    .as_ref()
+                    .map_or(position_bytes, |x| x.bytes());
+                let binding_bytes = bytes(&applicator.data);
+                let blend_bytes: usize = applicator
+                    .blend
+                    .iter()
+                    .flatten()
+                    .map(|x| bytes(&x.keys) + bytes(&x.weights))
+                    .sum();
+
+                ApplicatorMemory {
+                    target,
+                    kind_index: applicator.kind_index,
+                    keyforms,
+                    position_bytes,
+                    other_bytes: other_bytes + binding_bytes + blend_bytes,
+                }
+            })
+            .collect();
+
+        MemoryReport {
+            keyform_position_bytes: bytes(&self.keyform_positions),
+            sparse_position_bytes: self
+                .applicators
+                .iter()
+                .filter_map(|x| x.sparse.as_ref())
+                .map(|x| x.bytes())
+                .sum(),
+            binding_bytes: self.bindings.iter().map(|x| bytes(&x.keys)).sum(),
+            applicators,
+        }
+    }
+}
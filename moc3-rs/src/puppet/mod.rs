@@ -1,40 +1,85 @@
 mod applicator;
 mod collect;
+mod deferred;
+mod diff;
 mod draw_order;
+mod edit;
+mod graph;
+mod ids;
+mod keyforms;
+mod memory;
 mod node;
-
-use std::{mem::discriminant, slice};
+mod optimize;
+mod partial;
+mod precise;
+mod snapshot;
+mod sparse;
+mod stats;
+mod subset;
+mod user_data;
+mod velocity;
+
+use std::{borrow::Cow, collections::HashMap, slice};
 
 use bytemuck::{Pod, Zeroable};
-use glam::{vec2, Vec2, Vec3};
+use glam::{vec2, Mat3, Vec2, Vec3};
 use indextree::{Arena, NodeId};
 use node::PartNode;
 
 use crate::{
-    data::{ArtMeshFlags, DrawOrderGroupObjectType, Moc3Data, ParameterType},
+    data::{
+        ArtMeshFlags, Checked, DrawOrderGroupObjectType, LayoutError, Moc3Data, ParameterType,
+        TriangleCountError, WarpDeformerOffsets, WarpGridError,
+    },
     deformer::{
-        glue::apply_glue,
+        glue::{apply_glue, apply_glue_normalized, normalize_glue_weights},
         rotation_deformer::{
-            apply_rotation_deformer, calculate_rotation_deformer_angle, TransformData,
+            apply_rotation_deformer, calculate_rotation_deformer_angle, rotation_deformer_matrix,
+            rotation_deformer_turn,
         },
-        warp_deformer::apply_warp_deformer,
+        warp_deformer::{apply_warp_deformer, Grid2D},
     },
     puppet::{
-        applicator::{ApplicatorKind, ParamApplicator},
+        applicator::{ApplicatorKind, BindingCell, ParamApplicator, ParamBinding},
         node::{ArtMeshData, RotationDeformerData, WarpDeformerData},
     },
+    ParseError,
+};
+
+pub use crate::deformer::{rotation_deformer::TransformData, warp_deformer::WarpExtrapolation};
+
+pub use self::{
+    applicator::BlendShapeConstraints,
+    deferred::{DeferredDeform, DeferredDeformError, KeyformWeight, MeshParent},
+    diff::{IdChanges, KeyformCountChange, ParameterChange, PuppetDiff, TextureChange},
+    draw_order::compute_render_order,
+    edit::{KeyformEditError, KeyformsMut},
+    graph::{ApplicatorDependency, DependencyGraph},
+    ids::DeformerKind,
+    keyforms::{KeyformAxis, ObjectKeyforms},
+    memory::{ApplicatorMemory, ApplicatorTarget, MemoryReport},
+    node::GlueNode,
+    optimize::MeshOptimizationReport,
+    snapshot::{FrameSnapshot, SnapshotMismatch, SnapshotParseError, SnapshotTolerance},
+    sparse::{KeyformCompaction, SparseKeyforms},
+    stats::{BudgetOverrun, ComplexityBudget, ModelStats},
 };
 
 use self::{
     collect::{
         collect_blend_shapes, collect_colors_to_bind, collect_param_data,
-        collect_parameter_bindings,
+        collect_parameter_bindings, BindingInterner,
     },
-    draw_order::{draw_order_tree, DrawOrderNode},
-    node::{DeformerNode, GlueNode},
+    deferred::DeferredState,
+    draw_order::{DrawOrderGroupCursor, DrawOrderNode},
+    ids::IdTable,
+    node::DeformerNode,
+    partial::{applicators_by_param, AngleFixup, DirtyFlags},
+    precise::PreciseState,
 };
 
 #[derive(Debug, Clone)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
 #[non_exhaustive]
 pub struct ParamData {
     pub count: u32,
@@ -43,12 +88,124 @@ pub struct ParamData {
     pub maxes: Vec<f32>,
     pub mins: Vec<f32>,
     pub repeats: Vec<bool>,
+    /// How many decimals the editor shows each parameter with.
     pub decimals: Vec<u32>,
     pub types: Vec<ParameterType>,
+    /// The values each parameter has keys at in the editor, lowest first. Only 4.02 models
+    /// store these, so they're empty for anything older.
+    ///
+    /// Updates neither round parameters to their decimals nor snap them to keys, the same
+    /// as the official runtime, which blends between keyforms for any value. See
+    /// [ParamData::snap_to_key] for when that's wanted anyway.
+    pub keys: Vec<Vec<f32>>,
+}
+
+impl ParamData {
+    /// Values for these parameters taken from another version of the same model, matched
+    /// by ID and clamped to the new ranges. Parameters the other version didn't have start
+    /// at their defaults. This is handy for keeping a pose when reloading an edited model.
+    pub fn carry_over(&self, previous: &ParamData, values: &[f32]) -> Vec<f32> {
+        let previous: HashMap<&str, f32> = previous
+            .ids
+            .iter()
+            .map(String::as_str)
+            .zip(values.iter().copied())
+            .collect();
+
+        self.ids
+            .iter()
+            .enumerate()
+            .map(|(i, id)| match previous.get(id.as_str()) {
+                Some(value) => value.clamp(self.mins[i], self.maxes[i]),
+                None => self.defaults[i],
+            })
+            .collect()
+    }
+
+    /// The key of a parameter closest to `value`, if the model stores its keys.
+    pub fn nearest_key(&self, index: usize, value: f32) -> Option<f32> {
+        self.keys
+            .get(index)?
+            .iter()
+            .copied()
+            .min_by(|a, b| (a - value).abs().total_cmp(&(b - value).abs()))
+    }
+
+    /// Moves `value` onto a key of the parameter if it would look the same as that key in
+    /// the editor once rounded to the parameter's decimals, and leaves it as it is
+    /// otherwise. Handy for sliders and typed in values, which tend to land just short of
+    /// a key and blend in a sliver of the keyforms around it.
+    pub fn snap_to_key(&self, index: usize, value: f32) -> f32 {
+        let Some(key) = self.nearest_key(index, value) else {
+            return value;
+        };
+        let decimals = self.decimals.get(index).copied().unwrap_or(0).min(9);
+        let tolerance = 0.5 / 10f32.powi(decimals as i32);
+        if (key - value).abs() <= tolerance {
+            key
+        } else {
+            value
+        }
+    }
+}
+
+/// The area a model was drawn on in the Cubism editor.
+#[derive(Debug, Clone, Copy, PartialEq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct Canvas {
+    /// The size of the canvas, in pixels.
+    pub size: Vec2,
+    /// Where the model's origin sits on the canvas, in pixels from the top left.
+    pub origin: Vec2,
+    /// How many pixels make up one unit of the model's coordinates.
+    pub pixels_per_unit: f32,
 }
 
+impl Canvas {
+    /// The top left and bottom right corners of the canvas, in model coordinates.
+    pub fn bounds(&self) -> (Vec2, Vec2) {
+        (
+            -self.origin / self.pixels_per_unit,
+            (self.size - self.origin) / self.pixels_per_unit,
+        )
+    }
+
+    /// Maps model coordinates onto the canvas, in pixels from its top left.
+    pub fn model_to_pixels(&self) -> Mat3 {
+        Mat3::from_translation(self.origin) * Mat3::from_scale(Vec2::splat(self.pixels_per_unit))
+    }
+
+    /// Maps pixels on the canvas, from its top left, back into model coordinates.
+    pub fn pixels_to_model(&self) -> Mat3 {
+        Mat3::from_scale(Vec2::splat(self.pixels_per_unit.recip()))
+            * Mat3::from_translation(-self.origin)
+    }
+
+    /// Maps model coordinates to normalized device coordinates, with the canvas filling
+    /// -1 to 1 on both axes and +y pointing up, like most graphics APIs expect.
+    pub fn model_to_ndc(&self) -> Mat3 {
+        Mat3::from_translation(vec2(-1.0, 1.0))
+            * Mat3::from_scale(vec2(2.0, -2.0) / self.size)
+            * self.model_to_pixels()
+    }
+}
+
+/// A model built from moc3 data, ready to be posed.
+///
+/// A puppet is never modified by [PuppetRef::update], with all per-frame state living in
+/// [PuppetFrameData] instead. Both are `Send + Sync`, so a single puppet can be shared
+/// (for example behind an `Arc`) between any number of threads, each updating their own
+/// frame data.
+///
+/// With the `serde` feature, puppets can be serialized (with bincode or similar) and
+/// loaded back later, skipping the moc3 parsing and puppet building entirely.
+///
+/// Most code wants [Puppet], which owns all of its data. A [PuppetRef] can instead borrow
+/// its keyform positions (usually the bulk of a model) from the moc3 it was built from,
+/// see [crate::parse_puppet_ref].
 #[derive(Debug, Clone)]
-pub struct Puppet {
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct PuppetRef<'a> {
     node_roots: Vec<NodeId>,
     nodes: Arena<DeformerNode>,
 
@@ -59,10 +216,21 @@ pub struct Puppet {
 
     params: ParamData,
     applicators: Vec<ParamApplicator>,
+    bindings: Vec<ParamBinding>,
+    // The applicators reading from each parameter, for partial updates.
+    applicators_by_param: Vec<Vec<u32>>,
+    // Every keyform's vertexes, which applicators index into.
+    keyform_positions: Cow<'a, [Vec2]>,
+    // How many times keyforms have been edited, and how many as of each applicator's last
+    // edit, so partial updates know to redo them.
+    keyform_edits: u64,
+    applicator_edits: Vec<u64>,
+
+    canvas: Canvas,
 
     pub art_mesh_count: u32,
-    warp_deformer_count: u32,
-    rotation_deformer_count: u32,
+    pub warp_deformer_count: u32,
+    pub rotation_deformer_count: u32,
     pub part_count: u32,
     glue_count: u32,
 
@@ -74,12 +242,23 @@ pub struct Puppet {
     pub art_mesh_flags: Vec<ArtMeshFlags>,
     pub art_mesh_mask_indices: Vec<Vec<u32>>,
     pub art_mesh_vertexes: Vec<u32>,
+    art_mesh_user_data: Vec<Option<String>>,
 
     draw_order_nodes: Arena<DrawOrderNode>,
-    draw_order_root: NodeId,
+    draw_order_roots: Vec<NodeId>,
+
+    art_mesh_ids: IdTable,
+    deformer_ids: IdTable,
+    deformer_kinds: Vec<Option<(DeformerKind, u32)>>,
+    part_ids: IdTable,
+    glue_ids: IdTable,
 }
 
-#[derive(Pod, Zeroable, Debug, Clone, Copy)]
+/// A puppet that owns all of its data.
+pub type Puppet = PuppetRef<'static>;
+
+#[derive(Pod, Zeroable, Debug, Clone, Copy, PartialEq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
 #[repr(C)]
 pub struct BlendColor {
     pub multiply_color: Vec3,
@@ -115,18 +294,74 @@ impl BlendColor {
     }
 }
 
+/// The steps of [PuppetRef::update], in the order they happen. [PuppetRef::update_with_hook]
+/// can step in after each one.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum UpdateStage {
+    /// Parameters have been sanitized and clamped, and part opacities worked out.
+    Parameters,
+    /// Every keyform has been blended, so deformers and art meshes are still relative to
+    /// whatever they're in.
+    Applicators,
+    /// Everything has been moved by the deformers it's in, into model coordinates.
+    Deformers,
+    /// Glues have pulled art meshes together.
+    Glue,
+    /// The render order has been sorted out, finishing the update.
+    RenderOrder,
+}
+
+/// What [PuppetRef::update] does with input parameters that are NaN or infinite.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum InputSanitization {
+    /// Inputs are clamped to their range like any other, so infinities end up at either
+    /// end of it, but a single NaN will end up poisoning every vertex it touches. This is
+    /// the default, as it's how updates have always treated bad input.
+    #[default]
+    Disabled,
+    /// Bad inputs are replaced with the parameter's default value.
+    UseDefault,
+    /// Bad inputs are replaced with the value used in the previous update (which starts
+    /// out as the default).
+    UsePrevious,
+}
+
+/// How [PuppetRef::update] treats the weights of glued vertexes.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum GlueNormalization {
+    /// Weights are used as stored, and pairs are applied one after another. Pairs whose
+    /// weights don't add up to 1 won't fully meet, and a vertex in several pairs is pulled
+    /// by each of them in turn.
+    #[default]
+    Disabled,
+    /// Each pair's weights are scaled to add up to 1, and a vertex in several pairs is
+    /// pulled towards the average of its partners. Intensities are also clamped to `[0, 1]`.
+    PerVertex,
+}
+
+/// The results of posing a [Puppet], reused between frames to avoid reallocating.
+///
+/// Everything is indexed the same way as in the puppet, so art mesh `i` here is art mesh
+/// `i` there too. Positions are in model coordinates, the units the moc3 stores vertexes
+/// in with +y pointing down, which [Canvas::model_to_pixels] maps onto the canvas. Every
+/// supported moc3 version stores them as plain floats, so nothing needs rescaling to get
+/// there.
 #[derive(Debug, Clone)]
 pub struct PuppetFrameData {
+    input_sanitization: InputSanitization,
+    sanitized_params: Vec<usize>,
+
     corrected_params: Vec<f32>,
-    pub calculated_part_opacities: Vec<f32>,
+    binding_cells: Vec<BindingCell>,
+    calculated_part_opacities: Vec<f32>,
 
     art_mesh_draw_orders: Vec<f32>,
     part_draw_orders: Vec<f32>,
 
-    pub art_mesh_render_orders: Vec<u32>,
-    pub art_mesh_data: Vec<Vec<Vec2>>,
-    pub art_mesh_opacities: Vec<f32>,
-    pub art_mesh_colors: Vec<BlendColor>,
+    art_mesh_render_orders: Vec<u32>,
+    art_mesh_data: Vec<Vec<Vec2>>,
+    art_mesh_opacities: Vec<f32>,
+    art_mesh_colors: Vec<BlendColor>,
 
     warp_deformer_data: Vec<Vec<Vec2>>,
     rotation_deformer_data: Vec<TransformData>,
@@ -137,260 +372,867 @@ pub struct PuppetFrameData {
 
     deformer_scale_data: Vec<f32>,
     glue_data: Vec<f32>,
+    glue_normalization: GlueNormalization,
+    warp_extrapolation: WarpExtrapolation,
+    force_enabled: bool,
+    // Scratch space for normalized glues, which need every pull before applying any.
+    glue_deltas: Vec<Vec2>,
+    dirty: DirtyFlags,
+    angle_fixups: Vec<AngleFixup>,
+    // The puppet's keyform edit count as of the last update.
+    keyform_edits: u64,
+    deferred: Option<DeferredState>,
+    precise: Option<PreciseState>,
+
+    param_velocities: Vec<f32>,
+    art_mesh_velocities: Vec<Vec2>,
+    velocity_history: bool,
+    previous_params: Vec<f32>,
+    previous_centroids: Vec<Vec2>,
+
+    // Whether a draw order changed since the render order was last worked out.
+    render_order_stale: bool,
+    // Scratch space for working out the render order without allocating every frame.
+    draw_order_scratch: Vec<(f32, NodeId)>,
+    draw_order_stack: Vec<DrawOrderGroupCursor>,
 }
 
-impl Puppet {
+fn apply_glue_node(glue: &GlueNode, frame_data: &mut PuppetFrameData) {
+    // Glues are left for whoever deforms the art meshes.
+    if frame_data.deferred.is_some() {
+        return;
+    }
+
+    let (first, second) = pair_mut(
+        &mut frame_data.art_mesh_data,
+        glue.art_mesh_index[0] as usize,
+        glue.art_mesh_index[1] as usize,
+    );
+
+    let intensity = frame_data.glue_data[glue.kind_index as usize];
+    match frame_data.glue_normalization {
+        GlueNormalization::Disabled => {
+            apply_glue(intensity, &glue.mesh_indices, &glue.weights, first, second)
+        }
+        GlueNormalization::PerVertex => apply_glue_normalized(
+            intensity,
+            &glue.mesh_indices,
+            &glue.normalized_weights,
+            &mut frame_data.glue_deltas,
+            first,
+            second,
+        ),
+    }
+}
+
+// Grids are checked against the size of their deformer when the puppet is built, and the
+// frame data is made to match.
+fn warp_grid<'a, T: Copy>(points: &'a [T], data: &WarpDeformerData) -> Grid2D<'a, T> {
+    Grid2D::new(points, data.rows as usize, data.columns as usize)
+        .expect("warp deformer grid should match its size")
+}
+
+// Mutably borrows two different elements of a slice at once.
+fn pair_mut<T>(slice: &mut [T], a: usize, b: usize) -> (&mut T, &mut T) {
+    assert_ne!(a, b);
+    if a < b {
+        let (left, right) = slice.split_at_mut(b);
+        (&mut left[a], &mut right[0])
+    } else {
+        let (left, right) = slice.split_at_mut(a);
+        (&mut right[0], &mut left[b])
+    }
+}
+
+// Hosts commonly update on one thread and render on another, so make sure none of
+// the public data types accidentally lose thread safety.
+const _: fn() = || {
+    fn assert_send_sync<T: Send + Sync>() {}
+
+    assert_send_sync::<Puppet>();
+    assert_send_sync::<PuppetFrameData>();
+    assert_send_sync::<ParamData>();
+    assert_send_sync::<BlendColor>();
+    assert_send_sync::<Moc3Data>();
+};
+
+impl PuppetRef<'_> {
     pub fn param_data(&self) -> &ParamData {
         &self.params
     }
 
+    pub fn canvas(&self) -> &Canvas {
+        &self.canvas
+    }
+
+    /// Converts a point in model coordinates, like those in [PuppetFrameData], to pixels
+    /// on the canvas from its top left. See [Canvas::model_to_pixels].
+    pub fn to_canvas_pixels(&self, point: Vec2) -> Vec2 {
+        point * self.canvas.pixels_per_unit + self.canvas.origin
+    }
+
+    /// Converts pixels on the canvas, from its top left, to model coordinates. See
+    /// [Canvas::pixels_to_model].
+    pub fn from_canvas_pixels(&self, pixels: Vec2) -> Vec2 {
+        (pixels - self.canvas.origin) / self.canvas.pixels_per_unit
+    }
+
+    /// Fills `out` with every art mesh's vertexes from the last update in normalized device
+    /// coordinates (see [Canvas::model_to_ndc]), then moved by `view`. This saves simple
+    /// renderers from doing the transform themselves, and reuses `out`'s allocations.
+    pub fn ndc_positions(
+        &self,
+        frame_data: &PuppetFrameData,
+        view: Mat3,
+        out: &mut Vec<Vec<Vec2>>,
+    ) {
+        frame_data.transform_positions(view * self.canvas.model_to_ndc(), out);
+    }
+
+    /// Where every vertex of an art mesh ended up in the last update, in pixels on the
+    /// canvas rather than in model coordinates like [PuppetFrameData::art_mesh_positions].
+    pub fn art_mesh_canvas_positions<'a>(
+        &'a self,
+        frame_data: &'a PuppetFrameData,
+        art_mesh: usize,
+    ) -> impl ExactSizeIterator<Item = Vec2> + 'a {
+        frame_data.art_mesh_data[art_mesh]
+            .iter()
+            .map(|x| self.to_canvas_pixels(*x))
+    }
+
+    /// The smallest rectangle holding every UV of an art mesh, as its top left and bottom
+    /// right corners, or `None` if it has no vertexes. UVs go from 0 to 1 across the
+    /// texture, with +y pointing down.
+    pub fn art_mesh_uv_bounds(&self, art_mesh: usize) -> Option<(Vec2, Vec2)> {
+        let uvs = &self.art_mesh_uvs[art_mesh];
+        let first = *uvs.first()?;
+        Some(
+            uvs.iter()
+                .fold((first, first), |(min, max), x| (min.min(*x), max.max(*x))),
+        )
+    }
+
+    /// Every glue in the puppet, which can be used along with [PuppetFrameData::art_mesh_positions]
+    /// to find where glued vertexes are.
+    pub fn glues(&self) -> &[GlueNode] {
+        &self.glue_nodes
+    }
+
+    /// How many columns and rows of cells every warp deformer's grid has, as laid out in
+    /// [PuppetFrameData::warp_deformer_grids].
+    pub fn warp_deformer_grid_sizes(&self) -> Vec<[u32; 2]> {
+        let mut sizes = vec![[0, 0]; self.warp_deformer_count as usize];
+        for node in self.nodes.iter().filter(|x| !x.is_removed()) {
+            if let node::NodeKind::WarpDeformer(data, ind) = &node.get().data {
+                sizes[*ind as usize] = [data.columns, data.rows];
+            }
+        }
+        sizes
+    }
+
+    /// The part every art mesh sits directly in, if any.
+    pub fn art_mesh_parts(&self) -> Vec<Option<usize>> {
+        let mut parts = vec![None; self.art_mesh_count as usize];
+        for node in self.nodes.iter().filter(|x| !x.is_removed()) {
+            let node = node.get();
+            if let node::NodeKind::ArtMesh(_) = node.data {
+                parts[node.broad_index as usize] =
+                    (node.parent_part_index >= 0).then_some(node.parent_part_index as usize);
+            }
+        }
+        parts
+    }
+
+    /// The part every part sits directly in, if any.
+    pub fn part_parents(&self) -> Vec<Option<usize>> {
+        let mut parents = vec![None; self.part_count as usize];
+        for id in self
+            .part_roots
+            .iter()
+            .flat_map(|root| root.descendants(&self.parts))
+        {
+            let parent = self.parts[id].parent();
+            parents[self.parts[id].get().kind_index as usize] =
+                parent.map(|x| self.parts[x].get().kind_index as usize);
+        }
+        parents
+    }
+
+    /// Every rotation deformer's transform as of the last update, which maps points from
+    /// the deformer's own coordinates into model coordinates the same way its children are
+    /// moved. These are NaN until the frame data has been updated once.
+    pub fn rotation_deformer_transforms(&self, frame_data: &PuppetFrameData) -> Vec<Mat3> {
+        let mut transforms = vec![Mat3::NAN; self.rotation_deformer_count as usize];
+        for node in self.nodes.iter().filter(|x| !x.is_removed()) {
+            let node = node.get();
+            if let node::NodeKind::RotationDeformer(data, ind) = &node.data {
+                let scale = frame_data.deformer_scale_data[node.broad_index as usize];
+                transforms[*ind as usize] = rotation_deformer_matrix(
+                    &frame_data.rotation_deformer_data[*ind as usize].with_scale(scale),
+                    data.base_angle,
+                );
+            }
+        }
+        transforms
+    }
+
+    // Sanitizes and clamps the input parameters into the frame data, then works out where
+    // they fall between the keys of every binding.
+    fn correct_params(&self, input_params: &[f32], frame_data: &mut PuppetFrameData) {
+        frame_data.sanitized_params.clear();
+        for (i, input) in input_params.iter().copied().enumerate() {
+            self.correct_param(i, input, frame_data);
+        }
+
+        for (cell, binding) in frame_data.binding_cells.iter_mut().zip(&self.bindings) {
+            *cell = binding.cell(&frame_data.corrected_params);
+        }
+    }
+
+    fn correct_param(&self, i: usize, input: f32, frame_data: &mut PuppetFrameData) {
+        let input = if input.is_finite() {
+            input
+        } else {
+            frame_data.sanitized_params.push(i);
+            match frame_data.input_sanitization {
+                InputSanitization::Disabled => input,
+                InputSanitization::UseDefault => self.params.defaults[i],
+                InputSanitization::UsePrevious => frame_data.corrected_params[i],
+            }
+        };
+
+        let res = input.clamp(self.params.mins[i], self.params.maxes[i]);
+        frame_data.corrected_params[i] = res;
+    }
+
     pub fn update(
         &self,
         input_params: &[f32],
         part_opacities: &[f32],
         frame_data: &mut PuppetFrameData,
     ) {
-        for i in 0..input_params.len() {
-            let res = input_params[i].clamp(self.params.mins[i], self.params.maxes[i]);
-            frame_data.corrected_params[i] = res;
-        }
+        self.update_with_hook(input_params, part_opacities, frame_data, |_, _| {});
+    }
 
+    /// Like [PuppetRef::update], calling `hook` after every [UpdateStage] so it can change
+    /// the frame data before the next one. For example, rotating a rotation deformer after
+    /// [UpdateStage::Applicators] with [PuppetFrameData::rotation_deformers_mut] turns
+    /// everything in it too, which is handy for procedural motion parameters can't express.
+    pub fn update_with_hook(
+        &self,
+        input_params: &[f32],
+        part_opacities: &[f32],
+        frame_data: &mut PuppetFrameData,
+        mut hook: impl FnMut(UpdateStage, &mut PuppetFrameData),
+    ) {
+        self.stop_deferring_if_compacted(frame_data);
+        self.correct_params(input_params, frame_data);
+
+        // Parts hidden or disabled in the editor hide everything in them, like they do there.
+        let force_enabled = frame_data.force_enabled;
+        let own_opacity = |part: &PartNode| {
+            if part.is_visible && (part.is_enabled || force_enabled) {
+                part_opacities[part.kind_index as usize]
+            } else {
+                0.0
+            }
+        };
         for root in self.part_roots.iter().copied() {
             let root_node = self.parts[root].get();
             let root_index = root_node.kind_index as usize;
-            frame_data.calculated_part_opacities[root_index] = part_opacities[root_index];
+            frame_data.calculated_part_opacities[root_index] = own_opacity(root_node);
             for id in root.descendants(&self.parts).skip(1) {
                 let cur = &self.parts[id];
                 let cur_index = cur.get().kind_index as usize;
                 let parent = &self.parts[cur.parent().unwrap()];
                 let parent_index = parent.get().kind_index as usize;
 
-                frame_data.calculated_part_opacities[cur_index] = part_opacities[cur_index];
+                frame_data.calculated_part_opacities[cur_index] = own_opacity(cur.get());
                 frame_data.calculated_part_opacities[cur_index] *=
                     frame_data.calculated_part_opacities[parent_index];
             }
         }
+        hook(UpdateStage::Parameters, frame_data);
 
         for applicator in &self.applicators {
-            applicator.apply(frame_data);
+            applicator.apply(&self.keyform_positions, frame_data);
         }
+        frame_data.keyform_edits = self.keyform_edits;
+        hook(UpdateStage::Applicators, frame_data);
 
-        let art_mesh_ptr = frame_data.art_mesh_data.as_mut_ptr();
-        let warp_deformer_ptr = frame_data.warp_deformer_data.as_mut_ptr();
-        let rotation_deformer_ptr = frame_data.rotation_deformer_data.as_mut_ptr();
+        for root_id in self.node_roots.iter().copied() {
+            for id in root_id.descendants(&self.nodes) {
+                self.deform_node(id, false, frame_data);
+            }
+        }
+        hook(UpdateStage::Deformers, frame_data);
 
-        let art_mesh_opacity_ptr = frame_data.art_mesh_opacities.as_mut_ptr();
-        let warp_deformer_opacity_ptr = frame_data.warp_deformer_opacities.as_mut_ptr();
-        let rotation_deformer_opacity_ptr = frame_data.rotation_deformer_opacities.as_mut_ptr();
+        for glue in &self.glue_nodes {
+            apply_glue_node(glue, frame_data);
+        }
+        hook(UpdateStage::Glue, frame_data);
 
-        let art_mesh_color_ptr = frame_data.art_mesh_colors.as_mut_ptr();
-        let warp_deformer_color_ptr = frame_data.warp_deformer_colors.as_mut_ptr();
-        let rotation_deformer_color_ptr = frame_data.rotation_deformer_colors.as_mut_ptr();
+        self.refresh_render_order(frame_data);
+        hook(UpdateStage::RenderOrder, frame_data);
+    }
 
-        for root_id in self.node_roots.iter().copied() {
-            {
-                let root = self.nodes[root_id].get();
-                match &root.data {
-                    node::NodeKind::RotationDeformer(_, ind) => {
-                        let scale = unsafe { &(*rotation_deformer_ptr.add(*ind as usize)).scale };
-                        frame_data.deformer_scale_data[root.broad_index as usize] = *scale;
-                    }
-                    node::NodeKind::WarpDeformer(_, _) => {
-                        frame_data.deformer_scale_data[root.broad_index as usize] = 1.0;
-                    }
-                    node::NodeKind::ArtMesh(_) => {}
+    /// Recalculates only the draw orders and render order for the given parameters, skipping
+    /// all of the deforming done by [PuppetRef::update]. This is useful when only parameters
+    /// affecting draw order changed, but everything else in the frame data is left as it was.
+    pub fn update_draw_orders(&self, input_params: &[f32], frame_data: &mut PuppetFrameData) {
+        self.correct_params(input_params, frame_data);
+
+        for applicator in &self.applicators {
+            applicator.apply_draw_order(frame_data);
+        }
+
+        self.refresh_render_order(frame_data);
+    }
+
+    // Sorting everything again is skipped when no draw order changed enough to matter.
+    fn refresh_render_order(&self, frame_data: &mut PuppetFrameData) {
+        if frame_data.render_order_stale {
+            compute_render_order(self, frame_data);
+        }
+    }
+
+    // Disabled deformers and art meshes hide everything under them, so none of it gets
+    // deformed, unless they're forced on with [PuppetFrameData::set_force_enabled].
+    //
+    // `parent_clean` is for partial updates, when the node's parent is as it was the last
+    // time the node was deformed.
+    fn deform_node(&self, id: NodeId, parent_clean: bool, frame_data: &mut PuppetFrameData) {
+        let node = &self.nodes[id];
+        let disabled = !frame_data.force_enabled
+            && id
+                .ancestors(&self.nodes)
+                .any(|x| !self.nodes[x].get().is_enabled);
+
+        if disabled {
+            let node = node.get();
+            match &node.data {
+                node::NodeKind::ArtMesh(_) => {
+                    frame_data.art_mesh_opacities[node.broad_index as usize] = 0.0
+                }
+                node::NodeKind::WarpDeformer(_, ind) => {
+                    frame_data.warp_deformer_opacities[*ind as usize] = 0.0
+                }
+                node::NodeKind::RotationDeformer(_, ind) => {
+                    frame_data.rotation_deformer_opacities[*ind as usize] = 0.0
                 }
             }
-            for child_id in root_id.descendants(&self.nodes).skip(1) {
-                let parent_id = self.nodes[child_id]
-                    .parent()
-                    .expect("node should be child node");
-
-                let parent = self.nodes[parent_id].get();
-                let child = self.nodes[child_id].get();
-
-                // A well-formed file will not have a parent and child referring to the same data,
-                // but this is here to deal with malformed files.
-                assert_ne!(
-                    (discriminant(&child.data), child.broad_index),
-                    (discriminant(&parent.data), parent.broad_index),
-                );
+        } else if node.parent().is_none() {
+            self.deform_root(id, frame_data);
+        } else {
+            self.deform_child(id, parent_clean, frame_data);
+        }
+    }
 
-                let (child_changes, child_opacity, child_color) = match &child.data {
-                    // Safety: We ensure above that we will not have overlapping references.
-                    node::NodeKind::ArtMesh(_) => unsafe {
-                        let vec_data = &mut *art_mesh_ptr.add(child.broad_index as usize);
-                        (
-                            vec_data.as_mut_slice(),
-                            &mut *art_mesh_opacity_ptr.add(child.broad_index as usize),
-                            &mut *art_mesh_color_ptr.add(child.broad_index as usize),
-                        )
-                    },
-                    // Safety: We ensure above that we will not have overlapping references.
-                    node::NodeKind::WarpDeformer(_, ind) => unsafe {
-                        let vec_data = &mut *warp_deformer_ptr.add(*ind as usize);
-                        (
-                            vec_data.as_mut_slice(),
-                            &mut *warp_deformer_opacity_ptr.add(*ind as usize),
-                            &mut *warp_deformer_color_ptr.add(*ind as usize),
-                        )
-                    },
-                    // Safety: We ensure above that we will not have overlapping references.
-                    node::NodeKind::RotationDeformer(_, ind) => unsafe {
-                        let slice_data = slice::from_mut(
-                            &mut (*rotation_deformer_ptr.add(*ind as usize)).origin,
-                        );
-
-                        (
-                            slice_data,
-                            &mut *rotation_deformer_opacity_ptr.add(*ind as usize),
-                            &mut *rotation_deformer_color_ptr.add(child.broad_index as usize),
-                        )
-                    },
-                };
+    // How much a node's own part scales its opacity, on top of its keyforms and parent
+    // deformer.
+    fn own_opacity(&self, node: &DeformerNode, frame_data: &PuppetFrameData) -> f32 {
+        if node.parent_part_index != -1 {
+            frame_data.calculated_part_opacities[node.parent_part_index as usize]
+        } else {
+            1.0
+        }
+    }
 
-                let child_angle = if let node::NodeKind::RotationDeformer(_, ind) = &child.data {
-                    let child_angle =
-                        unsafe { &mut (*rotation_deformer_ptr.add(*ind as usize)).angle };
-                    let scale = unsafe { &(*rotation_deformer_ptr.add(*ind as usize)).scale };
-                    frame_data.deformer_scale_data[child.broad_index as usize] = *scale;
-                    Some(child_angle)
-                } else {
-                    None
-                };
+    // Roots aren't deformed by anything, they only start off the scale and take the opacity
+    // of the part they're in.
+    fn deform_root(&self, root_id: NodeId, frame_data: &mut PuppetFrameData) {
+        let root = self.nodes[root_id].get();
+        let own_opacity = self.own_opacity(root, frame_data);
+        match &root.data {
+            node::NodeKind::RotationDeformer(_, ind) => {
+                frame_data.deformer_scale_data[root.broad_index as usize] =
+                    frame_data.rotation_deformer_data[*ind as usize].scale;
+                frame_data.rotation_deformer_opacities[*ind as usize] *= own_opacity;
+            }
+            node::NodeKind::WarpDeformer(_, ind) => {
+                frame_data.deformer_scale_data[root.broad_index as usize] = 1.0;
+                frame_data.warp_deformer_opacities[*ind as usize] *= own_opacity;
+            }
+            node::NodeKind::ArtMesh(_) => {
+                frame_data.art_mesh_opacities[root.broad_index as usize] *= own_opacity;
+            }
+        }
+        self.start_precise(root, frame_data);
+    }
 
-                // Apply the parent deformer to the child deformer or underlying art mesh.
-                let (parent_opacity, parent_color) = match &parent.data {
-                    node::NodeKind::ArtMesh(_) => {
-                        unreachable!("art mesh should not have children")
+    // Applies the parent of the given node to it, as well as its opacity and color. The
+    // parent must already be fully deformed, and the node must have fresh applicator data.
+    fn deform_child(&self, child_id: NodeId, parent_clean: bool, frame_data: &mut PuppetFrameData) {
+        let parent_id = self.nodes[child_id]
+            .parent()
+            .expect("node should be child node");
+
+        let parent = self.nodes[parent_id].get();
+        let child = self.nodes[child_id].get();
+        let own_opacity = self.own_opacity(child, frame_data);
+        // Moving the child is left for deform_precise at the end, in high precision.
+        let precise = frame_data.precise.is_some();
+        let extrapolation = frame_data.warp_extrapolation;
+
+        // Everything needed from a rotation deformer parent is copied out up front, so
+        // only a warp deformer parent's grid is still borrowed while the child is changed.
+        let (parent_opacity, parent_color, parent_transform) = match &parent.data {
+            node::NodeKind::ArtMesh(_) => {
+                unreachable!("art mesh should not have children")
+            }
+            node::NodeKind::WarpDeformer(_, ind) => (
+                frame_data.warp_deformer_opacities[*ind as usize],
+                frame_data.warp_deformer_colors[*ind as usize],
+                None,
+            ),
+            node::NodeKind::RotationDeformer(_, ind) => (
+                frame_data.rotation_deformer_opacities[*ind as usize],
+                frame_data.rotation_deformer_colors[*ind as usize],
+                Some(
+                    frame_data.rotation_deformer_data[*ind as usize]
+                        .with_scale(frame_data.deformer_scale_data[parent.broad_index as usize]),
+                ),
+            ),
+        };
+        let grid_index = match &parent.data {
+            node::NodeKind::WarpDeformer(_, ind) => Some(*ind as usize),
+            _ => None,
+        };
+
+        let (grid, child_changes, child_opacity, child_color, child_angle) = match &child.data {
+            node::NodeKind::ArtMesh(_) => {
+                let i = child.broad_index as usize;
+                // When deforming is deferred, only the parent is noted down and there are
+                // no vertexes to change.
+                let vertexes: &mut [Vec2] = match &mut frame_data.deferred {
+                    Some(deferred) => {
+                        match (&parent.data, &parent_transform) {
+                            (node::NodeKind::WarpDeformer(data, ind), _) => {
+                                deferred.set_warp_parent(i, *ind as usize, data)
+                            }
+                            (node::NodeKind::RotationDeformer(data, _), Some(transform)) => {
+                                deferred.set_rotation_parent(i, transform, data.base_angle)
+                            }
+                            _ => unreachable!("art mesh should not have children"),
+                        }
+                        &mut []
                     }
-                    node::NodeKind::WarpDeformer(data, ind) => {
-                        // Safety: We ensure above that we will not have overlapping references.
-                        let grid = unsafe { &*warp_deformer_ptr.add(*ind as usize) };
-
-                        let transform = |p| {
-                            let mut ret = p;
-                            apply_warp_deformer(
-                                grid,
-                                data.is_new_deformerr,
-                                data.rows as usize,
-                                data.columns as usize,
-                                slice::from_mut(&mut ret),
-                            );
-                            ret
-                        };
+                    None => frame_data.art_mesh_data[i].as_mut_slice(),
+                };
+                (
+                    grid_index.map(|x| frame_data.warp_deformer_data[x].as_slice()),
+                    vertexes,
+                    &mut frame_data.art_mesh_opacities[i],
+                    &mut frame_data.art_mesh_colors[i],
+                    None,
+                )
+            }
+            node::NodeKind::WarpDeformer(_, ind) => {
+                let i = *ind as usize;
+                let (grid, vec_data) = match grid_index {
+                    Some(grid_index) => {
+                        let (grid, vec_data) =
+                            pair_mut(&mut frame_data.warp_deformer_data, grid_index, i);
+                        (Some(grid.as_slice()), vec_data)
+                    }
+                    None => (None, &mut frame_data.warp_deformer_data[i]),
+                };
+                (
+                    grid,
+                    vec_data.as_mut_slice(),
+                    &mut frame_data.warp_deformer_opacities[i],
+                    &mut frame_data.warp_deformer_colors[i],
+                    None,
+                )
+            }
+            node::NodeKind::RotationDeformer(_, ind) => {
+                let i = *ind as usize;
+                let transform_data = &mut frame_data.rotation_deformer_data[i];
+                frame_data.deformer_scale_data[child.broad_index as usize] = transform_data.scale;
+                (
+                    grid_index.map(|x| frame_data.warp_deformer_data[x].as_slice()),
+                    slice::from_mut(&mut transform_data.origin),
+                    &mut frame_data.rotation_deformer_opacities[i],
+                    &mut frame_data.rotation_deformer_colors[i],
+                    Some((i, &mut transform_data.angle)),
+                )
+            }
+        };
 
-                        // If the child is a rotation deformer, we need to fix up the angle.
-                        if let Some(child_angle) = child_angle {
-                            let angle_diff =
-                                calculate_rotation_deformer_angle(child_changes[0], 0.1, transform);
-
-                            *child_angle += angle_diff;
-                            child_changes[0] = transform(child_changes[0]);
-                        } else {
-                            apply_warp_deformer(
-                                grid,
-                                data.is_new_deformerr,
-                                data.rows as usize,
-                                data.columns as usize,
-                                child_changes,
-                            );
-                        }
+        // Apply the parent deformer to the child deformer or underlying art mesh.
+        match &parent.data {
+            _ if precise => {}
+            node::NodeKind::ArtMesh(_) => {
+                unreachable!("art mesh should not have children")
+            }
+            node::NodeKind::WarpDeformer(data, _) => {
+                let grid = grid.expect("warp deformer parent should have a grid");
+
+                let grid = warp_grid(grid, data);
+
+                let transform = |p| {
+                    let mut ret = p;
+                    apply_warp_deformer(
+                        grid,
+                        data.is_new_deformerr,
+                        extrapolation,
+                        slice::from_mut(&mut ret),
+                    );
+                    ret
+                };
 
-                        // Safety: we guarantee above this will not overlap
-                        (
-                            unsafe { *warp_deformer_opacity_ptr.add(*ind as usize) },
-                            unsafe { *warp_deformer_color_ptr.add(*ind as usize) },
-                        )
-                    }
-                    node::NodeKind::RotationDeformer(data, ind) => {
-                        let transform_data = unsafe { &*rotation_deformer_ptr.add(*ind as usize) };
-                        let new_transform_data = transform_data.with_scale(
-                            frame_data.deformer_scale_data[parent.broad_index as usize],
-                        );
-
-                        // If the child is a rotation deformer, we need to fix up the angle.
-                        if let Some(child_angle) = child_angle {
-                            let transform = |p| {
-                                let mut ret = p;
-                                apply_rotation_deformer(
-                                    &new_transform_data,
-                                    data.base_angle,
-                                    slice::from_mut(&mut ret),
-                                );
-                                ret
-                            };
-
-                            let angle_diff = calculate_rotation_deformer_angle(
+                // If the child is a rotation deformer, we need to fix up the angle.
+                if let Some((i, child_angle)) = child_angle {
+                    let fixup = &mut frame_data.angle_fixups[i];
+                    if !(parent_clean
+                        && fixup.blended_origin == child_changes[0]
+                        && fixup.extrapolation == extrapolation)
+                    {
+                        *fixup = AngleFixup {
+                            blended_origin: child_changes[0],
+                            extrapolation,
+                            origin: transform(child_changes[0]),
+                            angle: calculate_rotation_deformer_angle(
                                 child_changes[0],
-                                10.0,
+                                0.1,
                                 transform,
-                            );
-
-                            *child_angle += angle_diff;
-                            child_changes[0] = transform(child_changes[0]);
-                        } else {
-                            apply_rotation_deformer(
-                                &new_transform_data,
-                                data.base_angle,
-                                child_changes,
-                            );
-                        }
-
-                        // Safety: we guarantee above this will not overlap
-                        (
-                            unsafe { *rotation_deformer_opacity_ptr.add(*ind as usize) },
-                            unsafe { *rotation_deformer_color_ptr.add(*ind as usize) },
-                        )
+                            ),
+                        };
                     }
-                };
 
-                // Propogate down the opacity numbers
-                *child_opacity *= parent_opacity;
-                // The parent part also has opacity to deal with
-                if child.parent_part_index != -1 {
-                    *child_opacity *=
-                        frame_data.calculated_part_opacities[child.parent_part_index as usize];
+                    *child_angle += fixup.angle;
+                    child_changes[0] = fixup.origin;
+                } else {
+                    apply_warp_deformer(grid, data.is_new_deformerr, extrapolation, child_changes);
                 }
-                *child_color = parent_color.blend(&child_color);
-
-                match &child.data {
-                    // We don't need to fix scale for artmeshes.
-                    node::NodeKind::ArtMesh(_) => {}
-                    node::NodeKind::WarpDeformer(_, _) => {
-                        frame_data.deformer_scale_data[child.broad_index as usize] =
-                            frame_data.deformer_scale_data[parent.broad_index as usize];
-                    }
-                    node::NodeKind::RotationDeformer(_, _) => {
-                        frame_data.deformer_scale_data[child.broad_index as usize] *=
-                            frame_data.deformer_scale_data[parent.broad_index as usize];
-                    }
-                };
             }
+            node::NodeKind::RotationDeformer(data, _) => {
+                let new_transform_data =
+                    parent_transform.expect("rotation deformer parent should have a transform");
+
+                // If the child is a rotation deformer, it's turned along with its parent.
+                if let Some((_, child_angle)) = child_angle {
+                    *child_angle += rotation_deformer_turn(&new_transform_data, data.base_angle);
+                }
+                apply_rotation_deformer(&new_transform_data, data.base_angle, child_changes);
+            }
+        };
+
+        // Propogate down the opacity numbers, along with the part's and its own flags.
+        *child_opacity *= parent_opacity * own_opacity;
+        *child_color = parent_color.blend(&child_color);
+
+        match &child.data {
+            // We don't need to fix scale for artmeshes.
+            node::NodeKind::ArtMesh(_) => {}
+            node::NodeKind::WarpDeformer(_, _) => {
+                frame_data.deformer_scale_data[child.broad_index as usize] =
+                    frame_data.deformer_scale_data[parent.broad_index as usize];
+            }
+            node::NodeKind::RotationDeformer(_, _) => {
+                frame_data.deformer_scale_data[child.broad_index as usize] *=
+                    frame_data.deformer_scale_data[parent.broad_index as usize];
+            }
+        };
+        self.deform_precise(parent, child, frame_data);
+    }
+}
+
+impl PuppetFrameData {
+    /// Every parameter as used by the last update, after being sanitized (see
+    /// [PuppetFrameData::set_input_sanitization]) and clamped to its range.
+    pub fn params(&self) -> &[f32] {
+        &self.corrected_params
+    }
+
+    /// The opacity of every part as of the last update, from 0 to 1, including the
+    /// opacities of the parts they're in. Parts that are hidden or disabled in the moc3 come
+    /// out as 0, along with everything in them.
+    pub fn part_opacities(&self) -> &[f32] {
+        &self.calculated_part_opacities
+    }
+
+    /// The draw order of every art mesh, which decides the render order within their group.
+    pub fn art_mesh_draw_orders(&self) -> &[f32] {
+        &self.art_mesh_draw_orders
+    }
+
+    /// Changes art mesh draw orders from an [update hook](PuppetRef::update_with_hook)
+    /// before [UpdateStage::RenderOrder], which then sorts by them.
+    pub fn art_mesh_draw_orders_mut(&mut self) -> &mut [f32] {
+        self.render_order_stale = true;
+        &mut self.art_mesh_draw_orders
+    }
+
+    /// The draw order of every part, for parts that are draw order groups of their own.
+    pub fn part_draw_orders(&self) -> &[f32] {
+        &self.part_draw_orders
+    }
+
+    /// The index of every art mesh in the order they should be drawn, back to front.
+    pub fn render_order(&self) -> &[u32] {
+        &self.art_mesh_render_orders
+    }
+
+    /// Where every vertex of every art mesh ended up in the last update, in model
+    /// coordinates. Each art mesh's vertexes are in the same order as its UVs.
+    pub fn art_mesh_positions(&self) -> &[Vec<Vec2>] {
+        &self.art_mesh_data
+    }
+
+    /// Moves art mesh vertexes from an [update hook](PuppetRef::update_with_hook), which
+    /// are relative to their parent deformer until [UpdateStage::Deformers]. They aren't
+    /// touched by updates with [PuppetFrameData::set_deferred_deform] on.
+    pub fn art_mesh_positions_mut(&mut self) -> &mut [Vec<Vec2>] {
+        &mut self.art_mesh_data
+    }
+
+    /// Fills `out` with every art mesh's vertexes from the last update moved by `matrix`,
+    /// reusing its allocations. See [PuppetRef::ndc_positions] for going straight to
+    /// normalized device coordinates.
+    pub fn transform_positions(&self, matrix: Mat3, out: &mut Vec<Vec<Vec2>>) {
+        out.resize_with(self.art_mesh_data.len(), Vec::new);
+        for (out, positions) in out.iter_mut().zip(&self.art_mesh_data) {
+            out.clear();
+            out.extend(positions.iter().map(|x| matrix.transform_point2(*x)));
         }
+    }
 
-        for glue in &self.glue_nodes {
-            assert_ne!(glue.art_mesh_index[0], glue.art_mesh_index[1]);
-
-            apply_glue(
-                frame_data.glue_data[glue.kind_index as usize],
-                &glue.mesh_indices,
-                &glue.weights,
-                // Safety: We ensure above that we will not have overlapping references.
-                unsafe { &mut (*art_mesh_ptr.add(glue.art_mesh_index[0] as usize)) },
-                unsafe { &mut (*art_mesh_ptr.add(glue.art_mesh_index[1] as usize)) },
-            )
+    /// The final opacity of every art mesh, which is usually from 0 to 1 but not clamped.
+    /// This already includes the opacities of the deformers and parts it's in, and is 0 for
+    /// art meshes that are disabled or in a hidden part.
+    pub fn art_mesh_opacities(&self) -> &[f32] {
+        &self.art_mesh_opacities
+    }
+
+    /// Changes art mesh opacities from an [update hook](PuppetRef::update_with_hook). Until
+    /// [UpdateStage::Deformers] these are only from the art meshes' own keyforms, and get
+    /// multiplied by those of their deformers and parts.
+    pub fn art_mesh_opacities_mut(&mut self) -> &mut [f32] {
+        &mut self.art_mesh_opacities
+    }
+
+    /// The final multiply and screen color of every art mesh, with those of the deformers
+    /// it's in already blended in.
+    pub fn art_mesh_colors(&self) -> &[BlendColor] {
+        &self.art_mesh_colors
+    }
+
+    /// How fast each parameter is changing, in units per second. Only filled in by
+    /// [PuppetRef::measure_velocities].
+    pub fn param_velocities(&self) -> &[f32] {
+        &self.param_velocities
+    }
+
+    /// How fast the center of each art mesh is moving, in model units per second. Only
+    /// filled in by [PuppetRef::measure_velocities].
+    pub fn art_mesh_velocities(&self) -> &[Vec2] {
+        &self.art_mesh_velocities
+    }
+
+    pub fn input_sanitization(&self) -> InputSanitization {
+        self.input_sanitization
+    }
+
+    pub fn set_input_sanitization(&mut self, input_sanitization: InputSanitization) {
+        self.input_sanitization = input_sanitization;
+    }
+
+    pub fn glue_normalization(&self) -> GlueNormalization {
+        self.glue_normalization
+    }
+
+    pub fn set_glue_normalization(&mut self, glue_normalization: GlueNormalization) {
+        self.glue_normalization = glue_normalization;
+    }
+
+    pub fn warp_extrapolation(&self) -> WarpExtrapolation {
+        self.warp_extrapolation
+    }
+
+    /// Changes where points outside of a warp deformer's grid end up, trading faithfulness
+    /// to Cubism for vertexes that stay put under extreme parameters. Art meshes deformed
+    /// elsewhere with [PuppetFrameData::set_deferred_deform] always follow
+    /// [WarpExtrapolation::CubismApprox]. A full update is needed after changing this.
+    pub fn set_warp_extrapolation(&mut self, warp_extrapolation: WarpExtrapolation) {
+        if self.warp_extrapolation != warp_extrapolation {
+            self.angle_fixups.fill(AngleFixup::NONE);
         }
+        self.warp_extrapolation = warp_extrapolation;
+    }
+
+    pub fn force_enabled(&self) -> bool {
+        self.force_enabled
+    }
 
-        draw_order_tree(&self.draw_order_nodes, self.draw_order_root, frame_data);
+    /// Treats every part, deformer and art mesh as enabled, for seeing what's been turned
+    /// off in the editor. Normally disabled ones are hidden along with everything under
+    /// them. Parts hidden in the editor stay hidden. A full update is needed after changing
+    /// this.
+    pub fn set_force_enabled(&mut self, force_enabled: bool) {
+        self.force_enabled = force_enabled;
     }
+
+    /// The indices of the input parameters that were NaN or infinite during the last update.
+    /// These are reported even when sanitization is disabled.
+    pub fn sanitized_params(&self) -> &[usize] {
+        &self.sanitized_params
+    }
+
+    /// Every warp deformer's grid as of the last update, in model coordinates. Each is
+    /// stored row by row, with one more point per row and column than it has cells.
+    pub fn warp_deformer_grids(&self) -> &[Vec<Vec2>] {
+        &self.warp_deformer_data
+    }
+
+    /// Moves warp deformer grids from an [update hook](PuppetRef::update_with_hook), which
+    /// are relative to their parent deformer until [UpdateStage::Deformers].
+    pub fn warp_deformer_grids_mut(&mut self) -> &mut [Vec<Vec2>] {
+        &mut self.warp_deformer_data
+    }
+
+    /// Every rotation deformer's origin, scale and angle as of the last update, in model
+    /// coordinates.
+    pub fn rotation_deformers(&self) -> &[TransformData] {
+        &self.rotation_deformer_data
+    }
+
+    /// Changes rotation deformers from an [update hook](PuppetRef::update_with_hook). After
+    /// [UpdateStage::Applicators] these are as blended from keyforms, relative to their
+    /// parent deformer, and changing them moves everything in them along.
+    pub fn rotation_deformers_mut(&mut self) -> &mut [TransformData] {
+        &mut self.rotation_deformer_data
+    }
+
+    /// Where every rotation deformer's origin ended up in the last update, in model
+    /// coordinates.
+    pub fn rotation_deformer_origins(&self) -> impl ExactSizeIterator<Item = Vec2> + '_ {
+        self.rotation_deformer_data.iter().map(|x| x.origin)
+    }
+}
+
+/// Builds a puppet out of a parsed moc3, failing if any of its sections point outside of
+/// each other (as only the layout of the file itself is checked while parsing), or if a
+/// warp deformer's points don't fit its grid.
+pub fn puppet_from_moc3(read: &Moc3Data) -> Result<Puppet, ParseError> {
+    build_puppet(read, Cow::Owned(read.positions().to_vec()))
+}
+
+/// Builds a puppet that borrows its keyform positions from `bytes`, the moc3 file that
+/// `read` was parsed from. The positions are copied instead if they can't be borrowed,
+/// such as when they aren't aligned in memory.
+pub fn puppet_ref_from_bytes<'a>(
+    read: &Moc3Data,
+    bytes: &'a [u8],
+) -> Result<PuppetRef<'a>, ParseError> {
+    let positions = read.positions();
+    let start = read.positions_offset();
+
+    // The file is little endian, so the positions can only be used as-is on little
+    // endian machines.
+    let borrowed = bytes
+        .get(start..start.saturating_add(std::mem::size_of_val(positions)))
+        .filter(|_| cfg!(target_endian = "little"))
+        .and_then(|x| bytemuck::try_cast_slice(x).ok());
+
+    let keyform_positions = match borrowed {
+        Some(borrowed) => {
+            debug_assert_eq!(borrowed, positions);
+            Cow::Borrowed(borrowed)
+        }
+        None => Cow::Owned(positions.to_vec()),
+    };
+
+    build_puppet(read, keyform_positions)
+}
+
+// Parents have to come before their children, so they're already in `nodes` by the time the
+// children are added.
+fn parent_node(
+    nodes: &[Option<NodeId>],
+    what: &'static str,
+    parent: i32,
+) -> Result<Option<NodeId>, LayoutError> {
+    if parent == -1 {
+        return Ok(None);
+    }
+    match nodes.get(parent as u32 as usize).copied().flatten() {
+        Some(node) => Ok(Some(node)),
+        None => Err(LayoutError {
+            what,
+            start: parent as u32 as u64,
+            end: parent as u32 as u64 + 1,
+            len: nodes.iter().filter(|x| x.is_some()).count() as u64,
+        }),
+    }
+}
+
+// How many rows and columns of cells a warp deformer's grid has, which the number of points
+// it stores has to add up to. Some exporters are said to count rows and columns by their
+// points instead, so those are taken one off of each when that's the only way they add up.
+fn warp_grid_size(
+    warp_deformers: &WarpDeformerOffsets,
+    index: usize,
+) -> Result<(u32, u32), ParseError> {
+    let (rows, columns) = (warp_deformers.rows[index], warp_deformers.columns[index]);
+    // Grids need at least one cell to deform anything by.
+    LayoutError::check_index("warp_deformers.rows", 0, rows as usize)?;
+    LayoutError::check_index("warp_deformers.columns", 0, columns as usize)?;
+
+    // Fencepost error warning: rows and columns measure the user-visbile middle, not the edges
+    // containg the numbers.
+    let points = |rows: u32, columns: u32| (rows as u64 + 1) * (columns as u64 + 1);
+    let vertex_count = warp_deformers.vertex_counts[index];
+    if points(rows, columns) == vertex_count as u64 {
+        Ok((rows, columns))
+    } else if rows > 1 && columns > 1 && points(rows - 1, columns - 1) == vertex_count as u64 {
+        Ok((rows - 1, columns - 1))
+    } else {
+        Err(WarpGridError {
+            index,
+            rows,
+            columns,
+            vertex_count,
+        }
+        .into())
+    }
+}
+
+// Marks the data of a deformer as taken, as no two deformers can share the same data.
+fn claim_deformer(
+    used: &mut [bool],
+    specific: usize,
+    deformer: usize,
+    deformer_count: u32,
+) -> Result<(), LayoutError> {
+    if std::mem::replace(&mut used[specific], true) {
+        return Err(LayoutError::malformed(
+            "deformers.specific_sources_indices",
+            deformer..deformer + 1,
+            deformer_count as usize,
+        ));
+    }
+    Ok(())
 }
 
-pub fn puppet_from_moc3(read: &Moc3Data) -> Puppet {
+// Which part something's in, with -1 for none.
+fn check_part(what: &'static str, part: i32, part_count: u32) -> Result<(), LayoutError> {
+    if part == -1 {
+        return Ok(());
+    }
+    LayoutError::check_index(what, part as u32 as u64, part_count as usize)
+}
+
+fn build_puppet<'a>(
+    read: &Moc3Data,
+    keyform_positions: Cow<'a, [Vec2]>,
+) -> Result<PuppetRef<'a>, ParseError> {
+    let counts = &read.table.count_info;
     let art_meshes = &read.table.art_meshes;
     let parameters = &read.table.parameters;
     let keyform_bindings = &read.table.keyform_bindings;
-    let positions = read.positions();
+    let canvas_info = &*read.table.canvas_info;
 
     // We store our data in a slightly different way than how it was intended, so we
     // need this map of parameter binding index back up to the parameter itself. This is
@@ -405,10 +1247,12 @@ pub fn puppet_from_moc3(read: &Moc3Data) -> Puppet {
 
         let start = parameters.parameter_binding_sources_starts[i] as usize;
         let count = parameters.parameter_binding_sources_counts[i] as usize;
-
-        for a in start..(start + count) {
-            parameter_bindings_to_parameter[a] = i;
-        }
+        parameter_bindings_to_parameter.range(
+            "parameters.parameter_binding_sources_starts",
+            start,
+            count,
+        )?;
+        parameter_bindings_to_parameter[start..start + count].fill(i);
 
         // I think this works, as the way the format should not have regular
         // parameter bindings for blend shape parameters.
@@ -418,10 +1262,12 @@ pub fn puppet_from_moc3(read: &Moc3Data) -> Puppet {
                     parameters_v402.blend_shape_parameter_binding_sources_starts[i] as usize;
                 let count =
                     parameters_v402.blend_shape_parameter_binding_sources_counts[i] as usize;
-
-                for a in start..(start + count) {
-                    blend_shape_parameter_bindings_to_parameter[a] = i;
-                }
+                blend_shape_parameter_bindings_to_parameter.range(
+                    "parameters_v402.blend_shape_parameter_binding_sources_starts",
+                    start,
+                    count,
+                )?;
+                blend_shape_parameter_bindings_to_parameter[start..start + count].fill(i);
             }
         }
     }
@@ -434,6 +1280,7 @@ pub fn puppet_from_moc3(read: &Moc3Data) -> Puppet {
     // ----- BEGIN PARAMETER STUFF -----
 
     let mut applicators = Vec::new();
+    let mut bindings = BindingInterner::default();
 
     let mut node_roots: Vec<NodeId> = Vec::new();
     let mut node_arena = Arena::<DeformerNode>::with_capacity(
@@ -453,14 +1300,36 @@ pub fn puppet_from_moc3(read: &Moc3Data) -> Puppet {
     let rotation_deformer_keyforms = &read.table.rotation_deformer_keyforms;
     let rotation_deformer_keyforms_v402 = read.table.rotation_deformer_keyforms_v402.as_ref();
 
+    let warp_grid_sizes = (0..counts.warp_deformers as usize)
+        .map(|i| warp_grid_size(warp_deformers, i))
+        .collect::<Result<Vec<_>, _>>()?;
+
+    // Every deformer needs data of its own, as parents are read while their children are
+    // written to.
+    let mut used_warp_deformers = vec![false; counts.warp_deformers as usize];
+    let mut used_rotation_deformers = vec![false; counts.rotation_deformers as usize];
+
     for i in 0..read.table.count_info.deformers {
         let i: usize = i as usize;
         let specific = deformers.specific_sources_indices[i] as usize;
 
         let parent_deformer_index = deformers.parent_deformer_indices[i];
+        let parent = parent_node(
+            &deformer_indices_to_node_ids,
+            "deformers.parent_deformer_indices",
+            parent_deformer_index,
+        )?;
+        check_part(
+            "deformers.parent_part_indices",
+            deformers.parent_part_indices[i],
+            counts.parts,
+        )?;
         if deformers.types[i] == 0 {
-            let vertexes = warp_deformers.vertex_counts[specific] as usize;
-
+            // The rest of the warp deformer's own arrays are as long as this one.
+            warp_deformers
+                .rows
+                .at("deformers.specific_sources_indices", specific)?;
+            claim_deformer(&mut used_warp_deformers, specific, i, counts.deformers)?;
             let is_new_deformerr = read
                 .table
                 .warp_deformer_keyforms_v303
@@ -476,18 +1345,16 @@ pub fn puppet_from_moc3(read: &Moc3Data) -> Puppet {
                     is_enabled: deformers.is_enabled[i] != 0,
                     data: node::NodeKind::WarpDeformer(
                         WarpDeformerData {
-                            rows: warp_deformers.rows[specific],
-                            columns: warp_deformers.columns[specific],
+                            rows: warp_grid_sizes[specific].0,
+                            columns: warp_grid_sizes[specific].1,
                             is_new_deformerr: is_new_deformerr != 0,
                         },
                         specific as u32,
                     ),
                 };
 
-                let res = if parent_deformer_index != -1 {
-                    deformer_indices_to_node_ids[parent_deformer_index as usize]
-                        .unwrap()
-                        .append_value(node_to_append, &mut node_arena)
+                let res = if let Some(parent) = parent {
+                    parent.append_value(node_to_append, &mut node_arena)
                 } else {
                     let it = node_arena.new_node(node_to_append);
                     node_roots.push(it);
@@ -501,26 +1368,34 @@ pub fn puppet_from_moc3(read: &Moc3Data) -> Puppet {
             let start = warp_deformers.keyform_sources_starts[specific] as usize;
             let count = warp_deformers.keyform_sources_counts[specific] as usize;
 
-            let mut positions_to_bind = Vec::new();
-            for i in start..start + count {
-                let position_start =
-                    warp_deformer_keyforms.keyform_position_sources_starts[i] as usize / 2;
-                positions_to_bind
-                    .push(positions[position_start..position_start + vertexes].to_owned());
-            }
-            let opacities_to_bind = warp_deformer_keyforms.opacities[start..start + count].to_vec();
+            let positions_to_bind = warp_deformer_keyforms
+                .keyform_position_sources_starts
+                .range(
+                    "warp_deformer_keyforms.keyform_position_sources_starts",
+                    start,
+                    count,
+                )?
+                .iter()
+                .map(|x| x / 2)
+                .collect();
+            let opacities_to_bind = warp_deformer_keyforms
+                .opacities
+                .range("warp_deformer_keyforms.opacities", start, count)?
+                .to_vec();
             let colors_to_bind =
                 if let Some(warp_deformer_keyforms_v402) = warp_deformer_keyforms_v402 {
                     let colors_start =
                         warp_deformer_keyforms_v402.keyform_color_sources_start[specific] as usize;
 
-                    collect_colors_to_bind(read, colors_start, count)
+                    collect_colors_to_bind(read, colors_start, count)?
                 } else {
                     Vec::new()
                 };
 
-            let parameter_bindings_count =
-                keyform_bindings.parameter_binding_index_sources_counts[binding_index] as usize;
+            let parameter_bindings_count = *keyform_bindings
+                .parameter_binding_index_sources_counts
+                .at("keyform_binding_sources_indices", binding_index)?
+                as usize;
             let parameter_bindings_start: usize =
                 keyform_bindings.parameter_binding_index_sources_starts[binding_index] as usize;
 
@@ -533,14 +1408,19 @@ pub fn puppet_from_moc3(read: &Moc3Data) -> Puppet {
                 ),
                 data: collect_parameter_bindings(
                     read,
+                    &mut bindings,
                     &parameter_bindings_to_parameter,
                     parameter_bindings_start,
                     parameter_bindings_count,
-                ),
+                )?,
+                sparse: None,
                 blend: None,
             });
         } else if deformers.types[i] == 1 {
-            let base_angle = rotation_deformers.base_angles[specific];
+            let base_angle = *rotation_deformers
+                .base_angles
+                .at("deformers.specific_sources_indices", specific)?;
+            claim_deformer(&mut used_rotation_deformers, specific, i, counts.deformers)?;
 
             {
                 let node_to_append = DeformerNode {
@@ -554,10 +1434,8 @@ pub fn puppet_from_moc3(read: &Moc3Data) -> Puppet {
                     ),
                 };
 
-                let res = if parent_deformer_index != -1 {
-                    deformer_indices_to_node_ids[parent_deformer_index as usize]
-                        .unwrap()
-                        .append_value(node_to_append, &mut node_arena)
+                let res = if let Some(parent) = parent {
+                    parent.append_value(node_to_append, &mut node_arena)
                 } else {
                     let it = node_arena.new_node(node_to_append);
                     node_roots.push(it);
@@ -572,6 +1450,12 @@ pub fn puppet_from_moc3(read: &Moc3Data) -> Puppet {
             let start = rotation_deformers.keyform_sources_starts[specific] as usize;
             let count = rotation_deformers.keyform_sources_counts[specific] as usize;
 
+            // The rest of the keyform arrays are as long as this one.
+            rotation_deformer_keyforms.opacities.range(
+                "rotation_deformers.keyform_sources_starts",
+                start,
+                count,
+            )?;
             let mut positions_to_bind = Vec::new();
             for i in start..start + count {
                 let x_origin = rotation_deformer_keyforms.x_origin[i];
@@ -591,13 +1475,15 @@ pub fn puppet_from_moc3(read: &Moc3Data) -> Puppet {
                     let colors_start = rotation_deformer_keyforms_v402.keyform_color_sources_start
                         [specific] as usize;
 
-                    collect_colors_to_bind(read, colors_start, count)
+                    collect_colors_to_bind(read, colors_start, count)?
                 } else {
                     Vec::new()
                 };
 
-            let parameter_bindings_count =
-                keyform_bindings.parameter_binding_index_sources_counts[binding_index] as usize;
+            let parameter_bindings_count = *keyform_bindings
+                .parameter_binding_index_sources_counts
+                .at("keyform_binding_sources_indices", binding_index)?
+                as usize;
             let parameter_bindings_start: usize =
                 keyform_bindings.parameter_binding_index_sources_starts[binding_index] as usize;
 
@@ -610,10 +1496,12 @@ pub fn puppet_from_moc3(read: &Moc3Data) -> Puppet {
                 ),
                 data: collect_parameter_bindings(
                     read,
+                    &mut bindings,
                     &parameter_bindings_to_parameter,
                     parameter_bindings_start,
                     parameter_bindings_count,
-                ),
+                )?,
+                sparse: None,
                 blend: None,
             });
         }
@@ -635,38 +1523,81 @@ pub fn puppet_from_moc3(read: &Moc3Data) -> Puppet {
         let vertexes = art_meshes.vertex_counts[i] as usize;
         let index_start = art_meshes.vertex_index_sources_starts[i] as usize;
         let index_count = art_meshes.vertex_index_sources_counts[i] as usize;
-        art_mesh_uvs.push(uvs[uv_start..uv_start + vertexes].to_vec());
-        art_mesh_indices.push(vertex_indices[index_start..index_start + index_count].to_vec());
+        art_mesh_uvs.push(uvs.range("uvs.uvs", uv_start, vertexes)?.to_vec());
+
+        // Everything drawing or optimizing meshes goes by whole triangles of real vertexes.
+        if !index_count.is_multiple_of(3) {
+            return Err(TriangleCountError {
+                index: i,
+                index_count: index_count as u32,
+            }
+            .into());
+        }
+        let indices = vertex_indices.range("vertex_indices.indices", index_start, index_count)?;
+        for index in indices {
+            LayoutError::check_index("vertex_indices.indices", *index as u64, vertexes)?;
+        }
+        art_mesh_indices.push(indices.to_vec());
 
         let mask_start = art_meshes.art_mesh_mask_sources_starts[i] as usize;
         let mask_count = art_meshes.art_mesh_mask_sources_counts[i] as usize;
-        art_mesh_mask_indices.push(
-            art_mesh_masks.art_mesh_source_indices[mask_start..mask_start + mask_count].to_owned(),
-        );
+        let masks = art_mesh_masks.art_mesh_source_indices.range(
+            "art_mesh_masks.art_mesh_source_indices",
+            mask_start,
+            mask_count,
+        )?;
+        for mask in masks {
+            LayoutError::check_index(
+                "art_mesh_masks.art_mesh_source_indices",
+                *mask as u64,
+                counts.art_meshes as usize,
+            )?;
+        }
+        art_mesh_mask_indices.push(masks.to_owned());
 
         let binding_index = art_meshes.keyform_binding_sources_indices[i] as usize;
         let start = art_meshes.keyform_sources_starts[i] as usize;
         let count = art_meshes.keyform_sources_counts[i] as usize;
 
-        let mut positions_to_bind = Vec::new();
-        for i in start..start + count {
-            let position_start = art_mesh_keyforms.keyform_position_sources_starts[i] as usize / 2;
-            positions_to_bind.push(positions[position_start..position_start + vertexes].to_owned());
-        }
-        let opacities_to_bind = art_mesh_keyforms.opacities[start..start + count].to_vec();
-        let draw_orders_to_bind = art_mesh_keyforms.draw_orders[start..start + count].to_vec();
+        let positions_to_bind = art_mesh_keyforms
+            .keyform_position_sources_starts
+            .range(
+                "art_mesh_keyforms.keyform_position_sources_starts",
+                start,
+                count,
+            )?
+            .iter()
+            .map(|x| x / 2)
+            .collect();
+        let opacities_to_bind = art_mesh_keyforms
+            .opacities
+            .range("art_mesh_keyforms.opacities", start, count)?
+            .to_vec();
+        let draw_orders_to_bind = art_mesh_keyforms
+            .draw_orders
+            .range("art_mesh_keyforms.draw_orders", start, count)?
+            .to_vec();
         let colors_to_bind =
             if let Some(art_mesh_deformer_keyforms_v402) = art_mesh_deformer_keyforms_v402 {
                 let colors_start =
                     art_mesh_deformer_keyforms_v402.keyform_color_sources_start[i] as usize;
 
-                collect_colors_to_bind(read, colors_start, count)
+                collect_colors_to_bind(read, colors_start, count)?
             } else {
                 Vec::new()
             };
 
         {
-            let parent_deformer_index = art_meshes.parent_deformer_indices[i];
+            let parent = parent_node(
+                &deformer_indices_to_node_ids,
+                "art_meshes.parent_deformer_indices",
+                art_meshes.parent_deformer_indices[i],
+            )?;
+            check_part(
+                "art_meshes.parent_part_indices",
+                art_meshes.parent_part_indices[i],
+                counts.parts,
+            )?;
 
             let node_to_append = DeformerNode {
                 id: art_meshes.ids[i].name.to_string(),
@@ -678,18 +1609,18 @@ pub fn puppet_from_moc3(read: &Moc3Data) -> Puppet {
                 }),
             };
 
-            if parent_deformer_index != -1 {
-                deformer_indices_to_node_ids[parent_deformer_index as usize]
-                    .unwrap()
-                    .append_value(node_to_append, &mut node_arena);
+            if let Some(parent) = parent {
+                parent.append_value(node_to_append, &mut node_arena);
             } else {
                 let it = node_arena.new_node(node_to_append);
                 node_roots.push(it);
             };
         }
 
-        let parameter_bindings_count =
-            keyform_bindings.parameter_binding_index_sources_counts[binding_index] as usize;
+        let parameter_bindings_count = *keyform_bindings
+            .parameter_binding_index_sources_counts
+            .at("keyform_binding_sources_indices", binding_index)?
+            as usize;
         let parameter_bindings_start =
             keyform_bindings.parameter_binding_index_sources_starts[binding_index] as usize;
 
@@ -703,57 +1634,12 @@ pub fn puppet_from_moc3(read: &Moc3Data) -> Puppet {
             ),
             data: collect_parameter_bindings(
                 read,
+                &mut bindings,
                 &parameter_bindings_to_parameter,
                 parameter_bindings_start,
                 parameter_bindings_count,
-            ),
-            blend: None,
-        });
-    }
-
-    let mut glue_nodes = Vec::new();
-
-    let glues = &read.table.glues;
-    let glue_infos = &read.table.glue_infos;
-    let glue_keyforms = &read.table.glue_keyforms;
-    for i in 0..read.table.count_info.glues {
-        let i = i as usize;
-
-        let glue_info_start = glues.glue_info_sources_starts[i] as usize;
-        let glue_info_count = glues.glue_info_sources_counts[i] as usize;
-
-        let binding_index = glues.keyform_binding_sources_indices[i] as usize;
-        let start = glues.keyform_sources_starts[i] as usize;
-        let count = glues.keyform_sources_counts[i] as usize;
-
-        let mesh_indices =
-            &glue_infos.vertex_indices[glue_info_start..glue_info_start + glue_info_count];
-        let weights = &glue_infos.weights[glue_info_start..glue_info_start + glue_info_count];
-
-        let intensities_to_bind = glue_keyforms.intensities[start..start + count].to_vec();
-
-        glue_nodes.push(GlueNode {
-            id: glues.ids[i].name.to_string(),
-            kind_index: i as u32,
-            art_mesh_index: [glues.art_mesh_indices_a[i], glues.art_mesh_indices_b[i]],
-            mesh_indices: mesh_indices.to_vec(),
-            weights: weights.to_vec(),
-        });
-
-        let parameter_bindings_count =
-            keyform_bindings.parameter_binding_index_sources_counts[binding_index] as usize;
-        let parameter_bindings_start =
-            keyform_bindings.parameter_binding_index_sources_starts[binding_index] as usize;
-
-        applicators.push(ParamApplicator {
-            kind_index: i as u32,
-            values: ApplicatorKind::Glue(intensities_to_bind),
-            data: collect_parameter_bindings(
-                read,
-                &parameter_bindings_to_parameter,
-                parameter_bindings_start,
-                parameter_bindings_count,
-            ),
+            )?,
+            sparse: None,
             blend: None,
         });
     }
@@ -773,22 +1659,59 @@ pub fn puppet_from_moc3(read: &Moc3Data) -> Puppet {
         let start = glues.keyform_sources_starts[i] as usize;
         let count = glues.keyform_sources_counts[i] as usize;
 
-        let mesh_indices =
-            &glue_infos.vertex_indices[glue_info_start..glue_info_start + glue_info_count];
-        let weights = &glue_infos.weights[glue_info_start..glue_info_start + glue_info_count];
+        let mesh_indices = glue_infos.vertex_indices.range(
+            "glue_infos.vertex_indices",
+            glue_info_start,
+            glue_info_count,
+        )?;
+        let weights =
+            glue_infos
+                .weights
+                .range("glue_infos.weights", glue_info_start, glue_info_count)?;
+
+        // Vertexes alternate between the two art meshes.
+        let art_mesh_index = [glues.art_mesh_indices_a[i], glues.art_mesh_indices_b[i]];
+        for (what, mesh) in ["glues.art_mesh_indices_a", "glues.art_mesh_indices_b"]
+            .into_iter()
+            .zip(art_mesh_index)
+        {
+            art_meshes.vertex_counts.at(what, mesh as usize)?;
+        }
+        if art_mesh_index[0] == art_mesh_index[1] {
+            return Err(LayoutError::malformed(
+                "glues.art_mesh_indices_b",
+                i..i + 1,
+                counts.glues as usize,
+            )
+            .into());
+        }
+        for (a, vertex) in mesh_indices.iter().enumerate() {
+            let vertexes = art_meshes.vertex_counts[art_mesh_index[a % 2] as usize];
+            LayoutError::check_index(
+                "glue_infos.vertex_indices",
+                *vertex as u64,
+                vertexes as usize,
+            )?;
+        }
 
-        let intensities_to_bind = glue_keyforms.intensities[start..start + count].to_vec();
+        let intensities_to_bind = glue_keyforms
+            .intensities
+            .range("glue_keyforms.intensities", start, count)?
+            .to_vec();
 
         glue_nodes.push(GlueNode {
             id: glues.ids[i].name.to_string(),
             kind_index: i as u32,
-            art_mesh_index: [glues.art_mesh_indices_a[i], glues.art_mesh_indices_b[i]],
+            art_mesh_index,
             mesh_indices: mesh_indices.to_vec(),
             weights: weights.to_vec(),
+            normalized_weights: normalize_glue_weights(mesh_indices, weights),
         });
 
-        let parameter_bindings_count =
-            keyform_bindings.parameter_binding_index_sources_counts[binding_index] as usize;
+        let parameter_bindings_count = *keyform_bindings
+            .parameter_binding_index_sources_counts
+            .at("keyform_binding_sources_indices", binding_index)?
+            as usize;
         let parameter_bindings_start =
             keyform_bindings.parameter_binding_index_sources_starts[binding_index] as usize;
 
@@ -797,10 +1720,12 @@ pub fn puppet_from_moc3(read: &Moc3Data) -> Puppet {
             values: ApplicatorKind::Glue(intensities_to_bind),
             data: collect_parameter_bindings(
                 read,
+                &mut bindings,
                 &parameter_bindings_to_parameter,
                 parameter_bindings_start,
                 parameter_bindings_count,
-            ),
+            )?,
+            sparse: None,
             blend: None,
         });
     }
@@ -821,10 +1746,17 @@ pub fn puppet_from_moc3(read: &Moc3Data) -> Puppet {
         let start = part_data.keyform_sources_starts[i] as usize;
         let count = part_data.keyform_sources_counts[i] as usize;
 
-        let draw_orders_to_bind = part_keyforms.draw_orders[start..start + count].to_vec();
+        let draw_orders_to_bind = part_keyforms
+            .draw_orders
+            .range("part_keyforms.draw_orders", start, count)?
+            .to_vec();
 
         {
-            let parent_part_index = part_data.parent_part_indices[i];
+            let parent = parent_node(
+                &part_indices_to_node_ids,
+                "parts.parent_part_indices",
+                part_data.parent_part_indices[i],
+            )?;
 
             let node_to_append = PartNode {
                 id: part_data.ids[i].name.to_string(),
@@ -833,10 +1765,8 @@ pub fn puppet_from_moc3(read: &Moc3Data) -> Puppet {
                 is_visible: part_data.is_visible[i] != 0,
             };
 
-            let res = if parent_part_index != -1 {
-                part_indices_to_node_ids[parent_part_index as usize]
-                    .unwrap()
-                    .append_value(node_to_append, &mut part_arena)
+            let res = if let Some(parent) = parent {
+                parent.append_value(node_to_append, &mut part_arena)
             } else {
                 let it: NodeId = part_arena.new_node(node_to_append);
                 part_roots.push(it);
@@ -846,8 +1776,10 @@ pub fn puppet_from_moc3(read: &Moc3Data) -> Puppet {
             part_indices_to_node_ids[i] = Some(res);
         }
 
-        let parameter_bindings_count =
-            keyform_bindings.parameter_binding_index_sources_counts[binding_index] as usize;
+        let parameter_bindings_count = *keyform_bindings
+            .parameter_binding_index_sources_counts
+            .at("keyform_binding_sources_indices", binding_index)?
+            as usize;
         let parameter_bindings_start =
             keyform_bindings.parameter_binding_index_sources_starts[binding_index] as usize;
 
@@ -856,10 +1788,12 @@ pub fn puppet_from_moc3(read: &Moc3Data) -> Puppet {
             values: ApplicatorKind::Part(draw_orders_to_bind),
             data: collect_parameter_bindings(
                 read,
+                &mut bindings,
                 &parameter_bindings_to_parameter,
                 parameter_bindings_start,
                 parameter_bindings_count,
-            ),
+            )?,
+            sparse: None,
             blend: None,
         });
     }
@@ -867,70 +1801,150 @@ pub fn puppet_from_moc3(read: &Moc3Data) -> Puppet {
     // ----- END PARAMETER STUFF -----
     collect_blend_shapes(
         read,
+        &mut bindings,
         &blend_shape_parameter_bindings_to_parameter,
         &mut applicators,
-    );
+    )?;
 
     // Here we do the draw order groups. This lets us apply draw orders to the mesh depending on how
     // the draw order groups interact, and lets us calculate the actual priority when the nodes have the
     // same draw order by breaking ties via tree position.
 
-    // TODO: something like this for parts
     let draw_order_groups = &read.table.draw_order_groups;
     let draw_order_group_objects = &read.table.draw_order_group_objects;
+    let group_count = read.table.count_info.draw_order_groups as usize;
 
     let mut draw_order_nodes = Arena::<DrawOrderNode>::with_capacity(
-        read.table.count_info.draw_order_group_objects as usize,
+        read.table.count_info.draw_order_group_objects as usize + 1,
     );
 
-    let mut draw_order_indices_to_node_ids: Vec<Option<NodeId>> =
-        vec![None; read.table.count_info.draw_order_groups as usize];
-
-    draw_order_indices_to_node_ids[0] =
-        Some(draw_order_nodes.new_node(DrawOrderNode::Part { index: u32::MAX }));
-
-    for i in 0..read.table.count_info.draw_order_groups {
-        let i = i as usize;
-
-        let object_sources_start = draw_order_groups.object_sources_starts[i];
-        let object_sources_count = draw_order_groups.object_sources_counts[i];
-
-        for a in object_sources_start..(object_sources_start + object_sources_count) {
-            let a = a as usize;
-
+    // Every group gets its node up front, as the part owning a group can be listed after
+    // the group itself. Groups no part owns end up as roots.
+    let mut group_parts = vec![u32::MAX; group_count];
+    for (a, self_index) in draw_order_group_objects.self_indices.iter().enumerate() {
+        if let Some(part) = group_parts.get_mut(*self_index as usize) {
+            *part = draw_order_group_objects.indices[a];
+        }
+    }
+    let group_nodes: Vec<NodeId> = (0..group_count)
+        .map(|i| {
+            let (min, max) = (
+                draw_order_groups.minimum_draw_orders[i] as f32,
+                draw_order_groups.maximum_draw_orders[i] as f32,
+            );
+            draw_order_nodes.new_node(DrawOrderNode::Part {
+                index: group_parts[i],
+                range: (min <= max).then_some([min, max]),
+            })
+        })
+        .collect();
+
+    for (i, group) in group_nodes.iter().enumerate() {
+        let object_sources_start = draw_order_groups.object_sources_starts[i] as usize;
+        let object_sources_count = draw_order_groups.object_sources_counts[i] as usize;
+        // The rest of the object arrays are as long as this one.
+        draw_order_group_objects.indices.range(
+            "draw_order_groups.object_sources_starts",
+            object_sources_start,
+            object_sources_count,
+        )?;
+
+        for a in object_sources_start..object_sources_start + object_sources_count {
             let type_index = draw_order_group_objects.indices[a];
-            let to_append =
-                if draw_order_group_objects.types[a] == DrawOrderGroupObjectType::ArtMesh {
-                    DrawOrderNode::ArtMesh { index: type_index }
-                } else {
-                    DrawOrderNode::Part { index: type_index }
-                };
-
-            let res = draw_order_indices_to_node_ids[i]
-                .unwrap()
-                .append_value(to_append, &mut draw_order_nodes);
             let self_index = draw_order_group_objects.self_indices[a];
-            if self_index != -1 {
-                draw_order_indices_to_node_ids[self_index as usize] = Some(res);
-            }
+            let child = match group_nodes.get(self_index as usize) {
+                Some(owned) => *owned,
+                None if draw_order_group_objects.types[a] == DrawOrderGroupObjectType::ArtMesh => {
+                    LayoutError::check_index(
+                        "draw_order_group_objects.indices",
+                        type_index as u64,
+                        counts.art_meshes as usize,
+                    )?;
+                    draw_order_nodes.new_node(DrawOrderNode::ArtMesh { index: type_index })
+                }
+                None => {
+                    LayoutError::check_index(
+                        "draw_order_group_objects.indices",
+                        type_index as u64,
+                        counts.parts as usize,
+                    )?;
+                    draw_order_nodes.new_node(DrawOrderNode::Part {
+                        index: type_index,
+                        range: None,
+                    })
+                }
+            };
+
+            // Malformed files can have groups inside of themselves, which are left out.
+            let _ = group.checked_append(child, &mut draw_order_nodes);
         }
     }
+    let draw_order_roots: Vec<NodeId> = group_nodes
+        .iter()
+        .copied()
+        .filter(|x| draw_order_nodes[*x].parent().is_none())
+        .collect();
 
     // Here we parse all of the data related to parameters onto the puppet. Right now,
     // only the default value is saved, but this will be filled with all of the other data
     // in the future.
 
-    let mut warp_deformer_grid_count = Vec::new();
-    for i in 0..read.table.count_info.warp_deformers as usize {
-        // Fencepost error warning: rows and columns measure the user-visbile middle, not the edges
-        // containg the numbers.
-        warp_deformer_grid_count
-            .push((warp_deformers.rows[i] + 1) * (warp_deformers.columns[i] + 1));
+    // These are the warp deformers' point counts, as the grids were checked to add up to them.
+    let warp_deformer_grid_count: Vec<u32> = warp_grid_sizes
+        .iter()
+        .map(|(rows, columns)| (rows + 1) * (columns + 1))
+        .collect();
+
+    // Keyforms are laid out over the keys of every binding, which interpolating indexes
+    // straight into. Blend shapes were checked when they were collected.
+    for applicator in applicators.iter().filter(|x| x.blend.is_none()) {
+        let (what, len) = match &applicator.values {
+            ApplicatorKind::ArtMesh(..) => ("art_meshes.keyform_sources_counts", counts.art_meshes),
+            ApplicatorKind::WarpDeformer(..) => (
+                "warp_deformers.keyform_sources_counts",
+                counts.warp_deformers,
+            ),
+            ApplicatorKind::RotationDeformer(..) => (
+                "rotation_deformers.keyform_sources_counts",
+                counts.rotation_deformers,
+            ),
+            ApplicatorKind::Glue(_) => ("glues.keyform_sources_counts", counts.glues),
+            ApplicatorKind::Part(_) => ("parts.keyform_sources_counts", counts.parts),
+        };
+        let expected = applicator
+            .data
+            .iter()
+            .map(|x| bindings.bindings[*x].keys.len() as u64)
+            .fold(1, u64::saturating_mul);
+        if applicator.values.keyform_count() as u64 != expected {
+            let index = applicator.kind_index as usize;
+            return Err(LayoutError::malformed(what, index..index + 1, len as usize).into());
+        }
+    }
+
+    // Updates read every keyform's points straight out of the shared positions.
+    for applicator in &applicators {
+        let (starts, len, what) = match &applicator.values {
+            ApplicatorKind::ArtMesh(starts, ..) => (
+                starts,
+                art_meshes.vertex_counts[applicator.kind_index as usize],
+                "art_mesh_keyforms.keyform_position_sources_starts",
+            ),
+            ApplicatorKind::WarpDeformer(starts, ..) => (
+                starts,
+                warp_deformer_grid_count[applicator.kind_index as usize],
+                "warp_deformer_keyforms.keyform_position_sources_starts",
+            ),
+            _ => continue,
+        };
+        for start in starts {
+            keyform_positions.range(what, *start as usize, len as usize)?;
+        }
     }
 
     let params = collect_param_data(read);
 
-    Puppet {
+    Ok(PuppetRef {
         node_roots,
         nodes: node_arena,
 
@@ -939,8 +1953,23 @@ pub fn puppet_from_moc3(read: &Moc3Data) -> Puppet {
         part_roots,
         parts: part_arena,
 
+        applicators_by_param: applicators_by_param(
+            params.count as usize,
+            &applicators,
+            &bindings.bindings,
+        ),
         params,
+        keyform_edits: 0,
+        applicator_edits: vec![0; applicators.len()],
         applicators,
+        bindings: bindings.bindings,
+        keyform_positions,
+
+        canvas: Canvas {
+            size: Vec2::new(canvas_info.canvas_width, canvas_info.canvas_height),
+            origin: Vec2::new(canvas_info.x_origin, canvas_info.y_origin),
+            pixels_per_unit: canvas_info.pixels_per_unit,
+        },
 
         art_mesh_count: read.table.count_info.art_meshes,
         warp_deformer_count: read.table.count_info.warp_deformers,
@@ -956,13 +1985,20 @@ pub fn puppet_from_moc3(read: &Moc3Data) -> Puppet {
         art_mesh_flags: read.table.art_meshes.art_mesh_flags.clone(),
         art_mesh_mask_indices,
         art_mesh_vertexes: read.table.art_meshes.vertex_counts.clone(),
+        art_mesh_user_data: vec![None; read.table.count_info.art_meshes as usize],
 
         draw_order_nodes,
-        draw_order_root: draw_order_indices_to_node_ids[0].unwrap(),
-    }
+        draw_order_roots,
+
+        art_mesh_ids: IdTable::new(&read.table.art_meshes.ids),
+        deformer_ids: IdTable::new(&read.table.deformers.ids),
+        deformer_kinds: ids::deformer_kinds(&read.table.deformers),
+        part_ids: IdTable::new(&read.table.parts.ids),
+        glue_ids: IdTable::new(&read.table.glues.ids),
+    })
 }
 
-pub fn framedata_for_puppet(puppet: &Puppet) -> PuppetFrameData {
+pub fn framedata_for_puppet(puppet: &PuppetRef) -> PuppetFrameData {
     let mut warp_deformer_data = Vec::new();
     for count in &puppet.warp_deformer_grid_count {
         warp_deformer_data.push(vec![Vec2::NAN; *count as usize]);
@@ -973,14 +2009,20 @@ pub fn framedata_for_puppet(puppet: &Puppet) -> PuppetFrameData {
         art_mesh_data.push(vec![Vec2::NAN; *count as usize]);
     }
 
+    let draw_order_node_count = puppet.art_mesh_count as usize + puppet.part_count as usize + 1;
+
     PuppetFrameData {
+        input_sanitization: InputSanitization::default(),
+        sanitized_params: Vec::new(),
+
         corrected_params: puppet.params.defaults.clone(),
+        binding_cells: vec![BindingCell::default(); puppet.bindings.len()],
         calculated_part_opacities: vec![1.0; puppet.part_count as usize],
 
         art_mesh_draw_orders: vec![0.0; puppet.art_mesh_count as usize],
         part_draw_orders: vec![0.0; puppet.part_count as usize],
 
-        art_mesh_render_orders: vec![0; puppet.art_mesh_count as usize],
+        art_mesh_render_orders: vec![0; puppet.drawn_art_mesh_count()],
 
         art_mesh_data,
         art_mesh_opacities: vec![0.0; puppet.art_mesh_count as usize],
@@ -999,5 +2041,34 @@ pub fn framedata_for_puppet(puppet: &Puppet) -> PuppetFrameData {
                 + puppet.rotation_deformer_count as usize
         ],
         glue_data: vec![f32::NAN; puppet.glue_count as usize],
+        glue_normalization: GlueNormalization::default(),
+        warp_extrapolation: WarpExtrapolation::default(),
+        force_enabled: false,
+        dirty: DirtyFlags::new(puppet),
+        angle_fixups: vec![AngleFixup::NONE; puppet.rotation_deformer_count as usize],
+        keyform_edits: puppet.keyform_edits,
+        deferred: None,
+        precise: None,
+        glue_deltas: Vec::with_capacity(
+            puppet
+                .glue_nodes
+                .iter()
+                .map(|x| x.mesh_indices.len())
+                .max()
+                .unwrap_or(0),
+        ),
+
+        param_velocities: vec![0.0; puppet.params.count as usize],
+        art_mesh_velocities: vec![Vec2::ZERO; puppet.art_mesh_count as usize],
+        velocity_history: false,
+        previous_params: puppet.params.defaults.clone(),
+        previous_centroids: vec![Vec2::ZERO; puppet.art_mesh_count as usize],
+
+        render_order_stale: true,
+        // Every node (an art mesh or part, plus the root) is in the scratch space at most
+        // once, and the stack can't be any deeper than there are nodes, so these never need
+        // to grow.
+        draw_order_scratch: Vec::with_capacity(draw_order_node_count),
+        draw_order_stack: Vec::with_capacity(draw_order_node_count),
     }
 }
@@ -1,4 +1,5 @@
 #[derive(Debug, Clone)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
 pub struct DeformerNode {
     pub data: NodeKind,
     pub broad_index: u32,
@@ -8,6 +9,7 @@ pub struct DeformerNode {
 }
 
 #[derive(Debug, Clone)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
 pub struct PartNode {
     pub id: String,
     pub kind_index: u32,
@@ -16,6 +18,7 @@ pub struct PartNode {
 }
 
 #[derive(Debug, Clone)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
 pub enum NodeKind {
     ArtMesh(ArtMeshData),
     WarpDeformer(WarpDeformerData, u32),
@@ -23,11 +26,13 @@ pub enum NodeKind {
 }
 
 #[derive(Debug, Clone)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
 pub struct ArtMeshData {
     pub vertexes: u32,
 }
 
 #[derive(Debug, Clone)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
 pub struct WarpDeformerData {
     pub rows: u32,
     pub columns: u32,
@@ -35,15 +40,33 @@ pub struct WarpDeformerData {
 }
 
 #[derive(Debug, Clone)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
 pub struct RotationDeformerData {
     pub base_angle: f32,
 }
 
+/// Pulls pairs of vertexes from two art meshes towards each other.
 #[derive(Debug, Clone)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
 pub struct GlueNode {
     pub id: String,
     pub kind_index: u32,
+    /// The two art meshes being glued together.
     pub art_mesh_index: [u32; 2],
+    /// How far each vertex moves towards its partner, two per pair.
     pub weights: Vec<f32>,
+    /// The [GlueNode::weights] after normalization, see [super::GlueNormalization].
+    pub normalized_weights: Vec<f32>,
+    /// The vertex from each art mesh, two per pair.
     pub mesh_indices: Vec<u16>,
 }
+
+impl GlueNode {
+    /// Every glued pair of vertexes, along with the weight of each side.
+    pub fn pairs(&self) -> impl Iterator<Item = ([u16; 2], [f32; 2])> + '_ {
+        self.mesh_indices
+            .chunks_exact(2)
+            .zip(self.weights.chunks_exact(2))
+            .map(|(index, weight)| ([index[0], index[1]], [weight[0], weight[1]]))
+    }
+}
@@ -0,0 +1,273 @@
+use std::collections::HashMap;
+
+use super::{applicator::ApplicatorKind, PuppetRef};
+
+/// What [PuppetRef::optimize_meshes] managed to save.
+#[derive(Debug, Clone, Copy, Default, PartialEq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct MeshOptimizationReport {
+    /// Vertexes no triangle uses anymore, as they always end up in the same place with the
+    /// same UVs as another vertex of their mesh.
+    pub merged_vertexes: usize,
+    /// Triangles dropped for using the same vertex more than once after merging.
+    pub removed_triangles: usize,
+    /// The average number of vertexes a GPU has to process per triangle, before and after
+    /// reordering, assuming a cache of the last 32 vertexes. Lower is better, with 0.5
+    /// being about the best a regular grid can do.
+    pub acmr_before: f32,
+    pub acmr_after: f32,
+}
+
+// How many recently used vertexes the reordering assumes a GPU keeps around.
+const CACHE_SIZE: usize = 32;
+
+// Scoring from Tom Forsyth's "Linear-Speed Vertex Cache Optimisation".
+const CACHE_DECAY_POWER: f32 = 1.5;
+const LAST_TRIANGLE_SCORE: f32 = 0.75;
+const VALENCE_BOOST_SCALE: f32 = 2.0;
+const VALENCE_BOOST_POWER: f32 = 0.5;
+
+fn vertex_score(cache_position: Option<usize>, remaining_triangles: usize) -> f32 {
+    if remaining_triangles == 0 {
+        return -1.0;
+    }
+
+    let cache_score = match cache_position {
+        None => 0.0,
+        // The last triangle's vertexes get a fixed score, so the next one doesn't just
+        // pick whichever of them is the newest.
+        Some(x) if x < 3 => LAST_TRIANGLE_SCORE,
+        Some(x) => {
+            let scale = 1.0 / (CACHE_SIZE - 3) as f32;
+            (1.0 - (x - 3) as f32 * scale).powf(CACHE_DECAY_POWER)
+        }
+    };
+    // Vertexes with few triangles left get finished off first, so they don't linger.
+    cache_score + VALENCE_BOOST_SCALE * (remaining_triangles as f32).powf(-VALENCE_BOOST_POWER)
+}
+
+// Runs the indexes through a cache of recently used vertexes, giving how many vertexes had
+// to be processed per triangle.
+fn average_cache_miss_ratio(indices: &[u16]) -> f32 {
+    if indices.len() < 3 {
+        return 0.0;
+    }
+
+    let mut cache: Vec<u16> = Vec::with_capacity(CACHE_SIZE + 1);
+    let mut misses = 0;
+    for &index in indices {
+        if let Some(position) = cache.iter().position(|x| *x == index) {
+            cache.remove(position);
+        } else {
+            misses += 1;
+        }
+        cache.insert(0, index);
+        cache.truncate(CACHE_SIZE);
+    }
+    misses as f32 / (indices.len() / 3) as f32
+}
+
+// Reorders triangles so ones sharing vertexes are drawn close together, keeping each
+// triangle's own winding.
+fn reorder_for_cache(indices: &[u16], vertex_count: usize) -> Vec<u16> {
+    let triangles: Vec<[usize; 3]> = indices
+        .chunks_exact(3)
+        .map(|x| [x[0] as usize, x[1] as usize, x[2] as usize])
+        .collect();
+
+    let mut vertex_triangles: Vec<Vec<usize>> = vec![Vec::new(); vertex_count];
+    for (i, triangle) in triangles.iter().enumerate() {
+        for &vertex in triangle {
+            vertex_triangles[vertex].push(i);
+        }
+    }
+
+    let mut vertex_scores: Vec<f32> = vertex_triangles
+        .iter()
+        .map(|x| vertex_score(None, x.len()))
+        .collect();
+    let mut triangle_scores: Vec<f32> = triangles
+        .iter()
+        .map(|x| x.iter().map(|v| vertex_scores[*v]).sum())
+        .collect();
+    let mut added = vec![false; triangles.len()];
+
+    let mut cache: Vec<usize> = Vec::with_capacity(CACHE_SIZE + 3);
+    let mut out = Vec::with_capacity(indices.len());
+    let mut best =
+        (0..triangles.len()).max_by(|a, b| triangle_scores[*a].total_cmp(&triangle_scores[*b]));
+
+    while let Some(triangle) = best {
+        added[triangle] = true;
+        for &vertex in &triangles[triangle] {
+            out.push(vertex as u16);
+            vertex_triangles[vertex].retain(|x| *x != triangle);
+            cache.retain(|x| *x != vertex);
+        }
+        for &vertex in triangles[triangle].iter().rev() {
+            cache.insert(0, vertex);
+        }
+
+        // Everything in the cache moved, and whatever fell out of it lost its cache score.
+        let evicted = cache.split_off(cache.len().min(CACHE_SIZE));
+        for vertex in evicted {
+            vertex_scores[vertex] = vertex_score(None, vertex_triangles[vertex].len());
+        }
+        for (position, &vertex) in cache.iter().enumerate() {
+            vertex_scores[vertex] = vertex_score(Some(position), vertex_triangles[vertex].len());
+        }
+
+        // The next triangle is almost always one touching the cache, so only those get
+        // rescored and looked at.
+        best = None;
+        let mut best_score = f32::NEG_INFINITY;
+        for &vertex in &cache {
+            for &other in &vertex_triangles[vertex] {
+                let score = triangles[other].iter().map(|v| vertex_scores[*v]).sum();
+                triangle_scores[other] = score;
+                if score > best_score {
+                    best = Some(other);
+                    best_score = score;
+                }
+            }
+        }
+        if best.is_none() {
+            best = (0..triangles.len())
+                .filter(|x| !added[*x])
+                .max_by(|a, b| triangle_scores[*a].total_cmp(&triangle_scores[*b]));
+        }
+    }
+
+    out
+}
+
+impl PuppetRef<'_> {
+    /// Merges vertexes that can never be told apart and reorders every art mesh's
+    /// triangles so GPUs can reuse more of the vertexes they've already processed, which
+    /// helps with the dense meshes automatic mesh generation makes. Meant to be called
+    /// right after building the puppet.
+    ///
+    /// Only [PuppetRef::art_mesh_indices] changes, so vertexes keep their indexes and
+    /// everything indexed by them (keyforms, glues, frame data) stays as it was. Merged
+    /// vertexes are still deformed, just no longer drawn.
+    pub fn optimize_meshes(&mut self) -> MeshOptimizationReport {
+        let mut report = MeshOptimizationReport::default();
+        let mesh_count = self.art_mesh_count as usize;
+
+        // Every keyform of every mesh, to tell whether two vertexes always end up together.
+        // These are the applicator and keyform, as they might not be in the shared table.
+        let mut keyforms: Vec<Vec<(usize, usize)>> = vec![Vec::new(); mesh_count];
+        for (i, applicator) in self.applicators.iter().enumerate() {
+            if let ApplicatorKind::ArtMesh(starts, ..) = &applicator.values {
+                keyforms[applicator.kind_index as usize].extend((0..starts.len()).map(|x| (i, x)));
+            }
+        }
+
+        // Glues move vertexes one by one, so glued ones have to stay as they are.
+        let mut glued: Vec<Vec<bool>> = self
+            .art_mesh_vertexes
+            .iter()
+            .map(|x| vec![false; *x as usize])
+            .collect();
+        for glue in &self.glue_nodes {
+            for (i, vertex) in glue.mesh_indices.iter().enumerate() {
+                let mesh = glue.art_mesh_index[i % 2] as usize;
+                if let Some(x) = glued
+                    .get_mut(mesh)
+                    .and_then(|x| x.get_mut(*vertex as usize))
+                {
+                    *x = true;
+                }
+            }
+        }
+
+        let mut triangles_before = 0;
+        let mut misses_before = 0.0;
+        let mut triangles_after = 0;
+        let mut misses_after = 0.0;
+
+        for mesh in 0..mesh_count {
+            let vertex_count = self.art_mesh_vertexes[mesh] as usize;
+            let uvs = &self.art_mesh_uvs[mesh];
+            let positions = &self.keyform_positions;
+            let indices = &mut self.art_mesh_indices[mesh];
+            // Meshes pointing outside of their own data are left for whatever reads them to
+            // deal with.
+            if uvs.len() < vertex_count
+                || indices.iter().any(|x| *x as usize >= vertex_count)
+                || keyforms[mesh].iter().any(|(applicator, keyform)| {
+                    let applicator = &self.applicators[*applicator];
+                    let ApplicatorKind::ArtMesh(starts, ..) = &applicator.values else {
+                        return false;
+                    };
+                    applicator.sparse.is_none()
+                        && starts[*keyform] as usize + vertex_count > positions.len()
+                })
+            {
+                continue;
+            }
+
+            let mut canonical: Vec<u16> = (0..vertex_count as u16).collect();
+            let mut seen: HashMap<Vec<u32>, u16> = HashMap::new();
+            for vertex in 0..vertex_count {
+                if glued[mesh][vertex] {
+                    continue;
+                }
+                // Compared bit for bit, as merging vertexes that are only close would move
+                // them.
+                let mut key = vec![uvs[vertex].x.to_bits(), uvs[vertex].y.to_bits()];
+                for (applicator, keyform) in &keyforms[mesh] {
+                    let position =
+                        self.applicators[*applicator].keyform_point(positions, *keyform, vertex);
+                    key.extend([position.x.to_bits(), position.y.to_bits()]);
+                }
+                let first = *seen.entry(key).or_insert(vertex as u16);
+                if first as usize != vertex {
+                    canonical[vertex] = first;
+                }
+            }
+
+            let triangle_count = indices.len() / 3;
+            triangles_before += triangle_count;
+            misses_before += average_cache_miss_ratio(indices) * triangle_count as f32;
+
+            let used_before = used_vertexes(indices, vertex_count);
+            let merged: Vec<u16> = indices
+                .chunks_exact(3)
+                .map(|x| [x[0], x[1], x[2]].map(|v| canonical[v as usize]))
+                .filter(|[a, b, c]| a != b && b != c && a != c)
+                .flatten()
+                .collect();
+            report.removed_triangles += triangle_count - merged.len() / 3;
+            report.merged_vertexes += used_before - used_vertexes(&merged, vertex_count);
+
+            // Meshes that were already in a good order (like rows of a grid) can come out
+            // slightly worse, so those keep theirs.
+            let reordered = reorder_for_cache(&merged, vertex_count);
+            let (misses, best) = [merged, reordered]
+                .map(|x| (average_cache_miss_ratio(&x), x))
+                .into_iter()
+                .min_by(|a, b| a.0.total_cmp(&b.0))
+                .unwrap();
+            *indices = best;
+            triangles_after += indices.len() / 3;
+            misses_after += misses * (indices.len() / 3) as f32;
+        }
+
+        if triangles_before > 0 {
+            report.acmr_before = misses_before / triangles_before as f32;
+        }
+        if triangles_after > 0 {
+            report.acmr_after = misses_after / triangles_after as f32;
+        }
+        report
+    }
+}
+
+fn used_vertexes(indices: &[u16], vertex_count: usize) -> usize {
+    let mut used = vec![false; vertex_count];
+    for x in indices {
+        used[*x as usize] = true;
+    }
+    used.iter().filter(|x| **x).count()
+}
@@ -0,0 +1,224 @@
+use glam::Vec2;
+
+use crate::deformer::warp_deformer::WarpExtrapolation;
+
+use super::{
+    applicator::{ApplicatorKind, ParamApplicator, ParamBinding},
+    apply_glue_node,
+    node::{DeformerNode, NodeKind},
+    PuppetFrameData, PuppetRef,
+};
+
+// For every parameter, the indices of the applicators that read from it, in the order
+// they're applied.
+pub(super) fn applicators_by_param(
+    param_count: usize,
+    applicators: &[ParamApplicator],
+    bindings: &[ParamBinding],
+) -> Vec<Vec<u32>> {
+    let mut by_param = vec![Vec::new(); param_count];
+    for (i, applicator) in applicators.iter().enumerate() {
+        let params = applicator
+            .data
+            .iter()
+            .map(|x| bindings[*x].parameter_index)
+            .chain(applicator.blend.iter().flatten().map(|x| x.parameter_index));
+
+        for param in params {
+            let list: &mut Vec<u32> = &mut by_param[param];
+            if list.last() != Some(&(i as u32)) {
+                list.push(i as u32);
+            }
+        }
+    }
+
+    by_param
+}
+
+// What needs to be redone in a partial update, kept in the frame data to avoid allocating.
+#[derive(Debug, Clone, Default)]
+pub(super) struct DirtyFlags {
+    applicators: Vec<bool>,
+    art_meshes: Vec<bool>,
+    warp_deformers: Vec<bool>,
+    rotation_deformers: Vec<bool>,
+    glues: Vec<bool>,
+}
+
+impl DirtyFlags {
+    pub(super) fn new(puppet: &PuppetRef) -> Self {
+        Self {
+            applicators: vec![false; puppet.applicators.len()],
+            art_meshes: vec![false; puppet.art_mesh_count as usize],
+            warp_deformers: vec![false; puppet.warp_deformer_count as usize],
+            rotation_deformers: vec![false; puppet.rotation_deformer_count as usize],
+            glues: vec![false; puppet.glue_count as usize],
+        }
+    }
+
+    fn fill(&mut self, dirty: bool) {
+        self.applicators.fill(dirty);
+        self.art_meshes.fill(dirty);
+        self.warp_deformers.fill(dirty);
+        self.rotation_deformers.fill(dirty);
+        self.glues.fill(dirty);
+    }
+
+    fn node(&mut self, node: &DeformerNode) -> &mut bool {
+        match node.data {
+            NodeKind::ArtMesh(_) => &mut self.art_meshes[node.broad_index as usize],
+            NodeKind::WarpDeformer(_, index) => &mut self.warp_deformers[index as usize],
+            NodeKind::RotationDeformer(_, index) => &mut self.rotation_deformers[index as usize],
+        }
+    }
+
+    fn target(&mut self, applicator: &ParamApplicator) -> Option<&mut bool> {
+        let index = applicator.kind_index as usize;
+        match applicator.values {
+            ApplicatorKind::ArtMesh(..) => Some(&mut self.art_meshes[index]),
+            ApplicatorKind::WarpDeformer(..) => Some(&mut self.warp_deformers[index]),
+            ApplicatorKind::RotationDeformer(..) => Some(&mut self.rotation_deformers[index]),
+            ApplicatorKind::Glue(_) => Some(&mut self.glues[index]),
+            // Parts only have draw orders, which don't affect anything else.
+            ApplicatorKind::Part(_) => None,
+        }
+    }
+}
+
+// How a warp deformer moved and turned a rotation deformer in it, worked out from the
+// rotation deformer's blended origin. A partial update redoing the rotation deformer but
+// not its parent can reuse this while the origin blends to the same spot, and the grid
+// extrapolates the same way.
+#[derive(Debug, Clone, Copy)]
+pub(super) struct AngleFixup {
+    pub(super) blended_origin: Vec2,
+    pub(super) extrapolation: WarpExtrapolation,
+    pub(super) origin: Vec2,
+    pub(super) angle: f32,
+}
+
+impl AngleFixup {
+    pub(super) const NONE: Self = Self {
+        blended_origin: Vec2::NAN,
+        extrapolation: WarpExtrapolation::CubismApprox,
+        origin: Vec2::NAN,
+        angle: f32::NAN,
+    };
+}
+
+impl PuppetRef<'_> {
+    /// Updates the frame data for a handful of changed parameters, given as
+    /// `(index, value)` pairs, redoing only the work that depends on them. This gives the
+    /// same result as a full [PuppetRef::update] with the new values, which suits editors
+    /// scrubbing a single slider.
+    ///
+    /// Everything else, including the part opacities, is kept from the last update, so a
+    /// full update must have been done with this frame data first.
+    pub fn update_partial(
+        &self,
+        changed_params: &[(usize, f32)],
+        frame_data: &mut PuppetFrameData,
+    ) {
+        frame_data.sanitized_params.clear();
+        for (index, input) in changed_params.iter().copied() {
+            self.correct_param(index, input, frame_data);
+        }
+
+        for (cell, binding) in frame_data.binding_cells.iter_mut().zip(&self.bindings) {
+            if changed_params
+                .iter()
+                .any(|(index, _)| *index == binding.parameter_index)
+            {
+                *cell = binding.cell(&frame_data.corrected_params);
+            }
+        }
+
+        // Art meshes that were left to someone else have nothing to start from.
+        let redo_all = self.stop_deferring_if_compacted(frame_data);
+
+        let mut dirty = std::mem::take(&mut frame_data.dirty);
+        dirty.fill(redo_all);
+
+        for (index, _) in changed_params {
+            for applicator in self.applicators_by_param[*index].iter().copied() {
+                dirty.applicators[applicator as usize] = true;
+                if let Some(target) = dirty.target(&self.applicators[applicator as usize]) {
+                    *target = true;
+                }
+            }
+        }
+
+        // Keyforms edited since the last update need redoing as well.
+        for (i, edits) in self.applicator_edits.iter().enumerate() {
+            if *edits > frame_data.keyform_edits {
+                dirty.applicators[i] = true;
+                if let Some(target) = dirty.target(&self.applicators[i]) {
+                    *target = true;
+                }
+            }
+        }
+        frame_data.keyform_edits = self.keyform_edits;
+
+        // Anything under a changed deformer moves along with it.
+        for root_id in self.node_roots.iter().copied() {
+            for child_id in root_id.descendants(&self.nodes).skip(1) {
+                let parent_id = self.nodes[child_id]
+                    .parent()
+                    .expect("node should be child node");
+
+                if *dirty.node(self.nodes[parent_id].get()) {
+                    *dirty.node(self.nodes[child_id].get()) = true;
+                }
+            }
+        }
+
+        // Glues change both of their art meshes in place, so if either one is redone, the
+        // other has to be redone too. This can chain through several glues.
+        let mut changed = true;
+        while changed {
+            changed = false;
+            for glue in &self.glue_nodes {
+                let flags = [
+                    dirty.glues[glue.kind_index as usize],
+                    dirty.art_meshes[glue.art_mesh_index[0] as usize],
+                    dirty.art_meshes[glue.art_mesh_index[1] as usize],
+                ];
+
+                if flags.contains(&true) && flags.contains(&false) {
+                    dirty.glues[glue.kind_index as usize] = true;
+                    dirty.art_meshes[glue.art_mesh_index[0] as usize] = true;
+                    dirty.art_meshes[glue.art_mesh_index[1] as usize] = true;
+                    changed = true;
+                }
+            }
+        }
+
+        for (i, applicator) in self.applicators.iter().enumerate() {
+            let target = dirty.target(applicator).map(|x| *x).unwrap_or(false);
+            if dirty.applicators[i] || target {
+                applicator.apply(&self.keyform_positions, frame_data);
+            }
+        }
+
+        for root_id in self.node_roots.iter().copied() {
+            for id in root_id.descendants(&self.nodes) {
+                if *dirty.node(self.nodes[id].get()) {
+                    let parent_clean = self.nodes[id]
+                        .parent()
+                        .is_some_and(|parent| !*dirty.node(self.nodes[parent].get()));
+                    self.deform_node(id, parent_clean, frame_data);
+                }
+            }
+        }
+
+        for glue in &self.glue_nodes {
+            if dirty.glues[glue.kind_index as usize] {
+                apply_glue_node(glue, frame_data);
+            }
+        }
+
+        frame_data.dirty = dirty;
+
+        self.refresh_render_order(frame_data);
+    }
+}
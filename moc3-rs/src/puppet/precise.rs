@@ -0,0 +1,205 @@
+use std::mem::take;
+
+use glam::DVec2;
+
+use crate::deformer::{
+    rotation_deformer::{
+        calculate_rotation_deformer_angle_f64, rotation_deformer_matrix_f64,
+        rotation_deformer_turn_f64,
+    },
+    warp_deformer::{apply_warp_deformer_f64, WarpExtrapolation},
+};
+
+use super::{
+    node::{DeformerNode, NodeKind},
+    warp_grid, PuppetFrameData, PuppetRef,
+};
+
+// Deformers as of the last update in double precision, which children are deformed by
+// instead of the rounded copies in the frame data.
+#[derive(Debug, Clone)]
+pub(super) struct PreciseState {
+    warp_grids: Vec<Vec<DVec2>>,
+    rotations: Vec<PreciseRotation>,
+    // Indexed like the frame data's deformer scales.
+    scales: Vec<f64>,
+    // Scratch space for art meshes, which are rounded down again as soon as they're done.
+    vertexes: Vec<DVec2>,
+}
+
+#[derive(Debug, Clone, Copy)]
+struct PreciseRotation {
+    origin: DVec2,
+    angle: f64,
+}
+
+impl PreciseState {
+    fn new(frame_data: &PuppetFrameData) -> Self {
+        Self {
+            warp_grids: vec![Vec::new(); frame_data.warp_deformer_data.len()],
+            rotations: vec![
+                PreciseRotation {
+                    origin: DVec2::NAN,
+                    angle: f64::NAN,
+                };
+                frame_data.rotation_deformer_data.len()
+            ],
+            scales: vec![f64::NAN; frame_data.deformer_scale_data.len()],
+            vertexes: Vec::new(),
+        }
+    }
+
+    // Moves points the way the given deformer does, which must already be fully deformed.
+    fn transform(
+        &self,
+        parent: &DeformerNode,
+        extrapolation: WarpExtrapolation,
+        points: &mut [DVec2],
+    ) {
+        match &parent.data {
+            NodeKind::ArtMesh(_) => unreachable!("art mesh should not have children"),
+            NodeKind::WarpDeformer(data, ind) => apply_warp_deformer_f64(
+                warp_grid(&self.warp_grids[*ind as usize], data),
+                data.is_new_deformerr,
+                extrapolation,
+                points,
+            ),
+            NodeKind::RotationDeformer(data, ind) => {
+                let rotation = self.rotations[*ind as usize];
+                let matrix = rotation_deformer_matrix_f64(
+                    rotation.origin,
+                    self.scales[parent.broad_index as usize],
+                    rotation.angle,
+                    data.base_angle,
+                );
+                for point in points {
+                    *point = matrix.transform_point2(*point);
+                }
+            }
+        }
+    }
+}
+
+impl PuppetRef<'_> {
+    // Starts off a root deformer from what its keyforms blended to.
+    pub(super) fn start_precise(&self, root: &DeformerNode, frame_data: &mut PuppetFrameData) {
+        let Some(precise) = &mut frame_data.precise else {
+            return;
+        };
+
+        match &root.data {
+            NodeKind::ArtMesh(_) => {}
+            NodeKind::WarpDeformer(_, ind) => {
+                let grid = &frame_data.warp_deformer_data[*ind as usize];
+                let precise_grid = &mut precise.warp_grids[*ind as usize];
+                precise_grid.clear();
+                precise_grid.extend(grid.iter().map(|x| x.as_dvec2()));
+                precise.scales[root.broad_index as usize] = 1.0;
+            }
+            NodeKind::RotationDeformer(_, ind) => {
+                let data = frame_data.rotation_deformer_data[*ind as usize];
+                precise.rotations[*ind as usize] = PreciseRotation {
+                    origin: data.origin.as_dvec2(),
+                    angle: data.angle as f64,
+                };
+                precise.scales[root.broad_index as usize] = data.scale as f64;
+            }
+        }
+    }
+
+    // Moves a child by its parent like deform_child would, keeping deformers in double
+    // precision and rounding everything into the frame data. The child must still be as its
+    // keyforms blended it, and its opacity, color and scale in the frame data are left alone.
+    pub(super) fn deform_precise(
+        &self,
+        parent: &DeformerNode,
+        child: &DeformerNode,
+        frame_data: &mut PuppetFrameData,
+    ) {
+        let extrapolation = frame_data.warp_extrapolation;
+        let Some(precise) = &mut frame_data.precise else {
+            return;
+        };
+
+        match &child.data {
+            NodeKind::ArtMesh(_) => {
+                // There are no vertexes to move while deforming is deferred.
+                if frame_data.deferred.is_some() {
+                    return;
+                }
+
+                let vertexes = &mut frame_data.art_mesh_data[child.broad_index as usize];
+                let mut precise_vertexes = take(&mut precise.vertexes);
+                precise_vertexes.clear();
+                precise_vertexes.extend(vertexes.iter().map(|x| x.as_dvec2()));
+                precise.transform(parent, extrapolation, &mut precise_vertexes);
+                for (vertex, precise_vertex) in vertexes.iter_mut().zip(&precise_vertexes) {
+                    *vertex = precise_vertex.as_vec2();
+                }
+                precise.vertexes = precise_vertexes;
+            }
+            NodeKind::WarpDeformer(_, ind) => {
+                let grid = &mut frame_data.warp_deformer_data[*ind as usize];
+                let mut precise_grid = take(&mut precise.warp_grids[*ind as usize]);
+                precise_grid.clear();
+                precise_grid.extend(grid.iter().map(|x| x.as_dvec2()));
+                precise.transform(parent, extrapolation, &mut precise_grid);
+                for (point, precise_point) in grid.iter_mut().zip(&precise_grid) {
+                    *point = precise_point.as_vec2();
+                }
+                precise.warp_grids[*ind as usize] = precise_grid;
+                precise.scales[child.broad_index as usize] =
+                    precise.scales[parent.broad_index as usize];
+            }
+            NodeKind::RotationDeformer(_, ind) => {
+                let data = &mut frame_data.rotation_deformer_data[*ind as usize];
+                let transform = |point| {
+                    let mut ret = point;
+                    precise.transform(parent, extrapolation, std::slice::from_mut(&mut ret));
+                    ret
+                };
+
+                // Worked out the same way as deform_child, so both turn children alike.
+                let origin = data.origin.as_dvec2();
+                let angle_diff = match &parent.data {
+                    NodeKind::RotationDeformer(parent_data, ind) => rotation_deformer_turn_f64(
+                        precise.scales[parent.broad_index as usize],
+                        precise.rotations[*ind as usize].angle,
+                        parent_data.base_angle,
+                    ),
+                    _ => calculate_rotation_deformer_angle_f64(origin, 0.1, transform),
+                };
+                let rotation = PreciseRotation {
+                    origin: transform(origin),
+                    angle: data.angle as f64 + angle_diff,
+                };
+
+                data.origin = rotation.origin.as_vec2();
+                data.angle = rotation.angle as f32;
+                precise.rotations[*ind as usize] = rotation;
+                precise.scales[child.broad_index as usize] =
+                    data.scale as f64 * precise.scales[parent.broad_index as usize];
+            }
+        }
+    }
+}
+
+impl PuppetFrameData {
+    pub fn high_precision(&self) -> bool {
+        self.precise.is_some()
+    }
+
+    /// Keeps deformers in double precision between each other while updating, rounding
+    /// only what ends up in the frame data. Models nesting many warp and rotation deformers
+    /// otherwise pile up single precision error at every level, which shows as jitter when
+    /// zoomed far in.
+    ///
+    /// Deforming this way is a fair bit slower, so it's meant for captures and other offline
+    /// work. Keyforms are still blended in single precision, and hooks see and change the
+    /// rounded values as usual. A full update is needed after turning this on.
+    pub fn set_high_precision(&mut self, high_precision: bool) {
+        if high_precision != self.precise.is_some() {
+            self.precise = high_precision.then(|| PreciseState::new(self));
+        }
+    }
+}
@@ -0,0 +1,326 @@
+use std::fmt::Write;
+
+use glam::{Vec2, Vec3};
+use thiserror::Error;
+
+use super::{BlendColor, PuppetFrameData};
+
+/// Everything an update decides about how a puppet looks, copied out of
+/// [PuppetFrameData] to compare against later, see [PuppetFrameData::snapshot].
+///
+/// [FrameSnapshot::to_text] and [FrameSnapshot::from_text] store these as plain text, so
+/// they can be checked in as test vectors or written by other runtimes to compare against.
+#[derive(Debug, Clone, PartialEq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct FrameSnapshot {
+    pub art_mesh_positions: Vec<Vec<Vec2>>,
+    pub art_mesh_opacities: Vec<f32>,
+    pub art_mesh_colors: Vec<BlendColor>,
+    pub render_order: Vec<u32>,
+}
+
+/// How far apart two snapshots can be and still match, see [FrameSnapshot::compare].
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct SnapshotTolerance {
+    /// In model coordinates, per axis.
+    pub position: f32,
+    pub opacity: f32,
+    /// Per channel, for both multiply and screen colors.
+    pub color: f32,
+    /// Whether the render order has to match. Art meshes with the same draw order can end
+    /// up either way around depending on rounding, so this can be turned off when
+    /// comparing against other runtimes.
+    pub render_order: bool,
+}
+
+impl Default for SnapshotTolerance {
+    fn default() -> Self {
+        Self {
+            position: 1e-4,
+            opacity: 1e-4,
+            color: 1e-4,
+            render_order: true,
+        }
+    }
+}
+
+/// Where two snapshots first differ, from [FrameSnapshot::compare].
+#[derive(Error, Debug, Clone, PartialEq)]
+pub enum SnapshotMismatch {
+    #[error("expected {expected} art meshes, got {actual}")]
+    ArtMeshCount { expected: usize, actual: usize },
+    #[error("expected art mesh {art_mesh} to have {expected} vertexes, got {actual}")]
+    VertexCount {
+        art_mesh: usize,
+        expected: usize,
+        actual: usize,
+    },
+    #[error("expected vertex {vertex} of art mesh {art_mesh} at {expected}, got {actual}")]
+    Position {
+        art_mesh: usize,
+        vertex: usize,
+        expected: Vec2,
+        actual: Vec2,
+    },
+    #[error("expected art mesh {art_mesh} to have opacity {expected}, got {actual}")]
+    Opacity {
+        art_mesh: usize,
+        expected: f32,
+        actual: f32,
+    },
+    #[error("expected art mesh {art_mesh} to have colors {expected:?}, got {actual:?}")]
+    Color {
+        art_mesh: usize,
+        expected: BlendColor,
+        actual: BlendColor,
+    },
+    #[error("expected render order {expected:?}, got {actual:?}")]
+    RenderOrder {
+        expected: Vec<u32>,
+        actual: Vec<u32>,
+    },
+}
+
+#[derive(Error, Debug, Clone, PartialEq, Eq)]
+#[error("could not parse line {line} of snapshot")]
+pub struct SnapshotParseError {
+    pub line: usize,
+}
+
+// FNV-1a, as std's hashers aren't guaranteed to stay the same between releases.
+const FNV_OFFSET: u64 = 0xcbf2_9ce4_8422_2325;
+
+fn fnv(hash: &mut u64, x: i64) {
+    for byte in x.to_le_bytes() {
+        *hash = (*hash ^ byte as u64).wrapping_mul(0x0100_0000_01b3);
+    }
+}
+
+fn close(a: f32, b: f32, tolerance: f32) -> bool {
+    // NaNs only match each other, so a broken update still shows up as broken.
+    (a - b).abs() <= tolerance || (a.is_nan() && b.is_nan())
+}
+
+fn close_color(a: Vec3, b: Vec3, tolerance: f32) -> bool {
+    (0..3).all(|i| close(a[i], b[i], tolerance))
+}
+
+impl FrameSnapshot {
+    /// Checks `actual` against this snapshot, giving where they first differ by more than
+    /// `tolerance` if they do.
+    pub fn compare(
+        &self,
+        actual: &FrameSnapshot,
+        tolerance: SnapshotTolerance,
+    ) -> Result<(), SnapshotMismatch> {
+        if self.art_mesh_positions.len() != actual.art_mesh_positions.len() {
+            return Err(SnapshotMismatch::ArtMeshCount {
+                expected: self.art_mesh_positions.len(),
+                actual: actual.art_mesh_positions.len(),
+            });
+        }
+
+        for (art_mesh, (expected, actual)) in self
+            .art_mesh_positions
+            .iter()
+            .zip(&actual.art_mesh_positions)
+            .enumerate()
+        {
+            if expected.len() != actual.len() {
+                return Err(SnapshotMismatch::VertexCount {
+                    art_mesh,
+                    expected: expected.len(),
+                    actual: actual.len(),
+                });
+            }
+            for (vertex, (&expected, &actual)) in expected.iter().zip(actual).enumerate() {
+                if !close(expected.x, actual.x, tolerance.position)
+                    || !close(expected.y, actual.y, tolerance.position)
+                {
+                    return Err(SnapshotMismatch::Position {
+                        art_mesh,
+                        vertex,
+                        expected,
+                        actual,
+                    });
+                }
+            }
+        }
+
+        for (art_mesh, (&expected, &actual)) in self
+            .art_mesh_opacities
+            .iter()
+            .zip(&actual.art_mesh_opacities)
+            .enumerate()
+        {
+            if !close(expected, actual, tolerance.opacity) {
+                return Err(SnapshotMismatch::Opacity {
+                    art_mesh,
+                    expected,
+                    actual,
+                });
+            }
+        }
+
+        for (art_mesh, (&expected, &actual)) in self
+            .art_mesh_colors
+            .iter()
+            .zip(&actual.art_mesh_colors)
+            .enumerate()
+        {
+            if !close_color(
+                expected.multiply_color,
+                actual.multiply_color,
+                tolerance.color,
+            ) || !close_color(expected.screen_color, actual.screen_color, tolerance.color)
+            {
+                return Err(SnapshotMismatch::Color {
+                    art_mesh,
+                    expected,
+                    actual,
+                });
+            }
+        }
+
+        if tolerance.render_order && self.render_order != actual.render_order {
+            return Err(SnapshotMismatch::RenderOrder {
+                expected: self.render_order.clone(),
+                actual: actual.render_order.clone(),
+            });
+        }
+
+        Ok(())
+    }
+
+    /// A hash of the snapshot with every value rounded to a multiple of `precision`, for
+    /// telling at a glance whether two updates agree. It's the same on every platform, but
+    /// values right on a rounding boundary can still tip either way, so use
+    /// [FrameSnapshot::compare] when there's a full snapshot to compare against.
+    pub fn digest(&self, precision: f32) -> u64 {
+        let mut hash = FNV_OFFSET;
+        let mut add_float = |x: f32| fnv(&mut hash, (x / precision).round() as i64);
+        for mesh in &self.art_mesh_positions {
+            for x in mesh {
+                add_float(x.x);
+                add_float(x.y);
+            }
+        }
+        self.art_mesh_opacities.iter().for_each(|x| add_float(*x));
+        for color in &self.art_mesh_colors {
+            let channels = color.multiply_color.to_array().into_iter();
+            channels
+                .chain(color.screen_color.to_array())
+                .for_each(&mut add_float);
+        }
+
+        for x in &self.render_order {
+            fnv(&mut hash, *x as i64);
+        }
+        hash
+    }
+
+    /// Writes the snapshot as text, one art mesh per line. Values are written so they
+    /// read back exactly.
+    ///
+    /// ```text
+    /// order,<art mesh>,<art mesh>,...
+    /// mesh,<opacity>,<multiply r, g, b>,<screen r, g, b>,<x>,<y>,<x>,<y>,...
+    /// ```
+    ///
+    /// Lines starting with `#` are comments, and are skipped by [FrameSnapshot::from_text].
+    pub fn to_text(&self) -> String {
+        let mut text = String::from("order");
+        for x in &self.render_order {
+            write!(text, ",{x}").unwrap();
+        }
+        text.push('\n');
+
+        for ((positions, opacity), color) in self
+            .art_mesh_positions
+            .iter()
+            .zip(&self.art_mesh_opacities)
+            .zip(&self.art_mesh_colors)
+        {
+            write!(text, "mesh,{opacity}").unwrap();
+            for x in color
+                .multiply_color
+                .to_array()
+                .into_iter()
+                .chain(color.screen_color.to_array())
+            {
+                write!(text, ",{x}").unwrap();
+            }
+            for x in positions {
+                write!(text, ",{},{}", x.x, x.y).unwrap();
+            }
+            text.push('\n');
+        }
+
+        text
+    }
+
+    /// Reads a snapshot written by [FrameSnapshot::to_text].
+    pub fn from_text(text: &str) -> Result<Self, SnapshotParseError> {
+        let mut snapshot = FrameSnapshot {
+            art_mesh_positions: Vec::new(),
+            art_mesh_opacities: Vec::new(),
+            art_mesh_colors: Vec::new(),
+            render_order: Vec::new(),
+        };
+
+        for (number, line) in text.lines().enumerate() {
+            let line = line.trim();
+            if line.is_empty() || line.starts_with('#') {
+                continue;
+            }
+
+            let error = SnapshotParseError { line: number + 1 };
+            let mut fields = line.split(',');
+            match fields.next() {
+                Some("order") => {
+                    snapshot.render_order = fields
+                        .map(|x| x.trim().parse())
+                        .collect::<Result<_, _>>()
+                        .map_err(|_| error)?;
+                }
+                Some("mesh") => {
+                    let values: Vec<f32> = fields
+                        .map(|x| x.trim().parse())
+                        .collect::<Result<_, _>>()
+                        .map_err(|_| error.clone())?;
+                    let [opacity, mr, mg, mb, sr, sg, sb, ref positions @ ..] = values[..] else {
+                        return Err(error);
+                    };
+                    if positions.len() % 2 != 0 {
+                        return Err(error);
+                    }
+
+                    snapshot.art_mesh_opacities.push(opacity);
+                    snapshot.art_mesh_colors.push(BlendColor {
+                        multiply_color: Vec3::new(mr, mg, mb),
+                        screen_color: Vec3::new(sr, sg, sb),
+                    });
+                    snapshot
+                        .art_mesh_positions
+                        .push(positions.chunks(2).map(|x| Vec2::new(x[0], x[1])).collect());
+                }
+                _ => return Err(error),
+            }
+        }
+
+        Ok(snapshot)
+    }
+}
+
+impl PuppetFrameData {
+    /// Copies out the art meshes' positions, opacities, colors and render order as of the
+    /// last update, for regression tests or checking against other runtimes.
+    pub fn snapshot(&self) -> FrameSnapshot {
+        FrameSnapshot {
+            art_mesh_positions: self.art_mesh_data.clone(),
+            art_mesh_opacities: self.art_mesh_opacities.clone(),
+            art_mesh_colors: self.art_mesh_colors.clone(),
+            render_order: self.art_mesh_render_orders.clone(),
+        }
+    }
+}
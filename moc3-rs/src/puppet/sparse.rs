@@ -0,0 +1,215 @@
+use std::{
+    borrow::Cow,
+    collections::HashMap,
+    mem::{size_of, size_of_val},
+};
+
+use glam::Vec2;
+
+use super::{
+    applicator::{ApplicatorKind, ParamApplicator},
+    PuppetRef,
+};
+
+/// An applicator's keyforms stored as changes from its first one, as most keyforms only
+/// move a handful of vertexes. See [PuppetRef::compact_keyforms].
+#[derive(Debug, Clone)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct SparseKeyforms {
+    /// Every point of the first keyform.
+    pub base: Vec<Vec2>,
+    // Where each keyform's changes start in `points` and `deltas`, with one more for where
+    // the last one ends.
+    offsets: Vec<u32>,
+    points: Vec<u16>,
+    deltas: Vec<Vec2>,
+}
+
+impl SparseKeyforms {
+    // Keeps every change bigger than `tolerance` on either axis.
+    pub(super) fn encode<'a>(keyforms: impl Iterator<Item = &'a [Vec2]>, tolerance: f32) -> Self {
+        let mut keyforms = keyforms.peekable();
+        let base = keyforms.peek().map_or_else(Vec::new, |x| x.to_vec());
+
+        let mut sparse = Self {
+            base,
+            offsets: vec![0],
+            points: Vec::new(),
+            deltas: Vec::new(),
+        };
+        for keyform in keyforms {
+            for (point, (position, base)) in keyform.iter().zip(&sparse.base).enumerate() {
+                let delta = *position - *base;
+                if delta.abs().max_element() > tolerance {
+                    sparse.points.push(point as u16);
+                    sparse.deltas.push(delta);
+                }
+            }
+            sparse.offsets.push(sparse.points.len() as u32);
+        }
+        sparse
+    }
+
+    pub fn keyform_count(&self) -> usize {
+        self.offsets.len() - 1
+    }
+
+    /// Every point a keyform moves away from the base, along with how far.
+    pub fn changes(&self, keyform: usize) -> impl Iterator<Item = (usize, Vec2)> + '_ {
+        let range = self.offsets[keyform] as usize..self.offsets[keyform + 1] as usize;
+        self.points[range.clone()]
+            .iter()
+            .map(|x| *x as usize)
+            .zip(self.deltas[range].iter().copied())
+    }
+
+    /// Puts one point of a keyform back together.
+    pub fn position(&self, keyform: usize, point: usize) -> Vec2 {
+        let delta = self
+            .changes(keyform)
+            .find(|x| x.0 == point)
+            .map_or(Vec2::ZERO, |x| x.1);
+        self.base[point] + delta
+    }
+
+    pub(super) fn bytes(&self) -> usize {
+        size_of_val(&self.base[..])
+            + size_of_val(&self.offsets[..])
+            + size_of_val(&self.points[..])
+            + size_of_val(&self.deltas[..])
+    }
+}
+
+/// What [PuppetRef::compact_keyforms] saved.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct KeyformCompaction {
+    pub compacted_applicators: usize,
+    /// The bytes of keyform positions, shared and sparse, before and after.
+    pub bytes_before: usize,
+    pub bytes_after: usize,
+}
+
+impl PuppetRef<'_> {
+    /// Stores the keyforms of every art mesh and warp deformer as changes from their first
+    /// keyform, wherever that's smaller, and shrinks [PuppetRef::keyform_positions] down to
+    /// what's left. Updates get a little slower for it, but big models with many keyforms
+    /// that only move part of a mesh can get a lot smaller.
+    ///
+    /// Points moving less than `tolerance` from the first keyform on both axes are treated
+    /// as not moving at all, where 0 keeps keyforms as they were (up to float rounding).
+    ///
+    /// Compacted art meshes can't have their deforming deferred, as there's nothing left in
+    /// [PuppetRef::keyform_positions] for the [KeyformWeight](super::KeyformWeight)s to point at.
+    /// Frame data that's already deferring goes back to deforming them on its next update.
+    /// Nothing changes for puppets whose keyforms point outside of the table.
+    pub fn compact_keyforms(&mut self, tolerance: f32) -> KeyformCompaction {
+        let positions = &self.keyform_positions;
+        let sparse_bytes = |applicators: &[ParamApplicator]| -> usize {
+            applicators
+                .iter()
+                .filter_map(|x| x.sparse.as_ref())
+                .map(|x| x.bytes())
+                .sum()
+        };
+        let bytes_before = size_of_val(&positions[..]) + sparse_bytes(&self.applicators);
+        let mut report = KeyformCompaction {
+            bytes_before,
+            bytes_after: bytes_before,
+            ..Default::default()
+        };
+
+        // How long each keyform still in the table is.
+        let lengths: Vec<Option<usize>> = self
+            .applicators
+            .iter()
+            .map(|applicator| {
+                let index = applicator.kind_index as usize;
+                match &applicator.values {
+                    _ if applicator.sparse.is_some() => None,
+                    ApplicatorKind::ArtMesh(..) => Some(self.art_mesh_vertexes[index] as usize),
+                    ApplicatorKind::WarpDeformer(..) => {
+                        Some(self.warp_deformer_grid_count[index] as usize)
+                    }
+                    _ => None,
+                }
+            })
+            .collect();
+        let in_table = self
+            .applicators
+            .iter()
+            .zip(&lengths)
+            .all(|(applicator, len)| {
+                let (Some(len), Some(starts)) = (len, table_starts(&applicator.values)) else {
+                    return true;
+                };
+                starts.iter().all(|x| *x as usize + len <= positions.len())
+            });
+        if !in_table {
+            return report;
+        }
+
+        for (applicator, len) in self.applicators.iter_mut().zip(&lengths) {
+            let (Some(len), Some(starts)) = (*len, table_starts(&applicator.values)) else {
+                continue;
+            };
+            // Points are stored as u16s, which every art mesh fits in anyway.
+            if len > u16::MAX as usize + 1 {
+                continue;
+            }
+
+            let sparse = SparseKeyforms::encode(
+                starts
+                    .iter()
+                    .map(|x| &positions[*x as usize..*x as usize + len]),
+                tolerance,
+            );
+            if sparse.bytes() < starts.len() * len * size_of::<Vec2>() {
+                applicator.sparse = Some(sparse);
+                report.compacted_applicators += 1;
+            }
+        }
+
+        // Copy out what's still read from the table, so keyforms shared between applicators
+        // stay shared.
+        let mut table: Vec<Vec2> = Vec::new();
+        let mut moved: HashMap<(u32, usize), u32> = HashMap::new();
+        for (applicator, len) in self.applicators.iter_mut().zip(&lengths) {
+            let Some(len) = *len else {
+                continue;
+            };
+            let starts = match &mut applicator.values {
+                ApplicatorKind::ArtMesh(starts, ..) | ApplicatorKind::WarpDeformer(starts, ..) => {
+                    starts
+                }
+                _ => continue,
+            };
+            if applicator.sparse.is_some() {
+                // Nothing's left in the table for these.
+                starts.fill(u32::MAX);
+                continue;
+            }
+
+            for start in starts {
+                let old = *start as usize;
+                *start = *moved.entry((*start, len)).or_insert_with(|| {
+                    table.extend_from_slice(&positions[old..old + len]);
+                    (table.len() - len) as u32
+                });
+            }
+        }
+
+        self.keyform_positions = Cow::Owned(table);
+        report.bytes_after =
+            size_of_val(&self.keyform_positions[..]) + sparse_bytes(&self.applicators);
+        report
+    }
+}
+
+fn table_starts(values: &ApplicatorKind) -> Option<&[u32]> {
+    match values {
+        ApplicatorKind::ArtMesh(starts, ..) | ApplicatorKind::WarpDeformer(starts, ..) => {
+            Some(starts)
+        }
+        _ => None,
+    }
+}
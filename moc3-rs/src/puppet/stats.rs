@@ -0,0 +1,136 @@
+use super::{applicator::ApplicatorKind, node::NodeKind, PuppetRef};
+
+/// How big and how expensive to update a puppet is, from [PuppetRef::stats].
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct ModelStats {
+    pub art_meshes: usize,
+    /// Art meshes drawn through at least one mask.
+    pub masked_art_meshes: usize,
+    pub vertexes: usize,
+    pub triangles: usize,
+    /// Every keyform of every applicator, blend shapes included.
+    pub keyforms: usize,
+    pub applicators: usize,
+    pub blend_shapes: usize,
+    /// The most parameters any one applicator is bound to. Each one doubles how many
+    /// keyforms get blended together.
+    pub max_binding_dimensions: usize,
+    /// How many vertexes (or warp grid points) are read out of keyforms in a full update,
+    /// counting every keyform blended together at the most.
+    pub interpolated_vertexes: usize,
+    /// How many times a vertex or grid point is moved by a deformer or glue in a full
+    /// update, so deeply nested deformers count several times over.
+    pub deformed_vertexes: usize,
+}
+
+impl ModelStats {
+    /// A rough figure for how much work a full update is, for comparing models rather
+    /// than predicting frame times. Draw calls and texture sizes aren't counted.
+    pub fn estimated_cost(&self) -> usize {
+        self.interpolated_vertexes + self.deformed_vertexes
+    }
+
+    /// Every limit in `budget` these stats go over.
+    pub fn over_budget(&self, budget: &ComplexityBudget) -> Vec<BudgetOverrun> {
+        [
+            ("triangles", self.triangles, budget.triangles),
+            ("keyforms", self.keyforms, budget.keyforms),
+            (
+                "binding dimensions",
+                self.max_binding_dimensions,
+                budget.max_binding_dimensions,
+            ),
+            (
+                "estimated cost",
+                self.estimated_cost(),
+                budget.estimated_cost,
+            ),
+        ]
+        .into_iter()
+        .filter_map(|(what, value, limit)| {
+            let limit = limit?;
+            (value > limit).then_some(BudgetOverrun { what, value, limit })
+        })
+        .collect()
+    }
+}
+
+/// Limits for [ModelStats::over_budget], where `None` means anything goes.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct ComplexityBudget {
+    pub triangles: Option<usize>,
+    pub keyforms: Option<usize>,
+    pub max_binding_dimensions: Option<usize>,
+    pub estimated_cost: Option<usize>,
+}
+
+/// One limit of a [ComplexityBudget] that was gone over.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct BudgetOverrun {
+    /// What went over, like "triangles", for showing to users.
+    pub what: &'static str,
+    pub value: usize,
+    pub limit: usize,
+}
+
+impl PuppetRef<'_> {
+    /// Counts up what makes the puppet big or slow, for warning about heavy models.
+    pub fn stats(&self) -> ModelStats {
+        let mut stats = ModelStats {
+            art_meshes: self.art_mesh_count as usize,
+            masked_art_meshes: self
+                .art_mesh_mask_indices
+                .iter()
+                .filter(|x| !x.is_empty())
+                .count(),
+            vertexes: self.art_mesh_vertexes.iter().map(|x| *x as usize).sum(),
+            triangles: self.art_mesh_indices.iter().map(|x| x.len() / 3).sum(),
+            applicators: self.applicators.len(),
+            ..Default::default()
+        };
+
+        for applicator in &self.applicators {
+            let index = applicator.kind_index as usize;
+            let (keyforms, points) = match &applicator.values {
+                ApplicatorKind::ArtMesh(starts, ..) => {
+                    (starts.len(), self.art_mesh_vertexes[index] as usize)
+                }
+                ApplicatorKind::WarpDeformer(starts, ..) => {
+                    (starts.len(), self.warp_deformer_grid_count[index] as usize)
+                }
+                ApplicatorKind::RotationDeformer(transforms, ..) => (transforms.len(), 1),
+                ApplicatorKind::Glue(intensities) => (intensities.len(), 1),
+                ApplicatorKind::Part(draw_orders) => (draw_orders.len(), 1),
+            };
+
+            let dimensions = applicator.data.len();
+            stats.keyforms += keyforms;
+            stats.blend_shapes += applicator.blend.is_some() as usize;
+            stats.max_binding_dimensions = stats.max_binding_dimensions.max(dimensions);
+            stats.interpolated_vertexes += keyforms.min(1 << dimensions) * points;
+        }
+
+        // Everything gets moved once by every deformer above it.
+        for root in &self.node_roots {
+            for id in root.descendants(&self.nodes) {
+                let points = match &self.nodes[id].get().data {
+                    NodeKind::ArtMesh(data) => data.vertexes as usize,
+                    NodeKind::WarpDeformer(_, index) => {
+                        self.warp_deformer_grid_count[*index as usize] as usize
+                    }
+                    NodeKind::RotationDeformer(..) => 1,
+                };
+                stats.deformed_vertexes += points * (id.ancestors(&self.nodes).count() - 1);
+            }
+        }
+        stats.deformed_vertexes += self
+            .glue_nodes
+            .iter()
+            .map(|x| x.mesh_indices.len())
+            .sum::<usize>();
+
+        stats
+    }
+}
@@ -0,0 +1,226 @@
+use std::collections::{HashMap, HashSet};
+
+use indextree::{Arena, NodeId};
+
+use super::{
+    applicator::ApplicatorKind, draw_order::DrawOrderNode, node::NodeKind,
+    partial::applicators_by_param, PuppetRef,
+};
+
+// Copies every node that passes `keep` into a fresh arena, keeping the tree structure.
+// This relies on the parents of kept nodes always being kept as well.
+fn copy_tree<T: Clone>(
+    arena: &Arena<T>,
+    roots: &[NodeId],
+    mut keep: impl FnMut(NodeId, &T) -> bool,
+) -> (Arena<T>, Vec<NodeId>) {
+    let mut new_arena = Arena::new();
+    let mut new_roots = Vec::new();
+    let mut old_to_new = HashMap::new();
+
+    for root in roots.iter().copied() {
+        for id in root.descendants(arena) {
+            let node = &arena[id];
+            if !keep(id, node.get()) {
+                continue;
+            }
+
+            let new_id = match node.parent() {
+                Some(parent) => {
+                    let new_parent: NodeId = old_to_new[&parent];
+                    new_parent.append_value(node.get().clone(), &mut new_arena)
+                }
+                None => {
+                    let it = new_arena.new_node(node.get().clone());
+                    new_roots.push(it);
+                    it
+                }
+            };
+            old_to_new.insert(id, new_id);
+        }
+    }
+
+    (new_arena, new_roots)
+}
+
+impl<'a> PuppetRef<'a> {
+    /// Derives a puppet that only deforms and draws the art meshes under the given part
+    /// (and whatever those meshes are masked by), for example to show just the head in a
+    /// companion window. Returns [None] if no part has that ID.
+    ///
+    /// The subset keeps the indices and parameters of the full puppet, so it works with the
+    /// same inputs and renderer setup, but skips all the work for everything else.
+    /// [PuppetFrameData](super::PuppetFrameData) must be created for the subset itself.
+    pub fn part_subset(&self, part_id: &str) -> Option<PuppetRef<'a>> {
+        let part_root = self
+            .part_roots
+            .iter()
+            .flat_map(|root| root.descendants(&self.parts))
+            .find(|id| self.parts[*id].get().id == part_id)?;
+
+        let parts: HashSet<i32> = part_root
+            .descendants(&self.parts)
+            .map(|id| self.parts[id].get().kind_index as i32)
+            .collect();
+
+        let mut drawn_meshes = HashSet::new();
+        let mut mesh_nodes = Vec::new();
+        for root in self.node_roots.iter().copied() {
+            for id in root.descendants(&self.nodes) {
+                let node = self.nodes[id].get();
+                if let NodeKind::ArtMesh(_) = node.data {
+                    mesh_nodes.push((node.broad_index, id));
+                    if parts.contains(&node.parent_part_index) {
+                        drawn_meshes.insert(node.broad_index);
+                    }
+                }
+            }
+        }
+
+        // Masks need to be deformed too, even if they're somewhere else entirely.
+        let mut deformed_meshes = drawn_meshes.clone();
+        for mesh in drawn_meshes.iter().copied() {
+            for mask in self.art_mesh_mask_indices[mesh as usize].iter().copied() {
+                if mask != u32::MAX {
+                    deformed_meshes.insert(mask);
+                }
+            }
+        }
+
+        // Glues pull both of their meshes, so anything glued to a deformed mesh has to be
+        // deformed as well, following chains of glues all the way along.
+        let mut grown = true;
+        while grown {
+            grown = false;
+            for glue in &self.glue_nodes {
+                let [a, b] = glue.art_mesh_index;
+                if deformed_meshes.contains(&a) != deformed_meshes.contains(&b) {
+                    deformed_meshes.insert(a);
+                    deformed_meshes.insert(b);
+                    grown = true;
+                }
+            }
+        }
+
+        // Keep every deformer on the path from a kept mesh up to its root.
+        let mut kept_nodes = HashSet::new();
+        for (mesh, id) in mesh_nodes {
+            if deformed_meshes.contains(&mesh) {
+                kept_nodes.extend(id.ancestors(&self.nodes));
+            }
+        }
+
+        let mut warp_deformers = HashSet::new();
+        let mut rotation_deformers = HashSet::new();
+        for id in kept_nodes.iter() {
+            match self.nodes[*id].get().data {
+                NodeKind::WarpDeformer(_, index) => {
+                    warp_deformers.insert(index);
+                }
+                NodeKind::RotationDeformer(_, index) => {
+                    rotation_deformers.insert(index);
+                }
+                NodeKind::ArtMesh(_) => {}
+            }
+        }
+
+        let (nodes, node_roots) = copy_tree(&self.nodes, &self.node_roots, |id, _| {
+            kept_nodes.contains(&id)
+        });
+
+        let glue_nodes: Vec<_> = self
+            .glue_nodes
+            .iter()
+            .filter(|glue| {
+                deformed_meshes.contains(&glue.art_mesh_index[0])
+                    && deformed_meshes.contains(&glue.art_mesh_index[1])
+            })
+            .cloned()
+            .collect();
+        let glues: HashSet<u32> = glue_nodes.iter().map(|x| x.kind_index).collect();
+
+        // Parts are cheap and needed for opacities and draw order, so they all stay.
+        let applicators: Vec<_> = self
+            .applicators
+            .iter()
+            .filter(|applicator| {
+                let index = applicator.kind_index;
+                match applicator.values {
+                    ApplicatorKind::ArtMesh(..) => deformed_meshes.contains(&index),
+                    ApplicatorKind::WarpDeformer(..) => warp_deformers.contains(&index),
+                    ApplicatorKind::RotationDeformer(..) => rotation_deformers.contains(&index),
+                    ApplicatorKind::Glue(_) => glues.contains(&index),
+                    ApplicatorKind::Part(_) => true,
+                }
+            })
+            .cloned()
+            .collect();
+
+        let (draw_order_nodes, draw_order_roots) = copy_tree(
+            &self.draw_order_nodes,
+            &self.draw_order_roots,
+            |_, node| match node {
+                DrawOrderNode::ArtMesh { index } => drawn_meshes.contains(index),
+                DrawOrderNode::Part { .. } => true,
+            },
+        );
+
+        Some(PuppetRef {
+            node_roots,
+            nodes,
+
+            glue_nodes,
+
+            part_roots: self.part_roots.clone(),
+            parts: self.parts.clone(),
+
+            params: self.params.clone(),
+            applicators_by_param: applicators_by_param(
+                self.params.count as usize,
+                &applicators,
+                &self.bindings,
+            ),
+            keyform_edits: 0,
+            applicator_edits: vec![0; applicators.len()],
+            applicators,
+            bindings: self.bindings.clone(),
+            keyform_positions: self.keyform_positions.clone(),
+
+            canvas: self.canvas,
+
+            art_mesh_count: self.art_mesh_count,
+            warp_deformer_count: self.warp_deformer_count,
+            rotation_deformer_count: self.rotation_deformer_count,
+            part_count: self.part_count,
+            glue_count: self.glue_count,
+
+            warp_deformer_grid_count: self.warp_deformer_grid_count.clone(),
+
+            art_mesh_uvs: self.art_mesh_uvs.clone(),
+            art_mesh_indices: self.art_mesh_indices.clone(),
+            art_mesh_textures: self.art_mesh_textures.clone(),
+            art_mesh_flags: self.art_mesh_flags.clone(),
+            art_mesh_mask_indices: self.art_mesh_mask_indices.clone(),
+            art_mesh_vertexes: self.art_mesh_vertexes.clone(),
+            art_mesh_user_data: self.art_mesh_user_data.clone(),
+
+            draw_order_nodes,
+            draw_order_roots,
+
+            art_mesh_ids: self.art_mesh_ids.clone(),
+            deformer_ids: self.deformer_ids.clone(),
+            deformer_kinds: self.deformer_kinds.clone(),
+            part_ids: self.part_ids.clone(),
+            glue_ids: self.glue_ids.clone(),
+        })
+    }
+
+    /// How many art meshes are actually drawn, which is every art mesh unless this is a
+    /// [PuppetRef::part_subset].
+    pub fn drawn_art_mesh_count(&self) -> usize {
+        self.draw_order_nodes
+            .iter()
+            .filter(|x| !x.is_removed() && matches!(x.get(), DrawOrderNode::ArtMesh { .. }))
+            .count()
+    }
+}
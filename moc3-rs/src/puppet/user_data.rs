@@ -0,0 +1,32 @@
+use super::PuppetRef;
+
+impl PuppetRef<'_> {
+    /// Attaches a user data string (usually from a userdata3.json) to the art mesh with the
+    /// given ID, replacing anything attached before. Returns false if no art mesh has that ID.
+    pub fn set_art_mesh_user_data(&mut self, art_mesh_id: &str, value: impl Into<String>) -> bool {
+        let index = self.art_mesh_index(art_mesh_id);
+
+        match index {
+            Some(index) => {
+                self.art_mesh_user_data[index] = Some(value.into());
+                true
+            }
+            None => false,
+        }
+    }
+
+    /// The user data attached to an art mesh, if any.
+    pub fn art_mesh_user_data(&self, art_mesh_index: usize) -> Option<&str> {
+        self.art_mesh_user_data
+            .get(art_mesh_index)
+            .and_then(|x| x.as_deref())
+    }
+
+    /// Every art mesh index that has user data attached, along with the data.
+    pub fn art_meshes_with_user_data(&self) -> impl Iterator<Item = (usize, &str)> {
+        self.art_mesh_user_data
+            .iter()
+            .enumerate()
+            .filter_map(|(i, x)| Some((i, x.as_deref()?)))
+    }
+}
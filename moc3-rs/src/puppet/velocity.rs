@@ -0,0 +1,56 @@
+use glam::Vec2;
+
+use super::{PuppetFrameData, PuppetRef};
+
+fn centroid(vertexes: &[Vec2]) -> Vec2 {
+    if vertexes.is_empty() {
+        return Vec2::ZERO;
+    }
+
+    vertexes.iter().copied().sum::<Vec2>() / vertexes.len() as f32
+}
+
+impl PuppetRef<'_> {
+    /// Estimates how fast every parameter and art mesh is moving, from the change since
+    /// the last time this was called. Call it after [PuppetRef::update] with the time that
+    /// passed since the previous frame; the results end up in
+    /// [PuppetFrameData::param_velocities] and [PuppetFrameData::art_mesh_velocities].
+    ///
+    /// The first call only records a starting point, so everything reads as still.
+    pub fn measure_velocities(&self, delta_seconds: f32, frame_data: &mut PuppetFrameData) {
+        let history = frame_data.velocity_history;
+        let valid = history && delta_seconds > 0.0;
+
+        for i in 0..frame_data.corrected_params.len() {
+            let current = frame_data.corrected_params[i];
+            let mut diff = current - frame_data.previous_params[i];
+
+            // Repeating parameters go the short way around, so going from the maximum
+            // to the minimum doesn't look like a sudden jump.
+            if self.params.repeats[i] {
+                let range = self.params.maxes[i] - self.params.mins[i];
+                if range > 0.0 {
+                    diff = (diff + range / 2.0).rem_euclid(range) - range / 2.0;
+                }
+            }
+
+            frame_data.param_velocities[i] = if valid { diff / delta_seconds } else { 0.0 };
+            frame_data.previous_params[i] = current;
+        }
+
+        for (i, vertexes) in frame_data.art_mesh_data.iter().enumerate() {
+            let current = centroid(vertexes);
+            let velocity = (current - frame_data.previous_centroids[i]) / delta_seconds;
+
+            // Meshes that weren't deformed (as in puppet subsets) are NaN, so treat them as still.
+            frame_data.art_mesh_velocities[i] = if valid && velocity.is_finite() {
+                velocity
+            } else {
+                Vec2::ZERO
+            };
+            frame_data.previous_centroids[i] = current;
+        }
+
+        frame_data.velocity_history = true;
+    }
+}
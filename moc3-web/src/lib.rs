@@ -0,0 +1,188 @@
+//! Shows a model in the browser.
+//!
+//! Build it with `wasm-pack build moc3-web --target web`, then serve the `moc3-web`
+//! directory with the model next to it, at `model/model.model3.json` by default. Textures
+//! are fetched after the model is already on screen, and show up as they finish loading.
+
+#![cfg(target_arch = "wasm32")]
+
+use std::{cell::RefCell, rc::Rc};
+
+use moc3_rs::{
+    parse_puppet,
+    puppet::{framedata_for_puppet, Puppet, PuppetFrameData},
+};
+use moc3_wgpu::{
+    renderer::{new_renderer, request_device, ColorSpace, Renderer},
+    texture::RawRgba,
+};
+use serde::Deserialize;
+use wasm_bindgen::{prelude::*, JsCast};
+use wasm_bindgen_futures::JsFuture;
+use web_sys::{HtmlCanvasElement, Response};
+use wgpu::{Device, Extent3d, Queue, Surface};
+
+#[derive(Deserialize)]
+#[serde(rename_all = "PascalCase")]
+struct Model3 {
+    file_references: FileReferences,
+}
+
+#[derive(Deserialize)]
+#[serde(rename_all = "PascalCase")]
+struct FileReferences {
+    moc: String,
+    #[serde(default)]
+    textures: Vec<String>,
+}
+
+struct State {
+    puppet: Puppet,
+    frame_data: PuppetFrameData,
+    params: Vec<f32>,
+    part_opacities: Vec<f32>,
+
+    surface: Surface,
+    size: Extent3d,
+    device: Device,
+    queue: Queue,
+    renderer: Renderer,
+}
+
+impl State {
+    fn draw(&mut self) {
+        self.puppet
+            .update(&self.params, &self.part_opacities, &mut self.frame_data);
+
+        let output = match self.surface.get_current_texture() {
+            Ok(output) => output,
+            // The canvas can go away or be lost along with the context, just skip the frame.
+            Err(_) => return,
+        };
+        let view = output
+            .texture
+            .create_view(&wgpu::TextureViewDescriptor::default());
+
+        self.renderer
+            .prepare(&self.device, &self.queue, self.size, &self.frame_data);
+        let mut encoder = self
+            .device
+            .create_command_encoder(&wgpu::CommandEncoderDescriptor { label: None });
+        self.renderer.render(&view, &mut encoder);
+        self.queue.submit(std::iter::once(encoder.finish()));
+
+        output.present();
+    }
+}
+
+async fn fetch(url: &str) -> Result<Vec<u8>, JsValue> {
+    let window = web_sys::window().ok_or("no window")?;
+    let response: Response = JsFuture::from(window.fetch_with_str(url))
+        .await?
+        .dyn_into()?;
+    if !response.ok() {
+        return Err(format!("couldn't fetch {url}: {}", response.status()).into());
+    }
+
+    let buffer = JsFuture::from(response.array_buffer()?).await?;
+    Ok(js_sys::Uint8Array::new(&buffer).to_vec())
+}
+
+fn request_animation_frame(callback: &Closure<dyn FnMut()>) {
+    web_sys::window()
+        .unwrap()
+        .request_animation_frame(callback.as_ref().unchecked_ref())
+        .unwrap();
+}
+
+/// Loads the model3.json at `model_url` and draws it into the canvas every frame.
+#[wasm_bindgen]
+pub async fn start(canvas: HtmlCanvasElement, model_url: String) -> Result<(), JsValue> {
+    let model3: Model3 =
+        serde_json::from_slice(&fetch(&model_url).await?).map_err(|e| e.to_string())?;
+    let directory = &model_url[..model_url.rfind('/').map_or(0, |x| x + 1)];
+    let references = model3.file_references;
+
+    let moc3 = fetch(&format!("{directory}{}", references.moc)).await?;
+    let puppet = parse_puppet(&moc3).map_err(|e| e.to_string())?;
+
+    let size = Extent3d {
+        width: canvas.width(),
+        height: canvas.height(),
+        depth_or_array_layers: 1,
+    };
+
+    let instance = wgpu::Instance::new(wgpu::InstanceDescriptor {
+        backends: wgpu::Backends::GL,
+        ..Default::default()
+    });
+    let surface = instance
+        .create_surface_from_canvas(canvas)
+        .map_err(|e| e.to_string())?;
+    let adapter = instance
+        .request_adapter(&wgpu::RequestAdapterOptions {
+            power_preference: wgpu::PowerPreference::default(),
+            compatible_surface: Some(&surface),
+            force_fallback_adapter: false,
+        })
+        .await
+        .ok_or("no graphics adapter available")?;
+    let (device, queue) = request_device(&adapter).await.map_err(|e| e.to_string())?;
+
+    let capabilities = surface.get_capabilities(&adapter);
+    // Puppets are made to be blended in gamma space, so non-sRGB surfaces are preferred.
+    let format = ColorSpace::Gamma
+        .surface_format(&capabilities.formats)
+        .ok_or("surface has no supported formats")?;
+    surface.configure(
+        &device,
+        &wgpu::SurfaceConfiguration {
+            usage: wgpu::TextureUsages::RENDER_ATTACHMENT,
+            format,
+            width: size.width,
+            height: size.height,
+            present_mode: wgpu::PresentMode::Fifo,
+            alpha_mode: capabilities.alpha_modes[0],
+            view_formats: Vec::new(),
+        },
+    );
+
+    // No textures yet, meshes are drawn as placeholders until they arrive.
+    let renderer = new_renderer(&puppet, &device, &queue, format, &[] as &[RawRgba]);
+    let state = Rc::new(RefCell::new(State {
+        frame_data: framedata_for_puppet(&puppet),
+        params: puppet.param_data().defaults.clone(),
+        part_opacities: vec![1.0; puppet.part_count as usize],
+        puppet,
+
+        surface,
+        size,
+        device,
+        queue,
+        renderer,
+    }));
+
+    // The closure has to be able to schedule itself again, hence the indirection.
+    let frame = Rc::new(RefCell::new(None::<Closure<dyn FnMut()>>));
+    *frame.borrow_mut() = Some(Closure::new({
+        let frame = frame.clone();
+        let state = state.clone();
+        move || {
+            state.borrow_mut().draw();
+            request_animation_frame(frame.borrow().as_ref().unwrap());
+        }
+    }));
+    request_animation_frame(frame.borrow().as_ref().unwrap());
+
+    for (index, texture) in references.textures.iter().enumerate() {
+        let bytes = fetch(&format!("{directory}{texture}")).await?;
+
+        let state = &mut *state.borrow_mut();
+        state
+            .renderer
+            .load_texture(&state.device, &state.queue, index, &bytes)
+            .map_err(|e| format!("couldn't decode {texture}: {e}"))?;
+    }
+
+    Ok(())
+}
@@ -0,0 +1,265 @@
+//! Repacking a puppet's textures into fewer, smaller atlases.
+//!
+//! Models usually come with a few big textures that are mostly empty space, which adds up
+//! with many models on screen. [repack] cuts out only what the art meshes use, optionally
+//! scaled down, and packs that into new pages with UVs to match. Applying the result to the
+//! puppet with [Atlas::apply] is all the renderer needs, as it reads UVs and texture indexes
+//! from the puppet.
+
+use glam::{UVec2, Vec2};
+use image::{imageops, RgbaImage};
+use moc3_rs::puppet::PuppetRef;
+
+/// How [repack] lays out pages.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct AtlasOptions {
+    /// How wide every page is, in texels. Pages are only as tall as they need to be, up to
+    /// this too. Regions too big to fit get a page of their own that's as big as they are.
+    pub page_size: u32,
+    /// How much every region is scaled by, so anything below 1 downscales the textures.
+    pub scale: f32,
+    /// Texels of the original texture kept around every region, so filtering doesn't bleed
+    /// in neighbouring regions.
+    pub padding: u32,
+}
+
+impl Default for AtlasOptions {
+    fn default() -> Self {
+        Self {
+            page_size: 4096,
+            scale: 1.0,
+            padding: 2,
+        }
+    }
+}
+
+/// Repacked textures, with the UVs every art mesh needs to use them.
+pub struct Atlas {
+    pub pages: Vec<RgbaImage>,
+    /// Which page every art mesh uses, in place of [PuppetRef::art_mesh_textures].
+    pub art_mesh_textures: Vec<u32>,
+    /// Every art mesh's UVs on its page, in place of [PuppetRef::art_mesh_uvs].
+    pub art_mesh_uvs: Vec<Vec<Vec2>>,
+}
+
+impl Atlas {
+    /// Points the puppet's art meshes at the pages, which then replace its textures when
+    /// making a renderer for it.
+    pub fn apply(&self, puppet: &mut PuppetRef) {
+        puppet.art_mesh_textures.clone_from(&self.art_mesh_textures);
+        puppet.art_mesh_uvs.clone_from(&self.art_mesh_uvs);
+    }
+}
+
+// A rectangle of texels in one of the original textures, from min up to (not including) max.
+#[derive(Debug, Clone, Copy)]
+struct Region {
+    texture: u32,
+    min: UVec2,
+    max: UVec2,
+}
+
+impl Region {
+    fn overlaps(&self, other: &Region) -> bool {
+        self.texture == other.texture
+            && self.min.cmplt(other.max).all()
+            && other.min.cmplt(self.max).all()
+    }
+
+    fn size(&self) -> UVec2 {
+        self.max - self.min
+    }
+}
+
+/// Cuts out the parts of `textures` the puppet's art meshes use, and packs them into new
+/// pages, see [AtlasOptions]. Meshes sharing texels share a region, so nothing is copied
+/// twice.
+///
+/// Art meshes without vertexes or whose texture is missing keep their UVs, and use the
+/// first page.
+pub fn repack(puppet: &PuppetRef, textures: &[RgbaImage], options: AtlasOptions) -> Atlas {
+    let mesh_count = puppet.art_mesh_count as usize;
+
+    // Every mesh starts out with its own region, which get merged while any overlap.
+    let mut regions: Vec<Region> = Vec::new();
+    let mut mesh_regions: Vec<Option<usize>> = vec![None; mesh_count];
+    for (mesh, mesh_region) in mesh_regions.iter_mut().enumerate() {
+        let texture_index = puppet.art_mesh_textures[mesh];
+        let Some(texture) = textures.get(texture_index as usize) else {
+            continue;
+        };
+        let Some((min, max)) = puppet.art_mesh_uv_bounds(mesh) else {
+            continue;
+        };
+
+        let size = UVec2::new(texture.width(), texture.height());
+        if size.cmpeq(UVec2::ZERO).any() {
+            continue;
+        }
+
+        // Every region covers at least a texel, even for meshes entirely off the texture.
+        let padding = UVec2::splat(options.padding);
+        let min = (min * size.as_vec2()).floor().max(Vec2::ZERO).as_uvec2();
+        let max = (max * size.as_vec2()).ceil().max(Vec2::ZERO).as_uvec2();
+        let min = min.saturating_sub(padding).min(size - 1);
+        *mesh_region = Some(regions.len());
+        regions.push(Region {
+            texture: texture_index,
+            min,
+            max: (max + padding).min(size).max(min + 1),
+        });
+    }
+    merge_overlapping(&mut regions, &mut mesh_regions);
+
+    let scaled_sizes: Vec<UVec2> = regions
+        .iter()
+        .map(|x| {
+            (x.size().as_vec2() * options.scale)
+                .ceil()
+                .as_uvec2()
+                .max(UVec2::ONE)
+        })
+        .collect();
+    let (placements, page_sizes) = pack(&scaled_sizes, options.page_size);
+
+    let mut pages: Vec<RgbaImage> = page_sizes
+        .iter()
+        .map(|x| RgbaImage::new(x.x, x.y))
+        .collect();
+    for (i, region) in regions.iter().enumerate() {
+        let size = region.size();
+        let texture = &textures[region.texture as usize];
+        let cut = imageops::crop_imm(texture, region.min.x, region.min.y, size.x, size.y);
+        let scaled = if scaled_sizes[i] == size {
+            cut.to_image()
+        } else {
+            imageops::resize(
+                &*cut,
+                scaled_sizes[i].x,
+                scaled_sizes[i].y,
+                imageops::FilterType::Triangle,
+            )
+        };
+
+        let (page, position) = placements[i];
+        imageops::replace(
+            &mut pages[page],
+            &scaled,
+            position.x as i64,
+            position.y as i64,
+        );
+    }
+
+    let mut art_mesh_textures = vec![0; mesh_count];
+    let mut art_mesh_uvs = puppet.art_mesh_uvs.clone();
+    for (mesh, region_index) in mesh_regions.iter().enumerate() {
+        let Some(region_index) = *region_index else {
+            continue;
+        };
+        let region = &regions[region_index];
+        let (page, position) = placements[region_index];
+
+        let texture = &textures[region.texture as usize];
+        let texture_size = Vec2::new(texture.width() as f32, texture.height() as f32);
+        let scale = scaled_sizes[region_index].as_vec2() / region.size().as_vec2();
+        let page_size = page_sizes[page].as_vec2();
+
+        art_mesh_textures[mesh] = page as u32;
+        for uv in &mut art_mesh_uvs[mesh] {
+            let texel = *uv * texture_size - region.min.as_vec2();
+            *uv = (position.as_vec2() + texel * scale) / page_size;
+        }
+    }
+
+    Atlas {
+        pages,
+        art_mesh_textures,
+        art_mesh_uvs,
+    }
+}
+
+// Merges regions until none overlap, keeping meshes pointed at whatever their region ended
+// up part of.
+fn merge_overlapping(regions: &mut Vec<Region>, mesh_regions: &mut [Option<usize>]) {
+    let mut merged_into: Vec<usize> = (0..regions.len()).collect();
+    let mut kept: Vec<usize> = Vec::new();
+
+    for i in 0..regions.len() {
+        let mut region = regions[i];
+        // Growing a region can make it overlap ones that didn't before, so keep looking
+        // until nothing changes.
+        while let Some(position) = kept.iter().position(|x| regions[*x].overlaps(&region)) {
+            let other = kept.swap_remove(position);
+            region.min = region.min.min(regions[other].min);
+            region.max = region.max.max(regions[other].max);
+            merged_into[other] = i;
+        }
+        regions[i] = region;
+        kept.push(i);
+    }
+
+    // Follow merges to where they ended up, then renumber what's left.
+    let find = |mut x: usize| {
+        while merged_into[x] != x {
+            x = merged_into[x];
+        }
+        x
+    };
+    let mut renumbered = vec![usize::MAX; regions.len()];
+    let mut compacted = Vec::with_capacity(kept.len());
+    kept.sort_unstable();
+    for x in kept {
+        renumbered[x] = compacted.len();
+        compacted.push(regions[x]);
+    }
+    for region in mesh_regions.iter_mut().flatten() {
+        *region = renumbered[find(*region)];
+    }
+    *regions = compacted;
+}
+
+// Lays out rectangles on shelves, tallest first, returning which page and where each one
+// went along with how big every page ended up.
+fn pack(sizes: &[UVec2], page_size: u32) -> (Vec<(usize, UVec2)>, Vec<UVec2>) {
+    let mut order: Vec<usize> = (0..sizes.len()).collect();
+    order.sort_by_key(|x| std::cmp::Reverse((sizes[*x].y, sizes[*x].x)));
+
+    let mut placements = vec![(0, UVec2::ZERO); sizes.len()];
+    let mut pages: Vec<UVec2> = Vec::new();
+    // Where the next rectangle goes on the last page, and how tall its shelf is.
+    let mut cursor = UVec2::ZERO;
+    let mut shelf_height = 0;
+
+    for i in order {
+        let size = sizes[i];
+        if size.x > page_size || size.y > page_size {
+            placements[i] = (pages.len(), UVec2::ZERO);
+            pages.push(size);
+            // Whatever page was being filled is now before this one, so start a new one.
+            cursor = UVec2::new(0, page_size);
+            continue;
+        }
+
+        if cursor.x + size.x > page_size {
+            cursor = UVec2::new(0, cursor.y + shelf_height);
+            shelf_height = 0;
+        }
+        if pages.is_empty() || cursor.y + size.y > page_size {
+            pages.push(UVec2::new(page_size, 0));
+            cursor = UVec2::ZERO;
+            shelf_height = 0;
+        }
+
+        let page = pages.len() - 1;
+        placements[i] = (page, cursor);
+        pages[page].y = pages[page].y.max(cursor.y + size.y);
+        cursor.x += size.x;
+        shelf_height = shelf_height.max(size.y);
+    }
+
+    // Meshes without a region still need something to point at.
+    if pages.is_empty() {
+        pages.push(UVec2::ONE);
+    }
+    (placements, pages)
+}
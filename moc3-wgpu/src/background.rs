@@ -0,0 +1,267 @@
+use encase::{ShaderSize, ShaderType, UniformBuffer};
+use glam::Vec4;
+use image::RgbaImage;
+use wgpu::{
+    util::{BufferInitDescriptor, DeviceExt},
+    *,
+};
+
+use crate::renderer::ColorSpace;
+
+/// What gets drawn behind the puppet before any art meshes.
+#[derive(Debug, Clone, Default)]
+pub enum Background {
+    /// Nothing is drawn, leaving a transparent backdrop.
+    #[default]
+    None,
+    /// The target isn't cleared at all, so the puppet is drawn over whatever is already
+    /// there, such as the rest of an app's frame. That should be in premultiplied alpha,
+    /// like the puppet itself.
+    Keep,
+    /// A single flat color. Colors are given in straight (non-premultiplied) alpha.
+    Solid(Color),
+    /// A vertical gradient from `top` to `bottom`.
+    Gradient { top: Color, bottom: Color },
+    /// Alternating squares of two colors, the usual way of showing transparency.
+    /// `size` is the width of a square in pixels.
+    Checkerboard {
+        size: u32,
+        light: Color,
+        dark: Color,
+    },
+    /// An image stretched over the entire render target.
+    Image(RgbaImage),
+}
+
+impl Background {
+    /// A light gray checkerboard, like most image editors use.
+    pub const CHECKERBOARD: Background = Background::Checkerboard {
+        size: 16,
+        light: Color {
+            r: 0.8,
+            g: 0.8,
+            b: 0.8,
+            a: 1.0,
+        },
+        dark: Color {
+            r: 0.6,
+            g: 0.6,
+            b: 0.6,
+            a: 1.0,
+        },
+    };
+}
+
+// Which of the shader's fill modes to use.
+const FILL_GRADIENT: u32 = 0;
+const FILL_TEXTURE: u32 = 1;
+const FILL_CHECKERBOARD: u32 = 2;
+
+#[derive(ShaderType, Debug, Clone, Copy, PartialEq)]
+struct BackgroundUniform {
+    pub top: Vec4,
+    pub bottom: Vec4,
+    pub fill: u32,
+    pub cell_size: f32,
+}
+
+// The GPU resources needed to draw gradient and image backgrounds. Solid colors
+// don't need any of this, as they're handled by the render pass clear instead.
+pub(crate) struct BackgroundLayer {
+    pub pipeline: RenderPipeline,
+    pub uniform_bind_group: BindGroup,
+    pub texture_bind_group: BindGroup,
+}
+
+// What to clear a target of the given format to for a solid background. Colors are given
+// as they're stored, so sRGB targets need them decoded, as those are encoded when written.
+pub(crate) fn clear_color(color: Color, format: TextureFormat) -> Color {
+    let decode = |x: f64| match ColorSpace::of(format) {
+        ColorSpace::Gamma => x,
+        ColorSpace::Linear if x < 0.04045 => x / 12.92,
+        ColorSpace::Linear => ((x + 0.055) / 1.055).powf(2.4),
+    };
+    Color {
+        r: decode(color.r) * color.a,
+        g: decode(color.g) * color.a,
+        b: decode(color.b) * color.a,
+        a: color.a,
+    }
+}
+
+fn color_to_vec4(color: Color) -> Vec4 {
+    Vec4::new(
+        color.r as f32,
+        color.g as f32,
+        color.b as f32,
+        color.a as f32,
+    )
+}
+
+pub(crate) fn background_layer(
+    background: &Background,
+    device: &Device,
+    queue: &Queue,
+    format: TextureFormat,
+    texture_layout: &BindGroupLayout,
+    texture_sampler: &Sampler,
+) -> Option<BackgroundLayer> {
+    let (uniform, image) = match background {
+        Background::None | Background::Keep | Background::Solid(_) => return None,
+        Background::Gradient { top, bottom } => (
+            BackgroundUniform {
+                top: color_to_vec4(*top),
+                bottom: color_to_vec4(*bottom),
+                fill: FILL_GRADIENT,
+                cell_size: 1.0,
+            },
+            None,
+        ),
+        Background::Checkerboard { size, light, dark } => (
+            BackgroundUniform {
+                top: color_to_vec4(*light),
+                bottom: color_to_vec4(*dark),
+                fill: FILL_CHECKERBOARD,
+                cell_size: (*size).max(1) as f32,
+            },
+            None,
+        ),
+        Background::Image(image) => (
+            BackgroundUniform {
+                top: Vec4::ZERO,
+                bottom: Vec4::ZERO,
+                fill: FILL_TEXTURE,
+                cell_size: 1.0,
+            },
+            Some(image),
+        ),
+    };
+
+    // We still need something bound for the other fills, so a single white texel will do.
+    let (width, height, data) = match image {
+        Some(image) => (image.width(), image.height(), image.as_raw().as_slice()),
+        None => (1, 1, [255u8; 4].as_slice()),
+    };
+
+    let texture = device.create_texture_with_data(
+        queue,
+        &TextureDescriptor {
+            size: Extent3d {
+                width,
+                height,
+                depth_or_array_layers: 1,
+            },
+            mip_level_count: 1,
+            sample_count: 1,
+            dimension: TextureDimension::D2,
+            format: TextureFormat::Rgba8Unorm,
+            usage: TextureUsages::TEXTURE_BINDING,
+            view_formats: &[],
+            label: None,
+        },
+        data,
+    );
+    let texture_view = texture.create_view(&TextureViewDescriptor::default());
+
+    let texture_bind_group = device.create_bind_group(&BindGroupDescriptor {
+        layout: texture_layout,
+        entries: &[
+            BindGroupEntry {
+                binding: 0,
+                resource: BindingResource::TextureView(&texture_view),
+            },
+            BindGroupEntry {
+                binding: 1,
+                resource: BindingResource::Sampler(texture_sampler),
+            },
+        ],
+        label: None,
+    });
+
+    let mut buffer = UniformBuffer::new([0; BackgroundUniform::SHADER_SIZE.get() as usize]);
+    buffer.write(&uniform).unwrap();
+    let uniform_buffer = device.create_buffer_init(&BufferInitDescriptor {
+        contents: buffer.as_ref(),
+        usage: BufferUsages::UNIFORM,
+        label: None,
+    });
+
+    let uniform_layout = device.create_bind_group_layout(&BindGroupLayoutDescriptor {
+        entries: &[BindGroupLayoutEntry {
+            binding: 0,
+            visibility: ShaderStages::FRAGMENT,
+            ty: BindingType::Buffer {
+                ty: BufferBindingType::Uniform,
+                has_dynamic_offset: false,
+                min_binding_size: Some(BackgroundUniform::SHADER_SIZE),
+            },
+            count: None,
+        }],
+        label: None,
+    });
+
+    let uniform_bind_group = device.create_bind_group(&BindGroupDescriptor {
+        layout: &uniform_layout,
+        entries: &[BindGroupEntry {
+            binding: 0,
+            resource: uniform_buffer.as_entire_binding(),
+        }],
+        label: None,
+    });
+
+    let pipeline_layout = device.create_pipeline_layout(&PipelineLayoutDescriptor {
+        bind_group_layouts: &[&uniform_layout, texture_layout],
+        ..PipelineLayoutDescriptor::default()
+    });
+
+    let module = device.create_shader_module(include_wgsl!("./shader/background.wgsl"));
+
+    // The background shares the render pass with the puppet, so it needs to agree on the
+    // depth-stencil format, but it must never touch the stencil values used by masks.
+    let face_state = StencilFaceState {
+        compare: CompareFunction::Always,
+        fail_op: StencilOperation::Keep,
+        depth_fail_op: StencilOperation::Keep,
+        pass_op: StencilOperation::Keep,
+    };
+
+    let pipeline = device.create_render_pipeline(&RenderPipelineDescriptor {
+        label: None,
+        layout: Some(&pipeline_layout),
+        vertex: VertexState {
+            module: &module,
+            entry_point: "vs_main",
+            buffers: &[],
+        },
+        fragment: Some(FragmentState {
+            module: &module,
+            entry_point: ColorSpace::of(format).fs_main(),
+            targets: &[Some(ColorTargetState {
+                format,
+                blend: Some(BlendState::PREMULTIPLIED_ALPHA_BLENDING),
+                write_mask: ColorWrites::ALL,
+            })],
+        }),
+        primitive: PrimitiveState::default(),
+        depth_stencil: Some(DepthStencilState {
+            format: TextureFormat::Depth24PlusStencil8,
+            depth_write_enabled: false,
+            depth_compare: CompareFunction::Always,
+            stencil: StencilState {
+                front: face_state,
+                back: face_state,
+                read_mask: 0,
+                write_mask: 0,
+            },
+            bias: DepthBiasState::default(),
+        }),
+        multisample: MultisampleState::default(),
+        multiview: None,
+    });
+
+    Some(BackgroundLayer {
+        pipeline,
+        uniform_bind_group,
+        texture_bind_group,
+    })
+}
@@ -0,0 +1,329 @@
+use std::{
+    fs,
+    io::{self, Write},
+    path::PathBuf,
+    sync::mpsc,
+};
+
+use glam::Mat4;
+use image::RgbaImage;
+use wgpu::*;
+
+use moc3_rs::puppet::{framedata_for_puppet, PuppetFrameData, PuppetRef};
+
+use crate::{
+    crowd::CrowdRenderer,
+    renderer::{ColorSpace, Renderer},
+    scene::{PuppetId, SceneRenderer},
+};
+
+/// Renders puppets into an offscreen texture and reads the result back to the CPU.
+///
+/// The renderer used with a capture must have been created with its [format](FrameCapture::format),
+/// which is [FrameCapture::FORMAT] unless it's made with another [ColorSpace]. Reading back blocks on the GPU, so this is meant for offline work like previews and
+/// regression clips rather than anything running at frame rate.
+pub struct FrameCapture {
+    size: Extent3d,
+    format: TextureFormat,
+    texture: Texture,
+    view: TextureView,
+    readback: Buffer,
+    padded_bytes_per_row: u32,
+}
+
+impl FrameCapture {
+    pub const FORMAT: TextureFormat = TextureFormat::Rgba8Unorm;
+
+    pub fn new(device: &Device, width: u32, height: u32) -> Self {
+        Self::with_color_space(device, width, height, ColorSpace::Gamma)
+    }
+
+    /// Like [FrameCapture::new], but drawing in the given color space. Captures come out
+    /// sRGB encoded either way.
+    pub fn with_color_space(
+        device: &Device,
+        width: u32,
+        height: u32,
+        color_space: ColorSpace,
+    ) -> Self {
+        let format = match color_space {
+            ColorSpace::Gamma => Self::FORMAT,
+            ColorSpace::Linear => TextureFormat::Rgba8UnormSrgb,
+        };
+        let size = Extent3d {
+            width,
+            height,
+            depth_or_array_layers: 1,
+        };
+
+        let texture = device.create_texture(&TextureDescriptor {
+            size,
+            mip_level_count: 1,
+            sample_count: 1,
+            dimension: TextureDimension::D2,
+            format,
+            usage: TextureUsages::RENDER_ATTACHMENT | TextureUsages::COPY_SRC,
+            view_formats: &[],
+            label: None,
+        });
+        let view = texture.create_view(&TextureViewDescriptor::default());
+
+        // Buffer copies need every row aligned, so there may be some padding to strip later.
+        let padded_bytes_per_row = (width * 4).next_multiple_of(COPY_BYTES_PER_ROW_ALIGNMENT);
+        let readback = device.create_buffer(&BufferDescriptor {
+            size: padded_bytes_per_row as u64 * height as u64,
+            usage: BufferUsages::COPY_DST | BufferUsages::MAP_READ,
+            mapped_at_creation: false,
+            label: None,
+        });
+
+        Self {
+            size,
+            format,
+            texture,
+            view,
+            readback,
+            padded_bytes_per_row,
+        }
+    }
+
+    pub fn width(&self) -> u32 {
+        self.size.width
+    }
+
+    pub fn height(&self) -> u32 {
+        self.size.height
+    }
+
+    pub fn format(&self) -> TextureFormat {
+        self.format
+    }
+
+    /// Renders the given frame and returns it as straight alpha RGBA, ready to be saved.
+    /// Fails if the result can't be mapped for reading, for example after losing the device.
+    pub fn capture(
+        &mut self,
+        device: &Device,
+        queue: &Queue,
+        renderer: &mut Renderer,
+        frame_data: &PuppetFrameData,
+    ) -> Result<RgbaImage, BufferAsyncError> {
+        renderer.prepare(device, queue, self.size, frame_data);
+
+        let mut encoder = device.create_command_encoder(&CommandEncoderDescriptor { label: None });
+        renderer.render(&self.view, &mut encoder);
+        self.read_back(device, queue, encoder)
+    }
+
+    /// Like [FrameCapture::capture], but for a whole scene. The scene must have been created
+    /// with the capture's format too.
+    pub fn capture_scene(
+        &mut self,
+        device: &Device,
+        queue: &Queue,
+        scene: &mut SceneRenderer,
+        frames: &[(PuppetId, &PuppetFrameData)],
+    ) -> Result<RgbaImage, BufferAsyncError> {
+        scene.prepare(device, queue, self.size, frames);
+
+        let mut encoder = device.create_command_encoder(&CommandEncoderDescriptor { label: None });
+        scene.render(&self.view, &mut encoder);
+        self.read_back(device, queue, encoder)
+    }
+
+    /// Like [FrameCapture::capture], but for a whole crowd. The crowd must have been created
+    /// with the capture's format too.
+    pub fn capture_crowd(
+        &mut self,
+        device: &Device,
+        queue: &Queue,
+        crowd: &mut CrowdRenderer,
+        frames: &[(Mat4, &PuppetFrameData)],
+    ) -> Result<RgbaImage, BufferAsyncError> {
+        crowd.prepare(device, queue, self.size, frames);
+
+        let mut encoder = device.create_command_encoder(&CommandEncoderDescriptor { label: None });
+        crowd.render(&self.view, &mut encoder);
+        self.read_back(device, queue, encoder)
+    }
+
+    fn read_back(
+        &self,
+        device: &Device,
+        queue: &Queue,
+        mut encoder: CommandEncoder,
+    ) -> Result<RgbaImage, BufferAsyncError> {
+        encoder.copy_texture_to_buffer(
+            self.texture.as_image_copy(),
+            ImageCopyBuffer {
+                buffer: &self.readback,
+                layout: ImageDataLayout {
+                    offset: 0,
+                    bytes_per_row: Some(self.padded_bytes_per_row),
+                    rows_per_image: None,
+                },
+            },
+            self.size,
+        );
+        queue.submit(std::iter::once(encoder.finish()));
+
+        let slice = self.readback.slice(..);
+        let (sender, receiver) = mpsc::channel();
+        slice.map_async(MapMode::Read, move |result| {
+            let _ = sender.send(result);
+        });
+        device.poll(Maintain::Wait);
+        // Waiting runs the callback, so an empty channel means it was dropped without one.
+        receiver.try_recv().unwrap_or(Err(BufferAsyncError))?;
+
+        let row_bytes = self.size.width as usize * 4;
+        let mut pixels = Vec::with_capacity(row_bytes * self.size.height as usize);
+        for row in slice
+            .get_mapped_range()
+            .chunks_exact(self.padded_bytes_per_row as usize)
+        {
+            pixels.extend_from_slice(&row[..row_bytes]);
+        }
+        self.readback.unmap();
+
+        // The renderer works in premultiplied alpha, but image files expect straight alpha.
+        // sRGB targets premultiply before encoding, so they're divided in linear light.
+        let linear = ColorSpace::of(self.format) == ColorSpace::Linear;
+        for pixel in pixels.chunks_exact_mut(4) {
+            let alpha = pixel[3];
+            if alpha != 0 && alpha != 255 {
+                for channel in &mut pixel[..3] {
+                    *channel = if linear {
+                        let straight = linear_from_gamma(*channel) * 255.0 / alpha as f32;
+                        (gamma_from_linear(straight.min(1.0)) * 255.0).round() as u8
+                    } else {
+                        ((*channel as u32 * 255 + alpha as u32 / 2) / alpha as u32).min(255) as u8
+                    };
+                }
+            }
+        }
+
+        Ok(RgbaImage::from_raw(self.size.width, self.size.height, pixels).unwrap())
+    }
+}
+
+fn linear_from_gamma(channel: u8) -> f32 {
+    let x = channel as f32 / 255.0;
+    if x < 0.04045 {
+        x / 12.92
+    } else {
+        ((x + 0.055) / 1.055).powf(2.4)
+    }
+}
+
+fn gamma_from_linear(x: f32) -> f32 {
+    if x < 0.0031308 {
+        x * 12.92
+    } else {
+        1.055 * x.powf(1.0 / 2.4) - 0.055
+    }
+}
+
+/// Somewhere for recorded frames to go.
+pub trait FrameSink {
+    fn write_frame(&mut self, index: u32, frame: &RgbaImage) -> io::Result<()>;
+}
+
+/// Writes every frame as a numbered PNG (`frame_00000.png` and so on) into a directory.
+pub struct PngSequence {
+    directory: PathBuf,
+}
+
+impl PngSequence {
+    /// Creates the directory if it doesn't exist yet.
+    pub fn new(directory: impl Into<PathBuf>) -> io::Result<Self> {
+        let directory = directory.into();
+        fs::create_dir_all(&directory)?;
+
+        Ok(Self { directory })
+    }
+}
+
+impl FrameSink for PngSequence {
+    fn write_frame(&mut self, index: u32, frame: &RgbaImage) -> io::Result<()> {
+        frame
+            .save(self.directory.join(format!("frame_{index:05}.png")))
+            .map_err(io::Error::other)
+    }
+}
+
+/// Writes frames back to back as raw RGBA8, which is what encoders expect on stdin -
+/// for example `ffmpeg -f rawvideo -pix_fmt rgba -s 1000x1000 -r 30 -i - out.webm`.
+pub struct RawVideoSink<W: Write> {
+    pub writer: W,
+}
+
+impl<W: Write> FrameSink for RawVideoSink<W> {
+    fn write_frame(&mut self, _index: u32, frame: &RgbaImage) -> io::Result<()> {
+        self.writer.write_all(frame.as_raw())
+    }
+}
+
+/// Inputs for a single recorded frame, filled in by the caller's update callback.
+pub struct RecordingFrame<'a> {
+    pub index: u32,
+    /// Time since the start of the recording, in seconds.
+    pub time: f32,
+    /// The fixed timestep, to advance physics and motions by.
+    pub delta_seconds: f32,
+    pub params: &'a mut [f32],
+    pub part_opacities: &'a mut [f32],
+}
+
+/// What to [record], and at what pace.
+pub struct RecordingSettings<'a, 'p> {
+    pub puppet: &'a PuppetRef<'p>,
+    /// The fixed rate frames are recorded at, independent of how long rendering actually
+    /// takes.
+    pub fps: f32,
+    pub frame_count: u32,
+}
+
+/// Records [frame_count](RecordingSettings::frame_count) frames of a puppet into `sink`.
+///
+/// Before each frame, `update` is called to step whatever drives the puppet (motions,
+/// physics, a recorded parameter stream...) by exactly one timestep. Parameters and part
+/// opacities carry over between frames, starting from the puppet's defaults. Frames that
+/// can't be read back fail the recording like any other write error.
+pub fn record(
+    device: &Device,
+    queue: &Queue,
+    capture: &mut FrameCapture,
+    renderer: &mut Renderer,
+    settings: RecordingSettings,
+    mut update: impl FnMut(RecordingFrame),
+    sink: &mut impl FrameSink,
+) -> io::Result<()> {
+    let RecordingSettings {
+        puppet,
+        fps,
+        frame_count,
+    } = settings;
+    let delta_seconds = 1.0 / fps;
+    let mut params = puppet.param_data().defaults.clone();
+    let mut part_opacities = vec![1.0; puppet.part_count as usize];
+    let mut frame_data = framedata_for_puppet(puppet);
+
+    for index in 0..frame_count {
+        update(RecordingFrame {
+            index,
+            time: index as f32 * delta_seconds,
+            delta_seconds,
+            params: &mut params,
+            part_opacities: &mut part_opacities,
+        });
+
+        puppet.update(&params, &part_opacities, &mut frame_data);
+        let frame = capture
+            .capture(device, queue, renderer, &frame_data)
+            .map_err(io::Error::other)?;
+        sink.write_frame(index, &frame)?;
+    }
+
+    Ok(())
+}
@@ -0,0 +1,385 @@
+//! Drawing many copies of the same puppet at once.
+
+use encase::{DynamicUniformBuffer, ShaderSize};
+use glam::Mat4;
+use wgpu::*;
+
+use moc3_rs::{
+    data::ArtMeshFlags,
+    puppet::{Canvas, PuppetFrameData, PuppetRef},
+};
+
+use crate::{
+    background::{Background, BackgroundLayer},
+    renderer::{
+        begin_pass, draw_masked, resize_stencil, scissor_rect, FitMode, MeshBuffers, MeshOverride,
+        MeshOverrides, PipelineCache, Pipelines, RenderOverrides, Uniform,
+    },
+    texture::TextureSource,
+};
+
+/// Draws any number of copies of one puppet, each with its own frame and transform, in a
+/// single render pass. Useful for crowds and for stress testing.
+///
+/// Every copy shares the puppet's textures, UVs and indices, and the vertexes and uniforms
+/// of all of them are uploaded together, so a copy costs little more than its vertexes.
+/// Copies are drawn whole, one after another, in the order they're given to
+/// [CrowdRenderer::prepare]. Mesh and render overrides apply to every copy, and masks are
+/// always hard.
+pub struct CrowdRenderer {
+    pipelines: Pipelines,
+    mesh_flags: Vec<ArtMeshFlags>,
+    texture_nums: Vec<u32>,
+    mask_indices: Vec<Vec<u32>>,
+    canvas: Canvas,
+
+    bound_textures: Vec<BindGroup>,
+    overrides: MeshOverrides,
+    meshes: MeshBuffers,
+    fit_mode: FitMode,
+    placeholder_mode: bool,
+
+    background: Background,
+    background_layer: Option<BackgroundLayer>,
+
+    // How many copies the buffers have room for, which only ever grows.
+    capacity: usize,
+    camera_alignment: u64,
+    uniform_alignment: u64,
+    camera_buffer: Buffer,
+    uniform_buffer: Buffer,
+    // One for every copy there's room for, each pointing at that copy's camera and uniforms.
+    bind_groups: Vec<BindGroup>,
+    camera_staging: Vec<u8>,
+    uniform_staging: Vec<u8>,
+
+    // What each copy prepared last draws, and where.
+    render_orders: Vec<Vec<u32>>,
+    scissors: Vec<Option<[u32; 4]>>,
+    render_size: Extent3d,
+    mask_stencil: Option<Texture>,
+}
+
+#[cfg(not(target_arch = "wasm32"))]
+const _: fn() = || {
+    fn assert_send_sync<T: Send + Sync>() {}
+
+    assert_send_sync::<CrowdRenderer>();
+};
+
+impl CrowdRenderer {
+    pub fn new(
+        puppet: &PuppetRef,
+        device: &Device,
+        queue: &Queue,
+        format: TextureFormat,
+        textures: &[impl TextureSource],
+    ) -> Self {
+        let cache = PipelineCache::new(device, queue);
+        Self::with_cache(puppet, device, queue, &cache, format, textures)
+    }
+
+    /// Like [CrowdRenderer::new], but sharing pipelines with every other renderer and scene
+    /// using the same cache.
+    pub fn with_cache(
+        puppet: &PuppetRef,
+        device: &Device,
+        queue: &Queue,
+        cache: &PipelineCache,
+        format: TextureFormat,
+        textures: &[impl TextureSource],
+    ) -> Self {
+        let mut pipelines = Pipelines::new(cache, format);
+        pipelines.load(device, puppet);
+
+        let bound_textures = textures
+            .iter()
+            .map(|x| pipelines.bind_texture(device, &x.texture_view(device, queue)))
+            .collect();
+
+        let camera_alignment = (std::mem::size_of::<Mat4>() as u64)
+            .max(device.limits().min_uniform_buffer_offset_alignment as u64);
+        let uniform_alignment = Uniform::SHADER_SIZE
+            .get()
+            .max(device.limits().min_uniform_buffer_offset_alignment as u64);
+
+        let mut crowd = Self {
+            mesh_flags: puppet.art_mesh_flags.clone(),
+            texture_nums: puppet.art_mesh_textures.clone(),
+            mask_indices: puppet.art_mesh_mask_indices.clone(),
+            canvas: *puppet.canvas(),
+
+            bound_textures,
+            overrides: MeshOverrides::new(puppet.art_mesh_count as usize),
+            meshes: MeshBuffers::new(device, puppet),
+            fit_mode: FitMode::default(),
+            placeholder_mode: false,
+
+            background: Background::None,
+            background_layer: None,
+
+            capacity: 0,
+            camera_alignment,
+            uniform_alignment,
+            camera_buffer: uniform_buffer(device, camera_alignment),
+            uniform_buffer: uniform_buffer(device, uniform_alignment),
+            bind_groups: Vec::new(),
+            camera_staging: Vec::new(),
+            uniform_staging: Vec::new(),
+
+            render_orders: Vec::new(),
+            scissors: Vec::new(),
+            render_size: Extent3d::default(),
+            mask_stencil: None,
+
+            pipelines,
+        };
+        crowd.reserve(device, 1);
+        crowd
+    }
+
+    /// The cache the crowd's pipelines come from, for sharing them with other renderers.
+    pub fn pipeline_cache(&self) -> &PipelineCache {
+        self.pipelines.cache()
+    }
+
+    pub fn background(&self) -> &Background {
+        &self.background
+    }
+
+    pub fn set_background(&mut self, device: &Device, queue: &Queue, background: Background) {
+        self.background_layer = self.pipelines.background_layer(device, queue, &background);
+        self.background = background;
+    }
+
+    pub fn fit_mode(&self) -> FitMode {
+        self.fit_mode
+    }
+
+    /// Sets how each copy's canvas is fitted into the render target, before its own
+    /// transform is applied. Copies are never drawn outside of their canvas.
+    pub fn set_fit_mode(&mut self, fit_mode: FitMode) {
+        self.fit_mode = fit_mode;
+    }
+
+    /// Whether meshes are drawn as flat colors instead of textured.
+    pub fn placeholder_mode(&self) -> bool {
+        self.placeholder_mode
+    }
+
+    pub fn set_placeholder_mode(&mut self, placeholder_mode: bool) {
+        self.placeholder_mode = placeholder_mode;
+    }
+
+    /// The override on the art mesh with the given index, see
+    /// [CrowdRenderer::set_mesh_override].
+    pub fn mesh_override(&self, index: usize) -> MeshOverride {
+        self.overrides.meshes[index]
+    }
+
+    /// Tints or fades the art mesh with the given index in every copy from the next
+    /// prepare on, like [Renderer::set_mesh_override](crate::renderer::Renderer::set_mesh_override).
+    pub fn set_mesh_override(&mut self, index: usize, mesh_override: MeshOverride) {
+        self.overrides.meshes[index] = mesh_override;
+    }
+
+    /// Puts every art mesh back to [MeshOverride::NONE].
+    pub fn clear_mesh_overrides(&mut self) {
+        self.overrides.meshes.fill(MeshOverride::NONE);
+    }
+
+    /// Forces art meshes and parts to be shown or hidden in every copy from the next
+    /// prepare on, replacing whatever was set before.
+    pub fn set_render_overrides(&mut self, puppet: &PuppetRef, overrides: &RenderOverrides) {
+        self.overrides.set_visibilities(overrides.resolve(puppet));
+    }
+
+    /// How many copies were given to the last prepare.
+    pub fn len(&self) -> usize {
+        self.render_orders.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.render_orders.is_empty()
+    }
+
+    /// Uploads a frame for every copy of the puppet to draw, each paired with a transform in
+    /// clip space like [Renderer::set_camera](crate::renderer::Renderer::set_camera).
+    /// Only the copies given here are drawn, until the next prepare.
+    ///
+    /// Crowds are always deformed on the CPU, so this panics if any frame is deferred.
+    pub fn prepare(
+        &mut self,
+        device: &Device,
+        queue: &Queue,
+        render_size: Extent3d,
+        frames: &[(Mat4, &PuppetFrameData)],
+    ) {
+        resize_stencil(&mut self.mask_stencil, device, render_size);
+        self.render_size = render_size;
+        self.reserve(device, frames.len());
+
+        self.render_orders.resize_with(frames.len(), Vec::new);
+        self.scissors.clear();
+
+        let mut cameras = std::mem::take(&mut self.camera_staging);
+        cameras.clear();
+        let mut cameras = DynamicUniformBuffer::new_with_alignment(cameras, self.camera_alignment);
+        let mut uniforms = std::mem::take(&mut self.uniform_staging);
+        uniforms.clear();
+        let mut uniforms =
+            DynamicUniformBuffer::new_with_alignment(uniforms, self.uniform_alignment);
+
+        for (instance, (camera, frame_data)) in frames.iter().enumerate() {
+            assert!(
+                frame_data.deferred_deform().is_none(),
+                "crowds can't deform frames on the GPU"
+            );
+
+            let transform = *camera * self.fit_mode.matrix(&self.canvas, render_size);
+            self.scissors
+                .push(scissor_rect(transform, &self.canvas, render_size));
+            cameras.write(&transform).unwrap();
+
+            // Puppet subsets draw fewer meshes than they have buffers for.
+            let render_order = &mut self.render_orders[instance];
+            render_order.clear();
+            render_order.extend_from_slice(frame_data.render_order());
+
+            // Crowds always mask with the stencil, so there are no soft mask layers.
+            self.overrides
+                .write_uniforms(&mut uniforms, frame_data, &self.mesh_flags, None, |i| {
+                    self.uses_placeholder(i)
+                });
+        }
+
+        self.meshes
+            .write_instances(queue, frames.iter().map(|x| x.1.art_mesh_positions()));
+        // wgpu already copies every write_buffer of a submission out of one staging area,
+        // so a single write per buffer is all it takes to keep this cheap.
+        self.camera_staging = cameras.into_inner();
+        self.uniform_staging = uniforms.into_inner();
+        if !self.camera_staging.is_empty() {
+            queue.write_buffer(&self.camera_buffer, 0, &self.camera_staging);
+        }
+        if !self.uniform_staging.is_empty() {
+            queue.write_buffer(&self.uniform_buffer, 0, &self.uniform_staging);
+        }
+    }
+
+    pub fn render(&self, view: &TextureView, encoder: &mut CommandEncoder) {
+        let mask_stencil = self.mask_stencil.as_ref().unwrap();
+        let mask_view = mask_stencil.create_view(&TextureViewDescriptor::default());
+        let mut rpass = begin_pass(
+            encoder,
+            view,
+            &mask_view,
+            self.pipelines.format,
+            &self.background,
+            self.background_layer.as_ref(),
+        );
+
+        self.draw(&mut rpass);
+    }
+
+    // Each masked mesh of every copy gets the next stencil value, like puppets in a scene.
+    fn draw<'a>(&'a self, rpass: &mut RenderPass<'a>) {
+        let mut stencil_ref = 0;
+        for (instance, render_order) in self.render_orders.iter().enumerate() {
+            let Some(scissor) = self.scissors[instance] else {
+                continue;
+            };
+            if render_order.is_empty() {
+                continue;
+            }
+            let [x, y, width, height] = scissor;
+            rpass.set_scissor_rect(x, y, width, height);
+            self.meshes.bind_instance(rpass, instance);
+
+            let draw_mesh = |rpass: &mut RenderPass<'a>, art_index: usize| {
+                rpass.set_bind_group(
+                    0,
+                    &self.bind_groups[instance],
+                    &[(self.uniform_alignment * art_index as u64) as u32],
+                );
+                rpass.set_bind_group(1, self.texture_bind_group(art_index), &[]);
+                self.meshes.draw(rpass, art_index);
+            };
+            for art_index in render_order.iter().copied() {
+                draw_masked(
+                    &self.pipelines,
+                    rpass,
+                    &self.mesh_flags,
+                    &self.mask_indices,
+                    art_index as usize,
+                    scissor,
+                    self.render_size,
+                    &mut stencil_ref,
+                    draw_mesh,
+                );
+            }
+        }
+    }
+
+    // Grows the buffers to fit at least this many copies, recreating the bind groups
+    // pointing into them.
+    fn reserve(&mut self, device: &Device, instances: usize) {
+        if instances <= self.capacity {
+            return;
+        }
+        let capacity = instances.next_power_of_two();
+        let uniforms_size = self.uniform_alignment * self.mesh_flags.len().max(1) as u64;
+
+        self.meshes.reserve_instances(device, capacity);
+        self.camera_buffer = uniform_buffer(device, self.camera_alignment * capacity as u64);
+        self.uniform_buffer = uniform_buffer(device, uniforms_size * capacity as u64);
+        self.bind_groups = (0..capacity as u64)
+            .map(|instance| {
+                device.create_bind_group(&BindGroupDescriptor {
+                    layout: self.pipelines.uniform_layout(),
+                    entries: &[
+                        BindGroupEntry {
+                            binding: 0,
+                            resource: BindingResource::Buffer(BufferBinding {
+                                buffer: &self.camera_buffer,
+                                offset: self.camera_alignment * instance,
+                                size: BufferSize::new(std::mem::size_of::<Mat4>() as u64),
+                            }),
+                        },
+                        BindGroupEntry {
+                            binding: 1,
+                            resource: BindingResource::Buffer(BufferBinding {
+                                buffer: &self.uniform_buffer,
+                                offset: uniforms_size * instance,
+                                size: Some(Uniform::SHADER_SIZE),
+                            }),
+                        },
+                    ],
+                    label: None,
+                })
+            })
+            .collect();
+        self.capacity = capacity;
+    }
+
+    fn uses_placeholder(&self, art_index: usize) -> bool {
+        self.placeholder_mode || self.bound_textures.len() <= self.texture_nums[art_index] as usize
+    }
+
+    fn texture_bind_group(&self, art_index: usize) -> &BindGroup {
+        if self.uses_placeholder(art_index) {
+            self.pipelines.placeholder_texture()
+        } else {
+            &self.bound_textures[self.texture_nums[art_index] as usize]
+        }
+    }
+}
+
+fn uniform_buffer(device: &Device, size: u64) -> Buffer {
+    device.create_buffer(&BufferDescriptor {
+        size,
+        usage: BufferUsages::UNIFORM | BufferUsages::COPY_DST,
+        mapped_at_creation: false,
+        label: None,
+    })
+}
@@ -0,0 +1,328 @@
+//! Overlays showing how a puppet is put together, see
+//! [Renderer::set_debug_overlay](crate::renderer::Renderer::set_debug_overlay).
+
+use std::ops::Range;
+
+use bytemuck::cast_slice;
+use glam::{vec2, Vec2, Vec4};
+use wgpu::{
+    util::{BufferInitDescriptor, DeviceExt},
+    *,
+};
+
+use moc3_rs::puppet::{PuppetFrameData, PuppetRef};
+
+use crate::{hook::STENCIL_FORMAT, renderer::ColorSpace};
+
+/// Which debug overlays are drawn over a puppet, each toggled on its own.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub struct DebugOverlay {
+    /// The edges of every drawn art mesh's triangles.
+    pub wireframes: bool,
+    /// Every mesh that masks a drawn mesh, filled in with a translucent color.
+    pub masks: bool,
+    /// The grid of every warp deformer, as deformed by its parents.
+    pub warp_grids: bool,
+    /// A cross at the origin of every rotation deformer.
+    pub rotation_origins: bool,
+}
+
+impl DebugOverlay {
+    pub const ALL: Self = Self {
+        wireframes: true,
+        masks: true,
+        warp_grids: true,
+        rotation_origins: true,
+    };
+
+    pub fn is_empty(&self) -> bool {
+        *self == Self::default()
+    }
+
+    fn needs_lines(&self) -> bool {
+        self.warp_grids || self.rotation_origins
+    }
+}
+
+// Premultiplied, and indexed by the order they're listed in the overlay.
+const COLORS: [Vec4; 4] = [
+    Vec4::new(1.0, 1.0, 1.0, 1.0),
+    Vec4::new(0.0, 0.35, 0.5, 0.5),
+    Vec4::new(0.0, 0.8, 0.2, 1.0),
+    Vec4::new(1.0, 0.1, 0.6, 1.0),
+];
+const WIREFRAME_COLOR: u64 = 0;
+const MASK_COLOR: u64 = 1;
+const WARP_COLOR: u64 = 2;
+const ROTATION_COLOR: u64 = 3;
+
+pub(crate) struct DebugResources {
+    fill_pipeline: RenderPipeline,
+    line_pipeline: RenderPipeline,
+    colors: Buffer,
+
+    // Every edge of every mesh's triangles, once each and offset like the mesh indices.
+    edge_buffer: Buffer,
+    edge_format: IndexFormat,
+    edge_ranges: Vec<Range<u32>>,
+
+    // The warp grids' lines and then the rotation origins' crosses, rewritten each frame.
+    line_buffer: Buffer,
+    grid_sizes: Vec<[u32; 2]>,
+    grid_lines: Range<u32>,
+    origin_lines: Range<u32>,
+    cross_size: f32,
+    staging: Vec<Vec2>,
+}
+
+impl DebugResources {
+    pub fn new(
+        device: &Device,
+        format: TextureFormat,
+        uniform_layout: &BindGroupLayout,
+        puppet: &PuppetRef,
+    ) -> Self {
+        let layout = device.create_pipeline_layout(&PipelineLayoutDescriptor {
+            bind_group_layouts: &[uniform_layout],
+            ..PipelineLayoutDescriptor::default()
+        });
+        let module = device.create_shader_module(include_wgsl!("./shader/debug.wgsl"));
+
+        let mut edges = Vec::new();
+        let mut edge_ranges = Vec::with_capacity(puppet.art_mesh_count as usize);
+        let mut mesh_edges = Vec::new();
+        let mut first_vertex = 0;
+        for (mesh_indices, len) in puppet
+            .art_mesh_indices
+            .iter()
+            .zip(&puppet.art_mesh_vertexes)
+        {
+            mesh_edges.clear();
+            for triangle in mesh_indices.chunks_exact(3) {
+                for (a, b) in [(0, 1), (1, 2), (2, 0)] {
+                    let (a, b) = (triangle[a] as u32, triangle[b] as u32);
+                    mesh_edges.push((a.min(b), a.max(b)));
+                }
+            }
+            mesh_edges.sort_unstable();
+            mesh_edges.dedup();
+
+            let start = edges.len() as u32;
+            for (a, b) in &mesh_edges {
+                edges.extend([first_vertex + a, first_vertex + b]);
+            }
+            edge_ranges.push(start..edges.len() as u32);
+            first_vertex += len;
+        }
+
+        let (edge_format, edge_bytes) = if first_vertex <= u16::MAX as u32 + 1 {
+            let edges: Vec<u16> = edges.iter().map(|x| *x as u16).collect();
+            (IndexFormat::Uint16, cast_slice(&edges).to_vec())
+        } else {
+            (IndexFormat::Uint32, cast_slice(&edges).to_vec())
+        };
+
+        let grid_sizes = puppet.warp_deformer_grid_sizes();
+        let grid_points: u32 = grid_sizes
+            .iter()
+            .map(|[columns, rows]| columns * (rows + 1) + rows * (columns + 1))
+            .sum::<u32>()
+            * 2;
+        let grid_lines = 0..grid_points;
+        let origin_lines = grid_lines.end..grid_lines.end + puppet.rotation_deformer_count * 4;
+
+        let (min, max) = puppet.canvas().bounds();
+
+        Self {
+            fill_pipeline: debug_pipeline(
+                device,
+                &layout,
+                &module,
+                format,
+                PrimitiveTopology::TriangleList,
+            ),
+            line_pipeline: debug_pipeline(
+                device,
+                &layout,
+                &module,
+                format,
+                PrimitiveTopology::LineList,
+            ),
+            colors: device.create_buffer_init(&BufferInitDescriptor {
+                contents: cast_slice(&COLORS),
+                usage: BufferUsages::VERTEX,
+                label: None,
+            }),
+
+            edge_buffer: device.create_buffer_init(&BufferInitDescriptor {
+                contents: &edge_bytes,
+                usage: BufferUsages::INDEX,
+                label: None,
+            }),
+            edge_format,
+            edge_ranges,
+
+            line_buffer: device.create_buffer(&BufferDescriptor {
+                // Empty buffers can't be bound.
+                size: (origin_lines.end.max(1) as usize * std::mem::size_of::<Vec2>()) as u64,
+                usage: BufferUsages::VERTEX | BufferUsages::COPY_DST,
+                mapped_at_creation: false,
+                label: None,
+            }),
+            grid_sizes,
+            staging: Vec::with_capacity(origin_lines.end as usize),
+            grid_lines,
+            origin_lines,
+            cross_size: (max - min).max_element() * 0.02,
+        }
+    }
+
+    pub fn prepare(&mut self, queue: &Queue, overlay: DebugOverlay, frame_data: &PuppetFrameData) {
+        if !overlay.needs_lines() || self.origin_lines.end == 0 {
+            return;
+        }
+
+        self.staging.clear();
+        for (grid, [columns, rows]) in frame_data
+            .warp_deformer_grids()
+            .iter()
+            .zip(&self.grid_sizes)
+        {
+            let (columns, rows) = (*columns as usize, *rows as usize);
+            let point = |column: usize, row: usize| grid[column + row * (columns + 1)];
+            for row in 0..=rows {
+                for column in 0..columns {
+                    self.staging
+                        .extend([point(column, row), point(column + 1, row)]);
+                }
+            }
+            for column in 0..=columns {
+                for row in 0..rows {
+                    self.staging
+                        .extend([point(column, row), point(column, row + 1)]);
+                }
+            }
+        }
+
+        for origin in frame_data.rotation_deformer_origins() {
+            let size = self.cross_size;
+            self.staging.extend([
+                origin - vec2(size, 0.0),
+                origin + vec2(size, 0.0),
+                origin - vec2(0.0, size),
+                origin + vec2(0.0, size),
+            ]);
+        }
+
+        queue.write_buffer(&self.line_buffer, 0, cast_slice(&self.staging));
+    }
+
+    // Expects the mesh buffers to be bound already, for drawing masks with.
+    pub fn begin_masks<'a>(&'a self, rpass: &mut RenderPass<'a>) {
+        rpass.set_pipeline(&self.fill_pipeline);
+        self.set_color(rpass, MASK_COLOR);
+    }
+
+    pub fn draw_wireframes<'a>(
+        &'a self,
+        rpass: &mut RenderPass<'a>,
+        vertex_buffer: &'a Buffer,
+        art_meshes: impl Iterator<Item = usize>,
+    ) {
+        rpass.set_pipeline(&self.line_pipeline);
+        rpass.set_vertex_buffer(0, vertex_buffer.slice(..));
+        rpass.set_index_buffer(self.edge_buffer.slice(..), self.edge_format);
+        self.set_color(rpass, WIREFRAME_COLOR);
+        for art_index in art_meshes {
+            rpass.draw_indexed(self.edge_ranges[art_index].clone(), 0, 0..1);
+        }
+    }
+
+    pub fn draw_lines<'a>(&'a self, rpass: &mut RenderPass<'a>, overlay: DebugOverlay) {
+        if !overlay.needs_lines() {
+            return;
+        }
+
+        rpass.set_pipeline(&self.line_pipeline);
+        rpass.set_vertex_buffer(0, self.line_buffer.slice(..));
+        if overlay.warp_grids && !self.grid_lines.is_empty() {
+            self.set_color(rpass, WARP_COLOR);
+            rpass.draw(self.grid_lines.clone(), 0..1);
+        }
+        if overlay.rotation_origins && !self.origin_lines.is_empty() {
+            self.set_color(rpass, ROTATION_COLOR);
+            rpass.draw(self.origin_lines.clone(), 0..1);
+        }
+    }
+
+    // Colors are per instance, and picked by where their buffer is bound from since
+    // WebGL2 can't start drawing from any instance but the first.
+    fn set_color<'a>(&'a self, rpass: &mut RenderPass<'a>, color: u64) {
+        let size = std::mem::size_of::<Vec4>() as u64;
+        rpass.set_vertex_buffer(1, self.colors.slice(color * size..(color + 1) * size));
+    }
+}
+
+fn debug_pipeline(
+    device: &Device,
+    layout: &PipelineLayout,
+    module: &ShaderModule,
+    format: TextureFormat,
+    topology: PrimitiveTopology,
+) -> RenderPipeline {
+    // The stencil is left alone, overlays are drawn over everything regardless of masks.
+    let face_state = StencilFaceState {
+        compare: CompareFunction::Always,
+        fail_op: StencilOperation::Keep,
+        depth_fail_op: StencilOperation::Keep,
+        pass_op: StencilOperation::Keep,
+    };
+
+    device.create_render_pipeline(&RenderPipelineDescriptor {
+        label: None,
+        layout: Some(layout),
+        vertex: VertexState {
+            module,
+            entry_point: "vs_main",
+            buffers: &[
+                VertexBufferLayout {
+                    array_stride: std::mem::size_of::<Vec2>() as BufferAddress,
+                    step_mode: VertexStepMode::Vertex,
+                    attributes: &vertex_attr_array![0 => Float32x2],
+                },
+                VertexBufferLayout {
+                    array_stride: std::mem::size_of::<Vec4>() as BufferAddress,
+                    step_mode: VertexStepMode::Instance,
+                    attributes: &vertex_attr_array![1 => Float32x4],
+                },
+            ],
+        },
+        fragment: Some(FragmentState {
+            module,
+            entry_point: ColorSpace::of(format).fs_main(),
+            targets: &[Some(ColorTargetState {
+                format,
+                blend: Some(BlendState::PREMULTIPLIED_ALPHA_BLENDING),
+                write_mask: ColorWrites::ALL,
+            })],
+        }),
+        primitive: PrimitiveState {
+            topology,
+            ..PrimitiveState::default()
+        },
+        depth_stencil: Some(DepthStencilState {
+            format: STENCIL_FORMAT,
+            depth_write_enabled: false,
+            depth_compare: CompareFunction::Always,
+            stencil: StencilState {
+                front: face_state,
+                back: face_state,
+                read_mask: 0,
+                write_mask: 0,
+            },
+            bias: DepthBiasState::default(),
+        }),
+        multisample: MultisampleState::default(),
+        multiview: None,
+    })
+}
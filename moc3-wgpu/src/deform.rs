@@ -0,0 +1,409 @@
+//! Deforming puppets with compute shaders instead of on the CPU, see
+//! [Renderer::enable_gpu_deform](crate::renderer::Renderer::enable_gpu_deform).
+
+use std::borrow::Cow;
+
+use bytemuck::{cast_slice, Pod, Zeroable};
+use glam::Vec2;
+use moc3_rs::puppet::{DeferredDeform, GlueNormalization, KeyformWeight, PuppetRef};
+use wgpu::{util::DeviceExt, *};
+
+// How many storage buffers the deform shader binds at once, which is more than
+// Limits::downlevel_defaults allows.
+const STORAGE_BUFFERS_NEEDED: u32 = 7;
+const WORKGROUP_SIZE: u32 = 64;
+
+/// Whether the device can deform puppets with compute shaders, see
+/// [Renderer::enable_gpu_deform](crate::renderer::Renderer::enable_gpu_deform). This rules
+/// out WebGL2, and devices requested with [request_device](crate::renderer::request_device).
+pub fn supports_gpu_deform(device: &Device) -> bool {
+    let limits = device.limits();
+    limits.max_storage_buffers_per_shader_stage >= STORAGE_BUFFERS_NEEDED
+        && limits.max_compute_invocations_per_workgroup >= WORKGROUP_SIZE
+        && limits.max_compute_workgroup_size_x >= WORKGROUP_SIZE
+}
+
+#[derive(Pod, Zeroable, Clone, Copy)]
+#[repr(C)]
+struct Glue {
+    pair_start: u32,
+    pair_count: u32,
+}
+
+// Blends, deforms and glues a puppet's art meshes straight into its vertex buffer, from what
+// the frame data left for later with deferred deforming on.
+pub(crate) struct GpuDeform {
+    layout: BindGroupLayout,
+    pipeline: ComputePipeline,
+    bind_group: BindGroup,
+
+    keyform_positions: Buffer,
+    vertex_sources: Buffer,
+    // Sized by the first frame. Blend shapes change how many keyforms there are, so that
+    // one can grow later on.
+    keyform_offsets: GrowableBuffer,
+    keyforms: GrowableBuffer,
+    parents: GrowableBuffer,
+    warp_grids: GrowableBuffer,
+    vertex_count: u32,
+
+    glue: Option<GlueResources>,
+
+    // Scratch space for flattening each frame before uploading it.
+    offsets_scratch: Vec<u32>,
+    keyforms_scratch: Vec<KeyformWeight>,
+    grids_scratch: Vec<Vec2>,
+}
+
+struct GlueResources {
+    pipeline: ComputePipeline,
+    normalized_pipeline: ComputePipeline,
+    bind_group: BindGroup,
+    normalized_bind_group: BindGroup,
+    normalized: bool,
+
+    intensities: Buffer,
+    kind_indices: Vec<u32>,
+    intensities_scratch: Vec<f32>,
+}
+
+impl GpuDeform {
+    // `vertexes` is the puppet's merged vertex buffer, which needs storage usage.
+    pub fn new(device: &Device, puppet: &PuppetRef, vertexes: &Buffer) -> Self {
+        let mut first_vertexes = Vec::with_capacity(puppet.art_mesh_count as usize);
+        let mut vertex_sources = Vec::new();
+        for (mesh, len) in puppet.art_mesh_vertexes.iter().enumerate() {
+            first_vertexes.push(vertex_sources.len() as u32);
+            vertex_sources.extend((0..*len).map(|i| [mesh as u32, i]));
+        }
+
+        let shader = device.create_shader_module(ShaderModuleDescriptor {
+            label: None,
+            source: ShaderSource::Wgsl(Cow::Borrowed(include_str!("shader/deform.wgsl"))),
+        });
+        // Only the vertexes are written.
+        let mut writable = [false; STORAGE_BUFFERS_NEEDED as usize];
+        writable[6] = true;
+        let layout = storage_layout(device, &writable);
+        let pipeline = compute_pipeline(device, &layout, &shader, "deform");
+
+        let keyform_positions = storage_buffer(device, cast_slice(puppet.keyform_positions()));
+        let vertex_count = vertex_sources.len() as u32;
+        let vertex_sources = storage_buffer(device, cast_slice(&vertex_sources));
+
+        let keyform_offsets = GrowableBuffer::new(device);
+        let keyforms = GrowableBuffer::new(device);
+        let parents = GrowableBuffer::new(device);
+        let warp_grids = GrowableBuffer::new(device);
+
+        let bind_group = deform_bind_group(
+            device,
+            &layout,
+            [
+                &keyform_positions,
+                &vertex_sources,
+                &keyform_offsets.buffer,
+                &keyforms.buffer,
+                &parents.buffer,
+                &warp_grids.buffer,
+                vertexes,
+            ],
+        );
+
+        let glue = (!puppet.glues().is_empty())
+            .then(|| GlueResources::new(device, puppet, &first_vertexes, vertexes));
+
+        Self {
+            layout,
+            pipeline,
+            bind_group,
+
+            keyform_positions,
+            vertex_sources,
+            keyform_offsets,
+            keyforms,
+            parents,
+            warp_grids,
+            vertex_count,
+
+            glue,
+
+            offsets_scratch: Vec::with_capacity(puppet.art_mesh_count as usize + 1),
+            keyforms_scratch: Vec::new(),
+            grids_scratch: Vec::new(),
+        }
+    }
+
+    pub fn prepare(
+        &mut self,
+        device: &Device,
+        queue: &Queue,
+        deferred: DeferredDeform,
+        glue_normalization: GlueNormalization,
+        vertexes: &Buffer,
+    ) {
+        self.offsets_scratch.clear();
+        self.keyforms_scratch.clear();
+        for i in 0..deferred.parents().len() {
+            self.offsets_scratch
+                .push(self.keyforms_scratch.len() as u32);
+            self.keyforms_scratch
+                .extend_from_slice(deferred.keyforms(i));
+        }
+        self.offsets_scratch
+            .push(self.keyforms_scratch.len() as u32);
+
+        self.grids_scratch.clear();
+        for grid in deferred.warp_grids() {
+            self.grids_scratch.extend_from_slice(grid);
+        }
+
+        let mut grown = false;
+        grown |= self
+            .keyform_offsets
+            .write(device, queue, cast_slice(&self.offsets_scratch));
+        grown |= self
+            .keyforms
+            .write(device, queue, cast_slice(&self.keyforms_scratch));
+        grown |= self
+            .parents
+            .write(device, queue, cast_slice(deferred.parents()));
+        grown |= self
+            .warp_grids
+            .write(device, queue, cast_slice(&self.grids_scratch));
+
+        if grown {
+            self.bind_group = deform_bind_group(
+                device,
+                &self.layout,
+                [
+                    &self.keyform_positions,
+                    &self.vertex_sources,
+                    &self.keyform_offsets.buffer,
+                    &self.keyforms.buffer,
+                    &self.parents.buffer,
+                    &self.warp_grids.buffer,
+                    vertexes,
+                ],
+            );
+        }
+
+        if let Some(glue) = &mut self.glue {
+            let intensities = deferred.glue_intensities();
+            glue.intensities_scratch.clear();
+            glue.intensities_scratch
+                .extend(glue.kind_indices.iter().map(|x| intensities[*x as usize]));
+            queue.write_buffer(&glue.intensities, 0, cast_slice(&glue.intensities_scratch));
+            glue.normalized = glue_normalization == GlueNormalization::PerVertex;
+        }
+    }
+
+    pub fn dispatch(&self, encoder: &mut CommandEncoder) {
+        if self.vertex_count == 0 {
+            return;
+        }
+
+        let mut cpass = encoder.begin_compute_pass(&ComputePassDescriptor {
+            label: Some("moc3 deform"),
+        });
+        cpass.set_pipeline(&self.pipeline);
+        cpass.set_bind_group(0, &self.bind_group, &[]);
+        cpass.dispatch_workgroups(self.vertex_count.div_ceil(WORKGROUP_SIZE), 1, 1);
+
+        if let Some(glue) = &self.glue {
+            if glue.normalized {
+                cpass.set_pipeline(&glue.normalized_pipeline);
+                cpass.set_bind_group(0, &glue.normalized_bind_group, &[]);
+            } else {
+                cpass.set_pipeline(&glue.pipeline);
+                cpass.set_bind_group(0, &glue.bind_group, &[]);
+            }
+            cpass.dispatch_workgroups(1, 1, 1);
+        }
+    }
+}
+
+impl GlueResources {
+    fn new(device: &Device, puppet: &PuppetRef, first_vertexes: &[u32], vertexes: &Buffer) -> Self {
+        let mut glues = Vec::with_capacity(puppet.glues().len());
+        let mut pairs = Vec::new();
+        let mut weights = Vec::new();
+        let mut normalized_weights = Vec::new();
+        for glue in puppet.glues() {
+            let [first, second] = glue.art_mesh_index.map(|x| first_vertexes[x as usize]);
+            glues.push(Glue {
+                pair_start: pairs.len() as u32,
+                pair_count: glue.mesh_indices.len() as u32 / 2,
+            });
+            for index in glue.mesh_indices.chunks_exact(2) {
+                pairs.push([first + index[0] as u32, second + index[1] as u32]);
+            }
+            weights.extend_from_slice(&glue.weights);
+            normalized_weights.extend_from_slice(&glue.normalized_weights);
+        }
+
+        let shader = device.create_shader_module(ShaderModuleDescriptor {
+            label: None,
+            source: ShaderSource::Wgsl(Cow::Borrowed(include_str!("shader/glue.wgsl"))),
+        });
+        // The deltas and vertexes are written.
+        let layout = storage_layout(device, &[false, false, false, false, true, true]);
+        let pipeline = compute_pipeline(device, &layout, &shader, "glue");
+        let normalized_pipeline = compute_pipeline(device, &layout, &shader, "glue_normalized");
+
+        let glue_buffer = storage_buffer(device, cast_slice(&glues));
+        let pairs_buffer = storage_buffer(device, cast_slice(&pairs));
+        let weights = storage_buffer(device, cast_slice(&weights));
+        let normalized_weights = storage_buffer(device, cast_slice(&normalized_weights));
+        let intensities = empty_storage_buffer(device, (glues.len() * 4) as u64);
+        let deltas =
+            empty_storage_buffer(device, std::mem::size_of_val(pairs.as_slice()) as u64 * 2);
+
+        let bind_group = |weights: &Buffer| {
+            let buffers = [
+                &glue_buffer,
+                &pairs_buffer,
+                weights,
+                &intensities,
+                &deltas,
+                vertexes,
+            ];
+            device.create_bind_group(&BindGroupDescriptor {
+                layout: &layout,
+                entries: &storage_entries(&buffers),
+                label: None,
+            })
+        };
+
+        Self {
+            bind_group: bind_group(&weights),
+            normalized_bind_group: bind_group(&normalized_weights),
+            pipeline,
+            normalized_pipeline,
+            normalized: false,
+
+            intensities,
+            kind_indices: puppet.glues().iter().map(|x| x.kind_index).collect(),
+            intensities_scratch: Vec::with_capacity(glues.len()),
+        }
+    }
+}
+
+// Storage buffers bound one after another, only visible to compute shaders.
+fn storage_layout(device: &Device, writable: &[bool]) -> BindGroupLayout {
+    let entries: Vec<_> = writable
+        .iter()
+        .enumerate()
+        .map(|(i, writable)| BindGroupLayoutEntry {
+            binding: i as u32,
+            visibility: ShaderStages::COMPUTE,
+            ty: BindingType::Buffer {
+                ty: BufferBindingType::Storage {
+                    read_only: !writable,
+                },
+                has_dynamic_offset: false,
+                min_binding_size: None,
+            },
+            count: None,
+        })
+        .collect();
+
+    device.create_bind_group_layout(&BindGroupLayoutDescriptor {
+        entries: &entries,
+        label: None,
+    })
+}
+
+fn compute_pipeline(
+    device: &Device,
+    layout: &BindGroupLayout,
+    shader: &ShaderModule,
+    entry_point: &str,
+) -> ComputePipeline {
+    let pipeline_layout = device.create_pipeline_layout(&PipelineLayoutDescriptor {
+        bind_group_layouts: &[layout],
+        ..PipelineLayoutDescriptor::default()
+    });
+
+    device.create_compute_pipeline(&ComputePipelineDescriptor {
+        label: Some(entry_point),
+        layout: Some(&pipeline_layout),
+        module: shader,
+        entry_point,
+    })
+}
+
+fn deform_bind_group(
+    device: &Device,
+    layout: &BindGroupLayout,
+    buffers: [&Buffer; STORAGE_BUFFERS_NEEDED as usize],
+) -> BindGroup {
+    device.create_bind_group(&BindGroupDescriptor {
+        layout,
+        entries: &storage_entries(&buffers),
+        label: None,
+    })
+}
+
+fn storage_entries<'a>(buffers: &[&'a Buffer]) -> Vec<BindGroupEntry<'a>> {
+    buffers
+        .iter()
+        .enumerate()
+        .map(|(i, buffer)| BindGroupEntry {
+            binding: i as u32,
+            resource: buffer.as_entire_binding(),
+        })
+        .collect()
+}
+
+// Storage buffers can't be bound empty, so they're always big enough for at least one
+// of the largest element the shaders read.
+const MIN_STORAGE_SIZE: u64 = 64;
+
+fn storage_buffer(device: &Device, contents: &[u8]) -> Buffer {
+    if contents.is_empty() {
+        return empty_storage_buffer(device, 0);
+    }
+
+    device.create_buffer_init(&util::BufferInitDescriptor {
+        contents,
+        usage: BufferUsages::STORAGE,
+        label: None,
+    })
+}
+
+fn empty_storage_buffer(device: &Device, size: u64) -> Buffer {
+    device.create_buffer(&BufferDescriptor {
+        size: size.max(MIN_STORAGE_SIZE),
+        usage: BufferUsages::STORAGE | BufferUsages::COPY_DST,
+        mapped_at_creation: false,
+        label: None,
+    })
+}
+
+// A storage buffer that's recreated bigger whenever it needs to hold more.
+struct GrowableBuffer {
+    buffer: Buffer,
+}
+
+impl GrowableBuffer {
+    fn new(device: &Device) -> Self {
+        Self {
+            buffer: empty_storage_buffer(device, 0),
+        }
+    }
+
+    // Returns whether the buffer had to be recreated, which needs a new bind group.
+    fn write(&mut self, device: &Device, queue: &Queue, data: &[u8]) -> bool {
+        let grown = data.len() as u64 > self.buffer.size();
+        if grown {
+            let size = (data.len() as u64).next_power_of_two();
+            self.buffer = empty_storage_buffer(device, size);
+        }
+
+        if !data.is_empty() {
+            queue.write_buffer(&self.buffer, 0, data);
+        }
+        grown
+    }
+}
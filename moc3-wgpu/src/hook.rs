@@ -0,0 +1,41 @@
+//! Drawing your own things along with puppets, in the same encoder and into the same
+//! targets, without having to rewrite how a renderer renders.
+
+use wgpu::*;
+
+/// What a frame is being drawn into.
+pub struct FrameTargets<'a> {
+    pub view: &'a TextureView,
+    /// The stencil used for masking, which is cleared at the start of the puppets' pass.
+    pub stencil_view: &'a TextureView,
+    pub size: Extent3d,
+    pub format: TextureFormat,
+}
+
+/// The format of [FrameTargets::stencil_view], which pipelines drawing inside the puppets'
+/// pass need to list as their depth stencil format.
+pub const STENCIL_FORMAT: TextureFormat = TextureFormat::Depth24PlusStencil8;
+
+/// A custom step of rendering a frame, added with
+/// [Renderer::add_hook](crate::renderer::Renderer::add_hook) or
+/// [SceneRenderer::add_hook](crate::scene::SceneRenderer::add_hook).
+///
+/// Every method does nothing by default. Hooks run in the order they were added, and
+/// anything they need to change from frame to frame (uniforms and such) is best written
+/// between prepare and render, through a handle kept outside of the renderer.
+pub trait RenderHook: WasmNotSend + WasmNotSync {
+    /// Encodes passes of its own before the puppets' pass starts. Whatever is drawn into
+    /// the target here is cleared away again, unless the background is
+    /// [Background::Keep](crate::background::Background::Keep).
+    fn before_pass(&self, _encoder: &mut CommandEncoder, _targets: &FrameTargets) {}
+
+    /// Draws inside the puppets' pass, over the background and under every puppet.
+    fn draw_under<'a>(&'a self, _rpass: &mut RenderPass<'a>) {}
+
+    /// Draws inside the puppets' pass, over every puppet. The scissor is reset to the whole
+    /// target first, but the stencil reference is left as the last puppet set it.
+    fn draw_over<'a>(&'a self, _rpass: &mut RenderPass<'a>) {}
+
+    /// Encodes passes of its own after the puppets' pass ends, like post-processing.
+    fn after_pass(&self, _encoder: &mut CommandEncoder, _targets: &FrameTargets) {}
+}
@@ -1 +1,18 @@
+pub mod atlas;
+pub mod background;
+// Reading frames back blocks on the GPU, which browsers don't allow.
+#[cfg(not(target_arch = "wasm32"))]
+pub mod capture;
+pub mod crowd;
+pub mod debug;
+pub mod deform;
+pub mod hook;
+#[cfg(feature = "load")]
+pub mod load;
 pub mod renderer;
+pub mod scene;
+pub mod soft_mask;
+pub mod texture;
+// There's no file system to watch in browsers.
+#[cfg(all(feature = "watch", not(target_arch = "wasm32")))]
+pub mod watch;
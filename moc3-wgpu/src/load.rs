@@ -0,0 +1,173 @@
+//! Loading models from disk, along with everything [new_renderer](crate::renderer::new_renderer)
+//! needs to draw them.
+
+use std::path::{Path, PathBuf};
+
+use image::RgbaImage;
+use moc3_rs::{parse_puppet, puppet::Puppet, ParseError};
+use serde::Deserialize;
+use thiserror::Error;
+
+#[derive(Error, Debug)]
+pub enum LoadError {
+    #[error("could not read {}", .0.display())]
+    Io(PathBuf, #[source] std::io::Error),
+    #[error("could not parse model3.json")]
+    Json(#[from] serde_json::Error),
+    #[error(transparent)]
+    Parse(#[from] ParseError),
+    #[error("could not decode texture {}", .0.display())]
+    Texture(PathBuf, #[source] image::ImageError),
+}
+
+#[derive(Deserialize)]
+#[serde(rename_all = "PascalCase")]
+struct Model3 {
+    file_references: FileReferences,
+}
+
+#[derive(Deserialize)]
+#[serde(rename_all = "PascalCase")]
+struct FileReferences {
+    moc: String,
+    #[serde(default)]
+    textures: Vec<String>,
+    physics: Option<String>,
+}
+
+/// Where every file of a model is.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ModelFiles {
+    /// The model3.json the other files were found from, if there was one.
+    pub model3: Option<PathBuf>,
+    pub moc: PathBuf,
+    /// Every texture, in the order the model indexes them.
+    pub textures: Vec<PathBuf>,
+    pub physics: Option<PathBuf>,
+}
+
+/// A model read from disk, ready for [new_renderer](crate::renderer::new_renderer).
+pub struct LoadedModel {
+    pub puppet: Puppet,
+    pub textures: Vec<RgbaImage>,
+    pub files: ModelFiles,
+}
+
+impl ModelFiles {
+    /// Works out where a model's files are from its model3.json, or from a bare moc3 by
+    /// looking for textures and physics next to it the way Cubism exports them (see
+    /// [find_textures]). A model3.json without any textures listed has them looked for too.
+    pub fn find(path: impl AsRef<Path>) -> Result<Self, LoadError> {
+        let path = path.as_ref();
+        let directory = path.parent().unwrap_or(Path::new(""));
+
+        if path.extension().is_some_and(|x| x == "moc3") {
+            let physics = path.with_extension("physics3.json");
+            return Ok(Self {
+                model3: None,
+                moc: path.to_path_buf(),
+                textures: find_textures(path),
+                physics: physics.is_file().then_some(physics),
+            });
+        }
+
+        let json = std::fs::read(path).map_err(|e| LoadError::Io(path.to_path_buf(), e))?;
+        let references = serde_json::from_slice::<Model3>(&json)?.file_references;
+
+        let moc = directory.join(references.moc);
+        let textures = if references.textures.is_empty() {
+            find_textures(&moc)
+        } else {
+            references
+                .textures
+                .iter()
+                .map(|x| directory.join(x))
+                .collect()
+        };
+
+        Ok(Self {
+            model3: Some(path.to_path_buf()),
+            moc,
+            textures,
+            physics: references.physics.map(|x| directory.join(x)),
+        })
+    }
+
+    /// Reads and parses the moc3, and decodes every texture.
+    pub fn load(self) -> Result<LoadedModel, LoadError> {
+        let bytes = std::fs::read(&self.moc).map_err(|e| LoadError::Io(self.moc.clone(), e))?;
+        let puppet = parse_puppet(&bytes)?;
+
+        let textures = (0..self.textures.len())
+            .map(|index| self.load_texture(index))
+            .collect::<Result<_, _>>()?;
+
+        Ok(LoadedModel {
+            puppet,
+            textures,
+            files: self,
+        })
+    }
+
+    /// Decodes the texture with the given index, such as after it's changed on disk.
+    pub fn load_texture(&self, index: usize) -> Result<RgbaImage, LoadError> {
+        let path = &self.textures[index];
+        image::open(path)
+            .map(|x| x.into_rgba8())
+            .map_err(|e| LoadError::Texture(path.clone(), e))
+    }
+}
+
+/// Loads a model3.json or bare moc3 with everything it needs, see [ModelFiles::find].
+pub fn load_model(path: impl AsRef<Path>) -> Result<LoadedModel, LoadError> {
+    ModelFiles::find(path)?.load()
+}
+
+/// Looks for a moc3's textures the way Cubism exports them: `texture_00.png`,
+/// `texture_01.png` and so on, in a folder named after the model and the texture size
+/// (`model.4096/` for `model.moc3`). The biggest size wins if there's more than one, and
+/// textures right next to the moc3 are used if there's no such folder.
+pub fn find_textures(moc3: &Path) -> Vec<PathBuf> {
+    let directory = moc3.parent().unwrap_or(Path::new(""));
+    let stem = moc3.file_stem().unwrap_or_default().to_string_lossy();
+
+    let size_folder = read_dir(directory)
+        .filter(|x| x.is_dir())
+        .filter_map(|path| {
+            let name = path.file_name()?.to_str()?;
+            let size: u32 = name.strip_prefix(&*stem)?.strip_prefix('.')?.parse().ok()?;
+            Some((size, path))
+        })
+        .max_by_key(|(size, _)| *size)
+        .map(|(_, path)| path);
+
+    let mut textures: Vec<(u32, PathBuf)> = read_dir(size_folder.as_deref().unwrap_or(directory))
+        .filter_map(|path| {
+            let name = path.file_name()?.to_str()?;
+            let index = name
+                .strip_prefix("texture_")?
+                .strip_suffix(".png")?
+                .parse()
+                .ok()?;
+            Some((index, path))
+        })
+        .collect();
+    textures.sort();
+    textures.into_iter().map(|(_, path)| path).collect()
+}
+
+// Unreadable directories just don't have anything in them, as far as finding files goes.
+fn read_dir(directory: &Path) -> impl Iterator<Item = PathBuf> {
+    // An empty parent means the current directory.
+    let directory = if directory.as_os_str().is_empty() {
+        Path::new(".")
+    } else {
+        directory
+    };
+
+    std::fs::read_dir(directory)
+        .into_iter()
+        .flatten()
+        .filter_map(|x| x.ok())
+        .map(|x| x.path())
+}
@@ -1,7 +1,13 @@
+use std::{
+    collections::HashMap,
+    ops::Range,
+    sync::{Arc, Mutex},
+};
+
 use bytemuck::cast_slice;
-use encase::{ShaderSize, ShaderType, UniformBuffer};
-use glam::{Mat4, Vec2, Vec3};
-use image::RgbaImage;
+use encase::{DynamicUniformBuffer, ShaderSize, ShaderType};
+use glam::{vec2, vec3, Mat4, Vec2, Vec3};
+use image::ImageResult;
 use wgpu::{
     util::{BufferInitDescriptor, DeviceExt},
     *,
@@ -9,474 +15,1833 @@ use wgpu::{
 
 use moc3_rs::{
     data::{ArtMeshFlags, BlendMode},
-    puppet::{Puppet, PuppetFrameData},
+    puppet::{framedata_for_puppet, BlendColor, Canvas, PuppetFrameData, PuppetRef},
+};
+
+use crate::{
+    background::{background_layer, clear_color, Background, BackgroundLayer},
+    debug::{DebugOverlay, DebugResources},
+    deform::GpuDeform,
+    hook::{FrameTargets, RenderHook, STENCIL_FORMAT},
+    soft_mask::{SoftMask, SoftMaskResources},
+    texture::{RawRgba, TextureSource},
 };
 
-#[derive(ShaderType, Debug, Clone, Copy, PartialEq)]
-struct Uniform {
-    pub multiply_color: Vec3,
-    pub screen_color: Vec3,
-    pub opacity: f32,
-}
+/// How a puppet's [Canvas] is fitted into the render target.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum FitMode {
+    /// The whole canvas is shown at its own aspect ratio, leaving empty bars along the
+    /// sides of the target it doesn't reach.
+    #[default]
+    Contain,
+    /// The canvas covers the whole target at its own aspect ratio, cutting off whatever
+    /// sticks out.
+    Cover,
+    /// The canvas is stretched over the whole target.
+    Fill,
+}
+
+impl FitMode {
+    /// Maps the canvas, in model coordinates, into clip space.
+    pub fn matrix(self, canvas: &Canvas, target_size: Extent3d) -> Mat4 {
+        let (min, max) = canvas.bounds();
+        let target = vec2(target_size.width as f32, target_size.height as f32);
+        // How many target pixels each model unit takes up.
+        let pixels = target / (max - min);
+        let pixels = match self {
+            FitMode::Contain => Vec2::splat(pixels.min_element()),
+            FitMode::Cover => Vec2::splat(pixels.max_element()),
+            FitMode::Fill => pixels,
+        };
+
+        // Model coordinates point down, clip space points up.
+        let scale = pixels / target * 2.0;
+        Mat4::from_scale(vec3(scale.x, -scale.y, 1.0))
+            * Mat4::from_translation((-(min + max) / 2.0).extend(0.0))
+    }
+}
+
+/// Which space colors are blended in, which follows from the format of the render target.
+///
+/// Cubism draws everything in gamma space, with colors as they're stored, and models are
+/// made to look right that way. Drawing into an sRGB target decodes colors to linear light
+/// once they've been tinted instead, which keeps translucent layers and soft edges from
+/// darkening, at the cost of looking slightly different from the editor.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum ColorSpace {
+    /// Colors are blended as they're stored, like Cubism does. Targets with non-sRGB
+    /// formats, like [FrameCapture::FORMAT](crate::capture::FrameCapture::FORMAT), are
+    /// drawn this way.
+    #[default]
+    Gamma,
+    /// Colors are blended in linear light, and encoded again as they're written. Targets
+    /// with sRGB formats are drawn this way.
+    Linear,
+}
+
+impl ColorSpace {
+    /// The color space puppets are drawn in for the given target format.
+    pub fn of(format: TextureFormat) -> Self {
+        if format.is_srgb() {
+            Self::Linear
+        } else {
+            Self::Gamma
+        }
+    }
+
+    /// Picks a format to draw in this color space out of the ones a surface supports, as
+    /// listed by [SurfaceCapabilities::formats]. If none of them fit, the first is picked
+    /// anyway, which still comes out right, only blended in the other color space.
+    pub fn surface_format(self, formats: &[TextureFormat]) -> Option<TextureFormat> {
+        let mut fitting = formats.iter().filter(|x| Self::of(**x) == self);
+        fitting.next().or(formats.first()).copied()
+    }
+
+    // The entry point fragment shaders writing colors have for targets in this color space.
+    pub(crate) fn fs_main(self) -> &'static str {
+        match self {
+            Self::Gamma => "fs_main",
+            Self::Linear => "fs_main_srgb",
+        }
+    }
+}
+
+#[derive(ShaderType, Debug, Clone, Copy, PartialEq)]
+pub(crate) struct Uniform {
+    pub multiply_color: Vec3,
+    pub screen_color: Vec3,
+    pub opacity: f32,
+    // Only read with soft masks on.
+    pub mask_layer: u32,
+    pub mask_inverted: u32,
+}
+
+/// A tint and fade put on top of an art mesh's own colors and opacity when drawing, for
+/// effects like blush toggles or damage flashes that shouldn't go through parameters.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct MeshOverride {
+    /// Blended on top of the mesh's colors, so the multiply colors are multiplied together
+    /// and the screen colors are screened together.
+    pub color: BlendColor,
+    /// Multiplied with the mesh's opacity.
+    pub opacity: f32,
+}
+
+impl MeshOverride {
+    /// Leaves the mesh as it is.
+    pub const NONE: Self = Self {
+        color: BlendColor {
+            multiply_color: Vec3::ONE,
+            screen_color: Vec3::ZERO,
+        },
+        opacity: 1.0,
+    };
+}
+
+impl Default for MeshOverride {
+    fn default() -> Self {
+        Self::NONE
+    }
+}
+
+/// Draws the triangles of an art mesh back to front along an axis, so a translucent mesh
+/// that overlaps itself blends the same way no matter how its triangles were exported. See
+/// [Renderer::set_triangle_sort].
+///
+/// Sorting happens on the CPU and re-uploads the mesh's indices, so it's best kept to the
+/// few meshes that need it, or to captures where quality matters more than speed.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct TriangleSort {
+    /// Triangles further along this, in model coordinates, are drawn later, on top.
+    pub axis: Vec2,
+    /// How far any vertex has to move before the triangles are sorted again. Zero sorts
+    /// every frame the mesh moves at all.
+    pub threshold: f32,
+}
+
+impl TriangleSort {
+    /// Sorts along `axis` whenever a vertex moves by a thousandth of a unit.
+    pub fn along(axis: Vec2) -> Self {
+        Self {
+            axis,
+            threshold: 0.001,
+        }
+    }
+}
+
+/// Whether an art mesh is forced to be drawn or not, see [RenderOverrides].
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Visibility {
+    /// Drawn at full opacity, even when keyforms or parts would have hidden it.
+    Shown,
+    Hidden,
+}
+
+/// Art meshes and parts forced to be shown or hidden by ID, for things like switching
+/// costumes or toggling accessories without going through parameters. See
+/// [Renderer::set_render_overrides].
+///
+/// Art meshes go with what's set for them, or otherwise for the closest part they're in.
+/// Either way, [MeshOverride::opacity] still applies on top.
+#[derive(Debug, Clone, Default)]
+pub struct RenderOverrides {
+    art_meshes: HashMap<String, Visibility>,
+    parts: HashMap<String, Visibility>,
+}
+
+impl RenderOverrides {
+    /// Forces an art mesh to be shown or hidden, or leaves it to its part and keyforms again
+    /// with `None`.
+    pub fn set_art_mesh(&mut self, id: &str, visibility: Option<Visibility>) {
+        match visibility {
+            Some(x) => self.art_meshes.insert(id.to_owned(), x),
+            None => self.art_meshes.remove(id),
+        };
+    }
+
+    /// Forces every art mesh in a part, including in the parts inside it, to be shown or
+    /// hidden, or stops forcing them with `None`.
+    pub fn set_part(&mut self, id: &str, visibility: Option<Visibility>) {
+        match visibility {
+            Some(x) => self.parts.insert(id.to_owned(), x),
+            None => self.parts.remove(id),
+        };
+    }
+
+    pub fn clear(&mut self) {
+        self.art_meshes.clear();
+        self.parts.clear();
+    }
+
+    /// What's forced for every art mesh of the puppet. IDs the puppet doesn't have are
+    /// skipped.
+    pub fn resolve(&self, puppet: &PuppetRef) -> Vec<Option<Visibility>> {
+        let mut parts = vec![None; puppet.part_count as usize];
+        for (id, visibility) in &self.parts {
+            if let Some(index) = puppet.part_index(id) {
+                parts[index] = Some(*visibility);
+            }
+        }
+
+        let part_parents = puppet.part_parents();
+        let mut resolved: Vec<Option<Visibility>> = puppet
+            .art_mesh_parts()
+            .into_iter()
+            .map(|mut part| {
+                while let Some(index) = part {
+                    if parts[index].is_some() {
+                        return parts[index];
+                    }
+                    part = part_parents[index];
+                }
+                None
+            })
+            .collect();
+        for (id, visibility) in &self.art_meshes {
+            if let Some(index) = puppet.art_mesh_index(id) {
+                resolved[index] = Some(*visibility);
+            }
+        }
+        resolved
+    }
+}
+
+// What's put on top of the colors and opacities of every art mesh when drawing. Puppets
+// and crowds share this, so the same frame draws the same way in either.
+#[derive(Debug, Clone)]
+pub(crate) struct MeshOverrides {
+    pub meshes: Vec<MeshOverride>,
+    pub visibilities: Vec<Option<Visibility>>,
+}
+
+impl MeshOverrides {
+    pub fn new(art_mesh_count: usize) -> Self {
+        Self {
+            meshes: vec![MeshOverride::NONE; art_mesh_count],
+            visibilities: vec![None; art_mesh_count],
+        }
+    }
+
+    pub fn set_visibilities(&mut self, visibilities: Vec<Option<Visibility>>) {
+        debug_assert_eq!(visibilities.len(), self.visibilities.len());
+        self.visibilities = visibilities;
+    }
+
+    // Writes the uniform of every art mesh for a frame, with the overrides on top. Masks
+    // only have coverage layers to read from when they're soft.
+    pub fn write_uniforms(
+        &self,
+        staging: &mut DynamicUniformBuffer<Vec<u8>>,
+        frame_data: &PuppetFrameData,
+        mesh_flags: &[ArtMeshFlags],
+        soft_masks: Option<&SoftMaskResources>,
+        uses_placeholder: impl Fn(usize) -> bool,
+    ) {
+        for (i, flags) in mesh_flags.iter().enumerate() {
+            let color = if uses_placeholder(i) {
+                // The placeholder texel is black, so the screen color is all that shows.
+                BlendColor {
+                    multiply_color: Vec3::ONE,
+                    screen_color: placeholder_color(i),
+                }
+            } else {
+                frame_data.art_mesh_colors()[i]
+            };
+            let mesh_override = self.meshes[i];
+            let opacity = match self.visibilities[i] {
+                Some(Visibility::Shown) => 1.0,
+                Some(Visibility::Hidden) => 0.0,
+                None => frame_data.art_mesh_opacities()[i],
+            };
+            let color = color.blend(&mesh_override.color);
+            let mask_layer = soft_masks.and_then(|x| x.layer(i));
+            staging
+                .write(&Uniform {
+                    multiply_color: color.multiply_color,
+                    screen_color: color.screen_color,
+                    opacity: opacity * mesh_override.opacity,
+                    mask_layer: mask_layer.unwrap_or(0),
+                    mask_inverted: flags.inverted() as u32,
+                })
+                .unwrap();
+        }
+    }
+}
+
+/// Draws a single puppet with wgpu.
+///
+/// On native targets the renderer is `Send + Sync` like the wgpu objects it owns, so it can
+/// live on a dedicated render thread while the puppet is updated elsewhere.
+pub struct Renderer {
+    pipelines: Pipelines,
+    puppet: PuppetResources,
+    placeholder_mode: bool,
+
+    background: Background,
+    background_layer: Option<BackgroundLayer>,
+
+    camera: Mat4,
+    fit_mode: FitMode,
+    mask_stencil: Option<Texture>,
+
+    debug_overlay: DebugOverlay,
+    debug: Option<DebugResources>,
+    hooks: Vec<Box<dyn RenderHook>>,
+}
+
+#[cfg(not(target_arch = "wasm32"))]
+const _: fn() = || {
+    fn assert_send_sync<T: Send + Sync>() {}
+
+    assert_send_sync::<Renderer>();
+    assert_send_sync::<Background>();
+};
+
+impl Renderer {
+    /// The cache the renderer's pipelines come from, for sharing them with other renderers
+    /// through [new_renderer_with_cache].
+    pub fn pipeline_cache(&self) -> &PipelineCache {
+        self.pipelines.cache()
+    }
+
+    pub fn background(&self) -> &Background {
+        &self.background
+    }
+
+    pub fn set_background(&mut self, device: &Device, queue: &Queue, background: Background) {
+        self.background_layer = self.pipelines.background_layer(device, queue, &background);
+        self.background = background;
+    }
+
+    pub fn camera(&self) -> Mat4 {
+        self.camera
+    }
+
+    /// Transforms the puppet in clip space, on top of the usual fit to the render target.
+    /// This is the identity by default, and is handy for panning and zooming.
+    pub fn set_camera(&mut self, camera: Mat4) {
+        self.camera = camera;
+    }
+
+    pub fn fit_mode(&self) -> FitMode {
+        self.fit_mode
+    }
+
+    /// Sets how the puppet's canvas is fitted into the render target, which is
+    /// [FitMode::Contain] by default. Nothing outside of the canvas is ever drawn.
+    pub fn set_fit_mode(&mut self, fit_mode: FitMode) {
+        self.fit_mode = fit_mode;
+    }
+
+    /// Whether meshes are drawn as flat colors instead of textured.
+    pub fn placeholder_mode(&self) -> bool {
+        self.placeholder_mode
+    }
+
+    /// Draws every mesh as a flat color picked from its index, ignoring textures. This is
+    /// handy for inspecting a model's structure, or before its textures are available.
+    /// Meshes whose texture is missing are always drawn this way.
+    pub fn set_placeholder_mode(&mut self, placeholder_mode: bool) {
+        self.placeholder_mode = placeholder_mode;
+    }
+
+    /// Uploads (or replaces) the texture with the given index, from any [TextureSource].
+    /// Renderers can be created without any textures and have them filled in as they
+    /// finish downloading, with meshes drawn as placeholders until then. Textures can also
+    /// be shown as they stream in with a [TextureStream](crate::texture::TextureStream).
+    pub fn set_texture(
+        &mut self,
+        device: &Device,
+        queue: &Queue,
+        index: usize,
+        texture: &impl TextureSource,
+    ) {
+        self.puppet
+            .set_texture(&self.pipelines, device, queue, index, texture);
+    }
+
+    /// Drops the texture with the given index, drawing its meshes as placeholders again.
+    pub fn remove_texture(&mut self, index: usize) {
+        if let Some(texture) = self.puppet.bound_textures.get_mut(index) {
+            *texture = None;
+        }
+    }
+
+    /// Whether the texture with the given index has been set, or meshes using it are
+    /// drawn as placeholders.
+    pub fn has_texture(&self, index: usize) -> bool {
+        matches!(self.puppet.bound_textures.get(index), Some(Some(_)))
+    }
+
+    /// The override on the art mesh with the given index, see [Renderer::set_mesh_override].
+    pub fn mesh_override(&self, index: usize) -> MeshOverride {
+        self.puppet.overrides.meshes[index]
+    }
+
+    /// Tints or fades the art mesh with the given index from the next prepare on, on top
+    /// of whatever its keyforms give. Art mesh indexes can be looked up from their IDs with
+    /// [PuppetRef::art_mesh_index].
+    pub fn set_mesh_override(&mut self, index: usize, mesh_override: MeshOverride) {
+        self.puppet.set_mesh_override(index, mesh_override);
+    }
+
+    /// How masks are drawn, if they're soft, see [Renderer::set_soft_masks].
+    pub fn soft_masks(&self) -> Option<SoftMask> {
+        self.puppet.soft_masks()
+    }
+
+    /// Draws masked meshes faded by how much their masks cover from the next prepare on,
+    /// instead of cut off at the hard edges of the stencil, or goes back to the stencil
+    /// with None. Masks are hard by default.
+    ///
+    /// Every distinct set of masks gets a layer of coverage as big as the render target,
+    /// drawn in a pass of its own, so this costs more memory and time the more the puppet
+    /// masks. Inverted masks are softened too.
+    pub fn set_soft_masks(&mut self, device: &Device, soft_masks: Option<SoftMask>) {
+        self.puppet
+            .set_soft_masks(&self.pipelines, device, soft_masks);
+    }
+
+    /// How the triangles of the art mesh with the given index are sorted, see
+    /// [Renderer::set_triangle_sort].
+    pub fn triangle_sort(&self, index: usize) -> Option<TriangleSort> {
+        self.puppet.meshes.sorts[index]
+    }
+
+    /// Sorts the triangles of the art mesh with the given index from the next prepare on,
+    /// or puts them back in their original order with None. Meshes are drawn in their
+    /// original order by default.
+    ///
+    /// Frames deformed on the GPU have no vertexes on the CPU to sort by, so meshes keep
+    /// whatever order they were last sorted in for those.
+    pub fn set_triangle_sort(&mut self, index: usize, sort: Option<TriangleSort>) {
+        self.puppet.meshes.set_sort(index, sort);
+    }
+
+    /// Forces art meshes and parts of the puppet to be shown or hidden from the next
+    /// prepare on, replacing whatever was set before.
+    pub fn set_render_overrides(&mut self, puppet: &PuppetRef, overrides: &RenderOverrides) {
+        self.puppet.set_visibilities(overrides.resolve(puppet));
+    }
+
+    /// Puts every art mesh back to [MeshOverride::NONE].
+    pub fn clear_mesh_overrides(&mut self) {
+        self.puppet.overrides.meshes.fill(MeshOverride::NONE);
+    }
+
+    /// Like [Renderer::set_texture], but decodes the texture from an encoded image such as
+    /// the PNG bytes of a fetch response.
+    pub fn load_texture(
+        &mut self,
+        device: &Device,
+        queue: &Queue,
+        index: usize,
+        bytes: &[u8],
+    ) -> ImageResult<()> {
+        let texture = image::load_from_memory(bytes)?.into_rgba8();
+        self.set_texture(device, queue, index, &texture);
+        Ok(())
+    }
+
+    /// Deforms the puppet's art meshes with compute shaders from now on, whenever it's
+    /// prepared with frame data that has [PuppetFrameData::set_deferred_deform] on. This
+    /// skips most of the CPU work of updating big models, along with uploading every
+    /// vertex each frame, leaving only a few small buffers to write.
+    ///
+    /// The device must pass [supports_gpu_deform](crate::deform::supports_gpu_deform).
+    /// Frame data without deferred deforming is still drawn as usual.
+    pub fn enable_gpu_deform(&mut self, device: &Device, puppet: &PuppetRef) {
+        self.puppet.enable_gpu_deform(device, puppet);
+    }
+
+    /// Swaps in a new version of the puppet, such as one exported again after being edited,
+    /// along with its textures. Everything about how it's drawn is kept: the camera, fit
+    /// mode, background, debug overlays, hooks, soft masks and GPU deforming. Mesh and render overrides
+    /// and triangle sorts are cleared, as the meshes they were on might not be where they
+    /// were.
+    ///
+    /// The old frame data doesn't fit the new puppet, so it needs replacing with
+    /// [framedata_for_puppet](moc3_rs::puppet::framedata_for_puppet) too. Parameter values
+    /// can be carried over with [ParamData::carry_over](moc3_rs::puppet::ParamData::carry_over).
+    pub fn reload(
+        &mut self,
+        device: &Device,
+        queue: &Queue,
+        puppet: &PuppetRef,
+        textures: &[impl TextureSource],
+    ) {
+        self.puppet
+            .reload(&mut self.pipelines, device, queue, puppet, textures);
+        if self.debug.is_some() {
+            self.debug = Some(DebugResources::new(
+                device,
+                self.pipelines.format,
+                self.pipelines.uniform_layout(),
+                puppet,
+            ));
+        }
+    }
+
+    /// Uploads a frame to draw. Frame data with deferred deforming on needs
+    /// [Renderer::enable_gpu_deform] first, or this panics.
+    pub fn prepare(
+        &mut self,
+        device: &Device,
+        queue: &Queue,
+        render_size: Extent3d,
+        frame_data: &PuppetFrameData,
+    ) {
+        resize_stencil(&mut self.mask_stencil, device, render_size);
+        self.puppet.prepare(
+            device,
+            queue,
+            render_size,
+            self.camera,
+            self.fit_mode,
+            self.placeholder_mode,
+            frame_data,
+        );
+        if let Some(debug) = &mut self.debug {
+            debug.prepare(queue, self.debug_overlay, frame_data);
+        }
+    }
+
+    /// Gets everything ready for the first visible frame ahead of time: the puppet is
+    /// evaluated at its defaults, every buffer is uploaded, and a frame is drawn into a
+    /// throwaway target so drivers finish compiling pipelines they'd otherwise compile
+    /// lazily. Without this, revealing a freshly loaded puppet can hitch noticeably.
+    ///
+    /// The buffers hold the default pose afterwards, until the next [Renderer::prepare].
+    pub fn prewarm(
+        &mut self,
+        device: &Device,
+        queue: &Queue,
+        render_size: Extent3d,
+        puppet: &PuppetRef,
+    ) {
+        let mut frame_data = framedata_for_puppet(puppet);
+        let params = &puppet.param_data().defaults;
+        let opacities = vec![1.0; puppet.part_count as usize];
+        puppet.update(params, &opacities, &mut frame_data);
+
+        self.prepare(device, queue, render_size, &frame_data);
+
+        let target = device.create_texture(&TextureDescriptor {
+            size: render_size,
+            mip_level_count: 1,
+            sample_count: 1,
+            dimension: TextureDimension::D2,
+            format: self.pipelines.format,
+            usage: TextureUsages::RENDER_ATTACHMENT,
+            view_formats: &[],
+            label: None,
+        });
+        let view = target.create_view(&TextureViewDescriptor::default());
+
+        let mut encoder = device.create_command_encoder(&CommandEncoderDescriptor { label: None });
+        self.render(&view, &mut encoder);
+        queue.submit(std::iter::once(encoder.finish()));
+    }
+
+    pub fn debug_overlay(&self) -> DebugOverlay {
+        self.debug_overlay
+    }
+
+    /// Draws the given debug overlays over the puppet from the next prepared frame on.
+    /// The puppet must be the one the renderer was created for.
+    pub fn set_debug_overlay(
+        &mut self,
+        device: &Device,
+        puppet: &PuppetRef,
+        overlay: DebugOverlay,
+    ) {
+        if !overlay.is_empty() && self.debug.is_none() {
+            self.debug = Some(DebugResources::new(
+                device,
+                self.pipelines.format,
+                self.pipelines.uniform_layout(),
+                puppet,
+            ));
+        }
+        self.debug_overlay = overlay;
+    }
+
+    /// Adds a step of drawing to every frame from now on, see [RenderHook].
+    pub fn add_hook(&mut self, hook: impl RenderHook + 'static) {
+        self.hooks.push(Box::new(hook));
+    }
+
+    pub fn clear_hooks(&mut self) {
+        self.hooks.clear();
+    }
+
+    pub fn render(&self, view: &TextureView, encoder: &mut CommandEncoder) {
+        self.puppet.deform(encoder);
+        self.puppet
+            .draw_soft_masks(&self.pipelines, encoder, self.placeholder_mode);
+
+        let mask_stencil = self.mask_stencil.as_ref().unwrap();
+        let mask_view = mask_stencil.create_view(&wgpu::TextureViewDescriptor::default());
+        let targets = FrameTargets {
+            view,
+            stencil_view: &mask_view,
+            size: mask_stencil.size(),
+            format: self.pipelines.format,
+        };
+
+        for hook in &self.hooks {
+            hook.before_pass(encoder, &targets);
+        }
+
+        {
+            let mut rpass = begin_pass(
+                encoder,
+                view,
+                &mask_view,
+                self.pipelines.format,
+                &self.background,
+                self.background_layer.as_ref(),
+            );
+            for hook in &self.hooks {
+                hook.draw_under(&mut rpass);
+            }
+
+            let mut stencil_ref = 0;
+            self.puppet.draw(
+                &self.pipelines,
+                &mut rpass,
+                self.placeholder_mode,
+                &mut stencil_ref,
+            );
+            if let Some(debug) = &self.debug {
+                self.puppet
+                    .draw_debug(debug, self.debug_overlay, &mut rpass);
+            }
+
+            draw_over(&self.hooks, &mut rpass, targets.size);
+        }
+
+        for hook in &self.hooks {
+            hook.after_pass(encoder, &targets);
+        }
+    }
+}
+
+/// Requests a device with the limits the renderer needs, which are low enough for WebGL2 in
+/// browsers. This is the only part of setting up a renderer that has to be awaited, as
+/// [new_renderer] itself never blocks.
+pub async fn request_device(adapter: &Adapter) -> Result<(Device, Queue), RequestDeviceError> {
+    adapter
+        .request_device(
+            &DeviceDescriptor {
+                features: Features::empty(),
+                limits: Limits::downlevel_webgl2_defaults().using_resolution(adapter.limits()),
+                label: None,
+            },
+            None,
+        )
+        .await
+}
+
+pub fn new_renderer(
+    puppet: &PuppetRef,
+    device: &Device,
+    queue: &Queue,
+    format: TextureFormat,
+    textures: &[impl TextureSource],
+) -> Renderer {
+    let cache = PipelineCache::new(device, queue);
+    new_renderer_with_cache(puppet, device, queue, &cache, format, textures)
+}
+
+/// Like [new_renderer], but sharing pipelines with every other renderer and scene using
+/// the same cache.
+pub fn new_renderer_with_cache(
+    puppet: &PuppetRef,
+    device: &Device,
+    queue: &Queue,
+    cache: &PipelineCache,
+    format: TextureFormat,
+    textures: &[impl TextureSource],
+) -> Renderer {
+    let mut pipelines = Pipelines::new(cache, format);
+    let puppet = PuppetResources::new(&mut pipelines, device, queue, puppet, textures);
+
+    Renderer {
+        pipelines,
+        puppet,
+        placeholder_mode: false,
+
+        background: Background::None,
+        background_layer: None,
+
+        camera: Mat4::IDENTITY,
+        fit_mode: FitMode::default(),
+        mask_stencil: None,
+
+        debug_overlay: DebugOverlay::default(),
+        debug: None,
+        hooks: Vec::new(),
+    }
+}
+
+/// The layouts, samplers and render pipelines puppets are drawn with, which can be shared
+/// between any number of renderers and scenes on the same device.
+///
+/// Pipelines are only created once something drawn needs them, and only once for each
+/// target format no matter how many puppets need them. Clones share the same pipelines.
+#[derive(Clone)]
+pub struct PipelineCache(Arc<SharedPipelines>);
+
+struct SharedPipelines {
+    uniform_layout: BindGroupLayout,
+    texture_layout: BindGroupLayout,
+    pipeline_layout: PipelineLayout,
+    texture_sampler: Sampler,
+    placeholder_texture: BindGroup,
+
+    pipelines: Mutex<HashMap<(TextureFormat, PipelineKind, bool), Arc<RenderPipeline>>>,
+    stencil_reset_pipelines: Mutex<HashMap<TextureFormat, Arc<RenderPipeline>>>,
+}
+
+impl PipelineCache {
+    pub fn new(device: &Device, queue: &Queue) -> Self {
+        let texture_sampler = device.create_sampler(&SamplerDescriptor {
+            min_filter: FilterMode::Linear,
+            mag_filter: FilterMode::Linear,
+            mipmap_filter: FilterMode::Linear,
+            ..SamplerDescriptor::default()
+        });
+
+        let texture_layout = device.create_bind_group_layout(&BindGroupLayoutDescriptor {
+            entries: &[
+                BindGroupLayoutEntry {
+                    binding: 0,
+                    visibility: ShaderStages::FRAGMENT,
+                    ty: BindingType::Texture {
+                        multisampled: false,
+                        sample_type: TextureSampleType::Float { filterable: true },
+                        view_dimension: TextureViewDimension::D2,
+                    },
+                    count: None,
+                },
+                BindGroupLayoutEntry {
+                    binding: 1,
+                    visibility: ShaderStages::FRAGMENT,
+                    ty: BindingType::Sampler(SamplerBindingType::Filtering),
+                    count: None,
+                },
+            ],
+            label: None,
+        });
+
+        // A single opaque black texel, which placeholder colors are screened onto.
+        let placeholder_texture = bind_texture(
+            device,
+            &texture_layout,
+            &texture_sampler,
+            &RawRgba {
+                width: 1,
+                height: 1,
+                data: &[0, 0, 0, 255],
+            }
+            .texture_view(device, queue),
+        );
+
+        let uniform_layout = device.create_bind_group_layout(&BindGroupLayoutDescriptor {
+            entries: &[
+                BindGroupLayoutEntry {
+                    binding: 0,
+                    visibility: ShaderStages::VERTEX,
+                    ty: BindingType::Buffer {
+                        ty: BufferBindingType::Uniform,
+                        has_dynamic_offset: false,
+                        min_binding_size: BufferSize::new(std::mem::size_of::<Mat4>() as u64),
+                    },
+                    count: None,
+                },
+                BindGroupLayoutEntry {
+                    binding: 1,
+                    visibility: ShaderStages::FRAGMENT,
+                    ty: BindingType::Buffer {
+                        ty: BufferBindingType::Uniform,
+                        has_dynamic_offset: true,
+                        min_binding_size: Some(Uniform::SHADER_SIZE),
+                    },
+                    count: None,
+                },
+            ],
+            label: None,
+        });
+
+        let pipeline_layout = device.create_pipeline_layout(&PipelineLayoutDescriptor {
+            bind_group_layouts: &[&uniform_layout, &texture_layout],
+            ..PipelineLayoutDescriptor::default()
+        });
+
+        Self(Arc::new(SharedPipelines {
+            uniform_layout,
+            texture_layout,
+            pipeline_layout,
+            texture_sampler,
+            placeholder_texture,
+
+            pipelines: Mutex::default(),
+            stencil_reset_pipelines: Mutex::default(),
+        }))
+    }
+
+    fn pipeline(
+        &self,
+        device: &Device,
+        format: TextureFormat,
+        kind: PipelineKind,
+        double_sided: bool,
+    ) -> Arc<RenderPipeline> {
+        let mut pipelines = self.0.pipelines.lock().unwrap();
+        let pipeline = pipelines
+            .entry((format, kind, double_sided))
+            .or_insert_with(|| {
+                Arc::new(pipeline_for(
+                    device,
+                    None,
+                    &self.0.pipeline_layout,
+                    format,
+                    double_sided,
+                    kind,
+                ))
+            });
+        pipeline.clone()
+    }
+
+    fn stencil_reset_pipeline(
+        &self,
+        device: &Device,
+        format: TextureFormat,
+    ) -> Arc<RenderPipeline> {
+        let mut pipelines = self.0.stencil_reset_pipelines.lock().unwrap();
+        let pipeline = pipelines
+            .entry(format)
+            .or_insert_with(|| Arc::new(stencil_reset_pipeline(device, format)));
+        pipeline.clone()
+    }
+}
+
+// What a renderer or scene draws with, shared between all of a scene's puppets: the cache, and
+// whichever of its pipelines the puppets loaded so far need.
+pub(crate) struct Pipelines {
+    cache: PipelineCache,
+    // by kind, then double-sided
+    loaded: HashMap<(PipelineKind, bool), Arc<RenderPipeline>>,
+    stencil_reset_pipeline: Option<Arc<RenderPipeline>>,
+
+    pub format: TextureFormat,
+}
+
+impl Pipelines {
+    pub fn new(cache: &PipelineCache, format: TextureFormat) -> Self {
+        Self {
+            cache: cache.clone(),
+            loaded: HashMap::new(),
+            stencil_reset_pipeline: None,
+
+            format,
+        }
+    }
+
+    pub fn cache(&self) -> &PipelineCache {
+        &self.cache
+    }
+
+    pub fn uniform_layout(&self) -> &BindGroupLayout {
+        &self.cache.0.uniform_layout
+    }
+
+    pub fn texture_layout(&self) -> &BindGroupLayout {
+        &self.cache.0.texture_layout
+    }
+
+    pub fn bind_texture(&self, device: &Device, texture_view: &TextureView) -> BindGroup {
+        bind_texture(
+            device,
+            &self.cache.0.texture_layout,
+            &self.cache.0.texture_sampler,
+            texture_view,
+        )
+    }
+
+    // What meshes without a texture are drawn with.
+    pub fn placeholder_texture(&self) -> &BindGroup {
+        &self.cache.0.placeholder_texture
+    }
+
+    // Fetches every pipeline the puppet's meshes are drawn with that isn't loaded yet.
+    pub fn load(&mut self, device: &Device, puppet: &PuppetRef) {
+        let masks = puppet.art_mesh_mask_indices.iter();
+        for (flags, masks) in puppet.art_mesh_flags.iter().zip(masks) {
+            self.load_pipeline(device, render_kind(*flags, masks), flags.double_sided());
+
+            for mask_index in masks.iter().filter(|x| **x != u32::MAX) {
+                let mask_flags = puppet.art_mesh_flags[*mask_index as usize];
+                self.load_pipeline(device, PipelineKind::Mask, mask_flags.double_sided());
+            }
+            if !masks.is_empty() && self.stencil_reset_pipeline.is_none() {
+                self.stencil_reset_pipeline =
+                    Some(self.cache.stencil_reset_pipeline(device, self.format));
+            }
+        }
+    }
+
+    fn load_pipeline(&mut self, device: &Device, kind: PipelineKind, double_sided: bool) {
+        if !self.loaded.contains_key(&(kind, double_sided)) {
+            let pipeline = self.cache.pipeline(device, self.format, kind, double_sided);
+            self.loaded.insert((kind, double_sided), pipeline);
+        }
+    }
+
+    // One of the pipelines loaded for the puppets being drawn.
+    fn get(&self, kind: PipelineKind, double_sided: bool) -> &RenderPipeline {
+        &self.loaded[&(kind, double_sided)]
+    }
+
+    pub fn background_layer(
+        &self,
+        device: &Device,
+        queue: &Queue,
+        background: &Background,
+    ) -> Option<BackgroundLayer> {
+        background_layer(
+            background,
+            device,
+            queue,
+            self.format,
+            &self.cache.0.texture_layout,
+            &self.cache.0.texture_sampler,
+        )
+    }
+}
+
+// What a mesh is drawn with: outside of its masks if they're inverted, and inside of them
+// otherwise. Meshes without masks are drawn everywhere either way.
+fn render_kind(flags: ArtMeshFlags, masks: &[u32]) -> PipelineKind {
+    if flags.inverted() && !masks.is_empty() {
+        PipelineKind::InvertedRender(flags.blend_mode())
+    } else {
+        PipelineKind::Render(flags.blend_mode())
+    }
+}
+
+// Draws a mesh with its masks in the stencil, with the next stencil value for them. The
+// masks and the mesh are each drawn by draw_mesh, once the right pipeline is set.
+#[allow(clippy::too_many_arguments)]
+pub(crate) fn draw_masked<'a>(
+    pipelines: &'a Pipelines,
+    rpass: &mut RenderPass<'a>,
+    mesh_flags: &[ArtMeshFlags],
+    mask_indices: &[Vec<u32>],
+    art_index: usize,
+    [x, y, width, height]: [u32; 4],
+    render_size: Extent3d,
+    cur_stencil_test_ref: &mut u8,
+    draw_mesh: impl Fn(&mut RenderPass<'a>, usize),
+) {
+    let flags = mesh_flags[art_index];
+    let masks = &mask_indices[art_index];
+    if masks.is_empty() {
+        // Because we use greater, no matter what the value of anything in the stencil buffer, this will work.
+        rpass.set_stencil_reference(0);
+    } else {
+        if *cur_stencil_test_ref == u8::MAX {
+            // Out of stencil values, so start over from a clean stencil buffer.
+            // Other puppets may have left values outside of this one's canvas.
+            rpass.set_scissor_rect(0, 0, render_size.width, render_size.height);
+            rpass.set_pipeline(pipelines.stencil_reset_pipeline.as_ref().unwrap());
+            rpass.set_stencil_reference(0);
+            rpass.draw(0..3, 0..1);
+            rpass.set_scissor_rect(x, y, width, height);
+            *cur_stencil_test_ref = 0;
+        }
+
+        *cur_stencil_test_ref += 1;
+        rpass.set_stencil_reference(*cur_stencil_test_ref as u32);
+
+        for mask_index in masks.iter().copied() {
+            if mask_index == 4294967295 {
+                continue;
+            }
+            let mask_index = mask_index as usize;
+            let mask_flags = mesh_flags[mask_index];
+
+            rpass.set_pipeline(pipelines.get(PipelineKind::Mask, mask_flags.double_sided()));
+            draw_mesh(rpass, mask_index);
+        }
+    }
+
+    // Every mask of a mesh writes the same reference, so the mesh is drawn inside of all of
+    // them together, or outside of all of them when inverted.
+    let kind = render_kind(flags, masks);
+    rpass.set_pipeline(pipelines.get(kind, flags.double_sided()));
+    draw_mesh(rpass, art_index);
+}
+
+// The GPU side of a single puppet: its meshes, textures and uniforms.
+pub(crate) struct PuppetResources {
+    mesh_flags: Vec<ArtMeshFlags>,
+    texture_nums: Vec<u32>,
+    render_orders: Vec<u32>,
+    mask_indices: Vec<Vec<u32>>,
+
+    canvas: Canvas,
+    render_size: Extent3d,
+    // Where the canvas lands in the target, as x, y, width and height. This is None when
+    // it's entirely out of view.
+    scissor: Option<[u32; 4]>,
+
+    // Textures can arrive out of order, so any of them might be missing.
+    bound_textures: Vec<Option<BindGroup>>,
+    overrides: MeshOverrides,
+    uniform_bind_group: BindGroup,
+    uniform_alignment_needed: u64,
+
+    camera_buffer: Buffer,
+    uniform_buffer: Buffer,
+    // Every mesh's uniform, laid out like uniform_buffer so a frame's worth is uploaded at once.
+    uniform_staging: Vec<u8>,
+
+    meshes: MeshBuffers,
+    gpu_deform: Option<GpuDeform>,
+    // Whether the last frame prepared is waiting to be deformed on the GPU.
+    deform_pending: bool,
+    soft_masks: Option<SoftMaskResources>,
+}
+
+impl PuppetResources {
+    pub fn new(
+        pipelines: &mut Pipelines,
+        device: &Device,
+        queue: &Queue,
+        puppet: &PuppetRef,
+        textures: &[impl TextureSource],
+    ) -> Self {
+        pipelines.load(device, puppet);
+
+        let mut bound_textures = Vec::new();
+        for tex in textures {
+            bound_textures.push(Some(
+                pipelines.bind_texture(device, &tex.texture_view(device, queue)),
+            ));
+        }
+
+        let camera_buffer = device.create_buffer(&BufferDescriptor {
+            size: std::mem::size_of::<Mat4>() as u64,
+            usage: BufferUsages::UNIFORM | BufferUsages::COPY_DST,
+            mapped_at_creation: false,
+            label: None,
+        });
+
+        let min_uniform_alignment = device.limits().min_uniform_buffer_offset_alignment;
+        let uniform_alignment_needed = Uniform::SHADER_SIZE.get().max(min_uniform_alignment as u64);
+
+        let uniform_buffer = device.create_buffer(&BufferDescriptor {
+            size: uniform_alignment_needed * puppet.art_mesh_count as u64,
+            usage: BufferUsages::UNIFORM | BufferUsages::COPY_DST,
+            mapped_at_creation: false,
+            label: None,
+        });
+
+        let uniform_bind_group = device.create_bind_group(&BindGroupDescriptor {
+            layout: pipelines.uniform_layout(),
+            entries: &[
+                BindGroupEntry {
+                    binding: 0,
+                    resource: camera_buffer.as_entire_binding(),
+                },
+                BindGroupEntry {
+                    binding: 1,
+                    resource: BindingResource::Buffer(BufferBinding {
+                        buffer: &uniform_buffer,
+                        offset: 0,
+                        size: Some(Uniform::SHADER_SIZE),
+                    }),
+                },
+            ],
+            label: None,
+        });
+
+        Self {
+            mesh_flags: puppet.art_mesh_flags.clone(),
+            texture_nums: puppet.art_mesh_textures.clone(),
+            // Nothing is drawn until the first prepare.
+            render_orders: Vec::with_capacity(puppet.art_mesh_count as usize),
+            mask_indices: puppet.art_mesh_mask_indices.clone(),
+
+            canvas: *puppet.canvas(),
+            render_size: Extent3d::default(),
+            scissor: None,
+
+            bound_textures,
+            overrides: MeshOverrides::new(puppet.art_mesh_count as usize),
+            uniform_bind_group,
+            uniform_alignment_needed,
+
+            camera_buffer,
+            uniform_buffer,
+            uniform_staging: Vec::with_capacity(
+                (uniform_alignment_needed * puppet.art_mesh_count as u64) as usize,
+            ),
+
+            meshes: MeshBuffers::new(device, puppet),
+            gpu_deform: None,
+            deform_pending: false,
+            soft_masks: None,
+        }
+    }
+
+    pub fn enable_gpu_deform(&mut self, device: &Device, puppet: &PuppetRef) {
+        if self.gpu_deform.is_none() {
+            self.meshes.enable_storage(device);
+            self.gpu_deform = Some(GpuDeform::new(device, puppet, &self.meshes.vertex_buffer));
+        }
+    }
+
+    // Swaps in another puppet, keeping GPU deforming and soft masks on if they were.
+    pub fn reload(
+        &mut self,
+        pipelines: &mut Pipelines,
+        device: &Device,
+        queue: &Queue,
+        puppet: &PuppetRef,
+        textures: &[impl TextureSource],
+    ) {
+        let gpu_deform = self.gpu_deform.is_some();
+        let soft_masks = self.soft_masks();
+        *self = Self::new(pipelines, device, queue, puppet, textures);
+        if gpu_deform {
+            self.enable_gpu_deform(device, puppet);
+        }
+        self.set_soft_masks(pipelines, device, soft_masks);
+    }
+
+    pub fn soft_masks(&self) -> Option<SoftMask> {
+        self.soft_masks.as_ref().map(|x| x.settings())
+    }
+
+    pub fn set_soft_masks(
+        &mut self,
+        pipelines: &Pipelines,
+        device: &Device,
+        soft_masks: Option<SoftMask>,
+    ) {
+        match (soft_masks, &mut self.soft_masks) {
+            (None, _) => self.soft_masks = None,
+            (Some(settings), Some(resources)) => resources.set_settings(settings),
+            (Some(settings), None) => {
+                self.soft_masks = Some(SoftMaskResources::new(
+                    device,
+                    pipelines,
+                    &self.mask_indices,
+                    settings,
+                ));
+            }
+        }
+    }
+
+    pub fn set_mesh_override(&mut self, index: usize, mesh_override: MeshOverride) {
+        self.overrides.meshes[index] = mesh_override;
+    }
+
+    pub fn set_triangle_sort(&mut self, index: usize, sort: Option<TriangleSort>) {
+        self.meshes.set_sort(index, sort);
+    }
+
+    pub fn set_visibilities(&mut self, visibilities: Vec<Option<Visibility>>) {
+        self.overrides.set_visibilities(visibilities);
+    }
+
+    pub fn set_texture(
+        &mut self,
+        pipelines: &Pipelines,
+        device: &Device,
+        queue: &Queue,
+        index: usize,
+        texture: &impl TextureSource,
+    ) {
+        if self.bound_textures.len() <= index {
+            self.bound_textures.resize_with(index + 1, || None);
+        }
+
+        self.bound_textures[index] =
+            Some(pipelines.bind_texture(device, &texture.texture_view(device, queue)));
+    }
+
+    fn uses_placeholder(&self, placeholder_mode: bool, art_index: usize) -> bool {
+        placeholder_mode
+            || self
+                .bound_textures
+                .get(self.texture_nums[art_index] as usize)
+                .and_then(Option::as_ref)
+                .is_none()
+    }
+
+    fn texture_bind_group<'a>(
+        &'a self,
+        pipelines: &'a Pipelines,
+        placeholder_mode: bool,
+        art_index: usize,
+    ) -> &'a BindGroup {
+        if self.uses_placeholder(placeholder_mode, art_index) {
+            pipelines.placeholder_texture()
+        } else {
+            self.bound_textures[self.texture_nums[art_index] as usize]
+                .as_ref()
+                .unwrap()
+        }
+    }
+
+    #[allow(clippy::too_many_arguments)]
+    pub fn prepare(
+        &mut self,
+        device: &Device,
+        queue: &Queue,
+        render_size: Extent3d,
+        camera: Mat4,
+        fit_mode: FitMode,
+        placeholder_mode: bool,
+        frame_data: &PuppetFrameData,
+    ) {
+        let transform = camera * fit_mode.matrix(&self.canvas, render_size);
+        self.render_size = render_size;
+        if let Some(soft_masks) = &mut self.soft_masks {
+            soft_masks.prepare(device, queue, render_size);
+        }
+        self.scissor = scissor_rect(transform, &self.canvas, render_size);
+
+        // Puppet subsets draw fewer meshes than they have buffers for.
+        self.render_orders.clear();
+        self.render_orders
+            .extend_from_slice(frame_data.render_order());
+        self.deform_pending = false;
+        match (frame_data.deferred_deform(), &mut self.gpu_deform) {
+            (Some(deferred), Some(gpu_deform)) => {
+                gpu_deform.prepare(
+                    device,
+                    queue,
+                    deferred,
+                    frame_data.glue_normalization(),
+                    &self.meshes.vertex_buffer,
+                );
+                self.deform_pending = true;
+            }
+            (Some(_), None) => {
+                panic!("deferred frame data needs GPU deforming enabled")
+            }
+            (None, _) => self.meshes.write(queue, frame_data.art_mesh_positions()),
+        }
+        self.meshes.write_indices(queue);
+
+        queue.write_buffer(&self.camera_buffer, 0, bytemuck::cast_slice(&[transform]));
+
+        let mut staging = std::mem::take(&mut self.uniform_staging);
+        staging.clear();
+        let mut staging =
+            DynamicUniformBuffer::new_with_alignment(staging, self.uniform_alignment_needed);
+        self.overrides.write_uniforms(
+            &mut staging,
+            frame_data,
+            &self.mesh_flags,
+            self.soft_masks.as_ref(),
+            |i| self.uses_placeholder(placeholder_mode, i),
+        );
+        self.uniform_staging = staging.into_inner();
+
+        // wgpu already copies every write_buffer of a submission out of one staging area,
+        // so a single write per buffer is all it takes to keep this cheap.
+        if !self.uniform_staging.is_empty() {
+            queue.write_buffer(&self.uniform_buffer, 0, &self.uniform_staging);
+        }
+    }
+
+    // Runs the compute shaders for a frame prepared with deferred deforming, which has to
+    // happen outside of any render pass.
+    pub fn deform(&self, encoder: &mut CommandEncoder) {
+        if let (true, Some(gpu_deform)) = (self.deform_pending, &self.gpu_deform) {
+            gpu_deform.dispatch(encoder);
+        }
+    }
+
+    // Draws the coverage of every set of masks into its layer, for soft masks. These are
+    // passes of their own, so this has to happen before the puppets' pass.
+    pub fn draw_soft_masks(
+        &self,
+        pipelines: &Pipelines,
+        encoder: &mut CommandEncoder,
+        placeholder_mode: bool,
+    ) {
+        let Some(soft_masks) = &self.soft_masks else {
+            return;
+        };
+        if self.scissor.is_none() {
+            return;
+        }
+
+        for (layer, masks) in soft_masks.layers().iter().enumerate() {
+            let Some(mut rpass) = soft_masks.begin_layer(encoder, layer) else {
+                return;
+            };
+            self.meshes.bind(&mut rpass);
+            for mask_index in masks.iter().map(|x| *x as usize) {
+                let double_sided = self.mesh_flags[mask_index].double_sided();
+                rpass.set_pipeline(soft_masks.coverage_pipeline(double_sided));
+                rpass.set_bind_group(
+                    0,
+                    &self.uniform_bind_group,
+                    &[self.uniform_alignment_needed as u32 * mask_index as u32],
+                );
+                rpass.set_bind_group(
+                    1,
+                    self.texture_bind_group(pipelines, placeholder_mode, mask_index),
+                    &[],
+                );
+                self.meshes.draw(&mut rpass, mask_index);
+            }
+        }
+    }
+
+    // Each masked mesh gets the next stencil value, which carries over between puppets
+    // drawn in the same pass.
+    pub fn draw<'a>(
+        &'a self,
+        pipelines: &'a Pipelines,
+        rpass: &mut RenderPass<'a>,
+        placeholder_mode: bool,
+        cur_stencil_test_ref: &mut u8,
+    ) {
+        let Some([x, y, width, height]) = self.scissor else {
+            return;
+        };
+        rpass.set_scissor_rect(x, y, width, height);
+        if !self.render_orders.is_empty() {
+            self.meshes.bind(rpass);
+        }
+
+        let draw_mesh = |rpass: &mut RenderPass<'a>, art_index: usize| {
+            rpass.set_bind_group(
+                0,
+                &self.uniform_bind_group,
+                &[self.uniform_alignment_needed as u32 * art_index as u32],
+            );
+            rpass.set_bind_group(
+                1,
+                self.texture_bind_group(pipelines, placeholder_mode, art_index),
+                &[],
+            );
+            self.meshes.draw(rpass, art_index);
+        };
+
+        for art_index in self.render_orders.iter().copied() {
+            let art_index = art_index as usize;
+            let flags = self.mesh_flags[art_index];
 
-pub struct Renderer {
-    mesh_flags: Vec<ArtMeshFlags>,
-    texture_nums: Vec<u32>,
-    render_orders: Vec<u32>,
-    mask_indices: Vec<Vec<u32>>,
+            let soft_masked = match &self.soft_masks {
+                Some(soft_masks) if soft_masks.layer(art_index).is_some() => {
+                    soft_masks.begin_masked(rpass, flags.double_sided(), flags.blend_mode())
+                }
+                _ => false,
+            };
+            if soft_masked {
+                draw_mesh(rpass, art_index);
+                continue;
+            }
 
-    // blend mode first, then double-sided
-    pipeline: [[RenderPipeline; 3]; 2],
-    // just double-sided here
-    mask_pipeline: [RenderPipeline; 2],
+            draw_masked(
+                pipelines,
+                rpass,
+                &self.mesh_flags,
+                &self.mask_indices,
+                art_index,
+                [x, y, width, height],
+                self.render_size,
+                cur_stencil_test_ref,
+                draw_mesh,
+            );
+        }
+    }
 
-    bound_textures: Vec<BindGroup>,
-    uniform_bind_group: BindGroup,
-    uniform_alignment_needed: u64,
+    pub fn draw_debug<'a>(
+        &'a self,
+        debug: &'a DebugResources,
+        overlay: DebugOverlay,
+        rpass: &mut RenderPass<'a>,
+    ) {
+        if overlay.is_empty() || self.scissor.is_none() {
+            return;
+        }
+        // Deformer grids can reach well past the canvas.
+        rpass.set_scissor_rect(0, 0, self.render_size.width, self.render_size.height);
+        rpass.set_bind_group(0, &self.uniform_bind_group, &[0]);
 
-    camera_buffer: Buffer,
-    uniform_buffer: Buffer,
+        let art_meshes = self.render_orders.iter().map(|x| *x as usize);
+        if overlay.masks {
+            let mut masks: Vec<usize> = art_meshes
+                .clone()
+                .flat_map(|x| &self.mask_indices[x])
+                .filter(|x| **x != u32::MAX)
+                .map(|x| *x as usize)
+                .collect();
+            masks.sort_unstable();
+            masks.dedup();
 
-    uv_buffers: Vec<Buffer>,
-    index_buffers: Vec<Buffer>,
-    vertex_buffers: Vec<Buffer>,
+            self.meshes.bind(rpass);
+            debug.begin_masks(rpass);
+            for mask_index in masks {
+                self.meshes.draw(rpass, mask_index);
+            }
+        }
+        if overlay.wireframes {
+            debug.draw_wireframes(rpass, &self.meshes.vertex_buffer, art_meshes);
+        }
+        debug.draw_lines(rpass, overlay);
+    }
+}
 
-    mask_stencil: Option<Texture>,
+// Every mesh's vertexes, UVs and indices, each merged into a single buffer so they only
+// need binding once per puppet. Indices are offset to point into the merged vertexes up
+// front, instead of drawing with a base vertex, which WebGL2 doesn't support. That makes
+// them 32-bit for puppets with more vertexes than 16-bit indices can reach.
+pub(crate) struct MeshBuffers {
+    vertex_buffer: Buffer,
+    // How much of the vertex buffer a single copy of the puppet takes up, which is all of it
+    // unless it holds a crowd.
+    instance_size: u64,
+    uv_buffer: Buffer,
+    index_buffer: Buffer,
+    index_format: IndexFormat,
+    index_ranges: Vec<Range<u32>>,
+    // Every mesh's vertexes back to back, reused so each frame is a single upload.
+    staging: Vec<Vec2>,
+
+    // The indices in their original order, and as they're currently uploaded.
+    indices: Vec<u32>,
+    sorted_indices: Vec<u32>,
+    sorts: Vec<Option<TriangleSort>>,
+    // Where each sorted mesh's vertexes were the last time it was sorted, empty to sort it
+    // again on the next write.
+    sorted_positions: Vec<Vec<Vec2>>,
+    indices_dirty: bool,
 }
 
-impl Renderer {
-    pub fn prepare(
-        &mut self,
-        device: &Device,
-        queue: &Queue,
-        render_size: Extent3d,
-        frame_data: &PuppetFrameData,
-    ) {
-        if let Some(texture) = &mut self.mask_stencil {
-            if texture.size() != render_size {
-                self.mask_stencil = None;
-            }
+impl MeshBuffers {
+    pub fn new(device: &Device, puppet: &PuppetRef) -> Self {
+        let vertex_count: usize = puppet.art_mesh_vertexes.iter().map(|x| *x as usize).sum();
+
+        let mut indices = Vec::new();
+        let mut index_ranges = Vec::with_capacity(puppet.art_mesh_count as usize);
+        let mut first_vertex = 0;
+        for (mesh_indices, len) in puppet
+            .art_mesh_indices
+            .iter()
+            .zip(&puppet.art_mesh_vertexes)
+        {
+            let start = indices.len() as u32;
+            indices.extend(mesh_indices.iter().map(|x| first_vertex + *x as u32));
+            index_ranges.push(start..indices.len() as u32);
+            first_vertex += len;
         }
 
-        self.mask_stencil.get_or_insert_with(|| {
-            device.create_texture(&wgpu::TextureDescriptor {
-                size: render_size,
-                mip_level_count: 1,
-                sample_count: 1,
-                dimension: wgpu::TextureDimension::D2,
-                format: wgpu::TextureFormat::Depth24PlusStencil8,
-                usage: wgpu::TextureUsages::TEXTURE_BINDING
-                    | wgpu::TextureUsages::RENDER_ATTACHMENT,
-                view_formats: &[],
+        let index_format = if vertex_count <= u16::MAX as usize + 1 {
+            IndexFormat::Uint16
+        } else {
+            IndexFormat::Uint32
+        };
+
+        let uvs: Vec<Vec2> = puppet.art_mesh_uvs.concat();
+        let instance_size = (vertex_count * std::mem::size_of::<Vec2>()) as u64;
+
+        Self {
+            vertex_buffer: device.create_buffer(&BufferDescriptor {
+                size: instance_size,
+                usage: BufferUsages::VERTEX | BufferUsages::COPY_DST,
                 label: None,
-            })
-        });
+                mapped_at_creation: false,
+            }),
+            instance_size,
+            uv_buffer: device.create_buffer_init(&BufferInitDescriptor {
+                contents: cast_slice(&uvs),
+                usage: BufferUsages::VERTEX,
+                label: None,
+            }),
+            index_buffer: device.create_buffer_init(&BufferInitDescriptor {
+                contents: &index_bytes(&indices, index_format),
+                usage: BufferUsages::INDEX | BufferUsages::COPY_DST,
+                label: None,
+            }),
+            index_format,
+            staging: Vec::with_capacity(vertex_count),
 
-        self.render_orders[..].copy_from_slice(&frame_data.art_mesh_render_orders);
-        for (i, data) in frame_data.art_mesh_data.iter().enumerate() {
-            queue.write_buffer(&self.vertex_buffers[i], 0, cast_slice(data.as_slice()));
+            sorted_indices: indices.clone(),
+            indices,
+            sorts: vec![None; index_ranges.len()],
+            sorted_positions: vec![Vec::new(); index_ranges.len()],
+            indices_dirty: false,
+            index_ranges,
         }
+    }
 
-        queue.write_buffer(
-            &self.camera_buffer,
-            0,
-            bytemuck::cast_slice(&[Mat4::IDENTITY]),
-        );
+    fn set_sort(&mut self, index: usize, sort: Option<TriangleSort>) {
+        if self.sorts[index] == sort {
+            return;
+        }
+        self.sorts[index] = sort;
+        self.sorted_positions[index].clear();
+        if sort.is_none() {
+            let range =
+                self.index_ranges[index].start as usize..self.index_ranges[index].end as usize;
+            self.sorted_indices[range.clone()].copy_from_slice(&self.indices[range]);
+            self.indices_dirty = true;
+        }
+    }
 
-        for i in 0..self.texture_nums.len() {
-            let uniform = Uniform {
-                multiply_color: frame_data.art_mesh_colors[i].multiply_color,
-                screen_color: frame_data.art_mesh_colors[i].screen_color,
-                opacity: frame_data.art_mesh_opacities[i],
+    // Sorts the triangles of every sorted mesh that moved far enough since it last was.
+    fn sort_triangles(&mut self, art_mesh_data: &[Vec<Vec2>]) {
+        let mut first_vertex = 0;
+        for (i, positions) in art_mesh_data.iter().enumerate() {
+            let mesh_first_vertex = first_vertex;
+            first_vertex += positions.len() as u32;
+            let Some(sort) = self.sorts[i] else {
+                continue;
             };
 
-            let mut buffer = UniformBuffer::new([0; Uniform::SHADER_SIZE.get() as usize]);
-            buffer.write(&uniform).unwrap();
-            queue.write_buffer(
-                &self.uniform_buffer,
-                self.uniform_alignment_needed * i as u64,
-                buffer.as_ref(),
-            );
+            let last = &mut self.sorted_positions[i];
+            let moved = last.len() != positions.len()
+                || last
+                    .iter()
+                    .zip(positions)
+                    .any(|(a, b)| a.distance(*b) > sort.threshold);
+            if !moved {
+                continue;
+            }
+            last.clone_from(positions);
+
+            let range = self.index_ranges[i].start as usize..self.index_ranges[i].end as usize;
+            let sorted = &mut self.sorted_indices[range.clone()];
+            sorted.copy_from_slice(&self.indices[range]);
+            let Ok(triangles) = bytemuck::try_cast_slice_mut::<u32, [u32; 3]>(sorted) else {
+                continue;
+            };
+            // Sums of the corners sort the same as the centers would.
+            let depth = |triangle: &[u32; 3]| -> f32 {
+                triangle
+                    .iter()
+                    .map(|x| positions[(x - mesh_first_vertex) as usize].dot(sort.axis))
+                    .sum()
+            };
+            // Stable, so triangles at the same depth keep their original order.
+            triangles.sort_by(|a, b| depth(a).total_cmp(&depth(b)));
+            self.indices_dirty = true;
         }
     }
 
-    pub fn render(&mut self, view: &TextureView, encoder: &mut CommandEncoder) {
-        let mask_view = self
-            .mask_stencil
-            .as_ref()
-            .unwrap()
-            .create_view(&wgpu::TextureViewDescriptor::default());
+    fn write_indices(&mut self, queue: &Queue) {
+        if self.indices_dirty {
+            let bytes = index_bytes(&self.sorted_indices, self.index_format);
+            queue.write_buffer(&self.index_buffer, 0, &bytes);
+            self.indices_dirty = false;
+        }
+    }
 
-        let mut rpass = encoder.begin_render_pass(&RenderPassDescriptor {
-            color_attachments: &[Some(RenderPassColorAttachment {
-                view,
-                resolve_target: None,
-                ops: Operations {
-                    load: LoadOp::Clear(Color::TRANSPARENT),
-                    store: true,
-                },
-            })],
-            depth_stencil_attachment: Some(RenderPassDepthStencilAttachment {
-                view: &mask_view,
-                depth_ops: None,
-                stencil_ops: Some(Operations {
-                    load: LoadOp::Clear(0),
-                    store: true,
-                }),
-            }),
+    // Lets compute shaders write the vertexes, for deforming on the GPU.
+    fn enable_storage(&mut self, device: &Device) {
+        self.vertex_buffer = device.create_buffer(&BufferDescriptor {
+            size: self.vertex_buffer.size(),
+            usage: BufferUsages::VERTEX | BufferUsages::COPY_DST | BufferUsages::STORAGE,
             label: None,
+            mapped_at_creation: false,
         });
+    }
 
-        let mut cur_stencil_test_ref: u8 = 0;
+    fn write(&mut self, queue: &Queue, art_mesh_data: &[Vec<Vec2>]) {
+        self.sort_triangles(art_mesh_data);
 
-        for art_index in self.render_orders.iter().copied() {
-            let art_index = art_index as usize;
-            let flags = self.mesh_flags[art_index];
+        self.staging.clear();
+        for data in art_mesh_data {
+            self.staging.extend_from_slice(data);
+        }
 
-            if self.mask_indices[art_index].is_empty() {
-                // Because we use greater, no matter what the value of anything in the stencil buffer, this will work.
-                rpass.set_stencil_reference(0);
-            } else {
-                cur_stencil_test_ref += 1;
-                rpass.set_stencil_reference(cur_stencil_test_ref as u32);
+        if !self.staging.is_empty() {
+            queue.write_buffer(&self.vertex_buffer, 0, cast_slice(&self.staging));
+        }
+    }
 
-                for mask_index in self.mask_indices[art_index].iter().copied() {
-                    if mask_index == 4294967295 {
-                        continue;
-                    }
-                    let mask_index = mask_index as usize;
-                    let mask_flags = self.mesh_flags[mask_index];
-
-                    rpass.set_pipeline(&self.mask_pipeline[mask_flags.double_sided() as usize]);
-
-                    rpass.set_bind_group(
-                        0,
-                        &self.uniform_bind_group,
-                        &[self.uniform_alignment_needed as u32 * mask_index as u32],
-                    );
-                    rpass.set_bind_group(
-                        1,
-                        &self.bound_textures[self.texture_nums[mask_index] as usize],
-                        &[],
-                    );
-                    rpass.set_index_buffer(
-                        self.index_buffers[mask_index].slice(..),
-                        IndexFormat::Uint16,
-                    );
-                    rpass.set_vertex_buffer(0, self.vertex_buffers[mask_index].slice(..));
-                    rpass.set_vertex_buffer(1, self.uv_buffers[mask_index].slice(..));
-
-                    let x = self.index_buffers[mask_index].size() / 2;
-                    rpass.draw_indexed(0..(x as u32), 0, 0..1);
-                }
+    fn bind<'a>(&'a self, rpass: &mut RenderPass<'a>) {
+        self.bind_instance(rpass, 0);
+    }
 
-                if flags.inverted() {
-                    rpass.set_stencil_reference(0);
-                }
+    // Makes room for the vertexes of this many copies of the puppet, for crowds. Whatever
+    // was written before is lost when the buffer grows.
+    pub fn reserve_instances(&mut self, device: &Device, instances: usize) {
+        let size = self.instance_size * instances as u64;
+        if self.vertex_buffer.size() < size {
+            self.vertex_buffer = device.create_buffer(&BufferDescriptor {
+                size,
+                usage: BufferUsages::VERTEX | BufferUsages::COPY_DST,
+                label: None,
+                mapped_at_creation: false,
+            });
+        }
+    }
+
+    // Writes the vertexes of every copy of the puppet in a crowd, which there has to be room
+    // for. Triangles aren't sorted for these, as every copy shares the same indices.
+    pub fn write_instances<'b>(
+        &mut self,
+        queue: &Queue,
+        instances: impl IntoIterator<Item = &'b [Vec<Vec2>]>,
+    ) {
+        let instance_len = self.instance_size as usize / std::mem::size_of::<Vec2>();
+        self.staging.clear();
+        for (instance, art_mesh_data) in instances.into_iter().enumerate() {
+            self.staging.resize(instance * instance_len, Vec2::ZERO);
+            for data in art_mesh_data {
+                self.staging.extend_from_slice(data);
             }
+        }
 
-            rpass.set_pipeline(
-                &self.pipeline[flags.double_sided() as usize][flags.blend_mode() as usize],
-            );
+        if !self.staging.is_empty() {
+            queue.write_buffer(&self.vertex_buffer, 0, cast_slice(&self.staging));
+        }
+    }
 
-            rpass.set_bind_group(
-                0,
-                &self.uniform_bind_group,
-                &[self.uniform_alignment_needed as u32 * art_index as u32],
-            );
-            rpass.set_bind_group(
-                1,
-                &self.bound_textures[self.texture_nums[art_index] as usize],
-                &[],
-            );
-            rpass.set_index_buffer(self.index_buffers[art_index].slice(..), IndexFormat::Uint16);
-            rpass.set_vertex_buffer(0, self.vertex_buffers[art_index].slice(..));
-            rpass.set_vertex_buffer(1, self.uv_buffers[art_index].slice(..));
+    // Binds the buffers for drawing one copy of the puppet, which there must be vertexes for.
+    pub fn bind_instance<'a>(&'a self, rpass: &mut RenderPass<'a>, instance: usize) {
+        let start = self.instance_size * instance as u64;
+        rpass.set_index_buffer(self.index_buffer.slice(..), self.index_format);
+        rpass.set_vertex_buffer(
+            0,
+            self.vertex_buffer.slice(start..start + self.instance_size),
+        );
+        rpass.set_vertex_buffer(1, self.uv_buffer.slice(..));
+    }
 
-            let x = self.index_buffers[art_index].size() / 2;
-            rpass.draw_indexed(0..(x as u32), 0, 0..1);
-        }
+    pub fn draw(&self, rpass: &mut RenderPass, art_index: usize) {
+        rpass.draw_indexed(self.index_ranges[art_index].clone(), 0, 0..1);
     }
 }
 
-pub fn new_renderer(
-    puppet: &Puppet,
-    device: &Device,
-    queue: &Queue,
-    format: TextureFormat,
-    textures: &[RgbaImage],
-) -> Renderer {
-    let texture_sampler = device.create_sampler(&SamplerDescriptor {
-        min_filter: FilterMode::Linear,
-        mag_filter: FilterMode::Linear,
-        mipmap_filter: FilterMode::Linear,
-        ..SamplerDescriptor::default()
-    });
+// Buffer writes have to be a multiple of 4 bytes, which an odd number of 16-bit indices
+// isn't.
+fn index_bytes(indices: &[u32], index_format: IndexFormat) -> Vec<u8> {
+    let mut bytes = match index_format {
+        IndexFormat::Uint16 => {
+            let indices: Vec<u16> = indices.iter().map(|x| *x as u16).collect();
+            cast_slice(&indices).to_vec()
+        }
+        IndexFormat::Uint32 => cast_slice(indices).to_vec(),
+    };
+    bytes.resize(bytes.len().next_multiple_of(4), 0);
+    bytes
+}
 
-    let texture_layout = device.create_bind_group_layout(&BindGroupLayoutDescriptor {
-        entries: &[
-            BindGroupLayoutEntry {
-                binding: 0,
-                visibility: ShaderStages::FRAGMENT,
-                ty: BindingType::Texture {
-                    multisampled: false,
-                    sample_type: TextureSampleType::Float { filterable: true },
-                    view_dimension: TextureViewDimension::D2,
-                },
-                count: None,
-            },
-            BindGroupLayoutEntry {
-                binding: 1,
-                visibility: ShaderStages::FRAGMENT,
-                ty: BindingType::Sampler(SamplerBindingType::Filtering),
-                count: None,
-            },
-        ],
-        label: None,
-    });
+// The pixels covered by the canvas once it's transformed into clip space.
+pub(crate) fn scissor_rect(
+    transform: Mat4,
+    canvas: &Canvas,
+    render_size: Extent3d,
+) -> Option<[u32; 4]> {
+    let size = vec2(render_size.width as f32, render_size.height as f32);
+    let (min, max) = canvas.bounds();
 
-    let mut bound_textures = Vec::new();
-    for tex in textures {
-        let texture = device.create_texture_with_data(
-            queue,
-            &TextureDescriptor {
-                size: Extent3d {
-                    width: tex.width(),
-                    height: tex.height(),
-                    depth_or_array_layers: 1,
-                },
-                mip_level_count: 1,
-                sample_count: 1,
-                dimension: TextureDimension::D2,
-                format: TextureFormat::Rgba8Unorm,
-                usage: TextureUsages::TEXTURE_BINDING,
-                view_formats: &[],
-                label: None,
-            },
-            &tex,
-        );
+    let (mut low, mut high) = (Vec2::INFINITY, Vec2::NEG_INFINITY);
+    for corner in [min, vec2(max.x, min.y), max, vec2(min.x, max.y)] {
+        let clip = transform.project_point3(corner.extend(0.0));
+        let pixel = vec2(clip.x + 1.0, 1.0 - clip.y) / 2.0 * size;
+        low = low.min(pixel);
+        high = high.max(pixel);
+    }
 
-        let texture_view = texture.create_view(&TextureViewDescriptor::default());
+    let low = low.floor().max(Vec2::ZERO);
+    let high = high.ceil().min(size);
+    // Also catches canvases that come out as NaN.
+    if !(low.x < high.x && low.y < high.y) {
+        return None;
+    }
 
-        let bound_texture = device.create_bind_group(&BindGroupDescriptor {
-            layout: &texture_layout,
-            entries: &[
-                BindGroupEntry {
-                    binding: 0,
-                    resource: BindingResource::TextureView(&texture_view),
-                },
-                BindGroupEntry {
-                    binding: 1,
-                    resource: BindingResource::Sampler(&texture_sampler),
-                },
-            ],
-            label: None,
-        });
-        bound_textures.push(bound_texture);
+    Some([
+        low.x as u32,
+        low.y as u32,
+        (high.x - low.x) as u32,
+        (high.y - low.y) as u32,
+    ])
+}
+
+// (Re)creates the stencil texture masks are drawn into whenever the target changes size.
+pub(crate) fn resize_stencil(stencil: &mut Option<Texture>, device: &Device, size: Extent3d) {
+    if stencil.as_ref().is_some_and(|x| x.size() != size) {
+        *stencil = None;
     }
 
-    let uniform_layout = device.create_bind_group_layout(&BindGroupLayoutDescriptor {
-        entries: &[
-            BindGroupLayoutEntry {
-                binding: 0,
-                visibility: ShaderStages::VERTEX,
-                ty: BindingType::Buffer {
-                    ty: BufferBindingType::Uniform,
-                    has_dynamic_offset: false,
-                    min_binding_size: BufferSize::new(std::mem::size_of::<Mat4>() as u64),
-                },
-                count: None,
-            },
-            BindGroupLayoutEntry {
-                binding: 1,
-                visibility: ShaderStages::FRAGMENT,
-                ty: BindingType::Buffer {
-                    ty: BufferBindingType::Uniform,
-                    has_dynamic_offset: true,
-                    min_binding_size: Some(Uniform::SHADER_SIZE),
+    stencil.get_or_insert_with(|| {
+        device.create_texture(&wgpu::TextureDescriptor {
+            size,
+            mip_level_count: 1,
+            sample_count: 1,
+            dimension: wgpu::TextureDimension::D2,
+            format: STENCIL_FORMAT,
+            usage: wgpu::TextureUsages::TEXTURE_BINDING | wgpu::TextureUsages::RENDER_ATTACHMENT,
+            view_formats: &[],
+            label: None,
+        })
+    });
+}
+
+// Starts the render pass puppets are drawn in, clearing to the background and drawing it.
+pub(crate) fn begin_pass<'a>(
+    encoder: &'a mut CommandEncoder,
+    view: &'a TextureView,
+    mask_view: &'a TextureView,
+    format: TextureFormat,
+    background: &Background,
+    background_layer: Option<&'a BackgroundLayer>,
+) -> RenderPass<'a> {
+    let mut rpass = encoder.begin_render_pass(&RenderPassDescriptor {
+        color_attachments: &[Some(RenderPassColorAttachment {
+            view,
+            resolve_target: None,
+            ops: Operations {
+                load: match background {
+                    Background::Keep => LoadOp::Load,
+                    Background::Solid(color) => LoadOp::Clear(clear_color(*color, format)),
+                    _ => LoadOp::Clear(Color::TRANSPARENT),
                 },
-                count: None,
+                store: true,
             },
-        ],
+        })],
+        depth_stencil_attachment: Some(RenderPassDepthStencilAttachment {
+            view: mask_view,
+            depth_ops: None,
+            stencil_ops: Some(Operations {
+                load: LoadOp::Clear(0),
+                store: true,
+            }),
+        }),
         label: None,
     });
 
-    let pipeline_layout = device.create_pipeline_layout(&PipelineLayoutDescriptor {
-        bind_group_layouts: &[&uniform_layout, &texture_layout],
-        ..PipelineLayoutDescriptor::default()
-    });
-
-    let pipeline = [
-        [
-            pipeline_for(
-                device,
-                None,
-                &pipeline_layout,
-                format,
-                false,
-                PipelineKind::Render(BlendMode::Normal),
-            ),
-            pipeline_for(
-                device,
-                None,
-                &pipeline_layout,
-                format,
-                false,
-                PipelineKind::Render(BlendMode::Additive),
-            ),
-            pipeline_for(
-                device,
-                None,
-                &pipeline_layout,
-                format,
-                false,
-                PipelineKind::Render(BlendMode::Multiplicative),
-            ),
-        ],
-        [
-            pipeline_for(
-                device,
-                None,
-                &pipeline_layout,
-                format,
-                true,
-                PipelineKind::Render(BlendMode::Normal),
-            ),
-            pipeline_for(
-                device,
-                None,
-                &pipeline_layout,
-                format,
-                true,
-                PipelineKind::Render(BlendMode::Additive),
-            ),
-            pipeline_for(
-                device,
-                None,
-                &pipeline_layout,
-                format,
-                true,
-                PipelineKind::Render(BlendMode::Multiplicative),
-            ),
-        ],
-    ];
-
-    let mask_pipeline = [
-        pipeline_for(
-            device,
-            None,
-            &pipeline_layout,
-            format,
-            false,
-            PipelineKind::Mask,
-        ),
-        pipeline_for(
-            device,
-            None,
-            &pipeline_layout,
-            format,
-            true,
-            PipelineKind::Mask,
-        ),
-    ];
-
-    let camera_buffer = device.create_buffer(&BufferDescriptor {
-        size: std::mem::size_of::<Mat4>() as u64,
-        usage: BufferUsages::UNIFORM | BufferUsages::COPY_DST,
-        mapped_at_creation: false,
-        label: None,
-    });
+    if let Some(layer) = background_layer {
+        rpass.set_pipeline(&layer.pipeline);
+        rpass.set_bind_group(0, &layer.uniform_bind_group, &[]);
+        rpass.set_bind_group(1, &layer.texture_bind_group, &[]);
+        rpass.draw(0..3, 0..1);
+    }
 
-    let min_uniform_alignment = device.limits().min_uniform_buffer_offset_alignment;
-    let uniform_alignment_needed = Uniform::SHADER_SIZE.get().max(min_uniform_alignment as u64);
+    rpass
+}
 
-    let uniform_buffer = device.create_buffer(&BufferDescriptor {
-        size: uniform_alignment_needed * puppet.art_mesh_count as u64,
-        usage: BufferUsages::UNIFORM | BufferUsages::COPY_DST,
-        mapped_at_creation: false,
-        label: None,
-    });
+// Puppets leave the scissor around their canvas, which hooks shouldn't be stuck with.
+pub(crate) fn draw_over<'a>(
+    hooks: &'a [Box<dyn RenderHook>],
+    rpass: &mut RenderPass<'a>,
+    size: Extent3d,
+) {
+    if !hooks.is_empty() {
+        rpass.set_scissor_rect(0, 0, size.width, size.height);
+    }
+    for hook in hooks {
+        hook.draw_over(rpass);
+    }
+}
 
-    let uniform_bind_group = device.create_bind_group(&BindGroupDescriptor {
-        layout: &uniform_layout,
+fn bind_texture(
+    device: &Device,
+    texture_layout: &BindGroupLayout,
+    texture_sampler: &Sampler,
+    texture_view: &TextureView,
+) -> BindGroup {
+    device.create_bind_group(&BindGroupDescriptor {
+        layout: texture_layout,
         entries: &[
             BindGroupEntry {
                 binding: 0,
-                resource: camera_buffer.as_entire_binding(),
+                resource: BindingResource::TextureView(texture_view),
             },
             BindGroupEntry {
                 binding: 1,
-                resource: BindingResource::Buffer(BufferBinding {
-                    buffer: &uniform_buffer,
-                    offset: 0,
-                    size: Some(Uniform::SHADER_SIZE),
-                }),
+                resource: BindingResource::Sampler(texture_sampler),
             },
         ],
         label: None,
-    });
-
-    // TODO: this is dumb - blot it into a single buffer instead
-    let mut uv_buffers = Vec::with_capacity(puppet.art_mesh_count as usize);
-    for buf in &puppet.art_mesh_uvs {
-        let uv_buffer = device.create_buffer_init(&BufferInitDescriptor {
-            contents: bytemuck::cast_slice(&buf.as_slice()),
-            usage: BufferUsages::VERTEX,
-            label: None,
-        });
-        uv_buffers.push(uv_buffer);
-    }
-    let mut index_buffers = Vec::with_capacity(puppet.art_mesh_count as usize);
-    for buf in &puppet.art_mesh_indices {
-        let index_buffer = device.create_buffer_init(&BufferInitDescriptor {
-            contents: bytemuck::cast_slice(&buf.as_slice()),
-            usage: BufferUsages::INDEX,
-            label: None,
-        });
-        index_buffers.push(index_buffer);
-    }
-
-    let mut vertex_buffers = Vec::with_capacity(puppet.art_mesh_count as usize);
-    for len in &puppet.art_mesh_vertexes {
-        let vertex_buffer = device.create_buffer(&BufferDescriptor {
-            size: ((*len as usize) * std::mem::size_of::<Vec2>()) as u64,
-            usage: BufferUsages::VERTEX | BufferUsages::COPY_DST,
-            label: None,
-            mapped_at_creation: false,
-        });
-        vertex_buffers.push(vertex_buffer);
-    }
-
-    Renderer {
-        mesh_flags: puppet.art_mesh_flags.clone(),
-        texture_nums: puppet.art_mesh_textures.clone(),
-        render_orders: vec![0; puppet.art_mesh_count as usize],
-        mask_indices: puppet.art_mesh_mask_indices.clone(),
-
-        pipeline,
-        mask_pipeline,
-
-        bound_textures,
-        uniform_bind_group,
-        uniform_alignment_needed,
-
-        camera_buffer,
-        uniform_buffer,
+    })
+}
 
-        uv_buffers,
-        index_buffers,
-        vertex_buffers,
+// Spreads hues around the color wheel by the golden angle, so neighbouring meshes
+// always end up with clearly different colors.
+pub(crate) fn placeholder_color(index: usize) -> Vec3 {
+    let hue = (index as f32 * 0.618_034).fract() * 6.0;
+    let x = 1.0 - (hue % 2.0 - 1.0).abs();
+    let (r, g, b) = match hue as u32 {
+        0 => (1.0, x, 0.0),
+        1 => (x, 1.0, 0.0),
+        2 => (0.0, 1.0, x),
+        3 => (0.0, x, 1.0),
+        4 => (x, 0.0, 1.0),
+        _ => (1.0, 0.0, x),
+    };
 
-        mask_stencil: None,
-    }
+    // Keep things a bit pastel so outlines are still easy to make out.
+    Vec3::new(r, g, b) * 0.7 + 0.2
 }
 
-enum PipelineKind {
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub(crate) enum PipelineKind {
     Render(BlendMode),
+    // Drawn only outside of its masks, which is wherever the stencil isn't the reference.
+    InvertedRender(BlendMode),
     Mask,
+    // Masked by coverage instead of the stencil, see SoftMaskResources.
+    SoftMasked(BlendMode),
+    MaskCoverage,
 }
 
-fn pipeline_for(
+pub(crate) fn pipeline_for(
     device: &Device,
     label: Label<'_>,
     layout: &PipelineLayout,
@@ -491,12 +1856,24 @@ fn pipeline_for(
             depth_fail_op: StencilOperation::Keep,
             pass_op: StencilOperation::Keep,
         },
+        PipelineKind::InvertedRender(_) => StencilFaceState {
+            compare: CompareFunction::NotEqual,
+            fail_op: StencilOperation::Keep,
+            depth_fail_op: StencilOperation::Keep,
+            pass_op: StencilOperation::Keep,
+        },
         PipelineKind::Mask => StencilFaceState {
             compare: CompareFunction::Always,
             fail_op: StencilOperation::Replace,
             depth_fail_op: StencilOperation::Replace,
             pass_op: StencilOperation::Replace,
         },
+        PipelineKind::SoftMasked(_) | PipelineKind::MaskCoverage => StencilFaceState {
+            compare: CompareFunction::Always,
+            fail_op: StencilOperation::Keep,
+            depth_fail_op: StencilOperation::Keep,
+            pass_op: StencilOperation::Keep,
+        },
     };
 
     let stencil = StencilState {
@@ -507,7 +1884,9 @@ fn pipeline_for(
     };
 
     let (blend, write_mask) = match kind {
-        PipelineKind::Render(blend_mode) => {
+        PipelineKind::Render(blend_mode)
+        | PipelineKind::InvertedRender(blend_mode)
+        | PipelineKind::SoftMasked(blend_mode) => {
             let blend = match blend_mode {
                 BlendMode::Normal => BlendState::PREMULTIPLIED_ALPHA_BLENDING,
                 BlendMode::Additive => BlendState {
@@ -539,6 +1918,31 @@ fn pipeline_for(
             (Some(blend), ColorWrites::ALL)
         }
         PipelineKind::Mask => (None, ColorWrites::empty()),
+        PipelineKind::MaskCoverage => {
+            let max = BlendComponent {
+                src_factor: BlendFactor::One,
+                dst_factor: BlendFactor::One,
+                operation: BlendOperation::Max,
+            };
+            (
+                Some(BlendState {
+                    color: max,
+                    alpha: max,
+                }),
+                ColorWrites::RED,
+            )
+        }
+    };
+    // Coverage is drawn in passes of its own, without any stencil.
+    let depth_stencil = match kind {
+        PipelineKind::MaskCoverage => None,
+        _ => Some(DepthStencilState {
+            format: TextureFormat::Depth24PlusStencil8,
+            depth_write_enabled: false,
+            depth_compare: CompareFunction::Always,
+            stencil,
+            bias: DepthBiasState::default(),
+        }),
     };
 
     device.create_render_pipeline(&RenderPipelineDescriptor {
@@ -546,10 +1950,17 @@ fn pipeline_for(
         layout: Some(layout),
         fragment: Some(FragmentState {
             module: &device.create_shader_module(match kind {
-                PipelineKind::Render(_) => include_wgsl!("./shader/frag.wgsl"),
+                PipelineKind::Render(_) | PipelineKind::InvertedRender(_) => {
+                    include_wgsl!("./shader/frag.wgsl")
+                }
                 PipelineKind::Mask => include_wgsl!("./shader/mask.frag.wgsl"),
+                PipelineKind::SoftMasked(_) => include_wgsl!("./shader/soft_mask.wgsl"),
+                PipelineKind::MaskCoverage => include_wgsl!("./shader/mask_coverage.wgsl"),
             }),
-            entry_point: "fs_main",
+            entry_point: match kind {
+                PipelineKind::Mask | PipelineKind::MaskCoverage => "fs_main",
+                _ => ColorSpace::of(texture_format).fs_main(),
+            },
             targets: &[Some(ColorTargetState {
                 format: texture_format,
                 blend,
@@ -577,11 +1988,49 @@ fn pipeline_for(
             cull_mode: if double_sided { None } else { Some(Face::Back) },
             ..PrimitiveState::default()
         },
+        depth_stencil,
+        multisample: MultisampleState::default(),
+        multiview: None,
+    })
+}
+
+fn stencil_reset_pipeline(device: &Device, texture_format: TextureFormat) -> RenderPipeline {
+    let module = device.create_shader_module(include_wgsl!("./shader/stencil_reset.wgsl"));
+    let face_state = StencilFaceState {
+        compare: CompareFunction::Always,
+        fail_op: StencilOperation::Replace,
+        depth_fail_op: StencilOperation::Replace,
+        pass_op: StencilOperation::Replace,
+    };
+
+    device.create_render_pipeline(&RenderPipelineDescriptor {
+        label: None,
+        layout: Some(&device.create_pipeline_layout(&PipelineLayoutDescriptor::default())),
+        vertex: VertexState {
+            module: &module,
+            entry_point: "vs_main",
+            buffers: &[],
+        },
+        fragment: Some(FragmentState {
+            module: &module,
+            entry_point: "fs_main",
+            targets: &[Some(ColorTargetState {
+                format: texture_format,
+                blend: None,
+                write_mask: ColorWrites::empty(),
+            })],
+        }),
+        primitive: PrimitiveState::default(),
         depth_stencil: Some(DepthStencilState {
             format: TextureFormat::Depth24PlusStencil8,
             depth_write_enabled: false,
             depth_compare: CompareFunction::Always,
-            stencil,
+            stencil: StencilState {
+                front: face_state,
+                back: face_state,
+                read_mask: 0xff,
+                write_mask: 0xff,
+            },
             bias: DepthBiasState::default(),
         }),
         multisample: MultisampleState::default(),
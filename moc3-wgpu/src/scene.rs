@@ -0,0 +1,369 @@
+use glam::Mat4;
+use wgpu::*;
+
+use moc3_rs::puppet::{PuppetFrameData, PuppetRef};
+
+use crate::{
+    background::{Background, BackgroundLayer},
+    hook::{FrameTargets, RenderHook},
+    renderer::{
+        begin_pass, draw_over, resize_stencil, FitMode, MeshOverride, PipelineCache, Pipelines,
+        PuppetResources, RenderOverrides, TriangleSort,
+    },
+    soft_mask::SoftMask,
+    texture::TextureSource,
+};
+
+/// Identifies a puppet added to a [SceneRenderer].
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, PartialOrd, Ord)]
+pub struct PuppetId(u64);
+
+struct ScenePuppet {
+    id: PuppetId,
+    order: i32,
+    transform: Mat4,
+    resources: PuppetResources,
+}
+
+/// Draws any number of puppets into the same target, in a single render pass.
+///
+/// Pipelines, layouts and samplers are shared between every puppet, so each one only costs
+/// its own buffers and textures. Puppets are drawn whole, one after another, from the
+/// lowest [order](SceneRenderer::set_order) to the highest, with ties drawn in the order
+/// they were added.
+///
+/// Methods taking a [PuppetId] panic if the puppet has been removed.
+pub struct SceneRenderer {
+    pipelines: Pipelines,
+    // Kept sorted by order, then ID.
+    puppets: Vec<ScenePuppet>,
+    next_id: u64,
+    fit_mode: FitMode,
+    placeholder_mode: bool,
+
+    background: Background,
+    background_layer: Option<BackgroundLayer>,
+
+    mask_stencil: Option<Texture>,
+
+    hooks: Vec<Box<dyn RenderHook>>,
+}
+
+#[cfg(not(target_arch = "wasm32"))]
+const _: fn() = || {
+    fn assert_send_sync<T: Send + Sync>() {}
+
+    assert_send_sync::<SceneRenderer>();
+};
+
+impl SceneRenderer {
+    pub fn new(device: &Device, queue: &Queue, format: TextureFormat) -> Self {
+        Self::with_cache(&PipelineCache::new(device, queue), format)
+    }
+
+    /// Like [SceneRenderer::new], but sharing pipelines with every other renderer and scene
+    /// using the same cache.
+    pub fn with_cache(cache: &PipelineCache, format: TextureFormat) -> Self {
+        Self {
+            pipelines: Pipelines::new(cache, format),
+            puppets: Vec::new(),
+            next_id: 0,
+            fit_mode: FitMode::default(),
+            placeholder_mode: false,
+
+            background: Background::None,
+            background_layer: None,
+
+            mask_stencil: None,
+
+            hooks: Vec::new(),
+        }
+    }
+
+    /// The cache the scene's pipelines come from, for sharing them with other renderers.
+    pub fn pipeline_cache(&self) -> &PipelineCache {
+        self.pipelines.cache()
+    }
+
+    /// Adds a puppet to the scene, drawn on top of everything added before it with the
+    /// same order. It stays invisible until it's first prepared.
+    pub fn add_puppet(
+        &mut self,
+        device: &Device,
+        queue: &Queue,
+        puppet: &PuppetRef,
+        textures: &[impl TextureSource],
+    ) -> PuppetId {
+        let id = PuppetId(self.next_id);
+        self.next_id += 1;
+
+        let resources = PuppetResources::new(&mut self.pipelines, device, queue, puppet, textures);
+
+        self.puppets.push(ScenePuppet {
+            id,
+            order: 0,
+            transform: Mat4::IDENTITY,
+            resources,
+        });
+        self.sort();
+
+        id
+    }
+
+    /// Removes a puppet, freeing everything it used on the GPU. Returns whether it was
+    /// still in the scene.
+    pub fn remove_puppet(&mut self, id: PuppetId) -> bool {
+        let len = self.puppets.len();
+        self.puppets.retain(|x| x.id != id);
+        self.puppets.len() != len
+    }
+
+    /// Every puppet in the scene, in the order they're drawn.
+    pub fn puppets(&self) -> impl Iterator<Item = PuppetId> + '_ {
+        self.puppets.iter().map(|x| x.id)
+    }
+
+    pub fn transform(&self, id: PuppetId) -> Mat4 {
+        self.puppet(id).transform
+    }
+
+    /// Transforms a puppet in clip space, on top of the usual fit to the render target,
+    /// like [Renderer::set_camera](crate::renderer::Renderer::set_camera). Takes effect
+    /// the next time the puppet is prepared.
+    pub fn set_transform(&mut self, id: PuppetId, transform: Mat4) {
+        self.puppet_mut(id).transform = transform;
+    }
+
+    pub fn order(&self, id: PuppetId) -> i32 {
+        self.puppet(id).order
+    }
+
+    /// Moves a puppet in front of every puppet with a lower order, and behind every puppet
+    /// with a higher one. Puppets start out with an order of zero.
+    pub fn set_order(&mut self, id: PuppetId, order: i32) {
+        self.puppet_mut(id).order = order;
+        self.sort();
+    }
+
+    /// Uploads (or replaces) one of a puppet's textures, like
+    /// [Renderer::set_texture](crate::renderer::Renderer::set_texture).
+    pub fn set_texture(
+        &mut self,
+        device: &Device,
+        queue: &Queue,
+        id: PuppetId,
+        index: usize,
+        texture: &impl TextureSource,
+    ) {
+        let index_in_scene = self.index_of(id);
+        self.puppets[index_in_scene].resources.set_texture(
+            &self.pipelines,
+            device,
+            queue,
+            index,
+            texture,
+        );
+    }
+
+    /// Tints or fades one of a puppet's art meshes, like
+    /// [Renderer::set_mesh_override](crate::renderer::Renderer::set_mesh_override).
+    pub fn set_mesh_override(&mut self, id: PuppetId, index: usize, mesh_override: MeshOverride) {
+        let index_in_scene = self.index_of(id);
+        self.puppets[index_in_scene]
+            .resources
+            .set_mesh_override(index, mesh_override);
+    }
+
+    /// Draws a puppet's masks with soft edges, like
+    /// [Renderer::set_soft_masks](crate::renderer::Renderer::set_soft_masks).
+    pub fn set_soft_masks(&mut self, device: &Device, id: PuppetId, soft_masks: Option<SoftMask>) {
+        let index_in_scene = self.index_of(id);
+        self.puppets[index_in_scene]
+            .resources
+            .set_soft_masks(&self.pipelines, device, soft_masks);
+    }
+
+    /// Sorts the triangles of one of a puppet's art meshes, like
+    /// [Renderer::set_triangle_sort](crate::renderer::Renderer::set_triangle_sort).
+    pub fn set_triangle_sort(&mut self, id: PuppetId, index: usize, sort: Option<TriangleSort>) {
+        self.puppet_mut(id).resources.set_triangle_sort(index, sort);
+    }
+
+    /// Forces a puppet's art meshes and parts to be shown or hidden, like
+    /// [Renderer::set_render_overrides](crate::renderer::Renderer::set_render_overrides).
+    pub fn set_render_overrides(
+        &mut self,
+        id: PuppetId,
+        puppet: &PuppetRef,
+        overrides: &RenderOverrides,
+    ) {
+        let index_in_scene = self.index_of(id);
+        self.puppets[index_in_scene]
+            .resources
+            .set_visibilities(overrides.resolve(puppet));
+    }
+
+    /// Swaps in a new version of a puppet, keeping its ID, order and transform, like
+    /// [Renderer::reload](crate::renderer::Renderer::reload).
+    pub fn reload_puppet(
+        &mut self,
+        device: &Device,
+        queue: &Queue,
+        id: PuppetId,
+        puppet: &PuppetRef,
+        textures: &[impl TextureSource],
+    ) {
+        let index_in_scene = self.index_of(id);
+        self.puppets[index_in_scene].resources.reload(
+            &mut self.pipelines,
+            device,
+            queue,
+            puppet,
+            textures,
+        );
+    }
+
+    /// Deforms a puppet with compute shaders whenever it's given deferred frame data, like
+    /// [Renderer::enable_gpu_deform](crate::renderer::Renderer::enable_gpu_deform).
+    pub fn enable_gpu_deform(&mut self, device: &Device, id: PuppetId, puppet: &PuppetRef) {
+        self.puppet_mut(id)
+            .resources
+            .enable_gpu_deform(device, puppet);
+    }
+
+    pub fn background(&self) -> &Background {
+        &self.background
+    }
+
+    pub fn set_background(&mut self, device: &Device, queue: &Queue, background: Background) {
+        self.background_layer = self.pipelines.background_layer(device, queue, &background);
+        self.background = background;
+    }
+
+    pub fn fit_mode(&self) -> FitMode {
+        self.fit_mode
+    }
+
+    /// Sets how each puppet's canvas is fitted into the render target, before its own
+    /// transform is applied. Puppets are never drawn outside of their canvas.
+    pub fn set_fit_mode(&mut self, fit_mode: FitMode) {
+        self.fit_mode = fit_mode;
+    }
+
+    /// Whether every puppet's meshes are drawn as flat colors instead of textured.
+    pub fn placeholder_mode(&self) -> bool {
+        self.placeholder_mode
+    }
+
+    pub fn set_placeholder_mode(&mut self, placeholder_mode: bool) {
+        self.placeholder_mode = placeholder_mode;
+    }
+
+    /// Uploads the given frames, each for the puppet it's paired with. Puppets that aren't
+    /// given a frame keep showing the last one they were prepared with.
+    pub fn prepare(
+        &mut self,
+        device: &Device,
+        queue: &Queue,
+        render_size: Extent3d,
+        frames: &[(PuppetId, &PuppetFrameData)],
+    ) {
+        resize_stencil(&mut self.mask_stencil, device, render_size);
+
+        for (id, frame_data) in frames {
+            let index = self.index_of(*id);
+            let puppet = &mut self.puppets[index];
+            puppet.resources.prepare(
+                device,
+                queue,
+                render_size,
+                puppet.transform,
+                self.fit_mode,
+                self.placeholder_mode,
+                frame_data,
+            );
+        }
+    }
+
+    /// Adds a step of drawing to every frame from now on, see [RenderHook]. Hooks draw
+    /// under or over the whole scene, never between puppets.
+    pub fn add_hook(&mut self, hook: impl RenderHook + 'static) {
+        self.hooks.push(Box::new(hook));
+    }
+
+    pub fn clear_hooks(&mut self) {
+        self.hooks.clear();
+    }
+
+    pub fn render(&self, view: &TextureView, encoder: &mut CommandEncoder) {
+        for puppet in &self.puppets {
+            puppet.resources.deform(encoder);
+            puppet
+                .resources
+                .draw_soft_masks(&self.pipelines, encoder, self.placeholder_mode);
+        }
+
+        let mask_stencil = self.mask_stencil.as_ref().unwrap();
+        let mask_view = mask_stencil.create_view(&TextureViewDescriptor::default());
+        let targets = FrameTargets {
+            view,
+            stencil_view: &mask_view,
+            size: mask_stencil.size(),
+            format: self.pipelines.format,
+        };
+
+        for hook in &self.hooks {
+            hook.before_pass(encoder, &targets);
+        }
+
+        {
+            let mut rpass = begin_pass(
+                encoder,
+                view,
+                &mask_view,
+                self.pipelines.format,
+                &self.background,
+                self.background_layer.as_ref(),
+            );
+            for hook in &self.hooks {
+                hook.draw_under(&mut rpass);
+            }
+
+            let mut stencil_ref = 0;
+            for puppet in &self.puppets {
+                puppet.resources.draw(
+                    &self.pipelines,
+                    &mut rpass,
+                    self.placeholder_mode,
+                    &mut stencil_ref,
+                );
+            }
+
+            draw_over(&self.hooks, &mut rpass, targets.size);
+        }
+
+        for hook in &self.hooks {
+            hook.after_pass(encoder, &targets);
+        }
+    }
+
+    fn sort(&mut self) {
+        self.puppets.sort_by_key(|x| (x.order, x.id));
+    }
+
+    fn index_of(&self, id: PuppetId) -> usize {
+        self.puppets
+            .iter()
+            .position(|x| x.id == id)
+            .expect("puppet is not in the scene")
+    }
+
+    fn puppet(&self, id: PuppetId) -> &ScenePuppet {
+        &self.puppets[self.index_of(id)]
+    }
+
+    fn puppet_mut(&mut self, id: PuppetId) -> &mut ScenePuppet {
+        let index = self.index_of(id);
+        &mut self.puppets[index]
+    }
+}
@@ -0,0 +1,66 @@
+struct VertexOutput {
+    @builtin(position) position: vec4<f32>,
+    @location(0) uv: vec2<f32>,
+};
+
+struct Background {
+    top: vec4<f32>,
+    bottom: vec4<f32>,
+    // 0 for a gradient, 1 for the texture, 2 for a checkerboard of top and bottom
+    fill: u32,
+    cell_size: f32,
+}
+
+@group(0) @binding(0)
+var<uniform> data: Background;
+
+@group(1) @binding(0)
+var texture : texture_2d<f32>;
+@group(1) @binding(1)
+var texture_sampler : sampler;
+
+// A single triangle covering the entire screen, no vertex buffers needed.
+@vertex
+fn vs_main(@builtin(vertex_index) index: u32) -> VertexOutput {
+    var out: VertexOutput;
+    let uv = vec2f(f32((index << 1u) & 2u), f32(index & 2u));
+    out.position = vec4f(uv.x * 2.0 - 1.0, 1.0 - uv.y * 2.0, 0.0, 1.0);
+    out.uv = uv;
+    return out;
+}
+
+fn fill(in: VertexOutput) -> vec4<f32> {
+    let tex = textureSample(texture, texture_sampler, in.uv);
+    let gradient = mix(data.top, data.bottom, in.uv.y);
+
+    let cell = vec2<u32>(in.position.xy / data.cell_size);
+    let checker = select(data.top, data.bottom, ((cell.x + cell.y) & 1u) != 0u);
+
+    var color = gradient;
+    if (data.fill == 1u) {
+        color = tex;
+    } else if (data.fill == 2u) {
+        color = checker;
+    }
+    return color;
+}
+
+fn linear_from_gamma(gamma: vec3f) -> vec3f {
+    let cutoff = gamma < vec3f(0.04045);
+    let lower = gamma / vec3f(12.92);
+    let higher = pow((gamma + vec3f(0.055)) / vec3f(1.055), vec3f(2.4));
+    return select(higher, lower, cutoff);
+}
+
+@fragment
+fn fs_main(in: VertexOutput) -> @location(0) vec4<f32> {
+    let color = fill(in);
+    return vec4(color.rgb * color.a, color.a);
+}
+
+// Background colors are in gamma space, like the puppet's, so sRGB targets decode them.
+@fragment
+fn fs_main_srgb(in: VertexOutput) -> @location(0) vec4<f32> {
+    let color = fill(in);
+    return vec4(linear_from_gamma(color.rgb) * color.a, color.a);
+}
@@ -0,0 +1,42 @@
+// Flat colored lines and triangles drawn over a puppet, for the debug overlay.
+
+struct VertexOutput {
+    @builtin(position) position: vec4<f32>,
+    @location(0) color: vec4<f32>,
+};
+
+@group(0) @binding(0)
+var<uniform> u_camera: mat4x4<f32>;
+
+@vertex
+fn vs_main(
+    @location(0) vertex: vec2<f32>,
+    // The same color for every vertex of a draw, premultiplied.
+    @location(1) color: vec4<f32>,
+) -> VertexOutput {
+    var out: VertexOutput;
+    out.position = u_camera * vec4f(vertex, 0.0, 1.0);
+    out.color = color;
+    return out;
+}
+
+@fragment
+fn fs_main(in: VertexOutput) -> @location(0) vec4<f32> {
+    return in.color;
+}
+
+fn linear_from_gamma(gamma: vec3f) -> vec3f {
+    let cutoff = gamma < vec3f(0.04045);
+    let lower = gamma / vec3f(12.92);
+    let higher = pow((gamma + vec3f(0.055)) / vec3f(1.055), vec3f(2.4));
+    return select(higher, lower, cutoff);
+}
+
+// Overlay colors are in gamma space too, so sRGB targets decode them.
+@fragment
+fn fs_main_srgb(in: VertexOutput) -> @location(0) vec4<f32> {
+    if in.color.a <= 0.0 {
+        return vec4f(0.0);
+    }
+    return vec4f(linear_from_gamma(in.color.rgb / in.color.a) * in.color.a, in.color.a);
+}
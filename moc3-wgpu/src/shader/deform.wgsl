@@ -0,0 +1,223 @@
+// Blends every art mesh vertex from its keyforms, then moves it by the deformer the mesh
+// sits under. This is the same math moc3-rs does on the CPU, see `DeferredDeform`.
+
+struct KeyformWeight {
+    start: u32,
+    weight: f32,
+}
+
+struct MeshParent {
+    kind: u32,
+    grid_start: u32,
+    rows: u32,
+    columns: u32,
+    x_axis: vec2<f32>,
+    y_axis: vec2<f32>,
+    translation: vec2<f32>,
+}
+
+const PARENT_ROTATION: u32 = 1u;
+const PARENT_WARP: u32 = 2u;
+const PARENT_WARP_BILINEAR: u32 = 3u;
+
+@group(0) @binding(0)
+var<storage, read> keyform_positions: array<vec2<f32>>;
+// The art mesh each vertex belongs to, and the vertex's index within it.
+@group(0) @binding(1)
+var<storage, read> vertex_sources: array<vec2<u32>>;
+// Where each art mesh's keyforms start, plus one more for where the last one ends.
+@group(0) @binding(2)
+var<storage, read> keyform_offsets: array<u32>;
+@group(0) @binding(3)
+var<storage, read> keyforms: array<KeyformWeight>;
+@group(0) @binding(4)
+var<storage, read> parents: array<MeshParent>;
+@group(0) @binding(5)
+var<storage, read> warp_grids: array<vec2<f32>>;
+@group(0) @binding(6)
+var<storage, read_write> vertexes: array<vec2<f32>>;
+
+fn rescale(t: f32, lower: f32, upper: f32) -> f32 {
+    return (t - lower) / (upper - lower);
+}
+
+fn bilinear(t: vec2<f32>, bottom_left: vec2<f32>, bottom_right: vec2<f32>, top_left: vec2<f32>, top_right: vec2<f32>) -> vec2<f32> {
+    let neg = 1.0 - t;
+    return bottom_left * neg.x * neg.y
+        + bottom_right * t.x * neg.y
+        + top_left * neg.x * t.y
+        + top_right * t.x * t.y;
+}
+
+fn triangular(t: vec2<f32>, bottom_left: vec2<f32>, bottom_right: vec2<f32>, top_left: vec2<f32>, top_right: vec2<f32>) -> vec2<f32> {
+    let neg = 1.0 - t;
+    if t.x + t.y > 1.0 {
+        return top_right + (top_left - top_right) * neg.x + (bottom_right - top_right) * neg.y;
+    }
+    return bottom_left + (bottom_right - bottom_left) * t.x + (top_left - bottom_left) * t.y;
+}
+
+// See apply_warp_deformer in moc3-rs for what all of this is doing.
+fn warp(parent: MeshParent, point: vec2<f32>) -> vec2<f32> {
+    let columns = parent.columns;
+    let rows = parent.rows;
+    let stride = columns + 1u;
+    let start = parent.grid_start;
+
+    let point_grid = point * vec2f(f32(columns), f32(rows));
+    // Float to integer casts saturate in Rust, but negative ones aren't defined everywhere.
+    let grid_x = u32(max(point_grid.x, 0.0));
+    let grid_y = u32(max(point_grid.y, 0.0));
+
+    if all(point >= vec2f(0.0)) && all(point < vec2f(1.0)) {
+        let i = start + grid_x + grid_y * stride;
+        let t = fract(point_grid);
+        if parent.kind == PARENT_WARP_BILINEAR {
+            return bilinear(t, warp_grids[i], warp_grids[i + 1u], warp_grids[i + stride], warp_grids[i + stride + 1u]);
+        }
+        return triangular(t, warp_grids[i], warp_grids[i + 1u], warp_grids[i + stride], warp_grids[i + stride + 1u]);
+    }
+
+    let bottom_left = warp_grids[start];
+    let bottom_right = warp_grids[start + columns];
+    let top_left = warp_grids[start + rows * stride];
+    let top_right = warp_grids[start + columns + rows * stride];
+
+    let centroid = (bottom_left + bottom_right + top_left + top_right) / 4.0;
+    let diagonal_one = top_right - bottom_left;
+    let diagonal_two = bottom_right - top_left;
+    let v_x = (diagonal_one + diagonal_two) / 2.0;
+    let v_y = (diagonal_one - diagonal_two) / 2.0;
+    let origin = centroid - diagonal_one * 0.5;
+
+    let is_transition = all(point >= vec2f(-2.0)) && all(point <= vec2f(3.0));
+    if !is_transition {
+        return origin + point.x * v_x + point.y * v_y;
+    }
+
+    var case_x = 0u;
+    if point.x >= 1.0 {
+        case_x = 2u;
+    } else if point.x >= 0.0 {
+        case_x = 1u;
+    }
+    var case_y = 0u;
+    if point.y >= 1.0 {
+        case_y = 2u;
+    } else if point.y >= 0.0 {
+        case_y = 1u;
+    }
+
+    switch case_x + case_y * 3u {
+        case 7u: {
+            let x = min(grid_x, columns - 1u);
+            let first_f = f32(x) / f32(columns);
+            let second_f = f32(x + 1u) / f32(columns);
+            return triangular(
+                vec2f(point_grid.x - f32(x), rescale(point.y, 1.0, 3.0)),
+                warp_grids[start + x + rows * stride],
+                warp_grids[start + x + 1u + rows * stride],
+                origin + v_x * first_f + v_y * 3.0,
+                origin + v_x * second_f + v_y * 3.0,
+            );
+        }
+        case 1u: {
+            let x = min(grid_x, columns - 1u);
+            let first_f = f32(x) / f32(columns);
+            let second_f = f32(x + 1u) / f32(columns);
+            return triangular(
+                vec2f(point_grid.x - f32(x), rescale(point.y, -2.0, 0.0)),
+                origin + v_x * first_f + v_y * -2.0,
+                origin + v_x * second_f + v_y * -2.0,
+                warp_grids[start + x],
+                warp_grids[start + x + 1u],
+            );
+        }
+        case 3u: {
+            let y = min(grid_y, rows - 1u);
+            let first_f = f32(y) / f32(rows);
+            let second_f = f32(y + 1u) / f32(rows);
+            return triangular(
+                vec2f(rescale(point.x, -2.0, 0.0), point_grid.y - f32(y)),
+                origin + v_x * -2.0 + v_y * first_f,
+                warp_grids[start + y * stride],
+                origin + v_x * -2.0 + v_y * second_f,
+                warp_grids[start + (y + 1u) * stride],
+            );
+        }
+        case 5u: {
+            let y = min(grid_y, rows - 1u);
+            let first_f = f32(y) / f32(rows);
+            let second_f = f32(y + 1u) / f32(rows);
+            return triangular(
+                vec2f(rescale(point.x, 1.0, 3.0), point_grid.y - f32(y)),
+                warp_grids[start + columns + y * stride],
+                origin + v_x * 3.0 + v_y * first_f,
+                warp_grids[start + columns + (y + 1u) * stride],
+                origin + v_x * 3.0 + v_y * second_f,
+            );
+        }
+        case 6u: {
+            return triangular(
+                vec2f(rescale(point.x, -2.0, 0.0), rescale(point.y, 1.0, 3.0)),
+                origin + v_x * -2.0 + v_y * 1.0,
+                top_left,
+                origin + v_x * -2.0 + v_y * 3.0,
+                origin + v_x * 0.0 + v_y * 3.0,
+            );
+        }
+        case 8u: {
+            return triangular(
+                vec2f(rescale(point.x, 1.0, 3.0), rescale(point.y, 1.0, 3.0)),
+                top_right,
+                origin + v_x * 3.0 + v_y * 1.0,
+                origin + v_x * 1.0 + v_y * 3.0,
+                origin + v_x * 3.0 + v_y * 3.0,
+            );
+        }
+        case 0u: {
+            return triangular(
+                vec2f(rescale(point.x, -2.0, 0.0), rescale(point.y, -2.0, 0.0)),
+                origin + v_x * -2.0 + v_y * -2.0,
+                origin + v_x * 0.0 + v_y * -2.0,
+                origin + v_x * -2.0 + v_y * 0.0,
+                bottom_left,
+            );
+        }
+        default: {
+            return triangular(
+                vec2f(rescale(point.x, 1.0, 3.0), rescale(point.y, -2.0, 0.0)),
+                origin + v_x * 1.0 + v_y * -2.0,
+                origin + v_x * 3.0 + v_y * -2.0,
+                bottom_right,
+                origin + v_x * 3.0 + v_y * 0.0,
+            );
+        }
+    }
+}
+
+@compute @workgroup_size(64)
+fn deform(@builtin(global_invocation_id) id: vec3<u32>) {
+    let index = id.x;
+    if index >= arrayLength(&vertex_sources) {
+        return;
+    }
+
+    let source = vertex_sources[index];
+    let mesh = source.x;
+
+    var point = vec2f(0.0);
+    for (var i = keyform_offsets[mesh]; i < keyform_offsets[mesh + 1u]; i++) {
+        let keyform = keyforms[i];
+        point += keyform_positions[keyform.start + source.y] * keyform.weight;
+    }
+
+    let parent = parents[mesh];
+    if parent.kind == PARENT_ROTATION {
+        point = parent.x_axis * point.x + parent.y_axis * point.y + parent.translation;
+    } else if parent.kind == PARENT_WARP || parent.kind == PARENT_WARP_BILINEAR {
+        point = warp(parent, point);
+    }
+
+    vertexes[index] = point;
+}
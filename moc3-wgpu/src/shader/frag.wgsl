@@ -17,12 +17,32 @@ var texture : texture_2d<f32>;
 @group(1) @binding(1)
 var texture_sampler : sampler;
 
-@fragment
-fn fs_main(in: VertexOutput) -> @location(0) vec4<f32> {
+// Multiply first, then screen on top of that, same as the official runtime. Textures
+// aren't premultiplied, so that happens afterwards for every blend mode.
+fn tinted(in: VertexOutput) -> vec4<f32> {
     let tex = textureSample(texture, texture_sampler, in.uv);
     var color = tex.rgb * data.multiply_color;
-    color = (tex.rgb + data.screen_color) - (tex.rgb * data.screen_color);
-    color *= tex.a;
+    color = (color + data.screen_color) - (color * data.screen_color);
+    return vec4(color, tex.a);
+}
 
-    return vec4(color, tex.a) * data.opacity;
-}
\ No newline at end of file
+fn linear_from_gamma(gamma: vec3f) -> vec3f {
+    let cutoff = gamma < vec3f(0.04045);
+    let lower = gamma / vec3f(12.92);
+    let higher = pow((gamma + vec3f(0.055)) / vec3f(1.055), vec3f(2.4));
+    return select(higher, lower, cutoff);
+}
+
+@fragment
+fn fs_main(in: VertexOutput) -> @location(0) vec4<f32> {
+    let color = tinted(in);
+    return vec4(color.rgb * color.a, color.a) * data.opacity;
+}
+
+// sRGB targets blend in linear light, so colors are decoded once they're tinted, which
+// still happens in gamma space like in Cubism.
+@fragment
+fn fs_main_srgb(in: VertexOutput) -> @location(0) vec4<f32> {
+    let color = tinted(in);
+    return vec4(linear_from_gamma(color.rgb) * color.a, color.a) * data.opacity;
+}
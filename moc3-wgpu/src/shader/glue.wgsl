@@ -0,0 +1,68 @@
+// Applies every glue to the deformed vertexes, like moc3-rs does on the CPU.
+//
+// Glues run one after another from a single invocation, since a later glue can pull on
+// vertexes an earlier one already moved. Puppets rarely glue more than a few hundred pairs.
+
+struct Glue {
+    pair_start: u32,
+    pair_count: u32,
+}
+
+@group(0) @binding(0)
+var<storage, read> glues: array<Glue>;
+// The two vertexes of every pair, as indices into every vertex of the puppet.
+@group(0) @binding(1)
+var<storage, read> pairs: array<vec2<u32>>;
+@group(0) @binding(2)
+var<storage, read> weights: array<vec2<f32>>;
+@group(0) @binding(3)
+var<storage, read> intensities: array<f32>;
+// How far each side of every pair moves, for normalized glues.
+@group(0) @binding(4)
+var<storage, read_write> deltas: array<vec4<f32>>;
+@group(0) @binding(5)
+var<storage, read_write> vertexes: array<vec2<f32>>;
+
+@compute @workgroup_size(1)
+fn glue() {
+    for (var g = 0u; g < arrayLength(&glues); g++) {
+        let glue = glues[g];
+        let intensity = intensities[g];
+
+        for (var i = glue.pair_start; i < glue.pair_start + glue.pair_count; i++) {
+            let pair = pairs[i];
+            let weight = weights[i];
+            let a = vertexes[pair.x];
+            let b = vertexes[pair.y];
+
+            vertexes[pair.x] = a + (b - a) * weight.x * intensity;
+            vertexes[pair.y] = b + (a - b) * weight.y * intensity;
+        }
+    }
+}
+
+// Every pull within a glue is worked out before any are applied, and intensities are
+// clamped, see GlueNormalization::PerVertex.
+@compute @workgroup_size(1)
+fn glue_normalized() {
+    for (var g = 0u; g < arrayLength(&glues); g++) {
+        let glue = glues[g];
+        let intensity = clamp(intensities[g], 0.0, 1.0);
+        let end = glue.pair_start + glue.pair_count;
+
+        for (var i = glue.pair_start; i < end; i++) {
+            let pair = pairs[i];
+            let weight = weights[i];
+            let a = vertexes[pair.x];
+            let b = vertexes[pair.y];
+
+            deltas[i] = vec4f((b - a) * weight.x * intensity, (a - b) * weight.y * intensity);
+        }
+
+        for (var i = glue.pair_start; i < end; i++) {
+            let pair = pairs[i];
+            vertexes[pair.x] += deltas[i].xy;
+            vertexes[pair.y] += deltas[i].zw;
+        }
+    }
+}
@@ -0,0 +1,70 @@
+// Draws a masked mesh faded by how much of it its masks cover, instead of cutting it off
+// with the stencil.
+
+struct VertexOutput {
+    @builtin(position) position: vec4<f32>,
+    @location(0) uv: vec2<f32>,
+};
+
+struct Uniform {
+    multiply_color: vec3<f32>,
+    screen_color: vec3<f32>,
+    opacity: f32,
+    mask_layer: u32,
+    mask_inverted: u32,
+}
+
+struct SoftMask {
+    threshold: f32,
+    feather: f32,
+}
+
+@group(0) @binding(1)
+var<uniform> data: Uniform;
+
+@group(1) @binding(0)
+var texture : texture_2d<f32>;
+@group(1) @binding(1)
+var texture_sampler : sampler;
+
+@group(2) @binding(0)
+var mask_coverage : texture_2d_array<f32>;
+@group(2) @binding(1)
+var<uniform> soft_mask: SoftMask;
+
+fn tinted(in: VertexOutput) -> vec4<f32> {
+    let tex = textureSample(texture, texture_sampler, in.uv);
+    var color = tex.rgb * data.multiply_color;
+    color = (color + data.screen_color) - (color * data.screen_color);
+    return vec4(color, tex.a);
+}
+
+// How much of the mesh its masks show at this pixel.
+fn mask(in: VertexOutput) -> f32 {
+    // Coverage has the same size as the target, so it's read a pixel at a time. The
+    // default threshold and feather leave it as it is.
+    let coverage = textureLoad(mask_coverage, vec2<i32>(in.position.xy), i32(data.mask_layer), 0).r;
+    let edge = max(2.0 * soft_mask.feather, 0.00001);
+    let mask = clamp((coverage - soft_mask.threshold) / edge + 0.5, 0.0, 1.0);
+    return select(mask, 1.0 - mask, data.mask_inverted != 0u);
+}
+
+fn linear_from_gamma(gamma: vec3f) -> vec3f {
+    let cutoff = gamma < vec3f(0.04045);
+    let lower = gamma / vec3f(12.92);
+    let higher = pow((gamma + vec3f(0.055)) / vec3f(1.055), vec3f(2.4));
+    return select(higher, lower, cutoff);
+}
+
+@fragment
+fn fs_main(in: VertexOutput) -> @location(0) vec4<f32> {
+    let color = tinted(in);
+    return vec4(color.rgb * color.a, color.a) * data.opacity * mask(in);
+}
+
+// Like frag.wgsl, decoded once tinted for sRGB targets.
+@fragment
+fn fs_main_srgb(in: VertexOutput) -> @location(0) vec4<f32> {
+    let color = tinted(in);
+    return vec4(linear_from_gamma(color.rgb) * color.a, color.a) * data.opacity * mask(in);
+}
@@ -0,0 +1,13 @@
+// Zeroes the stencil buffer mid-pass once every stencil value has been used up by masks.
+// Only the stencil is written, the color output is thrown away.
+
+@vertex
+fn vs_main(@builtin(vertex_index) index: u32) -> @builtin(position) vec4<f32> {
+    let uv = vec2f(f32((index << 1u) & 2u), f32(index & 2u));
+    return vec4f(uv.x * 2.0 - 1.0, 1.0 - uv.y * 2.0, 0.0, 1.0);
+}
+
+@fragment
+fn fs_main() -> @location(0) vec4<f32> {
+    return vec4f(0.0);
+}
@@ -0,0 +1,316 @@
+//! Masks with smooth edges, see
+//! [Renderer::set_soft_masks](crate::renderer::Renderer::set_soft_masks).
+
+use encase::{ShaderType, UniformBuffer};
+use moc3_rs::data::BlendMode;
+use wgpu::*;
+
+use crate::renderer::{pipeline_for, PipelineKind, Pipelines};
+
+/// How masked meshes fade in along the edges of their masks, when drawn with soft masks.
+///
+/// Masks are drawn as coverage from 0 to 1, out of their textures' alpha, which is then
+/// mapped onto how much of the masked mesh is shown.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct SoftMask {
+    /// The coverage at which masked meshes are half shown.
+    pub threshold: f32,
+    /// How far either side of the threshold the fade reaches. Zero cuts meshes off at the
+    /// threshold, like stencil masks but without their jagged edges.
+    pub feather: f32,
+}
+
+impl SoftMask {
+    /// Shows masked meshes exactly as much as their masks cover, like the editor does.
+    pub const EDITOR: Self = Self {
+        threshold: 0.5,
+        feather: 0.5,
+    };
+}
+
+impl Default for SoftMask {
+    fn default() -> Self {
+        Self::EDITOR
+    }
+}
+
+#[derive(ShaderType)]
+struct SoftMaskUniform {
+    threshold: f32,
+    feather: f32,
+}
+
+// A layer of coverage for every distinct set of masks, as big as the render target.
+struct CoverageTarget {
+    texture: Texture,
+    layer_views: Vec<TextureView>,
+    bind_group: BindGroup,
+}
+
+pub(crate) struct SoftMaskResources {
+    settings: SoftMask,
+    // blend mode first, then double-sided, like the regular pipelines
+    pipeline: [[RenderPipeline; 3]; 2],
+    // just double-sided here
+    coverage_pipeline: [RenderPipeline; 2],
+    layout: BindGroupLayout,
+    settings_buffer: Buffer,
+
+    // The masks of each layer, and which layer every art mesh is masked by. Meshes sharing
+    // the same masks share a layer too.
+    layers: Vec<Vec<u32>>,
+    layer_of: Vec<Option<u32>>,
+    target: Option<CoverageTarget>,
+}
+
+impl SoftMaskResources {
+    pub fn new(
+        device: &Device,
+        pipelines: &Pipelines,
+        mask_indices: &[Vec<u32>],
+        settings: SoftMask,
+    ) -> Self {
+        // Meshes past however many layers the device can have keep using the stencil.
+        let max_layers = device.limits().max_texture_array_layers as usize;
+        let mut layers: Vec<Vec<u32>> = Vec::new();
+        let mut layer_of = Vec::with_capacity(mask_indices.len());
+        for masks in mask_indices {
+            let masks: Vec<u32> = masks.iter().copied().filter(|x| *x != u32::MAX).collect();
+            if masks.is_empty() {
+                layer_of.push(None);
+                continue;
+            }
+
+            let layer = match layers.iter().position(|x| *x == masks) {
+                Some(layer) => Some(layer),
+                None if layers.len() < max_layers => {
+                    layers.push(masks);
+                    Some(layers.len() - 1)
+                }
+                None => None,
+            };
+            layer_of.push(layer.map(|x| x as u32));
+        }
+
+        let layout = device.create_bind_group_layout(&BindGroupLayoutDescriptor {
+            entries: &[
+                BindGroupLayoutEntry {
+                    binding: 0,
+                    visibility: ShaderStages::FRAGMENT,
+                    ty: BindingType::Texture {
+                        multisampled: false,
+                        sample_type: TextureSampleType::Float { filterable: false },
+                        view_dimension: TextureViewDimension::D2Array,
+                    },
+                    count: None,
+                },
+                BindGroupLayoutEntry {
+                    binding: 1,
+                    visibility: ShaderStages::FRAGMENT,
+                    ty: BindingType::Buffer {
+                        ty: BufferBindingType::Uniform,
+                        has_dynamic_offset: false,
+                        min_binding_size: Some(SoftMaskUniform::min_size()),
+                    },
+                    count: None,
+                },
+            ],
+            label: None,
+        });
+
+        let pipeline_layout = device.create_pipeline_layout(&PipelineLayoutDescriptor {
+            bind_group_layouts: &[
+                pipelines.uniform_layout(),
+                pipelines.texture_layout(),
+                &layout,
+            ],
+            ..PipelineLayoutDescriptor::default()
+        });
+        let pipeline = [false, true].map(|double_sided| {
+            [
+                BlendMode::Normal,
+                BlendMode::Additive,
+                BlendMode::Multiplicative,
+            ]
+            .map(|blend_mode| {
+                pipeline_for(
+                    device,
+                    None,
+                    &pipeline_layout,
+                    pipelines.format,
+                    double_sided,
+                    PipelineKind::SoftMasked(blend_mode),
+                )
+            })
+        });
+
+        let coverage_layout = device.create_pipeline_layout(&PipelineLayoutDescriptor {
+            bind_group_layouts: &[pipelines.uniform_layout(), pipelines.texture_layout()],
+            ..PipelineLayoutDescriptor::default()
+        });
+        let coverage_pipeline = [false, true].map(|double_sided| {
+            pipeline_for(
+                device,
+                None,
+                &coverage_layout,
+                COVERAGE_FORMAT,
+                double_sided,
+                PipelineKind::MaskCoverage,
+            )
+        });
+
+        Self {
+            settings,
+            pipeline,
+            coverage_pipeline,
+            layout,
+            settings_buffer: device.create_buffer(&BufferDescriptor {
+                size: SoftMaskUniform::min_size().get(),
+                usage: BufferUsages::UNIFORM | BufferUsages::COPY_DST,
+                mapped_at_creation: false,
+                label: None,
+            }),
+
+            layers,
+            layer_of,
+            target: None,
+        }
+    }
+
+    pub fn settings(&self) -> SoftMask {
+        self.settings
+    }
+
+    pub fn set_settings(&mut self, settings: SoftMask) {
+        self.settings = settings;
+    }
+
+    // The layer of coverage the art mesh with the given index is masked by, if any.
+    pub fn layer(&self, art_index: usize) -> Option<u32> {
+        self.layer_of[art_index]
+    }
+
+    pub fn layers(&self) -> &[Vec<u32>] {
+        &self.layers
+    }
+
+    pub fn prepare(&mut self, device: &Device, queue: &Queue, render_size: Extent3d) {
+        let mut buffer = UniformBuffer::new(Vec::new());
+        buffer
+            .write(&SoftMaskUniform {
+                threshold: self.settings.threshold,
+                feather: self.settings.feather,
+            })
+            .unwrap();
+        queue.write_buffer(&self.settings_buffer, 0, &buffer.into_inner());
+
+        // The GL backend makes textures with a single layer plain 2D textures, which can't
+        // be read as arrays, so there's always a spare layer.
+        let size = Extent3d {
+            depth_or_array_layers: self.layers.len().max(2) as u32,
+            ..render_size
+        };
+        if self
+            .target
+            .as_ref()
+            .is_some_and(|x| x.texture.size() != size)
+        {
+            self.target = None;
+        }
+        if self.target.is_none() && !self.layers.is_empty() && size.width * size.height > 0 {
+            self.target = Some(self.coverage_target(device, size));
+        }
+    }
+
+    fn coverage_target(&self, device: &Device, size: Extent3d) -> CoverageTarget {
+        let texture = device.create_texture(&TextureDescriptor {
+            size,
+            mip_level_count: 1,
+            sample_count: 1,
+            dimension: TextureDimension::D2,
+            format: COVERAGE_FORMAT,
+            usage: TextureUsages::RENDER_ATTACHMENT | TextureUsages::TEXTURE_BINDING,
+            view_formats: &[],
+            label: None,
+        });
+        let layer_views = (0..size.depth_or_array_layers)
+            .map(|layer| {
+                texture.create_view(&TextureViewDescriptor {
+                    dimension: Some(TextureViewDimension::D2),
+                    base_array_layer: layer,
+                    array_layer_count: Some(1),
+                    ..TextureViewDescriptor::default()
+                })
+            })
+            .collect();
+        // Views of a single layer would come out as plain 2D textures otherwise.
+        let view = texture.create_view(&TextureViewDescriptor {
+            dimension: Some(TextureViewDimension::D2Array),
+            ..TextureViewDescriptor::default()
+        });
+
+        let bind_group = device.create_bind_group(&BindGroupDescriptor {
+            layout: &self.layout,
+            entries: &[
+                BindGroupEntry {
+                    binding: 0,
+                    resource: BindingResource::TextureView(&view),
+                },
+                BindGroupEntry {
+                    binding: 1,
+                    resource: self.settings_buffer.as_entire_binding(),
+                },
+            ],
+            label: None,
+        });
+
+        CoverageTarget {
+            texture,
+            layer_views,
+            bind_group,
+        }
+    }
+
+    // Starts the pass drawing a layer's masks into, if there's anywhere to draw them yet.
+    pub fn begin_layer<'a>(
+        &'a self,
+        encoder: &'a mut CommandEncoder,
+        layer: usize,
+    ) -> Option<RenderPass<'a>> {
+        let target = self.target.as_ref()?;
+        Some(encoder.begin_render_pass(&RenderPassDescriptor {
+            color_attachments: &[Some(RenderPassColorAttachment {
+                view: &target.layer_views[layer],
+                resolve_target: None,
+                ops: Operations {
+                    load: LoadOp::Clear(Color::TRANSPARENT),
+                    store: true,
+                },
+            })],
+            depth_stencil_attachment: None,
+            label: None,
+        }))
+    }
+
+    pub fn coverage_pipeline(&self, double_sided: bool) -> &RenderPipeline {
+        &self.coverage_pipeline[double_sided as usize]
+    }
+
+    // Sets up drawing a masked mesh in the puppets' pass, returning false if there's no
+    // coverage to draw it with yet.
+    pub fn begin_masked<'a>(
+        &'a self,
+        rpass: &mut RenderPass<'a>,
+        double_sided: bool,
+        blend_mode: BlendMode,
+    ) -> bool {
+        let Some(target) = &self.target else {
+            return false;
+        };
+        rpass.set_pipeline(&self.pipeline[double_sided as usize][blend_mode as usize]);
+        rpass.set_bind_group(2, &target.bind_group, &[]);
+        true
+    }
+}
+
+const COVERAGE_FORMAT: TextureFormat = TextureFormat::R8Unorm;
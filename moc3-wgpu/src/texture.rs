@@ -0,0 +1,460 @@
+//! Where a puppet's textures come from.
+//!
+//! The renderer takes anything implementing [TextureSource], so embedders with their own
+//! asset pipelines can hand over raw pixels, compressed data or textures they already
+//! uploaded, without going through the `image` crate.
+
+use std::{borrow::Cow, ops::Deref};
+
+use glam::Vec2;
+use image::{imageops, RgbaImage};
+use moc3_rs::puppet::PuppetRef;
+use wgpu::{util::DeviceExt, *};
+
+/// Something the renderer can sample a texture from.
+///
+/// The resulting view must be a filterable 2D float texture. Colors are sampled as they're
+/// stored, so the texture should use a non-sRGB format like Cubism's own textures do.
+/// Renderers drawing into sRGB targets decode them themselves, once they're tinted, see
+/// [ColorSpace](crate::renderer::ColorSpace).
+pub trait TextureSource {
+    /// Uploads the texture if it isn't on the GPU yet, returning a view of it.
+    fn texture_view(&self, device: &Device, queue: &Queue) -> SourceView<'_>;
+}
+
+/// A view returned by [TextureSource::texture_view], which only needs to live until the
+/// renderer has bound it.
+pub enum SourceView<'a> {
+    Owned(TextureView),
+    Borrowed(&'a TextureView),
+}
+
+impl Deref for SourceView<'_> {
+    type Target = TextureView;
+
+    fn deref(&self) -> &TextureView {
+        match self {
+            SourceView::Owned(view) => view,
+            SourceView::Borrowed(view) => view,
+        }
+    }
+}
+
+impl<T: TextureSource + ?Sized> TextureSource for &T {
+    fn texture_view(&self, device: &Device, queue: &Queue) -> SourceView<'_> {
+        (**self).texture_view(device, queue)
+    }
+}
+
+/// A texture that's already on the GPU, used as is.
+impl TextureSource for TextureView {
+    fn texture_view(&self, _device: &Device, _queue: &Queue) -> SourceView<'_> {
+        SourceView::Borrowed(self)
+    }
+}
+
+impl TextureSource for RgbaImage {
+    fn texture_view(&self, device: &Device, queue: &Queue) -> SourceView<'_> {
+        upload(
+            device,
+            queue,
+            self.width(),
+            self.height(),
+            TextureFormat::Rgba8Unorm,
+            1,
+            self.as_raw(),
+        )
+    }
+}
+
+/// Tightly packed 8-bit RGBA pixels, row by row from the top left.
+#[derive(Debug, Clone, Copy)]
+pub struct RawRgba<'a> {
+    pub width: u32,
+    pub height: u32,
+    pub data: &'a [u8],
+}
+
+impl TextureSource for RawRgba<'_> {
+    fn texture_view(&self, device: &Device, queue: &Queue) -> SourceView<'_> {
+        assert_eq!(
+            self.data.len(),
+            self.width as usize * self.height as usize * 4,
+            "RGBA data doesn't match its size"
+        );
+
+        upload(
+            device,
+            queue,
+            self.width,
+            self.height,
+            TextureFormat::Rgba8Unorm,
+            1,
+            self.data,
+        )
+    }
+}
+
+/// A texture filled in a few rows at a time, for showing a model while its textures are
+/// still downloading or decoding.
+///
+/// It can be handed to [Renderer::set_texture](crate::renderer::Renderer::set_texture)
+/// straight away, with rows that haven't arrived yet left transparent, or only once it's
+/// [complete](TextureStream::is_complete) to keep drawing placeholders until then. Rows
+/// written after it's been set show up without setting it again.
+pub struct TextureStream {
+    texture: Texture,
+    view: TextureView,
+    written: Vec<bool>,
+    remaining: u32,
+}
+
+impl TextureStream {
+    /// Makes an empty, fully transparent 8-bit RGBA texture.
+    pub fn new(device: &Device, width: u32, height: u32) -> Self {
+        let texture = device.create_texture(&TextureDescriptor {
+            size: Extent3d {
+                width,
+                height,
+                depth_or_array_layers: 1,
+            },
+            mip_level_count: 1,
+            sample_count: 1,
+            dimension: TextureDimension::D2,
+            format: TextureFormat::Rgba8Unorm,
+            usage: TextureUsages::TEXTURE_BINDING | TextureUsages::COPY_DST,
+            view_formats: &[],
+            label: None,
+        });
+
+        Self {
+            view: texture.create_view(&TextureViewDescriptor::default()),
+            texture,
+            written: vec![false; height as usize],
+            remaining: height,
+        }
+    }
+
+    /// Writes whole rows starting at `first_row`, as tightly packed RGBA pixels like
+    /// [RawRgba::data]. Rows can come in any order, and writing one again replaces it.
+    pub fn write_rows(&mut self, queue: &Queue, first_row: u32, data: &[u8]) {
+        let width = self.texture.width();
+        let row_bytes = width as usize * 4;
+        assert_eq!(
+            data.len() % row_bytes,
+            0,
+            "RGBA data isn't made of whole rows"
+        );
+        let rows = (data.len() / row_bytes) as u32;
+        assert!(
+            first_row + rows <= self.texture.height(),
+            "rows go past the bottom of the texture"
+        );
+        if rows == 0 {
+            return;
+        }
+
+        queue.write_texture(
+            ImageCopyTexture {
+                texture: &self.texture,
+                mip_level: 0,
+                origin: Origin3d {
+                    x: 0,
+                    y: first_row,
+                    z: 0,
+                },
+                aspect: TextureAspect::All,
+            },
+            data,
+            ImageDataLayout {
+                offset: 0,
+                bytes_per_row: Some(width * 4),
+                rows_per_image: None,
+            },
+            Extent3d {
+                width,
+                height: rows,
+                depth_or_array_layers: 1,
+            },
+        );
+
+        for written in &mut self.written[first_row as usize..(first_row + rows) as usize] {
+            if !*written {
+                *written = true;
+                self.remaining -= 1;
+            }
+        }
+    }
+
+    /// How many rows haven't been written yet.
+    pub fn remaining_rows(&self) -> u32 {
+        self.remaining
+    }
+
+    pub fn is_complete(&self) -> bool {
+        self.remaining == 0
+    }
+}
+
+impl TextureSource for TextureStream {
+    fn texture_view(&self, _device: &Device, _queue: &Queue) -> SourceView<'_> {
+        SourceView::Borrowed(&self.view)
+    }
+}
+
+/// Block compressed texture data, such as BC7 or ASTC, uploaded without decoding.
+///
+/// `data` holds every mip level one after another, starting from the largest. This is how
+/// the levels of a KTX2 file come out once supercompression is undone, see
+/// [CompressedTexture::from_ktx2]. The device must have been created with the feature the
+/// format needs, like [Features::TEXTURE_COMPRESSION_BC], see
+/// [CompressedTexture::is_supported].
+#[derive(Debug, Clone)]
+pub struct CompressedTexture<'a> {
+    pub width: u32,
+    pub height: u32,
+    pub format: TextureFormat,
+    pub mip_level_count: u32,
+    pub data: Cow<'a, [u8]>,
+}
+
+impl CompressedTexture<'_> {
+    /// Whether the device can sample textures in this format.
+    pub fn is_supported(&self, device: &Device) -> bool {
+        device.features().contains(self.format.required_features())
+    }
+}
+
+impl TextureSource for CompressedTexture<'_> {
+    fn texture_view(&self, device: &Device, queue: &Queue) -> SourceView<'_> {
+        upload(
+            device,
+            queue,
+            self.width,
+            self.height,
+            self.format,
+            self.mip_level_count,
+            &self.data,
+        )
+    }
+}
+
+#[cfg(feature = "ktx2")]
+mod ktx2_support {
+    use std::{borrow::Cow, io::Read};
+
+    use ktx2::{Format, Reader, SupercompressionScheme};
+    use thiserror::Error;
+    use wgpu::{AstcBlock, AstcChannel, TextureFormat};
+
+    use super::CompressedTexture;
+
+    #[derive(Error, Debug)]
+    pub enum Ktx2Error {
+        #[error("could not parse KTX2")]
+        Parse(#[from] ktx2::ParseError),
+        #[error("KTX2 format {0:?} is not supported")]
+        UnsupportedFormat(Option<Format>),
+        #[error("only single 2D textures are supported, not arrays, cube maps or 3D textures")]
+        UnsupportedShape,
+        #[error("KTX2 supercompression {0:?} is not supported")]
+        UnsupportedSupercompression(SupercompressionScheme),
+        #[error("could not decompress KTX2 level")]
+        Decompress,
+    }
+
+    impl CompressedTexture<'static> {
+        /// Reads a KTX2 file, undoing zstd supercompression if there is any.
+        ///
+        /// sRGB formats are loaded as their plain counterparts, since the renderer decodes
+        /// colors itself when it needs to. Basis Universal files need transcoding to a format the
+        /// GPU understands first, which isn't done here.
+        pub fn from_ktx2(bytes: &[u8]) -> Result<Self, Ktx2Error> {
+            let reader = Reader::new(bytes)?;
+            let header = reader.header();
+
+            if header.pixel_depth > 1 || header.layer_count > 1 || header.face_count > 1 {
+                return Err(Ktx2Error::UnsupportedShape);
+            }
+            let format = header
+                .format
+                .and_then(wgpu_format)
+                .ok_or(Ktx2Error::UnsupportedFormat(header.format))?;
+
+            let mut data = Vec::new();
+            for level in reader.levels() {
+                match header.supercompression_scheme {
+                    None => data.extend_from_slice(level),
+                    Some(SupercompressionScheme::Zstandard) => {
+                        let mut level = level;
+                        ruzstd::StreamingDecoder::new(&mut level)
+                            .map_err(|_| Ktx2Error::Decompress)?
+                            .read_to_end(&mut data)
+                            .map_err(|_| Ktx2Error::Decompress)?;
+                    }
+                    Some(scheme) => return Err(Ktx2Error::UnsupportedSupercompression(scheme)),
+                }
+            }
+
+            Ok(Self {
+                width: header.pixel_width,
+                height: header.pixel_height,
+                format,
+                mip_level_count: header.level_count.max(1),
+                data: Cow::Owned(data),
+            })
+        }
+    }
+
+    fn wgpu_format(format: Format) -> Option<TextureFormat> {
+        let astc = |block| TextureFormat::Astc {
+            block,
+            channel: AstcChannel::Unorm,
+        };
+
+        Some(match format {
+            Format::R8G8B8A8_UNORM | Format::R8G8B8A8_SRGB => TextureFormat::Rgba8Unorm,
+            Format::BC1_RGB_UNORM_BLOCK
+            | Format::BC1_RGB_SRGB_BLOCK
+            | Format::BC1_RGBA_UNORM_BLOCK
+            | Format::BC1_RGBA_SRGB_BLOCK => TextureFormat::Bc1RgbaUnorm,
+            Format::BC2_UNORM_BLOCK | Format::BC2_SRGB_BLOCK => TextureFormat::Bc2RgbaUnorm,
+            Format::BC3_UNORM_BLOCK | Format::BC3_SRGB_BLOCK => TextureFormat::Bc3RgbaUnorm,
+            Format::BC7_UNORM_BLOCK | Format::BC7_SRGB_BLOCK => TextureFormat::Bc7RgbaUnorm,
+            Format::ETC2_R8G8B8_UNORM_BLOCK | Format::ETC2_R8G8B8_SRGB_BLOCK => {
+                TextureFormat::Etc2Rgb8Unorm
+            }
+            Format::ETC2_R8G8B8A1_UNORM_BLOCK | Format::ETC2_R8G8B8A1_SRGB_BLOCK => {
+                TextureFormat::Etc2Rgb8A1Unorm
+            }
+            Format::ETC2_R8G8B8A8_UNORM_BLOCK | Format::ETC2_R8G8B8A8_SRGB_BLOCK => {
+                TextureFormat::Etc2Rgba8Unorm
+            }
+            Format::ASTC_4x4_UNORM_BLOCK | Format::ASTC_4x4_SRGB_BLOCK => astc(AstcBlock::B4x4),
+            Format::ASTC_5x4_UNORM_BLOCK | Format::ASTC_5x4_SRGB_BLOCK => astc(AstcBlock::B5x4),
+            Format::ASTC_5x5_UNORM_BLOCK | Format::ASTC_5x5_SRGB_BLOCK => astc(AstcBlock::B5x5),
+            Format::ASTC_6x5_UNORM_BLOCK | Format::ASTC_6x5_SRGB_BLOCK => astc(AstcBlock::B6x5),
+            Format::ASTC_6x6_UNORM_BLOCK | Format::ASTC_6x6_SRGB_BLOCK => astc(AstcBlock::B6x6),
+            Format::ASTC_8x5_UNORM_BLOCK | Format::ASTC_8x5_SRGB_BLOCK => astc(AstcBlock::B8x5),
+            Format::ASTC_8x6_UNORM_BLOCK | Format::ASTC_8x6_SRGB_BLOCK => astc(AstcBlock::B8x6),
+            Format::ASTC_8x8_UNORM_BLOCK | Format::ASTC_8x8_SRGB_BLOCK => astc(AstcBlock::B8x8),
+            Format::ASTC_10x5_UNORM_BLOCK | Format::ASTC_10x5_SRGB_BLOCK => astc(AstcBlock::B10x5),
+            Format::ASTC_10x6_UNORM_BLOCK | Format::ASTC_10x6_SRGB_BLOCK => astc(AstcBlock::B10x6),
+            Format::ASTC_10x8_UNORM_BLOCK | Format::ASTC_10x8_SRGB_BLOCK => astc(AstcBlock::B10x8),
+            Format::ASTC_10x10_UNORM_BLOCK | Format::ASTC_10x10_SRGB_BLOCK => {
+                astc(AstcBlock::B10x10)
+            }
+            Format::ASTC_12x10_UNORM_BLOCK | Format::ASTC_12x10_SRGB_BLOCK => {
+                astc(AstcBlock::B12x10)
+            }
+            Format::ASTC_12x12_UNORM_BLOCK | Format::ASTC_12x12_SRGB_BLOCK => {
+                astc(AstcBlock::B12x12)
+            }
+            _ => return None,
+        })
+    }
+}
+
+#[cfg(feature = "ktx2")]
+pub use ktx2_support::Ktx2Error;
+
+/// Compresses an image to BC3 on the CPU, which takes a quarter of the memory of plain
+/// RGBA at some cost in quality. Images whose sides aren't multiples of four can't be
+/// stored as BC3, and give `None`.
+///
+/// This is meant for loading PNGs on devices that support [Features::TEXTURE_COMPRESSION_BC],
+/// and takes a while for big textures, so it's best done off the main thread.
+#[cfg(feature = "bc")]
+pub fn compress_bc3(image: &RgbaImage) -> Option<CompressedTexture<'static>> {
+    let (width, height) = image.dimensions();
+    if width % 4 != 0 || height % 4 != 0 {
+        return None;
+    }
+
+    let format = texpresso::Format::Bc3;
+    let mut data = vec![0; format.compressed_size(width as usize, height as usize)];
+    format.compress(
+        image.as_raw(),
+        width as usize,
+        height as usize,
+        texpresso::Params::default(),
+        &mut data,
+    );
+
+    Some(CompressedTexture {
+        width,
+        height,
+        format: TextureFormat::Bc3RgbaUnorm,
+        mip_level_count: 1,
+        data: Cow::Owned(data),
+    })
+}
+
+/// The part of a texture an art mesh uses, cut out by [art_mesh_region].
+pub struct ArtMeshRegion {
+    /// Where the region's top left corner was in the texture, in texels.
+    pub x: u32,
+    pub y: u32,
+    pub image: RgbaImage,
+}
+
+/// Cuts out the texels under an art mesh's UVs, for thumbnails or repacking textures into
+/// a new atlas. The region covers every texel the mesh touches, rounding outwards, and is
+/// clipped to the texture.
+///
+/// `textures` are the puppet's textures in the order it indexes them. Gives `None` if the
+/// mesh has no vertexes, its texture is missing or it lies entirely outside of it.
+pub fn art_mesh_region(
+    puppet: &PuppetRef,
+    textures: &[RgbaImage],
+    art_mesh: usize,
+) -> Option<ArtMeshRegion> {
+    let texture = textures.get(puppet.art_mesh_textures[art_mesh] as usize)?;
+    let (min, max) = puppet.art_mesh_uv_bounds(art_mesh)?;
+
+    let size = Vec2::new(texture.width() as f32, texture.height() as f32);
+    let min = (min * size).floor().clamp(Vec2::ZERO, size);
+    let max = (max * size).ceil().clamp(Vec2::ZERO, size);
+    if min.x >= max.x || min.y >= max.y {
+        return None;
+    }
+
+    let (x, y) = (min.x as u32, min.y as u32);
+    let (width, height) = ((max.x - min.x) as u32, (max.y - min.y) as u32);
+    Some(ArtMeshRegion {
+        x,
+        y,
+        image: imageops::crop_imm(texture, x, y, width, height).to_image(),
+    })
+}
+
+fn upload(
+    device: &Device,
+    queue: &Queue,
+    width: u32,
+    height: u32,
+    format: TextureFormat,
+    mip_level_count: u32,
+    data: &[u8],
+) -> SourceView<'static> {
+    let texture = device.create_texture_with_data(
+        queue,
+        &TextureDescriptor {
+            size: Extent3d {
+                width,
+                height,
+                depth_or_array_layers: 1,
+            },
+            mip_level_count,
+            sample_count: 1,
+            dimension: TextureDimension::D2,
+            format,
+            usage: TextureUsages::TEXTURE_BINDING,
+            view_formats: &[],
+            label: None,
+        },
+        data,
+    );
+
+    SourceView::Owned(texture.create_view(&TextureViewDescriptor::default()))
+}
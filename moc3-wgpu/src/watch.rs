@@ -0,0 +1,136 @@
+//! Watching a model's files for changes, so it can be reloaded as it's edited and exported
+//! again. See [Renderer::reload](crate::renderer::Renderer::reload) for swapping the new
+//! version in.
+
+use std::{
+    path::{Path, PathBuf},
+    sync::mpsc::{channel, Receiver},
+    time::{Duration, Instant},
+};
+
+use notify::{Event, EventKind, RecommendedWatcher, RecursiveMode, Watcher};
+
+use crate::load::ModelFiles;
+
+// Exporting writes every file one after another, so changes are held back until things
+// have been quiet for this long.
+const SETTLE_TIME: Duration = Duration::from_millis(250);
+
+/// Which of a model's files changed since the last time a [ModelWatcher] was polled.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct ModelChanges {
+    /// The moc3 or model3.json changed, so the whole model needs reloading. Its files
+    /// might not be where they were anymore either, see [ModelFiles::find].
+    pub model: bool,
+    /// The indexes of every texture that changed, in order.
+    pub textures: Vec<usize>,
+    pub physics: bool,
+}
+
+impl ModelChanges {
+    pub fn is_empty(&self) -> bool {
+        *self == Self::default()
+    }
+}
+
+/// Watches every file of a model for changes.
+pub struct ModelWatcher {
+    // Nothing is ever called on it, but events stop as soon as it's dropped.
+    _watcher: RecommendedWatcher,
+    // Timestamped as they happen, since they're only looked at when polled.
+    events: Receiver<(Instant, notify::Result<Event>)>,
+
+    // The same files as given, but made absolute to match the paths in events.
+    model: Vec<PathBuf>,
+    textures: Vec<PathBuf>,
+    physics: Option<PathBuf>,
+
+    pending: ModelChanges,
+    last_event: Option<Instant>,
+}
+
+impl ModelWatcher {
+    pub fn new(files: &ModelFiles) -> notify::Result<Self> {
+        let (sender, events) = channel();
+        let mut watcher = notify::recommended_watcher(move |event| {
+            // Sending only fails once the watcher's being dropped along with the receiver.
+            let _ = sender.send((Instant::now(), event));
+        })?;
+
+        let model: Vec<PathBuf> = files
+            .model3
+            .iter()
+            .chain([&files.moc])
+            .map(|x| absolute(x))
+            .collect();
+        let textures: Vec<PathBuf> = files.textures.iter().map(|x| absolute(x)).collect();
+        let physics = files.physics.as_deref().map(absolute);
+
+        // Editors and exporters often replace files rather than writing to them, which only
+        // shows up when watching the directory they're in.
+        let mut directories: Vec<&Path> = model
+            .iter()
+            .chain(&textures)
+            .chain(&physics)
+            .filter_map(|x| x.parent())
+            .collect();
+        directories.sort_unstable();
+        directories.dedup();
+        for directory in directories {
+            watcher.watch(directory, RecursiveMode::NonRecursive)?;
+        }
+
+        Ok(Self {
+            _watcher: watcher,
+            events,
+
+            model,
+            textures,
+            physics,
+
+            pending: ModelChanges::default(),
+            last_event: None,
+        })
+    }
+
+    /// Returns what changed once files have stopped changing for a moment, so half written
+    /// files aren't picked up. This never blocks, and is meant to be called every frame.
+    pub fn poll(&mut self) -> Option<ModelChanges> {
+        while let Ok((time, event)) = self.events.try_recv() {
+            // Errors are usually from events being dropped, which can't be recovered anyway.
+            let Ok(event) = event else { continue };
+            if matches!(event.kind, EventKind::Access(_)) {
+                continue;
+            }
+
+            for path in &event.paths {
+                if self.model.contains(path) {
+                    self.pending.model = true;
+                } else if self.physics.as_ref() == Some(path) {
+                    self.pending.physics = true;
+                } else if let Some(index) = self.textures.iter().position(|x| x == path) {
+                    if !self.pending.textures.contains(&index) {
+                        self.pending.textures.push(index);
+                    }
+                } else {
+                    continue;
+                }
+                self.last_event = Some(time);
+            }
+        }
+
+        if self.last_event?.elapsed() < SETTLE_TIME {
+            return None;
+        }
+
+        self.last_event = None;
+        let mut changes = std::mem::take(&mut self.pending);
+        changes.textures.sort_unstable();
+        Some(changes)
+    }
+}
+
+// Falls back to the path as given when it doesn't exist (yet).
+fn absolute(path: &Path) -> PathBuf {
+    path.canonicalize().unwrap_or_else(|_| path.to_path_buf())
+}
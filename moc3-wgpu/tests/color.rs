@@ -0,0 +1,101 @@
+//! Renders a tiny generated model in both color spaces, checking that sRGB targets only
+//! change how colors blend, not the colors themselves. These need a graphics adapter, so
+//! they only run with `--ignored`.
+
+use image::RgbaImage;
+use moc3_bench::{pose_puppet, SyntheticModel};
+use moc3_rs::parse_puppet;
+use moc3_wgpu::{
+    background::Background,
+    capture::FrameCapture,
+    renderer::{new_renderer, ColorSpace, MeshOverride},
+};
+use wgpu::{Color, Device, Queue};
+
+mod common;
+
+const MODEL: SyntheticModel = SyntheticModel {
+    limbs: 3,
+    ..SyntheticModel::TINY
+};
+
+// Every mesh is drawn as a flat color, at the given opacity.
+fn render(
+    (device, queue): &(Device, Queue),
+    color_space: ColorSpace,
+    background: Background,
+    opacity: f32,
+) -> RgbaImage {
+    let puppet = parse_puppet(&MODEL.to_moc3()).unwrap();
+    let mut capture = FrameCapture::with_color_space(device, 128, 128, color_space);
+    let no_textures: &[RgbaImage] = &[];
+    let mut renderer = new_renderer(&puppet, device, queue, capture.format(), no_textures);
+    renderer.set_placeholder_mode(true);
+    renderer.set_background(device, queue, background);
+    for index in 0..puppet.art_mesh_count as usize {
+        renderer.set_mesh_override(
+            index,
+            MeshOverride {
+                opacity,
+                ..MeshOverride::NONE
+            },
+        );
+    }
+
+    let frame_data = pose_puppet(&puppet, |_, _, _| 0.0);
+    capture
+        .capture(device, queue, &mut renderer, &frame_data)
+        .unwrap()
+}
+
+#[test]
+#[ignore = "needs a graphics adapter"]
+fn opaque_colors_match_across_color_spaces() {
+    let gpu = common::gpu();
+    let backgrounds = [
+        Background::None,
+        Background::Solid(Color {
+            r: 0.3,
+            g: 0.5,
+            b: 0.7,
+            a: 1.0,
+        }),
+    ];
+    for background in backgrounds {
+        let gamma = render(&gpu, ColorSpace::Gamma, background.clone(), 1.0);
+        let linear = render(&gpu, ColorSpace::Linear, background, 1.0);
+        for (a, b) in gamma.pixels().zip(linear.pixels()) {
+            for (a, b) in a.0.iter().zip(b.0) {
+                assert!(a.abs_diff(b) <= 1, "{a} and {b} differ");
+            }
+        }
+    }
+}
+
+#[test]
+#[ignore = "needs a graphics adapter"]
+fn translucent_colors_blend_lighter_in_linear() {
+    let gpu = common::gpu();
+    let gamma = render(
+        &gpu,
+        ColorSpace::Gamma,
+        Background::Solid(Color::WHITE),
+        0.5,
+    );
+    let linear = render(
+        &gpu,
+        ColorSpace::Linear,
+        Background::Solid(Color::WHITE),
+        0.5,
+    );
+
+    // Darker colors over white come out darker than halfway when blended as they're stored.
+    let mut lighter = 0;
+    for (a, b) in gamma.pixels().zip(linear.pixels()) {
+        for (a, b) in a.0[..3].iter().zip(b.0) {
+            assert!(b.saturating_add(1) >= *a, "{b} is darker than {a}");
+            lighter += (b > a.saturating_add(1)) as usize;
+        }
+    }
+    assert!(lighter > 0);
+}
@@ -0,0 +1,12 @@
+// Shared by the tests that render. They need a graphics adapter, so they're all ignored by
+// default, and can be run with `cargo test -p moc3-wgpu -- --ignored`.
+
+use moc3_wgpu::renderer::request_device;
+use wgpu::{Device, Queue};
+
+pub fn gpu() -> (Device, Queue) {
+    let instance = wgpu::Instance::new(wgpu::InstanceDescriptor::default());
+    let adapter = pollster::block_on(instance.request_adapter(&Default::default()))
+        .expect("no graphics adapter to render with");
+    pollster::block_on(request_device(&adapter)).unwrap()
+}
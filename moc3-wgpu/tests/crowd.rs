@@ -0,0 +1,90 @@
+//! Draws a generated model as a crowd, checking it comes out the same as a scene with a
+//! puppet for every copy. These need a graphics adapter, so they only run with
+//! `--ignored`.
+
+use glam::{vec3, Mat4};
+use image::RgbaImage;
+use moc3_bench::{pose_puppet, SyntheticModel};
+use moc3_rs::parse_puppet;
+use moc3_wgpu::{
+    capture::FrameCapture,
+    crowd::CrowdRenderer,
+    renderer::{MeshOverride, RenderOverrides, Visibility},
+    scene::SceneRenderer,
+};
+
+mod common;
+
+#[test]
+#[ignore = "needs a graphics adapter"]
+fn crowds_match_scenes() {
+    let (device, queue) = common::gpu();
+
+    let mut puppet = parse_puppet(&SyntheticModel::SMALL.to_moc3()).unwrap();
+    // Masks too, so the copies have to share the stencil.
+    puppet.art_mesh_mask_indices[1] = vec![0];
+    puppet.art_mesh_flags[3].set_inverted(true);
+    puppet.art_mesh_mask_indices[3] = vec![2];
+
+    // Every copy gets a frame of its own, with the parameters at different values.
+    let frames: Vec<_> = (0..5)
+        .map(|i| {
+            let frame_data = pose_puppet(&puppet, |_, min, max| min + (max - min) * i as f32 / 4.0);
+            let camera = Mat4::from_translation(vec3(i as f32 * 0.4 - 0.8, 0.0, 0.0))
+                * Mat4::from_scale(vec3(0.5, 0.5, 1.0));
+            (camera, frame_data)
+        })
+        .collect();
+    let frames: Vec<_> = frames.iter().map(|(camera, x)| (*camera, x)).collect();
+
+    let no_textures: &[RgbaImage] = &[];
+    let mut capture = FrameCapture::new(&device, 128, 96);
+
+    // Overrides as well, which have to reach every copy.
+    let faded = MeshOverride {
+        opacity: 0.5,
+        ..MeshOverride::NONE
+    };
+    let mut overrides = RenderOverrides::default();
+    overrides.set_art_mesh("ArtMesh6", Some(Visibility::Hidden));
+
+    let mut crowd = CrowdRenderer::new(&puppet, &device, &queue, FrameCapture::FORMAT, no_textures);
+    crowd.set_placeholder_mode(true);
+    crowd.set_mesh_override(5, faded);
+    crowd.set_render_overrides(&puppet, &overrides);
+    let crowd_image = capture
+        .capture_crowd(&device, &queue, &mut crowd, &frames)
+        .unwrap();
+
+    let mut scene = SceneRenderer::with_cache(crowd.pipeline_cache(), FrameCapture::FORMAT);
+    scene.set_placeholder_mode(true);
+    let ids: Vec<_> = frames
+        .iter()
+        .map(|(camera, _)| {
+            let id = scene.add_puppet(&device, &queue, &puppet, no_textures);
+            scene.set_transform(id, *camera);
+            scene.set_mesh_override(id, 5, faded);
+            scene.set_render_overrides(id, &puppet, &overrides);
+            id
+        })
+        .collect();
+    let scene_frames: Vec<_> = ids.iter().zip(&frames).map(|(id, x)| (*id, x.1)).collect();
+    let scene_image = capture
+        .capture_scene(&device, &queue, &mut scene, &scene_frames)
+        .unwrap();
+
+    assert!(crowd_image.pixels().any(|x| x.0[3] != 0));
+    assert!(crowd_image == scene_image);
+
+    // Fewer copies than there's room for leaves the rest out.
+    let crowd_image = capture
+        .capture_crowd(&device, &queue, &mut crowd, &frames[..1])
+        .unwrap();
+    for id in &ids[1..] {
+        scene.remove_puppet(*id);
+    }
+    let scene_image = capture
+        .capture_scene(&device, &queue, &mut scene, &scene_frames[..1])
+        .unwrap();
+    assert!(crowd_image == scene_image);
+}
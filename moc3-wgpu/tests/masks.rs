@@ -0,0 +1,185 @@
+//! Renders a tiny generated model with masks patched onto one of its meshes, checking that
+//! the mesh only shows where its masks say it should. These need a graphics adapter, so
+//! they only run with `--ignored`.
+
+use image::RgbaImage;
+use moc3_bench::{pose_puppet, SyntheticModel};
+use moc3_rs::{parse_puppet, puppet::Puppet};
+use moc3_wgpu::{
+    capture::FrameCapture,
+    renderer::{new_renderer, RenderOverrides, Renderer, Visibility},
+    soft_mask::SoftMask,
+};
+use wgpu::{Device, Queue};
+
+mod common;
+
+// The mesh the masks go on, and a mesh of the other limb, which doesn't overlap it at all.
+const MASKED: usize = 1;
+const APART: u32 = 2;
+
+// Soft masks that cut off exactly where the stencil does, for flat colors.
+const SHARP: SoftMask = SoftMask {
+    threshold: 0.5,
+    feather: 0.0,
+};
+
+struct Scene<'a> {
+    masks: &'a [u32],
+    inverted: bool,
+    hidden: bool,
+    soft_masks: Option<SoftMask>,
+}
+
+impl Scene<'_> {
+    const PLAIN: Self = Self {
+        masks: &[],
+        inverted: false,
+        hidden: false,
+        soft_masks: None,
+    };
+}
+
+fn render((device, queue): &(Device, Queue), scene: Scene) -> RgbaImage {
+    let mut puppet = parse_puppet(&SyntheticModel::TINY.to_moc3()).unwrap();
+    puppet.art_mesh_mask_indices[MASKED] = scene.masks.to_vec();
+    puppet.art_mesh_flags[MASKED].set_inverted(scene.inverted);
+
+    render_puppet(device, queue, &puppet, |renderer| {
+        renderer.set_soft_masks(device, scene.soft_masks);
+        if scene.hidden {
+            let mut overrides = RenderOverrides::default();
+            overrides.set_art_mesh(&format!("ArtMesh{MASKED}"), Some(Visibility::Hidden));
+            renderer.set_render_overrides(&puppet, &overrides);
+        }
+    })
+}
+
+// Every mesh is drawn as a flat, opaque color.
+fn render_puppet(
+    device: &Device,
+    queue: &Queue,
+    puppet: &Puppet,
+    setup: impl FnOnce(&mut Renderer),
+) -> RgbaImage {
+    let no_textures: &[RgbaImage] = &[];
+    let mut renderer = new_renderer(puppet, device, queue, FrameCapture::FORMAT, no_textures);
+    renderer.set_placeholder_mode(true);
+    setup(&mut renderer);
+
+    let frame_data = pose_puppet(puppet, |_, _, _| 0.0);
+
+    let mut capture = FrameCapture::new(device, 128, 128);
+    capture
+        .capture(device, queue, &mut renderer, &frame_data)
+        .unwrap()
+}
+
+// Where the masked mesh shows up, going by what changes once it's hidden.
+fn shown(image: &RgbaImage, hidden: &RgbaImage) -> Vec<bool> {
+    image
+        .pixels()
+        .zip(hidden.pixels())
+        .map(|(a, b)| a != b)
+        .collect()
+}
+
+#[test]
+#[ignore = "needs a graphics adapter"]
+fn inverted_masks_hide_what_they_cover() {
+    let gpu = common::gpu();
+    let hidden = render(
+        &gpu,
+        Scene {
+            hidden: true,
+            ..Scene::PLAIN
+        },
+    );
+
+    // Masked by itself, there's nowhere left outside of the masks.
+    for masks in [&[MASKED as u32][..], &[APART, MASKED as u32]] {
+        for soft_masks in [None, Some(SHARP)] {
+            let image = render(
+                &gpu,
+                Scene {
+                    masks,
+                    inverted: true,
+                    soft_masks,
+                    ..Scene::PLAIN
+                },
+            );
+            assert!(image == hidden, "{masks:?} {soft_masks:?}");
+        }
+    }
+}
+
+#[test]
+#[ignore = "needs a graphics adapter"]
+fn inverted_masks_complement_regular_ones() {
+    let gpu = common::gpu();
+    let hidden = render(
+        &gpu,
+        Scene {
+            hidden: true,
+            ..Scene::PLAIN
+        },
+    );
+    let whole = shown(&render(&gpu, Scene::PLAIN), &hidden);
+
+    // Masked by itself, the mesh is entirely inside, and masked by the other limb entirely
+    // outside.
+    let itself = MASKED as u32;
+    for masks in [&[itself][..], &[APART], &[APART, itself]] {
+        for soft_masks in [None, Some(SHARP)] {
+            let [inside, outside] = [false, true].map(|inverted| {
+                let scene = Scene {
+                    masks,
+                    inverted,
+                    soft_masks,
+                    ..Scene::PLAIN
+                };
+                shown(&render(&gpu, scene), &hidden)
+            });
+
+            // Every pixel of the mesh shows up in exactly one of the two.
+            for ((inside, outside), whole) in inside.iter().zip(&outside).zip(&whole) {
+                assert_eq!(inside | outside, *whole, "{masks:?} {soft_masks:?}");
+                assert!(!(inside & outside), "{masks:?} {soft_masks:?}");
+            }
+            let covers = masks.contains(&itself);
+            assert_eq!(inside.contains(&true), covers, "{masks:?} {soft_masks:?}");
+        }
+    }
+}
+
+#[test]
+#[ignore = "needs a graphics adapter"]
+fn more_masked_meshes_than_stencil_values() {
+    let (device, queue) = common::gpu();
+    // Limbs this close together overlap, but the meshes of each stay in their own band,
+    // lined up with the same bands of every other limb.
+    let model = SyntheticModel {
+        limbs: 40,
+        meshes_per_limb: 8,
+        mesh_resolution: 2,
+        warp_resolution: 1,
+        ..SyntheticModel::TINY
+    };
+    let puppet = parse_puppet(&model.to_moc3()).unwrap();
+    assert!(model.art_mesh_count() > u8::MAX as usize);
+    let plain = render_puppet(&device, &queue, &puppet, |_| ());
+
+    let masked_by = |mask: &dyn Fn(usize) -> usize| {
+        let mut puppet = puppet.clone();
+        for (i, masks) in puppet.art_mesh_mask_indices.iter_mut().enumerate() {
+            *masks = vec![mask(i) as u32];
+        }
+        render_puppet(&device, &queue, &puppet, |_| ())
+    };
+
+    // Once the stencil values run out, masks from before must not show through.
+    let itself = masked_by(&|i| i);
+    assert!(itself == plain);
+    let next_band = masked_by(&|i| i / 8 * 8 + (i + 1) % 8);
+    assert!(next_band.pixels().all(|x| x.0 == [0; 4]));
+}